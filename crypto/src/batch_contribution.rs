@@ -1,6 +1,7 @@
 use crate::{
     signature::{identity::Identity, EcdsaSignature},
-    CeremoniesError, Contribution, Engine, Entropy, Tau, G2,
+    CeremoniesError, Contribution, Engine, Entropy, EntropyAttestation, Tau, VerificationTimings,
+    G2,
 };
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
@@ -12,8 +13,11 @@ use tracing::instrument;
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct BatchContribution {
-    pub contributions:   Vec<Contribution>,
-    pub ecdsa_signature: EcdsaSignature,
+    pub contributions:       Vec<Contribution>,
+    pub ecdsa_signature:     EcdsaSignature,
+    /// Optional report of the entropy sources the client used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entropy_attestation: Option<EntropyAttestation>,
 }
 
 impl BatchContribution {
@@ -42,6 +46,19 @@ impl BatchContribution {
         res
     }
 
+    /// True if every contribution carries a BLS signature of `identity` under
+    /// its `pot_pubkey`, which binds the contribution to the participant.
+    #[must_use]
+    #[instrument(level = "info", skip_all, fields(n=self.contributions.len()))]
+    pub fn is_bound_to<E: Engine>(&self, identity: &Identity) -> bool {
+        let message = identity.to_string();
+        self.contributions.par_iter().all(|contribution| {
+            contribution
+                .bls_signature
+                .verify::<E>(message.as_bytes(), contribution.pot_pubkey)
+        })
+    }
+
     #[instrument(level = "info", skip_all, fields(n=self.contributions.len()))]
     pub fn validate<E: Engine>(&mut self) -> Result<(), CeremoniesError> {
         let res =
@@ -55,6 +72,40 @@ impl BatchContribution {
                 });
         res
     }
+
+    /// Verifies that this contribution correctly builds on `previous`, the
+    /// contribution handed out by [`crate::BatchTranscript::contribution`].
+    pub fn verify_after<E: Engine>(&self, previous: &Self) -> Result<(), CeremoniesError> {
+        self.verify_after_timed::<E>(previous).map(drop)
+    }
+
+    /// Like [`Self::verify_after`], but also returns how long the checks of
+    /// each ceremony took.
+    #[instrument(level = "info", skip_all, fields(n=self.contributions.len()))]
+    pub fn verify_after_timed<E: Engine>(
+        &self,
+        previous: &Self,
+    ) -> Result<Vec<VerificationTimings>, CeremoniesError> {
+        if let Some(attestation) = &self.entropy_attestation {
+            attestation.validate()?;
+        }
+        if previous.contributions.len() != self.contributions.len() {
+            return Err(CeremoniesError::UnexpectedNumContributions(
+                previous.contributions.len(),
+                self.contributions.len(),
+            ));
+        }
+        self.contributions
+            .par_iter()
+            .zip(&previous.contributions)
+            .enumerate()
+            .map(|(i, (contribution, previous))| {
+                contribution
+                    .verify_after_timed::<E>(&previous.powers)
+                    .map_err(|e| CeremoniesError::InvalidCeremony(i, e))
+            })
+            .collect()
+    }
 }
 
 fn derive_taus<E: Engine>(entropy: &Entropy, size: usize) -> Vec<Tau> {
@@ -72,7 +123,13 @@ fn derive_taus<E: Engine>(entropy: &Entropy, size: usize) -> Vec<Tau> {
 #[allow(clippy::missing_panics_doc)] // Does not panic.
 #[must_use]
 pub fn get_pot_pubkeys<E: Engine>(entropy: &Entropy) -> Vec<G2> {
-    let taus = derive_taus::<E>(entropy, 4);
+    derive_pot_pubkeys::<E>(entropy, 4)
+}
+
+/// Returns the pubkeys that [`BatchContribution::add_entropy`] produces for
+/// `size` ceremonies.
+pub(crate) fn derive_pot_pubkeys<E: Engine>(entropy: &Entropy, size: usize) -> Vec<G2> {
+    let taus = derive_taus::<E>(entropy, size);
     let result: Vec<G2> = taus
         .into_par_iter()
         .map(|tau| {
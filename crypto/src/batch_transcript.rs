@@ -1,17 +1,29 @@
 use crate::{
+    batch_contribution::derive_pot_pubkeys,
+    metadata::{engine_name, ContributionMetadata, EntropyAttestation, TranscriptMetadata},
     signature::{identity::Identity, ContributionTypedData, EcdsaSignature},
-    BatchContribution, CeremoniesError, Engine, Transcript,
+    BatchContribution, CeremoniesError, Engine, Entropy, Transcript, VerificationTimings,
 };
 use rayon::prelude::*;
+use secrecy::Secret;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::instrument;
 
+/// Domain separation tag for deriving entropy from a beacon value.
+const BEACON_DST: &[u8] = b"KZG_CEREMONY_BEACON_V1";
+
+/// Domain separation tag for deriving the participants of fixtures.
+const FIXTURE_DST: &[u8] = b"KZG_CEREMONY_FIXTURE_V1";
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct BatchTranscript {
     pub transcripts:                  Vec<Transcript>,
     pub participant_ids:              Vec<Identity>,
     pub participant_ecdsa_signatures: Vec<EcdsaSignature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata:                     Option<TranscriptMetadata>,
 }
 
 impl BatchTranscript {
@@ -26,6 +38,7 @@ impl BatchTranscript {
                 .collect(),
             participant_ids:              vec![Identity::None],
             participant_ecdsa_signatures: vec![EcdsaSignature::empty()],
+            metadata:                     None,
         }
     }
 
@@ -39,12 +52,13 @@ impl BatchTranscript {
     #[must_use]
     pub fn contribution(&self) -> BatchContribution {
         BatchContribution {
-            contributions:   self
+            contributions:       self
                 .transcripts
                 .iter()
                 .map(Transcript::contribution)
                 .collect(),
-            ecdsa_signature: EcdsaSignature::empty(),
+            ecdsa_signature:     EcdsaSignature::empty(),
+            entropy_attestation: None,
         }
     }
 
@@ -53,9 +67,37 @@ impl BatchTranscript {
     #[instrument(level = "info", skip_all, fields(n=contribution.contributions.len()))]
     pub fn verify_add<E: Engine>(
         &mut self,
-        mut contribution: BatchContribution,
+        contribution: BatchContribution,
         identity: Identity,
     ) -> Result<(), CeremoniesError> {
+        self.verify::<E>(&contribution)?;
+        self.add::<E>(contribution, identity);
+        Ok(())
+    }
+
+    /// Verifies a batch contribution against the current state of the
+    /// transcript.
+    pub fn verify<E: Engine>(
+        &self,
+        contribution: &BatchContribution,
+    ) -> Result<(), CeremoniesError> {
+        self.verify_timed::<E>(contribution).map(drop)
+    }
+
+    /// Like [`Self::verify`], but also returns how long the checks of each
+    /// ceremony took.
+    pub fn verify_timed<E: Engine>(
+        &self,
+        contribution: &BatchContribution,
+    ) -> Result<Vec<VerificationTimings>, CeremoniesError> {
+        if self.has_beacon() {
+            return Err(CeremoniesError::BeaconApplied);
+        }
+
+        if let Some(attestation) = &contribution.entropy_attestation {
+            attestation.validate()?;
+        }
+
         // Verify contribution count
         if self.transcripts.len() != contribution.contributions.len() {
             return Err(CeremoniesError::UnexpectedNumContributions(
@@ -66,15 +108,46 @@ impl BatchTranscript {
 
         // Verify contributions in parallel
         self.transcripts
-            .par_iter_mut()
+            .par_iter()
             .zip(&contribution.contributions)
             .enumerate()
-            .try_for_each(|(i, (transcript, contribution))| {
+            .map(|(i, (transcript, contribution))| {
+                contribution
+                    .verify_after_timed::<E>(&transcript.powers)
+                    .map_err(|e| CeremoniesError::InvalidCeremony(i, e))
+            })
+            .collect()
+    }
+
+    /// Verifies the transcripts on their own, see [`Transcript::verify_self`],
+    /// and that they have a contribution for every participant.
+    pub fn verify_self<E: Engine>(&self) -> Result<(), CeremoniesError> {
+        let num_participants = self.participant_ids.len();
+        for transcript in &self.transcripts {
+            if transcript.witness.pubkeys.len() != num_participants {
+                return Err(CeremoniesError::UnexpectedNumContributions(
+                    num_participants,
+                    transcript.witness.pubkeys.len(),
+                ));
+            }
+        }
+        self.transcripts
+            .par_iter()
+            .enumerate()
+            .try_for_each(|(i, transcript)| {
                 transcript
-                    .verify::<E>(contribution)
+                    .verify_self::<E>()
                     .map_err(|e| CeremoniesError::InvalidCeremony(i, e))
             })?;
+        if self.has_beacon() {
+            self.verify_beacon::<E>()?;
+        }
+        Ok(())
+    }
 
+    /// Adds a batch contribution to the transcript. The contribution must be
+    /// verified.
+    pub fn add<E: Engine>(&mut self, mut contribution: BatchContribution, identity: Identity) {
         self.participant_ecdsa_signatures.push(
             contribution
                 .ecdsa_signature
@@ -98,8 +171,275 @@ impl BatchTranscript {
         }
 
         self.participant_ids.push(identity);
+    }
 
-        Ok(())
+    /// Records when the latest contribution was received and verified, and
+    /// by which sequencer version and engine, with the entropy attestation
+    /// of the contribution.
+    pub fn record_metadata<E: Engine>(
+        &mut self,
+        sequencer_version: &str,
+        received_at: u64,
+        verified_at: u64,
+        entropy_attestation: Option<EntropyAttestation>,
+    ) {
+        let participant = self.num_participants();
+        let metadata = self
+            .metadata
+            .get_or_insert_with(TranscriptMetadata::default);
+        metadata.sequencer_version = sequencer_version.to_string();
+        metadata.verification_engine = engine_name::<E>();
+        metadata.contributions.push(ContributionMetadata {
+            participant,
+            received_at,
+            verified_at,
+            entropy_attestation,
+        });
+    }
+
+    /// True if the transcript is finalized by a random beacon.
+    #[must_use]
+    pub fn has_beacon(&self) -> bool {
+        matches!(self.participant_ids.last(), Some(Identity::Beacon { .. }))
+    }
+
+    /// Applies a public random beacon, such as a drand round or a block hash,
+    /// as the final contribution. The entropy is derived from the beacon
+    /// value, which is recorded in the transcript as the participant
+    /// identity. No contributions are accepted afterwards.
+    ///
+    /// The `source` names the beacon and must not contain `|`.
+    pub fn apply_beacon<E: Engine>(
+        &mut self,
+        source: &str,
+        value: &[u8],
+    ) -> Result<(), CeremoniesError> {
+        let identity = Identity::Beacon {
+            source: source.to_string(),
+            value:  value.to_vec(),
+        };
+        let mut contribution = self.contribution();
+        contribution.add_entropy::<E>(&beacon_entropy(value), &identity)?;
+        self.verify_add::<E>(contribution, identity)
+    }
+
+    /// Checks that the last contribution was made with the recorded beacon
+    /// value. Together with the checks on the witness, this shows that the
+    /// output could not be influenced after the beacon value was known.
+    pub fn verify_beacon<E: Engine>(&self) -> Result<(), CeremoniesError> {
+        let value = match self.participant_ids.last() {
+            Some(Identity::Beacon { value, .. }) => value,
+            _ => return Err(CeremoniesError::InvalidBeacon),
+        };
+        let expected = derive_pot_pubkeys::<E>(&beacon_entropy(value), self.transcripts.len());
+        let recorded = self
+            .transcripts
+            .iter()
+            .map(|transcript| transcript.witness.pubkeys.last());
+        if expected.iter().map(Some).eq(recorded) {
+            Ok(())
+        } else {
+            Err(CeremoniesError::InvalidBeacon)
+        }
+    }
+
+    /// Adds `n` contributions of participants derived from `seed`, for test
+    /// fixtures of explorers and verifiers. Participant `i` is an Ethereum
+    /// identity contributing entropy derived from the seed and `i` alone, so
+    /// fixtures with the same seed agree on the participants they share, and
+    /// fixtures with more participants extend those with fewer.
+    ///
+    /// The contributions are built correctly, so they are added without
+    /// being verified. The entropy is public, never use this for a real
+    /// ceremony.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transcript is finalized by a beacon.
+    pub fn deterministic_with_participants<E: Engine>(
+        mut self,
+        n: usize,
+        seed: &[u8],
+    ) -> Result<Self, CeremoniesError> {
+        if self.has_beacon() && n > 0 {
+            return Err(CeremoniesError::BeaconApplied);
+        }
+        let first = self.num_participants();
+        for index in first..first + n {
+            let (entropy, identity) = fixture_participant(seed, index);
+            let mut contribution = self.contribution();
+            contribution.add_entropy::<E>(&entropy, &identity)?;
+            self.add::<E>(contribution, identity);
+        }
+        Ok(self)
+    }
+}
+
+fn fixture_participant(seed: &[u8], index: usize) -> (Entropy, Identity) {
+    let derive = |purpose: &[u8]| -> [u8; 32] {
+        Sha256::new()
+            .chain_update(FIXTURE_DST)
+            .chain_update(purpose)
+            .chain_update((index as u64).to_be_bytes())
+            .chain_update(seed)
+            .finalize()
+            .into()
+    };
+    let mut address = [0; 20];
+    address.copy_from_slice(&derive(b"identity")[..20]);
+    (Secret::new(derive(b"entropy")), Identity::Ethereum {
+        address,
+    })
+}
+
+fn beacon_entropy(value: &[u8]) -> Entropy {
+    let hash = Sha256::new()
+        .chain_update(BEACON_DST)
+        .chain_update(value)
+        .finalize();
+    Secret::new(hash.into())
+}
+
+#[cfg(all(test, feature = "arkworks"))]
+mod tests {
+    use super::*;
+    use crate::{Arkworks, CeremonyError};
+
+    #[test]
+    fn records_metadata() {
+        let mut transcript = BatchTranscript::new(&[(4, 2)]);
+        let json = serde_json::to_value(&transcript).unwrap();
+        assert!(json.get("metadata").is_none());
+
+        transcript.record_metadata::<Arkworks>("1.0.0", 10, 12, None);
+        let json = serde_json::to_value(&transcript).unwrap();
+        assert_eq!(
+            json["metadata"],
+            serde_json::json!({
+                "sequencerVersion": "1.0.0",
+                "verificationEngine": "Arkworks",
+                "contributions": [{ "participant": 0, "receivedAt": 10, "verifiedAt": 12 }],
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<BatchTranscript>(json).unwrap(),
+            transcript
+        );
+    }
+
+    #[test]
+    fn applies_beacon() {
+        let mut transcript = BatchTranscript::new(&[(4, 2), (8, 3)]);
+        let mut contribution = transcript.contribution();
+        contribution
+            .add_entropy::<Arkworks>(&Secret::new([1; 32]), &Identity::None)
+            .unwrap();
+        transcript
+            .verify_add::<Arkworks>(contribution, Identity::None)
+            .unwrap();
+        assert_eq!(
+            transcript.verify_beacon::<Arkworks>(),
+            Err(CeremoniesError::InvalidBeacon)
+        );
+
+        transcript
+            .apply_beacon::<Arkworks>("drand", &[0x12, 0x34])
+            .unwrap();
+        assert!(transcript.has_beacon());
+        assert_eq!(transcript.num_participants(), 2);
+        assert_eq!(transcript.verify_beacon::<Arkworks>(), Ok(()));
+
+        // Contributions after the beacon are rejected
+        assert_eq!(
+            transcript.verify::<Arkworks>(&transcript.contribution()),
+            Err(CeremoniesError::BeaconApplied)
+        );
+
+        // The beacon value must match the contribution
+        transcript.participant_ids[2] = Identity::Beacon {
+            source: "drand".to_string(),
+            value:  vec![0x56],
+        };
+        assert_eq!(
+            transcript.verify_beacon::<Arkworks>(),
+            Err(CeremoniesError::InvalidBeacon)
+        );
+    }
+
+    #[test]
+    fn builds_deterministic_fixtures() {
+        let fixture = |n: usize, seed: &[u8]| {
+            BatchTranscript::new(&[(4, 2), (8, 3)])
+                .deterministic_with_participants::<Arkworks>(n, seed)
+                .unwrap()
+        };
+        let transcript = fixture(3, b"seed");
+        assert_eq!(transcript.num_participants(), 3);
+        assert_eq!(transcript.verify_self::<Arkworks>(), Ok(()));
+        assert!(transcript.participant_ids[1..]
+            .iter()
+            .all(|identity| matches!(identity, Identity::Ethereum { .. })));
+        assert_eq!(fixture(3, b"seed"), transcript);
+        assert_ne!(fixture(3, b"other seed"), transcript);
+
+        // Participants extend a fixture with fewer, whichever way it is built
+        let extended = fixture(1, b"seed")
+            .deterministic_with_participants::<Arkworks>(2, b"seed")
+            .unwrap();
+        assert_eq!(extended, transcript);
+        assert_eq!(
+            fixture(5, b"seed").participant_ids[..4],
+            transcript.participant_ids[..]
+        );
+
+        let mut finalized = fixture(1, b"seed");
+        finalized.apply_beacon::<Arkworks>("drand", &[1]).unwrap();
+        assert_eq!(
+            finalized.deterministic_with_participants::<Arkworks>(1, b"seed"),
+            Err(CeremoniesError::BeaconApplied)
+        );
+    }
+
+    #[test]
+    fn verifies_itself() {
+        let mut transcript = BatchTranscript::new(&[(4, 2), (8, 3)]);
+        assert_eq!(transcript.verify_self::<Arkworks>(), Ok(()));
+
+        let mut contribution = transcript.contribution();
+        contribution
+            .add_entropy::<Arkworks>(&Secret::new([1; 32]), &Identity::None)
+            .unwrap();
+        transcript
+            .verify_add::<Arkworks>(contribution, Identity::None)
+            .unwrap();
+        assert_eq!(transcript.verify_self::<Arkworks>(), Ok(()));
+
+        // Swapped powers, a broken witness chain and a missing participant are
+        // all noticed
+        let mut tampered = transcript.clone();
+        tampered.transcripts[1].powers.g1.swap(2, 3);
+        assert_eq!(
+            tampered.verify_self::<Arkworks>(),
+            Err(CeremoniesError::InvalidCeremony(
+                1,
+                CeremonyError::G1PairingFailed
+            ))
+        );
+        let mut tampered = transcript.clone();
+        tampered.transcripts[0].witness.pubkeys[1] = tampered.transcripts[1].witness.pubkeys[1];
+        assert_eq!(
+            tampered.verify_self::<Arkworks>(),
+            Err(CeremoniesError::InvalidCeremony(
+                0,
+                CeremonyError::InvalidWitness(1)
+            ))
+        );
+        let mut tampered = transcript;
+        tampered.participant_ids.pop();
+        assert_eq!(
+            tampered.verify_self::<Arkworks>(),
+            Err(CeremoniesError::UnexpectedNumContributions(1, 2))
+        );
     }
 }
 
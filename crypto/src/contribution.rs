@@ -3,8 +3,17 @@ use crate::{
     CeremonyError, Engine, Powers, Tau, G2,
 };
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 use tracing::instrument;
 
+/// How long the steps of verifying a contribution took.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct VerificationTimings {
+    /// Encoding and subgroup checks of the points.
+    pub subgroup_checks: Duration,
+    pub pairing_checks:  Duration,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Contribution {
@@ -51,6 +60,59 @@ impl Contribution {
         E::validate_g2(&[self.pot_pubkey])?;
         Ok(())
     }
+
+    /// Verifies that this contribution correctly builds on the `previous`
+    /// powers.
+    pub fn verify_after<E: Engine>(&self, previous: &Powers) -> Result<(), CeremonyError> {
+        self.verify_after_timed::<E>(previous).map(drop)
+    }
+
+    /// Like [`Self::verify_after`], but also returns how long the checks
+    /// took.
+    #[instrument(level = "info", skip_all, fields(n1=previous.g1.len(), n2=previous.g2.len()))]
+    pub fn verify_after_timed<E: Engine>(
+        &self,
+        previous: &Powers,
+    ) -> Result<VerificationTimings, CeremonyError> {
+        // Compatibility checks
+        if previous.g1.len() != self.powers.g1.len() {
+            return Err(CeremonyError::UnexpectedNumG1Powers(
+                previous.g1.len(),
+                self.powers.g1.len(),
+            ));
+        }
+        if previous.g2.len() != self.powers.g2.len() {
+            return Err(CeremonyError::UnexpectedNumG2Powers(
+                previous.g2.len(),
+                self.powers.g2.len(),
+            ));
+        }
+
+        // Verify the contribution points (encoding and subgroup checks).
+        let started = Instant::now();
+        E::validate_g1(&self.powers.g1)?;
+        E::validate_g2(&self.powers.g2)?;
+        E::validate_g2(&[self.pot_pubkey])?;
+        let subgroup_checks = started.elapsed();
+
+        // Non-zero check
+        if self.pot_pubkey == G2::zero() {
+            return Err(CeremonyError::ZeroPubkey);
+        }
+
+        // Verify pairings.
+        let started = Instant::now();
+        E::verify_pubkey(self.powers.g1[1], previous.g1[1], self.pot_pubkey)?;
+        E::verify_g1(&self.powers.g1, self.powers.g2[1])?;
+        E::verify_g2(&self.powers.g1[..self.powers.g2.len()], &self.powers.g2)?;
+        let pairing_checks = started.elapsed();
+
+        // Accept
+        Ok(VerificationTimings {
+            subgroup_checks,
+            pairing_checks,
+        })
+    }
 }
 
 #[cfg(test)]
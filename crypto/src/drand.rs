@@ -0,0 +1,152 @@
+//! Verification of rounds of a [drand](https://drand.love) randomness beacon,
+//! so that whoever serves the rounds can't choose their randomness.
+//! <https://drand.love/docs/specification/>
+
+use blst::{min_pk, min_sig, BLST_ERROR};
+use sha2::{Digest, Sha256};
+use strum::{EnumString, IntoStaticStr};
+
+const G2_CYPHER_SUITE: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_";
+const G1_CYPHER_SUITE: &[u8] = b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_NUL_";
+
+/// Signature schemes of drand chains, by their `schemeID`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString, IntoStaticStr)]
+pub enum DrandScheme {
+    /// Keys on G1, and signatures on G2 of the previous signature and the
+    /// round, as on the League of Entropy mainnet.
+    #[strum(serialize = "pedersen-bls-chained")]
+    Chained,
+    /// Keys on G1, and signatures on G2 of the round.
+    #[strum(serialize = "pedersen-bls-unchained")]
+    Unchained,
+    /// Keys on G2, and signatures on G1 of the round.
+    #[strum(serialize = "bls-unchained-g1-rfc9380")]
+    UnchainedG1,
+}
+
+impl DrandScheme {
+    /// Scheme of chains that don't name one.
+    pub const DEFAULT: Self = Self::Chained;
+
+    /// True if `signature` is the chain's signature of `round`. The previous
+    /// signature is only signed by chained schemes.
+    #[must_use]
+    pub fn verify(
+        self,
+        public_key: &[u8],
+        round: u64,
+        signature: &[u8],
+        previous_signature: &[u8],
+    ) -> bool {
+        let mut hasher = Sha256::new();
+        if self == Self::Chained {
+            hasher.update(previous_signature);
+        }
+        hasher.update(round.to_be_bytes());
+        let message = hasher.finalize();
+
+        let result = match self {
+            Self::Chained | Self::Unchained => match (
+                min_pk::PublicKey::from_bytes(public_key),
+                min_pk::Signature::from_bytes(signature),
+            ) {
+                (Ok(pk), Ok(sig)) => sig.verify(true, &message, G2_CYPHER_SUITE, &[], &pk, true),
+                _ => return false,
+            },
+            Self::UnchainedG1 => match (
+                min_sig::PublicKey::from_bytes(public_key),
+                min_sig::Signature::from_bytes(signature),
+            ) {
+                (Ok(pk), Ok(sig)) => sig.verify(true, &message, G1_CYPHER_SUITE, &[], &pk, true),
+                _ => return false,
+            },
+        };
+        result == BLST_ERROR::BLST_SUCCESS
+    }
+}
+
+/// A drand chain, as described at its `/info` endpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DrandChain {
+    pub public_key:   Vec<u8>,
+    /// Seconds between rounds.
+    pub period:       u32,
+    pub genesis_time: i64,
+    pub group_hash:   Vec<u8>,
+    /// `schemeID`, empty for the default scheme.
+    pub scheme:       String,
+    /// `beaconID`, empty for the default beacon.
+    pub beacon_id:    String,
+}
+
+impl DrandChain {
+    /// Hash identifying the chain, computed as drand does. The default
+    /// scheme and beacon are left out, as they predate being named.
+    #[must_use]
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.period.to_be_bytes());
+        hasher.update(self.genesis_time.to_be_bytes());
+        hasher.update(&self.public_key);
+        hasher.update(&self.group_hash);
+        if !self.scheme.is_empty() && self.scheme != <&str>::from(DrandScheme::DEFAULT) {
+            hasher.update(self.scheme.as_bytes());
+        }
+        if !self.beacon_id.is_empty() && self.beacon_id != "default" {
+            hasher.update(self.beacon_id.as_bytes());
+        }
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn hashes_mainnet_chain() {
+        let chain = DrandChain {
+            public_key:   hex!("868f005eb8e6e4ca0a47c8a77ceaa5309a47978a7c71bc5cce96366b5d7a569937c529eeda66c7293784a9402801af31").to_vec(),
+            period:       30,
+            genesis_time: 1_595_431_050,
+            group_hash:   hex!("176f93498eac9ca337150b46d21dd58673ea4e3581185f869672e59fa4cb390a").to_vec(),
+            scheme:       "pedersen-bls-chained".to_string(),
+            beacon_id:    "default".to_string(),
+        };
+        assert_eq!(
+            chain.hash(),
+            hex!("8990e7a9aaed2ffed73dbd7092123d6f289930540d7651336225dc172e51b2ce")
+        );
+    }
+
+    #[test]
+    fn verifies_rounds() {
+        let message = |previous: &[u8], round: u64| {
+            let mut hasher = Sha256::new();
+            hasher.update(previous);
+            hasher.update(round.to_be_bytes());
+            hasher.finalize()
+        };
+
+        let sk = min_pk::SecretKey::key_gen(&[7; 32], &[]).unwrap();
+        let pk = sk.sk_to_pk().compress();
+        let previous = [1; 96];
+        let chained = sk
+            .sign(&message(&previous, 2), G2_CYPHER_SUITE, &[])
+            .compress();
+        assert!(DrandScheme::Chained.verify(&pk, 2, &chained, &previous));
+        assert!(!DrandScheme::Chained.verify(&pk, 3, &chained, &previous));
+        assert!(!DrandScheme::Chained.verify(&pk, 2, &chained, &[2; 96]));
+        let unchained = sk.sign(&message(&[], 2), G2_CYPHER_SUITE, &[]).compress();
+        assert!(DrandScheme::Unchained.verify(&pk, 2, &unchained, &[]));
+        assert!(!DrandScheme::Unchained.verify(&pk, 2, &chained, &[]));
+
+        let sk = min_sig::SecretKey::key_gen(&[7; 32], &[]).unwrap();
+        let pk = sk.sk_to_pk().compress();
+        let signature = sk.sign(&message(&[], 2), G1_CYPHER_SUITE, &[]).compress();
+        assert!(DrandScheme::UnchainedG1.verify(&pk, 2, &signature, &[]));
+        assert!(!DrandScheme::UnchainedG1.verify(&pk, 3, &signature, &[]));
+        assert!(!DrandScheme::Unchained.verify(&pk, 2, &signature, &[]));
+    }
+}
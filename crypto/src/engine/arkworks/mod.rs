@@ -8,7 +8,11 @@ mod hashing;
 mod zcash_format;
 
 use self::endomorphism::{g1_mul_glv, g1_subgroup_check, g2_subgroup_check};
-use super::Engine;
+use super::{
+    batch_security_bits, mask_bits,
+    precompute::{self, G2_LINES_BYTES},
+    Engine,
+};
 use crate::{
     engine::arkworks::hashing::{
         hash_to_curve::{HashToCurve, MapToCurveBasedHasher, WBMap},
@@ -22,7 +26,8 @@ use ark_bls12_381::{
 use ark_ec::{
     msm::VariableBaseMSM, wnaf::WnafContext, AffineCurve, PairingEngine, ProjectiveCurve,
 };
-use ark_ff::{BigInteger, One, PrimeField, UniformRand, Zero};
+use ark_ff::{BigInteger, One, PrimeField, Zero};
+use once_cell::sync::Lazy;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use rayon::prelude::*;
@@ -35,6 +40,11 @@ use tracing::instrument;
 /// optimizations.
 pub struct Arkworks;
 
+type G2Prepared = <Bls12_381 as PairingEngine>::G2Prepared;
+
+static G2_GENERATOR_PREPARED: Lazy<G2Prepared> =
+    Lazy::new(|| G2Affine::prime_subgroup_generator().into());
+
 impl Engine for Arkworks {
     #[instrument(level = "info", skip_all, fields(n=points.len()))]
     fn validate_g1(points: &[G1]) -> Result<(), CeremonyError> {
@@ -69,9 +79,7 @@ impl Engine for Arkworks {
         let tau = G1Affine::try_from(tau)?;
         let previous = G1Affine::try_from(previous)?;
         let pubkey = G2Affine::try_from(pubkey)?;
-        if Bls12_381::pairing(tau, G2Affine::prime_subgroup_generator())
-            != Bls12_381::pairing(previous, pubkey)
-        {
+        if !pairings_are_equal(tau, previous, pubkey) {
             return Err(CeremonyError::PubKeyPairingFailed);
         }
         Ok(())
@@ -87,14 +95,12 @@ impl Engine for Arkworks {
         let tau = G2Affine::try_from(tau)?;
 
         // Compute random linear combination
-        let (factors, sum) = random_factors(powers.len() - 1);
+        let factors = random_factors(powers.len() - 1, batch_security_bits());
         let lhs_g1 = VariableBaseMSM::multi_scalar_mul(&powers[1..], &factors[..]);
-        let lhs_g2 = G2Affine::prime_subgroup_generator().mul(sum);
         let rhs_g1 = VariableBaseMSM::multi_scalar_mul(&powers[..factors.len()], &factors[..]);
-        let rhs_g2 = tau.mul(sum);
 
-        // Check pairing
-        if Bls12_381::pairing(lhs_g1, lhs_g2) != Bls12_381::pairing(rhs_g1, rhs_g2) {
+        // Check e(lhs_g1, g2) = e(rhs_g1, tau) with a single multi-pairing
+        if !pairings_are_equal(lhs_g1.into_affine(), rhs_g1.into_affine(), tau) {
             return Err(CeremonyError::G1PairingFailed);
         }
         Ok(())
@@ -115,14 +121,16 @@ impl Engine for Arkworks {
             .collect::<Result<Vec<_>, _>>()?;
 
         // Compute random linear combination
-        let (factors, sum) = random_factors(g2.len());
+        let factors = random_factors(g2.len(), batch_security_bits());
         let lhs_g1 = VariableBaseMSM::multi_scalar_mul(&g1, &factors[..]);
-        let lhs_g2 = G2Affine::prime_subgroup_generator().mul(sum);
-        let rhs_g1 = G1Affine::prime_subgroup_generator().mul(sum);
         let rhs_g2 = VariableBaseMSM::multi_scalar_mul(&g2, &factors[..]);
 
-        // Check pairing
-        if Bls12_381::pairing(lhs_g1, lhs_g2) != Bls12_381::pairing(rhs_g1, rhs_g2) {
+        // Check e(lhs_g1, g2) = e(g1, rhs_g2) with a single multi-pairing
+        if !pairings_are_equal(
+            lhs_g1.into_affine(),
+            G1Affine::prime_subgroup_generator(),
+            rhs_g2.into_affine(),
+        ) {
             return Err(CeremonyError::G2PairingFailed);
         }
         Ok(())
@@ -223,10 +231,62 @@ impl Engine for Arkworks {
             _ => return false,
         };
 
-        let c1 = Bls12_381::pairing(msg, pk);
-        let c2 = Bls12_381::pairing(sig, G2Affine::prime_subgroup_generator());
+        pairings_are_equal(sig, msg, pk)
+    }
+
+    fn aggregate_signatures(sigs: &[G1]) -> Option<G1> {
+        if sigs.is_empty() {
+            return None;
+        }
+        let mut sum = G1Projective::zero();
+        for &sig in sigs {
+            let sig = G1Affine::try_from(sig).ok()?;
+            if !g1_subgroup_check(&sig) {
+                return None;
+            }
+            sum.add_assign_mixed(&sig);
+        }
+        Some(G1::from(sum.into_affine()))
+    }
+
+    fn verify_aggregate_signature(sig: G1, messages: &[&[u8]], pk: G2) -> bool {
+        if messages.is_empty() {
+            return false;
+        }
+        let sig = match G1Affine::try_from(sig) {
+            Ok(sig) => sig,
+            _ => return false,
+        };
+        if !g1_subgroup_check(&sig) {
+            return false;
+        }
+        let pk = match G2Affine::try_from(pk) {
+            Ok(pk) => pk,
+            _ => return false,
+        };
+        if !g2_subgroup_check(&pk) {
+            return false;
+        }
+        let mapper = match MapToCurveBasedHasher::<
+            G1Parameters,
+            DefaultFieldHasher<Sha256, 128>,
+            WBMap<G1Parameters>,
+        >::new(Self::CYPHER_SUITE.as_bytes())
+        {
+            Ok(mapper) => mapper,
+            _ => return false,
+        };
+
+        // With a single key, e(Σ sig, g2) = Π e(H(m), pk) = e(Σ H(m), pk)
+        let mut sum = G1Projective::zero();
+        for message in messages {
+            match mapper.hash(message) {
+                Ok(msg) => sum.add_assign_mixed(&msg),
+                _ => return false,
+            }
+        }
 
-        c1 == c2
+        pairings_are_equal(sig, sum.into_affine(), pk)
     }
 }
 
@@ -243,17 +303,37 @@ pub fn powers_of_tau(tau: &Tau, n: usize) -> SecretVec<Fr> {
     )
 }
 
-fn random_factors(n: usize) -> (Vec<<Fr as PrimeField>::BigInt>, Fr) {
+/// Samples `n` random scalars of `bits` bits each.
+pub(super) fn random_factors(n: usize, bits: usize) -> Vec<<Fr as PrimeField>::BigInt> {
     let mut rng = rand::thread_rng();
-    let mut sum = Fr::zero();
-    let factors = iter::from_fn(|| {
-        let r = Fr::rand(&mut rng);
-        sum += r;
-        Some(r.0)
+    iter::from_fn(|| {
+        let mut bytes = [0_u8; 32];
+        rng.fill(&mut bytes);
+        mask_bits(&mut bytes, bits);
+        Some(Fr::from_le_bytes_mod_order(&bytes).into_repr())
     })
     .take(n)
-    .collect::<Vec<_>>();
-    (factors, sum)
+    .collect()
+}
+
+/// The G2 generator prepared for pairings, from the precomputed table if it
+/// fits the budget.
+fn g2_generator_prepared() -> G2Prepared {
+    if precompute::fits(G2_LINES_BYTES) {
+        G2_GENERATOR_PREPARED.clone()
+    } else {
+        G2Affine::prime_subgroup_generator().into()
+    }
+}
+
+/// Checks `e(a1, g2) = e(a2, b2)` for the G2 generator `g2` by computing
+/// `e(a1, g2) · e(-a2, b2)` with a single final exponentiation.
+fn pairings_are_equal(a1: G1Affine, a2: G1Affine, b2: G2Affine) -> bool {
+    Bls12_381::product_of_pairings(&[
+        (a1.into(), g2_generator_prepared()),
+        ((-a2).into(), b2.into()),
+    ])
+    .is_one()
 }
 
 impl From<&F> for Fr {
@@ -284,7 +364,9 @@ pub mod test {
     #[allow(clippy::missing_panics_doc)]
     pub fn arb_fr() -> impl Strategy<Value = Fr> {
         any::<U256>().prop_map(|mut n| {
-            n %= uint!(52435875175126190479447740508185965837690552500527637822603658699938581184513_U256);
+            n %= uint!(
+                52435875175126190479447740508185965837690552500527637822603658699938581184513_U256
+            );
             Fr::from_repr(BigInteger256::from(n)).expect("n is smaller than modulus")
         })
     }
@@ -303,6 +385,7 @@ pub mod test {
 pub mod bench {
     use super::{super::bench::bench_engine, *};
     use ark_ec::ProjectiveCurve;
+    use ark_ff::UniformRand;
     use criterion::Criterion;
 
     pub fn group(criterion: &mut Criterion) {
@@ -0,0 +1,41 @@
+//! Security parameter of the batch verification in [`Engine::verify_g1`] and
+//! [`Engine::verify_g2`].
+//!
+//! All points are checked at once using a random linear combination with
+//! factors of this many bits, so invalid points go unnoticed with
+//! probability at most `2^-bits`. Fewer bits make the multi-scalar
+//! multiplications faster.
+//!
+//! [`Engine::verify_g1`]: super::Engine::verify_g1
+//! [`Engine::verify_g2`]: super::Engine::verify_g2
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default for [`set_batch_security_bits`].
+pub const DEFAULT_BATCH_SECURITY_BITS: usize = 128;
+
+/// Smallest value for [`set_batch_security_bits`]. Below it, forging a
+/// contribution that passes by chance becomes feasible.
+pub const MIN_BATCH_SECURITY_BITS: usize = 64;
+
+/// Largest value for [`set_batch_security_bits`], so that the factors are
+/// below the group order.
+pub const MAX_BATCH_SECURITY_BITS: usize = 255;
+
+static BITS: AtomicUsize = AtomicUsize::new(DEFAULT_BATCH_SECURITY_BITS);
+
+/// Sets the number of bits of the random factors in batch verification.
+/// Values outside of [`MIN_BATCH_SECURITY_BITS`] to
+/// [`MAX_BATCH_SECURITY_BITS`] are clamped.
+pub fn set_batch_security_bits(bits: usize) {
+    BITS.store(
+        bits.clamp(MIN_BATCH_SECURITY_BITS, MAX_BATCH_SECURITY_BITS),
+        Ordering::Relaxed,
+    );
+}
+
+/// The number of bits of the random factors in batch verification.
+#[must_use]
+pub fn batch_security_bits() -> usize {
+    BITS.load(Ordering::Relaxed)
+}
@@ -69,7 +69,13 @@ pub fn p2s_to_affine(ps: &[blst_p2]) -> Vec<blst_p2_affine> {
     out
 }
 
-pub fn p2s_mult_pippenger(bases: &[blst_p2_affine], scalars: &[blst_scalar]) -> blst_p2_affine {
+/// Computes the multi-scalar product of `bases` and `scalars`, using only the
+/// lowest `nbits` bits of each scalar.
+pub fn p2s_mult_pippenger(
+    bases: &[blst_p2_affine],
+    scalars: &[blst_scalar],
+    nbits: usize,
+) -> blst_p2_affine {
     assert_eq!(bases.len(), scalars.len());
     if bases.is_empty() {
         // NOTE: Without this special case the `blst_p1s_mult_pippenger` will
@@ -106,7 +112,7 @@ pub fn p2s_mult_pippenger(bases: &[blst_p2_affine], scalars: &[blst_scalar]) ->
             points_ptrs.as_ptr(),
             npoints,
             scalar_ptrs.as_ptr().cast(),
-            256,
+            nbits,
             scratch.as_mut_ptr(),
         );
         blst_p2_to_affine(&mut ret, &msm_result);
@@ -156,7 +162,7 @@ mod tests {
                 }).collect::<Vec<_>>();
 
                 // Compute dot product
-                let result = p2s_mult_pippenger(base.as_slice(), scalars.as_slice());
+                let result = p2s_mult_pippenger(base.as_slice(), scalars.as_slice(), 256);
 
                 // Check result
                 assert_eq!(p2_from_affine(&result), expected);
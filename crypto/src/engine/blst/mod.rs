@@ -4,19 +4,25 @@ mod scalar;
 
 use self::{
     g1::{p1_affine_in_g1, p1_from_affine, p1_mult, p1s_mult_pippenger, p1s_to_affine},
-    g2::{p2_affine_in_g2, p2_from_affine, p2_mult, p2_to_affine, p2s_to_affine},
-    scalar::{fr_from_scalar, fr_mul, fr_one, random_fr, scalar_from_fr},
+    g2::{p2_affine_in_g2, p2_from_affine, p2_mult, p2s_to_affine},
+    scalar::{fr_mul, fr_one, random_fr, scalar_from_fr},
+};
+use super::{
+    batch_security_bits, mask_bits,
+    precompute::{self, G2_LINES_BYTES},
 };
 use crate::{
     engine::blst::{g1::p1_to_affine, g2::p2s_mult_pippenger},
     CeremonyError, Engine, Entropy, ParseError, Tau, G1, G2,
 };
 use blst::{
-    blst_core_verify_pk_in_g2, blst_final_exp, blst_fp12, blst_fr, blst_fr_add, blst_hash_to_g1,
-    blst_miller_loop, blst_p1, blst_p1_affine, blst_p1_generator, blst_p2_affine,
-    blst_p2_affine_generator, blst_p2_generator, blst_scalar, blst_scalar_from_le_bytes,
-    blst_sign_pk_in_g2, BLST_ERROR,
+    blst_core_verify_pk_in_g2, blst_final_exp, blst_fp12, blst_fp12_is_one, blst_fp12_mul,
+    blst_fp6, blst_hash_to_g1, blst_miller_loop, blst_miller_loop_lines, blst_p1,
+    blst_p1_add_or_double, blst_p1_add_or_double_affine, blst_p1_affine, blst_p1_affine_generator,
+    blst_p1_cneg, blst_p2_affine, blst_p2_affine_generator, blst_precompute_lines, blst_scalar,
+    blst_scalar_from_le_bytes, blst_sign_pk_in_g2, BLST_ERROR,
 };
+use once_cell::sync::Lazy;
 use rand::Rng;
 use rayon::prelude::{
     IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator,
@@ -27,6 +33,12 @@ use std::iter;
 
 pub struct BLST;
 
+static G2_GENERATOR_LINES: Lazy<Vec<blst_fp6>> = Lazy::new(|| {
+    let mut lines = vec![blst_fp6::default(); 68];
+    unsafe { blst_precompute_lines(lines.as_mut_ptr(), blst_p2_affine_generator()) };
+    lines
+});
+
 impl Engine for BLST {
     fn generate_tau(entropy: &Entropy) -> Tau {
         // TODO: Use `blst_keygen` or one of its versions (EIP-2333).
@@ -121,11 +133,8 @@ impl Engine for BLST {
         let previous = blst_p1_affine::try_from(previous)?;
         let pubkey = blst_p2_affine::try_from(pubkey)?;
 
-        unsafe {
-            let g2 = *blst_p2_affine_generator();
-            if pairing(&tau, &g2) != pairing(&previous, &pubkey) {
-                return Err(CeremonyError::PubKeyPairingFailed);
-            }
+        if !pairings_are_equal(&tau, &previous, &pubkey) {
+            return Err(CeremonyError::PubKeyPairingFailed);
         }
         Ok(())
     }
@@ -137,20 +146,16 @@ impl Engine for BLST {
             .map(|p| blst_p1_affine::try_from(*p))
             .collect::<Result<Vec<_>, _>>()?;
         let tau = blst_p2_affine::try_from(tau)?;
-        let tau = p2_from_affine(&tau);
 
         // Compute random linear combination
-        let (factors, sum) = random_factors(powers.len() - 1);
-        let g2 = unsafe { *blst_p2_generator() };
-
-        let lhs_g1 = p1s_mult_pippenger(&powers[1..], &factors[..]);
-        let lhs_g2 = p2_to_affine(&p2_mult(&g2, &sum));
+        let bits = batch_security_bits();
+        let factors = random_factors(powers.len() - 1, bits);
 
-        let rhs_g1 = p1s_mult_pippenger(&powers[..factors.len()], &factors[..]);
-        let rhs_g2 = p2_to_affine(&p2_mult(&tau, &sum));
+        let lhs_g1 = p1s_mult_pippenger(&powers[1..], &factors[..], bits);
+        let rhs_g1 = p1s_mult_pippenger(&powers[..factors.len()], &factors[..], bits);
 
-        // Check pairing
-        if pairing(&lhs_g1, &lhs_g2) != pairing(&rhs_g1, &rhs_g2) {
+        // Check e(lhs_g1, g2) = e(rhs_g1, tau) with a single multi-pairing
+        if !pairings_are_equal(&lhs_g1, &rhs_g1, &tau) {
             return Err(CeremonyError::G1PairingFailed);
         }
 
@@ -172,19 +177,16 @@ impl Engine for BLST {
             .collect::<Result<Vec<_>, _>>()?;
 
         // Compute random linear combination
-        let (factors, sum) = random_factors(g2.len());
-        let g1_generator = unsafe { *blst_p1_generator() };
-        let g2_generator = unsafe { *blst_p2_generator() };
+        let bits = batch_security_bits();
+        let factors = random_factors(g2.len(), bits);
+        let g1_generator = unsafe { *blst_p1_affine_generator() };
 
-        let lhs_g1 = p1s_mult_pippenger(&g1, &factors[..]);
-        let lhs_g2 = p2_to_affine(&p2_mult(&g2_generator, &sum));
+        let lhs_g1 = p1s_mult_pippenger(&g1, &factors[..], bits);
+        let rhs_g2 = p2s_mult_pippenger(&g2, &factors[..], bits);
 
-        let rhs_g1 = p1_to_affine(&p1_mult(&g1_generator, &sum));
-        let rhs_g2 = p2s_mult_pippenger(&g2, &factors[..]);
-
-        // Check pairing
-        if pairing(&lhs_g1, &lhs_g2) != pairing(&rhs_g1, &rhs_g2) {
-            return Err(CeremonyError::G1PairingFailed);
+        // Check e(lhs_g1, g2) = e(g1, rhs_g2) with a single multi-pairing
+        if !pairings_are_equal(&lhs_g1, &g1_generator, &rhs_g2) {
+            return Err(CeremonyError::G2PairingFailed);
         }
 
         Ok(())
@@ -233,16 +235,63 @@ impl Engine for BLST {
         };
         result == BLST_ERROR::BLST_SUCCESS
     }
-}
 
-fn pairing(p: &blst_p1_affine, q: &blst_p2_affine) -> blst_fp12 {
-    let mut tmp = blst_fp12::default();
-    unsafe { blst_miller_loop(&mut tmp, q, p) };
+    fn aggregate_signatures(sigs: &[G1]) -> Option<G1> {
+        let mut sum: Option<blst_p1> = None;
+        for &sig in sigs {
+            let sig = blst_p1_affine::try_from(sig).ok()?;
+            if !p1_affine_in_g1(&sig) {
+                return None;
+            }
+            sum = Some(match sum {
+                Some(sum) => {
+                    let mut out = blst_p1::default();
+                    unsafe { blst_p1_add_or_double_affine(&mut out, &sum, &sig) };
+                    out
+                }
+                None => p1_from_affine(&sig),
+            });
+        }
+        G1::try_from(sum?).ok()
+    }
 
-    let mut out = blst_fp12::default();
-    unsafe { blst_final_exp(&mut out, &tmp) };
+    fn verify_aggregate_signature(sig: G1, messages: &[&[u8]], pk: G2) -> bool {
+        if messages.is_empty() {
+            return false;
+        }
+        let sig = match blst_p1_affine::try_from(sig).ok() {
+            Some(sig) if p1_affine_in_g1(&sig) => sig,
+            _ => return false,
+        };
+        let pk = match blst_p2_affine::try_from(pk).ok() {
+            Some(pk) if p2_affine_in_g2(&pk) => pk,
+            _ => return false,
+        };
 
-    out
+        // With a single key, e(Σ sig, g2) = Π e(H(m), pk) = e(Σ H(m), pk)
+        let mut sum = blst_p1::default();
+        for (i, message) in messages.iter().enumerate() {
+            let mut hash = blst_p1::default();
+            unsafe {
+                blst_hash_to_g1(
+                    &mut hash,
+                    message.as_ptr(),
+                    message.len(),
+                    Self::CYPHER_SUITE.as_ptr(),
+                    Self::CYPHER_SUITE.len(),
+                    [0; 0].as_ptr(),
+                    0,
+                );
+                if i == 0 {
+                    sum = hash;
+                } else {
+                    blst_p1_add_or_double(&mut sum, &sum, &hash);
+                }
+            }
+        }
+
+        pairings_are_equal(&sig, &p1_to_affine(&sum), &pk)
+    }
 }
 
 // TODO: Ideally we return `SecretVec` here, but `blst_fr` is not Zeroize.
@@ -254,26 +303,54 @@ fn powers_of_tau(tau: &Tau, n: usize) -> Vec<blst_scalar> {
         .collect()
 }
 
-fn random_factors(n: usize) -> (Vec<blst_scalar>, blst_scalar) {
+/// Miller loop of `p` and the G2 generator, using the precomputed lines if
+/// they fit the budget.
+fn generator_miller_loop(p: &blst_p1_affine) -> blst_fp12 {
+    let mut out = blst_fp12::default();
+    unsafe {
+        if precompute::fits(G2_LINES_BYTES) {
+            blst_miller_loop_lines(&mut out, G2_GENERATOR_LINES.as_ptr(), p);
+        } else {
+            blst_miller_loop(&mut out, blst_p2_affine_generator(), p);
+        }
+    }
+    out
+}
+
+/// Checks `e(a1, g2) = e(a2, b2)` for the G2 generator `g2` by computing
+/// `e(a1, g2) · e(-a2, b2)` with a single final exponentiation.
+fn pairings_are_equal(a1: &blst_p1_affine, a2: &blst_p1_affine, b2: &blst_p2_affine) -> bool {
+    let mut neg_a2 = p1_from_affine(a2);
+    unsafe { blst_p1_cneg(&mut neg_a2, true) };
+    let neg_a2 = p1_to_affine(&neg_a2);
+
+    let mut lhs = generator_miller_loop(a1);
+    let mut rhs = blst_fp12::default();
+    let mut out = blst_fp12::default();
+    unsafe {
+        blst_miller_loop(&mut rhs, b2, &neg_a2);
+        blst_fp12_mul(&mut lhs, &lhs, &rhs);
+        blst_final_exp(&mut out, &lhs);
+        blst_fp12_is_one(&out)
+    }
+}
+
+/// Samples `n` random scalars of `bits` bits each.
+pub(super) fn random_factors(n: usize, bits: usize) -> Vec<blst_scalar> {
     let mut rng = rand::thread_rng();
     let mut entropy = [0u8; 32];
 
-    let mut sum = blst_fr::default();
-    let factors = iter::from_fn(|| {
+    iter::from_fn(|| {
         let mut scalar = blst_scalar::default();
         rng.fill(&mut entropy);
+        mask_bits(&mut entropy, bits);
         unsafe {
             blst_scalar_from_le_bytes(&mut scalar, entropy.as_ptr(), entropy.len());
         }
-
-        let r = fr_from_scalar(&scalar);
-        unsafe { blst_fr_add(&mut sum, &sum, &r) };
-        Some(scalar_from_fr(&r))
+        Some(scalar)
     })
     .take(n)
-    .collect::<Vec<_>>();
-
-    (factors, scalar_from_fr(&sum))
+    .collect()
 }
 
 #[cfg(test)]
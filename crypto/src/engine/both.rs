@@ -92,4 +92,22 @@ impl<A: Engine, B: Engine> Engine for Both<A, B> {
         assert_eq!(a, b);
         a
     }
+
+    fn aggregate_signatures(sigs: &[G1]) -> Option<G1> {
+        let (a, b) = join(
+            || A::aggregate_signatures(sigs),
+            || B::aggregate_signatures(sigs),
+        );
+        assert_eq!(a, b);
+        a
+    }
+
+    fn verify_aggregate_signature(sig: G1, messages: &[&[u8]], pk: G2) -> bool {
+        let (a, b) = join(
+            || A::verify_aggregate_signature(sig, messages, pk),
+            || B::verify_aggregate_signature(sig, messages, pk),
+        );
+        assert_eq!(a, b);
+        a
+    }
 }
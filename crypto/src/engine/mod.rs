@@ -9,9 +9,11 @@
 
 #[cfg(feature = "arkworks")]
 mod arkworks;
+mod batch;
 #[cfg(feature = "blst")]
 mod blst;
 mod both;
+mod precompute;
 
 use crate::{CeremonyError, F, G1, G2};
 pub use secrecy::Secret;
@@ -20,7 +22,14 @@ pub use secrecy::Secret;
 pub use self::arkworks::Arkworks;
 #[cfg(feature = "blst")]
 pub use self::blst::BLST;
-pub use self::both::Both;
+pub use self::{
+    batch::{
+        batch_security_bits, set_batch_security_bits, DEFAULT_BATCH_SECURITY_BITS,
+        MAX_BATCH_SECURITY_BITS, MIN_BATCH_SECURITY_BITS,
+    },
+    both::Both,
+    precompute::{set_precompute_budget, DEFAULT_PRECOMPUTE_BUDGET},
+};
 
 pub type Entropy = Secret<[u8; 32]>;
 pub type Tau = Secret<F>;
@@ -45,9 +54,15 @@ pub trait Engine {
     fn verify_pubkey(tau: G1, previous: G1, pubkey: G2) -> Result<(), CeremonyError>;
 
     /// Verify that `powers` contains a sequence of powers of `tau`.
+    ///
+    /// The check is batched into a single multi-pairing, see
+    /// [`set_batch_security_bits`].
     fn verify_g1(powers: &[G1], tau: G2) -> Result<(), CeremonyError>;
 
     /// Verify that `g1` and `g2` contain the same values.
+    ///
+    /// The check is batched into a single multi-pairing, see
+    /// [`set_batch_security_bits`].
     fn verify_g2(g1: &[G1], g2: &[G2]) -> Result<(), CeremonyError>;
 
     /// Derive a secret scalar $τ$ from the given entropy.
@@ -64,12 +79,34 @@ pub trait Engine {
 
     /// Verify a `CYPHER_SUITE` signature.
     fn verify_signature(sig: G1, message: &[u8], pk: G2) -> bool;
+
+    /// Sum `CYPHER_SUITE` signatures into one. Returns `None` if there are
+    /// none, or one of them is not a valid point in the prime order subgroup.
+    fn aggregate_signatures(sigs: &[G1]) -> Option<G1>;
+
+    /// Verify an aggregate of `CYPHER_SUITE` signatures of `messages`, all
+    /// under `pk`, with a single pairing check. Nothing is verified without
+    /// messages.
+    fn verify_aggregate_signature(sig: G1, messages: &[&[u8]], pk: G2) -> bool;
+}
+
+/// Clears all but the lowest `bits` bits of a little endian number.
+#[cfg(any(feature = "arkworks", feature = "blst"))]
+fn mask_bits(bytes: &mut [u8], bits: usize) {
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let low = i * 8;
+        if bits <= low {
+            *byte = 0;
+        } else if bits < low + 8 {
+            *byte &= (1_u8 << (bits - low)) - 1;
+        }
+    }
 }
 
 #[cfg(all(test, feature = "arkworks", feature = "blst"))]
 pub mod tests {
     use super::*;
-    use proptest::{proptest, strategy::Strategy};
+    use proptest::{arbitrary::any, collection::vec, proptest, strategy::Strategy};
 
     pub fn arb_f() -> impl Strategy<Value = F> {
         arkworks::test::arb_fr().prop_map(F::from)
@@ -97,6 +134,92 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn test_mask_bits() {
+        let mut bytes = [0xff_u8; 4];
+        mask_bits(&mut bytes, 12);
+        assert_eq!(bytes, [0xff, 0x0f, 0, 0]);
+        mask_bits(&mut bytes, 0);
+        assert_eq!(bytes, [0; 4]);
+    }
+
+    #[test]
+    fn test_batch_security_bits() {
+        use ark_ff::BigInteger;
+
+        // Still plenty for the tests that verify meanwhile
+        set_batch_security_bits(64);
+        assert_eq!(batch_security_bits(), 64);
+        let factors = super::arkworks::random_factors(256, batch_security_bits());
+        assert!(factors.iter().all(|factor| factor.num_bits() <= 64));
+        assert!(factors.iter().any(|factor| factor.num_bits() > 56));
+        let factors = super::blst::random_factors(256, batch_security_bits());
+        assert!(factors.iter().all(|factor| factor.b[8..] == [0; 24]));
+
+        set_batch_security_bits(1000);
+        assert_eq!(batch_security_bits(), MAX_BATCH_SECURITY_BITS);
+        set_batch_security_bits(1);
+        assert_eq!(batch_security_bits(), MIN_BATCH_SECURITY_BITS);
+        set_batch_security_bits(DEFAULT_BATCH_SECURITY_BITS);
+    }
+
+    fn test_verify_powers<E: Engine>() {
+        proptest!(|(tau in arb_f())| {
+            let tau = Secret::new(tau);
+            let mut g1 = vec![G1::one(); 16];
+            let mut g2 = vec![G2::one(); 16];
+            Arkworks::add_tau_g1(&tau, &mut g1).unwrap();
+            Arkworks::add_tau_g2(&tau, &mut g2).unwrap();
+
+            E::verify_g1(&g1, g2[1]).unwrap();
+            E::verify_g2(&g1, &g2).unwrap();
+
+            // Swapping two powers must be noticed
+            g1.swap(3, 4);
+            assert_eq!(E::verify_g1(&g1, g2[1]), Err(CeremonyError::G1PairingFailed));
+            assert_eq!(E::verify_g2(&g1, &g2), Err(CeremonyError::G2PairingFailed));
+        });
+    }
+
+    #[test]
+    fn test_verify_powers_arkworks() {
+        test_verify_powers::<Arkworks>();
+    }
+
+    #[test]
+    fn test_verify_powers_blst() {
+        test_verify_powers::<BLST>();
+    }
+
+    fn test_verify_pubkey<E: Engine>() {
+        proptest!(|(tau in arb_f(), previous in arb_g1())| {
+            let tau = Secret::new(tau);
+            let mut g1 = [previous];
+            let mut g2 = [G2::one()];
+            Arkworks::add_tau_g1(&tau, &mut g1).unwrap();
+            Arkworks::add_tau_g2(&tau, &mut g2).unwrap();
+
+            // The precomputed tables must not change the outcome. Other tests
+            // may run without tables meanwhile, which is just as correct.
+            for budget in [DEFAULT_PRECOMPUTE_BUDGET, 0] {
+                set_precompute_budget(budget);
+                E::verify_pubkey(g1[0], previous, g2[0]).unwrap();
+                assert!(E::verify_pubkey(previous, g1[0], g2[0]).is_err());
+            }
+            set_precompute_budget(DEFAULT_PRECOMPUTE_BUDGET);
+        });
+    }
+
+    #[test]
+    fn test_verify_pubkey_arkworks() {
+        test_verify_pubkey::<Arkworks>();
+    }
+
+    #[test]
+    fn test_verify_pubkey_blst() {
+        test_verify_pubkey::<BLST>();
+    }
+
     #[test]
     fn test_add_tau_g2() {
         proptest!(|(tau in arb_f(), p in arb_g2())| {
@@ -110,6 +233,75 @@ pub mod tests {
             assert_eq!(points1, points2);
         });
     }
+
+    #[test]
+    fn test_validate_differential() {
+        // Flipping a bit of a valid point gives any mix of invalid encodings,
+        // points off the curve and points outside the subgroup.
+        proptest!(|(
+            mut g1 in arb_g1(),
+            mut g2 in arb_g2(),
+            g1_bit in 0_usize..384,
+            g2_bit in 0_usize..768
+        )| {
+            g1.0[g1_bit / 8] ^= 1 << (g1_bit % 8);
+            assert_eq!(
+                BLST::validate_g1(&[g1]).is_ok(),
+                Arkworks::validate_g1(&[g1]).is_ok()
+            );
+
+            g2.0[g2_bit / 8] ^= 1 << (g2_bit % 8);
+            assert_eq!(
+                BLST::validate_g2(&[g2]).is_ok(),
+                Arkworks::validate_g2(&[g2]).is_ok()
+            );
+        });
+    }
+
+    #[test]
+    fn test_signatures_differential() {
+        proptest!(|(tau in arb_f(), message in vec(any::<u8>(), 0..64))| {
+            let tau = Secret::new(tau);
+            let mut pk = [G2::one()];
+            Arkworks::add_tau_g2(&tau, &mut pk).unwrap();
+
+            let signature = BLST::sign_message(&tau, &message);
+            assert_eq!(signature, Arkworks::sign_message(&tau, &message));
+            if let Some(signature) = signature {
+                assert!(BLST::verify_signature(signature, &message, pk[0]));
+                assert!(Arkworks::verify_signature(signature, &message, pk[0]));
+
+                let other = [&message[..], b"!"].concat();
+                assert!(!BLST::verify_signature(signature, &other, pk[0]));
+                assert!(!Arkworks::verify_signature(signature, &other, pk[0]));
+            }
+        });
+    }
+
+    #[test]
+    fn test_aggregate_signatures_differential() {
+        proptest!(|(tau in arb_f(), messages in vec(vec(any::<u8>(), 0..64), 1..4))| {
+            let tau = Secret::new(tau);
+            let mut pk = [G2::one(), G2::one()];
+            Arkworks::add_tau_g2(&tau, &mut pk).unwrap();
+            let messages = messages.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+            let sigs = messages
+                .iter()
+                .map(|message| Arkworks::sign_message(&tau, message).unwrap())
+                .collect::<Vec<_>>();
+            let aggregate = BLST::aggregate_signatures(&sigs).unwrap();
+            assert_eq!(Some(aggregate), Arkworks::aggregate_signatures(&sigs));
+            assert!(BLST::verify_aggregate_signature(aggregate, &messages, pk[1]));
+            assert!(Arkworks::verify_aggregate_signature(aggregate, &messages, pk[1]));
+
+            let fewer = &messages[1..];
+            assert!(!BLST::verify_aggregate_signature(aggregate, fewer, pk[1]));
+            assert!(!Arkworks::verify_aggregate_signature(aggregate, fewer, pk[1]));
+        });
+        assert_eq!(BLST::aggregate_signatures(&[]), None);
+        assert_eq!(Arkworks::aggregate_signatures(&[]), None);
+    }
 }
 
 #[cfg(feature = "bench")]
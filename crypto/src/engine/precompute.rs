@@ -0,0 +1,31 @@
+//! Tables that are computed once and reused by every verification.
+//!
+//! All pairing checks pair against the G2 generator, so the engines keep its
+//! Miller loop line coefficients instead of recomputing them for each check.
+//! The powers of the previous contribution are not tabulated: a contribution
+//! replaces all of them, so nothing computed from them would be used twice.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default for [`set_precompute_budget`], enough for the generator table.
+pub const DEFAULT_PRECOMPUTE_BUDGET: usize = 1 << 20;
+
+/// Size of the line coefficients of one G2 point: 68 steps of three `Fp2`
+/// elements each.
+#[cfg(any(feature = "arkworks", feature = "blst"))]
+pub(crate) const G2_LINES_BYTES: usize = 68 * 3 * 96;
+
+static BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_PRECOMPUTE_BUDGET);
+
+/// Sets how many bytes each engine may keep in precomputed tables. Tables
+/// that don't fit are computed on the fly for each use. With 0, nothing is
+/// precomputed.
+pub fn set_precompute_budget(bytes: usize) {
+    BUDGET.store(bytes, Ordering::Relaxed);
+}
+
+/// True if an engine may use a table of `bytes` bytes.
+#[cfg(any(feature = "arkworks", feature = "blst"))]
+pub(crate) fn fits(bytes: usize) -> bool {
+    bytes <= BUDGET.load(Ordering::Relaxed)
+}
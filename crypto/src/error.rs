@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use strum::IntoStaticStr;
 use thiserror::Error;
 
@@ -5,12 +6,18 @@ pub trait ErrorCode {
     fn to_error_code(&self) -> String;
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Error, IntoStaticStr)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Error, IntoStaticStr, Serialize, Deserialize)]
 pub enum CeremoniesError {
     #[error("Unexpected number of contributions: expected {0}, got {1}")]
     UnexpectedNumContributions(usize, usize),
     #[error("Error in contribution {0}: {1}")]
     InvalidCeremony(usize, #[source] CeremonyError),
+    #[error("The ceremony is finalized by a random beacon")]
+    BeaconApplied,
+    #[error("Beacon contribution does not match the beacon value")]
+    InvalidBeacon,
+    #[error("Invalid entropy attestation")]
+    InvalidEntropyAttestation,
 }
 
 impl ErrorCode for CeremoniesError {
@@ -23,7 +30,7 @@ impl ErrorCode for CeremoniesError {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Error, IntoStaticStr)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Error, IntoStaticStr, Serialize, Deserialize)]
 pub enum CeremonyError {
     #[error("Unsupported number of G1 powers: {0}")]
     UnsupportedNumG1Powers(usize),
@@ -81,6 +88,14 @@ pub enum CeremonyError {
     ContributionNoEntropy,
     #[error("Mismatch in witness length: {0} products and {1} pubkeys")]
     WitnessLengthMismatch(usize, usize),
+    #[error("Witness entry {0} does not follow from the previous one or the powers")]
+    InvalidWitness(usize),
+    #[error("Witness entry {0} is not part of the segment")]
+    WitnessEntryMissing(usize),
+    #[error("Witness entry {0} has a different potPubkey")]
+    PubkeyNotIncluded(usize),
+    #[error("Lagrange basis does not match the G1 powers")]
+    InvalidLagrangeBasis,
 }
 
 impl ErrorCode for CeremonyError {
@@ -89,7 +104,7 @@ impl ErrorCode for CeremonyError {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Error, IntoStaticStr)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Error, IntoStaticStr, Serialize, Deserialize)]
 pub enum ParseError {
     #[error("Invalid x coordinate")]
     BigIntError,
@@ -1,6 +1,6 @@
 //! BLS12-381 group elements in ZCash encoding.
 
-use crate::hex_format::{bytes_to_hex, hex_to_bytes};
+use crate::hex_format::{batch_bytes_to_hex, batch_hex_to_bytes, bytes_to_hex, hex_to_bytes};
 use hex_literal::hex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use zeroize::Zeroize;
@@ -101,3 +101,32 @@ impl<'de> Deserialize<'de> for G2 {
         hex_to_bytes(deserializer).map(Self)
     }
 }
+
+/// Serde for lists of G1 points, decoding and encoding them in parallel. Use
+/// with `#[serde(with = "g1_list")]`.
+pub(crate) mod g1_list {
+    use super::{batch_bytes_to_hex, batch_hex_to_bytes, G1};
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(points: &[G1], serializer: S) -> Result<S::Ok, S::Error> {
+        batch_bytes_to_hex::<_, _, 48, 98>(serializer, points, |point| point.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<G1>, D::Error> {
+        batch_hex_to_bytes::<_, _, 48, 98>(deserializer, G1)
+    }
+}
+
+/// Like [`g1_list`], for G2 points.
+pub(crate) mod g2_list {
+    use super::{batch_bytes_to_hex, batch_hex_to_bytes, G2};
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(points: &[G2], serializer: S) -> Result<S::Ok, S::Error> {
+        batch_bytes_to_hex::<_, _, 96, 194>(serializer, points, |point| point.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<G2>, D::Error> {
+        batch_hex_to_bytes::<_, _, 96, 194>(deserializer, G2)
+    }
+}
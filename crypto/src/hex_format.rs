@@ -2,9 +2,14 @@ use crate::hex_format::HexDecodingError::{
     DecoderError, InvalidCharacter, InvalidLength, MissingPrefix,
 };
 use hex::FromHexError;
-use serde::{de, Deserializer, Serializer};
+use rayon::prelude::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 
+/// Least number of points a parallel task encodes or decodes, so that small
+/// lists aren't split up.
+const MIN_BATCH: usize = 256;
+
 /// Allocation free hex serializer with `0x` prefix.
 ///
 /// Constant generic `N` is the length of the byte array. The value
@@ -74,6 +79,141 @@ pub fn hex_str_to_bytes<const N: usize>(value: &str) -> Result<[u8; N], HexDecod
     Ok(result)
 }
 
+/// Like [`hex_str_to_bytes`], but decoding with SIMD where available.
+fn fast_hex_to_bytes<const N: usize>(value: &[u8]) -> Result<[u8; N], HexDecodingError> {
+    let mut result = [0_u8; N];
+    if value.len() != 2 + 2 * N {
+        return Err(InvalidLength(2 + 2 * N));
+    }
+    if &value[..2] != b"0x" {
+        return Err(MissingPrefix);
+    }
+    if !value[2..]
+        .iter()
+        .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return Err(InvalidCharacter);
+    }
+    faster_hex::hex_decode(&value[2..], &mut result).map_err(|_| InvalidCharacter)?;
+    Ok(result)
+}
+
+/// Serializes a list of points as `0x` prefixed hex strings, encoding them
+/// in parallel first. Equivalent to serializing each point with
+/// [`bytes_to_hex`], which non human readable formats still do.
+///
+/// Constant generic `N` is the length of a point. The value `M` must be set
+/// to `2 + 2 * N`.
+pub fn batch_bytes_to_hex<S, T, const N: usize, const M: usize>(
+    serializer: S,
+    points: &[T],
+    bytes: impl Fn(&T) -> [u8; N] + Sync,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize + Sync,
+{
+    assert_eq!(2 + 2 * N, M);
+    if !serializer.is_human_readable() {
+        return serializer.collect_seq(points);
+    }
+    let mut hex = vec![[0_u8; M]; points.len()];
+    hex.par_iter_mut()
+        .zip(points)
+        .with_min_len(MIN_BATCH)
+        .for_each(|(hex, point)| {
+            hex[0] = b'0';
+            hex[1] = b'x';
+            faster_hex::hex_encode(&bytes(point), &mut hex[2..])
+                .expect("BUG: output buffer is of the correct size");
+        });
+    serializer.collect_seq(
+        hex.iter()
+            .map(|hex| std::str::from_utf8(hex).expect("BUG: hex is valid UTF-8")),
+    )
+}
+
+/// Deserializes a list of `0x` prefixed hex strings, e.g. the powers of a
+/// transcript. The strings are only copied while parsing, and decoded in
+/// parallel afterwards, which is far faster for long lists than decoding
+/// each point with [`hex_to_bytes`]. Non human readable formats still do
+/// the latter.
+///
+/// Constant generic `N` is the length of a point. The value `M` must be set
+/// to `2 + 2 * N`.
+pub fn batch_hex_to_bytes<'de, D, T, const N: usize, const M: usize>(
+    deserializer: D,
+    from_bytes: impl Fn([u8; N]) -> T + Sync + Send,
+) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Send,
+{
+    assert_eq!(2 + 2 * N, M);
+    if !deserializer.is_human_readable() {
+        return Vec::deserialize(deserializer);
+    }
+    let hex = deserializer.deserialize_seq(HexSeqVisitor::<M>)?;
+    hex.par_iter()
+        .with_min_len(MIN_BATCH)
+        .map(|hex| fast_hex_to_bytes::<N>(hex).map(&from_bytes))
+        .collect::<Result<_, _>>()
+        .map_err(<D::Error as de::Error>::custom)
+}
+
+/// Collects a sequence of strings of length `M`, without decoding them.
+struct HexSeqVisitor<const M: usize>;
+
+impl<'de, const M: usize> de::Visitor<'de> for HexSeqVisitor<M> {
+    type Value = Vec<[u8; M]>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of hex strings starting with `0x`")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut result = Vec::new();
+        while let Some(RawHex(hex)) = seq.next_element()? {
+            result.push(hex);
+        }
+        Ok(result)
+    }
+}
+
+/// A hex string of length `M`, copied as is.
+struct RawHex<const M: usize>([u8; M]);
+
+impl<'de, const M: usize> Deserialize<'de> for RawHex<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(RawHexVisitor::<M>)
+    }
+}
+
+struct RawHexVisitor<const M: usize>;
+
+impl<'de, const M: usize> de::Visitor<'de> for RawHexVisitor<M> {
+    type Value = RawHex<M>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a hex string of length {M} starting with `0x`")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        let mut result = [0_u8; M];
+        if value.len() != M {
+            return Err(E::custom(InvalidLength(M)));
+        }
+        result.copy_from_slice(value.as_bytes());
+        Ok(RawHex(result))
+    }
+}
+
 /// Serde Visitor for human readable formats. Requires `0x` prefix, but is
 /// otherwise case insensitive.
 struct StrVisitor<const N: usize>;
@@ -6,24 +6,35 @@
 mod batch_contribution;
 mod batch_transcript;
 mod contribution;
+#[cfg(feature = "blst")]
+pub mod drand;
 mod engine;
 mod error;
 mod group;
 mod hex_format;
+mod metadata;
 mod powers;
 pub mod signature;
 mod transcript;
+mod trusted_setup;
 
 pub use crate::{
     batch_contribution::{get_pot_pubkeys, BatchContribution},
     batch_transcript::BatchTranscript,
-    contribution::Contribution,
-    engine::{Engine, Entropy, Secret, Tau},
+    contribution::{Contribution, VerificationTimings},
+    engine::{
+        batch_security_bits, set_batch_security_bits, set_precompute_budget, Engine, Entropy,
+        Secret, Tau, DEFAULT_BATCH_SECURITY_BITS, DEFAULT_PRECOMPUTE_BUDGET,
+        MAX_BATCH_SECURITY_BITS, MIN_BATCH_SECURITY_BITS,
+    },
     error::{CeremoniesError, CeremonyError, ErrorCode, ParseError},
     group::{F, G1, G2},
+    metadata::{
+        engine_name, ContributionMetadata, EntropyAttestation, EntropySource, TranscriptMetadata,
+    },
     powers::Powers,
     signature::identity::Identity,
-    transcript::Transcript,
+    transcript::{verify_chain_segment, verify_inclusion, Transcript, WitnessSegment},
 };
 
 pub use crate::engine::Both;
@@ -34,6 +45,9 @@ pub use crate::engine::Arkworks;
 #[cfg(feature = "blst")]
 pub use crate::engine::BLST;
 
+#[cfg(feature = "eip4844")]
+pub use crate::trusted_setup::TrustedSetup;
+
 #[cfg(all(feature = "arkworks", feature = "blst"))]
 pub type DefaultEngine = Both<Arkworks, BLST>;
 
@@ -57,6 +71,7 @@ pub mod bench {
         engine::bench::group(criterion);
         batch_contribution::bench::group(criterion);
         batch_transcript::bench::group(criterion);
+        powers::bench::group(criterion);
     }
 
     #[must_use]
@@ -0,0 +1,142 @@
+use crate::{
+    hex_format::{bytes_to_hex, hex_to_bytes},
+    CeremoniesError, Engine,
+};
+use serde::{Deserialize, Serialize, Serializer};
+use std::any::type_name;
+
+/// Describes how a transcript was produced, for auditors. Not part of the
+/// ceremony output itself.
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct TranscriptMetadata {
+    /// Version of the sequencer that processed the latest contribution.
+    pub sequencer_version:   String,
+    /// Engine that verified the latest contribution.
+    pub verification_engine: String,
+    /// Processing details of each contribution, in order.
+    pub contributions:       Vec<ContributionMetadata>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ContributionMetadata {
+    /// Index of the contribution in `participantIds`.
+    pub participant:         usize,
+    /// Unix timestamp of when the contribution was received.
+    pub received_at:         u64,
+    /// Unix timestamp of when the contribution was verified.
+    pub verified_at:         u64,
+    /// The entropy sources the contributor's client reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entropy_attestation: Option<EntropyAttestation>,
+}
+
+/// The entropy sources a client reports to have mixed into its secret. It is
+/// not verified in any way, and only helps analyzing how diverse the
+/// contributions were.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntropyAttestation {
+    pub sources: Vec<EntropySource>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntropySource {
+    /// Kind of the source, e.g. `os-rng` or `mouse`.
+    pub kind: String,
+    /// SHA256 hash of the input taken from the source.
+    #[serde(serialize_with = "hash_to_hex", deserialize_with = "hex_to_bytes")]
+    pub hash: [u8; 32],
+}
+
+fn hash_to_hex<S: Serializer>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    bytes_to_hex::<_, 32, 66>(serializer, *hash)
+}
+
+impl EntropyAttestation {
+    pub const MAX_KIND_LENGTH: usize = 32;
+    pub const MAX_SOURCES: usize = 16;
+
+    /// Checks that the attestation is small and only uses lower case
+    /// alphanumeric characters and `-` in source kinds.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CeremoniesError::InvalidEntropyAttestation`] otherwise.
+    pub fn validate(&self) -> Result<(), CeremoniesError> {
+        let valid_kind = |kind: &str| {
+            !kind.is_empty()
+                && kind.len() <= Self::MAX_KIND_LENGTH
+                && kind
+                    .bytes()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-')
+        };
+        if self.sources.len() > Self::MAX_SOURCES
+            || !self.sources.iter().all(|source| valid_kind(&source.kind))
+        {
+            return Err(CeremoniesError::InvalidEntropyAttestation);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the name of an engine without module paths, e.g.
+/// `Both<Arkworks, BLST>`.
+#[must_use]
+pub fn engine_name<E: Engine>() -> String {
+    let mut name = String::new();
+    for part in type_name::<E>().split_inclusive(|c| matches!(c, '<' | '>' | ',' | ' ')) {
+        name.push_str(part.rsplit("::").next().unwrap_or(part));
+    }
+    name
+}
+
+#[cfg(all(test, feature = "arkworks", feature = "blst"))]
+mod tests {
+    use super::*;
+    use crate::{Arkworks, Both, BLST};
+
+    #[test]
+    fn validates_entropy_attestation() {
+        let source = |kind: &str| EntropySource {
+            kind: kind.to_string(),
+            hash: [1; 32],
+        };
+        let attestation = EntropyAttestation {
+            sources: vec![source("os-rng"), source("mouse")],
+        };
+        assert!(attestation.validate().is_ok());
+        assert_eq!(
+            serde_json::to_value(&attestation.sources[0]).unwrap()["hash"],
+            format!("0x{}", "01".repeat(32))
+        );
+
+        for kind in ["", "OS RNG", &"a".repeat(33)] {
+            let attestation = EntropyAttestation {
+                sources: vec![source(kind)],
+            };
+            assert_eq!(
+                attestation.validate(),
+                Err(CeremoniesError::InvalidEntropyAttestation)
+            );
+        }
+        let attestation = EntropyAttestation {
+            sources: vec![source("os-rng"); EntropyAttestation::MAX_SOURCES + 1],
+        };
+        assert_eq!(
+            attestation.validate(),
+            Err(CeremoniesError::InvalidEntropyAttestation)
+        );
+    }
+
+    #[test]
+    fn test_engine_name() {
+        assert_eq!(engine_name::<Arkworks>(), "Arkworks");
+        assert_eq!(
+            engine_name::<Both<Arkworks, BLST>>(),
+            "Both<Arkworks, BLST>"
+        );
+    }
+}
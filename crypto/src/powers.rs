@@ -1,4 +1,5 @@
 use super::{CeremonyError, G1, G2};
+use crate::group::{g1_list, g2_list};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -19,7 +20,9 @@ struct PowersJson {
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase", deny_unknown_fields)]
 struct PowersOfTau {
+    #[serde(with = "g1_list")]
     g1_powers: Vec<G1>,
+    #[serde(with = "g2_list")]
     g2_powers: Vec<G2>,
 }
 
@@ -69,3 +72,63 @@ impl Powers {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn batch_matches_per_point_encoding() {
+        // Enough points to be split up between tasks
+        let mut powers = Powers::new(1000, 3);
+        for (i, point) in powers.g1.iter_mut().enumerate() {
+            point.0[47] = u8::try_from(i % 256).unwrap();
+        }
+        powers.g2[1].0[95] = 0xab;
+
+        let value = serde_json::to_value(&powers).unwrap();
+        let expected = json!({
+            "G1Powers": serde_json::to_value(&powers.g1).unwrap(),
+            "G2Powers": serde_json::to_value(&powers.g2).unwrap(),
+        });
+        assert_eq!(value["powersOfTau"], expected);
+        assert_eq!(serde_json::from_value::<Powers>(value).unwrap(), powers);
+    }
+
+    #[test]
+    fn batch_rejects_invalid_points() {
+        let with_g1 = |point: &str| {
+            let mut value = serde_json::to_value(Powers::new(2, 1)).unwrap();
+            value["powersOfTau"]["G1Powers"][1] = Value::from(point);
+            serde_json::from_value::<Powers>(value)
+        };
+        let valid = serde_json::to_value(G1::one()).unwrap();
+        let valid = valid.as_str().unwrap();
+        assert!(with_g1(valid).is_ok());
+        assert!(with_g1(&valid[..96]).is_err());
+        assert!(with_g1(&format!("00{}", &valid[2..])).is_err());
+        assert!(with_g1(&valid.to_uppercase().replace("0X", "0x")).is_err());
+        assert!(with_g1(&valid.replace('9', "g")).is_err());
+    }
+}
+
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    use super::*;
+    use crate::bench::BATCH_SIZE;
+    use criterion::{black_box, Criterion};
+
+    pub fn group(criterion: &mut Criterion) {
+        let (num_g1, num_g2) = BATCH_SIZE[BATCH_SIZE.len() - 1];
+        let powers = Powers::new(num_g1, num_g2);
+        let json = serde_json::to_string(&powers).unwrap();
+        criterion.bench_function(&format!("powers/serialize/{num_g1}"), |bencher| {
+            bencher.iter(|| black_box(serde_json::to_string(black_box(&powers)).unwrap()));
+        });
+        criterion.bench_function(&format!("powers/deserialize/{num_g1}"), |bencher| {
+            bencher.iter(|| black_box(serde_json::from_str::<Powers>(black_box(&json)).unwrap()));
+        });
+    }
+}
@@ -5,8 +5,37 @@ use thiserror::Error;
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Identity {
     None,
-    Ethereum { address: [u8; 20] },
-    Github { id: u64, username: String },
+    Ethereum {
+        address: [u8; 20],
+    },
+    Github {
+        id:       u64,
+        username: String,
+    },
+    Twitter {
+        id:       u64,
+        username: String,
+    },
+    Discord {
+        id:       u64,
+        username: String,
+    },
+    /// A salted hash standing in for another identity, for participants who
+    /// contribute pseudonymously.
+    Pseudonym {
+        hash: [u8; 32],
+    },
+    /// A participant who signed in with a passkey registered at the
+    /// sequencer.
+    Passkey {
+        id: [u8; 16],
+    },
+    /// A public random beacon applied as the final contribution, such as a
+    /// drand round or a block hash.
+    Beacon {
+        source: String,
+        value:  Vec<u8>,
+    },
 }
 
 impl Identity {
@@ -37,6 +66,11 @@ impl Identity {
         match self {
             Self::Ethereum { address } => format!("0x{}", hex::encode(address)),
             Self::Github { username, .. } => username.to_string(),
+            Self::Twitter { username, .. } => format!("@{username}"),
+            Self::Discord { username, .. } => username.to_string(),
+            Self::Pseudonym { hash } => format!("anon-{}", hex::encode(&hash[..4])),
+            Self::Passkey { id } => format!("passkey-{}", hex::encode(&id[..4])),
+            Self::Beacon { source, .. } => format!("beacon-{source}"),
             Self::None => "<<unauthorized>>".to_string(),
         }
     }
@@ -46,6 +80,11 @@ impl Identity {
         match self {
             Self::Ethereum { .. } => "Ethereum",
             Self::Github { .. } => "Github",
+            Self::Twitter { .. } => "Twitter",
+            Self::Discord { .. } => "Discord",
+            Self::Pseudonym { .. } => "Pseudonym",
+            Self::Passkey { .. } => "Passkey",
+            Self::Beacon { .. } => "Beacon",
             Self::None => "None",
         }
         .to_string()
@@ -64,6 +103,16 @@ pub enum IdentityError {
     InvalidEthereumAddress,
     #[error("Invalid Github ID")]
     InvalidGithubId,
+    #[error("Invalid Twitter ID")]
+    InvalidTwitterId,
+    #[error("Invalid Discord ID")]
+    InvalidDiscordId,
+    #[error("Invalid pseudonym")]
+    InvalidPseudonym,
+    #[error("Invalid passkey user id")]
+    InvalidPasskeyId,
+    #[error("Invalid beacon value")]
+    InvalidBeacon,
 }
 
 impl Display for Identity {
@@ -72,6 +121,11 @@ impl Display for Identity {
             Self::None => write!(f, ""),
             Self::Ethereum { address } => write!(f, "eth|0x{}", hex::encode(address)),
             Self::Github { id, username } => write!(f, "git|{id}|{username}"),
+            Self::Twitter { id, username } => write!(f, "twitter|{id}|{username}"),
+            Self::Discord { id, username } => write!(f, "discord|{id}|{username}"),
+            Self::Pseudonym { hash } => write!(f, "pseudo|0x{}", hex::encode(hash)),
+            Self::Passkey { id } => write!(f, "passkey|0x{}", hex::encode(id)),
+            Self::Beacon { source, value } => write!(f, "beacon|{source}|0x{}", hex::encode(value)),
         }
     }
 }
@@ -110,6 +164,73 @@ impl FromStr for Identity {
 
                 Ok(Self::Github { id, username })
             }
+            Some("twitter") => {
+                let id = parts.next().ok_or(IdentityError::MissingField)?;
+                let username = parts.next().ok_or(IdentityError::MissingField)?;
+                if parts.next().is_some() {
+                    return Err(IdentityError::TooManyFields);
+                }
+
+                let id = id.parse().map_err(|_| IdentityError::InvalidTwitterId)?;
+                let username = username.to_string();
+
+                Ok(Self::Twitter { id, username })
+            }
+            Some("discord") => {
+                let id = parts.next().ok_or(IdentityError::MissingField)?;
+                let username = parts.next().ok_or(IdentityError::MissingField)?;
+                if parts.next().is_some() {
+                    return Err(IdentityError::TooManyFields);
+                }
+
+                let id = id.parse().map_err(|_| IdentityError::InvalidDiscordId)?;
+                let username = username.to_string();
+
+                Ok(Self::Discord { id, username })
+            }
+            Some("pseudo") => {
+                let hash = parts.next().ok_or(IdentityError::MissingField)?;
+                if parts.next().is_some() {
+                    return Err(IdentityError::TooManyFields);
+                }
+
+                let hash = hash
+                    .strip_prefix("0x")
+                    .and_then(|hash| hex::decode(hash).ok())
+                    .and_then(|hash| hash.try_into().ok())
+                    .ok_or(IdentityError::InvalidPseudonym)?;
+
+                Ok(Self::Pseudonym { hash })
+            }
+            Some("passkey") => {
+                let id = parts.next().ok_or(IdentityError::MissingField)?;
+                if parts.next().is_some() {
+                    return Err(IdentityError::TooManyFields);
+                }
+
+                let id = id
+                    .strip_prefix("0x")
+                    .and_then(|id| hex::decode(id).ok())
+                    .and_then(|id| id.try_into().ok())
+                    .ok_or(IdentityError::InvalidPasskeyId)?;
+
+                Ok(Self::Passkey { id })
+            }
+            Some("beacon") => {
+                let source = parts.next().ok_or(IdentityError::MissingField)?;
+                let value = parts.next().ok_or(IdentityError::MissingField)?;
+                if parts.next().is_some() {
+                    return Err(IdentityError::TooManyFields);
+                }
+
+                let source = source.to_string();
+                let value = value
+                    .strip_prefix("0x")
+                    .and_then(|value| hex::decode(value).ok())
+                    .ok_or(IdentityError::InvalidBeacon)?;
+
+                Ok(Self::Beacon { source, value })
+            }
             Some("") => {
                 if parts.next().is_some() {
                     return Err(IdentityError::TooManyFields);
@@ -175,4 +296,74 @@ mod tests {
         assert_eq!(identity.to_string(), "git|123|username");
         assert_eq!(identity, "git|123|username".parse().unwrap());
     }
+
+    #[test]
+    fn test_twitter() {
+        let identity = Identity::Twitter {
+            id:       2_244_994_945,
+            username: "username".to_string(),
+        };
+        assert_eq!(identity.to_string(), "twitter|2244994945|username");
+        assert_eq!(identity, "twitter|2244994945|username".parse().unwrap());
+        assert_eq!(identity.nickname(), "@username");
+        assert_eq!(
+            "twitter|username|2244994945".parse::<Identity>(),
+            Err(IdentityError::InvalidTwitterId)
+        );
+    }
+
+    #[test]
+    fn test_discord() {
+        let identity = Identity::Discord {
+            id:       80_351_110_224_678_912,
+            username: "username".to_string(),
+        };
+        assert_eq!(identity.to_string(), "discord|80351110224678912|username");
+        assert_eq!(
+            identity,
+            "discord|80351110224678912|username".parse().unwrap()
+        );
+        assert_eq!(identity.nickname(), "username");
+    }
+
+    #[test]
+    fn test_pseudonym() {
+        let identity = Identity::Pseudonym { hash: [0xab; 32] };
+        let encoded = format!("pseudo|0x{}", "ab".repeat(32));
+        assert_eq!(identity.to_string(), encoded);
+        assert_eq!(identity, encoded.parse().unwrap());
+        assert_eq!(identity.nickname(), "anon-abababab");
+        assert_eq!(
+            "pseudo|0xabab".parse::<Identity>(),
+            Err(IdentityError::InvalidPseudonym)
+        );
+    }
+
+    #[test]
+    fn test_passkey() {
+        let identity = Identity::Passkey { id: [0xcd; 16] };
+        let encoded = format!("passkey|0x{}", "cd".repeat(16));
+        assert_eq!(identity.to_string(), encoded);
+        assert_eq!(identity, encoded.parse().unwrap());
+        assert_eq!(identity.nickname(), "passkey-cdcdcdcd");
+        assert_eq!(
+            "passkey|0xcdcd".parse::<Identity>(),
+            Err(IdentityError::InvalidPasskeyId)
+        );
+    }
+
+    #[test]
+    fn test_beacon() {
+        let identity = Identity::Beacon {
+            source: "drand".to_string(),
+            value:  vec![0x12, 0x34],
+        };
+        assert_eq!(identity.to_string(), "beacon|drand|0x1234");
+        assert_eq!(identity, "beacon|drand|0x1234".parse().unwrap());
+        assert_eq!(identity.nickname(), "beacon-drand");
+        assert_eq!(
+            "beacon|drand|1234".parse::<Identity>(),
+            Err(IdentityError::InvalidBeacon)
+        );
+    }
 }
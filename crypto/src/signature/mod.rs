@@ -27,19 +27,41 @@ impl BlsSignature {
 
     #[must_use]
     pub fn prune<E: Engine>(&self, message: &[u8], pk: G2) -> Self {
-        Self(self.0.and_then(|sig| {
-            if E::verify_signature(sig, message, pk) {
-                Some(sig)
-            } else {
-                None
-            }
-        }))
+        Self(self.0.filter(|_| self.verify::<E>(message, pk)))
+    }
+
+    /// True if this is a signature of `message` under `pk`. Empty signatures
+    /// verify nothing.
+    #[must_use]
+    pub fn verify<E: Engine>(&self, message: &[u8], pk: G2) -> bool {
+        self.0
+            .map_or(false, |sig| E::verify_signature(sig, message, pk))
     }
 
     #[must_use]
     pub fn sign<E: Engine>(message: &[u8], sk: &Tau) -> Self {
         Self(E::sign_message(sk, message))
     }
+
+    /// Sums signatures into one, verifiable with [`Self::verify_aggregate`].
+    /// Empty if any of them is.
+    #[must_use]
+    pub fn aggregate<E: Engine>(signatures: &[Self]) -> Self {
+        let sigs = signatures
+            .iter()
+            .map(|sig| sig.0)
+            .collect::<Option<Vec<_>>>();
+        Self(sigs.and_then(|sigs| E::aggregate_signatures(&sigs)))
+    }
+
+    /// True if this is the aggregate of signatures of `messages`, all under
+    /// `pk`.
+    #[must_use]
+    pub fn verify_aggregate<E: Engine>(&self, messages: &[&[u8]], pk: G2) -> bool {
+        self.0.map_or(false, |sig| {
+            E::verify_aggregate_signature(sig, messages, pk)
+        })
+    }
 }
 
 impl Serialize for BlsSignature {
@@ -239,6 +261,33 @@ mod tests {
         let pubkey = tmp[1];
         let recovered = signed.prune::<BothEngines>(wrong_msg, pubkey);
         assert_eq!(recovered, BlsSignature(None));
+        assert!(signed.verify::<BothEngines>(message, pubkey));
+        assert!(!signed.verify::<BothEngines>(wrong_msg, pubkey));
+        assert!(!BlsSignature::empty().verify::<BothEngines>(message, pubkey));
+    }
+
+    #[test]
+    fn test_bls_aggregate() {
+        let messages: [&[u8]; 3] = [b"git|1234|foobar", b"git|4567|bazbaz", b"eth|0x1234"];
+        let tau = BothEngines::generate_tau(&Entropy::new(thread_rng().gen()));
+        let mut tmp = vec![G2::one(), G2::one()];
+        BothEngines::add_tau_g2(&tau, &mut tmp).unwrap();
+        let pubkey = tmp[1];
+        let mut signed = messages
+            .iter()
+            .map(|message| BlsSignature::sign::<BothEngines>(message, &tau))
+            .collect::<Vec<_>>();
+
+        let aggregate = BlsSignature::aggregate::<BothEngines>(&signed);
+        assert!(aggregate.verify_aggregate::<BothEngines>(&messages, pubkey));
+        assert!(!aggregate.verify_aggregate::<BothEngines>(&messages[..2], pubkey));
+        assert!(!aggregate.verify_aggregate::<BothEngines>(&messages, G2::one()));
+
+        signed.push(BlsSignature::empty());
+        assert_eq!(
+            BlsSignature::aggregate::<BothEngines>(&signed),
+            BlsSignature::empty()
+        );
     }
 
     #[test]
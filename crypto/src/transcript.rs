@@ -1,5 +1,9 @@
 use super::{CeremonyError, Contribution, Powers, G1, G2};
-use crate::{engine::Engine, signature::BlsSignature};
+use crate::{
+    engine::Engine,
+    group::{g1_list, g2_list},
+    signature::BlsSignature,
+};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
@@ -13,16 +17,99 @@ pub struct Transcript {
 
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Witness {
-    #[serde(rename = "runningProducts")]
+    #[serde(rename = "runningProducts", with = "g1_list")]
     pub products: Vec<G1>,
 
-    #[serde(rename = "potPubkeys")]
+    #[serde(rename = "potPubkeys", with = "g2_list")]
     pub pubkeys: Vec<G2>,
 
     #[serde(rename = "blsSignatures")]
     pub signatures: Vec<BlsSignature>,
 }
 
+/// Consecutive entries of a transcript's witness, starting at entry `start`.
+/// Enough to check part of the chain of contributions, e.g. one's own,
+/// without the powers or the rest of the witness.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct WitnessSegment {
+    pub start: usize,
+
+    #[serde(rename = "runningProducts", with = "g1_list")]
+    pub products: Vec<G1>,
+
+    #[serde(rename = "potPubkeys", with = "g2_list")]
+    pub pubkeys: Vec<G2>,
+}
+
+impl WitnessSegment {
+    fn entry(&self, index: usize) -> Result<(G1, G2), CeremonyError> {
+        let offset = index
+            .checked_sub(self.start)
+            .ok_or(CeremonyError::WitnessEntryMissing(index))?;
+        match (self.products.get(offset), self.pubkeys.get(offset)) {
+            (Some(product), Some(pubkey)) => Ok((*product, *pubkey)),
+            _ => Err(CeremonyError::WitnessEntryMissing(index)),
+        }
+    }
+}
+
+impl Witness {
+    /// The entries `from` through `to` of the witness, if it has them.
+    #[must_use]
+    pub fn segment(&self, from: usize, to: usize) -> Option<WitnessSegment> {
+        let range = from..to.checked_add(1)?;
+        Some(WitnessSegment {
+            start:    from,
+            products: self.products.get(range.clone())?.to_vec(),
+            pubkeys:  self.pubkeys.get(range)?.to_vec(),
+        })
+    }
+}
+
+/// Verifies that the contribution with `pubkey` is entry `index` of the
+/// witness, and follows from the entry before it, which the segment must
+/// include. Together with [`verify_chain_segment`] up to the last entry,
+/// this shows that the contribution is part of the transcript.
+pub fn verify_inclusion<E: Engine>(
+    pubkey: G2,
+    index: usize,
+    segment: &WitnessSegment,
+) -> Result<(), CeremonyError> {
+    if segment.entry(index)?.1 != pubkey {
+        return Err(CeremonyError::PubkeyNotIncluded(index));
+    }
+    let previous = index
+        .checked_sub(1)
+        .ok_or(CeremonyError::InvalidWitness(index))?;
+    verify_chain_segment::<E>(segment, previous, index)
+}
+
+/// Verifies that the entries `from + 1` through `to` of the witness each
+/// follow from the one before, as [`Transcript::verify_self`] does for the
+/// whole witness. Long chains can be verified in overlapping segments.
+pub fn verify_chain_segment<E: Engine>(
+    segment: &WitnessSegment,
+    from: usize,
+    to: usize,
+) -> Result<(), CeremonyError> {
+    let (mut previous, pubkey) = segment.entry(from)?;
+    if from == 0 && (previous != G1::one() || pubkey != G2::one()) {
+        return Err(CeremonyError::InvalidWitness(0));
+    }
+    for index in from + 1..=to {
+        let (product, pubkey) = segment.entry(index)?;
+        if pubkey == G2::zero() {
+            return Err(CeremonyError::ZeroPubkey);
+        }
+        E::validate_g1(&[product])?;
+        E::validate_g2(&[pubkey])?;
+        E::verify_pubkey(product, previous, pubkey)
+            .map_err(|_| CeremonyError::InvalidWitness(index))?;
+        previous = product;
+    }
+    Ok(())
+}
+
 impl Transcript {
     /// Create a new transcript for a ceremony of a given size.
     ///
@@ -68,45 +155,65 @@ impl Transcript {
     }
 
     /// Verifies a contribution.
-    #[instrument(level = "info", skip_all, fields(n1=self.powers.g1.len(), n2=self.powers.g2.len()))]
     pub fn verify<E: Engine>(&self, contribution: &Contribution) -> Result<(), CeremonyError> {
-        // Compatibility checks
-        if self.powers.g1.len() != contribution.powers.g1.len() {
-            return Err(CeremonyError::UnexpectedNumG1Powers(
-                self.powers.g1.len(),
-                contribution.powers.g1.len(),
-            ));
+        contribution.verify_after::<E>(&self.powers)
+    }
+
+    /// Verifies the transcript on its own, e.g. after reading it from disk:
+    /// all points are valid, the powers are consecutive powers of the same
+    /// secret, and the witness links every contribution to the previous one
+    /// and the last one to the powers. Signatures are not checked, since they
+    /// may have been pruned.
+    #[instrument(level = "info", skip_all, fields(n1=self.powers.g1.len(), n2=self.powers.g2.len()))]
+    pub fn verify_self<E: Engine>(&self) -> Result<(), CeremonyError> {
+        let (num_g1, num_g2) = (self.powers.g1.len(), self.powers.g2.len());
+        if num_g1 < 2 {
+            return Err(CeremonyError::UnsupportedNumG1Powers(num_g1));
+        }
+        if num_g2 < 2 {
+            return Err(CeremonyError::UnsupportedNumG2Powers(num_g2));
         }
-        if self.powers.g2.len() != contribution.powers.g2.len() {
-            return Err(CeremonyError::UnexpectedNumG2Powers(
-                self.powers.g2.len(),
-                contribution.powers.g2.len(),
+        if num_g2 > num_g1 {
+            return Err(CeremonyError::UnsupportedMoreG2Powers(num_g1, num_g2));
+        }
+        let Witness {
+            products, pubkeys, ..
+        } = &self.witness;
+        if products.len() != pubkeys.len() {
+            return Err(CeremonyError::WitnessLengthMismatch(
+                products.len(),
+                pubkeys.len(),
             ));
         }
 
-        // Verify the contribution points (encoding and subgroup checks).
-        E::validate_g1(&contribution.powers.g1)?;
-        E::validate_g2(&contribution.powers.g2)?;
-        E::validate_g2(&[contribution.pot_pubkey])?;
+        // Encoding and subgroup checks
+        E::validate_g1(&self.powers.g1)?;
+        E::validate_g2(&self.powers.g2)?;
+        E::validate_g1(products)?;
+        E::validate_g2(pubkeys)?;
 
-        // Non-zero check
-        if contribution.pot_pubkey == G2::zero() {
-            return Err(CeremonyError::ZeroPubkey);
+        if self.powers.g1.first() != Some(&G1::one()) {
+            return Err(CeremonyError::InvalidG1FirstValue);
+        }
+        if self.powers.g2.first() != Some(&G2::one()) {
+            return Err(CeremonyError::InvalidG2FirstValue);
+        }
+        if products.first() != Some(&G1::one()) || pubkeys.first() != Some(&G2::one()) {
+            return Err(CeremonyError::InvalidWitness(0));
+        }
+        for i in 1..products.len() {
+            if pubkeys[i] == G2::zero() {
+                return Err(CeremonyError::ZeroPubkey);
+            }
+            E::verify_pubkey(products[i], products[i - 1], pubkeys[i])
+                .map_err(|_| CeremonyError::InvalidWitness(i))?;
+        }
+        if products.last() != self.powers.g1.get(1) {
+            return Err(CeremonyError::InvalidWitness(products.len() - 1));
         }
 
-        // Verify pairings.
-        E::verify_pubkey(
-            contribution.powers.g1[1],
-            self.powers.g1[1],
-            contribution.pot_pubkey,
-        )?;
-        E::verify_g1(&contribution.powers.g1, contribution.powers.g2[1])?;
-        E::verify_g2(
-            &contribution.powers.g1[..contribution.powers.g2.len()],
-            &contribution.powers.g2,
-        )?;
-
-        // Accept
+        E::verify_g1(&self.powers.g1, self.powers.g2[1])?;
+        E::verify_g2(&self.powers.g1[..num_g2], &self.powers.g2)?;
         Ok(())
     }
 
@@ -123,6 +230,8 @@ impl Transcript {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{signature::identity::Identity, Arkworks, BatchTranscript};
+    use secrecy::Secret;
 
     #[test]
     fn transcript_json() {
@@ -159,4 +268,45 @@ mod test {
         let deser = serde_json::from_value::<Transcript>(json).unwrap();
         assert_eq!(deser, t);
     }
+
+    #[test]
+    fn verifies_witness_segments() {
+        let mut batch = BatchTranscript::new(&[(4, 2)]);
+        for seed in 1..=3 {
+            let mut contribution = batch.contribution();
+            contribution
+                .add_entropy::<Arkworks>(&Secret::new([seed; 32]), &Identity::None)
+                .unwrap();
+            batch
+                .verify_add::<Arkworks>(contribution, Identity::None)
+                .unwrap();
+        }
+        let witness = &batch.transcripts[0].witness;
+        assert_eq!(witness.segment(2, 4), None);
+        let full = witness.segment(0, 3).unwrap();
+        assert_eq!(verify_chain_segment::<Arkworks>(&full, 0, 3), Ok(()));
+
+        let segment = witness.segment(1, 3).unwrap();
+        assert_eq!(verify_chain_segment::<Arkworks>(&segment, 1, 3), Ok(()));
+        assert_eq!(
+            verify_inclusion::<Arkworks>(witness.pubkeys[2], 2, &segment),
+            Ok(())
+        );
+        assert_eq!(
+            verify_inclusion::<Arkworks>(witness.pubkeys[3], 2, &segment),
+            Err(CeremonyError::PubkeyNotIncluded(2))
+        );
+        // The entry before has to be part of the segment
+        assert_eq!(
+            verify_inclusion::<Arkworks>(witness.pubkeys[1], 1, &segment),
+            Err(CeremonyError::WitnessEntryMissing(0))
+        );
+
+        let mut reordered = segment;
+        reordered.pubkeys.swap(1, 2);
+        assert_eq!(
+            verify_chain_segment::<Arkworks>(&reordered, 1, 3),
+            Err(CeremonyError::InvalidWitness(2))
+        );
+    }
 }
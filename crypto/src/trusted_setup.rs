@@ -0,0 +1,212 @@
+//! Conversion of the ceremony output into the trusted setup that consensus
+//! clients load for EIP-4844.
+
+#![cfg(feature = "eip4844")]
+
+use crate::{Arkworks, CeremonyError, Engine, Powers, G1, G2};
+use ark_bls12_381::{Fr, G1Affine, G1Projective};
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_poly::{EvaluationDomain, Radix2EvaluationDomain};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use tracing::instrument;
+
+/// The powers of tau in the `trusted_setup.json` format of the consensus
+/// specs, with the G1 powers both in monomial and in Lagrange form.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TrustedSetup {
+    #[serde(rename = "setup_G1")]
+    pub g1_monomial: Vec<G1>,
+    #[serde(rename = "setup_G2")]
+    pub g2_monomial: Vec<G2>,
+    /// The G1 powers in the Lagrange basis over the roots of unity of order
+    /// `g1_monomial.len()`, in natural order.
+    #[serde(rename = "setup_G1_lagrange")]
+    pub g1_lagrange: Vec<G1>,
+}
+
+impl TrustedSetup {
+    /// Converts the powers of a ceremony. The number of G1 powers must be a
+    /// power of two.
+    #[instrument(level = "info", skip_all, fields(n1=powers.g1.len(), n2=powers.g2.len()))]
+    pub fn from_powers(powers: &Powers) -> Result<Self, CeremonyError> {
+        Ok(Self {
+            g1_monomial: powers.g1.clone(),
+            g2_monomial: powers.g2.clone(),
+            g1_lagrange: g1_lagrange(&powers.g1)?,
+        })
+    }
+
+    /// Checks the setup the way consensus clients do when loading it: the
+    /// number of points, that all points are valid and in their subgroups,
+    /// and additionally that the Lagrange form and the G2 powers match the
+    /// G1 powers.
+    #[instrument(level = "info", skip_all)]
+    pub fn validate(&self) -> Result<(), CeremonyError> {
+        let num_g1 = self.g1_monomial.len();
+        let num_g2 = self.g2_monomial.len();
+        if num_g1 < 2 || !num_g1.is_power_of_two() {
+            return Err(CeremonyError::UnsupportedNumG1Powers(num_g1));
+        }
+        if num_g2 < 2 {
+            return Err(CeremonyError::UnsupportedNumG2Powers(num_g2));
+        }
+        if num_g2 > num_g1 {
+            return Err(CeremonyError::UnsupportedMoreG2Powers(num_g1, num_g2));
+        }
+        if self.g1_lagrange.len() != num_g1 {
+            return Err(CeremonyError::UnexpectedNumG1Powers(
+                num_g1,
+                self.g1_lagrange.len(),
+            ));
+        }
+        Arkworks::validate_g1(&self.g1_monomial)?;
+        Arkworks::validate_g1(&self.g1_lagrange)?;
+        Arkworks::validate_g2(&self.g2_monomial)?;
+        if self.g1_monomial[0] != G1::one() {
+            return Err(CeremonyError::InvalidG1FirstValue);
+        }
+        if self.g2_monomial[0] != G2::one() {
+            return Err(CeremonyError::InvalidG2FirstValue);
+        }
+        Arkworks::verify_g1(&self.g1_monomial, self.g2_monomial[1])?;
+        Arkworks::verify_g2(&self.g1_monomial[..num_g2], &self.g2_monomial)?;
+        if g1_lagrange(&self.g1_monomial)? != self.g1_lagrange {
+            return Err(CeremonyError::InvalidLagrangeBasis);
+        }
+        Ok(())
+    }
+
+    /// The setup in the `trusted_setup.txt` format read by c-kzg: the number
+    /// of G1 and of G2 points on a line each, followed by the G1 points in
+    /// Lagrange form and the G2 points, one hex encoded point per line.
+    #[must_use]
+    pub fn to_c_kzg_text(&self) -> String {
+        let mut text = format!("{}\n{}\n", self.g1_lagrange.len(), self.g2_monomial.len());
+        for point in &self.g1_lagrange {
+            let _ = writeln!(text, "{}", hex::encode(point.0));
+        }
+        for point in &self.g2_monomial {
+            let _ = writeln!(text, "{}", hex::encode(point.0));
+        }
+        text
+    }
+}
+
+/// The Lagrange form of the G1 powers, which is their inverse FFT.
+fn g1_lagrange(powers: &[G1]) -> Result<Vec<G1>, CeremonyError> {
+    let domain = Radix2EvaluationDomain::<Fr>::new(powers.len())
+        .filter(|domain| domain.size() == powers.len())
+        .ok_or(CeremonyError::UnsupportedNumG1Powers(powers.len()))?;
+    let points = powers
+        .iter()
+        .enumerate()
+        .map(|(i, point)| {
+            G1Affine::try_from(*point)
+                .map(|point| point.into_projective())
+                .map_err(|error| CeremonyError::InvalidG1Power(i, error))
+        })
+        .collect::<Result<Vec<G1Projective>, _>>()?;
+    let mut lagrange = domain.ifft(&points);
+    G1Projective::batch_normalization(&mut lagrange);
+    Ok(lagrange
+        .into_iter()
+        .map(|point| G1::from(point.into_affine()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BatchTranscript, Identity};
+    use ark_ff::{Field, One, PrimeField};
+    use secrecy::Secret;
+
+    fn final_powers() -> Powers {
+        let mut transcript = BatchTranscript::new(&[(8, 3)]);
+        let mut contribution = transcript.contribution();
+        contribution
+            .add_entropy::<Arkworks>(&Secret::new([3; 32]), &Identity::None)
+            .unwrap();
+        transcript
+            .verify_add::<Arkworks>(contribution, Identity::None)
+            .unwrap();
+        transcript.transcripts[0].powers.clone()
+    }
+
+    #[test]
+    fn converts_powers() {
+        let setup = TrustedSetup::from_powers(&final_powers()).unwrap();
+        assert_eq!(setup.validate(), Ok(()));
+
+        // Without a contribution τ = 1, so that the Lagrange form is the
+        // generator times the Lagrange polynomials at 1: a single generator.
+        let setup = TrustedSetup::from_powers(&Powers::new(4, 2)).unwrap();
+        assert_eq!(setup.g1_lagrange[0], G1::one());
+        assert_eq!(setup.g1_lagrange[1..], [G1::zero(); 3]);
+
+        let json = serde_json::to_value(&setup).unwrap();
+        assert_eq!(json["setup_G1"].as_array().unwrap().len(), 4);
+        assert_eq!(json["setup_G2"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            json["setup_G1_lagrange"][0],
+            serde_json::to_value(G1::one()).unwrap()
+        );
+    }
+
+    #[test]
+    fn uses_spec_roots_of_unity() {
+        // The consensus specs use 7 as the primitive root of unity.
+        let domain = Radix2EvaluationDomain::<Fr>::new(4096).unwrap();
+        let exponent = (Fr::from(0_u64) - Fr::one()) * Fr::from(4096_u64).inverse().unwrap();
+        assert_eq!(domain.group_gen, Fr::from(7_u64).pow(exponent.into_repr()));
+    }
+
+    #[test]
+    fn rejects_invalid_setups() {
+        let mut setup = TrustedSetup::from_powers(&final_powers()).unwrap();
+        setup.g1_lagrange.swap(1, 2);
+        assert_eq!(setup.validate(), Err(CeremonyError::InvalidLagrangeBasis));
+        setup.g1_lagrange.pop();
+        assert_eq!(
+            setup.validate(),
+            Err(CeremonyError::UnexpectedNumG1Powers(8, 7))
+        );
+        assert!(TrustedSetup::from_powers(&Powers::new(6, 2)).is_err());
+    }
+
+    /// Reads a setup the way the c-kzg loader does.
+    fn load_c_kzg(text: &str) -> (Vec<G1>, Vec<G2>) {
+        let mut tokens = text.split_whitespace();
+        let num_g1: usize = tokens.next().unwrap().parse().unwrap();
+        let num_g2: usize = tokens.next().unwrap().parse().unwrap();
+        let g1 = (0..num_g1)
+            .map(|_| {
+                G1(hex::decode(tokens.next().unwrap())
+                    .unwrap()
+                    .try_into()
+                    .unwrap())
+            })
+            .collect::<Vec<_>>();
+        let g2 = (0..num_g2)
+            .map(|_| {
+                G2(hex::decode(tokens.next().unwrap())
+                    .unwrap()
+                    .try_into()
+                    .unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert!(tokens.next().is_none());
+        Arkworks::validate_g1(&g1).unwrap();
+        Arkworks::validate_g2(&g2).unwrap();
+        (g1, g2)
+    }
+
+    #[test]
+    fn writes_c_kzg_text() {
+        let setup = TrustedSetup::from_powers(&final_powers()).unwrap();
+        let (g1, g2) = load_c_kzg(&setup.to_c_kzg_text());
+        assert_eq!(g1, setup.g1_lagrange);
+        assert_eq!(g2, setup.g2_monomial);
+    }
+}
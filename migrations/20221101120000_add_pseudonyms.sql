@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS pseudonyms (
+    pseudonym TEXT PRIMARY KEY NOT NULL,
+    uid       TEXT             NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS passkeys (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    passkey TEXT             NOT NULL
+);
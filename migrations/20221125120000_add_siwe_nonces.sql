@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS siwe_nonces (
+    nonce       TEXT    PRIMARY KEY NOT NULL,
+    fingerprint TEXT                NOT NULL,
+    expires_at  INTEGER             NOT NULL,
+    used_at     INTEGER
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS receipts (
+    session_hash TEXT    PRIMARY KEY NOT NULL,
+    participant  INTEGER             NOT NULL,
+    receipt      TEXT                NOT NULL,
+    signature    TEXT                NOT NULL
+);
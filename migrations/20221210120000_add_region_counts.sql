@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS region_counts (
+    region        TEXT    PRIMARY KEY NOT NULL,
+    contributions INTEGER             NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS failed_attempts (
+    id           INTEGER  PRIMARY KEY AUTOINCREMENT,
+    uid          TEXT     NOT NULL,
+    outcome      TEXT     NOT NULL,
+    attempted_at INTEGER  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS failed_attempts_uid ON failed_attempts (uid);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS verified_emails (
+    email_hash TEXT PRIMARY KEY,
+    uid        TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS verified_emails_uid ON verified_emails (uid);
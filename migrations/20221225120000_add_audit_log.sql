@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id        INTEGER  PRIMARY KEY AUTOINCREMENT,
+    event     TEXT     NOT NULL,
+    uid       TEXT     NOT NULL,
+    details   TEXT     NOT NULL,
+    logged_at INTEGER  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_uid ON audit_log (uid);
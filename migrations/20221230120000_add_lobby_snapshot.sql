@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS lobby_snapshot (
+    id       INTEGER PRIMARY KEY CHECK (id = 1),
+    snapshot TEXT    NOT NULL,
+    saved_at INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS staged_contributions (
+    participant  INTEGER PRIMARY KEY,
+    contribution TEXT    NOT NULL,
+    staged_at    INTEGER NOT NULL
+);
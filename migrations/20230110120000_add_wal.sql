@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS wal (
+    id        INTEGER  PRIMARY KEY AUTOINCREMENT,
+    operation TEXT     NOT NULL,
+    uid       TEXT     NOT NULL,
+    payload   TEXT     NOT NULL,
+    logged_at INTEGER  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS wal_operation_uid ON wal (operation, uid);
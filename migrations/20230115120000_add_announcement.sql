@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS announcement (
+    id      INTEGER PRIMARY KEY CHECK (id = 1),
+    message TEXT    NOT NULL,
+    set_at  INTEGER NOT NULL
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS contribution_guard (
+    uid       TEXT     PRIMARY KEY NOT NULL,
+    status    TEXT     NOT NULL,
+    marked_at INTEGER  NOT NULL
+);
+
+INSERT OR IGNORE INTO contribution_guard (uid, status, marked_at)
+SELECT uid, CASE WHEN finished_at IS NULL THEN 'aborted' ELSE 'contributed' END, started_at
+FROM contributors
+ORDER BY finished_at IS NULL;
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS lobby_peaks (
+    day  TEXT    PRIMARY KEY NOT NULL,
+    peak INTEGER             NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS slot_assignments (
+    id           INTEGER  PRIMARY KEY AUTOINCREMENT,
+    rule         TEXT     NOT NULL,
+    candidates   TEXT     NOT NULL,
+    selected     TEXT     NOT NULL,
+    seed         TEXT,
+    beacon_round INTEGER,
+    assigned_at  INTEGER  NOT NULL
+);
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS receipts_participant ON receipts (participant);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS rejected_contributions (
+    id            INTEGER  PRIMARY KEY AUTOINCREMENT,
+    identity_hash TEXT     NOT NULL,
+    code          TEXT     NOT NULL,
+    reason        TEXT     NOT NULL,
+    contribution  TEXT     NOT NULL,
+    rejected_at   INTEGER  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS rejected_contributions_rejected_at ON rejected_contributions (rejected_at);
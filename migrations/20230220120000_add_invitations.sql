@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS invitations (
+    code_hash  TEXT     PRIMARY KEY,
+    max_uses   INTEGER  NOT NULL,
+    uses       INTEGER  NOT NULL DEFAULT 0,
+    created_at INTEGER  NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS invitation_redemptions (
+    uid         TEXT     PRIMARY KEY,
+    code_hash   TEXT     NOT NULL,
+    redeemed_at INTEGER  NOT NULL
+);
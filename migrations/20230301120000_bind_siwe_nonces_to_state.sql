@@ -0,0 +1 @@
+ALTER TABLE siwe_nonces RENAME COLUMN fingerprint TO state_hash;
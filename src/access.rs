@@ -0,0 +1,229 @@
+//! Operator maintained lists of who may contribute. The lists are reloaded
+//! whenever their files change, so abusers can be locked out without a
+//! restart.
+
+use clap::Parser;
+use eyre::{Result as EyreResult, WrapErr};
+use kzg_ceremony_crypto::signature::identity::Identity;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// File listing the only GitHub logins and Ethereum addresses allowed to
+    /// contribute, one per line. Lines starting with `#` are ignored. The
+    /// lists don't apply to passkey, Twitter or Discord users.
+    #[clap(long, env)]
+    pub allowlist_file: Option<PathBuf>,
+
+    /// File listing GitHub logins and Ethereum addresses that may not
+    /// contribute, in the same format as the allowlist. Both lists are
+    /// checked at sign in and when entering the lobby, except for
+    /// pseudonymous users who are only checked at sign in.
+    #[clap(long, env)]
+    pub denylist_file: Option<PathBuf>,
+}
+
+/// A list of GitHub logins and Ethereum addresses, stored in lower case.
+struct List {
+    path:    Option<PathBuf>,
+    entries: RwLock<HashSet<String>>,
+}
+
+impl List {
+    fn load(path: Option<PathBuf>) -> EyreResult<Self> {
+        let entries = path.as_deref().map(read_list).transpose()?;
+        Ok(Self {
+            path,
+            entries: RwLock::new(entries.unwrap_or_default()),
+        })
+    }
+
+    const fn is_configured(&self) -> bool {
+        self.path.is_some()
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.entries
+            .read()
+            .expect("Access list lock poisoned")
+            .contains(key)
+    }
+
+    /// Reloads the list if `changed` is its file. A list that fails to load
+    /// keeps its previous entries.
+    fn reload_if(&self, changed: &Path) {
+        let path = match &self.path {
+            Some(path) if path.file_name() == changed.file_name() => path,
+            _ => return,
+        };
+        match read_list(path) {
+            Ok(entries) => {
+                info!(?path, entries = entries.len(), "Reloaded access list");
+                *self.entries.write().expect("Access list lock poisoned") = entries;
+            }
+            Err(error) => warn!(?path, ?error, "Could not reload access list"),
+        }
+    }
+}
+
+fn read_list(path: &Path) -> EyreResult<HashSet<String>> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("Cannot read access list {path:?}"))?;
+    Ok(parse_list(&contents))
+}
+
+fn parse_list(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The entry that matches an identity, if identities of its kind can be
+/// listed.
+fn list_key(identity: &Identity) -> Option<String> {
+    match identity {
+        Identity::Github { .. } | Identity::Ethereum { .. } => {
+            Some(identity.nickname().to_lowercase())
+        }
+        _ => None,
+    }
+}
+
+struct Lists {
+    allow: List,
+    deny:  List,
+}
+
+pub struct AccessLists {
+    lists:    Arc<Lists>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+pub type SharedAccessLists = Arc<AccessLists>;
+
+impl AccessLists {
+    /// Loads the lists and starts watching their files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a list cannot be read or watched.
+    pub fn new(options: &Options) -> EyreResult<Self> {
+        let lists = Arc::new(Lists {
+            allow: List::load(options.allowlist_file.clone())?,
+            deny:  List::load(options.denylist_file.clone())?,
+        });
+
+        let paths = [&options.allowlist_file, &options.denylist_file]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Ok(Self {
+                lists,
+                _watcher: None,
+            });
+        }
+
+        let watched = lists.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) => {
+                    for path in &event.paths {
+                        watched.allow.reload_if(path);
+                        watched.deny.reload_if(path);
+                    }
+                }
+                Err(error) => warn!(?error, "Error watching access lists"),
+            })?;
+        // Watch the directories, since editors often replace files instead of
+        // writing to them.
+        for path in paths {
+            let directory = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            watcher
+                .watch(directory, RecursiveMode::NonRecursive)
+                .wrap_err_with(|| format!("Cannot watch access list {path:?}"))?;
+        }
+
+        Ok(Self {
+            lists,
+            _watcher: Some(watcher),
+        })
+    }
+
+    /// False if the identity is on the denylist, or if there is an allowlist
+    /// and the identity is not on it.
+    pub fn is_allowed(&self, identity: &Identity) -> bool {
+        list_key(identity).map_or(true, |key| {
+            !self.lists.deny.contains(&key)
+                && (!self.lists.allow.is_configured() || self.lists.allow.contains(&key))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn github(username: &str) -> Identity {
+        Identity::Github {
+            id:       1,
+            username: username.to_string(),
+        }
+    }
+
+    #[test]
+    fn checks_lists() {
+        let dir = tempdir().unwrap();
+        let allowlist = dir.path().join("allowlist");
+        let denylist = dir.path().join("denylist");
+        fs::write(
+            &allowlist,
+            "# contributors\nAlice\nbob\n0x000000000000000000000000000000000000dEaD\n",
+        )
+        .unwrap();
+        fs::write(&denylist, "bob\n").unwrap();
+
+        let lists = AccessLists::new(&Options {
+            allowlist_file: Some(allowlist),
+            denylist_file:  Some(denylist.clone()),
+        })
+        .unwrap();
+        assert!(lists.is_allowed(&github("alice")));
+        assert!(!lists.is_allowed(&github("bob")));
+        assert!(!lists.is_allowed(&github("carol")));
+        assert!(lists.is_allowed(
+            &Identity::eth_from_str("0x000000000000000000000000000000000000dead").unwrap()
+        ));
+        assert!(lists.is_allowed(&Identity::Passkey { id: [0; 16] }));
+
+        fs::write(&denylist, "alice\n").unwrap();
+        lists.lists.deny.reload_if(&denylist);
+        assert!(!lists.is_allowed(&github("alice")));
+        assert!(lists.is_allowed(&github("bob")));
+    }
+
+    #[test]
+    fn allows_everyone_without_lists() {
+        let lists = AccessLists::new(&Options {
+            allowlist_file: None,
+            denylist_file:  None,
+        })
+        .unwrap();
+        assert!(lists.is_allowed(&github("alice")));
+    }
+}
@@ -0,0 +1,84 @@
+//! A message from the operators to all participants, e.g. about maintenance
+//! or a change of policy. It is set through the admin API, kept in storage
+//! so that it survives restarts, and sent along with `/info/status` and
+//! granted contribution slots for clients to show.
+
+use crate::storage::{PersistentStorage, StorageError};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+#[derive(Default)]
+pub struct Announcement {
+    message: RwLock<Option<String>>,
+}
+
+pub type SharedAnnouncement = Arc<Announcement>;
+
+impl Announcement {
+    /// Loads the stored announcement.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if storage fails.
+    pub async fn load(storage: &PersistentStorage) -> Result<Self, StorageError> {
+        Ok(Self {
+            message: RwLock::new(storage.announcement().await?),
+        })
+    }
+
+    pub async fn get(&self) -> Option<String> {
+        self.message.read().await.clone()
+    }
+
+    /// Replaces the announcement, or removes it with `None`. Empty messages
+    /// remove it as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if storage fails, in which case the announcement is
+    /// unchanged.
+    pub async fn set(
+        &self,
+        storage: &PersistentStorage,
+        message: Option<String>,
+    ) -> Result<(), StorageError> {
+        let message = message
+            .map(|message| message.trim().to_owned())
+            .filter(|message| !message.is_empty());
+        let mut current = self.message.write().await;
+        storage.set_announcement(message.as_deref()).await?;
+        *current = message;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::storage_client, test_util::test_options};
+
+    #[tokio::test]
+    async fn keeps_announcement_across_restarts() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+        let announcement = Announcement::load(&storage).await.unwrap();
+        assert_eq!(announcement.get().await, None);
+
+        announcement
+            .set(&storage, Some(" Maintenance at noon ".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            announcement.get().await.as_deref(),
+            Some("Maintenance at noon")
+        );
+        let restarted = Announcement::load(&storage).await.unwrap();
+        assert_eq!(
+            restarted.get().await.as_deref(),
+            Some("Maintenance at noon")
+        );
+
+        restarted.set(&storage, Some(String::new())).await.unwrap();
+        assert_eq!(restarted.get().await, None);
+        assert_eq!(storage.announcement().await.unwrap(), None);
+    }
+}
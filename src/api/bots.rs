@@ -0,0 +1,419 @@
+//! Scoring how automated the use of the lobby by a session looks, as the
+//! `bot-score` check. Every heuristic a session trips adds its points to the
+//! score, which is logged as it changes. Sessions reaching the threshold
+//! are, depending on the options, only logged, deprioritized in the lobby,
+//! or challenged with a proof of work or a captcha.
+
+use crate::{
+    api::{
+        checks::{
+            proof_of_work_bits, AbuseCheckError, CaptchaVerifier, Check, CheckRequest, Options,
+            CAPTCHA_HEADER, PROOF_OF_WORK_HEADER,
+        },
+        fingerprint::{self, fingerprint},
+    },
+    clock::REQUEST_TIME_HEADER,
+    lobby::SharedLobbyState,
+    reporting::session_id_hash,
+    sessions::SessionId,
+};
+use axum::{
+    async_trait,
+    response::{IntoResponse, Response},
+};
+use clap::ValueEnum;
+use eyre::Result as EyreResult;
+use http::{header::USER_AGENT, HeaderMap};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    str::FromStr,
+    time::Duration,
+};
+use strum::{EnumString, IntoStaticStr};
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{info, warn};
+
+/// Number of the last requests of a session the heuristics look at.
+const HISTORY_LENGTH: usize = 16;
+
+/// Number of intervals between requests needed to judge their regularity.
+const MIN_INTERVALS: usize = 8;
+
+/// Ratio of the standard deviation of the intervals between requests to
+/// their mean below which the polling is scripted.
+const MAX_POLL_VARIATION: f64 = 0.01;
+
+/// Interval below which two requests of a session can't have waited for the
+/// response to the other.
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the history of a session that stopped sending requests is kept.
+const HISTORY_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Heuristic {
+    /// The session polls at nearly constant intervals.
+    PollRegularity,
+    /// The User-Agent is missing, or the fingerprint of the client, as bound
+    /// to sessions, changes between requests of the session.
+    Headers,
+    /// Requests arrive too close together to have waited for each other, or
+    /// their request times go back.
+    ImpossibleTiming,
+}
+
+/// The heuristics to score with, and their points.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BotHeuristics(pub Vec<(Heuristic, u32)>);
+
+impl FromStr for BotHeuristics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|heuristic| !heuristic.is_empty())
+            .map(|heuristic| {
+                let (name, points) = heuristic
+                    .split_once('=')
+                    .ok_or_else(|| format!("Heuristic {heuristic} is missing its points"))?;
+                let name = Heuristic::from_str(name.trim())
+                    .map_err(|_| format!("Unknown heuristic {name}"))?;
+                let points = points
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid points for heuristic {name:?}"))?;
+                Ok((name, points))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BotAction {
+    /// Sessions are only logged.
+    Log,
+    /// Sessions only get the slot when nobody else is waiting.
+    Deprioritize,
+    /// Sessions have to send a proof of work with every request.
+    ProofOfWork,
+    /// Sessions have to solve a captcha once.
+    Captcha,
+}
+
+/// What the heuristics know of a session.
+#[derive(Debug, Default)]
+struct History {
+    /// Arrival of the last requests, with the request time the client sent.
+    requests:           VecDeque<(Instant, Option<f64>)>,
+    client_fingerprint: Option<String>,
+    headers_changed:    bool,
+    missing_user_agent: bool,
+    score:              u32,
+    flagged:            bool,
+    solved_captcha:     bool,
+}
+
+impl History {
+    fn observe(&mut self, now: Instant, headers: &HeaderMap, fingerprint: Option<String>) {
+        let request_time = headers
+            .get(REQUEST_TIME_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|time| time.is_finite());
+        if self.requests.len() == HISTORY_LENGTH {
+            self.requests.pop_front();
+        }
+        self.requests.push_back((now, request_time));
+
+        if let Some(fingerprint) = fingerprint {
+            match &self.client_fingerprint {
+                Some(first) => self.headers_changed |= *first != fingerprint,
+                None => self.client_fingerprint = Some(fingerprint),
+            }
+        }
+        self.missing_user_agent |= !headers.contains_key(USER_AGENT);
+    }
+
+    fn last_seen(&self) -> Option<Instant> {
+        self.requests.back().map(|(arrival, _)| *arrival)
+    }
+
+    fn intervals(&self) -> Vec<Duration> {
+        self.requests
+            .iter()
+            .zip(self.requests.iter().skip(1))
+            .map(|((earlier, _), (later, _))| later.saturating_duration_since(*earlier))
+            .collect()
+    }
+}
+
+#[allow(clippy::cast_precision_loss)] // Few intervals
+fn is_regular(intervals: &[Duration]) -> bool {
+    if intervals.len() < MIN_INTERVALS {
+        return false;
+    }
+    let seconds = intervals
+        .iter()
+        .map(Duration::as_secs_f64)
+        .collect::<Vec<_>>();
+    let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+    if mean <= 0.0 {
+        return false;
+    }
+    let variance = seconds
+        .iter()
+        .map(|seconds| (seconds - mean).powi(2))
+        .sum::<f64>()
+        / seconds.len() as f64;
+    variance.sqrt() / mean < MAX_POLL_VARIATION
+}
+
+impl Heuristic {
+    fn trips(self, history: &History) -> bool {
+        match self {
+            Self::PollRegularity => is_regular(&history.intervals()),
+            Self::Headers => history.missing_user_agent || history.headers_changed,
+            Self::ImpossibleTiming => {
+                let too_close = history
+                    .intervals()
+                    .iter()
+                    .any(|interval| *interval < MIN_REQUEST_INTERVAL);
+                let sent = history
+                    .requests
+                    .iter()
+                    .filter_map(|(_, sent)| *sent)
+                    .collect::<Vec<_>>();
+                too_close || sent.windows(2).any(|pair| pair[1] < pair[0])
+            }
+        }
+    }
+}
+
+pub struct BotScore {
+    heuristics:     BotHeuristics,
+    threshold:      u32,
+    action:         BotAction,
+    pow_difficulty: u32,
+    captcha:        Option<CaptchaVerifier>,
+    fingerprint:    fingerprint::Options,
+    lobby_state:    SharedLobbyState,
+    histories:      Mutex<BTreeMap<SessionId, History>>,
+}
+
+impl BotScore {
+    /// # Errors
+    ///
+    /// Returns an error if sessions are to be challenged with a captcha, but
+    /// the verification url or secret is not configured.
+    pub fn new(
+        options: &Options,
+        fingerprint: &fingerprint::Options,
+        lobby_state: SharedLobbyState,
+        http_client: reqwest::Client,
+    ) -> EyreResult<Self> {
+        let captcha = match options.abuse_bot_action {
+            BotAction::Captcha => Some(CaptchaVerifier::new(options, http_client)?),
+            _ => None,
+        };
+        Ok(Self {
+            heuristics: options.abuse_bot_heuristics.clone(),
+            threshold: options.abuse_bot_score_threshold,
+            action: options.abuse_bot_action,
+            pow_difficulty: options.abuse_pow_difficulty,
+            captcha,
+            fingerprint: fingerprint.clone(),
+            lobby_state,
+            histories: Mutex::default(),
+        })
+    }
+
+    /// Records the request and returns the session's score, whether it just
+    /// reached the threshold, and whether it solved a captcha already.
+    /// Sessions that reached the threshold once keep at least its score.
+    async fn score(&self, session_id: &SessionId, headers: &HeaderMap) -> (u32, bool, bool) {
+        let now = Instant::now();
+        let mut histories = self.histories.lock().await;
+        if !histories.contains_key(session_id) {
+            histories.retain(|_, history| {
+                history
+                    .last_seen()
+                    .map_or(false, |last_seen| now < last_seen + HISTORY_TTL)
+            });
+        }
+        let history = histories.entry(session_id.clone()).or_default();
+        history.observe(now, headers, fingerprint(&self.fingerprint, headers));
+
+        let tripped = self
+            .heuristics
+            .0
+            .iter()
+            .filter(|(heuristic, _)| heuristic.trips(history))
+            .collect::<Vec<_>>();
+        let score = tripped.iter().map(|(_, points)| points).sum::<u32>();
+        let session = session_id_hash(session_id);
+        if score != history.score {
+            let heuristics = tripped
+                .iter()
+                .map(|(heuristic, _)| <&str>::from(heuristic))
+                .collect::<Vec<_>>();
+            info!(%session, score, ?heuristics, "Bot score changed");
+            history.score = score;
+        }
+        let newly_flagged = !history.flagged && score >= self.threshold;
+        if newly_flagged {
+            warn!(%session, score, action = ?self.action, "Session looks automated");
+            history.flagged = true;
+        }
+        (
+            if history.flagged {
+                score.max(self.threshold)
+            } else {
+                score
+            },
+            newly_flagged,
+            history.solved_captcha,
+        )
+    }
+}
+
+#[async_trait]
+impl Check for BotScore {
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response> {
+        let session_id = match &request.session_id {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+        let (score, newly_flagged, solved_captcha) = self.score(session_id, request.headers).await;
+        if score < self.threshold {
+            return Ok(());
+        }
+        match self.action {
+            BotAction::Log => Ok(()),
+            BotAction::Deprioritize => {
+                if newly_flagged {
+                    self.lobby_state.deprioritize(session_id).await;
+                }
+                Ok(())
+            }
+            BotAction::ProofOfWork => {
+                let nonce = request
+                    .headers
+                    .get(PROOF_OF_WORK_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or_else(|| AbuseCheckError::MissingProofOfWork.into_response())?;
+                if proof_of_work_bits(session_id, nonce.trim()) < self.pow_difficulty {
+                    return Err(
+                        AbuseCheckError::InvalidProofOfWork(self.pow_difficulty).into_response()
+                    );
+                }
+                Ok(())
+            }
+            BotAction::Captcha => {
+                let verifier = match &self.captcha {
+                    Some(verifier) if !solved_captcha => verifier,
+                    _ => return Ok(()),
+                };
+                let token = request
+                    .headers
+                    .get(CAPTCHA_HEADER)
+                    .and_then(|value| value.to_str().ok());
+                verifier
+                    .verify(token)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                if let Some(history) = self.histories.lock().await.get_mut(session_id) {
+                    history.solved_captcha = true;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::checks::{AbuseChecks, CheckKind, CheckOrder},
+        test_util::{create_test_session_info, shared_access_lists, test_options},
+    };
+    use headers::{Authorization, HeaderMapExt};
+    use http::HeaderValue;
+
+    #[test]
+    fn parses_heuristics() {
+        assert_eq!(
+            BotHeuristics::from_str("headers=10, impossible-timing=5").unwrap(),
+            BotHeuristics(vec![
+                (Heuristic::Headers, 10),
+                (Heuristic::ImpossibleTiming, 5)
+            ])
+        );
+        assert!(BotHeuristics::from_str("headers").is_err());
+        assert!(BotHeuristics::from_str("mouse-movement=10").is_err());
+    }
+
+    #[tokio::test]
+    async fn deprioritizes_scripted_polling() {
+        tokio::time::pause();
+        let mut options = test_options();
+        options.checks.abuse_checks = CheckOrder(vec![CheckKind::BotScore]);
+        options.checks.abuse_bot_action = BotAction::Deprioritize;
+        let lobby_state = SharedLobbyState::new(options.lobby.clone());
+        let chain = AbuseChecks::new(
+            &options.checks,
+            &options.client_version,
+            &options.fingerprint,
+            shared_access_lists(),
+            lobby_state.clone(),
+            reqwest::Client::new(),
+        )
+        .unwrap();
+        let bot = SessionId::new();
+        let person = SessionId::new();
+        for id in [&bot, &person] {
+            lobby_state
+                .insert_session(id.clone(), create_test_session_info(100))
+                .await
+                .unwrap();
+        }
+        let headers_of = |session_id: &SessionId| {
+            let mut headers = HeaderMap::new();
+            headers.typed_insert(Authorization::bearer(&session_id.0).unwrap());
+            headers
+        };
+        let is_deprioritized = |session_id: SessionId| {
+            let lobby_state = lobby_state.clone();
+            async move {
+                lobby_state
+                    .modify_participant(&session_id, |info| info.deprioritized)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Missing the User-Agent alone stays below the threshold
+        let headers = headers_of(&bot);
+        for _ in 0..MIN_INTERVALS {
+            chain.run(&headers).await.unwrap();
+            tokio::time::advance(Duration::from_secs(30)).await;
+        }
+        assert!(!is_deprioritized(bot.clone()).await);
+
+        // Polling like clockwork adds up to it
+        chain.run(&headers).await.unwrap();
+        assert!(is_deprioritized(bot).await);
+
+        // A browser polling irregularly doesn't score
+        let mut headers = headers_of(&person);
+        headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0"));
+        for seconds in [20, 27, 22, 35, 24, 31, 26, 40, 21] {
+            chain.run(&headers).await.unwrap();
+            tokio::time::advance(Duration::from_secs(seconds)).await;
+        }
+        assert!(!is_deprioritized(person).await);
+    }
+}
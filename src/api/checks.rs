@@ -0,0 +1,759 @@
+//! Anti-abuse checks in front of the lobby. Operators pick the checks and
+//! their order in the options, and the chain runs them on each request to a
+//! guarded route before the handler sees it. The first rejection is sent to
+//! the client.
+
+use crate::{
+    access::SharedAccessLists,
+    api::{
+        bots::{BotAction, BotHeuristics, BotScore},
+        fingerprint,
+        v1::lobby::TryContributeError,
+    },
+    client_version::{self, ClientVersion, CLIENT_VERSION_HEADER},
+    lobby::{duration_from_str, SharedLobbyState},
+    sessions::SessionId,
+    util::Secret,
+};
+use axum::{
+    async_trait,
+    response::{IntoResponse, Response},
+};
+use clap::Parser;
+use eyre::{eyre, Result as EyreResult};
+use futures::future::BoxFuture;
+use headers::{authorization::Bearer, Authorization, HeaderMapExt};
+use http::{HeaderMap, Request};
+use kzg_ceremony_crypto::ErrorCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use strum::{EnumString, IntoStaticStr, ParseError};
+use thiserror::Error;
+use tokio::{sync::Mutex, time::Instant};
+use tower::{Layer, Service};
+use tracing::warn;
+use url::Url;
+
+/// Header holding the nonce for the proof of work check.
+pub const PROOF_OF_WORK_HEADER: &str = "X-Proof-Of-Work";
+
+/// Header holding the token for the captcha check.
+pub const CAPTCHA_HEADER: &str = "X-Captcha-Token";
+
+/// How long a session that solved a captcha is not asked again.
+const CAPTCHA_VALID_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Checks run on `/lobby/join`, `/lobby/try_contribute` and
+    /// `/contribute`, in order, separated by commas. Available are `rate-limit`, `proof-of-work`,
+    /// `allowlist`, `client-version`, `captcha` and `bot-score`. Empty to
+    /// disable all.
+    #[clap(long, env, default_value = "client-version,allowlist")]
+    pub abuse_checks: CheckOrder,
+
+    /// Number of requests a session may make to the guarded routes per rate
+    /// limit window.
+    #[clap(long, env, default_value = "30")]
+    pub abuse_rate_limit: u32,
+
+    /// Length of the rate limit window in seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="60")]
+    pub abuse_rate_limit_window: Duration,
+
+    /// Number of leading zero bits of `sha256(session_id || ":" || nonce)`
+    /// required for the nonce sent in the `X-Proof-Of-Work` header.
+    #[clap(long, env, default_value = "20")]
+    pub abuse_pow_difficulty: u32,
+
+    /// Verification endpoint of the captcha provider, e.g.
+    /// `https://hcaptcha.com/siteverify` or
+    /// `https://challenges.cloudflare.com/turnstile/v0/siteverify`. Required
+    /// for the captcha check and `--auth-captcha`.
+    #[clap(long, env)]
+    pub abuse_captcha_verify_url: Option<Url>,
+
+    /// Secret for the captcha verification endpoint.
+    #[clap(long, env)]
+    pub abuse_captcha_secret: Option<Secret>,
+
+    /// Require a captcha token with every sign in. Clients pass it as
+    /// `captcha_token` to `/auth/request_link`, or in the body of the
+    /// passkey requests, and the auth callbacks reject sign ins without a
+    /// valid one.
+    #[clap(long, env, default_value = "false")]
+    pub auth_captcha: bool,
+
+    /// Heuristics of the `bot-score` check, with the points each adds to the
+    /// score of a session that trips it, separated by commas. Available are
+    /// `poll-regularity`, `headers` and `impossible-timing`.
+    #[clap(
+        long,
+        env,
+        default_value = "poll-regularity=40,headers=40,impossible-timing=60"
+    )]
+    pub abuse_bot_heuristics: BotHeuristics,
+
+    /// Score from which the `bot-score` check treats a session as automated.
+    #[clap(long, env, default_value = "80")]
+    pub abuse_bot_score_threshold: u32,
+
+    /// What happens to sessions reaching the bot score threshold. With
+    /// `proof-of-work` they have to send one with every request, at the
+    /// difficulty of `--abuse-pow-difficulty`.
+    #[clap(long, env, value_enum, default_value = "log")]
+    pub abuse_bot_action: BotAction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum CheckKind {
+    RateLimit,
+    ProofOfWork,
+    Allowlist,
+    ClientVersion,
+    Captcha,
+    BotScore,
+}
+
+/// The checks to run, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckOrder(pub Vec<CheckKind>);
+
+impl FromStr for CheckOrder {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|kind| !kind.is_empty())
+            .map(CheckKind::from_str)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum AbuseCheckError {
+    #[error("too many requests, retry in {retry_after_seconds} seconds")]
+    RateLimited { retry_after_seconds: u64 },
+    #[error("missing proof of work")]
+    MissingProofOfWork,
+    #[error("proof of work does not meet the difficulty of {0} bits")]
+    InvalidProofOfWork(u32),
+    #[error("missing captcha token")]
+    MissingCaptcha,
+    #[error("captcha token is invalid")]
+    InvalidCaptcha,
+    #[error("captcha could not be verified")]
+    CaptchaUnavailable,
+}
+
+impl ErrorCode for AbuseCheckError {
+    fn to_error_code(&self) -> String {
+        format!("AbuseCheckError::{}", <&str>::from(self))
+    }
+}
+
+/// What checks get to see of a request.
+pub struct CheckRequest<'a> {
+    pub headers:    &'a HeaderMap,
+    /// The session from the `Authorization` header. Requests without one are
+    /// passed on by the checks that need it, for the handler to reject.
+    pub session_id: Option<SessionId>,
+}
+
+/// A single check of the chain.
+#[async_trait]
+pub trait Check: Send + Sync {
+    /// Passes the request on, or returns the response to reject it with.
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response>;
+}
+
+struct RateLimit {
+    limit:   u32,
+    window:  Duration,
+    /// Start of the current window and the number of requests in it, per
+    /// session.
+    windows: Mutex<BTreeMap<SessionId, (Instant, u32)>>,
+}
+
+#[async_trait]
+impl Check for RateLimit {
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response> {
+        let session_id = match &request.session_id {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let mut windows = self.windows.lock().await;
+        let (started, count) = windows.entry(session_id.clone()).or_insert((now, 0));
+        if now >= *started + self.window {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            let retry_after = (*started + self.window).saturating_duration_since(now);
+            return Err(AbuseCheckError::RateLimited {
+                retry_after_seconds: retry_after.as_secs().max(1),
+            }
+            .into_response());
+        }
+        *count += 1;
+        if *count == 1 {
+            windows.retain(|_, (started, _)| now < *started + self.window);
+        }
+        Ok(())
+    }
+}
+
+struct ProofOfWork {
+    difficulty: u32,
+}
+
+/// Number of leading zero bits of the hash of the session id and the nonce.
+pub fn proof_of_work_bits(session_id: &SessionId, nonce: &str) -> u32 {
+    let hash = Sha256::new()
+        .chain_update(session_id.0.as_bytes())
+        .chain_update(b":")
+        .chain_update(nonce.as_bytes())
+        .finalize();
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[async_trait]
+impl Check for ProofOfWork {
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response> {
+        let session_id = match &request.session_id {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+        let nonce = request
+            .headers
+            .get(PROOF_OF_WORK_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| AbuseCheckError::MissingProofOfWork.into_response())?;
+        if proof_of_work_bits(session_id, nonce.trim()) < self.difficulty {
+            return Err(AbuseCheckError::InvalidProofOfWork(self.difficulty).into_response());
+        }
+        Ok(())
+    }
+}
+
+struct Allowlist {
+    access_lists: SharedAccessLists,
+    lobby_state:  SharedLobbyState,
+}
+
+#[async_trait]
+impl Check for Allowlist {
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response> {
+        let session_id = match &request.session_id {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+        // The lists may have changed since the user authenticated
+        let identity = self
+            .lobby_state
+            .modify_participant(session_id, |info| info.token.identity.clone())
+            .await;
+        match identity {
+            Some(identity) if !self.access_lists.is_allowed(&identity) => {
+                Err(TryContributeError::NotAllowed.into_response())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+struct ClientVersionCheck {
+    options: client_version::Options,
+}
+
+#[async_trait]
+impl Check for ClientVersionCheck {
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response> {
+        let client_version = ClientVersion(
+            request
+                .headers
+                .get(CLIENT_VERSION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_owned()),
+        );
+        self.options
+            .check(&client_version)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+#[derive(Deserialize)]
+struct CaptchaVerification {
+    success: bool,
+}
+
+/// Verifies captcha tokens with the `siteverify` API shared by hCaptcha,
+/// Turnstile and reCAPTCHA.
+pub struct CaptchaVerifier {
+    verify_url:  Url,
+    secret:      Secret,
+    http_client: reqwest::Client,
+}
+
+impl CaptchaVerifier {
+    /// # Errors
+    ///
+    /// Returns an error if the verification url or secret is not configured.
+    pub fn new(options: &Options, http_client: reqwest::Client) -> EyreResult<Self> {
+        Ok(Self {
+            verify_url: options
+                .abuse_captcha_verify_url
+                .clone()
+                .ok_or_else(|| eyre!("Captchas require a verification url"))?,
+            secret: options
+                .abuse_captcha_secret
+                .clone()
+                .ok_or_else(|| eyre!("Captchas require a secret"))?,
+            http_client,
+        })
+    }
+
+    /// Checks a token a client sent. Tokens can only be verified once.
+    pub async fn verify(&self, token: Option<&str>) -> Result<(), AbuseCheckError> {
+        let token = token
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or(AbuseCheckError::MissingCaptcha)?;
+        let verification = async {
+            self.http_client
+                .post(self.verify_url.clone())
+                .form(&[("secret", self.secret.get_secret()), ("response", token)])
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<CaptchaVerification>()
+                .await
+        };
+        match verification.await {
+            Ok(verification) if verification.success => Ok(()),
+            Ok(_) => Err(AbuseCheckError::InvalidCaptcha),
+            Err(error) => {
+                warn!(?error, "Could not verify captcha");
+                Err(AbuseCheckError::CaptchaUnavailable)
+            }
+        }
+    }
+}
+
+/// Captcha verification for signing in, if enabled.
+pub struct AuthCaptcha(Option<CaptchaVerifier>);
+
+pub type SharedAuthCaptcha = Arc<AuthCaptcha>;
+
+impl AuthCaptcha {
+    /// # Errors
+    ///
+    /// Returns an error if captchas are required for signing in, but the
+    /// verification url or secret is not configured.
+    pub fn new(options: &Options, http_client: reqwest::Client) -> EyreResult<Self> {
+        Ok(Self(if options.auth_captcha {
+            Some(CaptchaVerifier::new(options, http_client)?)
+        } else {
+            None
+        }))
+    }
+
+    /// Accepts any token when captchas are not required for signing in.
+    pub async fn verify(&self, token: Option<&str>) -> Result<(), AbuseCheckError> {
+        match &self.0 {
+            Some(verifier) => verifier.verify(token).await,
+            None => Ok(()),
+        }
+    }
+}
+
+struct Captcha {
+    verifier: CaptchaVerifier,
+    /// Sessions that solved a captcha, with the time they did. Tokens can
+    /// only be verified once, so sessions are only asked once.
+    passed:   Mutex<BTreeMap<SessionId, Instant>>,
+}
+
+#[async_trait]
+impl Check for Captcha {
+    async fn check(&self, request: &CheckRequest<'_>) -> Result<(), Response> {
+        let session_id = match &request.session_id {
+            Some(session_id) => session_id,
+            None => return Ok(()),
+        };
+        if self.passed.lock().await.contains_key(session_id) {
+            return Ok(());
+        }
+        let token = request
+            .headers
+            .get(CAPTCHA_HEADER)
+            .and_then(|value| value.to_str().ok());
+        self.verifier
+            .verify(token)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let now = Instant::now();
+        let mut passed = self.passed.lock().await;
+        passed.retain(|_, solved| now < *solved + CAPTCHA_VALID_FOR);
+        passed.insert(session_id.clone(), now);
+        Ok(())
+    }
+}
+
+/// The configured checks, in order.
+pub struct AbuseChecks {
+    checks: Vec<Box<dyn Check>>,
+}
+
+pub type SharedAbuseChecks = Arc<AbuseChecks>;
+
+impl AbuseChecks {
+    /// # Errors
+    ///
+    /// Returns an error if the captcha check, or the bot score check with
+    /// captchas, is enabled without a verification endpoint and secret.
+    pub fn new(
+        options: &Options,
+        client_version: &client_version::Options,
+        fingerprint: &fingerprint::Options,
+        access_lists: SharedAccessLists,
+        lobby_state: SharedLobbyState,
+        http_client: reqwest::Client,
+    ) -> EyreResult<Self> {
+        let checks = options
+            .abuse_checks
+            .0
+            .iter()
+            .map(|kind| -> EyreResult<Box<dyn Check>> {
+                Ok(match kind {
+                    CheckKind::RateLimit => Box::new(RateLimit {
+                        limit:   options.abuse_rate_limit,
+                        window:  options.abuse_rate_limit_window,
+                        windows: Mutex::default(),
+                    }),
+                    CheckKind::ProofOfWork => Box::new(ProofOfWork {
+                        difficulty: options.abuse_pow_difficulty,
+                    }),
+                    CheckKind::Allowlist => Box::new(Allowlist {
+                        access_lists: access_lists.clone(),
+                        lobby_state:  lobby_state.clone(),
+                    }),
+                    CheckKind::ClientVersion => Box::new(ClientVersionCheck {
+                        options: client_version.clone(),
+                    }),
+                    CheckKind::Captcha => Box::new(Captcha {
+                        verifier: CaptchaVerifier::new(options, http_client.clone())?,
+                        passed:   Mutex::default(),
+                    }),
+                    CheckKind::BotScore => Box::new(BotScore::new(
+                        options,
+                        fingerprint,
+                        lobby_state.clone(),
+                        http_client.clone(),
+                    )?),
+                })
+            })
+            .collect::<EyreResult<_>>()?;
+        Ok(Self { checks })
+    }
+
+    /// Runs the checks in order, stopping at the first rejection.
+    pub async fn run(&self, headers: &HeaderMap) -> Result<(), Response> {
+        let request = CheckRequest {
+            headers,
+            session_id: headers
+                .typed_get::<Authorization<Bearer>>()
+                .map(|bearer| SessionId(bearer.token().to_owned())),
+        };
+        for check in &self.checks {
+            check.check(&request).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the checks before the routes it is applied to.
+#[derive(Clone)]
+pub struct AbuseChecksLayer(pub SharedAbuseChecks);
+
+impl<S> Layer<S> for AbuseChecksLayer {
+    type Service = AbuseChecksService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AbuseChecksService {
+            checks: self.0.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AbuseChecksService<S> {
+    checks: SharedAbuseChecks,
+    inner:  S,
+}
+
+impl<S, B> Service<Request<B>> for AbuseChecksService<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+    type Response = Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let checks = self.checks.clone();
+        // The ready service has to be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if let Err(rejection) = checks.run(request.headers()).await {
+                return Ok(rejection);
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{create_test_session_info, shared_access_lists, test_options};
+    use axum::{routing::post, Router};
+    use http::StatusCode;
+    use hyper::Body;
+    use tower::ServiceExt;
+
+    fn checks(options: &crate::Options, lobby_state: &SharedLobbyState) -> SharedAbuseChecks {
+        Arc::new(
+            AbuseChecks::new(
+                &options.checks,
+                &options.client_version,
+                &options.fingerprint,
+                shared_access_lists(),
+                lobby_state.clone(),
+                reqwest::Client::new(),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn request(session_id: &SessionId, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut request = Request::post("/lobby/try_contribute")
+            .header("Authorization", format!("Bearer {}", session_id.0));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    async fn error_code(response: Response) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["code"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn parses_check_order() {
+        assert_eq!(
+            CheckOrder::from_str("captcha, rate-limit").unwrap(),
+            CheckOrder(vec![CheckKind::Captcha, CheckKind::RateLimit])
+        );
+        assert_eq!(CheckOrder::from_str("").unwrap(), CheckOrder::default());
+        assert!(CheckOrder::from_str("allowlist,unknown").is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_outdated_client() {
+        let mut opts = test_options();
+        opts.client_version.min_client_version = Some("1.2.0".parse().unwrap());
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let app = Router::new().route(
+            "/lobby/try_contribute",
+            post(|| async { "ok" }).layer(AbuseChecksLayer(checks(&opts, &lobby_state))),
+        );
+        let session_id = SessionId::new();
+
+        let response = app
+            .clone()
+            .oneshot(request(&session_id, &[(CLIENT_VERSION_HEADER, "1.1.0")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(error_code(response).await, "ClientVersionError::Outdated");
+
+        let response = app
+            .oneshot(request(&session_id, &[(CLIENT_VERSION_HEADER, "1.2.0")]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn runs_checks_in_order() {
+        let mut opts = test_options();
+        opts.client_version.min_client_version = Some("1.2.0".parse().unwrap());
+        opts.checks.abuse_pow_difficulty = 4;
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let session_id = SessionId::new();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        let headers_of = |request: Request<Body>| request.headers().clone();
+
+        opts.checks.abuse_checks = CheckOrder::from_str("proof-of-work,client-version").unwrap();
+        let rejection = checks(&opts, &lobby_state)
+            .run(&headers_of(request(&session_id, &[])))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_code(rejection).await,
+            "AbuseCheckError::MissingProofOfWork"
+        );
+
+        opts.checks.abuse_checks = CheckOrder::from_str("client-version,proof-of-work").unwrap();
+        let chain = checks(&opts, &lobby_state);
+        let rejection = chain
+            .run(&headers_of(request(&session_id, &[])))
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(rejection).await, "ClientVersionError::Missing");
+
+        let nonce = (0_u32..)
+            .map(|nonce| nonce.to_string())
+            .find(|nonce| proof_of_work_bits(&session_id, nonce) >= 4)
+            .unwrap();
+        let weak = (0_u32..)
+            .map(|nonce| nonce.to_string())
+            .find(|nonce| proof_of_work_bits(&session_id, nonce) < 4)
+            .unwrap();
+        let rejection = chain
+            .run(&headers_of(request(&session_id, &[
+                (CLIENT_VERSION_HEADER, "1.2.0"),
+                (PROOF_OF_WORK_HEADER, &weak),
+            ])))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_code(rejection).await,
+            "AbuseCheckError::InvalidProofOfWork"
+        );
+        chain
+            .run(&headers_of(request(&session_id, &[
+                (CLIENT_VERSION_HEADER, "1.2.0"),
+                (PROOF_OF_WORK_HEADER, &nonce),
+            ])))
+            .await
+            .unwrap();
+
+        // Without checks, anything goes
+        opts.checks.abuse_checks = CheckOrder::default();
+        checks(&opts, &lobby_state)
+            .run(&HeaderMap::new())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_request_rate() {
+        let mut opts = test_options();
+        opts.checks.abuse_checks = CheckOrder(vec![CheckKind::RateLimit]);
+        opts.checks.abuse_rate_limit = 2;
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let chain = checks(&opts, &lobby_state);
+        let session_id = SessionId::new();
+        let headers = request(&session_id, &[]).headers().clone();
+
+        tokio::time::pause();
+        chain.run(&headers).await.unwrap();
+        chain.run(&headers).await.unwrap();
+        let rejection = chain.run(&headers).await.unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(rejection).await, "AbuseCheckError::RateLimited");
+        // Other sessions have their own budget
+        chain
+            .run(request(&SessionId::new(), &[]).headers())
+            .await
+            .unwrap();
+
+        tokio::time::advance(opts.checks.abuse_rate_limit_window).await;
+        chain.run(&headers).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_auth_captchas() {
+        async fn siteverify(
+            axum::Form(form): axum::Form<BTreeMap<String, String>>,
+        ) -> axum::Json<serde_json::Value> {
+            let success = form["secret"] == "secret" && form["response"] == "valid";
+            axum::Json(serde_json::json!({ "success": success }))
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap().serve(
+            Router::new()
+                .route("/siteverify", post(siteverify))
+                .into_make_service(),
+        );
+        tokio::spawn(server);
+
+        let mut options = test_options().checks;
+        assert!(AuthCaptcha::new(&options, reqwest::Client::new())
+            .unwrap()
+            .verify(None)
+            .await
+            .is_ok());
+
+        options.auth_captcha = true;
+        assert!(AuthCaptcha::new(&options, reqwest::Client::new()).is_err());
+        options.abuse_captcha_verify_url =
+            Some(format!("http://{address}/siteverify").parse().unwrap());
+        options.abuse_captcha_secret = Some("secret".parse().unwrap());
+        let captcha = AuthCaptcha::new(&options, reqwest::Client::new()).unwrap();
+        assert!(captcha.verify(Some("valid")).await.is_ok());
+        assert!(matches!(
+            captcha.verify(Some("invalid")).await,
+            Err(AbuseCheckError::InvalidCaptcha)
+        ));
+        assert!(matches!(
+            captcha.verify(Some(" ")).await,
+            Err(AbuseCheckError::MissingCaptcha)
+        ));
+
+        options.abuse_captcha_verify_url = Some(format!("http://{address}/gone").parse().unwrap());
+        let captcha = AuthCaptcha::new(&options, reqwest::Client::new()).unwrap();
+        assert!(matches!(
+            captcha.verify(Some("valid")).await,
+            Err(AbuseCheckError::CaptchaUnavailable)
+        ));
+    }
+}
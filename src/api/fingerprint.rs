@@ -0,0 +1,263 @@
+//! Binding sessions to the client that uses them, so that a session id
+//! stolen between signing in and uploading the contribution is of no use
+//! from another machine.
+//!
+//! Sign in happens in the browser, while the session is used from the
+//! contribution client, so a session is bound to the first client that uses
+//! it rather than the one that signed in. The client is identified by a
+//! header set by the TLS terminator, e.g. a hash of its TLS handshake, or
+//! otherwise by a hash of its User-Agent and address.
+
+use crate::{
+    lobby::SharedLobbyState,
+    reporting::session_id_hash,
+    sessions::{SessionError, SessionId},
+};
+use axum::response::{IntoResponse, Response};
+use clap::{Parser, ValueEnum};
+use futures::future::BoxFuture;
+use headers::{authorization::Bearer, Authorization, HeaderMapExt};
+use http::{header::USER_AGENT, HeaderMap, Request};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::warn;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FingerprintPolicy {
+    /// Sessions aren't bound.
+    Off,
+    /// Requests from a different client are logged.
+    Warn,
+    /// Requests from a different client are logged and rejected.
+    Reject,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// What to do with requests whose client fingerprint differs from the
+    /// one the session was first used with.
+    #[clap(long, env, value_enum, default_value = "off")]
+    pub session_fingerprint: FingerprintPolicy,
+
+    /// Header identifying the client, set by the TLS terminator. Without it,
+    /// clients are identified by their User-Agent and address.
+    #[clap(long, env)]
+    pub session_fingerprint_header: Option<String>,
+
+    /// Header holding the client's address, as set by the load balancer. If
+    /// it lists several addresses, the first one is used.
+    #[clap(long, env, default_value = "X-Forwarded-For")]
+    pub client_ip_header: String,
+}
+
+/// Identifies the client that sent a request, if the configured header is
+/// present.
+#[must_use]
+pub fn fingerprint(options: &Options, headers: &HeaderMap) -> Option<String> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    if let Some(name) = &options.session_fingerprint_header {
+        return header(name)
+            .filter(|value| !value.is_empty())
+            .map(str::to_owned);
+    }
+    let address = header(&options.client_ip_header)
+        .and_then(|addresses| addresses.split(',').next())
+        .map(str::trim)
+        .unwrap_or_default();
+    let user_agent = header(USER_AGENT.as_str()).unwrap_or_default();
+    Some(hex::encode(
+        Sha256::new()
+            .chain_update(user_agent.as_bytes())
+            .chain_update(b"\n")
+            .chain_update(address.as_bytes())
+            .finalize(),
+    ))
+}
+
+pub struct SessionFingerprints {
+    options:     Options,
+    lobby_state: SharedLobbyState,
+}
+
+pub type SharedSessionFingerprints = Arc<SessionFingerprints>;
+
+impl SessionFingerprints {
+    #[must_use]
+    pub fn new(options: &Options, lobby_state: SharedLobbyState) -> Self {
+        Self {
+            options: options.clone(),
+            lobby_state,
+        }
+    }
+
+    /// Binds the request's session to its client, or checks it against the
+    /// bound one. Requests without a known session, or without a
+    /// fingerprint, are passed on.
+    pub async fn check(&self, headers: &HeaderMap) -> Result<(), SessionError> {
+        if self.options.session_fingerprint == FingerprintPolicy::Off {
+            return Ok(());
+        }
+        let session_id = match headers.typed_get::<Authorization<Bearer>>() {
+            Some(bearer) => SessionId(bearer.token().to_owned()),
+            None => return Ok(()),
+        };
+        let fingerprint = match fingerprint(&self.options, headers) {
+            Some(fingerprint) => fingerprint,
+            None => return Ok(()),
+        };
+        if self
+            .lobby_state
+            .bind_fingerprint(&session_id, &fingerprint)
+            .await
+            == Some(false)
+        {
+            let session = session_id_hash(&session_id);
+            warn!(%session, "Session used from a different client");
+            if self.options.session_fingerprint == FingerprintPolicy::Reject {
+                return Err(SessionError::FingerprintMismatch);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks session fingerprints before the routes it is applied to.
+#[derive(Clone)]
+pub struct SessionFingerprintLayer(pub SharedSessionFingerprints);
+
+impl<S> Layer<S> for SessionFingerprintLayer {
+    type Service = SessionFingerprintService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionFingerprintService {
+            fingerprints: self.0.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionFingerprintService<S> {
+    fingerprints: SharedSessionFingerprints,
+    inner:        S,
+}
+
+impl<S, B> Service<Request<B>> for SessionFingerprintService<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+    type Response = Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let fingerprints = self.fingerprints.clone();
+        // The ready service has to be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if let Err(error) = fingerprints.check(request.headers()).await {
+                return Ok(error.into_response());
+            }
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    fn headers(user_agent: &str, address: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer alice".parse().unwrap());
+        headers.insert(USER_AGENT, user_agent.parse().unwrap());
+        headers.insert("X-Forwarded-For", address.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn binds_session_to_first_client() {
+        let mut options = test_options();
+        options.fingerprint.session_fingerprint = FingerprintPolicy::Reject;
+        let lobby_state = SharedLobbyState::new(options.lobby.clone());
+        let fingerprints = SessionFingerprints::new(&options.fingerprint, lobby_state.clone());
+        lobby_state
+            .insert_session(
+                SessionId("alice".to_string()),
+                create_test_session_info(100),
+            )
+            .await
+            .unwrap();
+
+        let client = headers("contributor/1.0", "1.2.3.4, 10.0.0.1");
+        assert!(fingerprints.check(&client).await.is_ok());
+        assert!(fingerprints
+            .check(&headers("contributor/1.0", "1.2.3.4"))
+            .await
+            .is_ok());
+        assert!(matches!(
+            fingerprints
+                .check(&headers("contributor/1.0", "5.6.7.8"))
+                .await,
+            Err(SessionError::FingerprintMismatch)
+        ));
+        assert!(matches!(
+            fingerprints.check(&headers("curl/7.0", "1.2.3.4")).await,
+            Err(SessionError::FingerprintMismatch)
+        ));
+
+        // The binding holds while the session has the slot
+        let alice = SessionId("alice".to_string());
+        lobby_state.enter_lobby(&alice).await.unwrap();
+        lobby_state
+            .set_current_contributor(
+                &alice,
+                options.lobby.compute_deadline,
+                storage_client(&options.storage).await.unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(fingerprints.check(&client).await.is_ok());
+        assert!(fingerprints
+            .check(&headers("curl/7.0", "1.2.3.4"))
+            .await
+            .is_err());
+
+        // Unknown sessions are left to the handlers
+        let mut other = headers("curl/7.0", "1.2.3.4");
+        other.insert("Authorization", "Bearer bob".parse().unwrap());
+        assert!(fingerprints.check(&other).await.is_ok());
+    }
+
+    #[test]
+    fn uses_configured_header() {
+        let mut options = test_options().fingerprint;
+        options.session_fingerprint_header = Some("X-Client-Fingerprint".to_string());
+        let mut client = headers("contributor/1.0", "1.2.3.4");
+        assert_eq!(fingerprint(&options, &client), None);
+        client.insert("X-Client-Fingerprint", "ja3:abc".parse().unwrap());
+        assert_eq!(fingerprint(&options, &client).as_deref(), Some("ja3:abc"));
+    }
+}
@@ -1 +1,6 @@
+pub mod bots;
+pub mod checks;
+pub mod fingerprint;
+pub mod shedding;
+pub mod timeouts;
 pub mod v1;
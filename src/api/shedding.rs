@@ -0,0 +1,192 @@
+//! Shedding the polled info routes while the server is busy with the work
+//! that matters, verifying a contribution and writing the transcript.
+//! Clients and dashboards poll `status` and `current_state` often, and
+//! rejecting them with a `Retry-After` for a few seconds keeps them from
+//! slowing down the contributor.
+
+use crate::{
+    lobby::duration_from_str, staging::SharedTranscriptWriter, verifier::SharedVerifier,
+    SharedTranscript,
+};
+use axum::response::{IntoResponse, Response};
+use clap::Parser;
+use futures::future::BoxFuture;
+use http::Request;
+use kzg_ceremony_crypto::ErrorCode;
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use strum::IntoStaticStr;
+use thiserror::Error;
+use tower::{Layer, Service};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Reject requests to the status routes while all verification workers
+    /// are busy or the transcript is being updated or written.
+    #[clap(long, env)]
+    pub load_shedding: bool,
+
+    /// How long clients are asked to wait before retrying a shed request, in
+    /// seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="5")]
+    pub load_shedding_retry_after: Duration,
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum OverloadError {
+    #[error("server is busy, retry in {retry_after_seconds} seconds")]
+    Busy { retry_after_seconds: u64 },
+}
+
+impl ErrorCode for OverloadError {
+    fn to_error_code(&self) -> String {
+        format!("OverloadError::{}", <&str>::from(self))
+    }
+}
+
+pub struct LoadShedder {
+    options:    Options,
+    verifier:   SharedVerifier,
+    transcript: SharedTranscript,
+    writer:     SharedTranscriptWriter,
+}
+
+pub type SharedLoadShedder = Arc<LoadShedder>;
+
+impl LoadShedder {
+    #[must_use]
+    pub fn new(
+        options: &Options,
+        verifier: SharedVerifier,
+        transcript: SharedTranscript,
+        writer: SharedTranscriptWriter,
+    ) -> Self {
+        Self {
+            options: options.clone(),
+            verifier,
+            transcript,
+            writer,
+        }
+    }
+
+    /// Whether the verification workers or the transcript are busy.
+    #[must_use]
+    pub fn is_busy(&self) -> bool {
+        self.verifier.is_saturated() || self.transcript.is_updating() || self.writer.is_writing()
+    }
+
+    /// Rejects the request if shedding is enabled and the server is busy.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`OverloadError::Busy`] with the configured retry period.
+    pub fn check(&self) -> Result<(), OverloadError> {
+        if self.options.load_shedding && self.is_busy() {
+            return Err(OverloadError::Busy {
+                retry_after_seconds: self.options.load_shedding_retry_after.as_secs().max(1),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Sheds requests to the routes it is applied to while the server is busy.
+#[derive(Clone)]
+pub struct LoadSheddingLayer(pub SharedLoadShedder);
+
+impl<S> Layer<S> for LoadSheddingLayer {
+    type Service = LoadSheddingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoadSheddingService {
+            shedder: self.0.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LoadSheddingService<S> {
+    shedder: SharedLoadShedder,
+    inner:   S,
+}
+
+impl<S, B> Service<Request<B>> for LoadSheddingService<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+    type Response = Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if let Err(error) = self.shedder.check() {
+            return Box::pin(async move { Ok(error.into_response()) });
+        }
+        // The ready service has to be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move { inner.call(request).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        staging::TranscriptWriter,
+        storage::storage_client,
+        test_util::{test_options, VirtualClock},
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+        verifier::Verifier,
+    };
+    use http::{header::RETRY_AFTER, StatusCode};
+    use kzg_ceremony_crypto::Identity;
+
+    #[tokio::test]
+    async fn sheds_while_verifying() {
+        let _clock = VirtualClock::start();
+        let mut options = test_options();
+        options.shedding.load_shedding = true;
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+        let storage = storage_client(&options.storage).await.unwrap();
+        let writer = Arc::new(TranscriptWriter::new(&options, transcript.clone(), storage));
+        let verifier = Arc::new(Verifier::simulated(Duration::from_secs(10)));
+        let shedder = LoadShedder::new(
+            &options.shedding,
+            verifier.clone(),
+            transcript.clone(),
+            writer,
+        );
+        assert!(shedder.check().is_ok());
+
+        let valid = valid_contribution(&transcript.snapshot(), 1);
+        let verifying = tokio::spawn({
+            let transcript = transcript.clone();
+            async move {
+                verifier
+                    .verify_add(&transcript, valid, Identity::None)
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        let response = shedder.check().unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+
+        verifying.await.unwrap().unwrap();
+        assert!(shedder.check().is_ok());
+    }
+}
@@ -0,0 +1,243 @@
+//! Time budgets for the routes of a ceremony, so that a hung OAuth provider
+//! or RPC node can't hold on to requests indefinitely. The budget includes
+//! reading the request body, so `/contribute` gets a long one by default,
+//! while the polled status routes get a short one. A timeout only drops the
+//! response: contributions that were read keep being processed, as the slot
+//! has to be given back either way.
+
+use crate::lobby::duration_from_str;
+use axum::response::{IntoResponse, Response};
+use clap::Parser;
+use eyre::{eyre, Result as EyreResult};
+use futures::future::BoxFuture;
+use http::Request;
+use kzg_ceremony_crypto::ErrorCode;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    task::{Context, Poll},
+    time::Duration,
+};
+use strum::IntoStaticStr;
+use thiserror::Error;
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// Budgets of the routes that differ from the default, before the ones
+/// configured with `--route-timeouts`.
+const DEFAULT_ROUTE_TIMEOUTS: [(&str, u64); 8] = [
+    ("/auth/callback/github", 15),
+    ("/auth/callback/eth", 15),
+    ("/auth/callback/twitter", 15),
+    ("/auth/callback/discord", 15),
+    ("/contribute", 600),
+    ("/info/status", 5),
+    ("/info/status/signed", 5),
+    ("/admin/receipts", 600),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Time in seconds a request may take, including reading its body, for
+    /// routes without a specific budget.
+    #[clap(long, env, value_parser=duration_from_str, default_value="30")]
+    pub default_route_timeout: Duration,
+
+    /// Time budgets in seconds for individual routes, taking precedence
+    /// over the defaults of 15 seconds for the auth callbacks, 600 for
+    /// `/contribute` and `/admin/receipts`, and 5 for the status routes. The
+    /// format is `PATH=SECONDS[,PATH=SECONDS]*`, for example
+    /// `/contribute=1200`.
+    #[clap(long, env, value_delimiter = ',', value_parser = RouteTimeout::parse_from_cmd)]
+    pub route_timeouts: Vec<RouteTimeout>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteTimeout {
+    pub path:    String,
+    pub timeout: Duration,
+}
+
+impl RouteTimeout {
+    /// Parses a route timeout of the form `PATH=SECONDS`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the description is malformed.
+    pub fn parse_from_cmd(cmd: &str) -> EyreResult<Self> {
+        let (path, timeout) = cmd
+            .split_once('=')
+            .filter(|(path, _)| path.starts_with('/'))
+            .ok_or_else(|| eyre!("Invalid route timeout {cmd}, expected PATH=SECONDS"))?;
+        Ok(Self {
+            path:    path.to_string(),
+            timeout: duration_from_str(timeout)?,
+        })
+    }
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum TimeoutError {
+    #[error("request took longer than {timeout_seconds} seconds")]
+    TimedOut { timeout_seconds: u64 },
+}
+
+impl ErrorCode for TimeoutError {
+    fn to_error_code(&self) -> String {
+        format!("TimeoutError::{}", <&str>::from(self))
+    }
+}
+
+/// The time budget of each route.
+pub struct RouteTimeouts {
+    default: Duration,
+    routes:  HashMap<String, Duration>,
+}
+
+impl RouteTimeouts {
+    #[must_use]
+    pub fn new(options: &Options) -> Self {
+        let mut routes = DEFAULT_ROUTE_TIMEOUTS
+            .iter()
+            .map(|&(path, seconds)| (path.to_string(), Duration::from_secs(seconds)))
+            .collect::<HashMap<_, _>>();
+        routes.extend(
+            options
+                .route_timeouts
+                .iter()
+                .map(|route| (route.path.clone(), route.timeout)),
+        );
+        Self {
+            default: options.default_route_timeout,
+            routes,
+        }
+    }
+
+    #[must_use]
+    pub fn timeout(&self, path: &str) -> Duration {
+        self.routes.get(path).copied().unwrap_or(self.default)
+    }
+
+    #[must_use]
+    pub fn layer(&self, path: &str) -> TimeoutLayer {
+        TimeoutLayer(self.timeout(path))
+    }
+}
+
+/// Answers requests to the routes it is applied to with
+/// [`TimeoutError::TimedOut`] once they take longer than the budget.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeoutLayer(pub Duration);
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = TimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimeoutService {
+            timeout: self.0,
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TimeoutService<S> {
+    timeout: Duration,
+    inner:   S,
+}
+
+impl<S, B> Service<Request<B>> for TimeoutService<S>
+where
+    S: Service<Request<B>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+    type Response = Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let timeout = self.timeout;
+        let path = request.uri().path().to_string();
+        // The ready service has to be the one called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if let Ok(response) = tokio::time::timeout(timeout, inner.call(request)).await {
+                return response;
+            }
+            warn!(%path, ?timeout, "Request timed out");
+            Ok(TimeoutError::TimedOut {
+                timeout_seconds: timeout.as_secs(),
+            }
+            .into_response())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    #[test]
+    fn parses_route_timeout() {
+        assert_eq!(
+            RouteTimeout::parse_from_cmd("/contribute=1200").unwrap(),
+            RouteTimeout {
+                path:    "/contribute".to_string(),
+                timeout: Duration::from_secs(1200),
+            }
+        );
+        assert!(RouteTimeout::parse_from_cmd("contribute=1200").is_err());
+        assert!(RouteTimeout::parse_from_cmd("/contribute").is_err());
+        assert!(RouteTimeout::parse_from_cmd("/contribute=long").is_err());
+
+        let timeouts = RouteTimeouts::new(&Options::parse_from([
+            "test",
+            "--route-timeouts",
+            "/contribute=1200,/info/current_state=2",
+        ]));
+        assert_eq!(timeouts.timeout("/contribute"), Duration::from_secs(1200));
+        assert_eq!(
+            timeouts.timeout("/info/current_state"),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            timeouts.timeout("/auth/callback/github"),
+            Duration::from_secs(15)
+        );
+        assert_eq!(timeouts.timeout("/lobby/join"), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn answers_slow_requests() {
+        tokio::time::pause();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    "done"
+                })
+                .layer(TimeoutLayer(Duration::from_secs(5))),
+            )
+            .route(
+                "/fast",
+                get(|| async { "done" }).layer(TimeoutLayer(Duration::from_secs(5))),
+            );
+        let request = |path| Request::get(path).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(request("/fast")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(request("/slow")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
@@ -0,0 +1,655 @@
+use crate::{
+    announcement::SharedAnnouncement,
+    invitations,
+    keys::SharedKeys,
+    lobby::{LobbyOverview, LobbyParams, LobbyParamsError, SharedLobbyState},
+    mirror::SharedMirrors,
+    receipt_export::{self, ReceiptExportError, ReceiptFormat},
+    storage::{PersistentStorage, StorageError},
+    verifier::{SharedVerifier, VerificationResult},
+    Options, SharedCeremonyStatus,
+};
+use axum::{
+    body::Bytes,
+    extract::Query,
+    response::{Html, IntoResponse, Response},
+    Extension, Json, TypedHeader,
+};
+use headers::{
+    authorization::{Basic, Bearer},
+    Authorization,
+};
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use kzg_ceremony_crypto::ErrorCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{sync::atomic::Ordering, time::Duration};
+use strum::IntoStaticStr;
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum AdminError {
+    #[error("admin endpoints are disabled")]
+    Disabled,
+    #[error("invalid admin token")]
+    Unauthorized,
+    #[error("invalid lobby parameters: {0}")]
+    InvalidLobbyParams(#[from] LobbyParamsError),
+    #[error("invalid invitation request: {0}")]
+    InvalidInvitationRequest(&'static str),
+    #[error("storage error: {0}")]
+    StorageError(#[from] StorageError),
+    #[error("receipt export failed: {0}")]
+    ReceiptExport(#[from] ReceiptExportError),
+}
+
+impl ErrorCode for AdminError {
+    fn to_error_code(&self) -> String {
+        format!("AdminError::{}", <&str>::from(self))
+    }
+}
+
+/// Checks the admin token, given either as a bearer token or, so that the
+/// dashboard can be opened in a browser, as the password of HTTP basic auth.
+fn authorize(
+    options: &Options,
+    bearer: Option<&Authorization<Bearer>>,
+    basic: Option<&Authorization<Basic>>,
+) -> Result<(), AdminError> {
+    let expected = options.admin_token.as_ref().ok_or(AdminError::Disabled)?;
+    let given = bearer
+        .map(|bearer| bearer.token())
+        .or_else(|| basic.map(|basic| basic.password()))
+        .ok_or(AdminError::Unauthorized)?;
+    // Comparing hashes doesn't reveal how much of the token matched
+    if Sha256::digest(given) == Sha256::digest(expected.get_secret()) {
+        Ok(())
+    } else {
+        Err(AdminError::Unauthorized)
+    }
+}
+
+/// A page for operators showing the lobby, the contribution slot, the latest
+/// verification results and the health of the storage. It reloads itself
+/// every few seconds.
+#[allow(clippy::too_many_arguments)]
+pub async fn dashboard(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Extension(options): Extension<Options>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(verifier): Extension<SharedVerifier>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(mirrors): Extension<SharedMirrors>,
+    Extension(ceremony_status): Extension<SharedCeremonyStatus>,
+) -> Result<Html<String>, AdminError> {
+    authorize(
+        &options,
+        bearer.as_ref().map(|header| &header.0),
+        basic.as_ref().map(|header| &header.0),
+    )?;
+
+    Ok(Html(render(&Dashboard {
+        num_contributions: ceremony_status.load(Ordering::Relaxed),
+        lobby:             lobby_state.overview().await,
+        verifications:     verifier.recent_results().await,
+        database:          storage.check_health().await,
+        mirrors:           mirrors.cached_report(&options.transcript_file).await,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnouncementRequest {
+    /// The new announcement, or `null` to remove it.
+    message: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnnouncementResponse {
+    message: Option<String>,
+}
+
+/// Sets or removes the announcement shown to all participants, see
+/// [`crate::announcement`]. Changes are recorded in the audit log.
+pub async fn set_announcement(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Extension(options): Extension<Options>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(announcement): Extension<SharedAnnouncement>,
+    Json(request): Json<AnnouncementRequest>,
+) -> Result<Json<AnnouncementResponse>, AdminError> {
+    authorize(
+        &options,
+        bearer.as_ref().map(|header| &header.0),
+        basic.as_ref().map(|header| &header.0),
+    )?;
+
+    announcement.set(&storage, request.message).await?;
+    let message = announcement.get().await;
+    if let Err(error) = storage
+        .insert_audit_event("announcement_set", "admin", &json!({ "message": message }))
+        .await
+    {
+        error!(?error, "Could not record announcement");
+    }
+    Ok(Json(AnnouncementResponse { message }))
+}
+
+/// Changes the lobby parameters given in the request, keeping everyone in the
+/// lobby, and returns all of them. Changes are recorded in the audit log.
+pub async fn set_lobby_params(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Extension(options): Extension<Options>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Json(request): Json<LobbyParams>,
+) -> Result<Json<LobbyParams>, AdminError> {
+    authorize(
+        &options,
+        bearer.as_ref().map(|header| &header.0),
+        basic.as_ref().map(|header| &header.0),
+    )?;
+
+    let params = lobby_state.reload(&request).await?;
+    if let Err(error) = storage
+        .insert_audit_event("lobby_reloaded", "admin", &json!(params))
+        .await
+    {
+        error!(?error, "Could not record lobby reload");
+    }
+    Ok(Json(params))
+}
+
+/// Most invitation codes minted at once.
+const MAX_INVITATIONS: usize = 1000;
+
+const fn one() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InvitationsRequest {
+    /// Number of codes to mint.
+    #[serde(default = "one")]
+    count:    usize,
+    /// Number of identities each code admits.
+    #[serde(default = "one")]
+    max_uses: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvitationsResponse {
+    codes: Vec<String>,
+}
+
+/// Mints invitation codes for `--invite-only`, see [`crate::invitations`].
+/// The codes are only returned here. Minting is recorded in the audit log,
+/// without the codes.
+pub async fn mint_invitations(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Extension(options): Extension<Options>,
+    Extension(storage): Extension<PersistentStorage>,
+    Json(request): Json<InvitationsRequest>,
+) -> Result<Json<InvitationsResponse>, AdminError> {
+    authorize(
+        &options,
+        bearer.as_ref().map(|header| &header.0),
+        basic.as_ref().map(|header| &header.0),
+    )?;
+    if request.count == 0 || request.count > MAX_INVITATIONS {
+        return Err(AdminError::InvalidInvitationRequest(
+            "count must be between 1 and 1000",
+        ));
+    }
+    if request.max_uses == 0 {
+        return Err(AdminError::InvalidInvitationRequest(
+            "max_uses must be at least 1",
+        ));
+    }
+
+    let codes = invitations::mint(&storage, request.count, request.max_uses).await?;
+    let event = json!({ "count": request.count, "max_uses": request.max_uses });
+    if let Err(error) = storage
+        .insert_audit_event("invitations_minted", "admin", &event)
+        .await
+    {
+        error!(?error, "Could not record minted invitations");
+    }
+    Ok(Json(InvitationsResponse { codes }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReceiptsQuery {
+    #[serde(default)]
+    format: ReceiptFormat,
+    /// Fields to export, separated by commas. Defaults to all of them.
+    fields: Option<String>,
+}
+
+/// All receipts for publication, see [`crate::receipt_export`], as JSON
+/// lines or, with `format=csv`, as CSV.
+pub async fn export_receipts(
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Query(query): Query<ReceiptsQuery>,
+    Extension(options): Extension<Options>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(keys): Extension<SharedKeys>,
+) -> Result<Response, AdminError> {
+    authorize(
+        &options,
+        bearer.as_ref().map(|header| &header.0),
+        basic.as_ref().map(|header| &header.0),
+    )?;
+    let fields = receipt_export::parse_fields(query.fields.as_deref().unwrap_or_default())?;
+
+    let contents = receipt_export::export(&storage, keys, query.format, fields)
+        .await?
+        .contents;
+    let event = json!({ "format": query.format, "fields": query.fields });
+    if let Err(error) = storage
+        .insert_audit_event("receipts_exported", "admin", &event)
+        .await
+    {
+        error!(?error, "Could not record receipt export");
+    }
+    let (content_type, disposition) = match query.format {
+        ReceiptFormat::Jsonl => (
+            "application/x-ndjson",
+            "attachment; filename=\"receipts.jsonl\"",
+        ),
+        ReceiptFormat::Csv => ("text/csv", "attachment; filename=\"receipts.csv\""),
+    };
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        contents,
+    )
+        .into_response())
+}
+
+struct Dashboard {
+    num_contributions: usize,
+    lobby:             LobbyOverview,
+    verifications:     Vec<VerificationResult>,
+    database:          Result<Duration, StorageError>,
+    mirrors:           eyre::Result<Bytes>,
+}
+
+fn render(dashboard: &Dashboard) -> String {
+    let slot = dashboard.lobby.slot_holder.as_ref().map_or_else(
+        || "<p>Free</p>".to_string(),
+        |holder| {
+            format!(
+                "<p>{} (session {}), {} for {}s</p>",
+                escape(&holder.identity),
+                holder.session_hash,
+                if holder.contributing {
+                    "verifying"
+                } else {
+                    "computing"
+                },
+                holder.held_for.as_secs()
+            )
+        },
+    );
+    let waiting = dashboard
+        .lobby
+        .waiting
+        .iter()
+        .map(|participant| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}s</td><td>{}</td></tr>",
+                escape(&participant.identity),
+                participant.session_hash,
+                participant.idle.as_secs(),
+                if participant.on_deck { "yes" } else { "" }
+            )
+        })
+        .collect::<String>();
+    let verifications = dashboard
+        .verifications
+        .iter()
+        .map(|result| {
+            format!(
+                "<tr><td>{}</td><td>{}ms</td><td>{}</td></tr>",
+                result.finished_at.to_rfc3339(),
+                result.duration.as_millis(),
+                result
+                    .error
+                    .as_deref()
+                    .map_or_else(|| "accepted".to_string(), escape)
+            )
+        })
+        .collect::<String>();
+    let database = match &dashboard.database {
+        Ok(latency) => format!("ok ({}ms)", latency.as_millis()),
+        Err(error) => format!("error: {}", escape(&error.to_string())),
+    };
+    let mirrors = match &dashboard.mirrors {
+        Ok(report) => serde_json::from_slice::<Value>(report)
+            .and_then(|report| serde_json::to_string_pretty(&report))
+            .map_or_else(|error| error.to_string(), |report| escape(&report)),
+        Err(error) => format!("error: {}", escape(&error.to_string())),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="5">
+<title>Sequencer dashboard</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Sequencer dashboard</h1>
+<p>{num_contributions} contributions</p>
+<h2>Contribution slot</h2>
+{slot}
+<h2>Lobby ({lobby_size})</h2>
+<table>
+<tr><th>Identity</th><th>Session</th><th>Last ping</th><th>On deck</th></tr>
+{waiting}</table>
+<h2>Recent verifications</h2>
+<table>
+<tr><th>Finished</th><th>Took</th><th>Result</th></tr>
+{verifications}</table>
+<h2>Storage</h2>
+<p>Database: {database}</p>
+<p>Transcript mirrors:</p>
+<pre>{mirrors}</pre>
+</body>
+</html>
+"#,
+        num_contributions = dashboard.num_contributions,
+        lobby_size = dashboard.lobby.waiting.len(),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, character| {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+        escaped
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keys::Keys,
+        mirror::{self, Mirrors},
+        receipt::Receipt,
+        storage::{storage_client, StoredReceipt},
+        test_util::{create_test_session_info, test_options},
+        verifier::{self, Verifier},
+        SessionId,
+    };
+    use clap::Parser;
+    use kzg_ceremony_crypto::{signature::identity::Identity, G2};
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    #[tokio::test]
+    async fn shows_dashboard_to_admins() {
+        let mut opts = test_options();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let db = storage_client(&opts.storage).await.unwrap();
+        let verifier =
+            Arc::new(Verifier::new(&verifier::Options::parse_from(Vec::<&str>::new())).unwrap());
+        let mirrors = Arc::new(
+            Mirrors::new(
+                &mirror::Options::parse_from(Vec::<&str>::new()),
+                reqwest::Client::new(),
+            )
+            .unwrap(),
+        );
+        let session_id = SessionId::new();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&session_id).await.unwrap();
+        let show = |opts: &Options, token: &str| {
+            dashboard(
+                Some(TypedHeader(Authorization::bearer(token).unwrap())),
+                None,
+                Extension(opts.clone()),
+                Extension(lobby_state.clone()),
+                Extension(verifier.clone()),
+                Extension(db.clone()),
+                Extension(mirrors.clone()),
+                Extension(Arc::new(AtomicUsize::new(3))),
+            )
+        };
+
+        assert!(matches!(
+            show(&opts, "secret").await,
+            Err(AdminError::Disabled)
+        ));
+        opts.admin_token = Some("secret".parse().unwrap());
+        assert!(matches!(
+            show(&opts, "guess").await,
+            Err(AdminError::Unauthorized)
+        ));
+        let Html(page) = show(&opts, "secret").await.unwrap();
+        assert!(page.contains("3 contributions"));
+        assert!(page.contains("<h2>Lobby (1)</h2>"));
+        assert!(page.contains("Database: ok"));
+    }
+
+    #[tokio::test]
+    async fn sets_announcement() {
+        let mut opts = test_options();
+        opts.admin_token = Some("secret".parse().unwrap());
+        let db = storage_client(&opts.storage).await.unwrap();
+        let announcement = SharedAnnouncement::default();
+        let set = |token: &str, message: Option<&str>| {
+            set_announcement(
+                Some(TypedHeader(Authorization::bearer(token).unwrap())),
+                None,
+                Extension(opts.clone()),
+                Extension(db.clone()),
+                Extension(announcement.clone()),
+                Json(AnnouncementRequest {
+                    message: message.map(ToOwned::to_owned),
+                }),
+            )
+        };
+
+        assert!(matches!(
+            set("guess", Some("Hi")).await,
+            Err(AdminError::Unauthorized)
+        ));
+        assert_eq!(announcement.get().await, None);
+        let Json(response) = set("secret", Some("Maintenance at noon")).await.unwrap();
+        assert_eq!(response.message.as_deref(), Some("Maintenance at noon"));
+        assert_eq!(
+            db.announcement().await.unwrap().as_deref(),
+            Some("Maintenance at noon")
+        );
+        let Json(response) = set("secret", None).await.unwrap();
+        assert_eq!(response.message, None);
+        assert_eq!(announcement.get().await, None);
+    }
+
+    #[tokio::test]
+    async fn mints_invitations() {
+        let mut opts = test_options();
+        opts.admin_token = Some("secret".parse().unwrap());
+        opts.invitations.invite_only = true;
+        let db = storage_client(&opts.storage).await.unwrap();
+        let mint = |token: &str, count: usize, max_uses: usize| {
+            mint_invitations(
+                Some(TypedHeader(Authorization::bearer(token).unwrap())),
+                None,
+                Extension(opts.clone()),
+                Extension(db.clone()),
+                Json(InvitationsRequest { count, max_uses }),
+            )
+        };
+
+        assert!(matches!(
+            mint("guess", 1, 1).await,
+            Err(AdminError::Unauthorized)
+        ));
+        assert!(matches!(
+            mint("secret", 0, 1).await,
+            Err(AdminError::InvalidInvitationRequest(_))
+        ));
+        assert!(matches!(
+            mint("secret", 1, 0).await,
+            Err(AdminError::InvalidInvitationRequest(_))
+        ));
+        let Json(response) = mint("secret", 3, 1).await.unwrap();
+        assert_eq!(response.codes.len(), 3);
+        for code in &response.codes {
+            assert!(invitations::accepts(&opts.invitations, &db, Some(code))
+                .await
+                .unwrap());
+        }
+        let audit_log = db.audit_events("invitations_minted").await.unwrap();
+        assert_eq!(audit_log.len(), 1);
+        assert!(!audit_log[0].details.contains(&response.codes[0]));
+    }
+
+    #[tokio::test]
+    async fn sets_lobby_params() {
+        let mut opts = test_options();
+        opts.admin_token = Some("secret".parse().unwrap());
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let session_id = SessionId::new();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&session_id).await.unwrap();
+        let set = |token: &str, request: LobbyParams| {
+            set_lobby_params(
+                Some(TypedHeader(Authorization::bearer(token).unwrap())),
+                None,
+                Extension(opts.clone()),
+                Extension(db.clone()),
+                Extension(lobby_state.clone()),
+                Json(request),
+            )
+        };
+
+        let request = LobbyParams {
+            max_lobby_size: Some(1),
+            ..LobbyParams::default()
+        };
+        assert!(matches!(
+            set("guess", request.clone()).await,
+            Err(AdminError::Unauthorized)
+        ));
+        let Json(params) = set("secret", request).await.unwrap();
+        assert_eq!(params.max_lobby_size, Some(1));
+        assert_eq!(
+            params.compute_deadline,
+            Some(opts.lobby.compute_deadline.as_secs())
+        );
+        assert_eq!(lobby_state.options().max_lobby_size, 1);
+        assert_eq!(lobby_state.get_lobby_size().await, 1);
+
+        assert!(matches!(
+            set("secret", LobbyParams {
+                compute_deadline: Some(0),
+                ..LobbyParams::default()
+            })
+            .await,
+            Err(AdminError::InvalidLobbyParams(
+                LobbyParamsError::ZeroComputeDeadline
+            ))
+        ));
+        let audit_log = db.dump().await.unwrap().audit_log;
+        assert_eq!(audit_log.len(), 1);
+        assert_eq!(audit_log[0].event, "lobby_reloaded");
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape("<script>\"a\" & 'b'</script>"),
+            "&lt;script&gt;&quot;a&quot; &amp; &#39;b&#39;&lt;/script&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_receipts() {
+        let mut opts = test_options();
+        opts.admin_token = Some("secret".parse().unwrap());
+        let db = storage_client(&opts.storage).await.unwrap();
+        let keys = Arc::new(Keys::new(&opts.keys).unwrap());
+        let receipt = Receipt {
+            identity:            Identity::Github {
+                id:       1,
+                username: "user1".to_string(),
+            },
+            witness:             vec![G2::one()],
+            entropy_attestation: None,
+            region:              None,
+            countersigner:       None,
+        };
+        let (receipt, signature) = receipt.sign(&keys).await.unwrap();
+        db.insert_receipt("session", &StoredReceipt {
+            participant: 1,
+            receipt,
+            signature: signature.as_hex().to_owned(),
+            bls_signature: None,
+            countersignature: None,
+        })
+        .await
+        .unwrap();
+        let export = |token: &str, format: ReceiptFormat, fields: Option<&str>| {
+            export_receipts(
+                Some(TypedHeader(Authorization::bearer(token).unwrap())),
+                None,
+                Query(ReceiptsQuery {
+                    format,
+                    fields: fields.map(str::to_string),
+                }),
+                Extension(opts.clone()),
+                Extension(db.clone()),
+                Extension(keys.clone()),
+            )
+        };
+
+        assert!(matches!(
+            export("guess", ReceiptFormat::Csv, None).await,
+            Err(AdminError::Unauthorized)
+        ));
+        assert!(matches!(
+            export("secret", ReceiptFormat::Csv, Some("participant,secret")).await,
+            Err(AdminError::ReceiptExport(ReceiptExportError::UnknownField(
+                _
+            )))
+        ));
+        let response = export("secret", ReceiptFormat::Csv, Some("participant,identity"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"participant,identity\n1,git|1|user1\n");
+        assert_eq!(db.audit_events("receipts_exported").await.unwrap().len(), 1);
+    }
+}
@@ -1,9 +1,22 @@
 use crate::{
+    access::SharedAccessLists,
+    api::checks::{AbuseCheckError, SharedAuthCaptcha},
+    attempts::AttemptError,
+    guard::Mark,
+    invitations,
     lobby::SharedLobbyState,
-    oauth::{EthOAuthClient, GithubOAuthClient, SharedAuthState},
+    oauth::{
+        discord_creation_time, is_old_enough, issue_lobby_token, parse_address, passkey_identity,
+        siwe_nonce, state_hash, DiscordOAuthClient, EmailError, EthOAuthClient, GithubOAuthClient,
+        PasskeyError, SharedAuthState, SharedEmailVerifier, SharedPasskeyAuth, TwitterOAuthClient,
+    },
+    proxy::OAuthHttpClient,
+    regions::Region,
     sessions::IdToken,
     storage::{PersistentStorage, StorageError},
-    EthAuthOptions, Options, SessionId, SessionInfo,
+    util::Secret,
+    wal::{self, Operation},
+    Options, SessionId,
 };
 use axum::{
     async_trait,
@@ -11,20 +24,20 @@ use axum::{
     response::{IntoResponse, Redirect, Response},
     Extension, Json,
 };
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use http::StatusCode;
 use kzg_ceremony_crypto::{signature::identity::Identity, ErrorCode};
 use oauth2::{
-    reqwest::async_http_client, AuthorizationCode, CsrfToken, RequestTokenError, Scope,
-    TokenResponse,
+    AuthorizationCode, CsrfToken, PkceCodeChallenge, RequestTokenError, Scope, TokenResponse,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 use strum::IntoStaticStr;
 use thiserror::Error;
-use tokio::time::Instant;
 use tracing::warn;
 use url::Url;
+use uuid::Uuid;
+use webauthn_rs::prelude::{Passkey, PublicKeyCredential, RegisterPublicKeyCredential};
 
 #[derive(Debug, Error)]
 #[error("{payload}")]
@@ -39,14 +52,40 @@ pub enum AuthErrorPayload {
     LobbyIsFull,
     #[error("user already contributed")]
     UserAlreadyContributed,
+    #[error("too many failed contribution attempts")]
+    TooManyFailedAttempts,
+    #[error("user holds too many sessions already")]
+    TooManySessions,
     #[error("invalid authorization code")]
     InvalidAuthCode,
+    #[error("sign-in nonce is invalid, expired or already used")]
+    InvalidNonce,
     #[error("could not fetch user data from auth server")]
     FetchUserDataError,
     #[error("could not extract user data from auth server")]
     CouldNotExtractUserData,
     #[error("user created after deadline")]
     UserCreatedAfterDeadline,
+    #[error("signing in from this chain is not supported")]
+    UnsupportedChain,
+    #[error("pseudonymous contributions are disabled")]
+    PseudonymsDisabled,
+    #[error("passkeys are disabled")]
+    PasskeysDisabled,
+    #[error("signing in with this provider is disabled")]
+    ProviderDisabled,
+    #[error("invalid passkey or challenge")]
+    InvalidPasskey,
+    #[error("user is not allowed to contribute")]
+    NotAllowed,
+    #[error("missing or invalid captcha token")]
+    InvalidCaptcha,
+    #[error("captcha could not be verified")]
+    CaptchaUnavailable,
+    #[error("signing in takes an invitation code")]
+    InvitationRequired,
+    #[error("invitation code is unknown or used up")]
+    InvalidInvitation,
     #[error("storage error: {0}")]
     Storage(#[from] StorageError),
 }
@@ -57,6 +96,33 @@ impl ErrorCode for AuthErrorPayload {
     }
 }
 
+impl From<PasskeyError> for AuthErrorPayload {
+    fn from(err: PasskeyError) -> Self {
+        match err {
+            PasskeyError::Disabled => Self::PasskeysDisabled,
+            PasskeyError::UnknownChallenge | PasskeyError::Webauthn(_) => Self::InvalidPasskey,
+        }
+    }
+}
+
+impl From<AbuseCheckError> for AuthErrorPayload {
+    fn from(err: AbuseCheckError) -> Self {
+        match err {
+            AbuseCheckError::CaptchaUnavailable => Self::CaptchaUnavailable,
+            _ => Self::InvalidCaptcha,
+        }
+    }
+}
+
+impl From<AuthErrorPayload> for AuthError {
+    fn from(payload: AuthErrorPayload) -> Self {
+        Self {
+            redirect: None,
+            payload,
+        }
+    }
+}
+
 pub struct UserVerifiedResponse {
     id_token:       IdToken,
     session_id:     String,
@@ -64,8 +130,11 @@ pub struct UserVerifiedResponse {
 }
 
 pub struct AuthUrl {
-    eth_auth_url:    String,
-    github_auth_url: String,
+    eth_auth_url:     String,
+    github_auth_url:  String,
+    /// Only set for the providers that are enabled.
+    twitter_auth_url: Option<String>,
+    discord_auth_url: Option<String>,
 }
 
 impl IntoResponse for AuthUrl {
@@ -73,6 +142,8 @@ impl IntoResponse for AuthUrl {
         Json(json!({
             "eth_auth_url": self.eth_auth_url,
             "github_auth_url": self.github_auth_url,
+            "twitter_auth_url": self.twitter_auth_url,
+            "discord_auth_url": self.discord_auth_url,
         }))
         .into_response()
     }
@@ -110,12 +181,35 @@ impl IntoResponse for UserVerifiedResponse {
 
 #[derive(Debug, Deserialize)]
 pub struct AuthClientLinkQueryParams {
-    redirect_to: Option<String>,
+    redirect_to:   Option<String>,
+    /// Contribute under a pseudonym instead of the identity used to sign in.
+    #[serde(default)]
+    pseudonymous:  bool,
+    /// Coarse region to be counted in `/info/stats/regions`, if the user
+    /// chooses to share it.
+    region:        Option<Region>,
+    /// Required when the sequencer is run with `--auth-captcha`.
+    captcha_token: Option<String>,
+    /// Required when the sequencer is run with `--invite-only`.
+    invite_code:   Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CsrfWithRedirect {
-    redirect: Option<String>,
+    redirect:      Option<String>,
+    #[serde(default)]
+    pseudonymous:  bool,
+    /// Single use nonce, required to sign in with Ethereum.
+    #[serde(default)]
+    nonce:         Option<String>,
+    #[serde(default)]
+    region:        Option<Region>,
+    /// Verified by the callback, once the user is back from the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    captcha_token: Option<String>,
+    /// Redeemed once the user is back from the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    invite_code:   Option<String>,
 }
 
 impl CsrfWithRedirect {
@@ -128,27 +222,75 @@ impl CsrfWithRedirect {
 
 // Returns the url that the user needs to call
 // in order to get an authorisation code
+#[allow(clippy::too_many_arguments)]
 pub async fn auth_client_link(
     Query(params): Query<AuthClientLinkQueryParams>,
     Extension(options): Extension<Options>,
     Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
     Extension(eth_client): Extension<EthOAuthClient>,
     Extension(gh_client): Extension<GithubOAuthClient>,
+    Extension(tw_client): Extension<TwitterOAuthClient>,
+    Extension(discord_client): Extension<DiscordOAuthClient>,
 ) -> Result<AuthUrl, AuthErrorPayload> {
     let lobby_size = lobby_state.get_lobby_size().await;
 
-    if lobby_size >= options.lobby.max_lobby_size {
+    if lobby_size >= lobby_state.options().max_lobby_size {
         return Err(AuthErrorPayload::LobbyIsFull);
     }
 
+    if params.pseudonymous && options.pseudonym.pseudonym_salt.is_none() {
+        return Err(AuthErrorPayload::PseudonymsDisabled);
+    }
+
+    // Don't send users to the provider with a code that can't admit them
+    let invite_code = params.invite_code.as_deref();
+    if !invitations::accepts(&options.invitations, &storage, invite_code).await? {
+        return Err(invitation_error(invite_code));
+    }
+
+    let nonce = siwe_nonce();
     let csrf_with_redirect = CsrfWithRedirect {
-        redirect: params.redirect_to,
+        redirect:      params.redirect_to,
+        pseudonymous:  params.pseudonymous,
+        nonce:         Some(nonce.clone()),
+        region:        params.region,
+        captcha_token: params.captcha_token,
+        invite_code:   params.invite_code,
     }
     .encode_into_csrf();
+    storage
+        .insert_siwe_nonce(
+            &nonce,
+            &state_hash(csrf_with_redirect.secret()),
+            options.ethereum.eth_siwe_nonce_ttl,
+        )
+        .await?;
+
+    let tw_url = tw_client.client().map(|client| {
+        let pkce_challenge =
+            PkceCodeChallenge::from_code_verifier_sha256(&tw_client.pkce_verifier(&nonce));
+        let (url, _) = client
+            .authorize_url(|| csrf_with_redirect.clone())
+            .add_scope(Scope::new("tweet.read".to_string()))
+            .add_scope(Scope::new("users.read".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        url.to_string()
+    });
+
+    let discord_url = discord_client.client().map(|client| {
+        let (url, _) = client
+            .authorize_url(|| csrf_with_redirect.clone())
+            .add_scope(Scope::new("identify".to_string()))
+            .url();
+        url.to_string()
+    });
 
     let eth_auth_request = eth_client
         .authorize_url(|| csrf_with_redirect)
-        .add_scope(Scope::new("openid".to_string()));
+        .add_scope(Scope::new("openid".to_string()))
+        .add_extra_param("nonce", nonce);
 
     let (auth_url, csrf_with_redirect) = eth_auth_request.url();
 
@@ -157,8 +299,10 @@ pub async fn auth_client_link(
     let (gh_url, _) = gh_auth_request.url();
 
     Ok(AuthUrl {
-        eth_auth_url:    auth_url.to_string(),
-        github_auth_url: gh_url.to_string(),
+        eth_auth_url:     auth_url.to_string(),
+        github_auth_url:  gh_url.to_string(),
+        twitter_auth_url: tw_url,
+        discord_auth_url: discord_url,
     })
 }
 
@@ -175,8 +319,13 @@ pub struct RawAuthPayload {
 
 #[derive(Debug)]
 pub struct AuthPayload {
-    code:        String,
-    redirect_to: Option<String>,
+    code:         String,
+    redirect_to:  Option<String>,
+    pseudonymous: bool,
+    nonce:        Option<String>,
+    state_hash:   String,
+    region:       Option<Region>,
+    invite_code:  Option<String>,
 }
 
 #[async_trait]
@@ -190,6 +339,7 @@ where
         let Query(raw): Query<RawAuthPayload> = Query::from_request(req)
             .await
             .map_err(IntoResponse::into_response)?;
+        let state_hash = state_hash(&raw.state);
         let decoded_state =
             base64::decode_config(raw.state, base64::URL_SAFE_NO_PAD).map_err(|_| {
                 (
@@ -212,9 +362,30 @@ where
                 )
                     .into_response()
             })?;
+
+        // Bots are turned away before the code is exchanged. Routes without
+        // the extension don't require captchas.
+        if let Some(captcha) = req.extensions().get::<SharedAuthCaptcha>().cloned() {
+            captcha
+                .verify(json_decoded_state.captcha_token.as_deref())
+                .await
+                .map_err(|error| {
+                    AuthError {
+                        redirect: json_decoded_state.redirect.clone(),
+                        payload:  error.into(),
+                    }
+                    .into_response()
+                })?;
+        }
+
         Ok(Self {
-            code:        raw.code,
-            redirect_to: json_decoded_state.redirect,
+            code:         raw.code,
+            redirect_to:  json_decoded_state.redirect,
+            pseudonymous: json_decoded_state.pseudonymous,
+            nonce:        json_decoded_state.nonce,
+            state_hash,
+            region:       json_decoded_state.region,
+            invite_code:  json_decoded_state.invite_code,
         })
     }
 }
@@ -235,10 +406,12 @@ pub async fn github_callback(
     Extension(storage): Extension<PersistentStorage>,
     Extension(gh_oauth_client): Extension<GithubOAuthClient>,
     Extension(http_client): Extension<reqwest::Client>,
+    Extension(oauth_http_client): Extension<OAuthHttpClient>,
+    Extension(access_lists): Extension<SharedAccessLists>,
 ) -> Result<UserVerifiedResponse, AuthError> {
     let token = gh_oauth_client
         .exchange_code(AuthorizationCode::new(payload.code))
-        .request_async(async_http_client)
+        .request_async(|request| oauth_http_client.request(request))
         .await
         .map_err(|e| {
             if let RequestTokenError::Parse(_, bytes) = e {
@@ -282,13 +455,206 @@ pub async fn github_callback(
         id:       gh_user_info.id,
         username: gh_user_info.login.clone(),
     };
+    if !access_lists.is_allowed(&user) {
+        return Err(AuthError {
+            redirect: payload.redirect_to,
+            payload:  AuthErrorPayload::NotAllowed,
+        });
+    }
+    post_authenticate(
+        auth_state,
+        lobby_state,
+        storage,
+        user,
+        payload.redirect_to,
+        payload.pseudonymous,
+        payload.region,
+        payload.invite_code,
+        &options,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+struct TwitterUserInfo {
+    data: TwitterUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct TwitterUser {
+    id:         String,
+    username:   String,
+    created_at: DateTime<Utc>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn twitter_callback(
+    payload: AuthPayload,
+    Extension(options): Extension<Options>,
+    Extension(auth_state): Extension<SharedAuthState>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(tw_oauth_client): Extension<TwitterOAuthClient>,
+    Extension(http_client): Extension<reqwest::Client>,
+    Extension(oauth_http_client): Extension<OAuthHttpClient>,
+    Extension(access_lists): Extension<SharedAccessLists>,
+) -> Result<UserVerifiedResponse, AuthError> {
+    let client = tw_oauth_client.client().ok_or_else(|| AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::ProviderDisabled,
+    })?;
+    let nonce = payload.nonce.as_deref().ok_or_else(|| AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::InvalidNonce,
+    })?;
+    let token = client
+        .exchange_code(AuthorizationCode::new(payload.code))
+        .set_pkce_verifier(tw_oauth_client.pkce_verifier(nonce))
+        .request_async(|request| oauth_http_client.request(request))
+        .await
+        .map_err(|e| {
+            warn!("Twitter Token Exchange Error: {e}");
+            AuthError {
+                redirect: payload.redirect_to.clone(),
+                payload:  AuthErrorPayload::InvalidAuthCode,
+            }
+        })?;
+
+    let response = http_client
+        .get(&options.twitter.tw_userinfo_url)
+        .bearer_auth(token.access_token().secret())
+        .send()
+        .await
+        .map_err(|_| AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::FetchUserDataError,
+        })?;
+    let tw_user = response
+        .json::<TwitterUserInfo>()
+        .await
+        .map_err(|_| AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::CouldNotExtractUserData,
+        })?
+        .data;
+    let id = tw_user.id.parse().map_err(|_| AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::CouldNotExtractUserData,
+    })?;
+    if !is_old_enough(tw_user.created_at, options.twitter.tw_min_account_age) {
+        return Err(AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::UserCreatedAfterDeadline,
+        });
+    }
+    let user = Identity::Twitter {
+        id,
+        username: tw_user.username,
+    };
+    if !access_lists.is_allowed(&user) {
+        return Err(AuthError {
+            redirect: payload.redirect_to,
+            payload:  AuthErrorPayload::NotAllowed,
+        });
+    }
+    post_authenticate(
+        auth_state,
+        lobby_state,
+        storage,
+        user,
+        payload.redirect_to,
+        payload.pseudonymous,
+        payload.region,
+        payload.invite_code,
+        &options,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordUserInfo {
+    id:       String,
+    username: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn discord_callback(
+    payload: AuthPayload,
+    Extension(options): Extension<Options>,
+    Extension(auth_state): Extension<SharedAuthState>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(discord_oauth_client): Extension<DiscordOAuthClient>,
+    Extension(http_client): Extension<reqwest::Client>,
+    Extension(oauth_http_client): Extension<OAuthHttpClient>,
+    Extension(access_lists): Extension<SharedAccessLists>,
+) -> Result<UserVerifiedResponse, AuthError> {
+    let client = discord_oauth_client.client().ok_or_else(|| AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::ProviderDisabled,
+    })?;
+    let token = client
+        .exchange_code(AuthorizationCode::new(payload.code))
+        .request_async(|request| oauth_http_client.request(request))
+        .await
+        .map_err(|e| {
+            warn!("Discord Token Exchange Error: {e}");
+            AuthError {
+                redirect: payload.redirect_to.clone(),
+                payload:  AuthErrorPayload::InvalidAuthCode,
+            }
+        })?;
+
+    let response = http_client
+        .get(&options.discord.discord_userinfo_url)
+        .bearer_auth(token.access_token().secret())
+        .send()
+        .await
+        .map_err(|_| AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::FetchUserDataError,
+        })?;
+    let discord_user = response
+        .json::<DiscordUserInfo>()
+        .await
+        .map_err(|_| AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::CouldNotExtractUserData,
+        })?;
+    let id = discord_user.id.parse().map_err(|_| AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::CouldNotExtractUserData,
+    })?;
+    let creation_time = discord_creation_time(id).ok_or_else(|| AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::CouldNotExtractUserData,
+    })?;
+    if !is_old_enough(creation_time, options.discord.discord_min_account_age) {
+        return Err(AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::UserCreatedAfterDeadline,
+        });
+    }
+    let user = Identity::Discord {
+        id,
+        username: discord_user.username,
+    };
+    if !access_lists.is_allowed(&user) {
+        return Err(AuthError {
+            redirect: payload.redirect_to,
+            payload:  AuthErrorPayload::NotAllowed,
+        });
+    }
     post_authenticate(
         auth_state,
         lobby_state,
         storage,
         user,
         payload.redirect_to,
-        options.multi_contribution,
+        payload.pseudonymous,
+        payload.region,
+        payload.invite_code,
+        &options,
     )
     .await
 }
@@ -316,10 +682,30 @@ pub async fn eth_callback(
     Extension(storage): Extension<PersistentStorage>,
     Extension(oauth_client): Extension<EthOAuthClient>,
     Extension(http_client): Extension<reqwest::Client>,
+    Extension(oauth_http_client): Extension<OAuthHttpClient>,
+    Extension(access_lists): Extension<SharedAccessLists>,
 ) -> Result<UserVerifiedResponse, AuthError> {
+    // Each auth link can only be redeemed once, and only with the state it was
+    // issued in, so a captured callback can't be replayed or altered.
+    let invalid_nonce = || AuthError {
+        redirect: payload.redirect_to.clone(),
+        payload:  AuthErrorPayload::InvalidNonce,
+    };
+    let nonce = payload.nonce.as_deref().ok_or_else(invalid_nonce)?;
+    let nonce_is_valid = storage
+        .consume_siwe_nonce(nonce, &payload.state_hash)
+        .await
+        .map_err(|error| AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::Storage(error),
+        })?;
+    if !nonce_is_valid {
+        return Err(invalid_nonce());
+    }
+
     let token = oauth_client
         .exchange_code(AuthorizationCode::new(payload.code))
-        .request_async(async_http_client)
+        .request_async(|request| oauth_http_client.request(request))
         .await
         .map_err(|_| AuthError {
             redirect: payload.redirect_to.clone(),
@@ -344,25 +730,32 @@ pub async fn eth_callback(
             payload:  AuthErrorPayload::CouldNotExtractUserData,
         })?;
 
+    // The subject is `eip155:<chain id>:<address>`
     let addr_parts: Vec<_> = eth_user.sub.split(':').collect();
     let address = (*addr_parts.get(2).ok_or(AuthError {
         redirect: payload.redirect_to.clone(),
         payload:  AuthErrorPayload::CouldNotExtractUserData,
     })?)
     .to_string();
-
-    let tx_count = get_tx_count(
-        &address,
-        &options.ethereum.eth_nonce_verification_block,
-        &http_client,
-        &options.ethereum,
-    )
-    .await
-    .ok_or(AuthError {
+    let chain_id = addr_parts
+        .get(1)
+        .and_then(|chain_id| chain_id.parse::<u64>().ok())
+        .ok_or(AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::CouldNotExtractUserData,
+        })?;
+    let (rpc_url, at_block) = options.ethereum.chain(chain_id).ok_or(AuthError {
         redirect: payload.redirect_to.clone(),
-        payload:  AuthErrorPayload::CouldNotExtractUserData,
+        payload:  AuthErrorPayload::UnsupportedChain,
     })?;
 
+    let tx_count = get_tx_count(&address, at_block, &http_client, rpc_url)
+        .await
+        .ok_or(AuthError {
+            redirect: payload.redirect_to.clone(),
+            payload:  AuthErrorPayload::CouldNotExtractUserData,
+        })?;
+
     if tx_count < options.ethereum.eth_min_nonce {
         return Err(AuthError {
             redirect: payload.redirect_to.clone(),
@@ -374,6 +767,12 @@ pub async fn eth_callback(
         redirect: payload.redirect_to.clone(),
         payload:  AuthErrorPayload::CouldNotExtractUserData,
     })?;
+    if !access_lists.is_allowed(&user_data) {
+        return Err(AuthError {
+            redirect: payload.redirect_to,
+            payload:  AuthErrorPayload::NotAllowed,
+        });
+    }
 
     post_authenticate(
         auth_state,
@@ -381,17 +780,230 @@ pub async fn eth_callback(
         storage,
         user_data,
         payload.redirect_to,
-        options.multi_contribution,
+        payload.pseudonymous,
+        payload.region,
+        payload.invite_code,
+        &options,
     )
     .await
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PasskeyRegisterStartRequest {
+    /// Name shown by the authenticator for the new passkey.
+    name: String,
+}
+
+// Starts the registration of a new passkey user. The returned options are
+// passed to `navigator.credentials.create` on the client.
+pub async fn passkey_register_start(
+    Extension(passkeys): Extension<SharedPasskeyAuth>,
+    Json(request): Json<PasskeyRegisterStartRequest>,
+) -> Result<Json<Value>, AuthErrorPayload> {
+    let (challenge_id, user_id, challenge) = passkeys.start_registration(&request.name).await?;
+    Ok(Json(json!({
+        "challenge_id": challenge_id,
+        "user_id": user_id,
+        "options": challenge,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyRegisterFinishRequest {
+    challenge_id:  Uuid,
+    credential:    RegisterPublicKeyCredential,
+    #[serde(default)]
+    pseudonymous:  bool,
+    region:        Option<Region>,
+    captcha_token: Option<String>,
+    invite_code:   Option<String>,
+}
+
+// Stores the newly created passkey and signs the user in.
+pub async fn passkey_register_finish(
+    Extension(options): Extension<Options>,
+    Extension(auth_state): Extension<SharedAuthState>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(passkeys): Extension<SharedPasskeyAuth>,
+    Extension(captcha): Extension<SharedAuthCaptcha>,
+    Json(request): Json<PasskeyRegisterFinishRequest>,
+) -> Result<UserVerifiedResponse, AuthError> {
+    captcha
+        .verify(request.captcha_token.as_deref())
+        .await
+        .map_err(AuthErrorPayload::from)?;
+    let (user_id, passkey) = passkeys
+        .finish_registration(&request.challenge_id, &request.credential)
+        .await
+        .map_err(AuthErrorPayload::from)?;
+    let passkey = serde_json::to_string(&passkey).expect("Passkeys serialize to JSON");
+    storage
+        .store_passkey(&user_id.to_string(), &passkey)
+        .await
+        .map_err(AuthErrorPayload::from)?;
+
+    post_authenticate(
+        auth_state,
+        lobby_state,
+        storage,
+        passkey_identity(user_id),
+        None,
+        request.pseudonymous,
+        request.region,
+        request.invite_code,
+        &options,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginStartRequest {
+    user_id: Uuid,
+}
+
+// Starts signing in a passkey user. The returned options are passed to
+// `navigator.credentials.get` on the client.
+pub async fn passkey_login_start(
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(passkeys): Extension<SharedPasskeyAuth>,
+    Json(request): Json<PasskeyLoginStartRequest>,
+) -> Result<Json<Value>, AuthErrorPayload> {
+    let passkey = storage
+        .get_passkey(&request.user_id.to_string())
+        .await?
+        .and_then(|passkey| serde_json::from_str::<Passkey>(&passkey).ok())
+        .ok_or(AuthErrorPayload::InvalidPasskey)?;
+    let (challenge_id, challenge) = passkeys
+        .start_authentication(request.user_id, passkey)
+        .await?;
+    Ok(Json(json!({
+        "challenge_id": challenge_id,
+        "options": challenge,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginFinishRequest {
+    challenge_id:  Uuid,
+    credential:    PublicKeyCredential,
+    #[serde(default)]
+    pseudonymous:  bool,
+    region:        Option<Region>,
+    captcha_token: Option<String>,
+    invite_code:   Option<String>,
+}
+
+pub async fn passkey_login_finish(
+    Extension(options): Extension<Options>,
+    Extension(auth_state): Extension<SharedAuthState>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(passkeys): Extension<SharedPasskeyAuth>,
+    Extension(captcha): Extension<SharedAuthCaptcha>,
+    Json(request): Json<PasskeyLoginFinishRequest>,
+) -> Result<UserVerifiedResponse, AuthError> {
+    captcha
+        .verify(request.captcha_token.as_deref())
+        .await
+        .map_err(AuthErrorPayload::from)?;
+    let (user_id, result) = passkeys
+        .finish_authentication(&request.challenge_id, &request.credential)
+        .await
+        .map_err(AuthErrorPayload::from)?;
+
+    // Keep the signature counter of the stored passkey up to date.
+    let stored = storage
+        .get_passkey(&user_id.to_string())
+        .await
+        .map_err(AuthErrorPayload::from)?
+        .and_then(|passkey| serde_json::from_str::<Passkey>(&passkey).ok());
+    if let Some(mut passkey) = stored {
+        if passkey.update_credential(&result) == Some(true) {
+            let passkey = serde_json::to_string(&passkey).expect("Passkeys serialize to JSON");
+            storage
+                .store_passkey(&user_id.to_string(), &passkey)
+                .await
+                .map_err(AuthErrorPayload::from)?;
+        }
+    }
+
+    post_authenticate(
+        auth_state,
+        lobby_state,
+        storage,
+        passkey_identity(user_id),
+        None,
+        request.pseudonymous,
+        request.region,
+        request.invite_code,
+        &options,
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailStartRequest {
+    email: String,
+}
+
+// Sends a code to the given address, to be entered at `/auth/email/confirm`.
+// Required before entering the lobby when the sequencer is run with
+// `--email-verification`.
+pub async fn email_start(
+    session_id: SessionId,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(emails): Extension<SharedEmailVerifier>,
+    Json(request): Json<EmailStartRequest>,
+) -> Result<(), EmailError> {
+    let address = parse_address(&request.email)?;
+    let uid = lobby_state
+        .modify_participant(&session_id, |info| info.token.unique_identifier())
+        .await
+        .ok_or(EmailError::UnknownSessionId)?;
+    // Don't send codes for addresses that can't be claimed anyway
+    let owner = storage.email_owner(&emails.email_hash(&address)?).await?;
+    if matches!(owner, Some(owner) if owner != uid) {
+        return Err(EmailError::AddressInUse);
+    }
+    emails.send_code(&session_id, address).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailConfirmRequest {
+    code: String,
+}
+
+// Confirms the address with the emailed code, which binds it to the
+// identity for good.
+pub async fn email_confirm(
+    session_id: SessionId,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(emails): Extension<SharedEmailVerifier>,
+    Json(request): Json<EmailConfirmRequest>,
+) -> Result<(), EmailError> {
+    let uid = lobby_state
+        .modify_participant(&session_id, |info| info.token.unique_identifier())
+        .await
+        .ok_or(EmailError::UnknownSessionId)?;
+    let email_hash = emails.confirm_code(&session_id, &request.code).await?;
+    if !storage.claim_email(&email_hash, &uid).await? {
+        return Err(EmailError::AddressInUse);
+    }
+    lobby_state
+        .modify_participant(&session_id, |info| info.email_verified = true)
+        .await
+        .ok_or(EmailError::UnknownSessionId)
+}
+
 // TODO: This has many failure modes and should return and eyre::Result.
 async fn get_tx_count(
     address: &str,
     at_block: &str,
     client: &reqwest::Client,
-    options: &EthAuthOptions,
+    rpc_url: &Secret,
 ) -> Option<u64> {
     let rpc_payload = json!({
         "id": 1,
@@ -401,7 +1013,7 @@ async fn get_tx_count(
     });
 
     let rpc_response = client
-        .post(options.eth_rpc_url.get_secret())
+        .post(rpc_url.get_secret())
         .json(&rpc_payload)
         .send()
         .await
@@ -414,68 +1026,148 @@ async fn get_tx_count(
     u64::from_str_radix(rpc_result.trim_start_matches("0x"), 16).ok()
 }
 
+fn invitation_error(invite_code: Option<&str>) -> AuthErrorPayload {
+    if invite_code.is_some() {
+        AuthErrorPayload::InvalidInvitation
+    } else {
+        AuthErrorPayload::InvitationRequired
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn post_authenticate(
     auth_state: SharedAuthState,
     lobby_state: SharedLobbyState,
     storage: PersistentStorage,
     user_data: Identity,
     redirect_to: Option<String>,
-    multi_contribution: bool,
+    pseudonymous: bool,
+    region: Option<Region>,
+    invite_code: Option<String>,
+    options: &Options,
 ) -> Result<UserVerifiedResponse, AuthError> {
-    // Check if they have already contributed
-    match storage.has_contributed(&user_data.unique_id()).await {
-        Err(error) => {
+    let storage_error = |error| AuthError {
+        redirect: redirect_to.clone(),
+        payload:  AuthErrorPayload::Storage(error),
+    };
+    let pseudonym = options.pseudonym.pseudonym(&user_data);
+    let user_uid = user_data.unique_id();
+
+    // Check if they have already contributed, under their identity or their
+    // pseudonym
+    let mut has_contributed = storage
+        .has_contributed(&user_uid)
+        .await
+        .map_err(storage_error)?;
+    if let Some(pseudonym) = &pseudonym {
+        has_contributed |= storage
+            .has_contributed(&pseudonym.unique_id())
+            .await
+            .map_err(storage_error)?;
+    }
+    if has_contributed {
+        if options.multi_contribution {
+            warn!(uid = %user_data, "User has already contributed, accepting multiple.");
+        } else {
             return Err(AuthError {
                 redirect: redirect_to.clone(),
-                payload:  AuthErrorPayload::Storage(error),
-            })
+                payload:  AuthErrorPayload::UserAlreadyContributed,
+            });
         }
-        Ok(true) => {
-            if multi_contribution {
-                warn!(uid = %user_data, "User has already contributed, accepting multiple.");
-            } else {
-                return Err(AuthError {
-                    redirect: redirect_to.clone(),
-                    payload:  AuthErrorPayload::UserAlreadyContributed,
-                });
-            }
-        }
-        Ok(false) => (),
     }
 
-    // Check if this user is already in the lobby
-    // If so, we send them back their session id
-    let session_id = {
-        let mut state = auth_state.write().await;
-
-        #[allow(clippy::option_if_let_else)]
-        if let Some(session_id) = state.unique_id_session.get(&user_data.unique_id()) {
-            session_id.clone()
-        } else {
-            let id = SessionId::new();
-            state
-                .unique_id_session
-                .insert(user_data.unique_id(), id.clone());
-            id
+    // Banned identities can't start over under their pseudonym. The ban
+    // lifts if the limit is raised, as in `/lobby/try_contribute`.
+    let mark = storage
+        .identity_mark(&user_uid)
+        .await
+        .map_err(storage_error)?;
+    if mark.as_deref() == Some(Mark::Banned.as_str()) {
+        let failed_attempts = storage
+            .failed_attempts(&user_uid)
+            .await
+            .map_err(storage_error)?;
+        if options.attempts.check(&failed_attempts, Utc::now())
+            == Err(AttemptError::TooManyFailedAttempts)
+        {
+            return Err(AuthError {
+                redirect: redirect_to.clone(),
+                payload:  AuthErrorPayload::TooManyFailedAttempts,
+            });
         }
-    };
+    }
 
-    let id_token = IdToken {
-        identity: user_data,
-        exp:      u64::MAX,
-    };
+    // Codes are redeemed by the identity signed in with, so that it stays
+    // admitted whether or not it contributes under a pseudonym
+    let admitted = invitations::admit(
+        &options.invitations,
+        &storage,
+        invite_code.as_deref(),
+        &user_uid,
+    )
+    .await
+    .map_err(storage_error)?;
+    if !admitted {
+        return Err(AuthError {
+            redirect: redirect_to.clone(),
+            payload:  invitation_error(invite_code.as_deref()),
+        });
+    }
 
-    lobby_state
-        .insert_session(session_id.clone(), SessionInfo {
-            token:                 id_token.clone(),
-            last_ping_time:        Instant::now(),
-            is_first_ping_attempt: true,
-        })
+    // Limit how many sessions this user holds at a time, so that signing in
+    // from several devices doesn't win them several spots in the lobby. The
+    // sessions making room are only dropped once the new one is in the lobby,
+    // so that a sign-in failing on the way doesn't cost the user theirs.
+    let mut auth_state = auth_state.write().await;
+    let replaced = auth_state
+        .sessions_to_replace(&user_uid, &options.sessions, &lobby_state)
         .await
-        .map_err(|_| AuthError {
+        .ok_or_else(|| AuthError {
+            redirect: redirect_to.clone(),
+            payload:  AuthErrorPayload::TooManySessions,
+        })?;
+
+    let identity = if pseudonymous {
+        let pseudonym = pseudonym.ok_or_else(|| AuthError {
             redirect: redirect_to.clone(),
-            payload:  AuthErrorPayload::LobbyIsFull,
+            payload:  AuthErrorPayload::PseudonymsDisabled,
         })?;
+        storage
+            .insert_pseudonym(&pseudonym.unique_id(), &user_uid)
+            .await
+            .map_err(storage_error)?;
+        pseudonym
+    } else {
+        user_data
+    };
+
+    let uid = identity.unique_id();
+    wal::log(&storage, Operation::SessionIssue, &uid, &json!({}))
+        .await
+        .map_err(storage_error)?;
+    let mut session_info = issue_lobby_token(identity, region, options.lobby.lobby_token_ttl);
+    session_info.uid = user_uid.clone();
+    // Addresses are only confirmed once per identity
+    session_info.email_verified = storage
+        .has_verified_email(&session_info.token.unique_identifier())
+        .await
+        .map_err(storage_error)?;
+    let id_token = session_info.token.clone();
+
+    let session_id = SessionId::new();
+    let inserted = lobby_state
+        .insert_session(session_id.clone(), session_info)
+        .await;
+    if inserted.is_ok() {
+        auth_state
+            .add_session(&user_uid, session_id.clone(), &replaced, &lobby_state)
+            .await;
+    }
+    wal::complete(&storage, Operation::SessionIssue, &uid).await;
+    inserted.map_err(|_| AuthError {
+        redirect: redirect_to.clone(),
+        payload:  AuthErrorPayload::LobbyIsFull,
+    })?;
 
     Ok(UserVerifiedResponse {
         id_token,
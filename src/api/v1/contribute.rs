@@ -1,27 +1,66 @@
 use crate::{
-    io::write_json_file,
-    keys::{SharedKeys, Signature, SignatureError},
-    lobby::SharedLobbyState,
-    receipt::Receipt,
-    storage::{PersistentStorage, StorageError},
-    Engine, Options, SessionId, SharedCeremonyStatus, SharedTranscript,
+    api::v1::upload::ContributionUpload,
+    api_types::{ConfirmRequest, ContributeQuery, ExtendQuery, ExtendResponse},
+    attempts::AttemptOutcome,
+    clock::server_time,
+    completion::SharedCompletion,
+    confirmation::{PendingContribution, SharedPendingContributions},
+    forecast::SharedForecast,
+    guard::{self, Mark},
+    keys::{Keys, SharedKeys, Signature, SignatureError},
+    lobby::{ActiveContributorError, SharedLobbyState, SlotId},
+    mirror::SharedMirrors,
+    quarantine,
+    receipt::{is_countersigned, parse_address, session_hash, Receipt},
+    report::CONTRIBUTION_EVENT,
+    reporting::{self, session_id_hash},
+    share::SharePayloads,
+    staging::{SharedTranscriptWriter, StagedContribution},
+    storage::{PersistentStorage, StorageError, StoredReceipt},
+    verifier::SharedVerifier,
+    wal::{self, Operation},
+    webhooks, Engine, Options, SessionId, SharedCeremonyStatus, SharedTranscript,
 };
 use axum::{
+    extract::Query,
     response::{IntoResponse, Response},
     Extension, Json,
 };
 use axum_extra::response::ErasedJson;
+use chrono::Utc;
 use http::StatusCode;
 use kzg_ceremony_crypto::{BatchContribution, CeremoniesError, ErrorCode};
 use serde::Serialize;
-use std::sync::atomic::Ordering;
+use serde_json::{json, Value};
+use std::{sync::atomic::Ordering, time::Duration};
 use strum::IntoStaticStr;
 use thiserror::Error;
+use tokio::{task::JoinError, time::Instant};
+use tracing::{error, info};
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct ContributeReceipt {
-    receipt:   String,
-    signature: Signature,
+    receipt:       String,
+    signature:     Signature,
+    /// Signature of `receipt` with the sequencer's BLS key, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bls_signature: Option<String>,
+    eip712:        TypedReceipt,
+    /// Payloads for publishing the receipt, if enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    share:         Option<SharePayloads>,
+    /// Set while the contribution waits for the participant to countersign
+    /// the receipt, the unix timestamp `/contribute/confirm` has to be called
+    /// by. The receipt only becomes valid once it is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirm_by:    Option<u64>,
+}
+
+/// The receipt as EIP-712 typed data, verifiable in standard wallets.
+#[derive(Clone, Serialize)]
+pub struct TypedReceipt {
+    typed_data: Value,
+    signature:  Signature,
 }
 
 impl IntoResponse for ContributeReceipt {
@@ -34,10 +73,33 @@ impl IntoResponse for ContributeReceipt {
 pub enum ContributeError {
     #[error("not your turn to participate")]
     NotUsersTurn,
+    #[error("contribution slot is no longer valid")]
+    StaleSlot,
+    #[error("contribution is not signed for the authenticated identity")]
+    UnboundContribution,
+    #[error("contribution was computed against another transcript")]
+    TranscriptMismatch,
+    #[error(
+        "potPubkey of contribution {ceremony} is already in the transcript, at witness entry \
+         {index}"
+    )]
+    DuplicatePubkey { ceremony: usize, index: usize },
+    #[error("deadline extensions are disabled")]
+    ExtensionDisabled,
+    #[error("deadline was extended already")]
+    AlreadyExtended,
+    #[error("no contribution is waiting for confirmation")]
+    NotAwaitingConfirmation,
+    #[error("countersignature is not a signature of the receipt by the countersigner")]
+    InvalidCountersignature,
+    #[error("confirming contributions requires a countersigner address")]
+    InvalidCountersigner,
     #[error("contribution invalid: {0}")]
     InvalidContribution(#[from] CeremoniesError),
     #[error("signature error: {0}")]
     Signature(SignatureError),
+    #[error("contribution could not be processed")]
+    ProcessingFailed,
     #[error("storage error: {0}")]
     StorageError(#[from] StorageError),
 }
@@ -48,111 +110,648 @@ impl ErrorCode for ContributeError {
     }
 }
 
+/// What adding a contribution to the transcript updates.
+struct CeremonyState {
+    lobby_state:       SharedLobbyState,
+    options:           Options,
+    transcript:        SharedTranscript,
+    storage:           PersistentStorage,
+    num_contributions: SharedCeremonyStatus,
+    mirrors:           SharedMirrors,
+    transcript_writer: SharedTranscriptWriter,
+    completion:        SharedCompletion,
+    forecast:          SharedForecast,
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn contribute(
     session_id: SessionId,
-    Json(contribution): Json<BatchContribution>,
+    Query(mut query): Query<ContributeQuery>,
+    ContributionUpload(contribution): ContributionUpload,
     Extension(lobby_state): Extension<SharedLobbyState>,
     Extension(options): Extension<Options>,
     Extension(shared_transcript): Extension<SharedTranscript>,
     Extension(storage): Extension<PersistentStorage>,
     Extension(num_contributions): Extension<SharedCeremonyStatus>,
     Extension(keys): Extension<SharedKeys>,
+    Extension(verifier): Extension<SharedVerifier>,
+    Extension(mirrors): Extension<SharedMirrors>,
+    Extension(transcript_writer): Extension<SharedTranscriptWriter>,
+    Extension(completion): Extension<SharedCompletion>,
+    Extension(forecast): Extension<SharedForecast>,
+    Extension(pending_contributions): Extension<SharedPendingContributions>,
 ) -> Result<ContributeReceipt, ContributeError> {
-    let id_token = lobby_state
-        .begin_contributing(&session_id)
-        .await
-        .map_err(|_| ContributeError::NotUsersTurn)?
-        .token;
+    // Responses are padded from here on, rejections included, see `timing`
+    let started = Instant::now();
+    query.countersigner = match countersigner(&options, query.countersigner.as_deref()) {
+        Ok(countersigner) => countersigner,
+        Err(error) => {
+            options.timing.pad(started, true).await;
+            return Err(error);
+        }
+    };
 
-    let result = {
-        let mut transcript = shared_transcript.write().await;
-        transcript
-            .verify_add::<Engine>(contribution.clone(), id_token.identity.clone())
-            .map_err(ContributeError::InvalidContribution)
+    let state = CeremonyState {
+        lobby_state: lobby_state.clone(),
+        options,
+        transcript: shared_transcript,
+        storage: storage.clone(),
+        num_contributions,
+        mirrors,
+        transcript_writer,
+        completion,
+        forecast,
     };
+    // Once the slot is taken, only processing the contribution gives it back,
+    // so it runs in its own task that the route timeout can't cancel.
+    let processed = tokio::spawn(process_contribution(
+        state,
+        started,
+        session_id.clone(),
+        query,
+        contribution,
+        keys,
+        verifier,
+        pending_contributions,
+    ))
+    .await;
+    match processed {
+        Ok(result) => result,
+        Err(error) => Err(processing_failed(&lobby_state, &storage, &session_id, error).await),
+    }
+}
 
-    if let Err(e) = result {
+/// The address the receipt has to be countersigned with, normalized, if
+/// contributions are confirmed. Checked before the slot is taken, so that
+/// participants can try again.
+fn countersigner(
+    options: &Options,
+    declared: Option<&str>,
+) -> Result<Option<String>, ContributeError> {
+    if !options.confirmation.confirm_contributions {
+        return Ok(None);
+    }
+    declared
+        .and_then(parse_address)
+        .map(|address| Some(format!("0x{}", hex::encode(address))))
+        .ok_or(ContributeError::InvalidCountersigner)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_contribution(
+    state: CeremonyState,
+    started: Instant,
+    session_id: SessionId,
+    query: ContributeQuery,
+    contribution: BatchContribution,
+    keys: SharedKeys,
+    verifier: SharedVerifier,
+    pending_contributions: SharedPendingContributions,
+) -> Result<ContributeReceipt, ContributeError> {
+    let CeremonyState {
+        lobby_state,
+        options,
+        transcript: shared_transcript,
+        storage,
+        ..
+    } = &state;
+    let timing = options.timing.clone();
+    let session_info = match lobby_state
+        .begin_contributing(&session_id, &SlotId(query.slot_id.clone()))
+        .await
+    {
+        Ok(session_info) => session_info,
+        Err(error) => {
+            timing.pad(started, true).await;
+            return Err(match error {
+                ActiveContributorError::StaleSlot => ContributeError::StaleSlot,
+                _ => ContributeError::NotUsersTurn,
+            });
+        }
+    };
+    let uid = session_info.uid;
+    let id_token = session_info.token;
+    let payload = wal::contribution_payload(shared_transcript.snapshot().num_participants() + 1);
+    if let Err(error) = wal::log(storage, Operation::ContributionApply, &uid, &payload).await {
+        // A contribution that can't be recovered must not be applied, so the
+        // slot is given back.
         lobby_state.clear_current_contributor().await;
-        storage
-            .expire_contribution(&id_token.unique_identifier())
-            .await?;
-        return Err(e);
+        storage.expire_contribution(&uid).await?;
+        guard::mark(storage, &uid, Mark::Aborted).await;
+        wal::complete(storage, Operation::SlotGrant, &uid).await;
+        timing.pad(started, true).await;
+        return Err(error.into());
     }
 
+    // Contributions built on another transcript can't verify, and comparing
+    // the hash is far cheaper than finding out with pairings.
+    let is_current =
+        query.transcript_hash == shared_transcript.contribution_template().transcript_hash;
+    // The spec has participants sign their identity with their secret, so
+    // that nobody else can claim their contribution.
+    let is_bound = options.allow_unbound_contributions
+        || contribution.is_bound_to::<Engine>(&id_token.identity);
+    let duplicate = shared_transcript.find_duplicate_pubkey(&contribution);
+    let result = if !is_current {
+        Err(ContributeError::TranscriptMismatch)
+    } else if let Some((ceremony, index)) = duplicate {
+        Err(ContributeError::DuplicatePubkey { ceremony, index })
+    } else if is_bound {
+        verifier
+            .verify(
+                shared_transcript,
+                contribution.clone(),
+                id_token.identity.clone(),
+            )
+            .await
+            .map_err(|error| {
+                let index = match error {
+                    CeremoniesError::InvalidCeremony(index, _) => Some(index),
+                    CeremoniesError::UnexpectedNumContributions(..)
+                    | CeremoniesError::BeaconApplied
+                    | CeremoniesError::InvalidBeacon
+                    | CeremoniesError::InvalidEntropyAttestation => None,
+                };
+                reporting::report(
+                    "verification_failure",
+                    error.to_string(),
+                    json!({
+                        "code": error.to_error_code(),
+                        "contribution_index": index,
+                        "session_id_hash": session_id_hash(&session_id),
+                    }),
+                );
+                webhooks::notify(
+                    "contribution_rejected",
+                    json!({
+                        "code": error.to_error_code(),
+                        "error": error.to_string(),
+                    }),
+                );
+                ContributeError::InvalidContribution(error)
+            })
+    } else {
+        Err(ContributeError::UnboundContribution)
+    };
+
+    let verified = match result {
+        Ok(verified) => verified,
+        Err(e) => {
+            give_back_slot(lobby_state, storage, &uid, AttemptOutcome::Rejected).await?;
+            quarantine::store(&options.quarantine, storage, &uid, &e, &contribution).await;
+            timing.pad(started, true).await;
+            return Err(e);
+        }
+    };
+
     let receipt = Receipt {
-        identity: id_token.identity,
-        witness:  contribution.receipt(),
+        identity:            id_token.identity,
+        witness:             contribution.receipt(),
+        entropy_attestation: contribution.entropy_attestation,
+        region:              session_info.region,
+        countersigner:       query.countersigner,
+    };
+    let response = match sign_receipt(options, &keys, &receipt).await {
+        Ok(response) => response,
+        Err(error) => {
+            give_back_slot(lobby_state, storage, &uid, AttemptOutcome::Rejected).await?;
+            return Err(error);
+        }
+    };
+    let pending = PendingContribution {
+        session_id,
+        slot_id: SlotId(query.slot_id),
+        uid,
+        region: session_info.region,
+        verified,
+        receipt,
+        response,
     };
 
-    let (signed_msg, signature) = receipt
-        .sign(&keys)
-        .await
-        .map_err(ContributeError::Signature)?;
+    let confirmation = &options.confirmation;
+    if confirmation.confirm_contributions {
+        let mut response = pending.response.clone();
+        response.confirm_by =
+            Some(server_time().saturating_add(confirmation.confirmation_deadline.as_secs()));
+        tokio::spawn(expire_unconfirmed(
+            pending_contributions.clone(),
+            pending.slot_id.clone(),
+            pending.uid.clone(),
+            confirmation.confirmation_deadline,
+            lobby_state.clone(),
+            storage.clone(),
+        ));
+        pending_contributions.hold(pending).await;
+        timing.pad(started, false).await;
+        return Ok(response);
+    }
 
-    write_json_file(
-        options.transcript_file,
-        options.transcript_in_progress_file,
-        shared_transcript,
-    )
-    .await;
+    let response = apply(&state, pending, None).await?;
+    timing.pad(started, false).await;
+    Ok(response)
+}
 
-    lobby_state.clear_current_contributor().await;
-    storage.finish_contribution(&session_id.0).await?;
+/// Adds the contribution held back by `/contribute` to the transcript, once
+/// the participant countersigned its receipt.
+#[allow(clippy::too_many_arguments)]
+pub async fn contribute_confirm(
+    session_id: SessionId,
+    Json(request): Json<ConfirmRequest>,
+    Extension(pending_contributions): Extension<SharedPendingContributions>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(options): Extension<Options>,
+    Extension(shared_transcript): Extension<SharedTranscript>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(num_contributions): Extension<SharedCeremonyStatus>,
+    Extension(mirrors): Extension<SharedMirrors>,
+    Extension(transcript_writer): Extension<SharedTranscriptWriter>,
+    Extension(completion): Extension<SharedCompletion>,
+    Extension(forecast): Extension<SharedForecast>,
+) -> Result<ContributeReceipt, ContributeError> {
+    let countersignature = request.countersignature.trim_start_matches("0x").to_owned();
+    let signature = Signature::from_hex(countersignature.clone());
+    // Checked while taking the contribution, so that a bad countersignature
+    // leaves it waiting for another try.
+    let mut error = ContributeError::NotAwaitingConfirmation;
+    let taken = pending_contributions
+        .take_if(|pending| {
+            if pending.session_id != session_id {
+                return false;
+            }
+            // Declared in the authenticated upload, and part of the receipt
+            let is_valid =
+                pending
+                    .receipt
+                    .countersigner
+                    .as_deref()
+                    .map_or(false, |countersigner| {
+                        is_countersigned(countersigner, &pending.response.receipt, &signature)
+                    });
+            if !is_valid {
+                error = ContributeError::InvalidCountersignature;
+            }
+            is_valid
+        })
+        .await;
+    let pending = taken.ok_or(error)?;
+    info!(uid = %pending.uid, "Contribution confirmed");
 
-    num_contributions.fetch_add(1, Ordering::Relaxed);
+    let state = CeremonyState {
+        lobby_state: lobby_state.clone(),
+        options,
+        transcript: shared_transcript,
+        storage: storage.clone(),
+        num_contributions,
+        mirrors,
+        transcript_writer,
+        completion,
+        forecast,
+    };
+    // Like `/contribute`, applying must finish once started
+    let applied =
+        tokio::spawn(async move { apply(&state, pending, Some(countersignature)).await }).await;
+    match applied {
+        Ok(result) => result,
+        Err(error) => Err(processing_failed(&lobby_state, &storage, &session_id, error).await),
+    }
+}
 
+/// Signs the receipt of a verified contribution.
+async fn sign_receipt(
+    options: &Options,
+    keys: &Keys,
+    receipt: &Receipt,
+) -> Result<ContributeReceipt, ContributeError> {
+    let (signed_msg, signature) = receipt
+        .sign(keys)
+        .await
+        .map_err(ContributeError::Signature)?;
+    let bls_signature = keys
+        .sign_bls(&signed_msg)
+        .and_then(|signature| signature.0)
+        .map(|signature| hex::encode(signature.0));
+    let (typed_data, typed_signature) = receipt.sign_typed(keys);
+    let share = options
+        .share
+        .share_payloads
+        .then(|| SharePayloads::new(&options.share, receipt, &signed_msg, &signature));
     Ok(ContributeReceipt {
         receipt: signed_msg,
         signature,
+        bls_signature,
+        eip712: TypedReceipt {
+            typed_data,
+            signature: typed_signature,
+        },
+        share,
+        confirm_by: None,
     })
 }
 
+/// Gives back the slot of a contribution that won't be added, and records
+/// the failed attempt.
+async fn give_back_slot(
+    lobby_state: &SharedLobbyState,
+    storage: &PersistentStorage,
+    uid: &str,
+    outcome: AttemptOutcome,
+) -> Result<(), StorageError> {
+    lobby_state.clear_current_contributor().await;
+    close_attempt(storage, uid, outcome).await
+}
+
+/// Cleans up after processing a contribution panicked. The slot is given
+/// back if the session still holds it, as on the other error paths.
+async fn processing_failed(
+    lobby_state: &SharedLobbyState,
+    storage: &PersistentStorage,
+    session_id: &SessionId,
+    error: JoinError,
+) -> ContributeError {
+    error!(?error, "Contribution processing panicked");
+    if let Some(session_info) = lobby_state.release_slot_of(session_id).await {
+        let uid = session_info.uid;
+        if let Err(error) = close_attempt(storage, &uid, AttemptOutcome::Rejected).await {
+            error!(?error, %uid, "Could not close contribution attempt");
+        }
+    }
+    ContributeError::ProcessingFailed
+}
+
+/// Records the end of an attempt whose slot was given back.
+async fn close_attempt(
+    storage: &PersistentStorage,
+    uid: &str,
+    outcome: AttemptOutcome,
+) -> Result<(), StorageError> {
+    storage.expire_contribution(uid).await?;
+    guard::mark(storage, uid, Mark::Aborted).await;
+    wal::complete(storage, Operation::ContributionApply, uid).await;
+    wal::complete(storage, Operation::SlotGrant, uid).await;
+    if let Err(error) = storage.insert_failed_attempt(uid, outcome.as_str()).await {
+        error!(?error, "Could not record failed attempt");
+    }
+    Ok(())
+}
+
+/// Drops the contribution made in `slot_id` if it isn't confirmed within
+/// `deadline`, and gives back the slot.
+async fn expire_unconfirmed(
+    pending_contributions: SharedPendingContributions,
+    slot_id: SlotId,
+    uid: String,
+    deadline: Duration,
+    lobby_state: SharedLobbyState,
+    storage: PersistentStorage,
+) {
+    tokio::time::sleep(deadline).await;
+    if pending_contributions
+        .take_if(|pending| pending.slot_id == slot_id)
+        .await
+        .is_none()
+    {
+        return;
+    }
+    info!(%uid, "Dropped contribution that was not confirmed in time");
+    if let Err(error) = give_back_slot(&lobby_state, &storage, &uid, AttemptOutcome::Expired).await
+    {
+        error!(?error, "Could not expire unconfirmed contribution");
+    }
+}
+
+/// Adds a verified contribution to the transcript and finishes the
+/// participant's turn.
+async fn apply(
+    state: &CeremonyState,
+    pending: PendingContribution,
+    countersignature: Option<String>,
+) -> Result<ContributeReceipt, ContributeError> {
+    let PendingContribution {
+        session_id,
+        uid,
+        region,
+        verified,
+        receipt,
+        response,
+        ..
+    } = pending;
+    let contribution = verified.contribution().clone();
+    let timings = match verified.add_to(&state.transcript).await {
+        Ok(timings) => timings,
+        Err(error) => {
+            give_back_slot(
+                &state.lobby_state,
+                &state.storage,
+                &uid,
+                AttemptOutcome::Rejected,
+            )
+            .await?;
+            return Err(error.into());
+        }
+    };
+
+    let snapshot = state.transcript.snapshot();
+    let participant = snapshot.num_participants();
+    let (received_at, verified_at) = snapshot
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.contributions.last())
+        .map_or((0, 0), |metadata| {
+            (metadata.received_at, metadata.verified_at)
+        });
+    let staged = StagedContribution {
+        contribution,
+        identity: receipt.identity.clone(),
+        received_at,
+        verified_at,
+    };
+
+    // Once staged, the contribution survives failing to write the transcript,
+    // so the request succeeds regardless.
+    if state.transcript_writer.persist(participant, &staged).await {
+        let mirrors = state.mirrors.clone();
+        let transcript_file = state.options.transcript_file.clone();
+        tokio::spawn(async move { mirrors.push_file(&transcript_file).await });
+    }
+
+    // Marked before the slot is freed, so that the identity can't take it
+    // again
+    guard::mark(&state.storage, &uid, Mark::Contributed).await;
+
+    // The last contribution closes the lobby before the slot is freed, so
+    // that nobody else takes it.
+    if state.completion.reach(participant) {
+        state.lobby_state.close().await;
+        let transcript = state.transcript.snapshot();
+        let completion = state.completion.clone();
+        tokio::spawn(async move { completion.announce(transcript).await });
+    }
+    state.lobby_state.clear_current_contributor().await;
+    state.storage.finish_contribution(&uid).await?;
+    wal::complete(&state.storage, Operation::ContributionApply, &uid).await;
+    wal::complete(&state.storage, Operation::SlotGrant, &uid).await;
+
+    // The contribution is in, so a failure to store the receipt must not fail
+    // the request. The participant still gets the receipt in the response.
+    let stored = state
+        .storage
+        .insert_receipt(&session_hash(&session_id), &StoredReceipt {
+            participant,
+            receipt: response.receipt.clone(),
+            signature: response.signature.as_hex().to_owned(),
+            bls_signature: response.bls_signature.clone(),
+            countersignature,
+        })
+        .await;
+    if let Err(error) = stored {
+        error!(?error, "Could not store receipt");
+    }
+    if let Some(region) = region {
+        if let Err(error) = state.storage.count_region(region.as_str()).await {
+            error!(?error, "Could not count region");
+        }
+    }
+    if let Err(error) = state
+        .storage
+        .insert_audit_event(
+            CONTRIBUTION_EVENT,
+            &receipt.identity.unique_id(),
+            &json!(timings),
+        )
+        .await
+    {
+        error!(?error, "Could not record verification timings");
+    }
+
+    let num_participants = state.num_contributions.fetch_add(1, Ordering::Relaxed) + 1;
+    state.forecast.record(Utc::now());
+    webhooks::notify(
+        "contribution_accepted",
+        json!({
+            "participant": receipt.identity.to_string(),
+            "num_participants": num_participants,
+        }),
+    );
+    Ok(response)
+}
+
 pub async fn contribute_abort(
     session_id: SessionId,
     Extension(lobby_state): Extension<SharedLobbyState>,
     Extension(storage): Extension<PersistentStorage>,
 ) -> Result<(), ContributeError> {
-    lobby_state
+    let session_info = lobby_state
         .abort_contribution(&session_id)
         .await
         .map_err(|_| ContributeError::NotUsersTurn)?;
-    storage.expire_contribution(&session_id.0).await?;
+    let uid = session_info.uid;
+    storage.expire_contribution(&uid).await?;
+    guard::mark(&storage, &uid, Mark::Aborted).await;
+    wal::complete(&storage, Operation::SlotGrant, &uid).await;
+    storage
+        .insert_failed_attempt(&uid, AttemptOutcome::Aborted.as_str())
+        .await?;
     Ok(())
 }
 
+/// Extends the deadline of the reserved slot, once per slot, for clients on
+/// slow hardware that can't finish in time. Each extension is recorded in the
+/// audit log.
+pub async fn contribute_extend(
+    session_id: SessionId,
+    Query(query): Query<ExtendQuery>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(storage): Extension<PersistentStorage>,
+) -> Result<Json<ExtendResponse>, ContributeError> {
+    let max_extension = lobby_state.options().compute_deadline_max_extension;
+    if max_extension.is_zero() {
+        return Err(ContributeError::ExtensionDisabled);
+    }
+    let extension = query
+        .seconds
+        .map_or(max_extension, Duration::from_secs)
+        .min(max_extension);
+
+    let (session_info, time_left) = lobby_state
+        .extend_deadline(&session_id, extension)
+        .await
+        .map_err(|error| match error {
+            ActiveContributorError::AlreadyExtended => ContributeError::AlreadyExtended,
+            _ => ContributeError::NotUsersTurn,
+        })?;
+    let uid = session_info.token.unique_identifier();
+    info!(%uid, seconds = extension.as_secs(), "Extended contribution deadline");
+    storage
+        .insert_audit_event(
+            "deadline_extended",
+            &uid,
+            &json!({
+                "session_id_hash": session_id_hash(&session_id),
+                "seconds": extension.as_secs(),
+            }),
+        )
+        .await?;
+
+    let now = server_time();
+    Ok(Json(ExtendResponse {
+        deadline:     now.saturating_add(time_left.as_secs()),
+        seconds_left: time_left.as_secs(),
+        server_time:  now,
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
+        announcement::SharedAnnouncement,
         api::v1::{
             contribute::ContributeError,
-            lobby::{try_contribute, TryContributeError, TryContributeResponse},
+            lobby::{try_contribute, TryContributeError},
         },
+        api_types::TryContributeResponse,
+        completion::SharedCompletion,
         contribute,
-        io::read_json_file,
+        forecast::SharedForecast,
+        io::{read_json_file, transcript_hash},
         keys,
         keys::SharedKeys,
-        lobby::SharedLobbyState,
+        lobby::{LobbyParams, SharedLobbyState, SlotId},
+        mirror::{self, Mirrors, SharedMirrors},
+        staging::TranscriptWriter,
         storage::storage_client,
-        test_util::{create_test_session_info, test_options},
-        tests::{invalid_contribution, test_transcript, valid_contribution},
-        Keys, SessionId,
+        test_util::{create_test_session_info, test_jwt, test_options},
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+        verifier::{self, SharedVerifier, Verifier},
+        Engine, Keys, SessionId,
     };
-    use axum::{Extension, Json};
+    use axum::{extract::Query, Extension, Json};
     use clap::Parser;
-    use kzg_ceremony_crypto::{signature::identity::Identity, BatchTranscript};
+    use kzg_ceremony_crypto::{
+        signature::{identity::Identity, BlsSignature},
+        BatchTranscript, Secret, G1,
+    };
     use std::{
         sync::{atomic::AtomicUsize, Arc},
         time::Duration,
     };
-    use tokio::sync::RwLock;
 
     fn shared_keys() -> SharedKeys {
         let options = keys::Options::parse_from(Vec::<&str>::new());
         Arc::new(Keys::new(&options).unwrap())
     }
 
+    fn shared_verifier() -> SharedVerifier {
+        let options = verifier::Options::parse_from(Vec::<&str>::new());
+        Arc::new(Verifier::new(&options).unwrap())
+    }
+
+    fn shared_mirrors() -> SharedMirrors {
+        let options = mirror::Options::parse_from(Vec::<&str>::new());
+        Arc::new(Mirrors::new(&options, reqwest::Client::new()).unwrap())
+    }
+
     #[tokio::test]
     async fn rejects_out_of_turn_contribution() {
         let opts = test_options();
@@ -160,15 +759,32 @@ mod tests {
         let lobby_state = SharedLobbyState::new(opts.lobby.clone());
         let transcript = test_transcript();
         let contrbution = valid_contribution(&transcript, 1);
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript.clone()));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
         let result = contribute(
             SessionId::new(),
-            Json(contrbution),
+            Query(ContributeQuery {
+                slot_id:         SlotId::new().0,
+                transcript_hash: transcript_hash(&transcript),
+                countersigner:   None,
+            }),
+            ContributionUpload(contrbution),
             Extension(lobby_state),
             Extension(opts),
-            Extension(Arc::new(RwLock::new(transcript))),
+            Extension(shared_transcript.clone()),
             Extension(db),
             Extension(Arc::new(AtomicUsize::new(0))),
             Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
         )
         .await;
         assert!(matches!(result, Err(ContributeError::NotUsersTurn)));
@@ -185,21 +801,40 @@ mod tests {
             .await
             .unwrap();
         lobby_state.enter_lobby(&participant).await.unwrap();
-        lobby_state
+        let slot_id = lobby_state
             .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
             .await
             .unwrap();
         let transcript = test_transcript();
-        let contribution = invalid_contribution(&transcript, 1);
+        // Keep the signature valid, so that the powers are checked
+        let mut contribution = valid_contribution(&transcript, 1);
+        contribution.contributions[0].powers.g1[1] = G1::zero();
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript.clone()));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
         let result = contribute(
             participant,
-            Json(contribution),
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: transcript_hash(&transcript),
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution),
             Extension(lobby_state),
             Extension(opts),
-            Extension(Arc::new(RwLock::new(transcript))),
+            Extension(shared_transcript.clone()),
             Extension(db),
             Extension(Arc::new(AtomicUsize::new(0))),
             Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
         )
         .await;
         assert!(matches!(
@@ -208,6 +843,241 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn rejects_contribution_to_other_transcript() {
+        let opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        let slot_id = lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        let transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
+        let result = contribute(
+            participant.clone(),
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: "0x00".to_string(),
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution),
+            Extension(lobby_state.clone()),
+            Extension(opts),
+            Extension(shared_transcript.clone()),
+            Extension(db),
+            Extension(Arc::new(AtomicUsize::new(0))),
+            Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(ContributeError::TranscriptMismatch)));
+        assert_eq!(shared_transcript.snapshot().num_participants(), 0);
+        assert!(lobby_state.is_slot_free().await);
+    }
+
+    #[tokio::test]
+    async fn rejects_replayed_contribution() {
+        let opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        let slot_id = lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        let mut transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        transcript
+            .verify_add::<Engine>(contribution.clone(), Identity::None)
+            .unwrap();
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
+        let result = contribute(
+            participant.clone(),
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution),
+            Extension(lobby_state.clone()),
+            Extension(opts),
+            Extension(shared_transcript.clone()),
+            Extension(db),
+            Extension(Arc::new(AtomicUsize::new(0))),
+            Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ContributeError::DuplicatePubkey {
+                ceremony: 0,
+                index:    1,
+            })
+        ));
+        assert_eq!(shared_transcript.snapshot().num_participants(), 1);
+        assert!(lobby_state.is_slot_free().await);
+    }
+
+    #[tokio::test]
+    async fn checks_identity_binding() {
+        let mut opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        let transcript = test_transcript();
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript.clone()));
+        // Signed for someone other than the participant
+        let mut contribution = transcript.contribution();
+        contribution
+            .add_entropy::<Engine>(&Secret::new([1; 32]), &Identity::None)
+            .unwrap();
+
+        for allow_unbound_contributions in [false, true] {
+            opts.allow_unbound_contributions = allow_unbound_contributions;
+            lobby_state
+                .insert_session(participant.clone(), create_test_session_info(100))
+                .await
+                .unwrap();
+            lobby_state.enter_lobby(&participant).await.unwrap();
+            let slot_id = lobby_state
+                .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+                .await
+                .unwrap();
+            let writer = Arc::new(TranscriptWriter::new(
+                &opts,
+                shared_transcript.clone(),
+                db.clone(),
+            ));
+            let result = contribute(
+                participant.clone(),
+                Query(ContributeQuery {
+                    slot_id:         slot_id.0,
+                    transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                    countersigner:   None,
+                }),
+                ContributionUpload(contribution.clone()),
+                Extension(lobby_state.clone()),
+                Extension(opts.clone()),
+                Extension(shared_transcript.clone()),
+                Extension(db.clone()),
+                Extension(Arc::new(AtomicUsize::new(0))),
+                Extension(shared_keys()),
+                Extension(shared_verifier()),
+                Extension(shared_mirrors()),
+                Extension(writer),
+                Extension(SharedCompletion::default()),
+                Extension(SharedForecast::default()),
+                Extension(SharedPendingContributions::default()),
+            )
+            .await;
+            if allow_unbound_contributions {
+                assert!(result.is_ok());
+            } else {
+                assert!(matches!(result, Err(ContributeError::UnboundContribution)));
+            }
+        }
+
+        // The signature that doesn't match is dropped
+        let transcript = shared_transcript.snapshot();
+        assert_eq!(transcript.num_participants(), 1);
+        assert_eq!(
+            transcript.transcripts[0].witness.signatures[1],
+            BlsSignature::empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_stale_slot() {
+        let opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        let stale_slot_id = lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        lobby_state.abort_contribution(&participant).await.unwrap();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        let transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript.clone()));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
+        let result = contribute(
+            participant,
+            Query(ContributeQuery {
+                slot_id:         stale_slot_id.0,
+                transcript_hash: transcript_hash(&transcript),
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution),
+            Extension(lobby_state),
+            Extension(opts),
+            Extension(shared_transcript.clone()),
+            Extension(db),
+            Extension(Arc::new(AtomicUsize::new(0))),
+            Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
+        )
+        .await;
+        assert!(matches!(result, Err(ContributeError::StaleSlot)));
+    }
+
     #[tokio::test]
     async fn accepts_valid_contribution() {
         let cfg = test_options();
@@ -238,7 +1108,7 @@ mod tests {
                 .unwrap();
             transcript
         };
-        let shared_transcript = Arc::new(RwLock::new(transcript));
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
 
         lobby_state
             .insert_session(participant.clone(), create_test_session_info(100))
@@ -246,56 +1116,320 @@ mod tests {
             .unwrap();
         lobby_state.enter_lobby(&participant).await.unwrap();
 
-        lobby_state
+        let slot_id = lobby_state
             .set_current_contributor(&participant, cfg.lobby.compute_deadline, db.clone())
             .await
             .unwrap();
+        let writer = Arc::new(TranscriptWriter::new(
+            &cfg,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
         let result = contribute(
             participant.clone(),
-            Json(contribution_1),
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution_1),
             Extension(lobby_state.clone()),
             Extension(cfg.clone()),
             Extension(shared_transcript.clone()),
             Extension(db.clone()),
             Extension(Arc::new(AtomicUsize::new(0))),
             Extension(keys.clone()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
         )
         .await;
 
         assert!(matches!(result, Ok(_)));
-        let transcript = read_json_file::<BatchTranscript>(cfg.transcript_file.clone()).await;
+        let mut transcript = read_json_file::<BatchTranscript>(cfg.transcript_file.clone()).await;
+        let metadata = transcript.metadata.take().expect("must record metadata");
+        assert_eq!(metadata.contributions.len(), 1);
+        assert_eq!(metadata.contributions[0].participant, 1);
         assert_eq!(transcript, transcript_1);
         lobby_state
             .insert_session(participant.clone(), create_test_session_info(100))
             .await
             .unwrap();
         lobby_state.enter_lobby(&participant).await.unwrap();
-        lobby_state
+        let slot_id = lobby_state
             .set_current_contributor(&participant, cfg.lobby.compute_deadline, db.clone())
             .await
             .unwrap();
+        let writer = Arc::new(TranscriptWriter::new(
+            &cfg,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
         let result = contribute(
             participant.clone(),
-            Json(contribution_2),
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution_2),
             Extension(lobby_state),
             Extension(cfg.clone()),
             Extension(shared_transcript.clone()),
             Extension(db.clone()),
             Extension(Arc::new(AtomicUsize::new(0))),
             Extension(keys.clone()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
         )
         .await;
 
         assert!(matches!(result, Ok(_)));
-        let transcript = read_json_file::<BatchTranscript>(cfg.transcript_file.clone()).await;
+        let mut transcript = read_json_file::<BatchTranscript>(cfg.transcript_file.clone()).await;
+        let metadata = transcript.metadata.take().expect("must record metadata");
+        assert_eq!(metadata.contributions.len(), 2);
+        assert_eq!(metadata.contributions[1].participant, 2);
         assert_eq!(transcript, transcript_2);
     }
 
+    #[tokio::test]
+    async fn confirms_countersigned_contribution() {
+        let mut opts = test_options();
+        opts.confirmation.confirm_contributions = true;
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        let slot_id = lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        let transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
+        let pending = SharedPendingContributions::default();
+        let sequencer_keys = shared_keys();
+        let participant_keys = shared_keys();
+        let upload = |countersigner: Option<String>| {
+            contribute(
+                participant.clone(),
+                Query(ContributeQuery {
+                    slot_id: slot_id.0.clone(),
+                    transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                    countersigner,
+                }),
+                ContributionUpload(contribution.clone()),
+                Extension(lobby_state.clone()),
+                Extension(opts.clone()),
+                Extension(shared_transcript.clone()),
+                Extension(db.clone()),
+                Extension(Arc::new(AtomicUsize::new(0))),
+                Extension(sequencer_keys.clone()),
+                Extension(shared_verifier()),
+                Extension(shared_mirrors()),
+                Extension(writer.clone()),
+                Extension(SharedCompletion::default()),
+                Extension(SharedForecast::default()),
+                Extension(pending.clone()),
+            )
+        };
+        // Without a countersigner the slot is kept for another try
+        assert!(matches!(
+            upload(None).await,
+            Err(ContributeError::InvalidCountersigner)
+        ));
+        let response = upload(Some(participant_keys.address().to_string()))
+            .await
+            .unwrap();
+        assert!(response.confirm_by.is_some());
+        assert_eq!(shared_transcript.snapshot().num_participants(), 0);
+        assert!(!lobby_state.is_slot_free().await);
+
+        let confirm = |countersignature: String| {
+            contribute_confirm(
+                participant.clone(),
+                Json(ConfirmRequest { countersignature }),
+                Extension(pending.clone()),
+                Extension(lobby_state.clone()),
+                Extension(opts.clone()),
+                Extension(shared_transcript.clone()),
+                Extension(db.clone()),
+                Extension(Arc::new(AtomicUsize::new(0))),
+                Extension(shared_mirrors()),
+                Extension(writer.clone()),
+                Extension(SharedCompletion::default()),
+                Extension(SharedForecast::default()),
+            )
+        };
+        // A bad countersignature leaves the contribution waiting
+        assert!(matches!(
+            confirm("0xdeadbeef".to_owned()).await,
+            Err(ContributeError::InvalidCountersignature)
+        ));
+        // So does one by any key but the declared one, such as the sequencer's
+        let forged = sequencer_keys.sign(&response.receipt).await.unwrap();
+        assert!(matches!(
+            confirm(forged.as_hex().to_owned()).await,
+            Err(ContributeError::InvalidCountersignature)
+        ));
+        let countersignature = participant_keys.sign(&response.receipt).await.unwrap();
+        let confirmed = confirm(format!("0x{}", countersignature.as_hex()))
+            .await
+            .unwrap();
+        assert_eq!(confirmed.receipt, response.receipt);
+        assert_eq!(confirmed.confirm_by, None);
+        assert_eq!(shared_transcript.snapshot().num_participants(), 1);
+        assert!(lobby_state.is_slot_free().await);
+        let stored = db
+            .get_receipt(&session_hash(&participant))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored.countersignature.as_deref(),
+            Some(countersignature.as_hex())
+        );
+        assert!(matches!(
+            confirm(countersignature.as_hex().to_owned()).await,
+            Err(ContributeError::NotAwaitingConfirmation)
+        ));
+    }
+
+    #[tokio::test]
+    async fn drops_unconfirmed_contribution() {
+        let mut opts = test_options();
+        opts.confirmation.confirm_contributions = true;
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        let slot_id = lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        let transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
+        let pending = SharedPendingContributions::default();
+        contribute(
+            participant,
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                countersigner:   Some(shared_keys().address().to_string()),
+            }),
+            ContributionUpload(contribution),
+            Extension(lobby_state.clone()),
+            Extension(opts.clone()),
+            Extension(shared_transcript.clone()),
+            Extension(db.clone()),
+            Extension(Arc::new(AtomicUsize::new(0))),
+            Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(pending.clone()),
+        )
+        .await
+        .unwrap();
+
+        tokio::time::pause();
+        tokio::time::sleep(opts.confirmation.confirmation_deadline + Duration::from_secs(1)).await;
+        assert!(lobby_state.is_slot_free().await);
+        assert_eq!(shared_transcript.snapshot().num_participants(), 0);
+        assert!(pending.take_if(|_| true).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn finishes_contribution_after_timeout() {
+        let opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let participant = SessionId::new();
+        lobby_state
+            .insert_session(participant.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&participant).await.unwrap();
+        let slot_id = lobby_state
+            .set_current_contributor(&participant, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+        let transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        let writer = Arc::new(TranscriptWriter::new(
+            &opts,
+            shared_transcript.clone(),
+            db.clone(),
+        ));
+        let request = contribute(
+            participant.clone(),
+            Query(ContributeQuery {
+                slot_id:         slot_id.0,
+                transcript_hash: shared_transcript.contribution_template().transcript_hash,
+                countersigner:   None,
+            }),
+            ContributionUpload(contribution),
+            Extension(lobby_state.clone()),
+            Extension(opts),
+            Extension(shared_transcript.clone()),
+            Extension(db),
+            Extension(Arc::new(AtomicUsize::new(0))),
+            Extension(shared_keys()),
+            Extension(shared_verifier()),
+            Extension(shared_mirrors()),
+            Extension(writer),
+            Extension(SharedCompletion::default()),
+            Extension(SharedForecast::default()),
+            Extension(SharedPendingContributions::default()),
+        );
+        // Times out while verifying, as the route timeout would
+        assert!(tokio::time::timeout(Duration::ZERO, request).await.is_err());
+        assert!(!lobby_state.is_slot_free().await);
+
+        for _ in 0..100 {
+            if lobby_state.is_slot_free().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(lobby_state.is_slot_free().await);
+        assert_eq!(shared_transcript.snapshot().num_participants(), 1);
+    }
+
     #[tokio::test]
     async fn aborts_contribution() {
         let opts = test_options();
         let lobby_state = SharedLobbyState::new(opts.lobby.clone());
-        let transcript = Arc::new(RwLock::new(test_transcript()));
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
         let db = storage_client(&opts.storage).await.unwrap();
 
         let session_id = SessionId::new();
@@ -322,13 +1456,15 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
 
         assert!(matches!(
             contribution_in_progress_response,
-            Err(TryContributeError::AnotherContributionInProgress)
+            Err(TryContributeError::AnotherContributionInProgress { .. })
         ));
 
         contribute_abort(
@@ -338,6 +1474,11 @@ mod tests {
         )
         .await
         .unwrap();
+        let uid = test_jwt(100).unique_identifier();
+        let failed_attempts = db.failed_attempts(&uid).await.unwrap();
+        assert_eq!(failed_attempts.count, 1);
+        // The aborted contribution is expired already
+        assert!(!db.expire_unfinished_contribution(&uid).await.unwrap());
 
         tokio::time::pause();
         tokio::time::advance(Duration::from_secs(30)).await;
@@ -347,10 +1488,79 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
 
         assert!(matches!(success_response, Ok(TryContributeResponse { .. })));
     }
+
+    #[tokio::test]
+    async fn extends_deadline_once() {
+        let mut opts = test_options();
+        opts.lobby.compute_deadline_max_extension = Duration::from_secs(60);
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+        let extend = |session_id: &SessionId, seconds| {
+            contribute_extend(
+                session_id.clone(),
+                Query(ExtendQuery { seconds }),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+            )
+        };
+
+        tokio::time::pause();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&session_id).await.unwrap();
+        lobby_state
+            .set_current_contributor(&session_id, opts.lobby.compute_deadline, db.clone())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            extend(&SessionId::new(), None).await,
+            Err(ContributeError::NotUsersTurn)
+        ));
+        let max_extension = |seconds| LobbyParams {
+            compute_deadline_max_extension: Some(seconds),
+            ..LobbyParams::default()
+        };
+        lobby_state.reload(&max_extension(0)).await.unwrap();
+        assert!(matches!(
+            extend(&session_id, None).await,
+            Err(ContributeError::ExtensionDisabled)
+        ));
+        lobby_state.reload(&max_extension(60)).await.unwrap();
+
+        // Extensions are capped at the configured maximum
+        let Json(response) = extend(&session_id, Some(3600)).await.unwrap();
+        let (_, time_left) = lobby_state.reserved_slot(&session_id).await.unwrap();
+        assert_eq!(
+            time_left,
+            opts.lobby.compute_deadline + Duration::from_secs(60)
+        );
+        assert_eq!(response.seconds_left, time_left.as_secs());
+        assert!(matches!(
+            extend(&session_id, Some(10)).await,
+            Err(ContributeError::AlreadyExtended)
+        ));
+
+        let audit_log = db.dump().await.unwrap().audit_log;
+        assert_eq!(audit_log.len(), 1);
+        assert_eq!(audit_log[0].event, "deadline_extended");
+        assert_eq!(audit_log[0].uid, test_jwt(100).unique_identifier());
+
+        // The slot outlives the original deadline, but not the extended one
+        tokio::time::advance(opts.lobby.compute_deadline + Duration::from_secs(1)).await;
+        assert!(lobby_state.reserved_slot(&session_id).await.is_some());
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(lobby_state.is_slot_free().await);
+    }
 }
@@ -1,24 +1,47 @@
 use super::{
+    admin::AdminError,
     auth::{AuthError, AuthErrorPayload},
     contribute::ContributeError,
+    info::{CurrentStateError, SelectionError, TranscriptHeadError, WitnessSegmentError},
     lobby::TryContributeError,
+    receipt::ReceiptError,
+    upload::UploadError,
+};
+use crate::{
+    api::{checks::AbuseCheckError, shedding::OverloadError, timeouts::TimeoutError},
+    api_types::{ApiErrorCode, ErrorResponse},
+    client_version::ClientVersionError,
+    keys::SignatureError,
+    oauth::EmailError,
+    receipt_export::ReceiptExportError,
+    sessions::SessionError,
 };
-use crate::{keys::SignatureError, sessions::SessionError};
 use axum::{
     response::{IntoResponse, Redirect, Response},
     Json,
 };
-use http::StatusCode;
+use http::{
+    header::{RETRY_AFTER, WWW_AUTHENTICATE},
+    StatusCode,
+};
 use kzg_ceremony_crypto::{CeremoniesError, ErrorCode};
-use serde_json::json;
+use serde_json::{Map, Value};
 use std::fmt::Display;
 use url::Url;
 
-fn error_to_json<Err: Display + ErrorCode>(error: &Err) -> Json<serde_json::Value> {
-    Json(json!({
-        "code": error.to_error_code(),
-        "error": error.to_string()
-    }))
+fn error_to_json<Err: Display + ErrorCode>(error: &Err) -> Json<ErrorResponse> {
+    error_with_details(error, Map::new())
+}
+
+fn error_with_details<Err: Display + ErrorCode>(
+    error: &Err,
+    details: Map<String, Value>,
+) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        code: ApiErrorCode::from_code(&error.to_error_code()),
+        message: error.to_string(),
+        details,
+    })
 }
 
 impl IntoResponse for SignatureError {
@@ -41,10 +64,52 @@ impl IntoResponse for SessionError {
             Self::InvalidSessionId => {
                 (StatusCode::BAD_REQUEST, error_to_json(&self)).into_response()
             }
+            Self::FingerprintMismatch => {
+                (StatusCode::UNAUTHORIZED, error_to_json(&self)).into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Disabled => (StatusCode::NOT_FOUND, error_to_json(&self)).into_response(),
+            // Lets browsers ask for the token
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Basic realm=\"admin\"")],
+                error_to_json(&self),
+            )
+                .into_response(),
+            Self::InvalidLobbyParams(_) | Self::InvalidInvitationRequest(_) => {
+                (StatusCode::BAD_REQUEST, error_to_json(&self)).into_response()
+            }
+            Self::StorageError(err) => err.into_response(),
+            Self::ReceiptExport(err) => err.into_response(),
         }
     }
 }
 
+impl IntoResponse for ReceiptExportError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::UnknownField(_) => (StatusCode::BAD_REQUEST, error_to_json(&self)),
+            // Receipts the sequencer signed itself should always verify
+            Self::InvalidReceipt { participant, .. } => {
+                let mut details = Map::new();
+                details.insert("participant".to_string(), participant.into());
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    error_with_details(&self, details),
+                )
+            }
+            Self::StorageError(err) => return err.into_response(),
+        };
+        (status, body).into_response()
+    }
+}
+
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
         let redirect_url = self.redirect.and_then(|r| Url::parse(&r).ok());
@@ -68,11 +133,55 @@ impl IntoResponse for AuthErrorPayload {
             Self::FetchUserDataError | Self::CouldNotExtractUserData => {
                 (StatusCode::INTERNAL_SERVER_ERROR, error_to_json(&self))
             }
-            Self::LobbyIsFull => (StatusCode::SERVICE_UNAVAILABLE, error_to_json(&self)),
-            Self::InvalidAuthCode | Self::UserAlreadyContributed => {
-                (StatusCode::BAD_REQUEST, error_to_json(&self))
+            Self::LobbyIsFull | Self::CaptchaUnavailable => {
+                (StatusCode::SERVICE_UNAVAILABLE, error_to_json(&self))
             }
+            Self::InvalidAuthCode
+            | Self::InvalidNonce
+            | Self::UserAlreadyContributed
+            | Self::PseudonymsDisabled
+            | Self::PasskeysDisabled
+            | Self::ProviderDisabled
+            | Self::InvalidPasskey
+            | Self::InvalidCaptcha
+            | Self::InvalidInvitation
+            | Self::UnsupportedChain => (StatusCode::BAD_REQUEST, error_to_json(&self)),
             Self::UserCreatedAfterDeadline => (StatusCode::UNAUTHORIZED, error_to_json(&self)),
+            Self::NotAllowed
+            | Self::TooManySessions
+            | Self::TooManyFailedAttempts
+            | Self::InvitationRequired => (StatusCode::FORBIDDEN, error_to_json(&self)),
+            Self::Storage(storage_error) => return storage_error.into_response(),
+        };
+        (status, body).into_response()
+    }
+}
+
+impl IntoResponse for EmailError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::UnknownSessionId => (StatusCode::UNAUTHORIZED, error_to_json(&self)),
+            Self::Disabled
+            | Self::InvalidAddress
+            | Self::AddressInUse
+            | Self::UnknownCode
+            | Self::WrongCode => (StatusCode::BAD_REQUEST, error_to_json(&self)),
+            Self::DeliveryFailed => (StatusCode::SERVICE_UNAVAILABLE, error_to_json(&self)),
+            Self::ResendTooSoon {
+                retry_after_seconds,
+            } => {
+                let mut details = Map::new();
+                details.insert(
+                    "retry_after_seconds".to_string(),
+                    retry_after_seconds.into(),
+                );
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after_seconds.to_string())],
+                    error_with_details(&self, details),
+                )
+                    .into_response();
+            }
             Self::Storage(storage_error) => return storage_error.into_response(),
         };
         (status, body).into_response()
@@ -82,8 +191,23 @@ impl IntoResponse for AuthErrorPayload {
 impl IntoResponse for ContributeError {
     fn into_response(self) -> Response {
         let (status, body) = match self {
-            Self::NotUsersTurn => (StatusCode::BAD_REQUEST, error_to_json(&self)),
+            Self::NotUsersTurn
+            | Self::StaleSlot
+            | Self::UnboundContribution
+            | Self::TranscriptMismatch
+            | Self::ExtensionDisabled
+            | Self::AlreadyExtended
+            | Self::NotAwaitingConfirmation
+            | Self::InvalidCountersignature
+            | Self::InvalidCountersigner => (StatusCode::BAD_REQUEST, error_to_json(&self)),
+            Self::DuplicatePubkey { ceremony, index } => {
+                let mut details = Map::new();
+                details.insert("ceremony".to_string(), ceremony.into());
+                details.insert("witness_index".to_string(), index.into());
+                (StatusCode::BAD_REQUEST, error_with_details(&self, details))
+            }
             Self::InvalidContribution(e) => return CeremoniesErrorFormatter(e).into_response(),
+            Self::ProcessingFailed => (StatusCode::INTERNAL_SERVER_ERROR, error_to_json(&self)),
             Self::Signature(err) => return err.into_response(),
             Self::StorageError(err) => return err.into_response(),
         };
@@ -92,14 +216,52 @@ impl IntoResponse for ContributeError {
     }
 }
 
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, error_to_json(&self)).into_response()
+    }
+}
+
 impl IntoResponse for TryContributeError {
     fn into_response(self) -> Response {
         let (status, body) = match self {
-            Self::UnknownSessionId => (StatusCode::UNAUTHORIZED, error_to_json(&self)),
-            Self::RateLimited | Self::LobbyIsFull => {
+            Self::UnknownSessionId | Self::LobbyTokenExpired => {
+                (StatusCode::UNAUTHORIZED, error_to_json(&self))
+            }
+            Self::Evicted { reason } => {
+                let mut details = Map::new();
+                details.insert("reason".to_string(), reason.to_string().into());
+                (StatusCode::UNAUTHORIZED, error_with_details(&self, details))
+            }
+            Self::RateLimited | Self::LobbyIsFull | Self::NotJoined | Self::DeferralDisabled => {
                 (StatusCode::BAD_REQUEST, error_to_json(&self))
             }
-            Self::AnotherContributionInProgress => (StatusCode::OK, error_to_json(&self)),
+            Self::AnotherContributionInProgress {
+                estimated_wait_seconds,
+            } => {
+                let mut details = Map::new();
+                details.insert(
+                    "estimated_wait_seconds".to_string(),
+                    estimated_wait_seconds.into(),
+                );
+                (StatusCode::OK, error_with_details(&self, details))
+            }
+            Self::NotAllowed
+            | Self::TooManyFailedAttempts
+            | Self::EmailNotVerified
+            | Self::AlreadyContributed => (StatusCode::FORBIDDEN, error_to_json(&self)),
+            Self::CoolingDown {
+                retry_after_seconds,
+            } => {
+                let mut details = Map::new();
+                details.insert(
+                    "retry_after_seconds".to_string(),
+                    retry_after_seconds.into(),
+                );
+                (StatusCode::BAD_REQUEST, error_with_details(&self, details))
+            }
+            Self::BeaconUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, error_to_json(&self)),
+            Self::CeremonyComplete => (StatusCode::GONE, error_to_json(&self)),
             Self::StorageError(err) => return err.into_response(),
         };
 
@@ -107,14 +269,131 @@ impl IntoResponse for TryContributeError {
     }
 }
 
+impl IntoResponse for AbuseCheckError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::RateLimited {
+                retry_after_seconds,
+            } => {
+                let mut details = Map::new();
+                details.insert(
+                    "retry_after_seconds".to_string(),
+                    retry_after_seconds.into(),
+                );
+                (StatusCode::BAD_REQUEST, error_with_details(&self, details))
+            }
+            Self::MissingProofOfWork
+            | Self::InvalidProofOfWork(_)
+            | Self::MissingCaptcha
+            | Self::InvalidCaptcha => (StatusCode::BAD_REQUEST, error_to_json(&self)),
+            Self::CaptchaUnavailable => (StatusCode::SERVICE_UNAVAILABLE, error_to_json(&self)),
+        };
+        (status, body).into_response()
+    }
+}
+
+impl IntoResponse for OverloadError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Busy {
+                retry_after_seconds,
+            } => {
+                let mut details = Map::new();
+                details.insert(
+                    "retry_after_seconds".to_string(),
+                    retry_after_seconds.into(),
+                );
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(RETRY_AFTER, retry_after_seconds.to_string())],
+                    error_with_details(&self, details),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for TimeoutError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::TimedOut { timeout_seconds } => {
+                let mut details = Map::new();
+                details.insert("timeout_seconds".to_string(), timeout_seconds.into());
+                (
+                    StatusCode::GATEWAY_TIMEOUT,
+                    error_with_details(&self, details),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for ClientVersionError {
+    fn into_response(self) -> Response {
+        match &self {
+            Self::Missing | Self::Invalid(_) => (StatusCode::BAD_REQUEST, error_to_json(&self)),
+            Self::Outdated { minimum, .. } => {
+                let mut details = Map::new();
+                details.insert("min_client_version".to_string(), minimum.to_string().into());
+                (
+                    StatusCode::UPGRADE_REQUIRED,
+                    error_with_details(&self, details),
+                )
+            }
+            Self::Blocked(_) => (StatusCode::UPGRADE_REQUIRED, error_to_json(&self)),
+        }
+        .into_response()
+    }
+}
+
+impl IntoResponse for ReceiptError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UnknownReceipt => (StatusCode::NOT_FOUND, error_to_json(&self)).into_response(),
+            Self::StorageError(err) => err.into_response(),
+        }
+    }
+}
+
+impl IntoResponse for CurrentStateError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, error_to_json(&self)).into_response()
+    }
+}
+
+impl IntoResponse for WitnessSegmentError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, error_to_json(&self)).into_response()
+    }
+}
+
+impl IntoResponse for SelectionError {
+    fn into_response(self) -> Response {
+        (StatusCode::NOT_FOUND, error_to_json(&self)).into_response()
+    }
+}
+
+impl IntoResponse for TranscriptHeadError {
+    fn into_response(self) -> Response {
+        (StatusCode::NOT_FOUND, error_to_json(&self)).into_response()
+    }
+}
+
 struct CeremoniesErrorFormatter(CeremoniesError);
 
 impl IntoResponse for CeremoniesErrorFormatter {
     fn into_response(self) -> Response {
-        let body = Json(json!({
-            "code": self.0.to_error_code(),
-            "error" : format!("contribution invalid: {}", self.0)
-        }));
+        let mut details = Map::new();
+        if let CeremoniesError::InvalidCeremony(index, _) = self.0 {
+            details.insert("contribution_index".to_string(), index.into());
+        }
+        let body = Json(ErrorResponse {
+            code: ApiErrorCode::from_code(&self.0.to_error_code()),
+            message: format!("contribution invalid: {}", self.0),
+            details,
+        });
 
         (StatusCode::BAD_REQUEST, body).into_response()
     }
@@ -1,24 +1,65 @@
 use crate::{
-    keys::{Address, SharedKeys},
-    lobby::SharedLobbyState,
-    Options, SharedCeremonyStatus,
+    announcement::SharedAnnouncement,
+    api_types::TreeHead,
+    cache::SharedInfoCache,
+    forecast::{GrowthForecast, SharedForecast},
+    inclusion::{leaf_hashes, tree_head},
+    io::{read_transcript_bytes, stream_sharded_transcript, CeremonySize, ContributionSpec},
+    keys::{Address, SharedKeys, Signature, SignatureError},
+    limits::BodyLimits,
+    lobby::{EvictionStats, Selection, SharedLobbyState},
+    mirror::SharedMirrors,
+    regions::{Region, RegionStats},
+    report::{CeremonyReport, ReportFormat},
+    storage::{PersistentStorage, StorageError},
+    verifier::SEQUENCER_VERSION,
+    Engine, Options, SharedCeremonyStatus, SharedTranscript,
 };
 use axum::{
-    body::StreamBody,
+    body::{Bytes, StreamBody},
+    extract::{Path, Query},
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use http::StatusCode;
-use serde::Serialize;
-use std::sync::atomic::Ordering;
+use chrono::{DateTime, Utc};
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    StatusCode,
+};
+use kzg_ceremony_crypto::{ErrorCode, Transcript, WitnessSegment, G2};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    any::type_name,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use strum::IntoStaticStr;
+use thiserror::Error;
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
+use tracing::error;
 
-#[derive(Debug, Serialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, PartialEq)]
 pub struct StatusResponse {
-    lobby_size:        usize,
-    num_contributions: usize,
-    sequencer_address: Address,
+    lobby_size:             usize,
+    num_contributions:      usize,
+    sequencer_address:      Address,
+    /// Public key of the BLS signatures of receipts, if they are signed with
+    /// BLS as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequencer_bls_pubkey:   Option<G2>,
+    /// Estimated time until a participant joining the lobby now gets to
+    /// contribute.
+    estimated_wait_seconds: u64,
+    ceremony_sizes:         Vec<CeremonySize>,
+    /// Message from the operators to show, see [`crate::announcement`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announcement:           Option<String>,
+    /// Projected growth of the ceremony, see [`crate::forecast`].
+    forecast:               GrowthForecast,
 }
 
 impl IntoResponse for StatusResponse {
@@ -28,34 +69,1012 @@ impl IntoResponse for StatusResponse {
     }
 }
 
+async fn current_status(
+    lobby_state: &SharedLobbyState,
+    ceremony_status: &SharedCeremonyStatus,
+    keys: &SharedKeys,
+    announcement: &SharedAnnouncement,
+    forecast: &SharedForecast,
+    options: &Options,
+) -> StatusResponse {
+    let contributions = ceremony_status.load(Ordering::Relaxed);
+    StatusResponse {
+        lobby_size:             lobby_state.get_lobby_size().await,
+        num_contributions:      contributions,
+        sequencer_address:      keys.address(),
+        sequencer_bls_pubkey:   keys.bls_pubkey(),
+        estimated_wait_seconds: lobby_state.estimated_wait().await.as_secs(),
+        ceremony_sizes:         options.ceremony_sizes.describe(),
+        announcement:           announcement.get().await,
+        forecast:               forecast.project(
+            Utc::now(),
+            contributions,
+            &options.ceremony_sizes,
+        ),
+    }
+}
+
 pub async fn status(
     Extension(lobby_state): Extension<SharedLobbyState>,
     Extension(ceremony_status): Extension<SharedCeremonyStatus>,
     Extension(keys): Extension<SharedKeys>,
-) -> StatusResponse {
-    let lobby_size = lobby_state.get_lobby_size().await;
+    Extension(cache): Extension<SharedInfoCache>,
+    Extension(announcement): Extension<SharedAnnouncement>,
+    Extension(forecast): Extension<SharedForecast>,
+    Extension(options): Extension<Options>,
+) -> Response {
+    cache
+        .status
+        .get(|| async {
+            let status = current_status(
+                &lobby_state,
+                &ceremony_status,
+                &keys,
+                &announcement,
+                &forecast,
+                &options,
+            )
+            .await;
+            serde_json::to_vec(&status).map(Bytes::from)
+        })
+        .await
+        .map_or_else(
+            |_| StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            json_response,
+        )
+}
 
-    let num_contributions = ceremony_status.load(Ordering::Relaxed);
-    let sequencer_address = keys.address();
+/// Sequence numbers of signed status snapshots. They follow the clock in
+/// milliseconds where possible, so that they keep increasing across restarts.
+#[derive(Debug, Default)]
+pub struct StatusSequence(AtomicU64);
 
-    StatusResponse {
-        lobby_size,
-        num_contributions,
-        sequencer_address,
+pub type SharedStatusSequence = Arc<StatusSequence>;
+
+impl StatusSequence {
+    pub fn next(&self, now_millis: u64) -> u64 {
+        let next = |last: u64| last.saturating_add(1).max(now_millis);
+        let last = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last)))
+            .unwrap_or_else(|last| last);
+        next(last)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SignedStatusPayload {
+    #[serde(flatten)]
+    status:    StatusResponse,
+    /// Unix timestamp of the snapshot, in seconds.
+    timestamp: i64,
+    sequence:  u64,
+}
+
+/// A status snapshot, as signed JSON string, and its signature by the
+/// sequencer address.
+#[derive(Debug, Serialize)]
+pub struct SignedStatusResponse {
+    status:    String,
+    signature: Signature,
+}
+
+/// The current status, uncached and signed with the key that signs receipts.
+/// Monitors can check the signature against `sequencer_address`, and tell
+/// from the timestamp and the increasing sequence number that a proxy in
+/// between didn't serve them an old snapshot.
+pub async fn signed_status(
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(ceremony_status): Extension<SharedCeremonyStatus>,
+    Extension(keys): Extension<SharedKeys>,
+    Extension(sequence): Extension<SharedStatusSequence>,
+    Extension(announcement): Extension<SharedAnnouncement>,
+    Extension(forecast): Extension<SharedForecast>,
+    Extension(options): Extension<Options>,
+) -> Result<Json<SignedStatusResponse>, SignatureError> {
+    let now = Utc::now();
+    let payload = SignedStatusPayload {
+        status:    current_status(
+            &lobby_state,
+            &ceremony_status,
+            &keys,
+            &announcement,
+            &forecast,
+            &options,
+        )
+        .await,
+        timestamp: now.timestamp(),
+        sequence:  sequence.next(u64::try_from(now.timestamp_millis()).unwrap_or_default()),
+    };
+    let status = serde_json::to_string(&payload).map_err(|_| SignatureError::SignatureCreation)?;
+    let signature = keys.sign(&status).await?;
+    Ok(Json(SignedStatusResponse { status, signature }))
+}
+
+#[derive(Debug, Serialize)]
+struct BuildInfo {
+    version:    &'static str,
+    /// Commit the sequencer was built from, if it was built from a checkout.
+    git_commit: Option<&'static str>,
+    /// Cargo features the sequencer was built with.
+    features:   Vec<&'static str>,
+    /// Engine contributions are verified with.
+    engine:     &'static str,
+}
+
+impl BuildInfo {
+    fn current() -> Self {
+        let features = [
+            ("adversarial", cfg!(feature = "adversarial")),
+            ("bench", cfg!(feature = "bench")),
+            ("client", cfg!(feature = "client")),
+            ("deterministic", cfg!(feature = "deterministic")),
+            ("mimalloc", cfg!(feature = "mimalloc")),
+            ("postgres", cfg!(feature = "postgres")),
+            ("sqlite", cfg!(feature = "sqlite")),
+        ];
+        Self {
+            version:    SEQUENCER_VERSION,
+            git_commit: option_env!("COMMIT_SHA"),
+            features:   features
+                .into_iter()
+                .filter_map(|(feature, enabled)| enabled.then_some(feature))
+                .collect(),
+            engine:     type_name::<Engine>(),
+        }
     }
 }
 
-pub async fn current_state(Extension(options): Extension<Options>) -> impl IntoResponse {
+#[derive(Debug, Serialize)]
+struct SequencerInfo {
+    build:               BuildInfo,
+    sequencer_address:   Address,
+    /// Public keys of the receipt signatures, see [`crate::keys::Keys::jwks`].
+    jwks:                Value,
+    ceremony_sizes:      Vec<CeremonySize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_participants: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ceremony_end:        Option<DateTime<Utc>>,
+    /// Unix timestamp of the description, in seconds.
+    timestamp:           i64,
+}
+
+/// A description of the sequencer, as signed JSON string, and its signature
+/// by the sequencer address.
+#[derive(Debug, Serialize)]
+pub struct SignedSequencerInfo {
+    sequencer: String,
+    signature: Signature,
+}
+
+/// Which build of the sequencer is running with which keys and ceremony
+/// parameters, signed like the status, so that clients can pin the sequencer
+/// they contributed to.
+pub async fn sequencer(
+    Extension(keys): Extension<SharedKeys>,
+    Extension(options): Extension<Options>,
+) -> Result<Json<SignedSequencerInfo>, SignatureError> {
+    let info = SequencerInfo {
+        build:               BuildInfo::current(),
+        sequencer_address:   keys.address(),
+        jwks:                keys.jwks(),
+        ceremony_sizes:      options.ceremony_sizes.describe(),
+        target_participants: options.completion.target_participants,
+        ceremony_end:        options.forecast.ceremony_end,
+        timestamp:           Utc::now().timestamp(),
+    };
+    let sequencer = serde_json::to_string(&info).map_err(|_| SignatureError::SignatureCreation)?;
+    let signature = keys.sign(&sequencer).await?;
+    Ok(Json(SignedSequencerInfo {
+        sequencer,
+        signature,
+    }))
+}
+
+fn json_response(body: Bytes) -> Response {
+    (StatusCode::OK, [(CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+/// Health of the transcript mirrors, and whether they hold the same
+/// transcript as the local file.
+pub async fn storage(
+    Extension(options): Extension<Options>,
+    Extension(mirrors): Extension<SharedMirrors>,
+) -> Response {
+    mirrors
+        .cached_report(&options.transcript_file)
+        .await
+        .map_or_else(
+            |error| {
+                error!(?error, "Could not check transcript mirrors");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+            json_response,
+        )
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum SelectionError {
+    #[error("no selection with this index")]
+    UnknownSelection,
+}
+
+impl ErrorCode for SelectionError {
+    fn to_error_code(&self) -> String {
+        format!("SelectionError::{}", <&str>::from(self))
+    }
+}
+
+/// How the contributor with the given selection index was picked from the
+/// lobby, when selecting contributors with a randomness beacon.
+pub async fn selection(
+    Path(index): Path<usize>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+) -> Result<Json<Selection>, SelectionError> {
+    lobby_state
+        .selection(index)
+        .await
+        .map(Json)
+        .ok_or(SelectionError::UnknownSelection)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TranscriptHeadQueryParams {
+    /// Number of entries the tree is over, all of them by default.
+    tree_size: Option<usize>,
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum TranscriptHeadError {
+    #[error("the transcript has fewer entries than the tree size")]
+    TreeTooLarge,
+}
+
+impl ErrorCode for TranscriptHeadError {
+    fn to_error_code(&self) -> String {
+        format!("TranscriptHeadError::{}", <&str>::from(self))
+    }
+}
+
+/// The root of the tree over the entries of the transcript, see
+/// [`crate::inclusion`], for other sequencer instances and auditors to
+/// compare their transcripts with.
+pub async fn transcript_head(
+    Query(params): Query<TranscriptHeadQueryParams>,
+    Extension(transcript): Extension<SharedTranscript>,
+) -> Result<Json<TreeHead>, TranscriptHeadError> {
+    let leaves = leaf_hashes(&transcript.snapshot());
+    let tree_size = params.tree_size.unwrap_or(leaves.len());
+    let leaves = leaves
+        .get(..tree_size)
+        .ok_or(TranscriptHeadError::TreeTooLarge)?;
+    Ok(Json(TreeHead {
+        tree_size,
+        root: hex::encode(tree_head(leaves)),
+    }))
+}
+
+/// Most slot assignments returned per page of `/info/selection_log`.
+pub const SELECTION_LOG_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SelectionLogQueryParams {
+    /// Only return assignments numbered after this one, i.e. `next` of the
+    /// previous page.
+    #[serde(default)]
+    after: i64,
+    /// Number of assignments to return, at most
+    /// [`SELECTION_LOG_PAGE_SIZE`].
+    limit: Option<usize>,
+}
+
+/// A decision on who got the slot, see [`crate::lobby::SlotAssignment`].
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct SelectionLogEntry {
+    id:           i64,
+    /// `first_come`, `on_deck` or `beacon`.
+    rule:         String,
+    candidates:   Vec<String>,
+    selected:     String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed:         Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    beacon_round: Option<i64>,
+    assigned_at:  i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelectionLogResponse {
+    assignments: Vec<SelectionLogEntry>,
+    /// Value of `after` for the next page, if there may be one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next:        Option<i64>,
+}
+
+/// Every decision on who got the contribution slot, oldest first, so that
+/// anyone can check the lobby's ordering. Participants can find themselves
+/// by hashing their session id.
+pub async fn selection_log(
+    Query(params): Query<SelectionLogQueryParams>,
+    Extension(storage): Extension<PersistentStorage>,
+) -> Result<Json<SelectionLogResponse>, StorageError> {
+    let limit = params
+        .limit
+        .unwrap_or(SELECTION_LOG_PAGE_SIZE)
+        .clamp(1, SELECTION_LOG_PAGE_SIZE);
+    let assignments = storage
+        .slot_assignments(params.after, limit)
+        .await?
+        .into_iter()
+        .map(|row| SelectionLogEntry {
+            id:           row.id,
+            rule:         row.rule,
+            candidates:   serde_json::from_str(&row.candidates).unwrap_or_default(),
+            selected:     row.selected,
+            seed:         row.seed,
+            beacon_round: row.beacon_round,
+            assigned_at:  row.assigned_at,
+        })
+        .collect::<Vec<_>>();
+    let next = assignments
+        .last()
+        .map(|entry| entry.id)
+        .filter(|_| assignments.len() == limit);
+    Ok(Json(SelectionLogResponse { assignments, next }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContributionSpecResponse {
+    /// Content types accepted by `/contribute`.
+    content_types:  &'static [&'static str],
+    /// Largest accepted request body of `/contribute`, in bytes.
+    max_body_bytes: usize,
+    /// The ceremonies, in the order of `contributions`.
+    ceremonies:     Vec<ContributionSpec>,
+}
+
+/// What a contribution must look like to be accepted, so that clients can
+/// check theirs before uploading it.
+pub async fn contribution_spec(
+    Extension(options): Extension<Options>,
+) -> Json<ContributionSpecResponse> {
+    Json(ContributionSpecResponse {
+        content_types:  &["application/json"],
+        max_body_bytes: BodyLimits::new(&options.limits, &options.ceremony_sizes)
+            .limit("/contribute"),
+        ceremonies:     options.ceremony_sizes.contribution_spec(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct LobbyStatsResponse {
+    lobby_size:      usize,
+    /// Evicted sessions whose owners haven't polled since.
+    pending_notices: usize,
+    #[serde(flatten)]
+    evictions:       EvictionStats,
+}
+
+/// How many participants the lobby flush has dropped, and why.
+pub async fn lobby_stats(
+    Extension(lobby_state): Extension<SharedLobbyState>,
+) -> Json<LobbyStatsResponse> {
+    Json(LobbyStatsResponse {
+        lobby_size:      lobby_state.get_lobby_size().await,
+        pending_notices: lobby_state.pending_evictions().await,
+        evictions:       lobby_state.eviction_stats().await,
+    })
+}
+
+/// Accepted contributions per region, for the participants who shared theirs.
+pub async fn region_stats(
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(options): Extension<Options>,
+) -> Result<Json<RegionStats>, StorageError> {
+    let counts = storage
+        .region_counts()
+        .await?
+        .into_iter()
+        .filter_map(|(region, count)| {
+            Some((region.parse::<Region>().ok()?, usize::try_from(count).ok()?))
+        });
+    Ok(Json(RegionStats::aggregate(
+        counts,
+        options.regions.region_stats_min_count,
+    )))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReportQueryParams {
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// Statistics of the whole ceremony for publication, see [`crate::report`],
+/// as JSON or, with `format=csv`, as CSV.
+pub async fn report(
+    Query(params): Query<ReportQueryParams>,
+    Extension(storage): Extension<PersistentStorage>,
+) -> Result<Response, StorageError> {
+    let report = CeremonyReport::generate(&storage).await?;
+    Ok(match params.format {
+        ReportFormat::Json => (
+            StatusCode::OK,
+            [(
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"ceremony-report.json\"",
+            )],
+            Json(report),
+        )
+            .into_response(),
+        ReportFormat::Csv => (
+            StatusCode::OK,
+            [
+                (CONTENT_TYPE, "text/csv"),
+                (
+                    CONTENT_DISPOSITION,
+                    "attachment; filename=\"ceremony-report.csv\"",
+                ),
+            ],
+            report.to_csv(),
+        )
+            .into_response(),
+    })
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum CurrentStateError {
+    #[error("no ceremony with this number of G1 powers")]
+    UnknownCeremonySize,
+    #[error("powers_start must not be greater than powers_end")]
+    InvalidPowersRange,
+}
+
+impl ErrorCode for CurrentStateError {
+    fn to_error_code(&self) -> String {
+        format!("CurrentStateError::{}", <&str>::from(self))
+    }
+}
+
+/// Selects part of the transcript, so that clients don't have to download all
+/// of it. Without any parameters, the full transcript is returned.
+#[derive(Debug, Default, Deserialize)]
+pub struct CurrentStateQueryParams {
+    /// Only include the ceremony with this number of G1 powers.
+    num_g1_powers: Option<usize>,
+    /// Index of the first power to include.
+    powers_start:  Option<usize>,
+    /// Index after the last power to include.
+    powers_end:    Option<usize>,
+    /// Leave out the powers and only include the witness.
+    #[serde(default)]
+    witness_only:  bool,
+}
+
+impl CurrentStateQueryParams {
+    /// True if only the witnesses are requested, which is what most pollers
+    /// ask for.
+    const fn is_witness_only(&self) -> bool {
+        self.witness_only
+            && self.num_g1_powers.is_none()
+            && self.powers_start.is_none()
+            && self.powers_end.is_none()
+    }
+
+    const fn is_filtered(&self) -> bool {
+        self.num_g1_powers.is_some()
+            || self.powers_start.is_some()
+            || self.powers_end.is_some()
+            || self.witness_only
+    }
+
+    /// Returns the part of a ceremony transcript selected by the parameters.
+    /// `numG1Powers` and `numG2Powers` stay the sizes of the full ceremony,
+    /// and `powersStart` is the index of the first included power.
+    fn select(&self, transcript: &Transcript) -> Value {
+        let mut value = json!({
+            "numG1Powers": transcript.powers.g1.len(),
+            "numG2Powers": transcript.powers.g2.len(),
+            "witness": transcript.witness,
+        });
+        if !self.witness_only {
+            let start = self.powers_start.unwrap_or(0);
+            let end = self.powers_end.unwrap_or(usize::MAX);
+            let range = |len: usize| start.min(len)..end.min(len);
+            value["powersStart"] = start.into();
+            value["powersOfTau"] = json!({
+                "G1Powers": transcript.powers.g1[range(transcript.powers.g1.len())],
+                "G2Powers": transcript.powers.g2[range(transcript.powers.g2.len())],
+            });
+        }
+        value
+    }
+}
+
+pub async fn current_state(
+    Query(params): Query<CurrentStateQueryParams>,
+    Extension(options): Extension<Options>,
+    Extension(transcript): Extension<SharedTranscript>,
+    Extension(cache): Extension<SharedInfoCache>,
+) -> Response {
+    if params.is_witness_only() {
+        return cache
+            .witness_state
+            .get(|| async {
+                filtered_state(&params, &transcript)
+                    .await
+                    .map(|Json(state)| Bytes::from(state.to_string()))
+            })
+            .await
+            .map_or_else(IntoResponse::into_response, json_response);
+    }
+    if params.is_filtered() {
+        return filtered_state(&params, &transcript).await.into_response();
+    }
+
+    // Sharded transcripts are put together one ceremony at a time
+    if options.io.transcript_shards {
+        return match stream_sharded_transcript(options.transcript_file, &options.io).await {
+            Ok(stream) => (StatusCode::OK, StreamBody::new(stream)).into_response(),
+            Err(error) => {
+                error!(?error, "Could not read transcript manifest");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "could not read transcript file",
+                )
+                    .into_response()
+            }
+        };
+    }
+
+    // Encrypted or compressed transcripts can't be streamed from disk as they
+    // are, and are decoded in memory instead.
+    if !options.io.writes_plain_json() {
+        return read_transcript_bytes(options.transcript_file, &options.io)
+            .await
+            .map_or_else(
+                |_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "could not read transcript file",
+                    )
+                        .into_response()
+                },
+                |contents| (StatusCode::OK, contents).into_response(),
+            );
+    }
+
     let f = match File::open(options.transcript_file).await {
         Ok(file) => file,
         Err(_) => {
-            return Err((
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "could not open transcript file",
-            ))
+            )
+                .into_response()
         }
     };
     let stream = ReaderStream::new(f);
     let body = StreamBody::new(stream);
-    Ok((StatusCode::OK, body))
+    (StatusCode::OK, body).into_response()
+}
+
+async fn filtered_state(
+    params: &CurrentStateQueryParams,
+    transcript: &SharedTranscript,
+) -> Result<Json<Value>, CurrentStateError> {
+    if params.powers_start.unwrap_or(0) > params.powers_end.unwrap_or(usize::MAX) {
+        return Err(CurrentStateError::InvalidPowersRange);
+    }
+
+    let transcript = transcript.snapshot();
+    let transcripts = transcript
+        .transcripts
+        .iter()
+        .filter(|t| {
+            params
+                .num_g1_powers
+                .map_or(true, |num_g1_powers| t.powers.g1.len() == num_g1_powers)
+        })
+        .map(|t| params.select(t))
+        .collect::<Vec<_>>();
+    if transcripts.is_empty() {
+        return Err(CurrentStateError::UnknownCeremonySize);
+    }
+
+    Ok(Json(json!({
+        "transcripts": transcripts,
+        "participantIds": transcript.participant_ids,
+        "participantEcdsaSignatures": transcript.participant_ecdsa_signatures,
+    })))
+}
+
+/// Most witness entries served at once. Longer chains are fetched and
+/// verified in overlapping segments.
+pub const MAX_WITNESS_SEGMENT: usize = 1024;
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum WitnessSegmentError {
+    #[error("from must be a witness entry, and not after to")]
+    InvalidRange,
+}
+
+impl ErrorCode for WitnessSegmentError {
+    fn to_error_code(&self) -> String {
+        format!("WitnessSegmentError::{}", <&str>::from(self))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WitnessSegmentQueryParams {
+    /// Index of the first witness entry to include.
+    from: usize,
+    /// Index of the last witness entry to include, by default the last one
+    /// within [`MAX_WITNESS_SEGMENT`] entries.
+    to:   Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WitnessSegmentResponse {
+    /// Number of witness entries, including the initial one. Segments
+    /// ending before the last entry can be continued from their last entry.
+    num_entries: usize,
+    /// The segment of each ceremony, in the order of `transcripts`.
+    segments:    Vec<WitnessSegment>,
+}
+
+/// Part of the witnesses, for participants to verify their inclusion with
+/// `verify_inclusion` and `verify_chain_segment` instead of the whole
+/// transcript. To include their entry `i`, the segment has to start at
+/// `i - 1`.
+pub async fn witness_segment(
+    Query(params): Query<WitnessSegmentQueryParams>,
+    Extension(transcript): Extension<SharedTranscript>,
+) -> Result<Json<WitnessSegmentResponse>, WitnessSegmentError> {
+    let transcript = transcript.snapshot();
+    let num_entries = transcript.num_participants() + 1;
+    let to = params
+        .to
+        .unwrap_or(usize::MAX)
+        .min(num_entries - 1)
+        .min(params.from.saturating_add(MAX_WITNESS_SEGMENT - 1));
+    if params.from > to {
+        return Err(WitnessSegmentError::InvalidRange);
+    }
+    let segments = transcript
+        .transcripts
+        .iter()
+        .map(|transcript| transcript.witness.segment(params.from, to))
+        .collect::<Option<_>>()
+        .ok_or(WitnessSegmentError::InvalidRange)?;
+    Ok(Json(WitnessSegmentResponse {
+        num_entries,
+        segments,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keys::{self, Keys},
+        storage::storage_client,
+        test_util::test_options,
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+        Engine,
+    };
+    use kzg_ceremony_crypto::Identity;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn describes_expected_contribution() {
+        let options = test_options();
+        let Json(spec) = contribution_spec(Extension(options.clone())).await;
+        assert_eq!(
+            spec.max_body_bytes,
+            options.ceremony_sizes.max_contribution_size()
+        );
+        assert_eq!(spec.ceremonies, options.ceremony_sizes.contribution_spec());
+        assert_eq!(spec.content_types, ["application/json"]);
+    }
+
+    #[tokio::test]
+    async fn serves_transcript_head() {
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+        let contribution = valid_contribution(&transcript.snapshot(), 1);
+        transcript
+            .update(|transcript| transcript.verify_add::<Engine>(contribution, Identity::None))
+            .await
+            .unwrap();
+        let leaves = leaf_hashes(&transcript.snapshot());
+
+        let head = |tree_size| {
+            transcript_head(
+                Query(TranscriptHeadQueryParams { tree_size }),
+                Extension(transcript.clone()),
+            )
+        };
+        let Json(current) = head(None).await.unwrap();
+        assert_eq!(current.tree_size, leaves.len());
+        assert_eq!(current.root, hex::encode(tree_head(&leaves)));
+        let Json(first) = head(Some(1)).await.unwrap();
+        assert_eq!(first.root, hex::encode(leaves[0]));
+        assert!(matches!(
+            head(Some(leaves.len() + 1)).await,
+            Err(TranscriptHeadError::TreeTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn pages_selection_log() {
+        use crate::{
+            lobby::{SelectionRule, SlotAssignment},
+            reporting::session_id_hash,
+            test_util::create_test_session_info,
+            SessionId,
+        };
+
+        let options = test_options();
+        let storage = storage_client(&options.storage).await.unwrap();
+        let lobby_state = SharedLobbyState::new(options.lobby.clone());
+        let alice = SessionId("alice".to_string());
+        lobby_state
+            .insert_session(alice.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&alice).await.unwrap();
+        lobby_state
+            .set_current_contributor(&alice, options.lobby.compute_deadline, storage.clone())
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            storage
+                .insert_slot_assignment(&SlotAssignment {
+                    rule:         SelectionRule::Beacon,
+                    candidates:   vec!["a".to_string(), "b".to_string()],
+                    selected:     "b".to_string(),
+                    seed:         Some("07".repeat(32)),
+                    beacon_round: Some(1),
+                })
+                .await
+                .unwrap();
+        }
+        // Assignments by the lobby are recorded in the background
+        while storage.slot_assignments(0, 10).await.unwrap().len() < 3 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let page = |after, limit| {
+            selection_log(
+                Query(SelectionLogQueryParams { after, limit }),
+                Extension(storage.clone()),
+            )
+        };
+        let Json(first) = page(0, Some(2)).await.unwrap();
+        assert_eq!(first.assignments.len(), 2);
+        assert_eq!(first.next, Some(first.assignments[1].id));
+        let Json(second) = page(first.next.unwrap(), Some(2)).await.unwrap();
+        assert_eq!(second.assignments.len(), 1);
+        assert_eq!(second.next, None);
+
+        let entries = first.assignments.iter().chain(&second.assignments);
+        let lobby_pick = entries
+            .clone()
+            .find(|entry| entry.rule == SelectionRule::FirstCome.as_str())
+            .unwrap();
+        assert_eq!(lobby_pick.selected, session_id_hash(&alice));
+        assert_eq!(lobby_pick.candidates, vec![session_id_hash(&alice)]);
+        assert_eq!(lobby_pick.seed, None);
+        assert_eq!(
+            entries
+                .filter(|entry| entry.beacon_round == Some(1))
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_current_state() {
+        let mut transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        transcript
+            .verify_add::<Engine>(contribution, Identity::None)
+            .unwrap();
+        let shared: SharedTranscript = Arc::new(TranscriptStore::new(transcript.clone()));
+
+        let Json(state) = filtered_state(
+            &CurrentStateQueryParams {
+                num_g1_powers: Some(4),
+                powers_start: Some(1),
+                ..CurrentStateQueryParams::default()
+            },
+            &shared,
+        )
+        .await
+        .unwrap();
+        let ceremony = &state["transcripts"][0];
+        assert_eq!(ceremony["numG1Powers"], 4);
+        assert_eq!(ceremony["powersStart"], 1);
+        assert_eq!(
+            ceremony["powersOfTau"]["G1Powers"],
+            json!(transcript.transcripts[0].powers.g1[1..])
+        );
+        assert_eq!(
+            ceremony["powersOfTau"]["G2Powers"],
+            json!(transcript.transcripts[0].powers.g2[1..])
+        );
+        assert_eq!(state["participantIds"].as_array().unwrap().len(), 2);
+
+        let Json(state) = filtered_state(
+            &CurrentStateQueryParams {
+                witness_only: true,
+                ..CurrentStateQueryParams::default()
+            },
+            &shared,
+        )
+        .await
+        .unwrap();
+        let ceremony = &state["transcripts"][0];
+        assert!(ceremony.get("powersOfTau").is_none());
+        assert_eq!(
+            ceremony["witness"],
+            json!(transcript.transcripts[0].witness)
+        );
+
+        assert!(matches!(
+            filtered_state(
+                &CurrentStateQueryParams {
+                    num_g1_powers: Some(8),
+                    ..CurrentStateQueryParams::default()
+                },
+                &shared
+            )
+            .await,
+            Err(CurrentStateError::UnknownCeremonySize)
+        ));
+        assert!(matches!(
+            filtered_state(
+                &CurrentStateQueryParams {
+                    powers_start: Some(3),
+                    powers_end: Some(2),
+                    ..CurrentStateQueryParams::default()
+                },
+                &shared
+            )
+            .await,
+            Err(CurrentStateError::InvalidPowersRange)
+        ));
+    }
+
+    #[tokio::test]
+    async fn signs_status() {
+        let options = test_options();
+        let keys = Arc::new(
+            Keys::new(&keys::Options {
+                signing_key:     None,
+                bls_signing_key: None,
+            })
+            .unwrap(),
+        );
+        let sequence = SharedStatusSequence::default();
+        let snapshot = || async {
+            let Json(response) = signed_status(
+                Extension(SharedLobbyState::new(options.lobby.clone())),
+                Extension(Arc::new(AtomicUsize::new(2))),
+                Extension(keys.clone()),
+                Extension(sequence.clone()),
+                Extension(SharedAnnouncement::default()),
+                Extension(SharedForecast::default()),
+                Extension(options.clone()),
+            )
+            .await
+            .unwrap();
+            keys.verify(&response.status, &response.signature).unwrap();
+            serde_json::from_str::<Value>(&response.status).unwrap()
+        };
+
+        let first = snapshot().await;
+        let second = snapshot().await;
+        assert_eq!(first["num_contributions"], 2);
+        assert_eq!(first["sequencer_address"], keys.address().to_string());
+        assert!(first["timestamp"].as_i64().unwrap() >= Utc::now().timestamp() - 60);
+        assert!(second["sequence"].as_u64() > first["sequence"].as_u64());
+
+        // The sequence doesn't go back with the clock
+        let last = sequence.next(u64::MAX - 1);
+        assert_eq!(sequence.next(0), last + 1);
+    }
+
+    #[tokio::test]
+    async fn signs_sequencer_info() {
+        let mut options = test_options();
+        options.completion.target_participants = Some(100);
+        let keys = Arc::new(
+            Keys::new(&keys::Options {
+                signing_key:     None,
+                bls_signing_key: Some("01".repeat(32)),
+            })
+            .unwrap(),
+        );
+        let Json(response) = sequencer(Extension(keys.clone()), Extension(options.clone()))
+            .await
+            .unwrap();
+        keys.verify(&response.sequencer, &response.signature)
+            .unwrap();
+        let info = serde_json::from_str::<Value>(&response.sequencer).unwrap();
+        assert_eq!(info["build"]["version"], SEQUENCER_VERSION);
+        assert!(info["build"]["engine"]
+            .as_str()
+            .unwrap()
+            .contains("kzg_ceremony_crypto"));
+        assert_eq!(info["sequencer_address"], keys.address().to_string());
+        let jwks = info["jwks"]["keys"].as_array().unwrap();
+        assert_eq!(jwks.len(), 2);
+        assert_eq!(jwks[0]["kid"], keys.address().to_string());
+        assert_eq!(jwks[0]["x"].as_str().unwrap().len(), 43);
+        assert_eq!(jwks[1]["crv"], "BLS12381G2");
+        assert_eq!(
+            info["ceremony_sizes"].as_array().unwrap().len(),
+            options.ceremony_sizes.sizes().len()
+        );
+        assert_eq!(info["target_participants"], 100);
+        assert!(info.get("ceremony_end").is_none());
+    }
+
+    #[tokio::test]
+    async fn publishes_region_counts() {
+        let options = test_options();
+        let storage = storage_client(&options.storage).await.unwrap();
+        for _ in 0..options.regions.region_stats_min_count {
+            storage.count_region("europe").await.unwrap();
+        }
+        storage.count_region("asia").await.unwrap();
+
+        let Json(stats) = region_stats(Extension(storage), Extension(options))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            json!({ "regions": { "europe": 5 }, "withheld": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_witness_segment() {
+        let mut transcript = test_transcript();
+        for index in 1..=2 {
+            let contribution = valid_contribution(&transcript, index);
+            transcript
+                .verify_add::<Engine>(contribution, Identity::None)
+                .unwrap();
+        }
+        let shared: SharedTranscript = Arc::new(TranscriptStore::new(transcript.clone()));
+        let segment = |from, to| {
+            witness_segment(
+                Query(WitnessSegmentQueryParams { from, to }),
+                Extension(shared.clone()),
+            )
+        };
+
+        let Json(response) = segment(1, None).await.unwrap();
+        assert_eq!(response.num_entries, 3);
+        assert_eq!(response.segments.len(), transcript.transcripts.len());
+        for (segment, transcript) in response.segments.iter().zip(&transcript.transcripts) {
+            assert_eq!(segment.start, 1);
+            assert_eq!(segment.pubkeys, transcript.witness.pubkeys[1..]);
+            kzg_ceremony_crypto::verify_inclusion::<Engine>(
+                transcript.witness.pubkeys[2],
+                2,
+                segment,
+            )
+            .unwrap();
+        }
+
+        let Json(response) = segment(0, Some(1)).await.unwrap();
+        assert_eq!(response.segments[0].products.len(), 2);
+        assert!(segment(3, None).await.is_err());
+        assert!(segment(2, Some(1)).await.is_err());
+    }
 }
@@ -1,31 +1,67 @@
 use crate::{
-    lobby::{ActiveContributorError, SharedLobbyState},
+    announcement::SharedAnnouncement,
+    api_types::{DeferQuery, PingResponse, Reservation, TryContributeResponse},
+    attempts::AttemptError,
+    clock::server_time,
+    guard::{self, Mark},
+    lobby::{
+        fetch_beacon, ActiveContributorError, BeaconError, EvictionReason, SharedLobbyState, SlotId,
+    },
     storage::{PersistentStorage, StorageError},
+    wal::{self, Operation},
     SessionId, SharedTranscript,
 };
 use axum::{
+    extract::Query,
     response::{IntoResponse, Response},
     Extension, Json,
 };
+use chrono::Utc;
 use http::StatusCode;
-use kzg_ceremony_crypto::{BatchContribution, ErrorCode};
+use kzg_ceremony_crypto::ErrorCode;
 use serde::Serialize;
+use serde_json::{json, value::RawValue};
+use std::{collections::BTreeSet, time::Duration};
 use strum::IntoStaticStr;
 use thiserror::Error;
 use tokio::time::Instant;
+use tracing::{error, warn};
+use url::Url;
 
 #[derive(Debug, Error, IntoStaticStr)]
 pub enum TryContributeError {
     #[error("unknown session id")]
     UnknownSessionId,
+    #[error("evicted: {reason}, please authenticate again")]
+    Evicted { reason: EvictionReason },
     #[error("call came too early. rate limited")]
     RateLimited,
     #[error("another contribution in progress")]
-    AnotherContributionInProgress,
+    AnotherContributionInProgress { estimated_wait_seconds: u64 },
     #[error("lobby is full")]
     LobbyIsFull,
+    #[error("lobby token expired, please authenticate again")]
+    LobbyTokenExpired,
+    #[error("user is not allowed to contribute")]
+    NotAllowed,
+    #[error("session has not joined the lobby")]
+    NotJoined,
+    #[error("email address has to be confirmed first")]
+    EmailNotVerified,
+    #[error("too many failed contribution attempts")]
+    TooManyFailedAttempts,
+    #[error("failed contribution attempt, retry in {retry_after_seconds} seconds")]
+    CoolingDown { retry_after_seconds: u64 },
     #[error("error in storage layer: {0}")]
     StorageError(#[from] StorageError),
+    #[error("randomness beacon unavailable: {0}")]
+    BeaconUnavailable(#[from] BeaconError),
+    #[error("the ceremony is complete, no more contributions are accepted")]
+    CeremonyComplete,
+    #[error("deferring a turn is disabled")]
+    DeferralDisabled,
+    #[error("user has already contributed")]
+    AlreadyContributed,
 }
 
 impl ErrorCode for TryContributeError {
@@ -37,61 +73,424 @@ impl ErrorCode for TryContributeError {
 impl From<ActiveContributorError> for TryContributeError {
     fn from(err: ActiveContributorError) -> Self {
         match err {
-            ActiveContributorError::AnotherContributionInProgress
-            | ActiveContributorError::NotUsersTurn => Self::AnotherContributionInProgress,
+            ActiveContributorError::AnotherContributionInProgress { estimated_wait } => {
+                Self::AnotherContributionInProgress {
+                    estimated_wait_seconds: estimated_wait.as_secs(),
+                }
+            }
+            ActiveContributorError::NotUsersTurn
+            | ActiveContributorError::StaleSlot
+            | ActiveContributorError::AlreadyExtended => Self::AnotherContributionInProgress {
+                estimated_wait_seconds: 0,
+            },
             ActiveContributorError::UserNotInLobby => Self::UnknownSessionId,
             ActiveContributorError::SessionCountLimitExceeded
             | ActiveContributorError::LobbySizeLimitExceeded => Self::LobbyIsFull,
+            ActiveContributorError::LobbyTokenExpired => Self::LobbyTokenExpired,
+            ActiveContributorError::LobbyClosed => Self::CeremonyComplete,
         }
     }
 }
 
-#[derive(Debug)]
-pub struct TryContributeResponse<C> {
-    contribution: C,
+impl From<AttemptError> for TryContributeError {
+    fn from(err: AttemptError) -> Self {
+        match err {
+            AttemptError::TooManyFailedAttempts => Self::TooManyFailedAttempts,
+            AttemptError::CoolingDown { retry_after } => Self::CoolingDown {
+                retry_after_seconds: retry_after.as_secs(),
+            },
+        }
+    }
 }
 
 impl<C: Serialize> IntoResponse for TryContributeResponse<C> {
     fn into_response(self) -> Response {
-        (StatusCode::OK, Json(self.contribution)).into_response()
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+fn ping_response(
+    lobby_state: &SharedLobbyState,
+    estimated_wait: Duration,
+    on_deck: Option<usize>,
+) -> PingResponse {
+    PingResponse {
+        estimated_wait_seconds: estimated_wait.as_secs(),
+        on_deck,
+        ping_within_seconds: Some(lobby_state.options().lobby_checkin_frequency.as_secs()),
+        server_time: Some(server_time()),
     }
 }
 
+impl IntoResponse for PingResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+/// The error for a session that is not in the lobby state, telling clients
+/// why if it was dropped by the lobby flush.
+async fn unknown_session(
+    lobby_state: &SharedLobbyState,
+    session_id: &SessionId,
+) -> TryContributeError {
+    lobby_state
+        .take_eviction(session_id)
+        .await
+        .map_or(TryContributeError::UnknownSessionId, |reason| {
+            TryContributeError::Evicted { reason }
+        })
+}
+
+/// Keeps a session alive without asking for the contribution slot. Unlike
+/// `/lobby/try_contribute` this is not rate limited, so clients can use it
+/// to check in as often as they like.
+pub async fn ping(
+    session_id: SessionId,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+) -> Result<PingResponse, TryContributeError> {
+    if lobby_state
+        .modify_participant(&session_id, |info| info.last_heartbeat = Instant::now())
+        .await
+        .is_none()
+    {
+        return Err(unknown_session(&lobby_state, &session_id).await);
+    }
+
+    Ok(ping_response(
+        &lobby_state,
+        lobby_state.estimated_wait_for(&session_id).await,
+        lobby_state.on_deck_position(&session_id).await,
+    ))
+}
+
+/// Joins the lobby. Required before `/lobby/try_contribute` when the
+/// sequencer is run with `--lobby-explicit-join`. Joining again is a no-op.
+pub async fn join(
+    session_id: SessionId,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(options): Extension<crate::Options>,
+) -> Result<PingResponse, TryContributeError> {
+    let email_verified = match lobby_state
+        .modify_participant(&session_id, |info| {
+            info.last_heartbeat = Instant::now();
+            info.email_verified
+        })
+        .await
+    {
+        Some(email_verified) => email_verified,
+        None => return Err(unknown_session(&lobby_state, &session_id).await),
+    };
+    if options.email.email_verification && !email_verified {
+        return Err(TryContributeError::EmailNotVerified);
+    }
+
+    lobby_state.enter_lobby(&session_id).await?;
+
+    Ok(ping_response(
+        &lobby_state,
+        lobby_state.estimated_wait_for(&session_id).await,
+        lobby_state.on_deck_position(&session_id).await,
+    ))
+}
+
+/// Leaves the lobby, giving up the place on deck if there was one. The
+/// session stays valid for joining again. To give back the slot, use
+/// `/contribute/abort` instead.
+pub async fn leave(
+    session_id: SessionId,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+) -> Result<(), TryContributeError> {
+    lobby_state
+        .leave_lobby(&session_id)
+        .await
+        .map_err(|_| TryContributeError::NotJoined)
+}
+
+/// Steps away from the lobby for a while without giving up the session, e.g.
+/// for participants who signed in early. They don't have to ping meanwhile,
+/// and don't get the slot until the time is up.
+pub async fn defer(
+    session_id: SessionId,
+    Query(query): Query<DeferQuery>,
+    Extension(lobby_state): Extension<SharedLobbyState>,
+    Extension(options): Extension<crate::Options>,
+) -> Result<PingResponse, TryContributeError> {
+    let max_deferral = options.lobby.lobby_max_deferral;
+    if max_deferral.is_zero() {
+        return Err(TryContributeError::DeferralDisabled);
+    }
+    let deferral = Duration::from_secs(query.seconds).min(max_deferral);
+
+    let estimated_wait = match lobby_state.defer(&session_id, deferral).await {
+        Ok(estimated_wait) => estimated_wait,
+        // Known, but outside the lobby
+        Err(_)
+            if lobby_state
+                .modify_participant(&session_id, |_| ())
+                .await
+                .is_some() =>
+        {
+            return Err(TryContributeError::NotJoined)
+        }
+        Err(_) => return Err(unknown_session(&lobby_state, &session_id).await),
+    };
+
+    let mut response = ping_response(&lobby_state, estimated_wait, None);
+    // No pings are needed while stepped away
+    response.ping_within_seconds = response
+        .ping_within_seconds
+        .map(|seconds| seconds + deferral.as_secs());
+    Ok(response)
+}
+
 pub async fn try_contribute(
     session_id: SessionId,
     Extension(lobby_state): Extension<SharedLobbyState>,
     Extension(storage): Extension<PersistentStorage>,
     Extension(transcript): Extension<SharedTranscript>,
+    Extension(http_client): Extension<reqwest::Client>,
     Extension(options): Extension<crate::Options>,
-) -> Result<TryContributeResponse<BatchContribution>, TryContributeError> {
-    let uid = lobby_state
+    Extension(announcement): Extension<SharedAnnouncement>,
+) -> Result<TryContributeResponse<Box<RawValue>>, TryContributeError> {
+    // Participants picked by the beacon learn of their slot when they next
+    // ask. Its grant is recorded by then, so they only get their reservation.
+    if options.lobby.lobby_selection_beacon_url.is_some() {
+        if let Some((slot_id, time_left)) = lobby_state.reserved_slot(&session_id).await {
+            return Ok(reservation_response(slot_id, time_left, &transcript, &announcement).await);
+        }
+    }
+
+    // Participants on deck may ask as often as they like, to take the slot
+    // as soon as it frees up
+    let is_on_deck = lobby_state.on_deck_position(&session_id).await.is_some();
+    let (uid, email_verified) = match lobby_state
         .modify_participant(&session_id, |mut info| {
             let now = Instant::now();
+            let lobby_options = lobby_state.options();
             let min_diff =
-                options.lobby.lobby_checkin_frequency - options.lobby.lobby_checkin_tolerance;
-            if !info.is_first_ping_attempt && now < info.last_ping_time + min_diff {
+                lobby_options.lobby_checkin_frequency - lobby_options.lobby_checkin_tolerance;
+            if !is_on_deck && !info.is_first_ping_attempt && now < info.last_ping_time + min_diff {
                 return Err(TryContributeError::RateLimited);
             }
             info.is_first_ping_attempt = false;
             info.last_ping_time = now;
-            Ok(info.token.unique_identifier())
+            info.last_heartbeat = now;
+            Ok((info.uid.clone(), info.email_verified))
         })
         .await
-        .unwrap_or(Err(TryContributeError::UnknownSessionId))?;
+    {
+        Some(participant) => participant?,
+        None => return Err(unknown_session(&lobby_state, &session_id).await),
+    };
+    if options.email.email_verification && !email_verified {
+        return Err(TryContributeError::EmailNotVerified);
+    }
+    check_identity(&storage, &uid, &options).await?;
 
-    lobby_state.enter_lobby(&session_id).await?;
+    if !options.lobby.lobby_explicit_join {
+        lobby_state.enter_lobby(&session_id).await?;
+    } else if !lobby_state.is_in_lobby(&session_id).await {
+        return Err(TryContributeError::NotJoined);
+    }
 
-    lobby_state
-        .set_current_contributor(&session_id, options.lobby.compute_deadline, storage.clone())
-        .await
-        .map_err(TryContributeError::from)?;
+    let (slot_id, time_left) = match &options.lobby.lobby_selection_beacon_url {
+        Some(url) => {
+            reserve_selected_slot(
+                &session_id,
+                url,
+                &lobby_state,
+                &storage,
+                &http_client,
+                &options,
+            )
+            .await?
+        }
+        None => {
+            let compute_deadline = lobby_state.options().compute_deadline;
+            let slot_id = lobby_state
+                .set_current_contributor(&session_id, compute_deadline, storage.clone())
+                .await
+                .map_err(TryContributeError::from)?;
+            record_grant(
+                &lobby_state,
+                &storage,
+                &uid,
+                &slot_id,
+                options.multi_contribution,
+            )
+            .await?;
+            (slot_id, compute_deadline)
+        }
+    };
+    Ok(reservation_response(slot_id, time_left, &transcript, &announcement).await)
+}
+
+async fn reservation_response(
+    slot_id: SlotId,
+    time_left: Duration,
+    transcript: &SharedTranscript,
+    announcement: &SharedAnnouncement,
+) -> TryContributeResponse<Box<RawValue>> {
+    let now = server_time();
+    let template = transcript.contribution_template();
+    TryContributeResponse {
+        reservation:  Reservation {
+            slot_id:         slot_id.0,
+            deadline:        now.saturating_add(time_left.as_secs()),
+            seconds_left:    Some(time_left.as_secs()),
+            server_time:     Some(now),
+            transcript_hash: template.transcript_hash,
+        },
+        contribution: template.contribution,
+        announcement: announcement.get().await,
+    }
+}
+
+/// Whether the identity may take the slot, going by its mark and its failed
+/// attempts.
+async fn check_identity(
+    storage: &PersistentStorage,
+    uid: &str,
+    options: &crate::Options,
+) -> Result<(), TryContributeError> {
+    let mark = storage.identity_mark(uid).await?;
+    if !guard::allows(mark.as_deref(), options.multi_contribution) {
+        return Err(TryContributeError::AlreadyContributed);
+    }
+
+    // Identities whose clients keep failing don't get to hold up the slot
+    let failed_attempts = storage.failed_attempts(uid).await?;
+    if let Err(error) = options.attempts.check(&failed_attempts, Utc::now()) {
+        if error == AttemptError::TooManyFailedAttempts {
+            guard::mark(storage, uid, Mark::Banned).await;
+        }
+        return Err(error.into());
+    }
+    Ok(())
+}
 
-    storage.insert_contributor(&uid).await?;
-    let transcript = transcript.read().await;
+/// Claims the slot just granted to `uid` against the guard and records it.
+/// Gives the slot back if that fails, or nobody gets it until the compute
+/// deadline.
+async fn record_grant(
+    lobby_state: &SharedLobbyState,
+    storage: &PersistentStorage,
+    uid: &str,
+    slot_id: &SlotId,
+    multi_contribution: bool,
+) -> Result<(), TryContributeError> {
+    let result = claim_grant(storage, uid, slot_id, multi_contribution).await;
+    if result.is_err() {
+        lobby_state.clear_current_contributor().await;
+        guard::mark(storage, uid, Mark::Aborted).await;
+        wal::complete(storage, Operation::SlotGrant, uid).await;
+    }
+    result
+}
 
-    Ok(TryContributeResponse {
-        contribution: transcript.contribution(),
-    })
+async fn claim_grant(
+    storage: &PersistentStorage,
+    uid: &str,
+    slot_id: &SlotId,
+    multi_contribution: bool,
+) -> Result<(), TryContributeError> {
+    // Claimed atomically, so that no other session of the identity, e.g. one
+    // restored after a restart, can have contributed meanwhile
+    if !guard::claim(storage, uid, multi_contribution).await? {
+        return Err(TryContributeError::AlreadyContributed);
+    }
+    wal::log(
+        storage,
+        Operation::SlotGrant,
+        uid,
+        &json!({ "slot_id": slot_id }),
+    )
+    .await?;
+    storage.insert_contributor(uid).await?;
+    Ok(())
+}
+
+/// With a selection beacon the free slot is handed to a participant picked
+/// by the beacon, rather than to whoever asks first. Returns the slot if it
+/// went to the caller, with the time they have left to contribute. The grant
+/// is recorded by the request that made the pick, so the picked
+/// participant's later polls only read their reservation.
+async fn reserve_selected_slot(
+    session_id: &SessionId,
+    beacon_url: &Url,
+    lobby_state: &SharedLobbyState,
+    storage: &PersistentStorage,
+    http_client: &reqwest::Client,
+    options: &crate::Options,
+) -> Result<(SlotId, Duration), TryContributeError> {
+    if lobby_state.is_slot_free().await {
+        let beacon = fetch_beacon(http_client, beacon_url, &options.lobby).await?;
+        // Participants who would be refused the slot once picked aren't
+        // drawn, or it would sit idle until the deadline
+        let mut excluded = BTreeSet::new();
+        for uid in lobby_state.lobby_uids().await {
+            match check_identity(storage, &uid, options).await {
+                Ok(()) => {}
+                Err(error @ TryContributeError::StorageError(_)) => return Err(error),
+                Err(_) => {
+                    excluded.insert(uid);
+                }
+            }
+        }
+        if let Some(pick) = lobby_state
+            .select_contributor(
+                &beacon,
+                &excluded,
+                lobby_state.options().compute_deadline,
+                storage.clone(),
+            )
+            .await
+        {
+            let selection = &pick.selection;
+            // The round makes the pick verifiable, see `/info/selection`
+            if let Err(error) = storage
+                .insert_audit_event(
+                    "contributor_selected",
+                    "beacon",
+                    &json!({
+                        "index": selection.index,
+                        "selected": selection.candidates[selection.selected],
+                        "beacon_round": selection.beacon_round,
+                        "beacon_randomness": selection.beacon_randomness,
+                        "candidates": selection.candidates.len(),
+                    }),
+                )
+                .await
+            {
+                error!(?error, "Could not record selection");
+            }
+
+            let granted = record_grant(
+                lobby_state,
+                storage,
+                &pick.uid,
+                &pick.slot_id,
+                options.multi_contribution,
+            )
+            .await;
+            if let Err(error) = granted {
+                if &pick.participant == session_id {
+                    return Err(error);
+                }
+                warn!(
+                    ?error,
+                    "Could not grant the slot to the selected participant"
+                );
+            }
+        }
+    }
+    match lobby_state.reserved_slot(session_id).await {
+        Some(slot) => Ok(slot),
+        None => Err(TryContributeError::AnotherContributionInProgress {
+            estimated_wait_seconds: lobby_state.estimated_wait().await.as_secs(),
+        }),
+    }
 }
 
 #[cfg(test)]
@@ -99,19 +498,24 @@ mod tests {
     use super::*;
     use crate::{
         api::v1::lobby::TryContributeError,
+        io::transcript_hash,
         storage::storage_client,
-        test_util::{create_test_session_info, test_options},
+        test_util::{create_test_session_info, test_jwt, test_options},
         tests::test_transcript,
+        transcript::TranscriptStore,
     };
-    use std::{sync::Arc, time::Duration};
-    use tokio::sync::RwLock;
+    use kzg_ceremony_crypto::{
+        drand::DrandChain, signature::identity::Identity, BatchContribution,
+    };
+    use sha2::{Digest, Sha256};
+    use std::{future::ready, sync::Arc, time::Duration};
 
     #[tokio::test]
     #[allow(clippy::too_many_lines)]
     async fn lobby_try_contribute_test() {
         let opts = test_options();
         let lobby_state = SharedLobbyState::new(opts.lobby.clone());
-        let transcript = Arc::new(RwLock::new(test_transcript()));
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
         let db = storage_client(&opts.storage).await.unwrap();
 
         let session_id = SessionId::new();
@@ -123,7 +527,9 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(opts),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
         assert!(matches!(
@@ -145,7 +551,9 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
         )
         .await
         .unwrap();
@@ -154,13 +562,15 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
 
         assert!(matches!(
             contribution_in_progress_response,
-            Err(TryContributeError::AnotherContributionInProgress)
+            Err(TryContributeError::AnotherContributionInProgress { .. })
         ));
 
         tokio::time::pause();
@@ -172,7 +582,9 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
 
@@ -192,7 +604,9 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
         assert!(matches!(
@@ -207,14 +621,536 @@ mod tests {
             Extension(lobby_state.clone()),
             Extension(db.clone()),
             Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
             Extension(test_options()),
+            Extension(SharedAnnouncement::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            success_response.reservation.transcript_hash,
+            transcript_hash(&transcript.snapshot())
+        );
+        // Clients with a wrong clock can rely on the seconds left
+        let reservation = &success_response.reservation;
+        let seconds_left = reservation.seconds_left.unwrap();
+        assert_eq!(
+            seconds_left,
+            test_options().lobby.compute_deadline.as_secs()
+        );
+        assert_eq!(
+            reservation.deadline,
+            reservation.server_time.unwrap() + seconds_left
+        );
+        assert_eq!(
+            serde_json::from_str::<BatchContribution>(success_response.contribution.get()).unwrap(),
+            transcript.snapshot().contribution()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_lobby_token() {
+        let opts = test_options();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+
+        tokio::time::pause();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+
+        tokio::time::advance(opts.lobby.lobby_token_ttl + Duration::from_secs(1)).await;
+        let expired_response = try_contribute(
+            session_id.clone(),
+            Extension(lobby_state.clone()),
+            Extension(db.clone()),
+            Extension(transcript.clone()),
+            Extension(reqwest::Client::new()),
+            Extension(opts.clone()),
+            Extension(SharedAnnouncement::default()),
+        )
+        .await;
+        assert!(matches!(
+            expired_response,
+            Err(TryContributeError::LobbyTokenExpired)
+        ));
+
+        // The expired session is dropped, re-authentication is required
+        let unknown_session_response = try_contribute(
+            session_id,
+            Extension(lobby_state),
+            Extension(db),
+            Extension(transcript),
+            Extension(reqwest::Client::new()),
+            Extension(opts),
+            Extension(SharedAnnouncement::default()),
         )
         .await;
         assert!(matches!(
-            success_response,
-            Ok(TryContributeResponse {
-                contribution: BatchContribution { .. },
+            unknown_session_response,
+            Err(TryContributeError::UnknownSessionId)
+        ));
+    }
+
+    #[tokio::test]
+    async fn ping_keeps_session_alive() {
+        let opts = test_options();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let session_id = SessionId::new();
+
+        let unknown_session_response =
+            ping(session_id.clone(), Extension(lobby_state.clone())).await;
+        assert!(matches!(
+            unknown_session_response,
+            Err(TryContributeError::UnknownSessionId)
+        ));
+
+        tokio::time::pause();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&session_id).await.unwrap();
+
+        // Pings are not rate limited, unlike try_contribute
+        for _ in 0..3 {
+            tokio::time::advance(Duration::from_secs(1)).await;
+            let response = ping(session_id.clone(), Extension(lobby_state.clone()))
+                .await
+                .unwrap();
+            assert_eq!(
+                response.ping_within_seconds,
+                Some(opts.lobby.lobby_checkin_frequency.as_secs())
+            );
+        }
+        let heartbeat = lobby_state
+            .modify_participant(&session_id, |info| info.last_heartbeat)
+            .await
+            .unwrap();
+        assert_eq!(heartbeat, Instant::now());
+    }
+
+    #[tokio::test]
+    async fn tells_evicted_clients_why() {
+        let opts = test_options();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let session_id = SessionId::new();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&session_id).await.unwrap();
+        lobby_state.clear_lobby(|_| true).await;
+
+        let response = ping(session_id.clone(), Extension(lobby_state.clone())).await;
+        assert!(matches!(
+            response,
+            Err(TryContributeError::Evicted {
+                reason: EvictionReason::Idle,
             })
         ));
+
+        // Later polls don't know the session at all
+        let response = ping(session_id, Extension(lobby_state)).await;
+        assert!(matches!(
+            response,
+            Err(TryContributeError::UnknownSessionId)
+        ));
+    }
+
+    #[tokio::test]
+    async fn keeps_slot_for_participants_on_deck() {
+        let mut opts = test_options();
+        opts.lobby.lobby_on_deck = 1;
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let contribute = |session_id: &SessionId| {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+
+        tokio::time::pause();
+        let sessions = [SessionId::new(), SessionId::new(), SessionId::new()];
+        for session_id in &sessions {
+            lobby_state
+                .insert_session(session_id.clone(), create_test_session_info(100))
+                .await
+                .unwrap();
+        }
+        contribute(&sessions[0]).await.unwrap();
+
+        // The first to ask for the taken slot is put on deck
+        for session_id in &sessions[1..] {
+            assert!(matches!(
+                contribute(session_id).await,
+                Err(TryContributeError::AnotherContributionInProgress { .. })
+            ));
+        }
+        let ping_response = ping(sessions[1].clone(), Extension(lobby_state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(ping_response.on_deck, Some(0));
+        let ping_response = ping(sessions[2].clone(), Extension(lobby_state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(ping_response.on_deck, None);
+
+        // They are not rate limited while waiting
+        assert!(matches!(
+            contribute(&sessions[1]).await,
+            Err(TryContributeError::AnotherContributionInProgress { .. })
+        ));
+        assert!(matches!(
+            contribute(&sessions[2]).await,
+            Err(TryContributeError::RateLimited)
+        ));
+
+        // The free slot is kept for them
+        lobby_state.clear_current_contributor().await;
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(matches!(
+            contribute(&sessions[2]).await,
+            Err(TryContributeError::AnotherContributionInProgress { .. })
+        ));
+        contribute(&sessions[1]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requires_joining_the_lobby() {
+        let mut opts = test_options();
+        opts.lobby.lobby_explicit_join = true;
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+        let contribute = || {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+        let join_lobby = || {
+            join(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(opts.clone()),
+            )
+        };
+
+        tokio::time::pause();
+        assert!(matches!(
+            join_lobby().await,
+            Err(TryContributeError::UnknownSessionId)
+        ));
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        assert!(matches!(
+            contribute().await,
+            Err(TryContributeError::NotJoined)
+        ));
+
+        tokio::time::advance(Duration::from_secs(100)).await;
+        join_lobby().await.unwrap();
+        join_lobby().await.unwrap();
+        assert_eq!(lobby_state.get_lobby_size().await, 1);
+        leave(session_id.clone(), Extension(lobby_state.clone()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            leave(session_id.clone(), Extension(lobby_state.clone())).await,
+            Err(TryContributeError::NotJoined)
+        ));
+        assert_eq!(lobby_state.get_lobby_size().await, 0);
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(matches!(
+            contribute().await,
+            Err(TryContributeError::NotJoined)
+        ));
+
+        // Leaving renews the lobby token, so that the session may join again
+        tokio::time::advance(opts.lobby.lobby_token_ttl - Duration::from_secs(60)).await;
+        join_lobby().await.unwrap();
+        contribute().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requires_confirmed_email() {
+        let mut opts = test_options();
+        opts.email.email_verification = true;
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+        let contribute = || {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        assert!(matches!(
+            contribute().await,
+            Err(TryContributeError::EmailNotVerified)
+        ));
+        assert_eq!(lobby_state.get_lobby_size().await, 0);
+
+        // Each address confirms a single identity
+        let uid = test_jwt(100).unique_identifier();
+        assert!(db.claim_email("hash", &uid).await.unwrap());
+        assert!(db.claim_email("hash", &uid).await.unwrap());
+        assert!(!db.claim_email("hash", "another uid").await.unwrap());
+        assert!(db.has_verified_email(&uid).await.unwrap());
+
+        lobby_state
+            .modify_participant(&session_id, |info| info.email_verified = true)
+            .await
+            .unwrap();
+        contribute().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_failed_attempts() {
+        let mut opts = test_options();
+        opts.attempts.max_failed_attempts = 2;
+        opts.attempts.failed_attempt_cooldown = Duration::from_secs(3600);
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+        let uid = test_jwt(100).unique_identifier();
+        let contribute = |opts: &crate::Options| {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+
+        tokio::time::pause();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        db.insert_failed_attempt(&uid, "aborted").await.unwrap();
+        let response = contribute(&opts).await;
+        assert!(
+            matches!(
+                response,
+                Err(TryContributeError::CoolingDown {
+                    retry_after_seconds: 3500..=3600,
+                })
+            ),
+            "{response:?}"
+        );
+
+        // Without a cooldown only the number of failures counts
+        opts.attempts.failed_attempt_cooldown = Duration::ZERO;
+        db.insert_failed_attempt(&uid, "rejected").await.unwrap();
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(matches!(
+            contribute(&opts).await,
+            Err(TryContributeError::TooManyFailedAttempts)
+        ));
+        opts.attempts.max_failed_attempts = 3;
+        tokio::time::advance(Duration::from_secs(30)).await;
+        contribute(&opts).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_identity_that_contributed() {
+        let mut opts = test_options();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+        let contribute = |opts: &crate::Options| {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+
+        // A session that outlived the contribution of its identity, e.g. in
+        // a lobby restored after a restart
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        db.mark_identity(&test_jwt(100).unique_identifier(), "contributed")
+            .await
+            .unwrap();
+        assert!(matches!(
+            contribute(&opts).await,
+            Err(TryContributeError::AlreadyContributed)
+        ));
+        assert!(lobby_state.is_slot_free().await);
+
+        opts.multi_contribution = true;
+        contribute(&opts).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn grants_selected_slot_once() {
+        // An unchained drand chain, serving its first round
+        let secret_key = blst::min_pk::SecretKey::key_gen(&[7; 32], &[]).unwrap();
+        let chain = DrandChain {
+            public_key:   secret_key.sk_to_pk().compress().to_vec(),
+            period:       30,
+            genesis_time: 0,
+            group_hash:   vec![0; 32],
+            scheme:       "pedersen-bls-unchained".to_string(),
+            beacon_id:    String::new(),
+        };
+        let signature = secret_key
+            .sign(
+                &Sha256::digest(1_u64.to_be_bytes()),
+                b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_NUL_",
+                &[],
+            )
+            .compress();
+        let info = json!({
+            "public_key": hex::encode(&chain.public_key),
+            "period": chain.period,
+            "genesis_time": chain.genesis_time,
+            "groupHash": hex::encode(&chain.group_hash),
+            "schemeID": chain.scheme,
+        });
+        let round = json!({
+            "round": 1,
+            "randomness": hex::encode(Sha256::digest(signature)),
+            "signature": hex::encode(signature),
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener).unwrap().serve(
+            axum::Router::new()
+                .route(
+                    "/info",
+                    axum::routing::get(move || ready(axum::Json(info.clone()))),
+                )
+                .route(
+                    "/public/latest",
+                    axum::routing::get(move || ready(axum::Json(round.clone()))),
+                )
+                .into_make_service(),
+        );
+        tokio::spawn(server);
+
+        let mut opts = test_options();
+        opts.lobby.lobby_selection_beacon_url =
+            Some(format!("http://{address}/public/latest").parse().unwrap());
+        opts.lobby.lobby_selection_beacon_chain_hash = chain.hash().to_vec();
+        opts.lobby.lobby_selection_beacon_public_key = chain.public_key.clone();
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let contribute = |session_id: &SessionId| {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+
+        let session_id = SessionId::new();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        let first = contribute(&session_id).await.unwrap();
+
+        // Asking again neither claims the slot again, which would fail, nor
+        // loses it
+        let second = contribute(&session_id).await.unwrap();
+        assert_eq!(first.reservation.slot_id, second.reservation.slot_id);
+        assert!(lobby_state.reserved_slot(&session_id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn keeps_pseudonymous_sessions_under_their_identity() {
+        let mut opts = test_options();
+        opts.attempts.max_failed_attempts = 1;
+        let lobby_state = SharedLobbyState::new(opts.lobby.clone());
+        let transcript = Arc::new(TranscriptStore::new(test_transcript()));
+        let db = storage_client(&opts.storage).await.unwrap();
+        let session_id = SessionId::new();
+        let uid = test_jwt(100).unique_identifier();
+        let contribute = |opts: &crate::Options| {
+            try_contribute(
+                session_id.clone(),
+                Extension(lobby_state.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+                Extension(reqwest::Client::new()),
+                Extension(opts.clone()),
+                Extension(SharedAnnouncement::default()),
+            )
+        };
+
+        let mut session_info = create_test_session_info(100);
+        session_info.token.identity = Identity::Pseudonym { hash: [7; 32] };
+        lobby_state
+            .insert_session(session_id.clone(), session_info)
+            .await
+            .unwrap();
+
+        // Failures of the identity count against its pseudonym
+        db.insert_failed_attempt(&uid, "rejected").await.unwrap();
+        assert!(matches!(
+            contribute(&opts).await,
+            Err(TryContributeError::TooManyFailedAttempts)
+        ));
+        assert_eq!(
+            db.identity_mark(&uid).await.unwrap().as_deref(),
+            Some("banned")
+        );
+
+        // And so does its contribution
+        opts.attempts.max_failed_attempts = 0;
+        db.mark_identity(&uid, "contributed").await.unwrap();
+        assert!(matches!(
+            contribute(&opts).await,
+            Err(TryContributeError::AlreadyContributed)
+        ));
     }
 }
@@ -1,5 +1,8 @@
+pub mod admin;
 pub mod auth;
 pub mod contribute;
 pub mod error_response;
 pub mod info;
 pub mod lobby;
+pub mod receipt;
+pub mod upload;
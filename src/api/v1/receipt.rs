@@ -0,0 +1,310 @@
+use crate::{
+    api_types::InclusionProof,
+    inclusion::{inclusion_path, leaf_hashes, tree_head, Entry},
+    keys::{Address, SharedKeys, Signature},
+    receipt::{receipt_hash, session_hash, verification_url},
+    storage::{PersistentStorage, StorageError},
+    Options, SessionId, SharedTranscript,
+};
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::response::ErasedJson;
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    StatusCode,
+};
+use kzg_ceremony_crypto::{ErrorCode, G2};
+use qrcode::{render::svg, QrCode};
+use serde::{Deserialize, Serialize};
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum ReceiptError {
+    #[error("no receipt for this session")]
+    UnknownReceipt,
+    #[error("storage error: {0}")]
+    StorageError(#[from] StorageError),
+}
+
+impl ErrorCode for ReceiptError {
+    fn to_error_code(&self) -> String {
+        format!("ReceiptError::{}", <&str>::from(self))
+    }
+}
+
+/// Everything a participant needs to verify their contribution without the
+/// sequencer.
+#[derive(Debug, Serialize)]
+pub struct ReceiptBundle {
+    receipt:              String,
+    signature:            Signature,
+    sequencer_address:    Address,
+    /// Signature of `receipt` with the sequencer's BLS key, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bls_signature:        Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequencer_bls_pubkey: Option<G2>,
+    /// Signature of `receipt` by the participant, if they confirmed the
+    /// contribution with it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    countersignature:     Option<String>,
+    entry:                Entry,
+    /// Root of the tree over the entries up to and including the
+    /// participant's, i.e. the transcript as of their contribution.
+    transcript_hash:      String,
+    inclusion_proof:      InclusionProof,
+    /// Where anyone can check the receipt, also as a QR code at
+    /// `/receipt/:participant/qr`.
+    verification_url:     String,
+}
+
+impl IntoResponse for ReceiptBundle {
+    fn into_response(self) -> Response {
+        let disposition = format!(
+            "attachment; filename=\"receipt-{}.json\"",
+            self.entry.participant
+        );
+        (
+            StatusCode::OK,
+            [(CONTENT_DISPOSITION, disposition)],
+            ErasedJson::pretty(self),
+        )
+            .into_response()
+    }
+}
+
+/// The receipt bundle of the contribution made in this session.
+pub async fn receipt_mine(
+    session_id: SessionId,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(transcript): Extension<SharedTranscript>,
+    Extension(keys): Extension<SharedKeys>,
+    Extension(options): Extension<Options>,
+) -> Result<ReceiptBundle, ReceiptError> {
+    let stored = storage
+        .get_receipt(&session_hash(&session_id))
+        .await?
+        .ok_or(ReceiptError::UnknownReceipt)?;
+
+    let transcript = transcript.snapshot();
+    let index = stored.participant;
+    let entry = Entry::from_transcript(&transcript, index).ok_or(ReceiptError::UnknownReceipt)?;
+    let leaves = leaf_hashes(&transcript);
+    let verification_url =
+        verification_url(&options.server, index, &stored.receipt, &stored.signature).to_string();
+
+    Ok(ReceiptBundle {
+        receipt: stored.receipt,
+        signature: Signature::from_hex(stored.signature),
+        sequencer_address: keys.address(),
+        bls_signature: stored.bls_signature,
+        sequencer_bls_pubkey: keys.bls_pubkey(),
+        countersignature: stored.countersignature,
+        entry,
+        transcript_hash: hex::encode(tree_head(&leaves[..=index])),
+        inclusion_proof: InclusionProof {
+            tree_size: leaves.len(),
+            root:      hex::encode(tree_head(&leaves)),
+            path:      inclusion_path(index, &leaves)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+        },
+        verification_url,
+    })
+}
+
+/// The verification url of a participant's receipt as an SVG QR code, for
+/// showing the proof of contribution at events.
+///
+/// # Panics
+///
+/// Panics if the url doesn't fit in a QR code, which it does by far.
+pub async fn receipt_qr(
+    Path(participant): Path<usize>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(options): Extension<Options>,
+) -> Result<Response, ReceiptError> {
+    let stored = storage
+        .get_receipt_of_participant(participant)
+        .await?
+        .ok_or(ReceiptError::UnknownReceipt)?;
+    let url = verification_url(
+        &options.server,
+        participant,
+        &stored.receipt,
+        &stored.signature,
+    );
+    let image = QrCode::new(url.as_str())
+        .expect("Verification urls fit in a QR code")
+        .render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .build();
+    Ok((StatusCode::OK, [(CONTENT_TYPE, "image/svg+xml")], image).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReceiptVerificationQuery {
+    hash:      String,
+    signature: String,
+}
+
+/// The result of checking a receipt from its verification url.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ReceiptVerification {
+    /// Whether the receipt in the url is the one the sequencer signed for
+    /// the participant.
+    valid:             bool,
+    participant:       usize,
+    receipt:           String,
+    sequencer_address: Address,
+}
+
+/// Checks the receipt hash and signature of a verification url against the
+/// receipt stored for the participant, and the signature against the
+/// sequencer's key.
+pub async fn receipt_verify(
+    Path(participant): Path<usize>,
+    Query(query): Query<ReceiptVerificationQuery>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(keys): Extension<SharedKeys>,
+) -> Result<Json<ReceiptVerification>, ReceiptError> {
+    let stored = storage
+        .get_receipt_of_participant(participant)
+        .await?
+        .ok_or(ReceiptError::UnknownReceipt)?;
+    let valid = receipt_hash(&stored.receipt) == query.hash.to_lowercase()
+        && stored.signature == query.signature
+        && keys
+            .verify(&stored.receipt, &Signature::from_hex(query.signature))
+            .is_ok();
+    Ok(Json(ReceiptVerification {
+        valid,
+        participant,
+        receipt: stored.receipt,
+        sequencer_address: keys.address(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keys::{self, Keys},
+        storage::{storage_client, StoredReceipt},
+        test_util::test_options,
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+        Engine,
+    };
+    use clap::Parser;
+    use kzg_ceremony_crypto::Identity;
+    use std::{collections::HashMap, sync::Arc};
+    use url::Url;
+
+    #[tokio::test]
+    async fn bundles_receipt() {
+        let opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let keys = Arc::new(Keys::new(&keys::Options::parse_from(Vec::<&str>::new())).unwrap());
+        let mut transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        transcript
+            .verify_add::<Engine>(contribution, Identity::None)
+            .unwrap();
+        let transcript = Arc::new(TranscriptStore::new(transcript));
+        let session_id = SessionId::new();
+
+        let response = receipt_mine(
+            session_id.clone(),
+            Extension(db.clone()),
+            Extension(transcript.clone()),
+            Extension(keys.clone()),
+            Extension(opts.clone()),
+        )
+        .await;
+        assert!(matches!(response, Err(ReceiptError::UnknownReceipt)));
+
+        db.insert_receipt(&session_hash(&session_id), &StoredReceipt {
+            participant:      1,
+            receipt:          "receipt".to_string(),
+            signature:        "signature".to_string(),
+            bls_signature:    None,
+            countersignature: None,
+        })
+        .await
+        .unwrap();
+        let bundle = receipt_mine(
+            session_id,
+            Extension(db),
+            Extension(transcript.clone()),
+            Extension(keys),
+            Extension(opts),
+        )
+        .await
+        .unwrap();
+        let leaves = leaf_hashes(&transcript.snapshot());
+        assert_eq!(bundle.entry.participant, 1);
+        assert_eq!(bundle.transcript_hash, hex::encode(tree_head(&leaves)));
+        assert_eq!(bundle.inclusion_proof.tree_size, 2);
+        assert_eq!(bundle.inclusion_proof.path, vec![hex::encode(leaves[0])]);
+        assert!(bundle
+            .verification_url
+            .starts_with("http://127.0.0.1:3000/receipt/1/verify?hash="));
+    }
+
+    #[tokio::test]
+    async fn verifies_receipt_from_url() {
+        let opts = test_options();
+        let db = storage_client(&opts.storage).await.unwrap();
+        let keys = Arc::new(Keys::new(&keys::Options::parse_from(Vec::<&str>::new())).unwrap());
+        let receipt = "receipt".to_string();
+        let signature = keys.sign(&receipt).await.unwrap();
+        db.insert_receipt("session", &StoredReceipt {
+            participant:      1,
+            receipt:          receipt.clone(),
+            signature:        signature.as_hex().to_string(),
+            bls_signature:    None,
+            countersignature: None,
+        })
+        .await
+        .unwrap();
+
+        let url = verification_url(&opts.server, 1, &receipt, signature.as_hex());
+        let query = |url: &Url| {
+            let pairs = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+            ReceiptVerificationQuery {
+                hash:      pairs["hash"].clone(),
+                signature: pairs["signature"].clone(),
+            }
+        };
+        let verify = |participant, query| {
+            receipt_verify(
+                Path(participant),
+                Query(query),
+                Extension(db.clone()),
+                Extension(keys.clone()),
+            )
+        };
+        let Json(verification) = verify(1, query(&url)).await.unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.receipt, receipt);
+
+        let forged = verification_url(&opts.server, 1, "forged", signature.as_hex());
+        let Json(verification) = verify(1, query(&forged)).await.unwrap();
+        assert!(!verification.valid);
+        assert!(matches!(
+            verify(2, query(&url)).await,
+            Err(ReceiptError::UnknownReceipt)
+        ));
+
+        let response = receipt_qr(Path(1), Extension(db), Extension(opts))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/svg+xml");
+    }
+}
@@ -0,0 +1,241 @@
+//! Contributions uploaded as `multipart/form-data`, with the contribution to
+//! each ceremony in a part of its own. Every part is parsed and checked
+//! against the sizes of its ceremony as it arrives, so that a bad upload is
+//! rejected at its first bad part instead of after buffering the whole
+//! batch. Uploads of any other type are parsed as a JSON batch.
+//!
+//! The parts are
+//! - `contribution`, once per ceremony and in their order, as in the
+//!   `contributions` of a batch,
+//! - `ecdsaSignature`, optional, the hex encoded signature of a batch,
+//! - `entropyAttestation`, optional, as in a batch.
+
+use crate::Options;
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{BodyStream, FromRequest, RequestParts},
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
+use http::header::CONTENT_TYPE;
+use kzg_ceremony_crypto::{BatchContribution, Contribution, EcdsaSignature, ErrorCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum UploadError {
+    #[error("invalid multipart body: {0}")]
+    InvalidMultipart(String),
+    #[error("unexpected part {0}")]
+    UnexpectedPart(String),
+    #[error("part {name} is invalid: {reason}")]
+    InvalidPart { name: String, reason: String },
+    #[error("contribution {index} does not have the number of powers of its ceremony")]
+    UnexpectedSize { index: usize },
+    #[error("expected {expected} contributions, received {received}")]
+    UnexpectedNumContributions { expected: usize, received: usize },
+}
+
+impl ErrorCode for UploadError {
+    fn to_error_code(&self) -> String {
+        format!("UploadError::{}", <&str>::from(self))
+    }
+}
+
+impl From<multer::Error> for UploadError {
+    fn from(error: multer::Error) -> Self {
+        Self::InvalidMultipart(error.to_string())
+    }
+}
+
+/// A batch contribution, from a JSON or a multipart upload.
+#[derive(Debug)]
+pub struct ContributionUpload(pub BatchContribution);
+
+fn parse_part<T: DeserializeOwned>(name: &str, bytes: &[u8]) -> Result<T, UploadError> {
+    serde_json::from_slice(bytes).map_err(|error| UploadError::InvalidPart {
+        name:   name.to_owned(),
+        reason: error.to_string(),
+    })
+}
+
+/// Reads the parts of a multipart upload. With `sizes`, each contribution is
+/// checked against the numbers of powers of its ceremony.
+async fn read_parts(
+    mut multipart: multer::Multipart<'_>,
+    sizes: Option<&[(usize, usize)]>,
+) -> Result<BatchContribution, UploadError> {
+    let mut contributions = Vec::<Contribution>::new();
+    let mut ecdsa_signature = EcdsaSignature::empty();
+    let mut entropy_attestation = None;
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_owned();
+        match name.as_str() {
+            "contribution" => {
+                let index = contributions.len();
+                let expected = sizes.map(|sizes| sizes.get(index).copied());
+                if expected == Some(None) {
+                    return Err(UploadError::UnexpectedNumContributions {
+                        expected: index,
+                        received: index + 1,
+                    });
+                }
+                let contribution: Contribution = parse_part(&name, &field.bytes().await?)?;
+                let size = (contribution.powers.g1.len(), contribution.powers.g2.len());
+                if expected.map_or(false, |expected| expected != Some(size)) {
+                    return Err(UploadError::UnexpectedSize { index });
+                }
+                contributions.push(contribution);
+            }
+            "ecdsaSignature" => {
+                let signature = field.text().await?;
+                ecdsa_signature = serde_json::from_value(Value::String(
+                    signature.trim().to_owned(),
+                ))
+                .map_err(|error| UploadError::InvalidPart {
+                    name:   name.clone(),
+                    reason: error.to_string(),
+                })?;
+            }
+            "entropyAttestation" => {
+                entropy_attestation = Some(parse_part(&name, &field.bytes().await?)?);
+            }
+            _ => return Err(UploadError::UnexpectedPart(name)),
+        }
+    }
+    if let Some(sizes) = sizes {
+        if contributions.len() != sizes.len() {
+            return Err(UploadError::UnexpectedNumContributions {
+                expected: sizes.len(),
+                received: contributions.len(),
+            });
+        }
+    }
+    Ok(BatchContribution {
+        contributions,
+        ecdsa_signature,
+        entropy_attestation,
+    })
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for ContributionUpload
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Into<Bytes> + Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let boundary = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|content_type| content_type.starts_with("multipart/form-data"))
+            .map(multer::parse_boundary);
+        let boundary = match boundary {
+            Some(boundary) => boundary.map_err(|error| UploadError::from(error).into_response())?,
+            None => {
+                let Json(contribution) = Json::<BatchContribution>::from_request(req)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                return Ok(Self(contribution));
+            }
+        };
+        let sizes = req
+            .extensions()
+            .get::<Options>()
+            .map(|options| options.ceremony_sizes.sizes().to_vec());
+        let stream = BodyStream::from_request(req)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        read_parts(multer::Multipart::new(stream, boundary), sizes.as_deref())
+            .await
+            .map(Self)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::CeremonySizes,
+        test_util::test_options,
+        tests::{test_transcript, valid_contribution},
+    };
+    use axum::body::Body;
+    use http::{Request, StatusCode};
+    use kzg_ceremony_crypto::BatchTranscript;
+    use std::fmt::Write;
+
+    fn multipart_request(parts: &[(&str, String)]) -> RequestParts<Body> {
+        let mut body = String::new();
+        for (name, content) in parts {
+            write!(
+                body,
+                "--boundary\r\nContent-Disposition: form-data; \
+                 name=\"{name}\"\r\n\r\n{content}\r\n"
+            )
+            .unwrap();
+        }
+        body.push_str("--boundary--\r\n");
+        let mut request = Request::post("/contribute")
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+            .body(Body::from(body))
+            .unwrap();
+        let mut options = test_options();
+        options.ceremony_sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        request.extensions_mut().insert(options);
+        RequestParts::new(request)
+    }
+
+    async fn error_code(response: Response) -> String {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice::<Value>(&body).unwrap()["code"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[tokio::test]
+    async fn reads_contribution_parts() {
+        let part = |contribution: &BatchContribution| {
+            (
+                "contribution",
+                serde_json::to_string(&contribution.contributions[0]).unwrap(),
+            )
+        };
+        let contribution = valid_contribution(&test_transcript(), 1);
+        let ContributionUpload(uploaded) =
+            ContributionUpload::from_request(&mut multipart_request(&[part(&contribution)]))
+                .await
+                .unwrap();
+        assert_eq!(uploaded, contribution);
+
+        let rejection_code = |parts: Vec<(&'static str, String)>| async move {
+            let rejection = ContributionUpload::from_request(&mut multipart_request(&parts))
+                .await
+                .unwrap_err();
+            assert_eq!(rejection.status(), StatusCode::BAD_REQUEST);
+            error_code(rejection).await
+        };
+        let larger = valid_contribution(&BatchTranscript::new(&[(8, 2)]), 1);
+        assert_eq!(
+            rejection_code(vec![part(&larger)]).await,
+            "UploadError::UnexpectedSize"
+        );
+        assert_eq!(
+            rejection_code(vec![part(&contribution), part(&contribution)]).await,
+            "UploadError::UnexpectedNumContributions"
+        );
+        assert_eq!(
+            rejection_code(vec![part(&contribution), ("signature", String::new())]).await,
+            "UploadError::UnexpectedPart"
+        );
+    }
+}
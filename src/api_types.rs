@@ -0,0 +1,413 @@
+//! Types of the public API, for clients of the sequencer. They only depend
+//! on serde, so that client generators can target them instead of the
+//! handlers.
+//!
+//! All error responses share the body [`ErrorResponse`]. Its `code` is one of
+//! [`ApiErrorCode`], which stay the same across releases.
+//!
+//! The requests and responses of the participant flow are `#[non_exhaustive]`.
+//! Fields added after they were first released are optional, so that clients
+//! keep working against older sequencers.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// The body of every error response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code:    ApiErrorCode,
+    /// Human readable description of the error. Not meant to be parsed.
+    pub message: String,
+    /// Additional data for some errors, e.g. `estimated_wait_seconds` for
+    /// `TryContributeError::AnotherContributionInProgress`. Empty otherwise.
+    #[serde(default)]
+    pub details: Map<String, Value>,
+}
+
+macro_rules! api_error_codes {
+    ($($variant:ident => $code:literal,)*) => {
+        /// Machine readable error codes. Clients should handle
+        /// [`ApiErrorCode::Unknown`], since servers may add new codes.
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum ApiErrorCode {
+            $(
+                #[doc = concat!("`", $code, "`")]
+                $variant,
+            )*
+            /// A code this version doesn't know about.
+            Unknown(String),
+        }
+
+        impl ApiErrorCode {
+            #[must_use]
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $code,)*
+                    Self::Unknown(code) => code,
+                }
+            }
+
+            #[must_use]
+            pub fn from_code(code: &str) -> Self {
+                match code {
+                    $($code => Self::$variant,)*
+                    _ => Self::Unknown(code.to_string()),
+                }
+            }
+        }
+    };
+}
+
+/// The contribution slot handed out to a participant. The slot id and the
+/// transcript hash must be passed along with the contribution, see
+/// [`ContributeQuery`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct Reservation {
+    pub slot_id:         String,
+    /// Unix timestamp after which the slot is given to someone else, by the
+    /// sequencer's clock. Prefer `seconds_left` if the local clock may be off.
+    pub deadline:        u64,
+    /// Seconds until the deadline when the slot was handed out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds_left:    Option<u64>,
+    /// Unix timestamp of the response, by the sequencer's clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_time:     Option<u64>,
+    pub transcript_hash: String,
+}
+
+/// Response of `/lobby/try_contribute` once the slot is granted, with the
+/// powers to contribute to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TryContributeResponse<C> {
+    pub reservation:  Reservation,
+    pub contribution: C,
+    /// Message from the operators to show.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announcement: Option<String>,
+}
+
+/// Response of `/lobby/ping`, `/lobby/join` and `/lobby/defer`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct PingResponse {
+    pub estimated_wait_seconds: u64,
+    /// Set when the participant gets the slot next, as the number of
+    /// participants still ahead of them. Clients can use this to download
+    /// the current powers before asking for the slot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_deck:                Option<usize>,
+    /// Seconds until the next ping is due.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ping_within_seconds:    Option<u64>,
+    /// Unix timestamp of the response, by the sequencer's clock.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_time:            Option<u64>,
+}
+
+/// Query of `/lobby/defer`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct DeferQuery {
+    /// How long to step away in seconds, capped at the configured maximum.
+    /// Zero ends a deferral early.
+    pub seconds: u64,
+}
+
+/// Query of `/contribute`, with the contribution as body.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ContributeQuery {
+    pub slot_id:         String,
+    /// Hash of the transcript the contribution was computed against, as
+    /// handed out with the slot.
+    pub transcript_hash: String,
+    /// Address of the key the participant will countersign the receipt
+    /// with, required by sequencers that confirm contributions. It is part
+    /// of the signed receipt, so anyone can check the countersignature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub countersigner:   Option<String>,
+}
+
+/// Query of `/contribute/extend`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ExtendQuery {
+    /// Requested extension in seconds, defaults to and is capped at the
+    /// configured maximum.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<u64>,
+}
+
+/// Response of `/contribute/extend`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ExtendResponse {
+    /// Unix timestamp of the new deadline, by the sequencer's clock.
+    pub deadline:     u64,
+    /// Seconds until the new deadline.
+    pub seconds_left: u64,
+    /// Unix timestamp of the response, by the sequencer's clock.
+    pub server_time:  u64,
+}
+
+/// Body of `/contribute/confirm`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ConfirmRequest {
+    /// The participant's `personal_sign` signature of the `receipt` returned
+    /// by `/contribute`, hex encoded. It has to be made with the key of the
+    /// `countersigner` address given to `/contribute`.
+    pub countersignature: String,
+}
+
+/// Proof that a participant's entry is a leaf of the tree over all entries
+/// of the current transcript, part of the bundle of `/receipt/mine`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct InclusionProof {
+    pub tree_size: usize,
+    pub root:      String,
+    pub path:      Vec<String>,
+}
+
+/// Root of the tree over the first `tree_size` entries of the transcript,
+/// as served at `/info/transcript_head`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TreeHead {
+    pub tree_size: usize,
+    pub root:      String,
+}
+
+api_error_codes! {
+    AuthLobbyIsFull => "AuthErrorPayload::LobbyIsFull",
+    AuthUserAlreadyContributed => "AuthErrorPayload::UserAlreadyContributed",
+    AuthTooManyFailedAttempts => "AuthErrorPayload::TooManyFailedAttempts",
+    AuthTooManySessions => "AuthErrorPayload::TooManySessions",
+    AuthInvalidAuthCode => "AuthErrorPayload::InvalidAuthCode",
+    AuthInvalidNonce => "AuthErrorPayload::InvalidNonce",
+    AuthFetchUserDataError => "AuthErrorPayload::FetchUserDataError",
+    AuthCouldNotExtractUserData => "AuthErrorPayload::CouldNotExtractUserData",
+    AuthUserCreatedAfterDeadline => "AuthErrorPayload::UserCreatedAfterDeadline",
+    AuthUnsupportedChain => "AuthErrorPayload::UnsupportedChain",
+    AuthPseudonymsDisabled => "AuthErrorPayload::PseudonymsDisabled",
+    AuthPasskeysDisabled => "AuthErrorPayload::PasskeysDisabled",
+    AuthProviderDisabled => "AuthErrorPayload::ProviderDisabled",
+    AuthInvalidPasskey => "AuthErrorPayload::InvalidPasskey",
+    AuthNotAllowed => "AuthErrorPayload::NotAllowed",
+    AuthInvalidCaptcha => "AuthErrorPayload::InvalidCaptcha",
+    AuthCaptchaUnavailable => "AuthErrorPayload::CaptchaUnavailable",
+    AuthInvitationRequired => "AuthErrorPayload::InvitationRequired",
+    AuthInvalidInvitation => "AuthErrorPayload::InvalidInvitation",
+
+    SessionInvalidSessionId => "SessionError::InvalidSessionId",
+    SessionFingerprintMismatch => "SessionError::FingerprintMismatch",
+
+    EmailUnknownSessionId => "EmailError::UnknownSessionId",
+    EmailDisabled => "EmailError::Disabled",
+    EmailInvalidAddress => "EmailError::InvalidAddress",
+    EmailAddressInUse => "EmailError::AddressInUse",
+    EmailUnknownCode => "EmailError::UnknownCode",
+    EmailWrongCode => "EmailError::WrongCode",
+    EmailDeliveryFailed => "EmailError::DeliveryFailed",
+    EmailResendTooSoon => "EmailError::ResendTooSoon",
+
+    AdminDisabled => "AdminError::Disabled",
+    AdminUnauthorized => "AdminError::Unauthorized",
+    AdminInvalidLobbyParams => "AdminError::InvalidLobbyParams",
+    AdminInvalidInvitationRequest => "AdminError::InvalidInvitationRequest",
+
+    ReceiptExportUnknownField => "ReceiptExportError::UnknownField",
+    ReceiptExportInvalidReceipt => "ReceiptExportError::InvalidReceipt",
+
+    LobbyUnknownSessionId => "TryContributeError::UnknownSessionId",
+    LobbyEvicted => "TryContributeError::Evicted",
+    LobbyRateLimited => "TryContributeError::RateLimited",
+    LobbyAnotherContributionInProgress => "TryContributeError::AnotherContributionInProgress",
+    LobbyIsFull => "TryContributeError::LobbyIsFull",
+    LobbyTokenExpired => "TryContributeError::LobbyTokenExpired",
+    LobbyNotAllowed => "TryContributeError::NotAllowed",
+    LobbyNotJoined => "TryContributeError::NotJoined",
+    LobbyEmailNotVerified => "TryContributeError::EmailNotVerified",
+    LobbyTooManyFailedAttempts => "TryContributeError::TooManyFailedAttempts",
+    LobbyCoolingDown => "TryContributeError::CoolingDown",
+    LobbyBeaconUnavailable => "TryContributeError::BeaconUnavailable",
+    LobbyCeremonyComplete => "TryContributeError::CeremonyComplete",
+    LobbyDeferralDisabled => "TryContributeError::DeferralDisabled",
+    LobbyAlreadyContributed => "TryContributeError::AlreadyContributed",
+
+    ContributeNotUsersTurn => "ContributeError::NotUsersTurn",
+    ContributeStaleSlot => "ContributeError::StaleSlot",
+    ContributeUnboundContribution => "ContributeError::UnboundContribution",
+    ContributeTranscriptMismatch => "ContributeError::TranscriptMismatch",
+    ContributeDuplicatePubkey => "ContributeError::DuplicatePubkey",
+    ContributeExtensionDisabled => "ContributeError::ExtensionDisabled",
+    ContributeAlreadyExtended => "ContributeError::AlreadyExtended",
+    ContributeNotAwaitingConfirmation => "ContributeError::NotAwaitingConfirmation",
+    ContributeInvalidCountersignature => "ContributeError::InvalidCountersignature",
+    ContributeInvalidCountersigner => "ContributeError::InvalidCountersigner",
+    ContributeProcessingFailed => "ContributeError::ProcessingFailed",
+
+    UploadInvalidMultipart => "UploadError::InvalidMultipart",
+    UploadUnexpectedPart => "UploadError::UnexpectedPart",
+    UploadInvalidPart => "UploadError::InvalidPart",
+    UploadUnexpectedSize => "UploadError::UnexpectedSize",
+    UploadUnexpectedNumContributions => "UploadError::UnexpectedNumContributions",
+
+    ClientVersionMissing => "ClientVersionError::Missing",
+    ClientVersionInvalid => "ClientVersionError::Invalid",
+    ClientVersionOutdated => "ClientVersionError::Outdated",
+    ClientVersionBlocked => "ClientVersionError::Blocked",
+
+    AbuseCheckRateLimited => "AbuseCheckError::RateLimited",
+    AbuseCheckMissingProofOfWork => "AbuseCheckError::MissingProofOfWork",
+    AbuseCheckInvalidProofOfWork => "AbuseCheckError::InvalidProofOfWork",
+    AbuseCheckMissingCaptcha => "AbuseCheckError::MissingCaptcha",
+    AbuseCheckInvalidCaptcha => "AbuseCheckError::InvalidCaptcha",
+    AbuseCheckCaptchaUnavailable => "AbuseCheckError::CaptchaUnavailable",
+
+    OverloadBusy => "OverloadError::Busy",
+
+    TimeoutTimedOut => "TimeoutError::TimedOut",
+
+    CeremoniesUnexpectedNumContributions => "CeremoniesError::UnexpectedNumContributions",
+    CeremoniesBeaconApplied => "CeremoniesError::BeaconApplied",
+    CeremoniesInvalidBeacon => "CeremoniesError::InvalidBeacon",
+    CeremoniesInvalidEntropyAttestation => "CeremoniesError::InvalidEntropyAttestation",
+
+    CeremonyUnsupportedNumG1Powers => "CeremonyError::UnsupportedNumG1Powers",
+    CeremonyUnsupportedNumG2Powers => "CeremonyError::UnsupportedNumG2Powers",
+    CeremonyUnexpectedNumG1Powers => "CeremonyError::UnexpectedNumG1Powers",
+    CeremonyUnexpectedNumG2Powers => "CeremonyError::UnexpectedNumG2Powers",
+    CeremonyInconsistentNumG1Powers => "CeremonyError::InconsistentNumG1Powers",
+    CeremonyInconsistentNumG2Powers => "CeremonyError::InconsistentNumG2Powers",
+    CeremonyUnsupportedMoreG2Powers => "CeremonyError::UnsupportedMoreG2Powers",
+    CeremonyInvalidG1Power => "CeremonyError::InvalidG1Power",
+    CeremonyInvalidG2Power => "CeremonyError::InvalidG2Power",
+    CeremonyParserError => "CeremonyError::ParserError",
+    CeremonyInvalidPubKey => "CeremonyError::InvalidPubKey",
+    CeremonyInvalidWitnessProduct => "CeremonyError::InvalidWitnessProduct",
+    CeremonyInvalidWitnessPubKey => "CeremonyError::InvalidWitnessPubKey",
+    CeremonyPubKeyPairingFailed => "CeremonyError::PubKeyPairingFailed",
+    CeremonyG1PairingFailed => "CeremonyError::G1PairingFailed",
+    CeremonyG2PairingFailed => "CeremonyError::G2PairingFailed",
+    CeremonyZeroPubkey => "CeremonyError::ZeroPubkey",
+    CeremonyZeroG1 => "CeremonyError::ZeroG1",
+    CeremonyZeroG2 => "CeremonyError::ZeroG2",
+    CeremonyInvalidG1FirstValue => "CeremonyError::InvalidG1FirstValue",
+    CeremonyInvalidG2FirstValue => "CeremonyError::InvalidG2FirstValue",
+    CeremonyInvalidG1One => "CeremonyError::InvalidG1One",
+    CeremonyInvalidG2One => "CeremonyError::InvalidG2One",
+    CeremonyInvalidG2Pubkey => "CeremonyError::InvalidG2Pubkey",
+    CeremonyDuplicateG1 => "CeremonyError::DuplicateG1",
+    CeremonyDuplicateG2 => "CeremonyError::DuplicateG2",
+    CeremonyContributionNoEntropy => "CeremonyError::ContributionNoEntropy",
+    CeremonyWitnessLengthMismatch => "CeremonyError::WitnessLengthMismatch",
+    CeremonyInvalidWitness => "CeremonyError::InvalidWitness",
+    CeremonyWitnessEntryMissing => "CeremonyError::WitnessEntryMissing",
+    CeremonyPubkeyNotIncluded => "CeremonyError::PubkeyNotIncluded",
+    CeremonyInvalidLagrangeBasis => "CeremonyError::InvalidLagrangeBasis",
+
+    SignatureCreation => "SignatureError::SignatureCreation",
+    SignatureInvalidToken => "SignatureError::InvalidToken",
+    SignatureInvalidSignature => "SignatureError::InvalidSignature",
+
+    ReceiptUnknownReceipt => "ReceiptError::UnknownReceipt",
+    SelectionUnknownSelection => "SelectionError::UnknownSelection",
+    TranscriptHeadTreeTooLarge => "TranscriptHeadError::TreeTooLarge",
+    CurrentStateUnknownCeremonySize => "CurrentStateError::UnknownCeremonySize",
+    CurrentStateInvalidPowersRange => "CurrentStateError::InvalidPowersRange",
+    WitnessSegmentInvalidRange => "WitnessSegmentError::InvalidRange",
+
+    StorageDatabaseError => "StorageError::DatabaseError",
+}
+
+impl Serialize for ApiErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiErrorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|code| Self::from_code(&code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{
+            shedding::OverloadError,
+            timeouts::TimeoutError,
+            v1::{auth::AuthErrorPayload, contribute::ContributeError, lobby::TryContributeError},
+        },
+        client_version::ClientVersionError,
+    };
+    use kzg_ceremony_crypto::{CeremoniesError, CeremonyError, ErrorCode};
+
+    #[test]
+    fn knows_server_error_codes() {
+        let codes = [
+            AuthErrorPayload::InvalidNonce.to_error_code(),
+            TryContributeError::AnotherContributionInProgress {
+                estimated_wait_seconds: 0,
+            }
+            .to_error_code(),
+            ContributeError::StaleSlot.to_error_code(),
+            ClientVersionError::Missing.to_error_code(),
+            OverloadError::Busy {
+                retry_after_seconds: 5,
+            }
+            .to_error_code(),
+            TimeoutError::TimedOut {
+                timeout_seconds: 15,
+            }
+            .to_error_code(),
+            CeremoniesError::InvalidCeremony(0, CeremonyError::G1PairingFailed).to_error_code(),
+            CeremoniesError::BeaconApplied.to_error_code(),
+        ];
+        for code in codes {
+            let parsed = ApiErrorCode::from_code(&code);
+            assert!(!matches!(parsed, ApiErrorCode::Unknown(_)), "{code}");
+            assert_eq!(parsed.as_str(), code);
+        }
+    }
+
+    #[test]
+    fn reads_responses_of_older_sequencers() {
+        let ping: PingResponse = serde_json::from_value(serde_json::json!({
+            "estimated_wait_seconds": 30,
+        }))
+        .unwrap();
+        assert_eq!(ping.ping_within_seconds, None);
+        assert_eq!(
+            serde_json::to_value(&ping).unwrap(),
+            serde_json::json!({ "estimated_wait_seconds": 30 })
+        );
+
+        let query: ExtendQuery = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(query, ExtendQuery::default());
+    }
+
+    #[test]
+    fn keeps_unknown_codes() {
+        let response: ErrorResponse = serde_json::from_value(serde_json::json!({
+            "code": "NewError::Something",
+            "message": "something new",
+        }))
+        .unwrap();
+        assert_eq!(
+            response.code,
+            ApiErrorCode::Unknown("NewError::Something".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(&response).unwrap()["code"],
+            "NewError::Something"
+        );
+    }
+}
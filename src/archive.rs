@@ -0,0 +1,257 @@
+//! Archives of the sequencer state, for moving a running ceremony to new
+//! infrastructure. An archive is a JSON file holding the transcript file as
+//! is, so it stays encrypted if it was, and a dump of the database. Every
+//! entry carries the SHA-256 hash of its contents. Sharded transcripts are
+//! archived whole, as plain JSON.
+//!
+//! Sessions and the lobby only live in memory, so they are not part of the
+//! archive. Participants sign in again after a migration.
+
+use crate::{
+    io::{decode_contents, persist_file, read_transcript},
+    storage::{PersistentStorage, StorageDump},
+    Options,
+};
+use chrono::Utc;
+use eyre::{ensure, eyre, WrapErr};
+use kzg_ceremony_crypto::BatchTranscript;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tracing::info;
+
+/// Version of the archive format. Archives of other versions are rejected.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const TRANSCRIPT_ENTRY: &str = "transcript";
+const DATABASE_ENTRY: &str = "database";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Archive {
+    version:    u32,
+    created_at: String,
+    entries:    Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    name:     String,
+    /// Hex encoded SHA-256 hash of the decoded contents.
+    sha256:   String,
+    /// Base64 encoded contents.
+    contents: String,
+}
+
+impl Entry {
+    fn new(name: &str, contents: &[u8]) -> Self {
+        Self {
+            name:     name.to_string(),
+            sha256:   hex::encode(Sha256::digest(contents)),
+            contents: base64::encode(contents),
+        }
+    }
+
+    fn decode(&self) -> eyre::Result<Vec<u8>> {
+        let contents = base64::decode(&self.contents)
+            .wrap_err_with(|| format!("Archive entry {} is not valid base64", self.name))?;
+        let hash = hex::encode(Sha256::digest(&contents));
+        ensure!(
+            hash == self.sha256,
+            "Archive entry {} is corrupted: expected hash {}, but got {hash}",
+            self.name,
+            self.sha256
+        );
+        Ok(contents)
+    }
+}
+
+impl Archive {
+    fn entry(&self, name: &str) -> eyre::Result<Vec<u8>> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| eyre!("Archive has no {name} entry"))?
+            .decode()
+    }
+}
+
+/// Writes the transcript file and the database into an archive.
+///
+/// # Errors
+///
+/// - when the transcript or the database cannot be read.
+/// - when the archive cannot be written.
+pub async fn export_state(
+    options: &Options,
+    storage: &PersistentStorage,
+    archive_path: &Path,
+) -> eyre::Result<()> {
+    let transcript = if options.io.transcript_shards {
+        serde_json::to_vec_pretty(
+            &read_transcript(options.transcript_file.clone(), &options.io).await?,
+        )?
+    } else {
+        tokio::fs::read(&options.transcript_file)
+            .await
+            .wrap_err_with(|| format!("Cannot read {:?}", options.transcript_file))?
+    };
+    let database = serde_json::to_vec(&storage.dump().await?)?;
+
+    let archive = Archive {
+        version:    ARCHIVE_VERSION,
+        created_at: Utc::now().to_rfc3339(),
+        entries:    vec![
+            Entry::new(TRANSCRIPT_ENTRY, &transcript),
+            Entry::new(DATABASE_ENTRY, &database),
+        ],
+    };
+    tokio::fs::write(archive_path, serde_json::to_vec_pretty(&archive)?)
+        .await
+        .wrap_err_with(|| format!("Cannot write {archive_path:?}"))?;
+    info!(?archive_path, "Exported sequencer state");
+    Ok(())
+}
+
+/// Restores the transcript file and the database from an archive. Refuses
+/// to overwrite an existing transcript or a database that is in use.
+///
+/// # Errors
+///
+/// - when the archive has another version, or an entry is missing or corrupted.
+/// - when the transcript cannot be decrypted, or is not a valid transcript of
+///   the configured sizes.
+/// - when there already is a transcript or data in the database.
+pub async fn import_state(
+    options: &Options,
+    storage: &PersistentStorage,
+    archive_path: &Path,
+) -> eyre::Result<()> {
+    let archive: Archive = serde_json::from_slice(
+        &tokio::fs::read(archive_path)
+            .await
+            .wrap_err_with(|| format!("Cannot read {archive_path:?}"))?,
+    )
+    .wrap_err("Not a sequencer state archive")?;
+    ensure!(
+        archive.version == ARCHIVE_VERSION,
+        "Unsupported archive version {}, expected {ARCHIVE_VERSION}",
+        archive.version
+    );
+
+    let transcript_contents = archive.entry(TRANSCRIPT_ENTRY)?;
+    let transcript: BatchTranscript =
+        serde_json::from_slice(&decode_contents(transcript_contents.clone(), &options.io)?)
+            .wrap_err("Archived transcript is not a valid transcript")?;
+    options
+        .ceremony_sizes
+        .validate_batch_transcript(&transcript)?;
+    let dump: StorageDump = serde_json::from_slice(&archive.entry(DATABASE_ENTRY)?)
+        .wrap_err("Archived database is not a valid dump")?;
+
+    ensure!(
+        !options.transcript_file.exists(),
+        "Transcript {:?} already exists",
+        options.transcript_file
+    );
+    ensure!(
+        storage.dump().await? == StorageDump::default(),
+        "Database is not empty"
+    );
+
+    storage.restore(&dump).await?;
+    tokio::fs::write(&options.transcript_in_progress_file, transcript_contents).await?;
+    let (work_path, target_path) = (
+        options.transcript_in_progress_file.clone(),
+        options.transcript_file.clone(),
+    );
+    tokio::task::spawn_blocking(move || persist_file(&work_path, &target_path)).await??;
+    info!(
+        ?archive_path,
+        participants = transcript.num_participants(),
+        created_at = %archive.created_at,
+        "Imported sequencer state"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::{read_transcript, CeremonySizes},
+        storage::{storage_client, StoredReceipt},
+        test_util::test_options,
+        tests::test_transcript,
+    };
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn exports_and_imports_state() {
+        let dir = tempdir().unwrap();
+        let archive_path = dir.path().join("state.json");
+        let mut source = test_options();
+        source.transcript_file = dir.path().join("source.json");
+        let mut target = test_options();
+        target.transcript_file = dir.path().join("target.json");
+        target.transcript_in_progress_file = dir.path().join("target.json.next");
+        for options in [&mut source, &mut target] {
+            options.ceremony_sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        }
+
+        fs::write(
+            &source.transcript_file,
+            serde_json::to_vec_pretty(&test_transcript()).unwrap(),
+        )
+        .unwrap();
+        let source_storage = storage_client(&source.storage).await.unwrap();
+        source_storage.insert_contributor("git|1234").await.unwrap();
+        source_storage
+            .insert_failed_attempt("git|1234", "aborted")
+            .await
+            .unwrap();
+        source_storage
+            .insert_receipt("hash", &StoredReceipt {
+                participant:      1,
+                receipt:          "receipt".to_string(),
+                signature:        "signature".to_string(),
+                bls_signature:    None,
+                countersignature: None,
+            })
+            .await
+            .unwrap();
+        export_state(&source, &source_storage, &archive_path)
+            .await
+            .unwrap();
+
+        let target_storage = storage_client(&target.storage).await.unwrap();
+        import_state(&target, &target_storage, &archive_path)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_transcript(target.transcript_file.clone(), &target.io)
+                .await
+                .unwrap(),
+            test_transcript()
+        );
+        assert_eq!(
+            target_storage.dump().await.unwrap(),
+            source_storage.dump().await.unwrap()
+        );
+
+        // Importing again would overwrite the ceremony
+        assert!(import_state(&target, &target_storage, &archive_path)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_corrupted_archive() {
+        let entry = Entry {
+            sha256: "0".repeat(64),
+            ..Entry::new(TRANSCRIPT_ENTRY, b"{}")
+        };
+        assert!(entry.decode().is_err());
+        assert!(Entry::new(TRANSCRIPT_ENTRY, b"{}").decode().is_ok());
+    }
+}
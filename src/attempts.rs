@@ -0,0 +1,117 @@
+//! Limits on contribution attempts that did not make it into the transcript,
+//! so that a broken or malicious client can't hold up the ceremony by taking
+//! the contribution slot over and over. Attempts are recorded per identity in
+//! the database, so the limits also hold across sessions.
+
+use crate::{lobby::duration_from_str, storage::FailedAttempts};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use std::time::Duration;
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Number of failed contribution attempts after which an identity may no
+    /// longer take the contribution slot. Invalid contributions, aborts and
+    /// slots that ran out all count as failed. Set to 0 for no limit.
+    #[clap(long, env, default_value = "0")]
+    pub max_failed_attempts: usize,
+
+    /// How long an identity has to wait after a failed attempt before it may
+    /// take the contribution slot again, in seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="0")]
+    pub failed_attempt_cooldown: Duration,
+}
+
+/// How a contribution attempt ended without a contribution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum AttemptOutcome {
+    /// The contribution failed verification or wasn't bound to the identity.
+    Rejected,
+    /// The participant gave the slot back.
+    Aborted,
+    /// The participant didn't contribute before the compute deadline.
+    Expired,
+}
+
+impl AttemptOutcome {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.into()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum AttemptError {
+    #[error("too many failed contribution attempts")]
+    TooManyFailedAttempts,
+    #[error("failed contribution attempt, retry later")]
+    CoolingDown { retry_after: Duration },
+}
+
+impl Options {
+    /// Whether an identity with these failed attempts may take the
+    /// contribution slot at `now`.
+    pub fn check(&self, attempts: &FailedAttempts, now: DateTime<Utc>) -> Result<(), AttemptError> {
+        if self.max_failed_attempts > 0 && attempts.count >= self.max_failed_attempts {
+            return Err(AttemptError::TooManyFailedAttempts);
+        }
+        if let Some(last) = attempts.last_at {
+            let since = (now - last).to_std().unwrap_or_default();
+            if since < self.failed_attempt_cooldown {
+                return Err(AttemptError::CoolingDown {
+                    retry_after: self.failed_attempt_cooldown - since,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as ChronoDuration;
+
+    #[test]
+    fn enforces_limits() {
+        let options = Options {
+            max_failed_attempts:     3,
+            failed_attempt_cooldown: Duration::from_secs(60),
+        };
+        let now = Utc::now();
+        assert_eq!(options.check(&FailedAttempts::default(), now), Ok(()));
+
+        let attempts = FailedAttempts {
+            count:   1,
+            last_at: Some(now - ChronoDuration::seconds(20)),
+        };
+        assert_eq!(
+            options.check(&attempts, now),
+            Err(AttemptError::CoolingDown {
+                retry_after: Duration::from_secs(40),
+            })
+        );
+        assert_eq!(
+            options.check(&attempts, now + ChronoDuration::seconds(40)),
+            Ok(())
+        );
+
+        let attempts = FailedAttempts {
+            count:   3,
+            last_at: Some(now - ChronoDuration::hours(1)),
+        };
+        assert_eq!(
+            options.check(&attempts, now),
+            Err(AttemptError::TooManyFailedAttempts)
+        );
+        let unlimited = Options {
+            max_failed_attempts:     0,
+            failed_attempt_cooldown: Duration::ZERO,
+        };
+        assert_eq!(unlimited.check(&attempts, now), Ok(()));
+    }
+}
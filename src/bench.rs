@@ -0,0 +1,149 @@
+//! Timings of the verification engines on the configured ceremony sizes, to
+//! size the hardware of the sequencer before the ceremony starts. Run as the
+//! `bench-engine` command, which needs the `bench` feature.
+
+use crate::io::CeremonySizes;
+use clap::ValueEnum;
+use kzg_ceremony_crypto::{
+    bench::rand_entropy, Arkworks, BatchTranscript, Both, Engine, Identity, BLST,
+};
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BenchEngine {
+    Arkworks,
+    Blst,
+    /// Both engines, which is what the sequencer verifies with.
+    Both,
+}
+
+/// How long the steps of one contribution took.
+#[derive(Clone, Debug)]
+struct Run {
+    contribute:      Duration,
+    /// Per ceremony.
+    subgroup_checks: Vec<Duration>,
+    /// Per ceremony.
+    pairing_checks:  Vec<Duration>,
+    verify:          Duration,
+    add:             Duration,
+}
+
+fn run<E: Engine>(transcript: &BatchTranscript) -> eyre::Result<Run> {
+    let mut contribution = transcript.contribution();
+    let started = Instant::now();
+    contribution.add_entropy::<E>(&rand_entropy(), &Identity::None)?;
+    let contribute = started.elapsed();
+
+    let started = Instant::now();
+    let timings = transcript.verify_timed::<E>(&contribution)?;
+    let verify = started.elapsed();
+
+    let mut next = transcript.clone();
+    let started = Instant::now();
+    next.add::<E>(contribution, Identity::None);
+    let add = started.elapsed();
+
+    Ok(Run {
+        contribute,
+        subgroup_checks: timings
+            .iter()
+            .map(|timing| timing.subgroup_checks)
+            .collect(),
+        pairing_checks: timings.iter().map(|timing| timing.pairing_checks).collect(),
+        verify,
+        add,
+    })
+}
+
+fn average(durations: impl Iterator<Item = Duration>, count: usize) -> Duration {
+    durations.sum::<Duration>() / u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+fn bench<E: Engine>(
+    report: &mut String,
+    name: &str,
+    sizes: &CeremonySizes,
+    iterations: usize,
+) -> eyre::Result<()> {
+    // The powers of a new transcript are trivial, so verify against one with
+    // a contribution, as in the benchmarks of the crypto crate
+    let mut transcript = BatchTranscript::new(sizes.sizes());
+    let mut contribution = transcript.contribution();
+    contribution.add_entropy::<E>(&rand_entropy(), &Identity::None)?;
+    transcript.verify_add::<E>(contribution, Identity::None)?;
+
+    let iterations = iterations.max(1);
+    let runs = (0..iterations)
+        .map(|_| run::<E>(&transcript))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let _ = writeln!(report, "{name}, average of {iterations} contributions:");
+    for (index, (num_g1, num_g2)) in sizes.sizes().iter().enumerate() {
+        let _ = writeln!(
+            report,
+            "  ceremony {num_g1},{num_g2}: subgroup checks {:.3?}, pairing checks {:.3?}",
+            average(
+                runs.iter().map(|run| run.subgroup_checks[index]),
+                iterations
+            ),
+            average(runs.iter().map(|run| run.pairing_checks[index]), iterations),
+        );
+    }
+    let _ = writeln!(
+        report,
+        "  contribute {:.3?}, verify {:.3?}, add {:.3?}",
+        average(runs.iter().map(|run| run.contribute), iterations),
+        average(runs.iter().map(|run| run.verify), iterations),
+        average(runs.iter().map(|run| run.add), iterations),
+    );
+    Ok(())
+}
+
+/// Contributes to, verifies and adds `iterations` contributions to a
+/// transcript of `sizes` with each of the engines, or with the one of the
+/// sequencer if none are given. Returns the average timings as text.
+///
+/// # Errors
+///
+/// Returns an error if a contribution can't be computed or is rejected.
+pub fn bench_engines(
+    sizes: &CeremonySizes,
+    engines: &[BenchEngine],
+    iterations: usize,
+) -> eyre::Result<String> {
+    let engines = if engines.is_empty() {
+        &[BenchEngine::Both]
+    } else {
+        engines
+    };
+    let mut report = String::new();
+    for engine in engines {
+        match engine {
+            BenchEngine::Arkworks => bench::<Arkworks>(&mut report, "arkworks", sizes, iterations)?,
+            BenchEngine::Blst => bench::<BLST>(&mut report, "blst", sizes, iterations)?,
+            BenchEngine::Both => {
+                bench::<Both<Arkworks, BLST>>(&mut report, "both", sizes, iterations)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_ceremony() {
+        let sizes = CeremonySizes::parse_from_cmd("4,2:8,3").unwrap();
+        let report = bench_engines(&sizes, &[BenchEngine::Arkworks, BenchEngine::Blst], 1).unwrap();
+        assert!(report.starts_with("arkworks, average of 1 contributions:\n"));
+        assert!(report.contains("\nblst, "));
+        assert_eq!(report.matches("ceremony 4,2:").count(), 2);
+        assert_eq!(report.matches("ceremony 8,3:").count(), 2);
+    }
+}
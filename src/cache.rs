@@ -0,0 +1,120 @@
+//! Short lived caches for responses that many clients poll, so that polling
+//! doesn't contend for the transcript and lobby locks.
+
+use arc_swap::ArcSwapOption;
+use axum::body::Bytes;
+use clap::Parser;
+use std::{future::Future, num::ParseIntError, str::FromStr, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+
+pub(crate) fn duration_from_millis(value: &str) -> Result<Duration, ParseIntError> {
+    Ok(Duration::from_millis(u64::from_str(value)?))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// How long responses of `/info/status` and of `/info/current_state` with
+    /// only `witness_only` set are cached, in milliseconds. Set to 0 to
+    /// disable caching.
+    #[clap(long, env, value_parser=duration_from_millis, default_value="500")]
+    pub info_cache_ttl: Duration,
+}
+
+/// A cached response body. Reading a fresh body doesn't take any locks.
+pub struct ResponseCache {
+    ttl:       Duration,
+    entry:     ArcSwapOption<(Instant, Bytes)>,
+    rendering: Mutex<()>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: ArcSwapOption::empty(),
+            rendering: Mutex::new(()),
+        }
+    }
+
+    fn fresh(&self) -> Option<Bytes> {
+        let entry = self.entry.load();
+        entry
+            .as_ref()
+            .filter(|entry| entry.0.elapsed() < self.ttl)
+            .map(|entry| entry.1.clone())
+    }
+
+    /// Returns the cached body, or renders a new one once it is older than
+    /// the ttl. Only one caller renders at a time, the others wait for its
+    /// result. Failed renders are not cached.
+    pub async fn get<E, F, Fut>(&self, render: F) -> Result<Bytes, E>
+    where
+        F: FnOnce() -> Fut + Send,
+        Fut: Future<Output = Result<Bytes, E>> + Send,
+    {
+        if let Some(body) = self.fresh() {
+            return Ok(body);
+        }
+        let _rendering = self.rendering.lock().await;
+        if let Some(body) = self.fresh() {
+            return Ok(body);
+        }
+        let body = render().await?;
+        self.entry
+            .store(Some(Arc::new((Instant::now(), body.clone()))));
+        Ok(body)
+    }
+}
+
+pub struct InfoCache {
+    pub status:        ResponseCache,
+    pub witness_state: ResponseCache,
+}
+
+pub type SharedInfoCache = Arc<InfoCache>;
+
+impl InfoCache {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            status:        ResponseCache::new(options.info_cache_ttl),
+            witness_state: ResponseCache::new(options.info_cache_ttl),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn renders_once_per_ttl() {
+        tokio::time::pause();
+        let cache = ResponseCache::new(Duration::from_millis(500));
+        let render =
+            |body: &'static str| move || async move { Ok::<_, Infallible>(Bytes::from(body)) };
+
+        assert_eq!(cache.get(render("first")).await.unwrap(), "first");
+        tokio::time::advance(Duration::from_millis(400)).await;
+        assert_eq!(cache.get(render("second")).await.unwrap(), "first");
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(cache.get(render("third")).await.unwrap(), "third");
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_errors() {
+        let cache = ResponseCache::new(Duration::from_secs(1));
+        assert!(cache
+            .get(|| async { Err::<Bytes, _>("failed") })
+            .await
+            .is_err());
+        assert_eq!(
+            cache
+                .get(|| async { Ok::<_, Infallible>(Bytes::from("ok")) })
+                .await
+                .unwrap(),
+            "ok"
+        );
+    }
+}
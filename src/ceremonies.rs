@@ -0,0 +1,185 @@
+//! Hosting of additional, independent ceremonies next to the main one.
+
+use crate::{reporting, webhooks, Options};
+use clap::{Arg, CommandFactory, Parser};
+use eyre::{ensure, eyre, Result as EyreResult, WrapErr};
+use std::{fs, iter, path::PathBuf};
+
+/// An additional ceremony served under `/ceremony/NAME/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CeremonyConfig {
+    pub name:      String,
+    pub args_file: PathBuf,
+}
+
+impl CeremonyConfig {
+    /// Parses a ceremony description of the form `NAME=ARGS_FILE`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the description is malformed, or the name contains
+    /// characters other than ASCII alphanumerics, `-` and `_`.
+    pub fn parse_from_cmd(cmd: &str) -> eyre::Result<Self> {
+        let (name, args_file) = cmd
+            .split_once('=')
+            .ok_or_else(|| eyre!("Invalid ceremony description {cmd}, expected NAME=ARGS_FILE"))?;
+        ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Invalid ceremony name {name}"
+        );
+        Ok(Self {
+            name:      name.to_string(),
+            args_file: args_file.into(),
+        })
+    }
+
+    /// Reads the options of this ceremony from its arguments file, which
+    /// contains one command line argument per line. Empty lines and lines
+    /// starting with `#` are skipped. Arguments missing from the file fall
+    /// back to environment variables and defaults, as for the main ceremony.
+    ///
+    /// Nested ceremonies and commands in the file are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed, if it sets
+    /// error reporting or webhook options, which only the main ceremony can
+    /// set, or if the ceremony shares its transcript files or database with
+    /// the parent.
+    pub fn load(&self, parent: &Options) -> EyreResult<Options> {
+        let contents = fs::read_to_string(&self.args_file)
+            .wrap_err_with(|| format!("Cannot read arguments of ceremony {}", self.name))?;
+        let args = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect::<Vec<_>>();
+
+        // Reporting, webhooks and the verification engines are set up once for
+        // the whole process
+        let process_wide = process_wide_flags();
+        if let Some(arg) = args.iter().find(|arg| {
+            let flag = arg.split('=').next().unwrap_or_default();
+            process_wide.iter().any(|process_wide| process_wide == flag)
+        }) {
+            return Err(eyre!(
+                "Ceremony {} can't set {arg}, it is shared with the main ceremony",
+                self.name
+            ));
+        }
+
+        let mut options = Options::try_parse_from(iter::once("kzg-ceremony-sequencer").chain(args))
+            .wrap_err_with(|| format!("Invalid arguments for ceremony {}", self.name))?;
+
+        ensure!(
+            options.transcript_file != parent.transcript_file
+                && options.transcript_in_progress_file != parent.transcript_in_progress_file,
+            "Ceremony {} must use its own transcript files",
+            self.name
+        );
+        ensure!(
+            options.storage.database_url != parent.storage.database_url,
+            "Ceremony {} must use its own database",
+            self.name
+        );
+
+        options.server = parent.server.join(&format!("ceremony/{}/", self.name))?;
+        options.ceremonies = vec![];
+        options.command = None;
+        Ok(options)
+    }
+}
+
+/// Flags of the options that apply to the whole process, and so only to the
+/// main ceremony.
+fn process_wide_flags() -> Vec<String> {
+    [reporting::Options::command(), webhooks::Options::command()]
+        .iter()
+        .flat_map(|command| command.get_arguments().filter_map(Arg::get_long))
+        .chain(["precompute-memory-bytes", "batch-security-bits"])
+        .map(|long| format!("--{long}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io::CeremonySizes, test_util::test_options};
+    use tempfile::tempdir;
+
+    #[test]
+    fn parses_ceremony_description() {
+        let config = CeremonyConfig::parse_from_cmd("small-test=./small.args").unwrap();
+        assert_eq!(config.name, "small-test");
+        assert_eq!(config.args_file, PathBuf::from("./small.args"));
+
+        assert!(CeremonyConfig::parse_from_cmd("small.args").is_err());
+        assert!(CeremonyConfig::parse_from_cmd("=small.args").is_err());
+        assert!(CeremonyConfig::parse_from_cmd("../x=small.args").is_err());
+    }
+
+    #[test]
+    fn loads_ceremony_options() {
+        let dir = tempdir().unwrap();
+        let args_file = dir.path().join("small.args");
+        let write_args = |transcript: &str| {
+            let args: [&str; 12] = [
+                "# A small ceremony",
+                "--gh-client-secret=INVALID",
+                "--gh-client-id=INVALID",
+                "--eth-rpc-url=INVALID",
+                "--eth-client-secret=INVALID",
+                "--eth-client-id=INVALID",
+                "--database-url=sqlite://small.db",
+                "",
+                &format!("--transcript-file={transcript}"),
+                &format!("--transcript-in-progress-file={transcript}.next"),
+                "--ceremony-sizes",
+                "4,2",
+            ];
+            fs::write(&args_file, args.join("\n")).unwrap();
+        };
+        let config = CeremonyConfig {
+            name:      "small".to_string(),
+            args_file: args_file.clone(),
+        };
+        let parent = test_options();
+
+        write_args("./small.json");
+        let options = config.load(&parent).unwrap();
+        assert_eq!(options.transcript_file, PathBuf::from("./small.json"));
+        assert_eq!(
+            options.ceremony_sizes,
+            CeremonySizes::parse_from_cmd("4,2").unwrap()
+        );
+        assert_eq!(
+            options.server.as_str(),
+            "http://127.0.0.1:3000/ceremony/small/"
+        );
+
+        write_args("./transcript.json");
+        assert!(config.load(&parent).is_err());
+
+        // Webhooks are only set up for the main ceremony
+        write_args("./small.json");
+        let mut contents = fs::read_to_string(&args_file).unwrap();
+        contents.push_str("\n--error-webhook-url=http://127.0.0.1/errors");
+        fs::write(&args_file, contents).unwrap();
+        let error = config.load(&parent).unwrap_err();
+        assert!(error.to_string().contains("--error-webhook-url"), "{error}");
+
+        // So are the verification engines
+        write_args("./small.json");
+        let mut contents = fs::read_to_string(&args_file).unwrap();
+        contents.push_str("\n--batch-security-bits=64");
+        fs::write(&args_file, contents).unwrap();
+        let error = config.load(&parent).unwrap_err();
+        assert!(
+            error.to_string().contains("--batch-security-bits"),
+            "{error}"
+        );
+    }
+}
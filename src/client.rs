@@ -0,0 +1,424 @@
+//! Typed client for the participant flow, for contribution tools that talk
+//! to a sequencer. Enabled with the `client` feature.
+//!
+//! [`Client::auth_links`] returns the URLs to sign in with. Signing in ends
+//! with a session id, which is passed to [`Client::with_session`]. Then
+//! [`Client::try_contribute`] is polled until the slot is handed out along
+//! with the powers to contribute to, and the contribution computed from them
+//! is uploaded with [`Client::contribute`]. Sequencers that require
+//! confirmation hold the contribution back until its receipt is
+//! countersigned with [`Client::confirm`]. [`Client::receipt`] fetches the
+//! receipt bundle afterwards.
+//!
+//! The response types are `#[non_exhaustive]`, so that the fields the
+//! sequencer adds over time don't break clients. Those shared with the
+//! sequencer are in [`crate::api_types`].
+
+use crate::{
+    api_types::{
+        ApiErrorCode, ConfirmRequest, ContributeQuery, ErrorResponse, PingResponse,
+        TryContributeResponse,
+    },
+    client_version::CLIENT_VERSION_HEADER,
+    clock::REQUEST_TIME_HEADER,
+};
+use chrono::Utc;
+use kzg_ceremony_crypto::{BatchContribution, BatchTranscript};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+pub use crate::api_types::{InclusionProof, Reservation};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("sequencer responded with {status}: {}", .error.message)]
+    Api {
+        status: StatusCode,
+        error:  ErrorResponse,
+    },
+    #[error("unexpected response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("invalid url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("no session, sign in first")]
+    NoSession,
+}
+
+impl ClientError {
+    /// The error code of the sequencer, if it responded with an error.
+    #[must_use]
+    pub const fn code(&self) -> Option<&ApiErrorCode> {
+        match self {
+            Self::Api { error, .. } => Some(&error.code),
+            _ => None,
+        }
+    }
+}
+
+/// Options for signing in, see `/auth/request_link`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct AuthLinkRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_to:   Option<String>,
+    pub pseudonymous:  bool,
+    /// Coarse region to be counted in the region stats, e.g. `europe`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region:        Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha_token: Option<String>,
+}
+
+/// The URLs to sign in with each provider.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct AuthLinks {
+    pub eth_auth_url:     String,
+    pub github_auth_url:  String,
+    #[serde(default)]
+    pub twitter_auth_url: Option<String>,
+    #[serde(default)]
+    pub discord_auth_url: Option<String>,
+}
+
+/// A granted slot, with the powers to contribute to.
+pub type Slot = TryContributeResponse<BatchContribution>;
+
+/// The answer to polling the lobby.
+#[derive(Clone, Debug)]
+pub enum LobbyPoll {
+    /// The slot is ours.
+    Slot(Box<Slot>),
+    /// Someone else holds the slot. Poll again after the lobby checkin
+    /// frequency at the earliest, to not be rate limited.
+    Waiting { estimated_wait: Duration },
+}
+
+/// The signed receipt returned for an accepted contribution.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct ContributionReceipt {
+    pub receipt:       String,
+    pub signature:     String,
+    /// Only there if the sequencer signs receipts with BLS as well.
+    #[serde(default)]
+    pub bls_signature: Option<String>,
+    /// The receipt as EIP-712 typed data, with its signature.
+    pub eip712:        Value,
+    #[serde(default)]
+    pub share:         Option<Value>,
+    /// Set while the contribution waits for [`Client::confirm`], the unix
+    /// timestamp it has to be confirmed by.
+    #[serde(default)]
+    pub confirm_by:    Option<u64>,
+}
+
+/// Everything needed to verify a contribution without the sequencer, see
+/// `/receipt/mine`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct ReceiptBundle {
+    pub receipt:              String,
+    pub signature:            String,
+    pub sequencer_address:    String,
+    #[serde(default)]
+    pub bls_signature:        Option<String>,
+    #[serde(default)]
+    pub sequencer_bls_pubkey: Option<String>,
+    #[serde(default)]
+    pub countersignature:     Option<String>,
+    pub entry:                Value,
+    pub transcript_hash:      String,
+    pub inclusion_proof:      InclusionProof,
+    /// Missing from older sequencers.
+    #[serde(default)]
+    pub verification_url:     Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    http:           reqwest::Client,
+    base:           Url,
+    session_id:     Option<String>,
+    client_version: Option<String>,
+    countersigner:  Option<String>,
+}
+
+impl Client {
+    /// A client for the sequencer at `base`, e.g.
+    /// `https://seq.ceremony.ethereum.org/`.
+    #[must_use]
+    pub fn new(base: Url) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base,
+            session_id: None,
+            client_version: None,
+            countersigner: None,
+        }
+    }
+
+    /// Uses the session id obtained by signing in.
+    #[must_use]
+    pub fn with_session(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    /// Sends the semantic version of the contribution tool along, for
+    /// sequencers that require a minimum version.
+    #[must_use]
+    pub fn with_client_version(mut self, version: impl Into<String>) -> Self {
+        self.client_version = Some(version.into());
+        self
+    }
+
+    /// Names the address whose key countersigns the receipt, for sequencers
+    /// that require confirmation, see [`Client::confirm`].
+    #[must_use]
+    pub fn with_countersigner(mut self, address: impl Into<String>) -> Self {
+        self.countersigner = Some(address.into());
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, ClientError> {
+        // Lets the sequencer tell how far our clock is off
+        let now = Utc::now();
+        let mut request = self.http.request(method, self.base.join(path)?).header(
+            REQUEST_TIME_HEADER,
+            format!("{}.{:03}", now.timestamp(), now.timestamp_subsec_millis()),
+        );
+        if let Some(version) = &self.client_version {
+            request = request.header(CLIENT_VERSION_HEADER, version);
+        }
+        Ok(request)
+    }
+
+    fn session_request(&self, method: Method, path: &str) -> Result<RequestBuilder, ClientError> {
+        let session_id = self.session_id.as_ref().ok_or(ClientError::NoSession)?;
+        Ok(self.request(method, path)?.bearer_auth(session_id))
+    }
+
+    /// The URLs to sign in with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the sequencer refuses it,
+    /// e.g. because the lobby is full.
+    pub async fn auth_links(&self, request: &AuthLinkRequest) -> Result<AuthLinks, ClientError> {
+        let response = self
+            .request(Method::GET, "auth/request_link")?
+            .query(request)
+            .send()
+            .await?;
+        decode(response.status(), &response.bytes().await?)
+    }
+
+    /// Keeps the session alive, returning the estimated wait for the slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the session is unknown.
+    pub async fn ping(&self) -> Result<Duration, ClientError> {
+        let response = self
+            .session_request(Method::POST, "lobby/ping")?
+            .send()
+            .await?;
+        let ping: PingResponse = decode(response.status(), &response.bytes().await?)?;
+        Ok(Duration::from_secs(ping.estimated_wait_seconds))
+    }
+
+    /// Asks for the contribution slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the sequencer refuses it,
+    /// e.g. because it was polled too often.
+    pub async fn try_contribute(&self) -> Result<LobbyPoll, ClientError> {
+        let response = self
+            .session_request(Method::POST, "lobby/try_contribute")?
+            .send()
+            .await?;
+        lobby_poll(response.status(), &response.bytes().await?)
+    }
+
+    /// The current transcript.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub async fn current_state(&self) -> Result<BatchTranscript, ClientError> {
+        let response = self
+            .request(Method::GET, "info/current_state")?
+            .send()
+            .await?;
+        decode(response.status(), &response.bytes().await?)
+    }
+
+    /// Uploads the contribution for the reserved slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the contribution is
+    /// rejected.
+    pub async fn contribute(
+        &self,
+        reservation: &Reservation,
+        contribution: &BatchContribution,
+    ) -> Result<ContributionReceipt, ClientError> {
+        let response = self
+            .session_request(Method::POST, "contribute")?
+            .query(&ContributeQuery {
+                slot_id:         reservation.slot_id.clone(),
+                transcript_hash: reservation.transcript_hash.clone(),
+                countersigner:   self.countersigner.clone(),
+            })
+            .json(contribution)
+            .send()
+            .await?;
+        decode(response.status(), &response.bytes().await?)
+    }
+
+    /// Confirms the contribution held back by the sequencer with the
+    /// `personal_sign` signature of its `receipt` by the key named with
+    /// [`Client::with_countersigner`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the countersignature is
+    /// rejected.
+    pub async fn confirm(
+        &self,
+        countersignature: &str,
+    ) -> Result<ContributionReceipt, ClientError> {
+        let response = self
+            .session_request(Method::POST, "contribute/confirm")?
+            .json(&ConfirmRequest {
+                countersignature: countersignature.to_owned(),
+            })
+            .send()
+            .await?;
+        decode(response.status(), &response.bytes().await?)
+    }
+
+    /// Gives the reserved slot back.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the slot isn't ours.
+    pub async fn abort(&self) -> Result<(), ClientError> {
+        let response = self
+            .session_request(Method::POST, "contribute/abort")?
+            .send()
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        Err(api_error(status, &response.bytes().await?))
+    }
+
+    /// The receipt bundle of the contribution made in this session.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or there is no receipt yet.
+    pub async fn receipt(&self) -> Result<ReceiptBundle, ClientError> {
+        let response = self
+            .session_request(Method::GET, "receipt/mine")?
+            .send()
+            .await?;
+        decode(response.status(), &response.bytes().await?)
+    }
+}
+
+fn api_error(status: StatusCode, body: &[u8]) -> ClientError {
+    match serde_json::from_slice(body) {
+        Ok(error) => ClientError::Api { status, error },
+        Err(error) => ClientError::Decode(error),
+    }
+}
+
+fn decode<T: DeserializeOwned>(status: StatusCode, body: &[u8]) -> Result<T, ClientError> {
+    if status.is_success() {
+        Ok(serde_json::from_slice(body)?)
+    } else {
+        Err(api_error(status, body))
+    }
+}
+
+/// The sequencer answers with an error body, but a success status, while
+/// someone else holds the slot.
+fn lobby_poll(status: StatusCode, body: &[u8]) -> Result<LobbyPoll, ClientError> {
+    if !status.is_success() {
+        return Err(api_error(status, body));
+    }
+    if let Ok(error) = serde_json::from_slice::<ErrorResponse>(body) {
+        if error.code != ApiErrorCode::LobbyAnotherContributionInProgress {
+            return Err(ClientError::Api { status, error });
+        }
+        let seconds = error
+            .details
+            .get("estimated_wait_seconds")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        return Ok(LobbyPoll::Waiting {
+            estimated_wait: Duration::from_secs(seconds),
+        });
+    }
+    Ok(LobbyPoll::Slot(Box::new(serde_json::from_slice(body)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_transcript;
+    use serde_json::json;
+
+    #[test]
+    fn decodes_lobby_poll() {
+        let waiting = json!({
+            "code": "TryContributeError::AnotherContributionInProgress",
+            "message": "another contribution in progress",
+            "details": { "estimated_wait_seconds": 90 },
+        });
+        assert!(matches!(
+            lobby_poll(StatusCode::OK, waiting.to_string().as_bytes()),
+            Ok(LobbyPoll::Waiting { estimated_wait }) if estimated_wait == Duration::from_secs(90)
+        ));
+
+        let slot = json!({
+            "reservation": {
+                "slot_id": "slot",
+                "deadline": 1_700_000_000,
+                "seconds_left": 180,
+                "server_time": 1_699_999_820,
+                "transcript_hash": "0xabcd",
+            },
+            "contribution": test_transcript().contribution(),
+        });
+        match lobby_poll(StatusCode::OK, slot.to_string().as_bytes()) {
+            Ok(LobbyPoll::Slot(slot)) => {
+                assert_eq!(slot.reservation.slot_id, "slot");
+                assert_eq!(slot.reservation.seconds_left, Some(180));
+                assert_eq!(slot.contribution, test_transcript().contribution());
+                assert_eq!(slot.announcement, None);
+            }
+            other => panic!("expected a slot, got {other:?}"),
+        }
+
+        let rate_limited = json!({
+            "code": "TryContributeError::RateLimited",
+            "message": "call came too early. rate limited",
+        });
+        let error =
+            lobby_poll(StatusCode::BAD_REQUEST, rate_limited.to_string().as_bytes()).unwrap_err();
+        assert_eq!(error.code(), Some(&ApiErrorCode::LobbyRateLimited));
+    }
+}
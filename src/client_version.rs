@@ -0,0 +1,149 @@
+//! Fencing off contribution clients with known bugs. Clients send their
+//! version in a header, and releases older than the minimum or explicitly
+//! blocked are asked to upgrade.
+
+use async_session::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use clap::Parser;
+use kzg_ceremony_crypto::ErrorCode;
+use semver::Version;
+use std::convert::Infallible;
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+/// Header holding the version of the contribution client.
+pub const CLIENT_VERSION_HEADER: &str = "X-Client-Version";
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Oldest contribution client version allowed to contribute. When set,
+    /// clients must send their semantic version in the `X-Client-Version`
+    /// header.
+    #[clap(long, env)]
+    pub min_client_version: Option<Version>,
+
+    /// Contribution client versions that may not contribute, separated by
+    /// commas. When set, clients must send the `X-Client-Version` header.
+    #[clap(long, env, value_delimiter = ',')]
+    pub blocked_client_versions: Vec<Version>,
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum ClientVersionError {
+    #[error("missing client version, please upgrade your client")]
+    Missing,
+    #[error("invalid client version {0}")]
+    Invalid(String),
+    #[error(
+        "client version {version} is no longer supported, please upgrade to {minimum} or later"
+    )]
+    Outdated { version: Version, minimum: Version },
+    #[error("client version {0} has known issues, please upgrade your client")]
+    Blocked(Version),
+}
+
+impl ErrorCode for ClientVersionError {
+    fn to_error_code(&self) -> String {
+        format!("ClientVersionError::{}", <&str>::from(self))
+    }
+}
+
+/// The version the client sent in the `X-Client-Version` header, if any.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientVersion(pub Option<String>);
+
+#[async_trait]
+impl<B> FromRequest<B> for ClientVersion
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            req.headers()
+                .get(CLIENT_VERSION_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_owned()),
+        ))
+    }
+}
+
+impl Options {
+    fn is_configured(&self) -> bool {
+        self.min_client_version.is_some() || !self.blocked_client_versions.is_empty()
+    }
+
+    /// Checks the client version against the policy. Any client is accepted
+    /// when no policy is configured.
+    pub fn check(&self, client_version: &ClientVersion) -> Result<(), ClientVersionError> {
+        if !self.is_configured() {
+            return Ok(());
+        }
+        let raw = client_version
+            .0
+            .as_deref()
+            .ok_or(ClientVersionError::Missing)?;
+        let version = Version::parse(raw.trim_start_matches('v'))
+            .map_err(|_| ClientVersionError::Invalid(raw.to_owned()))?;
+
+        if let Some(minimum) = &self.min_client_version {
+            if &version < minimum {
+                return Err(ClientVersionError::Outdated {
+                    version,
+                    minimum: minimum.clone(),
+                });
+            }
+        }
+        if self.blocked_client_versions.contains(&version) {
+            return Err(ClientVersionError::Blocked(version));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(version: &str) -> ClientVersion {
+        ClientVersion(Some(version.to_owned()))
+    }
+
+    #[test]
+    fn checks_client_versions() {
+        let options = Options::parse_from([
+            "test",
+            "--min-client-version",
+            "1.2.0",
+            "--blocked-client-versions",
+            "1.3.0,1.3.1",
+        ]);
+        assert!(options.check(&client("1.2.0")).is_ok());
+        assert!(options.check(&client("v1.4.0")).is_ok());
+        assert!(matches!(
+            options.check(&client("1.1.9")),
+            Err(ClientVersionError::Outdated { .. })
+        ));
+        assert!(matches!(
+            options.check(&client("1.3.1")),
+            Err(ClientVersionError::Blocked(_))
+        ));
+        assert!(matches!(
+            options.check(&client("latest")),
+            Err(ClientVersionError::Invalid(_))
+        ));
+        assert!(matches!(
+            options.check(&ClientVersion(None)),
+            Err(ClientVersionError::Missing)
+        ));
+    }
+
+    #[test]
+    fn accepts_any_client_without_policy() {
+        let options = Options::parse_from(["test"]);
+        assert!(options.check(&ClientVersion(None)).is_ok());
+        assert!(options.check(&client("latest")).is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+//! Deadlines that don't depend on the clocks of clients. Responses about the
+//! slot and the lobby hold the seconds left along with the server's time, so
+//! that clients with a wrong clock don't miss their deadline. Clients may
+//! send their time in a header, and how far it is off is recorded in the
+//! `client_clock_skew_seconds` metric.
+
+use chrono::Utc;
+use http::{HeaderMap, Request};
+use once_cell::sync::Lazy;
+use prometheus::{exponential_buckets, register_histogram_vec, HistogramVec};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Header holding the client's time of sending the request, as a Unix
+/// timestamp in seconds, with an optional fraction.
+pub const REQUEST_TIME_HEADER: &str = "X-Request-Time";
+
+/// How far the clocks of clients are off, labelled with the direction:
+/// `ahead` or `behind` of the server.
+static CLOCK_SKEW_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "client_clock_skew_seconds",
+        "Difference between the request time sent by clients and the server's clock.",
+        &["direction"],
+        exponential_buckets(0.1, 2.0, 16).expect("Buckets are valid")
+    )
+    .expect("Metric can be registered")
+});
+
+/// The server's time, as a Unix timestamp.
+#[must_use]
+pub fn server_time() -> u64 {
+    u64::try_from(Utc::now().timestamp()).unwrap_or_default()
+}
+
+/// How many seconds the client's clock is ahead of `now`, if the request
+/// carries a valid request time. Negative if it is behind.
+#[must_use]
+pub fn clock_skew(headers: &HeaderMap, now: f64) -> Option<f64> {
+    let sent = headers
+        .get(REQUEST_TIME_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    sent.is_finite().then(|| sent - now)
+}
+
+#[allow(clippy::cast_precision_loss)] // Milliseconds since 1970 fit
+fn now_seconds() -> f64 {
+    Utc::now().timestamp_millis() as f64 / 1000.0
+}
+
+fn record(skew: f64) {
+    let direction = if skew < 0.0 { "behind" } else { "ahead" };
+    CLOCK_SKEW_SECONDS
+        .with_label_values(&[direction])
+        .observe(skew.abs());
+}
+
+/// Records the clock skew of requests to the routes it is applied to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClockSkewLayer;
+
+impl<S> Layer<S> for ClockSkewLayer {
+    type Service = ClockSkewService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClockSkewService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClockSkewService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for ClockSkewService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Error = S::Error;
+    type Future = S::Future;
+    type Response = S::Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        if let Some(skew) = clock_skew(request.headers(), now_seconds()) {
+            record(skew);
+        }
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_clock_skew() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(REQUEST_TIME_HEADER, value.parse().unwrap());
+            headers
+        };
+        let now = 1_700_000_000.0;
+        assert_eq!(clock_skew(&headers("1700000030"), now), Some(30.0));
+        assert_eq!(clock_skew(&headers(" 1699999999.5 "), now), Some(-0.5));
+        assert_eq!(clock_skew(&headers("yesterday"), now), None);
+        assert_eq!(clock_skew(&headers("NaN"), now), None);
+        assert_eq!(clock_skew(&HeaderMap::new(), now), None);
+    }
+}
@@ -0,0 +1,384 @@
+#[cfg(feature = "bench")]
+use crate::bench::{bench_engines, BenchEngine};
+use crate::{
+    archive::{export_state, import_state},
+    io::{read_transcript, restore_backup, write_transcript_file},
+    keys::Keys,
+    lobby::fetch_beacon,
+    mirror::Mirrors,
+    proxy::http_client,
+    receipt_export::{self, ReceiptField, ReceiptFormat},
+    storage::{storage_client, PersistentStorage},
+    verifier, webhooks, Engine, Options,
+};
+use clap::{Subcommand, ValueEnum};
+use eyre::{ensure, eyre, WrapErr};
+use kzg_ceremony_crypto::TrustedSetup;
+use serde_json::{json, Value};
+use std::{path::PathBuf, sync::Arc};
+use tokio::io::{stdin, stdout, BufReader};
+use tracing::info;
+use url::Url;
+
+/// Maintenance commands that run instead of the server.
+#[derive(Clone, Debug, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Validates a transcript backup and promotes it to be the current
+    /// transcript.
+    Restore {
+        /// Path of the backup file to restore.
+        backup: PathBuf,
+    },
+
+    /// Applies a public random beacon as the final contribution to the
+    /// transcript. No contributions are accepted afterwards.
+    ApplyBeacon {
+        /// Name of the beacon, e.g. `drand-3000000`. Recorded in the
+        /// transcript, so it must not contain `|`.
+        #[clap(value_parser = parse_beacon_source)]
+        source: String,
+
+        /// Beacon value as hex, e.g. the drand randomness or a block hash.
+        #[clap(value_parser = parse_beacon_value)]
+        value: Vec<u8>,
+    },
+
+    /// Fetches a round of a drand compatible randomness beacon, and applies
+    /// it as the final contribution named `drand-<round>`.
+    ApplyDrandBeacon {
+        /// Url of the latest round of the beacon, e.g.
+        /// `https://api.drand.sh/public/latest`. Defaults to the lobby's
+        /// selection beacon. Rounds are checked against the selection
+        /// beacon's chain.
+        url: Option<Url>,
+
+        /// Round to apply instead of the latest one, e.g. the first round
+        /// after the announced end of the ceremony.
+        #[clap(long)]
+        round: Option<u64>,
+    },
+
+    /// Writes the transcript and the database into a single archive, to
+    /// migrate the ceremony to new infrastructure. Stop the server first.
+    ExportState {
+        /// Path of the archive to write.
+        archive: PathBuf,
+    },
+
+    /// Restores the transcript and the database from an archive written by
+    /// `export-state`. The transcript file must not exist yet, and the
+    /// database must be empty.
+    ImportState {
+        /// Path of the archive to read.
+        archive: PathBuf,
+    },
+
+    /// Writes the powers of one ceremony in a format that other software can
+    /// load, such as the trusted setup of consensus clients. The setup is
+    /// checked before it is written.
+    Export {
+        /// Format to write.
+        #[clap(long, value_enum, default_value = "eip4844")]
+        format: ExportFormat,
+
+        /// Number of G1 powers of the ceremony to export.
+        #[clap(long, default_value = "4096")]
+        num_g1_powers: usize,
+
+        /// Path of the file to write.
+        output: PathBuf,
+    },
+
+    /// Writes the contributions kept by `--quarantine-rejected` as JSON
+    /// lines, with the reasons they were rejected for, to reproduce the
+    /// verification failures offline.
+    ExportRejected {
+        /// Path of the file to write.
+        output: PathBuf,
+    },
+
+    /// Writes all receipts for publication, checking their signatures with
+    /// the configured keys first. Fails if any receipt doesn't verify.
+    ExportReceipts {
+        /// Format to write.
+        #[clap(long, value_enum, default_value = "jsonl")]
+        format: ReceiptFormat,
+
+        /// Fields to write, separated by commas. Defaults to all of them.
+        #[clap(long, value_enum, value_delimiter = ',')]
+        fields: Vec<ReceiptField>,
+
+        /// Path to write the aggregate of the BLS signatures of all receipts
+        /// to, as hex, which proves all of them with a single check.
+        #[clap(long)]
+        bls_aggregate: Option<PathBuf>,
+
+        /// Path of the file to write.
+        output: PathBuf,
+    },
+
+    /// Times contributing, verifying and adding a contribution to a
+    /// transcript of the configured ceremony sizes, to size the hardware of
+    /// the sequencer. Prints the average timings of each engine.
+    #[cfg(feature = "bench")]
+    BenchEngine {
+        /// Engines to time, separated by commas. Defaults to the one the
+        /// sequencer verifies with.
+        #[clap(long = "engine", value_enum, value_delimiter = ',')]
+        engines: Vec<BenchEngine>,
+
+        /// Number of contributions to average over.
+        #[clap(long, default_value = "3")]
+        iterations: usize,
+    },
+
+    /// Verifies contributions for a server running with
+    /// `--verification-workers`, reading requests from stdin.
+    #[clap(hide = true)]
+    VerifyWorker,
+}
+
+impl Command {
+    #[allow(clippy::missing_errors_doc)]
+    pub async fn run(self, options: &Options) -> eyre::Result<()> {
+        webhooks::init(&options.webhooks, http_client(&options.proxy)?);
+        match self {
+            Self::Restore { backup } => {
+                let name = backup
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+                restore_backup(
+                    backup,
+                    options.transcript_file.clone(),
+                    options.transcript_in_progress_file.clone(),
+                    &options.io,
+                    &options.ceremony_sizes,
+                )
+                .await?;
+                webhooks::send("backup_restored", json!({ "backup": name })).await;
+                Ok(())
+            }
+            Self::ApplyBeacon { source, value } => apply_beacon(options, source, value, None).await,
+            Self::ApplyDrandBeacon { url, round } => apply_drand_beacon(options, url, round).await,
+            Self::ExportState { archive } => {
+                let storage = storage_client(&options.storage).await?;
+                export_state(options, &storage, &archive).await
+            }
+            Self::ImportState { archive } => {
+                let storage = storage_client(&options.storage).await?;
+                import_state(options, &storage, &archive).await
+            }
+            Self::Export {
+                format,
+                num_g1_powers,
+                output,
+            } => export(options, format, num_g1_powers, output).await,
+            Self::ExportRejected { output } => {
+                let storage = storage_client(&options.storage).await?;
+                export_rejected(&storage, output).await
+            }
+            Self::ExportReceipts {
+                format,
+                fields,
+                bls_aggregate,
+                output,
+            } => export_receipts(options, format, fields, bls_aggregate, output).await,
+            #[cfg(feature = "bench")]
+            Self::BenchEngine {
+                engines,
+                iterations,
+            } => {
+                let sizes = options.ceremony_sizes.clone();
+                let report = tokio::task::spawn_blocking(move || {
+                    bench_engines(&sizes, &engines, iterations)
+                })
+                .await??;
+                print!("{report}");
+                Ok(())
+            }
+            Self::VerifyWorker => {
+                verifier::init(&options.verifier);
+                verifier::serve(BufReader::new(stdin()), stdout()).await
+            }
+        }
+    }
+}
+
+async fn apply_drand_beacon(
+    options: &Options,
+    url: Option<Url>,
+    round: Option<u64>,
+) -> eyre::Result<()> {
+    let url = url
+        .or_else(|| options.lobby.lobby_selection_beacon_url.clone())
+        .ok_or_else(|| eyre!("No beacon url given"))?;
+    // Rounds are next to `latest`, e.g. `/public/3000000`
+    let url = match round {
+        Some(round) => url.join(&round.to_string())?,
+        None => url,
+    };
+    let beacon = fetch_beacon(&http_client(&options.proxy)?, &url, &options.lobby)
+        .await
+        .wrap_err("Cannot fetch beacon")?;
+    if let Some(round) = round {
+        ensure!(
+            beacon.round == round,
+            "Beacon returned round {} instead of {round}",
+            beacon.round
+        );
+    }
+    apply_beacon(
+        options,
+        format!("drand-{}", beacon.round),
+        beacon.randomness,
+        Some(beacon.round),
+    )
+    .await
+}
+
+/// Applies the beacon, recording it and its round, if any, in the audit
+/// log.
+async fn apply_beacon(
+    options: &Options,
+    source: String,
+    value: Vec<u8>,
+    round: Option<u64>,
+) -> eyre::Result<()> {
+    let mirrors = Mirrors::new(&options.mirror, http_client(&options.proxy)?)?;
+    let storage = storage_client(&options.storage).await?;
+    let mut transcript = read_transcript(options.transcript_file.clone(), &options.io).await?;
+    let event = json!({
+        "source": source,
+        "value": hex::encode(&value),
+        "round": round,
+    });
+    let transcript = tokio::task::spawn_blocking(move || {
+        transcript
+            .apply_beacon::<Engine>(&source, &value)
+            .wrap_err("Cannot apply beacon")?;
+        transcript
+            .verify_beacon::<Engine>()
+            .wrap_err("Beacon verification failed")?;
+        eyre::Ok(transcript)
+    })
+    .await??;
+    info!(
+        participants = transcript.num_participants(),
+        "Applied random beacon"
+    );
+
+    write_transcript_file(
+        options.transcript_file.clone(),
+        options.transcript_in_progress_file.clone(),
+        &options.io,
+        Arc::new(transcript),
+    )
+    .await;
+    mirrors.push_file(&options.transcript_file).await;
+    storage
+        .insert_audit_event("beacon_applied", "admin", &event)
+        .await?;
+    webhooks::send("beacon_applied", event).await;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// `trusted_setup.json` of the consensus specs, for EIP-4844.
+    Eip4844,
+    /// `trusted_setup.txt` as read by c-kzg.
+    CKzg,
+}
+
+async fn export(
+    options: &Options,
+    format: ExportFormat,
+    num_g1_powers: usize,
+    output: PathBuf,
+) -> eyre::Result<()> {
+    let transcript = read_transcript(options.transcript_file.clone(), &options.io).await?;
+    let powers = transcript
+        .transcripts
+        .into_iter()
+        .find(|transcript| transcript.powers.g1.len() == num_g1_powers)
+        .ok_or_else(|| eyre!("No ceremony with {num_g1_powers} G1 powers"))?
+        .powers;
+    let contents = tokio::task::spawn_blocking(move || {
+        let setup = TrustedSetup::from_powers(&powers).wrap_err("Cannot convert the powers")?;
+        setup.validate().wrap_err("Trusted setup is invalid")?;
+        eyre::Ok(match format {
+            ExportFormat::Eip4844 => serde_json::to_vec_pretty(&setup)?,
+            ExportFormat::CKzg => setup.to_c_kzg_text().into_bytes(),
+        })
+    })
+    .await??;
+    tokio::fs::write(&output, contents)
+        .await
+        .wrap_err_with(|| format!("Cannot write {}", output.display()))?;
+    info!(?format, path = %output.display(), "Exported trusted setup");
+    Ok(())
+}
+
+async fn export_rejected(storage: &PersistentStorage, output: PathBuf) -> eyre::Result<()> {
+    let rejected = storage.rejected_contributions().await?;
+    let mut contents = Vec::new();
+    for row in &rejected {
+        let line = json!({
+            "id": row.id,
+            "identity_hash": row.identity_hash,
+            "code": row.code,
+            "reason": row.reason,
+            "rejected_at": row.rejected_at,
+            "contribution": serde_json::from_str::<Value>(&row.contribution)?,
+        });
+        serde_json::to_writer(&mut contents, &line)?;
+        contents.push(b'\n');
+    }
+    tokio::fs::write(&output, contents)
+        .await
+        .wrap_err_with(|| format!("Cannot write {}", output.display()))?;
+    info!(count = rejected.len(), path = %output.display(), "Exported rejected contributions");
+    Ok(())
+}
+
+async fn export_receipts(
+    options: &Options,
+    format: ReceiptFormat,
+    fields: Vec<ReceiptField>,
+    bls_aggregate: Option<PathBuf>,
+    output: PathBuf,
+) -> eyre::Result<()> {
+    let keys = Arc::new(Keys::new(&options.keys)?);
+    let storage = storage_client(&options.storage).await?;
+    let export = receipt_export::export(&storage, keys, format, fields).await?;
+    if let Some(path) = bls_aggregate {
+        let aggregate = export
+            .bls_aggregate
+            .ok_or_else(|| eyre!("No receipt has a BLS signature"))?;
+        tokio::fs::write(&path, aggregate)
+            .await
+            .wrap_err_with(|| format!("Cannot write {}", path.display()))?;
+    }
+    let contents = export.contents;
+    tokio::fs::write(&output, &contents)
+        .await
+        .wrap_err_with(|| format!("Cannot write {}", output.display()))?;
+    let count = contents.lines().count() - usize::from(format == ReceiptFormat::Csv);
+    info!(count, ?format, path = %output.display(), "Exported receipts");
+    Ok(())
+}
+
+fn parse_beacon_source(source: &str) -> eyre::Result<String> {
+    ensure!(
+        !source.is_empty() && !source.contains('|'),
+        "Invalid beacon source {source}"
+    );
+    Ok(source.to_string())
+}
+
+fn parse_beacon_value(value: &str) -> eyre::Result<Vec<u8>> {
+    let value = hex::decode(value.trim_start_matches("0x"))
+        .map_err(|error| eyre!("Invalid beacon value: {error}"))?;
+    ensure!(!value.is_empty(), "Beacon value is empty");
+    Ok(value)
+}
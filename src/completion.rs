@@ -0,0 +1,126 @@
+//! Closing the ceremony once it has the targeted number of contributions.
+//!
+//! The lobby is closed, so nobody else can take the slot, and the
+//! `ceremony_completed` webhook is sent with the final transcript hash.
+//! Applying a random beacon remains up to the operators, with
+//! `apply-beacon` or `apply-drand-beacon`, since its value should only be
+//! known after the last contribution.
+
+use crate::{io::transcript_hash, webhooks};
+use clap::Parser;
+use kzg_ceremony_crypto::BatchTranscript;
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tracing::info;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Number of contributions after which the ceremony is complete, and no
+    /// more participants are admitted.
+    #[clap(long, env)]
+    pub target_participants: Option<usize>,
+
+    /// Shut the sequencer down gracefully once the ceremony is complete.
+    #[clap(long, env, default_value = "false")]
+    pub shutdown_on_completion: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Completion {
+    target:   Option<usize>,
+    shutdown: bool,
+    complete: AtomicBool,
+}
+
+pub type SharedCompletion = Arc<Completion>;
+
+impl Completion {
+    /// Starts out complete if the transcript already has `participants`
+    /// reaching the target.
+    #[must_use]
+    pub fn new(options: &Options, participants: usize) -> Self {
+        let completion = Self {
+            target:   options.target_participants,
+            shutdown: options.shutdown_on_completion,
+            complete: AtomicBool::new(false),
+        };
+        if completion.is_reached(participants) {
+            completion.complete.store(true, Ordering::SeqCst);
+        }
+        completion
+    }
+
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.complete.load(Ordering::SeqCst)
+    }
+
+    const fn is_reached(&self, participants: usize) -> bool {
+        match self.target {
+            Some(target) => participants >= target,
+            None => false,
+        }
+    }
+
+    /// Marks the ceremony complete once the transcript has `participants`
+    /// reaching the target. Returns whether this completed it, which happens
+    /// only once.
+    pub fn reach(&self, participants: usize) -> bool {
+        self.is_reached(participants) && !self.complete.swap(true, Ordering::SeqCst)
+    }
+
+    /// Sends the `ceremony_completed` webhook for the final transcript, and
+    /// shuts down if configured to.
+    pub async fn announce(&self, transcript: Arc<BatchTranscript>) {
+        let participants = transcript.num_participants();
+        let hash = tokio::task::spawn_blocking(move || transcript_hash(&transcript))
+            .await
+            .expect("Hashing the transcript panicked");
+        info!(participants, %hash, "Ceremony complete");
+        // Awaited, so that the webhook goes out before shutting down
+        webhooks::send(
+            "ceremony_completed",
+            json!({
+                "participants": participants,
+                "transcript_hash": hash,
+            }),
+        )
+        .await;
+        if self.shutdown {
+            info!("Shutting down after completing the ceremony");
+            cli_batteries::shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_once() {
+        let options = Options {
+            target_participants:    Some(2),
+            shutdown_on_completion: false,
+        };
+        let completion = Completion::new(&options, 0);
+        assert!(!completion.reach(1));
+        assert!(!completion.is_complete());
+        assert!(completion.reach(2));
+        assert!(completion.is_complete());
+        assert!(!completion.reach(3));
+
+        // Restarting with a complete transcript keeps the ceremony complete
+        assert!(Completion::new(&options, 2).is_complete());
+        assert!(!Completion::new(&options, 2).reach(2));
+
+        // Without a target the ceremony goes on
+        let open = Completion::default();
+        assert!(!open.reach(usize::MAX));
+        assert!(!open.is_complete());
+    }
+}
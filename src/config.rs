@@ -0,0 +1,275 @@
+//! Reading options from a TOML config file given with `--config`, for
+//! deployments where the list of flags gets unwieldy.
+//!
+//! Keys are the long flag names, with `_` or `-`, e.g.
+//! `lobby_max_deferral = 60`, and lists set options that take several
+//! values. Each value is handed to its option as the option's environment
+//! variable, unless that is set already, so that flags take precedence over
+//! environment variables, which take precedence over the file.
+//!
+//! On `SIGHUP` the file is read again and its lobby parameters, see
+//! [`LobbyParams`], are applied to the running lobby. These then take
+//! precedence over flags and environment variables.
+
+use crate::{
+    lobby::{LobbyParams, SharedLobbyState},
+    Options,
+};
+use clap::{Arg, ArgAction, Command, CommandFactory};
+use eyre::{bail, ensure, eyre, Result as EyreResult, WrapErr};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::Value;
+use tracing::{error, info};
+
+/// Applies the config file given on the command line or with `CONFIG`, if
+/// any. Must be called before the command line is parsed, and before other
+/// threads are started.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, or sets unknown options or
+/// invalid values.
+pub fn apply_config_file() -> EyreResult<()> {
+    if let Some(path) = config_path(env::args_os()) {
+        for (name, value) in read(&path)? {
+            if env::var_os(&name).is_none() {
+                env::set_var(name, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the lobby parameters in the config file whenever the process
+/// receives `SIGHUP`.
+#[cfg(unix)]
+pub async fn reload_on_hangup(path: PathBuf, lobby_state: SharedLobbyState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            error!(?error, "Cannot listen for SIGHUP");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!(path = %path.display(), "Reloading lobby parameters");
+        let params = match read(&path).and_then(|vars| lobby_params(&vars)) {
+            Ok(params) => params,
+            Err(error) => {
+                error!(?error, "Cannot reload config file");
+                continue;
+            }
+        };
+        if let Err(error) = lobby_state.reload(&params).await {
+            error!(%error, "Cannot reload lobby parameters");
+        }
+    }
+}
+
+/// The lobby parameters among the environment variables set by a config
+/// file.
+fn lobby_params(vars: &[(OsString, String)]) -> EyreResult<LobbyParams> {
+    let mut params = LobbyParams::default();
+    for (name, value) in vars {
+        let seconds = || {
+            value
+                .parse::<u64>()
+                .wrap_err_with(|| format!("Invalid value for {}", name.to_string_lossy()))
+        };
+        match name.to_str() {
+            Some("MAX_LOBBY_SIZE") => {
+                params.max_lobby_size = Some(value.parse().wrap_err("Invalid max_lobby_size")?);
+            }
+            Some("COMPUTE_DEADLINE") => params.compute_deadline = Some(seconds()?),
+            Some("COMPUTE_DEADLINE_MAX_EXTENSION") => {
+                params.compute_deadline_max_extension = Some(seconds()?);
+            }
+            Some("LOBBY_CHECKIN_FREQUENCY") => params.lobby_checkin_frequency = Some(seconds()?),
+            Some("LOBBY_CHECKIN_TOLERANCE") => params.lobby_checkin_tolerance = Some(seconds()?),
+            _ => {}
+        }
+    }
+    Ok(params)
+}
+
+fn config_path(args: impl Iterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(path.into());
+        }
+    }
+    env::var_os("CONFIG").map(PathBuf::from)
+}
+
+fn read(path: &Path) -> EyreResult<Vec<(OsString, String)>> {
+    let contents = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Cannot read config file {}", path.display()))?;
+    parse(&contents).wrap_err_with(|| format!("Invalid config file {}", path.display()))
+}
+
+/// Parses a config file into the environment variables of the options it
+/// sets.
+fn parse(contents: &str) -> EyreResult<Vec<(OsString, String)>> {
+    let table: toml::value::Table = toml::from_str(contents)?;
+    let mut command = Options::command();
+    command.build();
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let long = key.replace('_', "-");
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long.as_str()))
+                .ok_or_else(|| eyre!("Unknown key `{key}`"))?;
+            let env = match arg.get_env() {
+                Some(env) if long != "config" => env,
+                _ => bail!("Key `{key}` can't be set in a config file"),
+            };
+            let value = match value {
+                Value::Array(values) => {
+                    let delimiter = arg
+                        .get_value_delimiter()
+                        .ok_or_else(|| eyre!("Key `{key}` takes a single value, not a list"))?;
+                    values
+                        .into_iter()
+                        .map(|value| scalar(&key, value))
+                        .collect::<EyreResult<Vec<_>>>()?
+                        .join(&delimiter.to_string())
+                }
+                value => scalar(&key, value)?,
+            };
+            validate(arg, &key, &value)?;
+            Ok((env.to_owned(), value))
+        })
+        .collect()
+}
+
+fn scalar(key: &str, value: Value) -> EyreResult<String> {
+    match value {
+        Value::String(value) => Ok(value),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Datetime(_) => {
+            Ok(value.to_string())
+        }
+        Value::Array(_) | Value::Table(_) => {
+            bail!("Key `{key}` must be a string, number or boolean")
+        }
+    }
+}
+
+/// Parses the value as its option would, so that errors point at the key
+/// rather than at the environment variable.
+fn validate(arg: &Arg, key: &str, value: &str) -> EyreResult<()> {
+    if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+        ensure!(
+            matches!(value, "true" | "false"),
+            "Invalid value for `{key}`: expected a boolean, found {value}"
+        );
+        return Ok(());
+    }
+    let mut check = Arg::new("value")
+        .long("value")
+        .value_parser(arg.get_value_parser().clone())
+        .action(ArgAction::Set);
+    if let Some(delimiter) = arg.get_value_delimiter() {
+        check = check.value_delimiter(delimiter);
+    }
+    Command::new("config")
+        .no_binary_name(true)
+        .arg(check)
+        .try_get_matches_from([format!("--value={value}")])
+        .map_err(|error| {
+            // Only keep the reason, which doesn't refer to the stand-in flag
+            let error = error.to_string();
+            let message = error.lines().next().unwrap_or_default();
+            let reason = message
+                .split_once("': ")
+                .map_or(message, |(_, reason)| reason);
+            eyre!("Invalid value for `{key}`: {reason}")
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_config_path() {
+        let args = |args: &[&str]| args.iter().map(OsString::from);
+        assert_eq!(
+            config_path(args(&["sequencer", "-vv", "--config", "a.toml"])),
+            Some("a.toml".into())
+        );
+        assert_eq!(
+            config_path(args(&["sequencer", "--config=b.toml"])),
+            Some("b.toml".into())
+        );
+    }
+
+    #[test]
+    fn parses_config_file() {
+        let vars = parse(
+            r#"
+            lobby_max_deferral = 60
+            multi-contribution = true
+            ceremony_sizes = "4,2"
+            transcript_mirrors = ["file:///a.json", "file:///b.json"]
+            "#,
+        )
+        .unwrap();
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(var("LOBBY_MAX_DEFERRAL"), Some("60"));
+        assert_eq!(var("MULTI_CONTRIBUTION"), Some("true"));
+        assert_eq!(var("CEREMONY_SIZES"), Some("4,2"));
+        assert_eq!(
+            var("TRANSCRIPT_MIRRORS"),
+            Some("file:///a.json,file:///b.json")
+        );
+    }
+
+    #[test]
+    fn reads_lobby_params() {
+        let vars = parse(
+            r#"
+            max_lobby_size = 500
+            lobby_checkin_tolerance = 5
+            lobby_max_deferral = 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(lobby_params(&vars).unwrap(), LobbyParams {
+            max_lobby_size: Some(500),
+            lobby_checkin_tolerance: Some(5),
+            ..LobbyParams::default()
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_config_file() {
+        let error = |contents: &str| parse(contents).unwrap_err().to_string();
+        assert!(error("lobby_max_defferal = 60").contains("`lobby_max_defferal`"));
+        assert!(error("lobby_max_deferral = \"soon\"").contains("`lobby_max_deferral`"));
+        assert!(error("multi_contribution = \"maybe\"").contains("`multi_contribution`"));
+        assert!(error("ceremony_sizes = [\"4,2\"]").contains("single value"));
+        assert!(error("config = \"other.toml\"").contains("`config`"));
+        assert!(parse("lobby_max_deferral = ").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Two-phase contributions, for a record that both sides attest to. With
+//! `--confirm-contributions`, participants name the address of a key they
+//! hold as `countersigner` when uploading to `/contribute`. A contribution
+//! that passes verification is held back, and `/contribute` answers with the
+//! receipt it would get, which includes the countersigner. The participant
+//! countersigns the receipt with that key and sends the signature to
+//! `/contribute/confirm`. Only then is the contribution added to the
+//! transcript, and the countersignature is stored with the receipt. As the
+//! address comes with the authenticated upload and is signed into the
+//! receipt, anyone can check the countersignature later. Contributions that
+//! aren't confirmed in time are dropped, and the slot is given back.
+
+use crate::{
+    api::v1::contribute::ContributeReceipt,
+    lobby::{duration_from_str, SlotId},
+    receipt::Receipt,
+    regions::Region,
+    verifier::VerifiedContribution,
+    SessionId,
+};
+use clap::Parser;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Add verified contributions to the transcript only once the
+    /// participant countersigned their receipt with `/contribute/confirm`.
+    #[clap(long, env, default_value = "false")]
+    pub confirm_contributions: bool,
+
+    /// How many seconds participants have to confirm a verified contribution
+    /// before it is dropped.
+    #[clap(long, env, value_parser=duration_from_str, default_value="60")]
+    pub confirmation_deadline: Duration,
+}
+
+/// A verified contribution waiting for the countersignature of its
+/// participant.
+pub struct PendingContribution {
+    pub session_id: SessionId,
+    pub slot_id:    SlotId,
+    pub uid:        String,
+    pub region:     Option<Region>,
+    pub verified:   VerifiedContribution,
+    pub receipt:    Receipt,
+    /// The answer to the upload, which becomes valid once the contribution
+    /// is added.
+    pub response:   ContributeReceipt,
+}
+
+/// The contribution waiting for confirmation. There is at most one, as only
+/// the holder of the slot can contribute.
+#[derive(Default)]
+pub struct PendingContributions(Mutex<Option<PendingContribution>>);
+
+pub type SharedPendingContributions = Arc<PendingContributions>;
+
+impl PendingContributions {
+    pub async fn hold(&self, pending: PendingContribution) {
+        *self.0.lock().await = Some(pending);
+    }
+
+    /// Takes the waiting contribution if `predicate` accepts it, and leaves
+    /// it waiting otherwise.
+    pub async fn take_if(
+        &self,
+        predicate: impl FnOnce(&PendingContribution) -> bool + Send,
+    ) -> Option<PendingContribution> {
+        let mut pending = self.0.lock().await;
+        if pending.as_ref().map_or(false, predicate) {
+            pending.take()
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Cross-checks of the transcript against other sequencer instances of the
+//! same ceremony, to catch a split brain in deployments with more than one.
+//! Peers are asked for the root of the tree over their entries, see
+//! [`crate::inclusion`], which is compared with the local tree of the same
+//! size. Peers that are only behind or ahead are not diverged.
+//!
+//! Diverged peers are reported in the `transcript_peer_diverged` metric, and
+//! with a `transcript_diverged` event to the webhooks when they start to
+//! diverge. File and object mirrors are checked at `/info/storage` instead.
+
+use crate::{
+    api_types::TreeHead,
+    inclusion::{leaf_hashes, tree_head, Hash},
+    lobby::duration_from_str,
+    webhooks, SharedTranscript,
+};
+use clap::Parser;
+use eyre::{ensure, eyre};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use serde_json::json;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Base urls of other sequencer instances serving the same ceremony,
+    /// separated by commas. Their transcripts are compared with the local
+    /// one.
+    #[clap(long, env, value_delimiter = ',')]
+    pub consistency_peer_urls: Vec<Url>,
+
+    /// How often the transcripts of the peers are compared, in seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="60")]
+    pub consistency_check_interval: Duration,
+}
+
+static PEER_DIVERGED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "transcript_peer_diverged",
+        "Whether the transcript of a peer sequencer differs from the local one.",
+        &["peer"]
+    )
+    .expect("Metric can be registered")
+});
+
+static PEER_CHECK_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "transcript_peer_check_errors_total",
+        "Number of times the transcript of a peer sequencer could not be compared.",
+        &["peer"]
+    )
+    .expect("Metric can be registered")
+});
+
+/// The roots of the local tree and a peer's over the same entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    pub tree_size:  usize,
+    pub local_root: String,
+    pub peer_root:  String,
+}
+
+impl Comparison {
+    #[must_use]
+    pub fn is_diverged(&self) -> bool {
+        self.local_root != self.peer_root
+    }
+}
+
+/// Compares a peer's head with the local tree, or returns `None` if the peer
+/// is ahead and has to be asked for a smaller tree.
+#[must_use]
+pub fn compare(leaves: &[Hash], head: &TreeHead) -> Option<Comparison> {
+    let leaves = leaves.get(..head.tree_size)?;
+    Some(Comparison {
+        tree_size:  head.tree_size,
+        local_root: hex::encode(tree_head(leaves)),
+        peer_root:  head.root.clone(),
+    })
+}
+
+async fn fetch_head(
+    client: &reqwest::Client,
+    peer: &Url,
+    tree_size: Option<usize>,
+) -> eyre::Result<TreeHead> {
+    let mut url = peer.clone();
+    url.path_segments_mut()
+        .map_err(|_| eyre!("Peer {peer} can't be a base url"))?
+        .pop_if_empty()
+        .extend(["info", "transcript_head"]);
+    if let Some(tree_size) = tree_size {
+        url.query_pairs_mut()
+            .append_pair("tree_size", &tree_size.to_string());
+    }
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Compares the transcript of `peer` with the local `leaves`.
+async fn check_peer(
+    client: &reqwest::Client,
+    peer: &Url,
+    leaves: &[Hash],
+) -> eyre::Result<Comparison> {
+    let head = fetch_head(client, peer, None).await?;
+    if let Some(comparison) = compare(leaves, &head) {
+        return Ok(comparison);
+    }
+    let head = fetch_head(client, peer, Some(leaves.len())).await?;
+    ensure!(
+        head.tree_size == leaves.len(),
+        "Peer {peer} returned a tree over {} entries instead of {}",
+        head.tree_size,
+        leaves.len()
+    );
+    compare(leaves, &head).ok_or_else(|| eyre!("Peer {peer} returned a larger tree"))
+}
+
+/// Compares the transcript with the peers every
+/// [`Options::consistency_check_interval`].
+pub async fn check_peers_on_interval(
+    options: Options,
+    client: reqwest::Client,
+    transcript: SharedTranscript,
+) {
+    if options.consistency_peer_urls.is_empty() {
+        return;
+    }
+    let mut diverged = vec![false; options.consistency_peer_urls.len()];
+    let mut interval = tokio::time::interval(
+        options
+            .consistency_check_interval
+            .max(Duration::from_secs(1)),
+    );
+    loop {
+        interval.tick().await;
+        let snapshot = transcript.snapshot();
+        let leaves = match tokio::task::spawn_blocking(move || leaf_hashes(&snapshot)).await {
+            Ok(leaves) => leaves,
+            Err(error) => {
+                error!(?error, "Could not hash the transcript entries");
+                continue;
+            }
+        };
+        for (peer, was_diverged) in options.consistency_peer_urls.iter().zip(&mut diverged) {
+            let label = peer.as_str();
+            let comparison = match check_peer(&client, peer, &leaves).await {
+                Ok(comparison) => comparison,
+                Err(error) => {
+                    warn!(?error, %peer, "Could not compare transcript with peer");
+                    PEER_CHECK_ERRORS.with_label_values(&[label]).inc();
+                    continue;
+                }
+            };
+            let is_diverged = comparison.is_diverged();
+            PEER_DIVERGED
+                .with_label_values(&[label])
+                .set(i64::from(is_diverged));
+            if is_diverged && !*was_diverged {
+                error!(
+                    %peer,
+                    tree_size = comparison.tree_size,
+                    local_root = comparison.local_root,
+                    peer_root = comparison.peer_root,
+                    "Transcript diverged from peer"
+                );
+                webhooks::notify(
+                    "transcript_diverged",
+                    json!({
+                        "peer": peer,
+                        "tree_size": comparison.tree_size,
+                        "local_root": comparison.local_root,
+                        "peer_root": comparison.peer_root,
+                    }),
+                );
+            } else if !is_diverged && *was_diverged {
+                info!(%peer, "Transcript is consistent with peer again");
+            }
+            *was_diverged = is_diverged;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tests::{test_transcript, valid_contribution},
+        Engine,
+    };
+    use kzg_ceremony_crypto::Identity;
+
+    #[test]
+    fn compares_trees_of_same_size() {
+        let mut transcript = test_transcript();
+        let before = leaf_hashes(&transcript);
+        let contribution = valid_contribution(&transcript, 1);
+        transcript
+            .verify_add::<Engine>(contribution, Identity::None)
+            .unwrap();
+        let leaves = leaf_hashes(&transcript);
+        let head = |leaves: &[Hash]| TreeHead {
+            tree_size: leaves.len(),
+            root:      hex::encode(tree_head(leaves)),
+        };
+
+        // A peer that is behind is compared with the same entries
+        let behind = compare(&leaves, &head(&before)).unwrap();
+        assert_eq!(behind.tree_size, before.len());
+        assert!(!behind.is_diverged());
+        assert!(!compare(&leaves, &head(&leaves)).unwrap().is_diverged());
+
+        // A peer that is ahead has to be asked for a smaller tree
+        assert_eq!(compare(&before, &head(&leaves)), None);
+
+        let mut other = head(&leaves);
+        other.root = hex::encode([0; 32]);
+        assert!(compare(&leaves, &other).unwrap().is_diverged());
+    }
+}
@@ -0,0 +1,189 @@
+//! Where the ceremony is heading, for `/info/status`. The rate of
+//! contributions is measured over a rolling window of the recent ones, which
+//! is seeded from storage on startup, and projected to the configured end of
+//! the ceremony along with the size of the transcript by then.
+
+use crate::{io::CeremonySizes, lobby::duration_from_str};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// When the ceremony is planned to end, as RFC 3339 date, e.g.
+    /// `2023-04-01T00:00:00Z`. Participants are projected up to then.
+    #[clap(long, env)]
+    pub ceremony_end: Option<DateTime<Utc>>,
+
+    /// Over how many of the last seconds the rate of contributions is
+    /// measured.
+    #[clap(long, env, value_parser=duration_from_str, default_value="86400")]
+    pub forecast_window: Duration,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GrowthForecast {
+    /// Accepted contributions per hour over the forecast window.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributions_per_hour:     Option<f64>,
+    /// Participants by the end of the ceremony at the current rate, at most
+    /// the targeted number.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projected_participants:     Option<usize>,
+    /// Rough size of the final transcript, for the projected or targeted
+    /// number of participants.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_transcript_bytes: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    /// The first contribution seen, so that the rate isn't underestimated
+    /// before the ceremony has run for a whole window.
+    first:  Option<DateTime<Utc>>,
+    recent: VecDeque<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Forecast {
+    window:  Duration,
+    ends_at: Option<DateTime<Utc>>,
+    target:  Option<usize>,
+    state:   Mutex<Window>,
+}
+
+pub type SharedForecast = Arc<Forecast>;
+
+impl Forecast {
+    /// A forecast towards `target` participants, if any, starting from the
+    /// finish times of earlier contributions.
+    #[must_use]
+    pub fn new(
+        options: &Options,
+        target: Option<usize>,
+        finished: impl IntoIterator<Item = DateTime<Utc>>,
+    ) -> Self {
+        let mut recent = finished.into_iter().collect::<Vec<_>>();
+        recent.sort_unstable();
+        Self {
+            window: options.forecast_window,
+            ends_at: options.ceremony_end,
+            target,
+            state: Mutex::new(Window {
+                first:  recent.first().copied(),
+                recent: recent.into(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Window> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn prune(&self, state: &mut Window, now: DateTime<Utc>) {
+        let window = chrono::Duration::from_std(self.window)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        while let Some(&oldest) = state.recent.front() {
+            if now.signed_duration_since(oldest) <= window {
+                break;
+            }
+            state.recent.pop_front();
+        }
+    }
+
+    /// Records a contribution accepted at `now`.
+    pub fn record(&self, now: DateTime<Utc>) {
+        let mut state = self.lock();
+        state.first.get_or_insert(now);
+        state.recent.push_back(now);
+        self.prune(&mut state, now);
+    }
+
+    /// Projects the ceremony from `participants` at `now`.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // Counts of contributions
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Rounded, positive
+    pub fn project(
+        &self,
+        now: DateTime<Utc>,
+        participants: usize,
+        sizes: &CeremonySizes,
+    ) -> GrowthForecast {
+        let rate = {
+            let mut state = self.lock();
+            self.prune(&mut state, now);
+            let span = state
+                .first
+                .and_then(|first| now.signed_duration_since(first).to_std().ok())
+                .map(|since_first| since_first.min(self.window))
+                .filter(|span| !span.is_zero());
+            span.map(|span| state.recent.len() as f64 / span.as_secs_f64())
+        };
+
+        let remaining = self
+            .ends_at
+            .map(|ends_at| ends_at.signed_duration_since(now).num_seconds().max(0) as f64);
+        let projected = rate.zip(remaining).map(|(rate, remaining)| {
+            let projected = participants + (rate * remaining).round() as usize;
+            self.target
+                .map_or(projected, |target| projected.min(target))
+        });
+        GrowthForecast {
+            contributions_per_hour:     rate.map(|rate| rate * 3600.0),
+            projected_participants:     projected,
+            estimated_transcript_bytes: projected
+                .or(self.target)
+                .map(|participants| sizes.transcript_size(participants)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_participants_by_the_end() {
+        let now = Utc::now();
+        let hours = |hours: i64| chrono::Duration::hours(hours);
+        let options = Options::parse_from([
+            "test",
+            "--forecast-window",
+            "7200",
+            "--ceremony-end",
+            &(now + hours(10)).to_rfc3339(),
+        ]);
+        let sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+
+        // The contribution before the window only counts towards its start
+        let forecast = Forecast::new(&options, None, [now - hours(3), now - hours(1)]);
+        forecast.record(now);
+        let projection = forecast.project(now, 3, &sizes);
+        assert_eq!(projection.contributions_per_hour, Some(1.0));
+        assert_eq!(projection.projected_participants, Some(13));
+        assert_eq!(
+            projection.estimated_transcript_bytes,
+            Some(sizes.transcript_size(13))
+        );
+        assert!(sizes.transcript_size(13) > sizes.transcript_size(3));
+
+        let capped = Forecast::new(&options, Some(5), [now - hours(1), now]);
+        assert_eq!(
+            capped.project(now, 2, &sizes).projected_participants,
+            Some(5)
+        );
+
+        // Without contributions there is no rate, only the targeted size
+        let empty = Forecast::new(&options, Some(5), []);
+        assert_eq!(empty.project(now, 0, &sizes), GrowthForecast {
+            estimated_transcript_bytes: Some(sizes.transcript_size(5)),
+            ..GrowthForecast::default()
+        });
+    }
+}
@@ -0,0 +1,99 @@
+//! Persistent guard against identities contributing more than once.
+//!
+//! The transcript only lists finished contributions, and sessions live in
+//! memory, so after a restart the sequencer can't tell from either that an
+//! identity already holds or used the slot. The guard keeps a mark per
+//! identity in storage instead, and the slot is claimed against it
+//! atomically: an identity marked as having contributed can't take the slot
+//! again, whichever session or restored lobby it comes from. Identities that
+//! ran out of failed attempts are marked as banned for the record, while the
+//! limit itself is enforced by [`crate::attempts`].
+
+use crate::storage::{PersistentStorage, StorageError};
+use strum::{EnumString, IntoStaticStr};
+use tracing::error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoStaticStr, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Mark {
+    /// The identity holds the contribution slot.
+    Contributing,
+    /// The identity's contribution is in the transcript.
+    Contributed,
+    /// The identity's last attempt ended without a contribution. It may try
+    /// again.
+    Aborted,
+    /// The identity ran out of failed attempts. It may try again if the
+    /// limit is raised.
+    Banned,
+}
+
+impl Mark {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// Claims the contribution slot for `uid`. Fails for identities that
+/// contributed already, unless `multi_contribution` is set.
+///
+/// # Errors
+///
+/// Returns an error if storage fails, in which case the slot should not be
+/// handed out.
+pub async fn claim(
+    storage: &PersistentStorage,
+    uid: &str,
+    multi_contribution: bool,
+) -> Result<bool, StorageError> {
+    Ok(storage.claim_contribution(uid).await? || multi_contribution)
+}
+
+/// Whether an identity with the stored mark may take the slot.
+#[must_use]
+pub fn allows(mark: Option<&str>, multi_contribution: bool) -> bool {
+    multi_contribution || mark != Some(Mark::Contributed.as_str())
+}
+
+/// Marks how an attempt of `uid` ended. The attempt is over either way, so
+/// failing to do so is only logged.
+pub async fn mark(storage: &PersistentStorage, uid: &str, mark: Mark) {
+    if let Err(error) = storage.mark_identity(uid, mark.as_str()).await {
+        error!(?error, %uid, mark = mark.as_str(), "Could not mark identity");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::storage_client, test_util::test_options};
+
+    #[tokio::test]
+    async fn claims_slot_once_per_identity() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+
+        // Aborted attempts can be retried
+        assert!(claim(&storage, "alice", false).await.unwrap());
+        mark(&storage, "alice", Mark::Aborted).await;
+        assert!(claim(&storage, "alice", false).await.unwrap());
+        mark(&storage, "alice", Mark::Contributed).await;
+
+        // Contributed identities keep their mark
+        assert!(!claim(&storage, "alice", false).await.unwrap());
+        mark(&storage, "alice", Mark::Aborted).await;
+        assert_eq!(
+            storage.identity_mark("alice").await.unwrap().as_deref(),
+            Some("contributed")
+        );
+        assert!(!allows(Some("contributed"), false));
+        assert!(claim(&storage, "alice", true).await.unwrap());
+        assert!(allows(Some("contributed"), true));
+
+        // Banned identities get the slot once the limit is raised
+        mark(&storage, "mallory", Mark::Banned).await;
+        assert!(allows(Some("banned"), false));
+        assert!(claim(&storage, "mallory", false).await.unwrap());
+        assert!(allows(None, false));
+    }
+}
@@ -0,0 +1,174 @@
+//! A Merkle tree over the participants of the transcript, so participants
+//! can prove their contribution is part of it without the whole transcript.
+//!
+//! The tree is built as in [RFC 6962](https://www.rfc-editor.org/rfc/rfc6962#section-2.1),
+//! with SHA-256 and one leaf per entry of `participantIds`, starting at the
+//! initial entry. A leaf is the JSON encoding of [`Entry`].
+
+use kzg_ceremony_crypto::{signature::BlsSignature, BatchTranscript, Identity, G1, G2};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+/// The witness of one participant in one of the ceremonies.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct WitnessEntry {
+    #[serde(rename = "runningProduct")]
+    pub running_product: G1,
+    #[serde(rename = "potPubkey")]
+    pub pot_pubkey:      G2,
+    #[serde(rename = "blsSignature")]
+    pub bls_signature:   BlsSignature,
+}
+
+/// Everything the transcript records about one participant.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub participant: usize,
+    pub identity:    Identity,
+    pub witness:     Vec<WitnessEntry>,
+}
+
+impl Entry {
+    /// The entry of the participant at `index`, if there is one.
+    pub fn from_transcript(transcript: &BatchTranscript, index: usize) -> Option<Self> {
+        let identity = transcript.participant_ids.get(index)?.clone();
+        let witness = transcript
+            .transcripts
+            .iter()
+            .map(|transcript| {
+                Some(WitnessEntry {
+                    running_product: *transcript.witness.products.get(index)?,
+                    pot_pubkey:      *transcript.witness.pubkeys.get(index)?,
+                    bls_signature:   transcript.witness.signatures.get(index)?.clone(),
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self {
+            participant: index,
+            identity,
+            witness,
+        })
+    }
+
+    pub fn leaf_hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update([0]);
+        serde_json::to_writer(&mut hasher, self).expect("Cannot serialize entry");
+        hasher.finalize().into()
+    }
+}
+
+/// The leaf hashes of all participants of the transcript.
+pub fn leaf_hashes(transcript: &BatchTranscript) -> Vec<Hash> {
+    (0..transcript.participant_ids.len())
+        .filter_map(|index| Entry::from_transcript(transcript, index))
+        .map(|entry| entry.leaf_hash())
+        .collect()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The largest power of two smaller than `n`, for `n > 1`.
+fn split(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+/// The root hash of the tree over `leaves`.
+pub fn tree_head(leaves: &[Hash]) -> Hash {
+    match leaves {
+        [] => Sha256::digest(b"").into(),
+        [leaf] => *leaf,
+        _ => {
+            let (left, right) = leaves.split_at(split(leaves.len()));
+            node_hash(&tree_head(left), &tree_head(right))
+        }
+    }
+}
+
+/// The audit path of the leaf at `index`, starting next to the leaf.
+pub fn inclusion_path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    if leaves.len() <= 1 {
+        return vec![];
+    }
+    let k = split(leaves.len());
+    let (left, right) = leaves.split_at(k);
+    let (mut path, sibling) = if index < k {
+        (inclusion_path(index, left), tree_head(right))
+    } else {
+        (inclusion_path(index - k, right), tree_head(left))
+    };
+    path.push(sibling);
+    path
+}
+
+/// Recomputes the root hash from a leaf and its audit path, as a verifier
+/// would.
+#[cfg(test)]
+fn root_from_path(index: usize, tree_size: usize, leaf: Hash, path: &[Hash]) -> Option<Hash> {
+    if tree_size <= 1 {
+        return path.is_empty().then_some(leaf);
+    }
+    let k = split(tree_size);
+    let (sibling, rest) = path.split_last()?;
+    Some(if index < k {
+        node_hash(&root_from_path(index, k, leaf, rest)?, sibling)
+    } else {
+        node_hash(
+            sibling,
+            &root_from_path(index - k, tree_size - k, leaf, rest)?,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tests::{test_transcript, valid_contribution},
+        Engine,
+    };
+
+    #[test]
+    fn proves_inclusion() {
+        let leaves = (0..7_u8)
+            .map(|i| Sha256::digest([i]).into())
+            .collect::<Vec<Hash>>();
+        for size in 1..=leaves.len() {
+            let leaves = &leaves[..size];
+            let root = tree_head(leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let path = inclusion_path(index, leaves);
+                assert_eq!(root_from_path(index, size, *leaf, &path), Some(root));
+                assert_ne!(root_from_path(index, size, [0; 32], &path), Some(root));
+            }
+        }
+    }
+
+    #[test]
+    fn hashes_transcript_entries() {
+        let mut transcript = test_transcript();
+        let contribution = valid_contribution(&transcript, 1);
+        transcript
+            .verify_add::<Engine>(contribution, Identity::None)
+            .unwrap();
+
+        let leaves = leaf_hashes(&transcript);
+        assert_eq!(leaves.len(), 2);
+        let entry = Entry::from_transcript(&transcript, 1).unwrap();
+        assert_eq!(entry.witness.len(), transcript.transcripts.len());
+        assert_eq!(
+            entry.witness[0].pot_pubkey,
+            transcript.transcripts[0].witness.pubkeys[1]
+        );
+        assert_eq!(leaves[1], entry.leaf_hash());
+        assert!(Entry::from_transcript(&transcript, 2).is_none());
+    }
+}
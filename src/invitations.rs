@@ -0,0 +1,135 @@
+//! Invite-only lobbies, for practice ceremonies and staged rollouts.
+//! Operators mint codes with `/admin/invitations`, and with `--invite-only`
+//! signing in takes a code with uses left, given to `/auth/request_link` or
+//! to the passkey sign-in. Each identity uses up a code once, and can sign in
+//! again later without one. Only hashes of the codes are stored, so they are
+//! shown once, when they are minted.
+
+use crate::storage::{PersistentStorage, StorageError};
+use clap::Parser;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Only admit identities that sign in with an invitation code.
+    #[clap(long, env, default_value = "false")]
+    pub invite_only: bool,
+}
+
+fn code_hash(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().as_bytes()))
+}
+
+/// Mints `count` invitation codes that admit `max_uses` identities each.
+///
+/// # Errors
+///
+/// Returns an error if storage fails, in which case some of the codes may
+/// be stored but none are returned.
+pub async fn mint(
+    storage: &PersistentStorage,
+    count: usize,
+    max_uses: usize,
+) -> Result<Vec<String>, StorageError> {
+    let mut codes = Vec::with_capacity(count);
+    for _ in 0..count {
+        let code = hex::encode(thread_rng().gen::<[u8; 12]>());
+        storage
+            .insert_invitation(&code_hash(&code), max_uses)
+            .await?;
+        codes.push(code);
+    }
+    Ok(codes)
+}
+
+/// Whether a sign-in with `code` can go ahead. Only checks that the code
+/// exists, as identities that used up a single use code may sign in with it
+/// again.
+///
+/// # Errors
+///
+/// Returns an error if storage fails.
+pub async fn accepts(
+    options: &Options,
+    storage: &PersistentStorage,
+    code: Option<&str>,
+) -> Result<bool, StorageError> {
+    if !options.invite_only {
+        return Ok(true);
+    }
+    match code {
+        Some(code) => storage.has_invitation(&code_hash(code)).await,
+        None => Ok(false),
+    }
+}
+
+/// Admits `uid` with `code`, using it up once unless the identity was
+/// admitted before. Returns whether the identity is admitted.
+///
+/// # Errors
+///
+/// Returns an error if storage fails.
+pub async fn admit(
+    options: &Options,
+    storage: &PersistentStorage,
+    code: Option<&str>,
+    uid: &str,
+) -> Result<bool, StorageError> {
+    if !options.invite_only {
+        return Ok(true);
+    }
+    storage
+        .redeem_invitation(code.map(code_hash).as_deref(), uid)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::storage_client, test_util::test_options};
+
+    #[tokio::test]
+    async fn admits_identities_with_codes() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+        let open = Options::parse_from(["test"]);
+        let invite_only = Options::parse_from(["test", "--invite-only"]);
+        assert!(accepts(&open, &storage, None).await.unwrap());
+        assert!(admit(&open, &storage, None, "alice").await.unwrap());
+        assert!(!accepts(&invite_only, &storage, None).await.unwrap());
+        assert!(!accepts(&invite_only, &storage, Some("unknown"))
+            .await
+            .unwrap());
+
+        let codes = mint(&storage, 2, 1).await.unwrap();
+        assert_eq!(codes.len(), 2);
+        assert_ne!(codes[0], codes[1]);
+        assert!(accepts(&invite_only, &storage, Some(&codes[0]))
+            .await
+            .unwrap());
+        assert!(admit(&invite_only, &storage, Some(&codes[0]), "alice")
+            .await
+            .unwrap());
+
+        // A single use code is used up, but its identity stays admitted
+        assert!(!admit(&invite_only, &storage, Some(&codes[0]), "bob")
+            .await
+            .unwrap());
+        assert!(admit(&invite_only, &storage, None, "alice").await.unwrap());
+        assert!(!admit(&invite_only, &storage, None, "bob").await.unwrap());
+        assert!(admit(&invite_only, &storage, Some(&codes[1]), "bob")
+            .await
+            .unwrap());
+
+        let shared = mint(&storage, 1, 2).await.unwrap();
+        for uid in ["carol", "dave"] {
+            assert!(admit(&invite_only, &storage, Some(&shared[0]), uid)
+                .await
+                .unwrap());
+        }
+        assert!(!admit(&invite_only, &storage, Some(&shared[0]), "eve")
+            .await
+            .unwrap());
+    }
+}
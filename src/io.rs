@@ -1,12 +1,323 @@
 // TODO: Error handling
 
-use crate::SharedTranscript;
-use eyre::eyre;
-use kzg_ceremony_crypto::BatchTranscript;
-use serde::{de::DeserializeOwned, Serialize};
-use std::{path::PathBuf, sync::Arc};
-use tokio::sync::RwLock;
-use tracing::{info, warn};
+use crate::{transcript::TranscriptStore, SharedTranscript};
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use chrono::Utc;
+use clap::Parser;
+use eyre::{ensure, eyre, WrapErr};
+use futures::{future::join_all, stream, Stream, StreamExt};
+use kzg_ceremony_crypto::{
+    signature::EcdsaSignature, BatchTranscript, Identity, Transcript, TranscriptMetadata,
+};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fmt, fs,
+    io::{BufRead, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tracing::{error, info, warn};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Number of rotated backups of the transcript file to keep. Before each
+    /// write, the current transcript is kept as a backup named after its
+    /// timestamp and hash. Set to 0 to disable backups.
+    #[clap(long, env, default_value = "0")]
+    pub transcript_backups: usize,
+
+    /// Hex encoded 256-bit key to encrypt the transcript files with
+    /// AES-256-GCM. Usually injected through the environment, e.g. from a
+    /// KMS. Unencrypted transcripts are still read, and encrypted on the next
+    /// write.
+    #[clap(long, env, value_parser = EncryptionKey::parse_from_cmd)]
+    pub transcript_encryption_key: Option<EncryptionKey>,
+
+    /// Compress the transcript files with zstd at this level, from 1 to 22.
+    /// Hex encoded points compress to about half their size. Uncompressed
+    /// transcripts are still read, and compressed on the next write.
+    #[clap(long, env, value_parser = clap::value_parser!(i32).range(1..=22))]
+    pub transcript_compression_level: Option<i32>,
+
+    /// End the transcript files with a line holding the length and SHA-256
+    /// hash of their contents, so that truncated or partially written files
+    /// are detected when they are read. Such files are no longer plain JSON.
+    /// Files without it are still read, and get it on the next write.
+    #[clap(long, env, default_value = "false")]
+    pub transcript_integrity_trailer: bool,
+
+    /// Store each ceremony's transcript in its own shard file next to the
+    /// transcript file, which then holds a manifest tying them together.
+    /// Shards are read and written one at a time, which needs less memory
+    /// for large ceremonies. Transcripts without shards are still read, and
+    /// sharded when opened. Exported archives hold the whole transcript as
+    /// plain JSON.
+    #[clap(long, env, default_value = "false")]
+    pub transcript_shards: bool,
+}
+
+impl Options {
+    /// True if transcript files are written as plain JSON, which can be
+    /// served as it is.
+    #[must_use]
+    pub const fn writes_plain_json(&self) -> bool {
+        self.transcript_encryption_key.is_none()
+            && self.transcript_compression_level.is_none()
+            && !self.transcript_integrity_trailer
+    }
+}
+
+/// Prefix of encrypted transcript files, followed by the nonce and the
+/// ciphertext.
+const ENCRYPTED_MAGIC: &[u8] = b"kzg-aes256gcm-v1\n";
+
+const NONCE_SIZE: usize = 12;
+
+/// Magic number at the start of every zstd frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Start of the integrity trailer, followed by the length of the contents
+/// before the trailer as 16 hex digits, a space, their hex encoded SHA-256
+/// hash and a newline.
+const TRAILER_MAGIC: &[u8] = b"\nkzg-integrity-v1 ";
+
+const TRAILER_SIZE: usize = TRAILER_MAGIC.len() + 16 + 1 + 64 + 1;
+
+fn integrity_trailer(length: u64, hash: &[u8]) -> Vec<u8> {
+    let mut trailer = TRAILER_MAGIC.to_vec();
+    trailer.extend_from_slice(format!("{length:016x} {}\n", hex::encode(hash)).as_bytes());
+    trailer
+}
+
+/// Checks and removes the integrity trailer, if the contents have one.
+fn strip_integrity_trailer(mut contents: Vec<u8>) -> eyre::Result<Vec<u8>> {
+    let trailer_start = contents.len().saturating_sub(TRAILER_SIZE);
+    if !contents[trailer_start..].starts_with(TRAILER_MAGIC) {
+        // A partial trailer means that the file was cut off while it was
+        // written.
+        ensure!(
+            !contents[trailer_start..]
+                .windows(TRAILER_MAGIC.len())
+                .any(|window| window == TRAILER_MAGIC),
+            "Transcript is truncated"
+        );
+        return Ok(contents);
+    }
+    let expected = integrity_trailer(
+        trailer_start as u64,
+        &Sha256::digest(&contents[..trailer_start]),
+    );
+    ensure!(
+        contents[trailer_start..] == expected,
+        "Transcript does not match its integrity trailer, the file is truncated or corrupted"
+    );
+    contents.truncate(trailer_start);
+    Ok(contents)
+}
+
+/// Passes writes through, keeping track of their length and hash for the
+/// integrity trailer.
+struct HashingWriter<W> {
+    inner:  W,
+    hasher: Sha256,
+    length: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            length: 0,
+        }
+    }
+
+    fn finish(mut self, trailer: bool) -> std::io::Result<W> {
+        if trailer {
+            let hash = self.hasher.finalize();
+            self.inner
+                .write_all(&integrity_trailer(self.length, &hash))?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.length += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Key for encrypting transcript files at rest.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Parses a hex encoded key, with or without a `0x` prefix.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is not valid hex, or not 32 bytes long.
+    pub fn parse_from_cmd(cmd: &str) -> eyre::Result<Self> {
+        let bytes = hex::decode(cmd.trim_start_matches("0x"))
+            .map_err(|_| eyre!("Encryption key must be hex encoded"))?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| eyre!("Encryption key must be 32 bytes long"))?;
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = rand::thread_rng().gen::<[u8; NONCE_SIZE]>();
+        let ciphertext = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("Cannot encrypt transcript");
+        [ENCRYPTED_MAGIC, &nonce, &ciphertext].concat()
+    }
+
+    fn decrypt(&self, data: &[u8]) -> eyre::Result<Vec<u8>> {
+        ensure!(
+            data.len() >= NONCE_SIZE,
+            "Encrypted transcript is truncated"
+        );
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| eyre!("Cannot decrypt transcript, wrong key or corrupted file"))
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("[REDACTED]")
+    }
+}
+
+/// Checks the integrity trailer, decrypts and decompresses the contents of a
+/// transcript file, as needed.
+///
+/// # Errors
+///
+/// - when the contents don't match their integrity trailer.
+/// - when the contents are encrypted and cannot be decrypted.
+/// - when the contents are compressed and cannot be decompressed.
+pub fn decode_contents(contents: Vec<u8>, options: &Options) -> eyre::Result<Vec<u8>> {
+    let contents = strip_integrity_trailer(contents)?;
+    let contents = match (
+        contents.strip_prefix(ENCRYPTED_MAGIC),
+        &options.transcript_encryption_key,
+    ) {
+        (Some(data), Some(key)) => key.decrypt(data)?,
+        (Some(_), None) => {
+            return Err(eyre!(
+                "Transcript is encrypted, but no encryption key was given"
+            ))
+        }
+        (None, _) => contents,
+    };
+    if contents.starts_with(ZSTD_MAGIC) {
+        zstd::stream::decode_all(contents.as_slice()).wrap_err("Cannot decompress transcript")
+    } else {
+        Ok(contents)
+    }
+}
+
+/// Limits on the ceremony sizes the sequencer agrees to host.
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct SizePolicy {
+    /// Smallest number of G1 points allowed in a ceremony.
+    #[clap(long, env, default_value = "1")]
+    pub min_g1_powers: usize,
+
+    /// Largest number of G1 points allowed in a ceremony.
+    #[clap(long, env, default_value = "32768")]
+    pub max_g1_powers: usize,
+
+    /// Largest estimated memory, in MiB, needed to hold the powers of all
+    /// ceremonies while a contribution is processed.
+    #[clap(long, env, default_value = "1024")]
+    pub max_ceremony_memory_mib: usize,
+
+    /// Accept any ceremony sizes, skipping the checks above and the
+    /// requirement for the number of G1 points to be a power of two.
+    #[clap(long, env, default_value = "false")]
+    pub allow_nonstandard_sizes: bool,
+}
+
+/// The size of one ceremony, as reported by `/info/status`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CeremonySize {
+    pub num_g1_powers:          usize,
+    pub num_g2_powers:          usize,
+    pub estimated_memory_bytes: usize,
+    /// `estimated_memory_bytes` in binary units, e.g. `4.5 MiB`.
+    pub estimated_memory:       String,
+}
+
+/// Size of a compressed G1 point.
+const G1_BYTES: usize = 48;
+/// Size of a compressed G2 point.
+const G2_BYTES: usize = 96;
+
+/// How points are encoded in a contribution.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PointEncoding {
+    pub format:     &'static str,
+    /// Size of the compressed point.
+    pub bytes:      usize,
+    /// Length of the encoded string, including the `0x` prefix.
+    pub hex_length: usize,
+}
+
+impl PointEncoding {
+    const fn compressed(bytes: usize) -> Self {
+        Self {
+            format: "0x-prefixed lowercase hex of the compressed point",
+            bytes,
+            hex_length: 2 + 2 * bytes,
+        }
+    }
+}
+
+/// What a contribution to one ceremony must hold, as reported by
+/// `/info/contribution_spec`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ContributionSpec {
+    /// Number of points in `powersOfTau.G1Powers`.
+    pub num_g1_powers:     usize,
+    /// Number of points in `powersOfTau.G2Powers`.
+    pub num_g2_powers:     usize,
+    pub g1_encoding:       PointEncoding,
+    /// Encoding of the G2 powers and of `potPubkey`.
+    pub g2_encoding:       PointEncoding,
+    /// Upper bound on the JSON encoded size of this ceremony's part of the
+    /// contribution.
+    pub max_encoded_bytes: usize,
+}
 
 /// Represents a size constraint on a batch transcript
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -15,6 +326,14 @@ pub struct CeremonySizes {
 }
 
 impl CeremonySizes {
+    /// Field names, pubkey and signatures of a contribution.
+    const CONTRIBUTION_OVERHEAD: usize = 1024;
+    /// A hex encoded point in quotes, with a separator.
+    const ENCODED_G1_SIZE: usize = 2 + 2 * G1_BYTES + 3;
+    const ENCODED_G2_SIZE: usize = 2 + 2 * G2_BYTES + 3;
+    /// Participant id and ECDSA signature in the witness of a contribution.
+    const WITNESS_OVERHEAD: usize = 256;
+
     /// Parses a size constraint from command line format. The format accepted
     /// is a `:`-separated list of `,`-separated pairs denoting the expected
     /// number of, respectively, G1 and G2 points in consecutive ceremonies.
@@ -45,6 +364,127 @@ impl CeremonySizes {
         })
     }
 
+    /// Numbers of G1 and G2 powers of each ceremony, in order.
+    #[must_use]
+    pub fn sizes(&self) -> &[(usize, usize)] {
+        &self.sizes
+    }
+
+    /// Rough size of a ceremony's part of a JSON encoded contribution, with
+    /// hex encoded points in quotes and a separator.
+    const fn encoded_size(num_g1: usize, num_g2: usize) -> usize {
+        num_g1 * Self::ENCODED_G1_SIZE
+            + num_g2 * Self::ENCODED_G2_SIZE
+            + CeremonySizes::CONTRIBUTION_OVERHEAD
+    }
+
+    /// Rough size of the JSON encoded transcript once it has `participants`
+    /// contributions: the powers of each ceremony, and a running product, a
+    /// pubkey and a BLS signature per contribution in its witness.
+    #[must_use]
+    pub fn transcript_size(&self, participants: usize) -> usize {
+        const WITNESS_SIZE: usize = 2 * CeremonySizes::ENCODED_G1_SIZE
+            + CeremonySizes::ENCODED_G2_SIZE
+            + CeremonySizes::WITNESS_OVERHEAD;
+        self.sizes
+            .iter()
+            .map(|&(num_g1, num_g2)| {
+                Self::encoded_size(num_g1, num_g2) + participants.saturating_mul(WITNESS_SIZE)
+            })
+            .sum()
+    }
+
+    /// Returns an upper bound on the size of a JSON encoded contribution to
+    /// ceremonies of these sizes. There is ample room for whitespace, so that
+    /// pretty printed contributions are accepted as well.
+    #[must_use]
+    pub fn max_contribution_size(&self) -> usize {
+        let size = self
+            .sizes
+            .iter()
+            .map(|&(num_g1, num_g2)| Self::encoded_size(num_g1, num_g2))
+            .sum::<usize>();
+        2 * (size + Self::CONTRIBUTION_OVERHEAD)
+    }
+
+    /// What contributions to each of the ceremonies must hold, in order.
+    #[must_use]
+    pub fn contribution_spec(&self) -> Vec<ContributionSpec> {
+        self.sizes
+            .iter()
+            .map(|&(num_g1_powers, num_g2_powers)| ContributionSpec {
+                num_g1_powers,
+                num_g2_powers,
+                g1_encoding: PointEncoding::compressed(G1_BYTES),
+                g2_encoding: PointEncoding::compressed(G2_BYTES),
+                max_encoded_bytes: 2 * Self::encoded_size(num_g1_powers, num_g2_powers),
+            })
+            .collect()
+    }
+
+    /// Rough estimate of the memory needed to hold the powers of a ceremony
+    /// while a contribution is processed: the current and the next version
+    /// of the transcript, the received contribution, and its JSON encoding.
+    const fn estimated_memory(num_g1: usize, num_g2: usize) -> usize {
+        let points = num_g1 * G1_BYTES + num_g2 * G2_BYTES;
+        // Hex encoding doubles the size, pretty printing adds some more
+        3 * points + 2 * (2 * points)
+    }
+
+    /// The sizes of the ceremonies, with their memory estimates.
+    #[must_use]
+    pub fn describe(&self) -> Vec<CeremonySize> {
+        self.sizes
+            .iter()
+            .map(|&(num_g1_powers, num_g2_powers)| {
+                let estimated_memory_bytes = Self::estimated_memory(num_g1_powers, num_g2_powers);
+                CeremonySize {
+                    num_g1_powers,
+                    num_g2_powers,
+                    estimated_memory_bytes,
+                    estimated_memory: human_bytes(estimated_memory_bytes),
+                }
+            })
+            .collect()
+    }
+
+    /// Checks that the sizes are within the limits of the policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first violation, unless the policy
+    /// allows nonstandard sizes.
+    pub fn validate(&self, policy: &SizePolicy) -> eyre::Result<()> {
+        if policy.allow_nonstandard_sizes {
+            return Ok(());
+        }
+        for (i, &(num_g1, _)) in self.sizes.iter().enumerate() {
+            ensure!(
+                num_g1.is_power_of_two(),
+                "Number of G1 points in ceremony #{i} must be a power of two, but is {num_g1}"
+            );
+            ensure!(
+                (policy.min_g1_powers..=policy.max_g1_powers).contains(&num_g1),
+                "Number of G1 points in ceremony #{i} must be between {} and {}, but is {num_g1}",
+                policy.min_g1_powers,
+                policy.max_g1_powers
+            );
+        }
+        let memory = self
+            .sizes
+            .iter()
+            .map(|&(num_g1, num_g2)| Self::estimated_memory(num_g1, num_g2))
+            .sum::<usize>();
+        let max_memory = policy.max_ceremony_memory_mib.saturating_mul(1 << 20);
+        ensure!(
+            memory <= max_memory,
+            "Ceremonies need about {} of memory, more than the allowed {}",
+            human_bytes(memory),
+            human_bytes(max_memory)
+        );
+        Ok(())
+    }
+
     /// Validates a batch transcript against this shape description
     ///
     /// # Errors:
@@ -83,60 +523,1255 @@ impl CeremonySizes {
     }
 }
 
-/// Reads a transcript file from disk, or creates it, if it doesn't exist.
+/// Formats a number of bytes in binary units, e.g. `4.5 MiB`.
+#[allow(clippy::cast_precision_loss)]
+fn human_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Reads a transcript file from disk, or creates it, if it doesn't exist. If
+/// the file is truncated or otherwise unreadable, the newest backup that is
+/// intact is restored instead.
 ///
 /// # Errors
 ///
 /// - when the transcript exists, but does not conform to the required shape.
+/// - when the transcript cannot be read or decrypted, and there is no intact
+///   backup.
 pub async fn read_or_create_transcript(
     path: PathBuf,
     work_path: PathBuf,
+    options: &Options,
     ceremony_sizes: &CeremonySizes,
 ) -> eyre::Result<SharedTranscript> {
     if path.exists() {
         info!(?path, "Opening transcript file");
-        let transcript = read_json_file::<BatchTranscript>(path).await;
+        let (transcript, sharded) = match load_transcript(path.clone(), options).await {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                error!(
+                    ?error,
+                    ?path,
+                    "Cannot read transcript, falling back to backups"
+                );
+                let transcript =
+                    restore_latest_backup(path.clone(), work_path.clone(), options, ceremony_sizes)
+                        .await
+                        .wrap_err("Cannot read transcript, and there is no intact backup")?;
+                (transcript, false)
+            }
+        };
         ceremony_sizes.validate_batch_transcript(&transcript)?;
-        Ok(Arc::new(RwLock::new(transcript)))
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        if options.transcript_shards && !sharded {
+            info!(?path, "Sharding transcript file");
+            try_write_transcript_file(path, work_path, options, shared_transcript.snapshot())
+                .await?;
+        }
+        Ok(shared_transcript)
     } else {
         warn!(?path, "No transcript found, creating new transcript file");
         let transcript = BatchTranscript::new(&ceremony_sizes.sizes);
-        let shared_transcript = Arc::new(RwLock::new(transcript));
-        write_json_file(path, work_path, shared_transcript.clone()).await;
+        let shared_transcript = Arc::new(TranscriptStore::new(transcript));
+        write_transcript_file(path, work_path, options, shared_transcript.snapshot()).await;
         Ok(shared_transcript)
     }
 }
 
-/// Asynchronously reads a JSON file from disk.
+/// Reads the contents of a transcript file, decrypting them if necessary.
+///
+/// # Errors
+///
+/// - when the file cannot be read or decrypted.
+pub async fn read_transcript_bytes(path: PathBuf, options: &Options) -> eyre::Result<Vec<u8>> {
+    let contents = tokio::fs::read(&path)
+        .await
+        .wrap_err_with(|| format!("Cannot read {path:?}"))?;
+    decode_contents(contents, options)
+}
+
+/// Reads a transcript file, decrypting it if necessary.
+///
+/// # Errors
+///
+/// - when the file cannot be read or decrypted, or is not a valid transcript.
+pub async fn read_transcript(path: PathBuf, options: &Options) -> eyre::Result<BatchTranscript> {
+    Ok(load_transcript(path, options).await?.0)
+}
+
+/// Reads a transcript file, and tells whether it is sharded. The ceremonies
+/// are parsed in parallel, with progress logged as each is done.
+async fn load_transcript(
+    path: PathBuf,
+    options: &Options,
+) -> eyre::Result<(BatchTranscript, bool)> {
+    let started = Instant::now();
+    let outline = {
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || {
+            let contents = decode_contents(
+                fs::read(&path).wrap_err_with(|| format!("Cannot read {path:?}"))?,
+                &options,
+            )?;
+            TranscriptOutline::parse(&path, contents)
+        })
+        .await??
+    };
+    let total = outline.ceremonies.len();
+    let parsed = Arc::new(AtomicUsize::new(0));
+    let ceremonies = join_all(outline.ceremonies.iter().cloned().map(|source| {
+        let options = options.clone();
+        let parsed = parsed.clone();
+        async move {
+            let ceremony = tokio::task::spawn_blocking(move || source.parse(&options)).await??;
+            let done = parsed.fetch_add(1, Ordering::Relaxed) + 1;
+            info!(
+                num_g1_powers = ceremony.powers.g1.len(),
+                elapsed = ?started.elapsed(),
+                "Parsed ceremony {done} of {total}"
+            );
+            eyre::Ok(ceremony)
+        }
+    }))
+    .await
+    .into_iter()
+    .collect::<eyre::Result<Vec<_>>>()?;
+    info!(elapsed = ?started.elapsed(), "Loaded transcript");
+    Ok(outline.assemble(ceremonies))
+}
+
+/// Writes the transcript to disk, keeping the previously persisted version as
+/// a rotated backup.
+///
+/// # Panics
+///
+/// * Panics if writing fails.
+pub async fn write_transcript_file(
+    target_path: PathBuf,
+    work_path: PathBuf,
+    options: &Options,
+    transcript: Arc<BatchTranscript>,
+) {
+    try_write_transcript_file(target_path, work_path, options, transcript)
+        .await
+        .expect("Cannot write transcript");
+}
+
+/// Like [`write_transcript_file`], but returns an error if writing fails.
+///
+/// # Errors
+///
+/// - when the transcript can't be written or persisted.
+pub async fn try_write_transcript_file(
+    target_path: PathBuf,
+    work_path: PathBuf,
+    options: &Options,
+    transcript: Arc<BatchTranscript>,
+) -> eyre::Result<()> {
+    let backups = options.transcript_backups;
+    let backup_target = target_path.clone();
+    let result = tokio::task::spawn_blocking(move || rotate_backups(&backup_target, backups))
+        .await
+        .wrap_err("Cannot back up transcript")?;
+    // A failed backup must not prevent persisting the transcript itself.
+    if let Err(error) = result {
+        error!(?error, "Could not back up transcript");
+    }
+    if options.transcript_shards {
+        let options = options.clone();
+        return tokio::task::spawn_blocking(move || {
+            write_sharded(&target_path, &work_path, &options, &transcript)
+        })
+        .await?;
+    }
+    try_write_json_file(
+        target_path,
+        work_path,
+        options.transcript_encryption_key.clone(),
+        options.transcript_compression_level,
+        options.transcript_integrity_trailer,
+        transcript,
+    )
+    .await
+}
+
+/// Validates a transcript backup and promotes it to be the current transcript.
+///
+/// # Errors
+///
+/// - when the backup file name does not carry a hash, or the hash does not
+///   match the contents.
+/// - when the backup cannot be decrypted, or is not a valid transcript of the
+///   required shape.
+pub async fn restore_backup(
+    backup_path: PathBuf,
+    target_path: PathBuf,
+    work_path: PathBuf,
+    options: &Options,
+    ceremony_sizes: &CeremonySizes,
+) -> eyre::Result<()> {
+    let options = options.clone();
+    let ceremony_sizes = ceremony_sizes.clone();
+    tokio::task::spawn_blocking(move || {
+        read_backup(&backup_path, &options, &ceremony_sizes)?;
+        promote_backup(&backup_path, &target_path, &work_path)
+    })
+    .await?
+}
+
+/// Restores the newest backup that is intact, and returns its transcript.
+async fn restore_latest_backup(
+    target_path: PathBuf,
+    work_path: PathBuf,
+    options: &Options,
+    ceremony_sizes: &CeremonySizes,
+) -> eyre::Result<BatchTranscript> {
+    let options = options.clone();
+    let ceremony_sizes = ceremony_sizes.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut backups = list_backups(&target_path)?;
+        backups.sort_by_key(|(timestamp, _)| std::cmp::Reverse(*timestamp));
+        for (_, backup_path) in backups {
+            match read_backup(&backup_path, &options, &ceremony_sizes) {
+                Ok(transcript) => {
+                    promote_backup(&backup_path, &target_path, &work_path)?;
+                    return Ok(transcript);
+                }
+                Err(error) => warn!(?error, ?backup_path, "Skipping unusable backup"),
+            }
+        }
+        Err(eyre!("No intact backup of {target_path:?}"))
+    })
+    .await?
+}
+
+/// Reads a backup, checking it against the hash in its name.
+fn read_backup(
+    backup_path: &Path,
+    options: &Options,
+    ceremony_sizes: &CeremonySizes,
+) -> eyre::Result<BatchTranscript> {
+    let expected_hash = parse_backup_name(backup_path)
+        .ok_or_else(|| eyre!("{backup_path:?} is not a transcript backup"))?
+        .1;
+    let actual_hash = file_hash(backup_path)?;
+    if actual_hash != expected_hash {
+        return Err(eyre!(
+            "Backup is corrupted: expected hash {expected_hash}, but got {actual_hash}"
+        ));
+    }
+
+    let contents = decode_contents(fs::read(backup_path)?, options)?;
+    let (transcript, _) = parse_transcript(backup_path, contents, options)
+        .wrap_err("backup is not a valid transcript")?;
+    ceremony_sizes.validate_batch_transcript(&transcript)?;
+    Ok(transcript)
+}
+
+fn promote_backup(backup_path: &Path, target_path: &Path, work_path: &Path) -> eyre::Result<()> {
+    fs::copy(backup_path, work_path)?;
+    persist_file(work_path, target_path)?;
+    info!(?backup_path, ?target_path, "Restored transcript backup");
+    Ok(())
+}
+
+/// Durably replaces the file at `target_path` with the one at `work_path`:
+/// the new contents are flushed to disk before the rename, and the rename
+/// itself before returning. A crash leaves either the old or the new file.
+///
+/// # Errors
+///
+/// - when syncing or renaming fails.
+pub fn persist_file(work_path: &Path, target_path: &Path) -> std::io::Result<()> {
+    fs::File::open(work_path)?.sync_all()?;
+    fs::rename(work_path, target_path)?;
+    sync_dir(parent_dir(target_path))
+}
+
+/// Flushes the entries of a directory, such as a rename, to disk.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as files on other platforms.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Keeps a copy of the file at `target_path` as a backup named
+/// `<file name>.<timestamp>.<sha256>.bak`, and removes all but the newest
+/// `count` backups.
+fn rotate_backups(target_path: &Path, count: usize) -> eyre::Result<()> {
+    if count == 0 || !target_path.exists() {
+        return Ok(());
+    }
+    let hash = file_hash(target_path)?;
+    let backup_path = backup_path(target_path, Utc::now().timestamp_millis(), &hash);
+    // A hard link is cheap and keeps the old contents once the target is
+    // replaced. Fall back to copying on file systems that don't support them.
+    if fs::hard_link(target_path, &backup_path).is_err() {
+        fs::copy(target_path, &backup_path)?;
+    }
+    info!(?backup_path, "Created transcript backup");
+
+    let mut backups = list_backups(target_path)?;
+    backups.sort_by_key(|(timestamp, _)| *timestamp);
+    let excess = backups.len().saturating_sub(count);
+    for (_, path) in backups.into_iter().take(excess) {
+        fs::remove_file(&path)?;
+        info!(?path, "Removed old transcript backup");
+    }
+    Ok(())
+}
+
+fn backup_path(target_path: &Path, timestamp: i64, hash: &str) -> PathBuf {
+    let mut name = target_path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{timestamp}.{hash}.bak"));
+    target_path.with_file_name(name)
+}
+
+/// Returns the timestamp and hash encoded in the name of a backup file.
+fn parse_backup_name(path: &Path) -> Option<(i64, String)> {
+    let name = path.file_name()?.to_str()?;
+    let mut parts = name.strip_suffix(".bak")?.rsplitn(3, '.');
+    let hash = parts.next()?;
+    let timestamp = parts.next()?.parse().ok()?;
+    Some((timestamp, hash.to_string()))
+}
+
+/// Lists all backups of the file at `target_path`, together with their
+/// timestamps.
+fn list_backups(target_path: &Path) -> eyre::Result<Vec<(i64, PathBuf)>> {
+    let prefix = format!(
+        "{}.",
+        target_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    );
+    let mut backups = vec![];
+    for entry in fs::read_dir(parent_dir(target_path))? {
+        let path = entry?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with(&prefix));
+        if let Some((timestamp, _)) = parse_backup_name(&path).filter(|_| is_backup) {
+            backups.push((timestamp, path));
+        }
+    }
+    Ok(backups)
+}
+
+/// The contents of a sharded transcript file: the transcript without its
+/// ceremonies, which are stored in the shard files it lists.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ShardManifest {
+    pub shards: Vec<Shard>,
+    pub participant_ids: Vec<Identity>,
+    pub participant_ecdsa_signatures: Vec<EcdsaSignature>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<TranscriptMetadata>,
+}
+
+/// The transcript of one ceremony, stored in its own file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct Shard {
+    /// Name of the file, in the directory of the manifest.
+    pub file:          String,
+    pub num_g1_powers: usize,
+    pub num_g2_powers: usize,
+    /// Hex encoded SHA-256 hash of the JSON encoding of the ceremony's
+    /// transcript, before compression and encryption.
+    pub sha256:        String,
+}
+
+/// The fields of a transcript after its ceremonies.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestTail<'a> {
+    participant_ids:              &'a [Identity],
+    participant_ecdsa_signatures: &'a [EcdsaSignature],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata:                     &'a Option<TranscriptMetadata>,
+}
+
+/// Shards are named `<file name>.<index>.<sha256>.shard`, so that a new
+/// version of the transcript never overwrites the shards of the manifest it
+/// replaces.
+fn shard_path(target_path: &Path, index: usize, hash: &str) -> PathBuf {
+    let mut name = target_path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{index}.{hash}.shard"));
+    target_path.with_file_name(name)
+}
+
+/// Parses the decoded contents of a transcript file, reading the shards if
+/// it holds a manifest. Also tells whether it does.
+fn parse_transcript(
+    path: &Path,
+    contents: Vec<u8>,
+    options: &Options,
+) -> eyre::Result<(BatchTranscript, bool)> {
+    let outline = TranscriptOutline::parse(path, contents)?;
+    let ceremonies = outline
+        .ceremonies
+        .iter()
+        .map(|source| source.parse(options))
+        .collect::<eyre::Result<_>>()?;
+    Ok(outline.assemble(ceremonies))
+}
+
+/// Where the transcript of a ceremony is read from.
+#[derive(Clone)]
+enum CeremonySource {
+    /// A part of the contents of the transcript file.
+    Inline(Arc<Vec<u8>>, Range<usize>),
+    /// A shard of the manifest at the path.
+    Shard(PathBuf, Shard),
+}
+
+impl CeremonySource {
+    fn parse(&self, options: &Options) -> eyre::Result<Transcript> {
+        match self {
+            Self::Inline(contents, range) => {
+                serde_json::from_slice(&contents[range.clone()]).wrap_err("unreadable transcript")
+            }
+            Self::Shard(path, shard) => {
+                let contents = read_shard(path, shard, options)?;
+                serde_json::from_slice(&contents)
+                    .wrap_err_with(|| format!("Shard {} is not a valid transcript", shard.file))
+            }
+        }
+    }
+}
+
+/// A transcript with its ceremonies left unparsed, so that they can be
+/// parsed independently.
+struct TranscriptOutline {
+    ceremonies: Vec<CeremonySource>,
+    participant_ids: Vec<Identity>,
+    participant_ecdsa_signatures: Vec<EcdsaSignature>,
+    metadata: Option<TranscriptMetadata>,
+    sharded: bool,
+}
+
+/// [`BatchTranscript`] with the ceremonies as they are in the file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct RawBatchTranscript<'a> {
+    #[serde(borrow)]
+    transcripts:                  Vec<&'a RawValue>,
+    participant_ids:              Vec<Identity>,
+    participant_ecdsa_signatures: Vec<EcdsaSignature>,
+    #[serde(default)]
+    metadata:                     Option<TranscriptMetadata>,
+}
+
+impl TranscriptOutline {
+    /// Parses everything but the ceremonies of the decoded contents of the
+    /// transcript file at `path`.
+    fn parse(path: &Path, contents: Vec<u8>) -> eyre::Result<Self> {
+        let contents = Arc::new(contents);
+        let error = match serde_json::from_slice::<RawBatchTranscript>(&contents) {
+            Ok(raw) => {
+                // The raw ceremonies borrow from the contents
+                let base = contents.as_ptr() as usize;
+                let ceremonies = raw
+                    .transcripts
+                    .iter()
+                    .map(|ceremony| {
+                        let start = ceremony.get().as_ptr() as usize - base;
+                        CeremonySource::Inline(
+                            contents.clone(),
+                            start..start + ceremony.get().len(),
+                        )
+                    })
+                    .collect();
+                return Ok(Self {
+                    ceremonies,
+                    participant_ids: raw.participant_ids,
+                    participant_ecdsa_signatures: raw.participant_ecdsa_signatures,
+                    metadata: raw.metadata,
+                    sharded: false,
+                });
+            }
+            Err(error) => error,
+        };
+        let manifest = match serde_json::from_slice::<ShardManifest>(&contents) {
+            Ok(manifest) => manifest,
+            Err(_) => return Err(error).wrap_err("unreadable transcript"),
+        };
+        Ok(Self {
+            ceremonies: manifest
+                .shards
+                .into_iter()
+                .map(|shard| CeremonySource::Shard(path.to_owned(), shard))
+                .collect(),
+            participant_ids: manifest.participant_ids,
+            participant_ecdsa_signatures: manifest.participant_ecdsa_signatures,
+            metadata: manifest.metadata,
+            sharded: true,
+        })
+    }
+
+    /// The transcript with the parsed ceremonies, and whether it is sharded.
+    fn assemble(self, transcripts: Vec<Transcript>) -> (BatchTranscript, bool) {
+        (
+            BatchTranscript {
+                transcripts,
+                participant_ids: self.participant_ids,
+                participant_ecdsa_signatures: self.participant_ecdsa_signatures,
+                metadata: self.metadata,
+            },
+            self.sharded,
+        )
+    }
+}
+
+/// Reads the decoded contents of a shard of the manifest at `manifest_path`,
+/// checking them against the manifest.
+fn read_shard(manifest_path: &Path, shard: &Shard, options: &Options) -> eyre::Result<Vec<u8>> {
+    ensure!(
+        Path::new(&shard.file).file_name() == Some(OsStr::new(&shard.file)),
+        "Shard {:?} is not in the directory of the manifest",
+        shard.file
+    );
+    let path = parent_dir(manifest_path).join(&shard.file);
+    let contents = fs::read(&path).wrap_err_with(|| format!("Cannot read shard {path:?}"))?;
+    let contents = decode_contents(contents, options)?;
+    ensure!(
+        hex::encode(Sha256::digest(&contents)) == shard.sha256,
+        "Shard {path:?} does not match the manifest"
+    );
+    Ok(contents)
+}
+
+/// Reads the manifest at `path`, if the file holds one.
+fn read_manifest(path: &Path, options: &Options) -> eyre::Result<Option<ShardManifest>> {
+    let contents = decode_contents(fs::read(path)?, options)?;
+    Ok(serde_json::from_slice(&contents).ok())
+}
+
+/// Writes every ceremony of the transcript to its own shard, then the
+/// manifest, and finally removes the shards no longer needed.
+fn write_sharded(
+    target_path: &Path,
+    work_path: &Path,
+    options: &Options,
+    transcript: &BatchTranscript,
+) -> eyre::Result<()> {
+    let replaced = read_manifest(target_path, options).ok().flatten();
+    let key = options.transcript_encryption_key.as_ref();
+    let shards = transcript
+        .transcripts
+        .iter()
+        .enumerate()
+        .map(|(index, ceremony)| {
+            let sha256 = json_hash(ceremony);
+            let path = shard_path(target_path, index, &sha256);
+            write_json(
+                &path,
+                work_path,
+                key,
+                options.transcript_compression_level,
+                options.transcript_integrity_trailer,
+                ceremony,
+            )?;
+            Ok(Shard {
+                file: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                num_g1_powers: ceremony.powers.g1.len(),
+                num_g2_powers: ceremony.powers.g2.len(),
+                sha256,
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let manifest = ShardManifest {
+        shards,
+        participant_ids: transcript.participant_ids.clone(),
+        participant_ecdsa_signatures: transcript.participant_ecdsa_signatures.clone(),
+        metadata: transcript.metadata.clone(),
+    };
+    write_json(
+        target_path,
+        work_path,
+        key,
+        options.transcript_compression_level,
+        options.transcript_integrity_trailer,
+        &manifest,
+    )?;
+    // A failed cleanup only leaves unused files behind.
+    if let Err(error) = remove_unused_shards(target_path, options, &manifest, replaced.as_ref()) {
+        error!(?error, "Could not remove unused transcript shards");
+    }
+    Ok(())
+}
+
+/// Removes the shards of the transcript file at `target_path` that neither
+/// the manifest, the one it replaced nor any backup refers to. The replaced
+/// manifest's shards are kept for requests that are still reading them.
+fn remove_unused_shards(
+    target_path: &Path,
+    options: &Options,
+    manifest: &ShardManifest,
+    replaced: Option<&ShardManifest>,
+) -> eyre::Result<()> {
+    let mut used = HashSet::new();
+    let backups = list_backups(target_path)?
+        .into_iter()
+        .filter_map(|(_, path)| read_manifest(&path, options).ok().flatten());
+    for kept in [Some(manifest.clone()), replaced.cloned()]
+        .into_iter()
+        .flatten()
+        .chain(backups)
+    {
+        used.extend(kept.shards.into_iter().map(|shard| shard.file));
+    }
+
+    let prefix = format!(
+        "{}.",
+        target_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    );
+    for entry in fs::read_dir(parent_dir(target_path))? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        if name.starts_with(&prefix) && name.ends_with(".shard") && !used.contains(name) {
+            fs::remove_file(&path)?;
+            info!(?path, "Removed unused transcript shard");
+        }
+    }
+    Ok(())
+}
+
+/// Streams the JSON encoding of a sharded transcript, reading one shard at a
+/// time rather than the whole transcript into memory.
+///
+/// # Errors
+///
+/// - when the manifest cannot be read. Shards that cannot be read end the
+///   stream with an error.
+pub async fn stream_sharded_transcript(
+    path: PathBuf,
+    options: &Options,
+) -> eyre::Result<impl Stream<Item = std::io::Result<Vec<u8>>>> {
+    let contents = read_transcript_bytes(path.clone(), options).await?;
+    let manifest: ShardManifest =
+        serde_json::from_slice(&contents).wrap_err("Transcript file is not a shard manifest")?;
+    let mut tail = b"],".to_vec();
+    let fields = serde_json::to_vec(&ManifestTail {
+        participant_ids:              &manifest.participant_ids,
+        participant_ecdsa_signatures: &manifest.participant_ecdsa_signatures,
+        metadata:                     &manifest.metadata,
+    })?;
+    // Continues the object opened before the ceremonies
+    tail.extend_from_slice(&fields[1..]);
+
+    let options = options.clone();
+    let shards =
+        stream::iter(manifest.shards.into_iter().enumerate()).then(move |(index, shard)| {
+            let path = path.clone();
+            let options = options.clone();
+            async move {
+                let contents =
+                    tokio::task::spawn_blocking(move || read_shard(&path, &shard, &options))
+                        .await?;
+                let contents = contents.map_err(|error| {
+                    std::io::Error::new(std::io::ErrorKind::Other, format!("{error:#}"))
+                })?;
+                Ok::<_, std::io::Error>(if index == 0 {
+                    contents
+                } else {
+                    [b",".as_slice(), &contents].concat()
+                })
+            }
+        });
+    Ok(stream::once(async { Ok(b"{\"transcripts\":[".to_vec()) })
+        .chain(shards)
+        .chain(stream::once(async { Ok(tail) })))
+}
+
+/// Computes the hex encoded SHA-256 hash of the JSON encoding of a
+/// transcript, as written to the transcript file before compression and
+/// encryption.
+pub fn transcript_hash(transcript: &BatchTranscript) -> String {
+    json_hash(transcript)
+}
+
+/// Computes the hex encoded SHA-256 hash of the pretty printed JSON encoding
+/// of `data`, without holding the encoding in memory.
+fn json_hash<T: Serialize>(data: &T) -> String {
+    let mut hasher = Sha256::new();
+    serde_json::to_writer_pretty(&mut hasher, data).expect("Cannot serialize transcript");
+    hex::encode(hasher.finalize())
+}
+
+/// Computes the hex encoded SHA-256 hash of a file.
+fn file_hash(path: &Path) -> eyre::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Asynchronously reads a JSON file from disk, decompressing it on the fly
+/// if it is compressed.
 pub async fn read_json_file<T: DeserializeOwned + Send + 'static>(path: PathBuf) -> T {
     let handle = tokio::task::spawn_blocking::<_, T>(|| {
         let f = std::fs::File::open(path).expect("can't access transcript file.");
-        let reader = std::io::BufReader::new(f);
-        serde_json::from_reader::<_, T>(reader).expect("unreadable transcript")
+        let mut reader = std::io::BufReader::new(f);
+        let is_compressed = reader
+            .fill_buf()
+            .expect("can't access transcript file.")
+            .starts_with(ZSTD_MAGIC);
+        if is_compressed {
+            let decoder =
+                zstd::Decoder::with_buffer(reader).expect("can't decompress transcript file.");
+            serde_json::from_reader::<_, T>(decoder).expect("unreadable transcript")
+        } else {
+            serde_json::from_reader::<_, T>(reader).expect("unreadable transcript")
+        }
     });
     handle.await.expect("can't read transcript")
 }
 
-/// Asynchroniously writes a JSON file to disk using a tempfile.
+/// Asynchroniously writes a JSON file to disk using a tempfile, compressing
+/// it if a compression level is given, encrypting it if a key is given and
+/// ending it with an integrity trailer if asked to. Unencrypted files are
+/// compressed while they are written. The tempfile is synced to disk and
+/// atomically renamed to the target.
 ///
 /// # Panics
 ///
 /// * Panics if writing fails.
-// TODO: Return result
 pub async fn write_json_file<T: Serialize + Send + Sync + 'static>(
     target_path: PathBuf,
     work_path: PathBuf,
-    data: Arc<RwLock<T>>,
+    key: Option<EncryptionKey>,
+    compression_level: Option<i32>,
+    with_trailer: bool,
+    data: Arc<T>,
 ) {
+    try_write_json_file(
+        target_path,
+        work_path,
+        key,
+        compression_level,
+        with_trailer,
+        data,
+    )
+    .await
+    .expect("Cannot write transcript");
+}
+
+/// Like [`write_json_file`], but returns an error if writing fails.
+///
+/// # Errors
+///
+/// - when the work file can't be written, or not be moved to the target.
+pub async fn try_write_json_file<T: Serialize + Send + Sync + 'static>(
+    target_path: PathBuf,
+    work_path: PathBuf,
+    key: Option<EncryptionKey>,
+    compression_level: Option<i32>,
+    with_trailer: bool,
+    data: Arc<T>,
+) -> eyre::Result<()> {
     let handle = tokio::task::spawn_blocking(move || {
-        let f = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&work_path)
-            .expect("Can't access work file.");
-        let guard = data.blocking_read();
-        serde_json::to_writer_pretty(&f, &*guard).expect("Cannot write transcript");
-        std::fs::rename(&work_path, &target_path).unwrap();
+        write_json(
+            &target_path,
+            &work_path,
+            key.as_ref(),
+            compression_level,
+            with_trailer,
+            &*data,
+        )
     });
-    handle.await.expect("Cannot write transcript");
+    handle.await?
+}
+
+/// Blocking part of [`try_write_json_file`].
+fn write_json<T: Serialize>(
+    target_path: &Path,
+    work_path: &Path,
+    key: Option<&EncryptionKey>,
+    compression_level: Option<i32>,
+    with_trailer: bool,
+    data: &T,
+) -> eyre::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(work_path)
+        .wrap_err("Can't access work file")?;
+    let mut f = HashingWriter::new(std::io::BufWriter::new(file));
+    match (key, compression_level) {
+        (Some(key), _) => {
+            let mut plaintext = serde_json::to_vec_pretty(data)?;
+            if let Some(level) = compression_level {
+                plaintext = zstd::stream::encode_all(plaintext.as_slice(), level)
+                    .wrap_err("Cannot compress transcript")?;
+            }
+            f.write_all(&key.encrypt(&plaintext))?;
+        }
+        (None, Some(level)) => {
+            let mut encoder =
+                zstd::Encoder::new(&mut f, level).wrap_err("Cannot compress transcript")?;
+            serde_json::to_writer_pretty(&mut encoder, data)?;
+            encoder.finish()?;
+        }
+        (None, None) => {
+            serde_json::to_writer_pretty(&mut f, data)?;
+        }
+    }
+    f.finish(with_trailer).and_then(|writer| {
+        writer
+            .into_inner()
+            .map_err(std::io::IntoInnerError::into_error)
+    })?;
+    persist_file(work_path, target_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_transcript;
+    use kzg_ceremony_crypto::{signature::identity::Identity, G2};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn rotates_and_restores_backups() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let options = Options {
+            transcript_backups:           2,
+            transcript_encryption_key:    None,
+            transcript_compression_level: None,
+            transcript_integrity_trailer: false,
+            transcript_shards:            false,
+        };
+        let sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        let mut transcript = test_transcript();
+
+        // Every write changes the transcript, so that all versions are distinct
+        let mut versions = vec![];
+        for _ in 0..4 {
+            transcript.participant_ids.push(Identity::None);
+            versions.push(transcript.clone());
+            write_transcript_file(
+                target.clone(),
+                work.clone(),
+                &options,
+                Arc::new(transcript.clone()),
+            )
+            .await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let mut backups = list_backups(&target).unwrap();
+        backups.sort_by_key(|(timestamp, _)| *timestamp);
+        assert_eq!(backups.len(), 2);
+
+        fs::write(&target, "corrupted").unwrap();
+        restore_backup(backups[1].1.clone(), target.clone(), work, &options, &sizes)
+            .await
+            .unwrap();
+        let restored = read_json_file::<BatchTranscript>(target).await;
+        assert_eq!(restored, versions[2]);
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_backup() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        let backup = backup_path(&target, 0, &"0".repeat(64));
+        fs::write(&backup, "{}").unwrap();
+
+        let options = Options {
+            transcript_backups:           0,
+            transcript_encryption_key:    None,
+            transcript_compression_level: None,
+            transcript_integrity_trailer: false,
+            transcript_shards:            false,
+        };
+        assert!(restore_backup(backup, target, work, &options, &sizes)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn encrypts_transcript_at_rest() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        let options = |key: Option<&str>| Options {
+            transcript_backups:           0,
+            transcript_encryption_key:    key
+                .map(|key| EncryptionKey::parse_from_cmd(key).unwrap()),
+            transcript_compression_level: None,
+            transcript_integrity_trailer: false,
+            transcript_shards:            false,
+        };
+        let key = options(Some(&"11".repeat(32)));
+        let other_key = options(Some(&format!("0x{}", "22".repeat(32))));
+
+        // Unencrypted transcripts are still accepted
+        let transcript =
+            read_or_create_transcript(target.clone(), work.clone(), &options(None), &sizes)
+                .await
+                .unwrap();
+        let expected = transcript.snapshot();
+        assert_eq!(
+            read_transcript(target.clone(), &key).await.unwrap(),
+            *expected
+        );
+
+        write_transcript_file(target.clone(), work, &key, expected.clone()).await;
+        assert!(fs::read(&target).unwrap().starts_with(ENCRYPTED_MAGIC));
+        assert_eq!(
+            read_transcript(target.clone(), &key).await.unwrap(),
+            *expected
+        );
+        assert!(read_transcript(target.clone(), &options(None))
+            .await
+            .is_err());
+        assert!(read_transcript(target, &other_key).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn compresses_transcript() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let options = |key: Option<&str>, level: Option<i32>| Options {
+            transcript_backups:           0,
+            transcript_encryption_key:    key
+                .map(|key| EncryptionKey::parse_from_cmd(key).unwrap()),
+            transcript_compression_level: level,
+            transcript_integrity_trailer: false,
+            transcript_shards:            false,
+        };
+        let plain = options(None, None);
+        let compressed = options(None, Some(3));
+        let encrypted = options(Some(&"11".repeat(32)), Some(3));
+        let transcript = Arc::new(test_transcript());
+
+        write_transcript_file(target.clone(), work.clone(), &plain, transcript.clone()).await;
+        let plain_size = fs::metadata(&target).unwrap().len();
+
+        write_transcript_file(
+            target.clone(),
+            work.clone(),
+            &compressed,
+            transcript.clone(),
+        )
+        .await;
+        assert!(fs::read(&target).unwrap().starts_with(ZSTD_MAGIC));
+        assert!(fs::metadata(&target).unwrap().len() < plain_size);
+        // Compressed transcripts are read regardless of the options
+        assert_eq!(
+            read_transcript(target.clone(), &plain).await.unwrap(),
+            *transcript
+        );
+        assert_eq!(
+            read_json_file::<BatchTranscript>(target.clone()).await,
+            *transcript
+        );
+
+        write_transcript_file(target.clone(), work, &encrypted, transcript.clone()).await;
+        assert!(fs::read(&target).unwrap().starts_with(ENCRYPTED_MAGIC));
+        assert_eq!(
+            read_transcript(target, &encrypted).await.unwrap(),
+            *transcript
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_truncated_transcript() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let options = |compression_level| Options {
+            transcript_backups:           0,
+            transcript_encryption_key:    None,
+            transcript_compression_level: compression_level,
+            transcript_integrity_trailer: true,
+            transcript_shards:            false,
+        };
+        let transcript = Arc::new(test_transcript());
+
+        for options in [options(None), options(Some(3))] {
+            write_transcript_file(target.clone(), work.clone(), &options, transcript.clone()).await;
+            assert!(!work.exists());
+            let contents = fs::read(&target).unwrap();
+            assert!(contents[contents.len() - TRAILER_SIZE..].starts_with(TRAILER_MAGIC));
+            assert_eq!(
+                read_transcript(target.clone(), &options).await.unwrap(),
+                *transcript
+            );
+
+            // Cut off inside the contents, and inside the trailer
+            for cut in [TRAILER_SIZE + 10, 10] {
+                fs::write(&target, &contents[..contents.len() - cut]).unwrap();
+                assert!(read_transcript(target.clone(), &options).await.is_err());
+            }
+            let mut corrupted = contents.clone();
+            corrupted[10] ^= 1;
+            fs::write(&target, corrupted).unwrap();
+            assert!(read_transcript(target.clone(), &options).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_backup() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let options = Options {
+            transcript_backups:           2,
+            transcript_encryption_key:    None,
+            transcript_compression_level: None,
+            transcript_integrity_trailer: true,
+            transcript_shards:            false,
+        };
+        let sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        let mut transcript = test_transcript();
+
+        let mut versions = vec![];
+        for _ in 0..3 {
+            transcript.participant_ids.push(Identity::None);
+            versions.push(transcript.clone());
+            write_transcript_file(
+                target.clone(),
+                work.clone(),
+                &options,
+                Arc::new(transcript.clone()),
+            )
+            .await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // The newest backup is corrupted as well, the one before is used
+        let mut backups = list_backups(&target).unwrap();
+        backups.sort_by_key(|(timestamp, _)| *timestamp);
+        fs::write(&backups[1].1, "corrupted").unwrap();
+        let contents = fs::read(&target).unwrap();
+        fs::write(&target, &contents[..contents.len() / 2]).unwrap();
+
+        let restored = read_or_create_transcript(target.clone(), work.clone(), &options, &sizes)
+            .await
+            .unwrap();
+        assert_eq!(*restored.snapshot(), versions[0]);
+        assert_eq!(
+            read_transcript(target.clone(), &options).await.unwrap(),
+            versions[0]
+        );
+
+        fs::write(&target, "corrupted").unwrap();
+        for (_, backup) in list_backups(&target).unwrap() {
+            fs::remove_file(backup).unwrap();
+        }
+        assert!(read_or_create_transcript(target, work, &options, &sizes)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn shards_transcript() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let work = dir.path().join("transcript.json.next");
+        let options = |transcript_shards| Options {
+            transcript_backups: 2,
+            transcript_encryption_key: None,
+            transcript_compression_level: Some(3),
+            transcript_integrity_trailer: false,
+            transcript_shards,
+        };
+        let sizes = CeremonySizes::parse_from_cmd("4,2:8,3").unwrap();
+        let shards = || {
+            let mut shards = fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.ends_with(".shard"))
+                .collect::<Vec<_>>();
+            shards.sort();
+            shards
+        };
+
+        // Transcripts without shards are sharded when opened
+        let mut transcript = BatchTranscript::new(&sizes.sizes);
+        write_transcript_file(
+            target.clone(),
+            work.clone(),
+            &options(false),
+            Arc::new(transcript.clone()),
+        )
+        .await;
+        let opened =
+            read_or_create_transcript(target.clone(), work.clone(), &options(true), &sizes)
+                .await
+                .unwrap();
+        assert_eq!(*opened.snapshot(), transcript);
+        assert!(read_manifest(&target, &options(true)).unwrap().is_some());
+        assert_eq!(shards().len(), 2);
+
+        // Shards of the replaced manifest and of the backups are kept, and
+        // unchanged ceremonies keep their shard
+        for _ in 0..3 {
+            transcript.transcripts[0].witness.pubkeys.push(G2::one());
+            transcript.participant_ids.push(Identity::None);
+            write_transcript_file(
+                target.clone(),
+                work.clone(),
+                &options(true),
+                Arc::new(transcript.clone()),
+            )
+            .await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(shards().len(), 4);
+        assert_eq!(
+            read_transcript(target.clone(), &options(true))
+                .await
+                .unwrap(),
+            transcript
+        );
+
+        let streamed = stream_sharded_transcript(target.clone(), &options(true))
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .concat()
+            .await;
+        assert_eq!(
+            serde_json::from_slice::<BatchTranscript>(&streamed).unwrap(),
+            transcript
+        );
+
+        let manifest = read_manifest(&target, &options(true)).unwrap().unwrap();
+        fs::write(dir.path().join(&manifest.shards[1].file), "{}").unwrap();
+        assert!(read_transcript(target, &options(true)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn parses_ceremonies_in_parallel() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("transcript.json");
+        let options = Options {
+            transcript_backups:           0,
+            transcript_encryption_key:    None,
+            transcript_compression_level: None,
+            transcript_integrity_trailer: false,
+            transcript_shards:            false,
+        };
+        let sizes = CeremonySizes::parse_from_cmd("4,2:8,3:16,4").unwrap();
+        let mut transcript = BatchTranscript::new(&sizes.sizes);
+        transcript.transcripts[1].witness.pubkeys.push(G2::one());
+        transcript.participant_ids.push(Identity::None);
+        fs::write(&target, serde_json::to_vec(&transcript).unwrap()).unwrap();
+        assert_eq!(
+            read_transcript(target.clone(), &options).await.unwrap(),
+            transcript
+        );
+
+        // A broken ceremony fails the whole transcript
+        let mut value = serde_json::to_value(&transcript).unwrap();
+        value["transcripts"][2]["numG1Powers"] = "many".into();
+        fs::write(&target, value.to_string()).unwrap();
+        assert!(read_transcript(target, &options).await.is_err());
+    }
+
+    #[test]
+    fn parses_encryption_key() {
+        assert!(EncryptionKey::parse_from_cmd(&"ab".repeat(32)).is_ok());
+        assert!(EncryptionKey::parse_from_cmd(&"ab".repeat(31)).is_err());
+        assert!(EncryptionKey::parse_from_cmd("not hex").is_err());
+    }
+
+    #[test]
+    fn validates_ceremony_sizes() {
+        let mut policy = SizePolicy {
+            min_g1_powers:           4,
+            max_g1_powers:           32_768,
+            max_ceremony_memory_mib: 1,
+            allow_nonstandard_sizes: false,
+        };
+        let sizes = |cmd| CeremonySizes::parse_from_cmd(cmd).unwrap();
+        assert!(sizes("4,2:8,2").validate(&policy).is_ok());
+        assert!(sizes("4,2:6,2").validate(&policy).is_err());
+        assert!(sizes("2,2").validate(&policy).is_err());
+        assert!(sizes("65536,2").validate(&policy).is_err());
+        assert!(sizes("32768,65").validate(&policy).is_err());
+        policy.max_ceremony_memory_mib = 64;
+        assert!(sizes("32768,65").validate(&policy).is_ok());
+
+        policy.allow_nonstandard_sizes = true;
+        assert!(sizes("6,2:65536,2").validate(&policy).is_ok());
+    }
+
+    #[test]
+    fn describes_ceremony_sizes() {
+        let sizes = CeremonySizes::parse_from_cmd("8,2:32768,65")
+            .unwrap()
+            .describe();
+        assert_eq!(sizes[0].estimated_memory_bytes, 4_032);
+        assert_eq!(sizes[0].estimated_memory, "3.9 KiB");
+        assert_eq!(sizes[1].num_g1_powers, 32_768);
+        assert_eq!(sizes[1].estimated_memory, "10.5 MiB");
+        assert_eq!(human_bytes(1000), "1000 B");
+    }
+
+    #[test]
+    fn describes_contribution_spec() {
+        let sizes = CeremonySizes::parse_from_cmd("4,2:8,3").unwrap();
+        let spec = sizes.contribution_spec();
+        assert_eq!(spec.len(), 2);
+        assert_eq!(spec[1].num_g1_powers, 8);
+        assert_eq!(spec[1].num_g2_powers, 3);
+        assert_eq!(spec[0].g1_encoding.hex_length, 98);
+        assert_eq!(spec[0].g2_encoding.hex_length, 194);
+        // The whole contribution fits its parts
+        assert!(
+            spec.iter()
+                .map(|ceremony| ceremony.max_encoded_bytes)
+                .sum::<usize>()
+                < sizes.max_contribution_size()
+        );
+    }
 }
@@ -1,13 +1,15 @@
+use crate::Engine;
 use clap::Parser;
 use ethers_core::{
     rand::thread_rng,
-    types::{RecoveryMessage, H160},
+    types::{RecoveryMessage, H160, H256},
     utils::to_checksum,
 };
 use ethers_signers::{LocalWallet, Signer};
-use eyre::Result;
-use kzg_ceremony_crypto::ErrorCode;
+use eyre::{ensure, Result};
+use kzg_ceremony_crypto::{signature::BlsSignature, Engine as _, Entropy, ErrorCode, Tau, G2};
 use serde::Serialize;
+use serde_json::{json, Value};
 use std::{fmt, sync::Arc};
 use strum::IntoStaticStr;
 use thiserror::Error;
@@ -19,11 +21,41 @@ pub struct Options {
     /// Ethereum private key to use for signing receipts.
     #[clap(long, env)]
     pub signing_key: Option<String>,
+
+    /// Hex encoded 32 byte secret to derive a BLS key from, to sign receipts
+    /// with as well. BLS signatures of all receipts can be aggregated and
+    /// checked with a single pairing.
+    #[clap(long, env)]
+    pub bls_signing_key: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct Signature(String);
 
+impl Signature {
+    pub const fn from_hex(hex: String) -> Self {
+        Self(hex)
+    }
+
+    pub fn as_hex(&self) -> &str {
+        &self.0
+    }
+
+    fn decode(&self) -> Result<ethers_core::types::Signature, SignatureError> {
+        let h = hex::decode(&self.0).map_err(|_| SignatureError::InvalidToken)?;
+        ethers_core::types::Signature::try_from(h.as_ref())
+            .map_err(|_| SignatureError::InvalidSignature)
+    }
+
+    /// The Ethereum address that signed `message`, as with `personal_sign`.
+    pub fn recover(&self, message: &str) -> Result<[u8; 20], SignatureError> {
+        self.decode()?
+            .recover(RecoveryMessage::Data(message.as_bytes().to_owned()))
+            .map(|address| address.0)
+            .map_err(|_| SignatureError::InvalidSignature)
+    }
+}
+
 #[derive(Debug, Error, IntoStaticStr)]
 pub enum SignatureError {
     #[error("couldn't sign the receipt")]
@@ -42,6 +74,28 @@ impl ErrorCode for SignatureError {
 
 pub struct Keys {
     wallet: LocalWallet,
+    bls:    Option<BlsKey>,
+}
+
+struct BlsKey {
+    secret: Tau,
+    pubkey: G2,
+}
+
+impl BlsKey {
+    fn new(secret: &str) -> Result<Self> {
+        let bytes = hex::decode(secret.trim_start_matches("0x"))?;
+        ensure!(bytes.len() == 32, "BLS signing key must be 32 bytes");
+        let mut entropy = [0; 32];
+        entropy.copy_from_slice(&bytes);
+        let secret = Engine::generate_tau(&Entropy::new(entropy));
+        let mut powers = [G2::one(), G2::one()];
+        Engine::add_tau_g2(&secret, &mut powers)?;
+        Ok(Self {
+            secret,
+            pubkey: powers[1],
+        })
+    }
 }
 
 pub type SharedKeys = Arc<Keys>;
@@ -66,18 +120,27 @@ impl Serialize for Address {
 
 impl Keys {
     pub fn new(options: &Options) -> Result<Self> {
-        match &options.signing_key {
+        let wallet = match &options.signing_key {
             Some(signing_key) => {
                 let wallet = signing_key.parse::<LocalWallet>()?;
                 info!(address = ?wallet.address(), "Wallet created from the provided signing key");
-                Ok(Self { wallet })
+                wallet
             }
             None => {
                 let wallet = LocalWallet::new(&mut thread_rng());
                 warn!(address = ?wallet.address(), "Random wallet created. Make sure to provide a signing key in prod!");
-                Ok(Self { wallet })
+                wallet
             }
+        };
+        let bls = options
+            .bls_signing_key
+            .as_deref()
+            .map(BlsKey::new)
+            .transpose()?;
+        if let Some(bls) = &bls {
+            info!(pubkey = %hex::encode(bls.pubkey.0), "Receipts are also signed with BLS");
         }
+        Ok(Self { wallet, bls })
     }
 
     pub async fn sign(&self, message: &str) -> Result<Signature, SignatureError> {
@@ -89,12 +152,23 @@ impl Keys {
         Ok(Signature(hex::encode::<Vec<u8>>(signature.into())))
     }
 
+    /// Signs a prehashed message, such as an EIP-712 signing hash.
+    pub fn sign_hash(&self, hash: H256) -> Signature {
+        let signature = self.wallet.sign_hash(hash);
+        Signature(hex::encode::<Vec<u8>>(signature.into()))
+    }
+
     #[allow(unused)]
+    pub fn verify_hash(&self, hash: H256, signature: &Signature) -> Result<(), SignatureError> {
+        signature
+            .decode()?
+            .verify(RecoveryMessage::Hash(hash), self.wallet.address())
+            .map_err(|_| SignatureError::InvalidToken)
+    }
+
     pub fn verify(&self, message: &str, signature: &Signature) -> Result<(), SignatureError> {
-        let h = hex::decode(&signature.0).map_err(|_| SignatureError::InvalidToken)?;
-        let signature = ethers_core::types::Signature::try_from(h.as_ref())
-            .map_err(|_| SignatureError::InvalidSignature)?;
         signature
+            .decode()?
             .verify(
                 RecoveryMessage::Data(message.as_bytes().to_owned()),
                 self.wallet.address(),
@@ -105,6 +179,45 @@ impl Keys {
     pub fn address(&self) -> Address {
         Address(self.wallet.address())
     }
+
+    /// Signs a message with the BLS key, if there is one.
+    pub fn sign_bls(&self, message: &str) -> Option<BlsSignature> {
+        self.bls
+            .as_ref()
+            .map(|bls| BlsSignature::sign::<Engine>(message.as_bytes(), &bls.secret))
+    }
+
+    /// The public key BLS signatures verify under, in G2.
+    pub fn bls_pubkey(&self) -> Option<G2> {
+        self.bls.as_ref().map(|bls| bls.pubkey)
+    }
+
+    /// The public keys as JWK set. The signing key is listed as `ES256K`,
+    /// although messages are signed as Ethereum personal messages, and the
+    /// BLS key, if any, with curve `BLS12381G2`.
+    pub fn jwks(&self) -> Value {
+        let encode = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        let point = self.wallet.signer().verifying_key().to_encoded_point(false);
+        let mut keys = vec![json!({
+            "kty": "EC",
+            "crv": "secp256k1",
+            "alg": "ES256K",
+            "use": "sig",
+            "kid": self.address().to_string(),
+            "x": point.x().map(|x| encode(x.as_slice())),
+            "y": point.y().map(|y| encode(y.as_slice())),
+        })];
+        if let Some(pubkey) = self.bls_pubkey() {
+            keys.push(json!({
+                "kty": "OKP",
+                "crv": "BLS12381G2",
+                "use": "sig",
+                "kid": hex::encode(pubkey.0),
+                "x": encode(&pubkey.0),
+            }));
+        }
+        json!({ "keys": keys })
+    }
 }
 
 #[cfg(test)]
@@ -134,4 +247,25 @@ mod tests {
         let result = keys.verify(&message, &signature);
         println!("result {result:?}");
     }
+
+    #[test]
+    fn signs_with_bls_key() {
+        let keys = Keys::new(&Options::parse_from(Vec::<&str>::new())).unwrap();
+        assert!(keys.sign_bls("message").is_none());
+        assert!(keys.bls_pubkey().is_none());
+
+        let options = Options::parse_from(["", "--bls-signing-key", &"01".repeat(32)]);
+        let keys = Keys::new(&options).unwrap();
+        let pubkey = keys.bls_pubkey().unwrap();
+        let signature = keys.sign_bls("message").unwrap();
+        assert!(signature.verify::<Engine>(b"message", pubkey));
+        assert!(!signature.verify::<Engine>(b"other message", pubkey));
+        assert_eq!(
+            Keys::new(&options).unwrap().bls_pubkey(),
+            Some(pubkey),
+            "BLS key is derived deterministically"
+        );
+
+        assert!(Keys::new(&Options::parse_from(["", "--bls-signing-key", "0102"])).is_err());
+    }
 }
@@ -7,21 +7,58 @@
 #![allow(clippy::module_name_repetitions)]
 
 use crate::{
-    api::v1::{
-        auth::{auth_client_link, eth_callback, github_callback},
-        contribute::{contribute, contribute_abort},
-        info::{current_state, status},
-        lobby::try_contribute,
+    access::AccessLists,
+    announcement::Announcement,
+    api::{
+        checks::{AbuseChecks, AbuseChecksLayer, AuthCaptcha},
+        fingerprint::{SessionFingerprintLayer, SessionFingerprints},
+        shedding::{LoadShedder, LoadSheddingLayer},
+        timeouts::RouteTimeouts,
+        v1::{
+            admin::{
+                dashboard, export_receipts, mint_invitations, set_announcement, set_lobby_params,
+            },
+            auth::{
+                auth_client_link, discord_callback, email_confirm, email_start, eth_callback,
+                github_callback, passkey_login_finish, passkey_login_start,
+                passkey_register_finish, passkey_register_start, twitter_callback,
+            },
+            contribute::{contribute, contribute_abort, contribute_confirm, contribute_extend},
+            info::{
+                contribution_spec, current_state, lobby_stats, region_stats, report, selection,
+                selection_log, sequencer, signed_status, status, storage, transcript_head,
+                witness_segment, SharedStatusSequence,
+            },
+            lobby::{defer, join, leave, ping, try_contribute},
+            receipt::{receipt_mine, receipt_qr, receipt_verify},
+        },
     },
+    cache::InfoCache,
+    ceremonies::CeremonyConfig,
+    clock::ClockSkewLayer,
+    commands::Command,
+    completion::Completion,
+    confirmation::SharedPendingContributions,
+    consistency::check_peers_on_interval,
+    forecast::Forecast,
     io::{read_or_create_transcript, CeremonySizes},
     keys::Keys,
-    lobby::{clear_lobby_on_interval, SharedLobbyState},
+    limits::BodyLimits,
+    lobby::{clear_lobby_on_interval, persist_lobby_on_change, SharedLobbyState},
+    mirror::Mirrors,
     oauth::{
-        eth_oauth_client, github_oauth_client, EthAuthOptions, GithubAuthOptions, SharedAuthState,
+        discord_oauth_client, eth_oauth_client, github_oauth_client, twitter_oauth_client,
+        DiscordAuthOptions, EmailOptions, EmailVerifier, EthAuthOptions, GithubAuthOptions,
+        PasskeyAuth, PasskeyOptions, PseudonymOptions, SharedAuthState, TwitterAuthOptions,
     },
+    proxy::OAuthHttpClient,
+    report::sample_lobby_size,
     sessions::{SessionId, SessionInfo},
+    staging::{replay_staged, retry_failed_writes, TranscriptWriter},
     storage::storage_client,
-    util::parse_url,
+    transcript::TranscriptStore,
+    util::{parse_url, Secret},
+    verifier::Verifier,
 };
 use axum::{
     extract::{DefaultBodyLimit, Extension},
@@ -32,46 +69,88 @@ use axum::{
 };
 use clap::Parser;
 use cli_batteries::await_shutdown;
-use eyre::Result as EyreResult;
+use eyre::{ensure, Result as EyreResult, WrapErr};
 use http::StatusCode;
 use hyper::server::conn::AddrIncoming;
-use kzg_ceremony_crypto::BatchTranscript;
 use std::{
+    collections::HashSet,
+    net::SocketAddr,
     path::PathBuf,
     sync::{atomic::AtomicUsize, Arc},
 };
-use tokio::sync::RwLock;
-use tower_http::{
-    cors::CorsLayer,
-    limit::RequestBodyLimitLayer,
-    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
-};
-use tracing::{debug, info, Level};
+use tower_http::cors::CorsLayer;
+use tracing::{debug, info};
 use url::Url;
 
+mod access;
+mod announcement;
 mod api;
+pub mod api_types;
+mod archive;
+mod attempts;
+#[cfg(feature = "bench")]
+mod bench;
+mod cache;
+mod ceremonies;
+#[cfg(any(test, feature = "client"))]
+pub mod client;
+mod client_version;
+mod clock;
+mod commands;
+mod completion;
+mod config;
+mod confirmation;
+mod consistency;
+mod forecast;
+mod guard;
+mod inclusion;
+mod invitations;
 pub mod io;
 mod keys;
+mod limits;
 mod lobby;
+mod logging;
+mod mirror;
 mod oauth;
+mod proxy;
+mod quarantine;
 mod receipt;
+mod receipt_export;
+mod regions;
+mod report;
+mod reporting;
 mod sessions;
+mod share;
+mod staging;
 mod storage;
-#[cfg(test)]
+#[cfg(any(test, feature = "deterministic"))]
 pub mod test_util;
+mod timing;
+mod tls;
+mod transcript;
 mod util;
+mod verifier;
+mod wal;
+mod webhooks;
+
+pub use crate::config::apply_config_file;
 
 pub type Engine = kzg_ceremony_crypto::DefaultEngine;
-pub type SharedTranscript = Arc<RwLock<BatchTranscript>>;
+pub type SharedTranscript = Arc<TranscriptStore>;
 pub type SharedCeremonyStatus = Arc<AtomicUsize>;
 
 pub const DEFAULT_CEREMONY_SIZES: &str = "4096,65:8192,65:16384,65:32768,65";
-pub const MAX_CONTRIBUTION_SIZE: usize = 10_485_760; // 10MB
 
 #[derive(Clone, Debug, PartialEq, Eq, Parser)]
 #[group(skip)]
 pub struct Options {
-    /// API Server url to bind
+    /// TOML file with options to use where neither a flag nor an environment
+    /// variable sets them. Keys are the flag names, e.g.
+    /// `lobby_max_deferral = 60`.
+    #[clap(long, env)]
+    pub config: Option<PathBuf>,
+
+    /// API Server url to bind. Use `https://` when serving with TLS.
     #[clap(long, env, default_value = "http://127.0.0.1:3000/")]
     pub server: Url,
 
@@ -84,10 +163,36 @@ pub struct Options {
     #[clap(flatten)]
     pub ethereum: EthAuthOptions,
 
+    #[clap(flatten)]
+    pub twitter: TwitterAuthOptions,
+
+    #[clap(flatten)]
+    pub discord: DiscordAuthOptions,
+
+    #[clap(flatten)]
+    pub pseudonym: PseudonymOptions,
+
+    #[clap(flatten)]
+    pub passkey: PasskeyOptions,
+
+    #[clap(flatten)]
+    pub email: EmailOptions,
+
+    /// Bearer token for the admin endpoints, such as `/admin/dashboard`. They
+    /// are disabled when not set.
+    #[clap(long, env)]
+    pub admin_token: Option<Secret>,
+
     /// Allow multiple contributions from the same participant.
     #[clap(long, env, default_value = "false")]
     pub multi_contribution: bool,
 
+    /// Accept contributions whose BLS signatures don't commit to the
+    /// participant's identity, for clients that predate the check. Such
+    /// signatures are dropped from the transcript.
+    #[clap(long, env, default_value = "false")]
+    pub allow_unbound_contributions: bool,
+
     /// Storage location for the ceremony transcript json file.
     #[clap(long, env, default_value = "./transcript.json")]
     pub transcript_file: PathBuf,
@@ -102,20 +207,136 @@ pub struct Options {
     #[clap(long, env, value_parser=CeremonySizes::parse_from_cmd, default_value=DEFAULT_CEREMONY_SIZES)]
     pub ceremony_sizes: CeremonySizes,
 
+    #[clap(flatten)]
+    pub size_policy: io::SizePolicy,
+
     #[clap(flatten)]
     pub lobby: lobby::Options,
 
     #[clap(flatten)]
     pub storage: storage::Options,
+
+    #[clap(flatten)]
+    pub io: io::Options,
+
+    #[clap(flatten)]
+    pub staging: staging::Options,
+
+    #[clap(flatten)]
+    pub completion: completion::Options,
+
+    #[clap(flatten)]
+    pub forecast: forecast::Options,
+
+    #[clap(flatten)]
+    pub invitations: invitations::Options,
+
+    #[clap(flatten)]
+    pub mirror: mirror::Options,
+
+    #[clap(flatten)]
+    pub logging: logging::Options,
+
+    #[clap(flatten)]
+    pub proxy: proxy::Options,
+
+    #[clap(flatten)]
+    pub quarantine: quarantine::Options,
+
+    #[clap(flatten)]
+    pub confirmation: confirmation::Options,
+
+    #[clap(flatten)]
+    pub reporting: reporting::Options,
+
+    #[clap(flatten)]
+    pub verifier: verifier::Options,
+
+    #[clap(flatten)]
+    pub limits: limits::Options,
+
+    #[clap(flatten)]
+    pub webhooks: webhooks::Options,
+
+    #[clap(flatten)]
+    pub cache: cache::Options,
+
+    #[clap(flatten)]
+    pub tls: tls::Options,
+
+    #[clap(flatten)]
+    pub access: access::Options,
+
+    #[clap(flatten)]
+    pub regions: regions::Options,
+
+    #[clap(flatten)]
+    pub attempts: attempts::Options,
+
+    #[clap(flatten)]
+    pub share: share::Options,
+
+    #[clap(flatten)]
+    pub timing: timing::Options,
+
+    #[clap(flatten)]
+    pub client_version: client_version::Options,
+
+    #[clap(flatten)]
+    pub checks: api::checks::Options,
+
+    #[clap(flatten)]
+    pub fingerprint: api::fingerprint::Options,
+
+    #[clap(flatten)]
+    pub shedding: api::shedding::Options,
+
+    #[clap(flatten)]
+    pub timeouts: api::timeouts::Options,
+
+    #[clap(flatten)]
+    pub sessions: sessions::Options,
+
+    #[clap(flatten)]
+    pub consistency: consistency::Options,
+
+    /// Additional ceremonies to host, each with its own transcript, sizes,
+    /// lobby and auth settings. The format is
+    /// `NAME=ARGS_FILE[,NAME=ARGS_FILE]*` where `ARGS_FILE` holds the
+    /// ceremony's command line arguments, one per line. A ceremony is
+    /// served under `/ceremony/NAME/`, so its auth redirect urls need to
+    /// include that prefix.
+    #[clap(long, env, value_delimiter = ',', value_parser = CeremonyConfig::parse_from_cmd)]
+    pub ceremonies: Vec<CeremonyConfig>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 }
 
 #[allow(clippy::missing_errors_doc)]
 pub async fn async_main(options: Options) -> EyreResult<()> {
     debug!(?options, "Options");
 
-    let addr = options.server.clone();
+    if let Some(command) = options.command.clone() {
+        return command.run(&options).await;
+    }
+
+    let url = options.server.clone();
+    let tls_config = tls::server_config(&options.tls)?;
+    ensure!(
+        tls_config.is_some() == (url.scheme() == "https"),
+        "Server url {} must use https:// exactly when a TLS certificate is configured",
+        url
+    );
+
+    if let Some(tls_config) = tls_config {
+        let (addr, app) = app(options).await?;
+        info!("Listening on {}", url);
+        return tls::serve(&addr, tls_config, app, await_shutdown()).await;
+    }
+
     let server = start_server(options).await?;
-    info!("Listening on http://{}{}", server.local_addr(), addr.path());
+    info!("Listening on http://{}{}", server.local_addr(), url.path());
     server.with_graceful_shutdown(await_shutdown()).await?;
     Ok(())
 }
@@ -124,23 +345,159 @@ pub async fn async_main(options: Options) -> EyreResult<()> {
 pub async fn start_server(
     options: Options,
 ) -> EyreResult<Server<AddrIncoming, IntoMakeService<Router>>> {
+    let (addr, app) = app(options).await?;
+    let server = Server::try_bind(&addr)?.serve(app.into_make_service());
+    Ok(server)
+}
+
+/// Builds the routes of all ceremonies, and returns them with the address to
+/// serve them on.
+async fn app(options: Options) -> EyreResult<(SocketAddr, Router)> {
     info!(size=?options.ceremony_sizes, "Starting sequencer for KZG ceremony.");
 
+    let http_client = proxy::http_client(&options.proxy)?;
+    reporting::init(&options.reporting, http_client.clone());
+    webhooks::init(&options.webhooks, http_client.clone());
+    verifier::init(&options.verifier);
+
+    let mut app = ceremony_app(&options, http_client.clone()).await?;
+
+    let mut names = HashSet::new();
+    for ceremony in &options.ceremonies {
+        ensure!(
+            names.insert(&ceremony.name),
+            "Ceremony {} is defined more than once",
+            ceremony.name
+        );
+        let ceremony_options = ceremony.load(&options)?;
+        info!(name = %ceremony.name, size=?ceremony_options.ceremony_sizes, "Hosting additional ceremony.");
+        app = app.nest(
+            &format!("/ceremony/{}", ceremony.name),
+            ceremony_app(&ceremony_options, http_client.clone()).await?,
+        );
+    }
+
+    let (addr, prefix) = parse_url(&options.server)?;
+    let app = Router::new()
+        .nest(prefix, app)
+        .fallback(handle_404.into_service());
+    Ok((addr, logging::trace_requests(app, &options.logging)))
+}
+
+/// Builds the routes of a single ceremony, with its own transcript, lobby and
+/// auth state.
+async fn ceremony_app(options: &Options, http_client: reqwest::Client) -> EyreResult<Router> {
     let keys = Arc::new(Keys::new(&options.keys)?);
+    let verifier = Arc::new(Verifier::new(&options.verifier)?);
+    let passkeys = Arc::new(PasskeyAuth::new(&options.passkey)?);
+    let emails = Arc::new(EmailVerifier::new(&options.email)?);
+    let auth_captcha = Arc::new(AuthCaptcha::new(&options.checks, http_client.clone())?);
+    let access_lists = Arc::new(AccessLists::new(&options.access)?);
+    let mirrors = Arc::new(Mirrors::new(&options.mirror, http_client.clone())?);
+    options.ceremony_sizes.validate(&options.size_policy)?;
+    ensure!(
+        !options.io.transcript_shards || options.mirror.transcript_mirrors.is_empty(),
+        "Sharded transcripts can't be mirrored"
+    );
 
     let transcript = read_or_create_transcript(
         options.transcript_file.clone(),
         options.transcript_in_progress_file.clone(),
+        &options.io,
         &options.ceremony_sizes,
     )
     .await?;
+    if options.verifier.verify_on_start {
+        info!("Verifying the transcript");
+        let snapshot = transcript.snapshot();
+        tokio::task::spawn_blocking(move || snapshot.verify_self::<Engine>())
+            .await?
+            .wrap_err("Transcript failed verification, refusing to start")?;
+        info!("Transcript verified");
+    }
+
+    let storage = storage_client(&options.storage).await?;
+    let replayed = replay_staged(&transcript, &storage).await?;
+    let transcript_writer = Arc::new(TranscriptWriter::new(
+        options,
+        transcript.clone(),
+        storage.clone(),
+    ));
+    tokio::spawn(retry_failed_writes(
+        transcript_writer.clone(),
+        mirrors.clone(),
+        options.staging.clone(),
+    ));
+    if replayed > 0 {
+        info!(replayed, "Added staged contributions to the transcript");
+        transcript_writer.write_or_retry().await;
+    }
+    let recovered = wal::recover(&storage, &transcript).await?;
+    if recovered > 0 {
+        info!(
+            recovered,
+            "Recovered operations cut short by the last shutdown"
+        );
+    }
+
+    // Bring mirrors that were added or were down up to date.
+    tokio::spawn({
+        let mirrors = mirrors.clone();
+        let path = options.transcript_file.clone();
+        async move { mirrors.push_file(&path).await }
+    });
 
-    let ceremony_status = {
-        let lock = transcript.read().await;
-        Arc::new(AtomicUsize::new(lock.num_participants()))
-    };
+    let ceremony_status = Arc::new(AtomicUsize::new(transcript.snapshot().num_participants()));
     let lobby_state = SharedLobbyState::new(options.lobby.clone());
+    if !options.lobby.lobby_restore_max_age.is_zero() {
+        let restored = lobby_state.restore_from(&storage).await?;
+        info!(restored, "Restored saved lobby");
+        tokio::spawn(persist_lobby_on_change(
+            lobby_state.clone(),
+            storage.clone(),
+        ));
+    }
+    let completion = Arc::new(Completion::new(
+        &options.completion,
+        transcript.snapshot().num_participants(),
+    ));
+    if completion.is_complete() {
+        info!("Ceremony is complete, the lobby stays closed");
+        lobby_state.close().await;
+    }
+    let forecast = Arc::new(Forecast::new(
+        &options.forecast,
+        options.completion.target_participants,
+        storage
+            .contribution_times()
+            .await?
+            .into_iter()
+            .map(|(_, finished_at)| finished_at),
+    ));
+    let announcement = Arc::new(Announcement::load(&storage).await?);
     let auth_state = SharedAuthState::default();
+    let limits = BodyLimits::new(&options.limits, &options.ceremony_sizes);
+    let timeouts = RouteTimeouts::new(&options.timeouts);
+    let info_cache = Arc::new(InfoCache::new(&options.cache));
+    let checks = AbuseChecksLayer(Arc::new(AbuseChecks::new(
+        &options.checks,
+        &options.client_version,
+        &options.fingerprint,
+        access_lists.clone(),
+        lobby_state.clone(),
+        http_client.clone(),
+    )?));
+
+    let fingerprints = SessionFingerprintLayer(Arc::new(SessionFingerprints::new(
+        &options.fingerprint,
+        lobby_state.clone(),
+    )));
+    let shedding = LoadSheddingLayer(Arc::new(LoadShedder::new(
+        &options.shedding,
+        verifier.clone(),
+        transcript.clone(),
+        transcript_writer.clone(),
+    )));
 
     // Spawn automatic queue flusher -- flushes those in the lobby whom have not
     // pinged in a considerable amount of time
@@ -148,43 +505,307 @@ pub async fn start_server(
         lobby_state.clone(),
         options.lobby.clone(),
     ));
+    tokio::spawn(sample_lobby_size(lobby_state.clone(), storage.clone()));
+    tokio::spawn(check_peers_on_interval(
+        options.consistency.clone(),
+        http_client.clone(),
+        transcript.clone(),
+    ));
+    #[cfg(unix)]
+    if let Some(path) = options.config.clone() {
+        tokio::spawn(config::reload_on_hangup(path, lobby_state.clone()));
+    }
 
     let app = Router::new()
-        .route("/hello_world", get(hello_world))
-        .route("/auth/request_link", get(auth_client_link))
-        .route("/auth/callback/github", get(github_callback))
-        .route("/auth/callback/eth", get(eth_callback))
-        .route("/lobby/try_contribute", post(try_contribute))
-        .route("/contribute", post(contribute))
-        .route("/contribute/abort", post(contribute_abort))
-        .route("/info/status", get(status))
-        .route("/info/current_state", get(current_state))
+        .route(
+            "/hello_world",
+            get(hello_world)
+                .layer(limits.layer("/hello_world"))
+                .layer(timeouts.layer("/hello_world")),
+        )
+        .route(
+            "/auth/request_link",
+            get(auth_client_link)
+                .layer(limits.layer("/auth/request_link"))
+                .layer(timeouts.layer("/auth/request_link")),
+        )
+        .route(
+            "/auth/callback/github",
+            get(github_callback)
+                .layer(limits.layer("/auth/callback/github"))
+                .layer(timeouts.layer("/auth/callback/github")),
+        )
+        .route(
+            "/auth/callback/eth",
+            get(eth_callback)
+                .layer(limits.layer("/auth/callback/eth"))
+                .layer(timeouts.layer("/auth/callback/eth")),
+        )
+        .route(
+            "/auth/callback/twitter",
+            get(twitter_callback)
+                .layer(limits.layer("/auth/callback/twitter"))
+                .layer(timeouts.layer("/auth/callback/twitter")),
+        )
+        .route(
+            "/auth/callback/discord",
+            get(discord_callback)
+                .layer(limits.layer("/auth/callback/discord"))
+                .layer(timeouts.layer("/auth/callback/discord")),
+        )
+        .route(
+            "/auth/passkey/register/start",
+            post(passkey_register_start)
+                .layer(limits.layer("/auth/passkey/register/start"))
+                .layer(timeouts.layer("/auth/passkey/register/start")),
+        )
+        .route(
+            "/auth/passkey/register/finish",
+            post(passkey_register_finish)
+                .layer(limits.layer("/auth/passkey/register/finish"))
+                .layer(timeouts.layer("/auth/passkey/register/finish")),
+        )
+        .route(
+            "/auth/passkey/login/start",
+            post(passkey_login_start)
+                .layer(limits.layer("/auth/passkey/login/start"))
+                .layer(timeouts.layer("/auth/passkey/login/start")),
+        )
+        .route(
+            "/auth/passkey/login/finish",
+            post(passkey_login_finish)
+                .layer(limits.layer("/auth/passkey/login/finish"))
+                .layer(timeouts.layer("/auth/passkey/login/finish")),
+        )
+        .route(
+            "/auth/email/start",
+            post(email_start)
+                .layer(limits.layer("/auth/email/start"))
+                .layer(timeouts.layer("/auth/email/start")),
+        )
+        .route(
+            "/auth/email/confirm",
+            post(email_confirm)
+                .layer(limits.layer("/auth/email/confirm"))
+                .layer(timeouts.layer("/auth/email/confirm")),
+        )
+        .route(
+            "/lobby/join",
+            post(join)
+                .layer(limits.layer("/lobby/join"))
+                .layer(timeouts.layer("/lobby/join"))
+                .layer(checks.clone()),
+        )
+        .route(
+            "/lobby/leave",
+            post(leave)
+                .layer(limits.layer("/lobby/leave"))
+                .layer(timeouts.layer("/lobby/leave")),
+        )
+        .route(
+            "/lobby/ping",
+            post(ping)
+                .layer(limits.layer("/lobby/ping"))
+                .layer(timeouts.layer("/lobby/ping")),
+        )
+        .route(
+            "/lobby/defer",
+            post(defer)
+                .layer(limits.layer("/lobby/defer"))
+                .layer(timeouts.layer("/lobby/defer")),
+        )
+        .route(
+            "/lobby/try_contribute",
+            post(try_contribute)
+                .layer(limits.layer("/lobby/try_contribute"))
+                .layer(timeouts.layer("/lobby/try_contribute"))
+                .layer(checks.clone()),
+        )
+        .route(
+            "/contribute",
+            post(contribute)
+                .layer(limits.layer("/contribute"))
+                .layer(timeouts.layer("/contribute"))
+                .layer(checks),
+        )
+        .route(
+            "/contribute/confirm",
+            post(contribute_confirm)
+                .layer(limits.layer("/contribute/confirm"))
+                .layer(timeouts.layer("/contribute/confirm")),
+        )
+        .route(
+            "/contribute/abort",
+            post(contribute_abort)
+                .layer(limits.layer("/contribute/abort"))
+                .layer(timeouts.layer("/contribute/abort")),
+        )
+        .route(
+            "/contribute/extend",
+            post(contribute_extend)
+                .layer(limits.layer("/contribute/extend"))
+                .layer(timeouts.layer("/contribute/extend")),
+        )
+        .route(
+            "/receipt/mine",
+            get(receipt_mine)
+                .layer(limits.layer("/receipt/mine"))
+                .layer(timeouts.layer("/receipt/mine")),
+        )
+        .route(
+            "/receipt/:participant/qr",
+            get(receipt_qr)
+                .layer(limits.layer("/receipt/:participant/qr"))
+                .layer(timeouts.layer("/receipt/:participant/qr")),
+        )
+        .route(
+            "/receipt/:participant/verify",
+            get(receipt_verify)
+                .layer(limits.layer("/receipt/:participant/verify"))
+                .layer(timeouts.layer("/receipt/:participant/verify")),
+        )
+        .route(
+            "/info/status",
+            get(status)
+                .layer(limits.layer("/info/status"))
+                .layer(timeouts.layer("/info/status"))
+                .layer(shedding.clone()),
+        )
+        .route(
+            "/info/status/signed",
+            get(signed_status)
+                .layer(limits.layer("/info/status/signed"))
+                .layer(timeouts.layer("/info/status/signed")),
+        )
+        .route(
+            "/info/sequencer",
+            get(sequencer)
+                .layer(limits.layer("/info/sequencer"))
+                .layer(timeouts.layer("/info/sequencer")),
+        )
+        .route(
+            "/info/current_state",
+            get(current_state)
+                .layer(limits.layer("/info/current_state"))
+                .layer(timeouts.layer("/info/current_state"))
+                .layer(shedding),
+        )
+        .route(
+            "/info/selection/:index",
+            get(selection)
+                .layer(limits.layer("/info/selection/:index"))
+                .layer(timeouts.layer("/info/selection/:index")),
+        )
+        .route(
+            "/info/transcript_head",
+            get(transcript_head)
+                .layer(limits.layer("/info/transcript_head"))
+                .layer(timeouts.layer("/info/transcript_head")),
+        )
+        .route(
+            "/info/selection_log",
+            get(selection_log)
+                .layer(limits.layer("/info/selection_log"))
+                .layer(timeouts.layer("/info/selection_log")),
+        )
+        .route(
+            "/info/storage",
+            get(storage)
+                .layer(limits.layer("/info/storage"))
+                .layer(timeouts.layer("/info/storage")),
+        )
+        .route(
+            "/info/contribution_spec",
+            get(contribution_spec)
+                .layer(limits.layer("/info/contribution_spec"))
+                .layer(timeouts.layer("/info/contribution_spec")),
+        )
+        .route(
+            "/info/witness_segment",
+            get(witness_segment)
+                .layer(limits.layer("/info/witness_segment"))
+                .layer(timeouts.layer("/info/witness_segment")),
+        )
+        .route(
+            "/info/lobby_stats",
+            get(lobby_stats)
+                .layer(limits.layer("/info/lobby_stats"))
+                .layer(timeouts.layer("/info/lobby_stats")),
+        )
+        .route(
+            "/info/stats/regions",
+            get(region_stats)
+                .layer(limits.layer("/info/stats/regions"))
+                .layer(timeouts.layer("/info/stats/regions")),
+        )
+        .route(
+            "/info/report",
+            get(report)
+                .layer(limits.layer("/info/report"))
+                .layer(timeouts.layer("/info/report")),
+        )
+        .route(
+            "/admin/dashboard",
+            get(dashboard)
+                .layer(limits.layer("/admin/dashboard"))
+                .layer(timeouts.layer("/admin/dashboard")),
+        )
+        .route(
+            "/admin/announcement",
+            post(set_announcement)
+                .layer(limits.layer("/admin/announcement"))
+                .layer(timeouts.layer("/admin/announcement")),
+        )
+        .route(
+            "/admin/lobby",
+            post(set_lobby_params)
+                .layer(limits.layer("/admin/lobby"))
+                .layer(timeouts.layer("/admin/lobby")),
+        )
+        .route(
+            "/admin/invitations",
+            post(mint_invitations)
+                .layer(limits.layer("/admin/invitations"))
+                .layer(timeouts.layer("/admin/invitations")),
+        )
+        .route(
+            "/admin/receipts",
+            get(export_receipts)
+                .layer(limits.layer("/admin/receipts"))
+                .layer(timeouts.layer("/admin/receipts")),
+        )
+        .layer(fingerprints)
+        .layer(ClockSkewLayer)
         .layer(CorsLayer::permissive())
         .layer(Extension(lobby_state))
         .layer(Extension(auth_state))
         .layer(Extension(ceremony_status))
         .layer(Extension(keys))
+        .layer(Extension(verifier))
+        .layer(Extension(passkeys))
+        .layer(Extension(emails))
+        .layer(Extension(auth_captcha))
+        .layer(Extension(info_cache))
+        .layer(Extension(SharedStatusSequence::default()))
+        .layer(Extension(access_lists))
+        .layer(Extension(mirrors))
+        .layer(Extension(transcript_writer))
+        .layer(Extension(announcement))
+        .layer(Extension(completion))
+        .layer(Extension(forecast))
+        .layer(Extension(SharedPendingContributions::default()))
         .layer(Extension(eth_oauth_client(&options.ethereum)))
         .layer(Extension(github_oauth_client(&options.github)))
-        .layer(Extension(reqwest::Client::new()))
-        .layer(Extension(storage_client(&options.storage).await?))
+        .layer(Extension(twitter_oauth_client(&options.twitter)))
+        .layer(Extension(discord_oauth_client(&options.discord)))
+        .layer(Extension(OAuthHttpClient::new(&options.proxy)?))
+        .layer(Extension(http_client))
+        .layer(Extension(storage))
         .layer(Extension(transcript))
         .layer(Extension(options.clone()))
-        .layer(DefaultBodyLimit::disable())
-        .layer(RequestBodyLimitLayer::new(MAX_CONTRIBUTION_SIZE));
+        .layer(DefaultBodyLimit::disable());
 
-    // Run the server
-    let (addr, prefix) = parse_url(&options.server)?;
-    let app = Router::new()
-        .nest(prefix, app)
-        .fallback(handle_404.into_service())
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::default().level(Level::INFO))
-                .on_response(DefaultOnResponse::default().level(Level::INFO)),
-        );
-    let server = Server::try_bind(&addr)?.serve(app.into_make_service());
-    Ok(server)
+    Ok(app)
 }
 
 #[allow(clippy::unused_async)] // Required for axum function signature
@@ -200,20 +821,20 @@ async fn handle_404() -> impl IntoResponse {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use kzg_ceremony_crypto::{
-        signature::identity::Identity, BatchContribution, BatchTranscript, G2,
-    };
+    use crate::test_util::test_jwt;
+    use kzg_ceremony_crypto::{BatchContribution, BatchTranscript, G2};
     use secrecy::Secret;
 
     pub fn test_transcript() -> BatchTranscript {
         BatchTranscript::new(&[(4, 2)])
     }
 
+    /// A contribution signed for the identity of the test sessions.
     pub fn valid_contribution(transcript: &BatchTranscript, no: u8) -> BatchContribution {
         let entropy = Secret::new([no; 32]);
         let mut contribution = transcript.contribution();
         contribution
-            .add_entropy::<Engine>(&entropy, &Identity::None)
+            .add_entropy::<Engine>(&entropy, &test_jwt(0).identity)
             .unwrap();
         contribution
     }
@@ -0,0 +1,130 @@
+//! Request body size limits for the routes of a ceremony.
+
+use crate::io::CeremonySizes;
+use clap::Parser;
+use eyre::{eyre, Result as EyreResult};
+use std::collections::HashMap;
+use tower_http::limit::RequestBodyLimitLayer;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Maximum request body size in bytes for routes without a specific
+    /// limit.
+    #[clap(long, env, default_value = "65536")]
+    pub default_body_limit: usize,
+
+    /// Maximum size of a contribution in bytes. By default, the limit is
+    /// computed from the ceremony sizes.
+    #[clap(long, env)]
+    pub contribution_body_limit: Option<usize>,
+
+    /// Body size limits in bytes for individual routes, taking precedence
+    /// over the limits above. The format is `PATH=BYTES[,PATH=BYTES]*`, for
+    /// example `/auth/passkey/register/finish=16384`.
+    #[clap(long, env, value_delimiter = ',', value_parser = RouteLimit::parse_from_cmd)]
+    pub route_body_limits: Vec<RouteLimit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteLimit {
+    pub path:  String,
+    pub limit: usize,
+}
+
+impl RouteLimit {
+    /// Parses a route limit of the form `PATH=BYTES`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the description is malformed.
+    pub fn parse_from_cmd(cmd: &str) -> EyreResult<Self> {
+        let (path, limit) = cmd
+            .split_once('=')
+            .filter(|(path, _)| path.starts_with('/'))
+            .ok_or_else(|| eyre!("Invalid route body limit {cmd}, expected PATH=BYTES"))?;
+        Ok(Self {
+            path:  path.to_string(),
+            limit: limit.parse()?,
+        })
+    }
+}
+
+/// The body size limit of each route.
+pub struct BodyLimits {
+    default: usize,
+    routes:  HashMap<String, usize>,
+}
+
+impl BodyLimits {
+    pub fn new(options: &Options, ceremony_sizes: &CeremonySizes) -> Self {
+        let contribution = options
+            .contribution_body_limit
+            .unwrap_or_else(|| ceremony_sizes.max_contribution_size());
+        let mut routes = HashMap::from([("/contribute".to_string(), contribution)]);
+        routes.extend(
+            options
+                .route_body_limits
+                .iter()
+                .map(|route| (route.path.clone(), route.limit)),
+        );
+        Self {
+            default: options.default_body_limit,
+            routes,
+        }
+    }
+
+    pub fn limit(&self, path: &str) -> usize {
+        self.routes.get(path).copied().unwrap_or(self.default)
+    }
+
+    pub fn layer(&self, path: &str) -> RequestBodyLimitLayer {
+        RequestBodyLimitLayer::new(self.limit(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_transcript, valid_contribution};
+
+    #[test]
+    fn parses_route_limit() {
+        assert_eq!(
+            RouteLimit::parse_from_cmd("/contribute=1024").unwrap(),
+            RouteLimit {
+                path:  "/contribute".to_string(),
+                limit: 1024,
+            }
+        );
+        assert!(RouteLimit::parse_from_cmd("contribute=1024").is_err());
+        assert!(RouteLimit::parse_from_cmd("/contribute").is_err());
+        assert!(RouteLimit::parse_from_cmd("/contribute=lots").is_err());
+    }
+
+    #[test]
+    fn computes_limits() {
+        let sizes = CeremonySizes::parse_from_cmd("4,2").unwrap();
+        let options = |contribution_body_limit, route_body_limits| Options {
+            default_body_limit: 100,
+            contribution_body_limit,
+            route_body_limits,
+        };
+
+        let limits = BodyLimits::new(&options(None, vec![]), &sizes);
+        let contribution = valid_contribution(&test_transcript(), 1);
+        let size = serde_json::to_vec_pretty(&contribution).unwrap().len();
+        assert!(limits.limit("/contribute") >= size);
+        assert_eq!(limits.limit("/info/status"), 100);
+
+        let limits = BodyLimits::new(
+            &options(Some(200), vec![RouteLimit {
+                path:  "/info/status".to_string(),
+                limit: 0,
+            }]),
+            &sizes,
+        );
+        assert_eq!(limits.limit("/contribute"), 200);
+        assert_eq!(limits.limit("/info/status"), 0);
+    }
+}
@@ -1,18 +1,47 @@
 use crate::{
-    sessions::{SessionId, SessionInfo},
-    storage::PersistentStorage,
+    attempts::AttemptOutcome,
+    guard::{self, Mark},
+    regions::Region,
+    reporting::session_id_hash,
+    sessions::{IdToken, SessionId, SessionInfo},
+    storage::{PersistentStorage, StorageError},
+    wal::{self, Operation},
 };
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
 use clap::Parser;
+use kzg_ceremony_crypto::{
+    drand::{DrandChain, DrandScheme},
+    signature::identity::Identity,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::BTreeMap, mem, num::ParseIntError, str::FromStr, sync::Arc, time::Duration,
+    collections::{BTreeMap, BTreeSet},
+    fmt, mem,
+    num::ParseIntError,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
+use strum::{EnumString, IntoStaticStr};
 use thiserror::Error;
-use tokio::{sync::Mutex, time::Instant};
+use tokio::{
+    sync::{Mutex, Notify},
+    time::Instant,
+};
+use tracing::{error, info, warn};
+use url::Url;
+use uuid::Uuid;
 
-fn duration_from_str(value: &str) -> Result<Duration, ParseIntError> {
+pub(crate) fn duration_from_str(value: &str) -> Result<Duration, ParseIntError> {
     Ok(Duration::from_secs(u64::from_str(value)?))
 }
 
+fn hex_from_str(value: &str) -> Result<Vec<u8>, hex::FromHexError> {
+    hex::decode(value.trim_start_matches("0x"))
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Parser)]
 #[group(skip)]
 pub struct Options {
@@ -20,8 +49,14 @@ pub struct Options {
     #[clap(long, env, value_parser=duration_from_str, default_value="180")]
     pub compute_deadline: Duration,
 
-    /// How often participants should ping the server to keep their session
-    /// alive in seconds.
+    /// Longest extension of the compute deadline, in seconds, that the
+    /// contributor can ask for once with `/contribute/extend`. Set to 0 to
+    /// disable extensions.
+    #[clap(long, env, value_parser=duration_from_str, default_value="0")]
+    pub compute_deadline_max_extension: Duration,
+
+    /// How often participants should ping the server, via `/lobby/ping` or
+    /// `/lobby/try_contribute`, to keep their session alive in seconds.
     #[clap(long, env, value_parser=duration_from_str, default_value="30")]
     pub lobby_checkin_frequency: Duration,
 
@@ -45,6 +80,67 @@ pub struct Options {
     /// Maximum number of active sessions.
     #[clap(long, env, default_value = "100000")]
     pub max_sessions_count: usize,
+
+    /// How long the lobby token issued after authentication stays valid, in
+    /// seconds. The token must be redeemed by entering the lobby before it
+    /// expires, otherwise the user has to authenticate again.
+    #[clap(long, env, value_parser=duration_from_str, default_value="600")]
+    pub lobby_token_ttl: Duration,
+
+    /// Url of the latest round of a drand compatible randomness beacon, e.g.
+    /// `https://api.drand.sh/public/latest`. When set, the next contributor
+    /// is picked from the lobby using the beacon instead of whoever asks
+    /// first, and each pick is published at `/info/selection/:index`.
+    #[clap(long, env)]
+    pub lobby_selection_beacon_url: Option<Url>,
+
+    /// Hash of the drand chain of the selection beacon, as hex. Rounds are
+    /// only accepted from this chain. Defaults to the League of Entropy
+    /// mainnet.
+    #[clap(
+        long,
+        env,
+        value_parser = hex_from_str,
+        default_value = "8990e7a9aaed2ffed73dbd7092123d6f289930540d7651336225dc172e51b2ce"
+    )]
+    pub lobby_selection_beacon_chain_hash: Vec<u8>,
+
+    /// Public key of the drand chain of the selection beacon, as hex. Rounds
+    /// are only accepted with a valid signature under this key. Defaults to
+    /// the League of Entropy mainnet.
+    #[clap(
+        long,
+        env,
+        value_parser = hex_from_str,
+        default_value = "868f005eb8e6e4ca0a47c8a77ceaa5309a47978a7c71bc5cce96366b5d7a569937c529eeda66c7293784a9402801af31"
+    )]
+    pub lobby_selection_beacon_public_key: Vec<u8>,
+
+    /// Number of participants, usually 1 or 2, told ahead of time that they
+    /// get the slot next, so that their clients can download the current
+    /// powers before it opens. The first participants to ask for the taken
+    /// slot are put on deck, and are not rate limited while they wait. Not
+    /// used with a selection beacon. Set to 0 to disable.
+    #[clap(long, env, default_value = "0")]
+    pub lobby_on_deck: usize,
+
+    /// Require participants to join the lobby with `/lobby/join` before
+    /// asking for the slot, and let them leave it with `/lobby/leave`.
+    /// Otherwise the first call to `/lobby/try_contribute` joins the lobby.
+    #[clap(long, env, default_value = "false")]
+    pub lobby_explicit_join: bool,
+
+    /// The lobby is saved to storage whenever participants join or leave it,
+    /// and restored on startup if it was saved less than this many seconds
+    /// ago. Set to 0 to disable.
+    #[clap(long, env, value_parser=duration_from_str, default_value="300")]
+    pub lobby_restore_max_age: Duration,
+
+    /// Longest time, in seconds, that participants in the lobby can step away
+    /// for with `/lobby/defer`. They keep their session without pinging, and
+    /// don't get the slot until they are back. Set to 0 to disable.
+    #[clap(long, env, value_parser=duration_from_str, default_value="0")]
+    pub lobby_max_deferral: Duration,
 }
 
 #[derive(Default)]
@@ -52,6 +148,397 @@ pub struct LobbyState {
     pub sessions_in_lobby:     BTreeMap<SessionId, SessionInfo>,
     pub sessions_out_of_lobby: BTreeMap<SessionId, SessionInfo>,
     pub active_contributor:    ActiveContributor,
+    pub cycle_time:            CycleTime,
+    pub selections:            Vec<Selection>,
+    /// Sessions dropped by the lobby flush whose owners haven't been told
+    /// yet, with the time they were dropped.
+    pub evicted:               BTreeMap<SessionId, (EvictionReason, Instant)>,
+    pub eviction_stats:        EvictionStats,
+    /// Participants in the lobby who get the slot next, in order.
+    pub on_deck:               Vec<SessionId>,
+    /// Set once the ceremony is complete. Nobody may enter the lobby or take
+    /// the slot afterwards.
+    pub closed:                bool,
+    /// Until when the longer check-in deadline before the last reload still
+    /// applies, and that deadline.
+    pub checkin_grace:         Option<(Instant, Duration)>,
+}
+
+impl LobbyState {
+    /// Frees the contribution slot, recording how long it was held.
+    fn release_slot(&mut self) {
+        if let ActiveContributor::AwaitingContribution(slot)
+        | ActiveContributor::Contributing(slot) = mem::take(&mut self.active_contributor)
+        {
+            self.cycle_time.record(slot.started.elapsed());
+        }
+    }
+
+    /// Whether the session holds the contribution slot.
+    fn holds_slot(&self, session_id: &SessionId) -> bool {
+        match &self.active_contributor {
+            ActiveContributor::None => false,
+            ActiveContributor::AwaitingContribution(slot)
+            | ActiveContributor::Contributing(slot) => &slot.participant.id == session_id,
+        }
+    }
+
+    /// Drops a session and records why, so that its owner can be told.
+    fn evict(&mut self, session_id: SessionId, reason: EvictionReason, now: Instant) {
+        match reason {
+            EvictionReason::Idle => self.eviction_stats.idle_evictions += 1,
+            EvictionReason::Expired => self.eviction_stats.expired_sessions += 1,
+            EvictionReason::Replaced => self.eviction_stats.replaced_sessions += 1,
+        }
+        self.eviction_stats.last_eviction_at = Some(Utc::now());
+        self.evicted.insert(session_id, (reason, now));
+    }
+
+    /// Rough estimate of how long a participant in the lobby waits for the
+    /// slot: the rest of the current cycle, plus one cycle per participant in
+    /// the lobby.
+    fn estimated_wait(&self, default_cycle_time: Duration) -> Duration {
+        self.estimated_wait_behind(self.sessions_in_lobby.len(), default_cycle_time)
+    }
+
+    /// Rough estimate of the wait for a participant with `ahead` others
+    /// before them.
+    fn estimated_wait_behind(&self, ahead: usize, default_cycle_time: Duration) -> Duration {
+        let average = self.cycle_time.average.unwrap_or(default_cycle_time);
+        let current = match &self.active_contributor {
+            ActiveContributor::None => Duration::ZERO,
+            ActiveContributor::AwaitingContribution(slot)
+            | ActiveContributor::Contributing(slot) => {
+                average.saturating_sub(slot.started.elapsed())
+            }
+        };
+        let ahead = u32::try_from(ahead).unwrap_or(u32::MAX);
+        current.saturating_add(average.saturating_mul(ahead))
+    }
+
+    /// The wait of a participant, which is shorter for those on deck, and at
+    /// least the rest of their deferral.
+    fn estimated_wait_for(
+        &self,
+        participant: &SessionId,
+        default_cycle_time: Duration,
+    ) -> Duration {
+        let wait = self
+            .on_deck
+            .iter()
+            .position(|id| id == participant)
+            .map_or_else(
+                || self.estimated_wait(default_cycle_time),
+                |position| self.estimated_wait_behind(position, default_cycle_time),
+            );
+        let deferred = self
+            .sessions_in_lobby
+            .get(participant)
+            .and_then(|session| session.deferred_until)
+            .map_or(Duration::ZERO, |until| {
+                until.saturating_duration_since(Instant::now())
+            });
+        wait.max(deferred)
+    }
+
+    /// Whether a participant other than `except` who isn't deprioritized or
+    /// deferred is waiting in the lobby.
+    fn has_prioritized_waiting(&self, now: Instant, except: Option<&SessionId>) -> bool {
+        self.sessions_in_lobby.iter().any(|(id, session)| {
+            Some(id) != except && !session.deprioritized && !session.is_deferred(now)
+        })
+    }
+
+    /// Hashed session ids of the participants in the lobby who can get the
+    /// slot, sorted, with the sessions.
+    fn candidates(&self, now: Instant) -> Vec<(String, SessionId)> {
+        // Deprioritized sessions are only picked when nobody else is waiting
+        let has_priority = self.has_prioritized_waiting(now, None);
+        let mut candidates = self
+            .sessions_in_lobby
+            .iter()
+            .filter(|(_, session)| !session.is_deferred(now))
+            .filter(|(_, session)| !has_priority || !session.deprioritized)
+            .map(|(id, _)| (session_id_hash(id), id.clone()))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        candidates
+    }
+}
+
+/// Why a session was dropped by the lobby flush, or by signing in again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// The participant stopped pinging while in the lobby.
+    Idle,
+    /// The session outside the lobby expired, or its lobby token did.
+    Expired,
+    /// The identity signed in again, see
+    /// [`crate::sessions::ConcurrentLoginPolicy`].
+    Replaced,
+}
+
+impl fmt::Display for EvictionReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Self::Idle => "idle",
+            Self::Expired => "expired",
+            Self::Replaced => "replaced",
+        })
+    }
+}
+
+/// Counts of the sessions dropped by the lobby flush since the start.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct EvictionStats {
+    /// Participants dropped from the lobby because they stopped pinging.
+    pub idle_evictions:    u64,
+    /// Sessions outside the lobby dropped because they expired.
+    pub expired_sessions:  u64,
+    /// Sessions dropped because their identity signed in again.
+    pub replaced_sessions: u64,
+    pub last_eviction_at:  Option<DateTime<Utc>>,
+}
+
+/// Rolling average of the time participants hold the contribution slot.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CycleTime {
+    average: Option<Duration>,
+}
+
+impl CycleTime {
+    /// Weight of the latest cycle in the average.
+    const SMOOTHING: f64 = 0.1;
+
+    pub fn record(&mut self, cycle: Duration) {
+        self.average = Some(match self.average {
+            None => cycle,
+            Some(average) => {
+                average.mul_f64(1.0 - Self::SMOOTHING) + cycle.mul_f64(Self::SMOOTHING)
+            }
+        });
+    }
+}
+
+/// A round of public randomness.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Beacon {
+    pub round:      u64,
+    pub randomness: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct BeaconResponse {
+    round:              u64,
+    randomness:         String,
+    signature:          String,
+    #[serde(default)]
+    previous_signature: String,
+}
+
+#[derive(Deserialize)]
+struct ChainInfoResponse {
+    public_key:   String,
+    period:       u32,
+    genesis_time: i64,
+    #[serde(rename = "groupHash")]
+    group_hash:   String,
+    #[serde(default, rename = "schemeID")]
+    scheme_id:    String,
+    #[serde(default)]
+    metadata:     ChainMetadata,
+}
+
+#[derive(Default, Deserialize)]
+struct ChainMetadata {
+    #[serde(default, rename = "beaconID")]
+    beacon_id: String,
+}
+
+#[derive(Debug, Error)]
+pub enum BeaconError {
+    #[error("beacon request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("invalid beacon url: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("invalid hex in beacon response: {0}")]
+    InvalidHex(#[from] hex::FromHexError),
+    #[error("beacon serves another chain")]
+    UnknownChain,
+    #[error("unsupported beacon scheme: {0}")]
+    UnsupportedScheme(String),
+    #[error("invalid beacon signature")]
+    InvalidSignature,
+}
+
+/// Fetches a round of a drand compatible beacon, e.g. the latest one, and
+/// checks that it was signed by the configured chain. The chain is described
+/// at `/info`, next to `/public/latest`.
+pub async fn fetch_beacon(
+    client: &reqwest::Client,
+    url: &Url,
+    options: &Options,
+) -> Result<Beacon, BeaconError> {
+    let info: ChainInfoResponse = client
+        .get(url.join("../info")?)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chain = DrandChain {
+        public_key:   hex::decode(info.public_key)?,
+        period:       info.period,
+        genesis_time: info.genesis_time,
+        group_hash:   hex::decode(info.group_hash)?,
+        scheme:       info.scheme_id,
+        beacon_id:    info.metadata.beacon_id,
+    };
+    if chain.hash()[..] != options.lobby_selection_beacon_chain_hash[..]
+        || chain.public_key != options.lobby_selection_beacon_public_key
+    {
+        return Err(BeaconError::UnknownChain);
+    }
+    let scheme = if chain.scheme.is_empty() {
+        DrandScheme::DEFAULT
+    } else {
+        DrandScheme::from_str(&chain.scheme)
+            .map_err(|_| BeaconError::UnsupportedScheme(chain.scheme.clone()))?
+    };
+
+    let response: BeaconResponse = client
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let signature = hex::decode(response.signature)?;
+    let randomness = hex::decode(response.randomness)?;
+    // The randomness is the hash of the signature
+    if !scheme.verify(
+        &chain.public_key,
+        response.round,
+        &signature,
+        &hex::decode(response.previous_signature)?,
+    ) || randomness[..] != Sha256::digest(&signature)[..]
+    {
+        return Err(BeaconError::InvalidSignature);
+    }
+    Ok(Beacon {
+        round: response.round,
+        randomness,
+    })
+}
+
+/// Record of a contributor picked from the lobby by a beacon. Anyone can
+/// recompute the pick: it is the first 8 bytes of
+/// `sha256(randomness || index)`, with the selection index as big endian
+/// `u64`, read as a big endian integer modulo the number of candidates.
+/// Integers from the last, incomplete multiple of the number of candidates
+/// below `2^64` would favor the first candidates, so for those the hash is
+/// hashed again until it gives one that isn't.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Selection {
+    pub index:             usize,
+    pub beacon_round:      u64,
+    /// Hex encoded randomness of the beacon round.
+    pub beacon_randomness: String,
+    /// Hashed session ids of everyone in the lobby at the time, sorted.
+    /// Participants can find themselves by hashing their own session id.
+    pub candidates:        Vec<String>,
+    /// Index of the picked participant in `candidates`.
+    pub selected:          usize,
+}
+
+/// A participant picked by [`SharedLobbyState::select_contributor`], with the
+/// slot they were handed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pick {
+    pub participant: SessionId,
+    /// Uid of the participant's identity.
+    pub uid:         String,
+    pub slot_id:     SlotId,
+    pub selection:   Selection,
+}
+
+/// How the slot was handed out, see [`SlotAssignment`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoStaticStr, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum SelectionRule {
+    /// To the first participant to ask for the free slot.
+    FirstCome,
+    /// To the first participant on deck, see `--lobby-on-deck`.
+    OnDeck,
+    /// To the participant picked by the selection beacon, see [`Selection`].
+    Beacon,
+}
+
+impl SelectionRule {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// Record of a decision on who gets the slot, kept in storage and published
+/// at `/info/selection_log`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotAssignment {
+    pub rule:         SelectionRule,
+    /// Hashed session ids of everyone who could have got the slot, sorted.
+    pub candidates:   Vec<String>,
+    /// Hashed session id of the participant who got it.
+    pub selected:     String,
+    /// Hex encoded randomness the pick was computed from, for picks by the
+    /// beacon.
+    pub seed:         Option<String>,
+    pub beacon_round: Option<u64>,
+}
+
+fn selection_index(randomness: &[u8], index: usize, candidates: usize) -> usize {
+    let candidates = candidates as u64;
+    let limit = u64::MAX - u64::MAX % candidates;
+    let mut hash = Sha256::new()
+        .chain_update(randomness)
+        .chain_update((index as u64).to_be_bytes())
+        .finalize();
+    loop {
+        let mut value = [0; 8];
+        value.copy_from_slice(&hash[..8]);
+        let value = u64::from_be_bytes(value);
+        if value < limit {
+            return usize::try_from(value % candidates).unwrap_or_default();
+        }
+        hash = Sha256::digest(hash);
+    }
+}
+
+/// A participant waiting in the lobby, as shown to the operator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WaitingParticipant {
+    pub identity:     String,
+    pub session_hash: String,
+    /// Time since the participant last pinged.
+    pub idle:         Duration,
+    pub on_deck:      bool,
+}
+
+/// The holder of the contribution slot, as shown to the operator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotHolder {
+    pub identity:     String,
+    pub session_hash: String,
+    /// Whether the contribution has been uploaded and is being verified.
+    pub contributing: bool,
+    pub held_for:     Duration,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LobbyOverview {
+    pub waiting:     Vec<WaitingParticipant>,
+    pub slot_holder: Option<SlotHolder>,
 }
 
 #[derive(Clone, Debug)]
@@ -60,10 +547,38 @@ pub struct SessionInfoWithId {
     info: SessionInfo,
 }
 
+/// Identifies a single reservation of the contribution slot. A participant
+/// who lost their slot cannot contribute with it, even if they get a new one.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct SlotId(pub String);
+
+impl SlotId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+}
+
+impl Default for SlotId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ActiveSlot {
+    slot_id:     SlotId,
+    participant: SessionInfoWithId,
+    started:     Instant,
+    deadline:    Instant,
+    /// Whether the deadline was extended, which is only allowed once.
+    extended:    bool,
+}
+
 pub enum ActiveContributor {
     None,
-    AwaitingContribution(SessionInfoWithId),
-    Contributing(SessionInfoWithId),
+    AwaitingContribution(ActiveSlot),
+    Contributing(ActiveSlot),
 }
 
 impl Default for ActiveContributor {
@@ -75,117 +590,690 @@ impl Default for ActiveContributor {
 #[derive(Debug, Error)]
 pub enum ActiveContributorError {
     #[error("another contribution in progress")]
-    AnotherContributionInProgress,
+    AnotherContributionInProgress { estimated_wait: Duration },
     #[error("not user's turn")]
     NotUsersTurn,
+    #[error("contribution slot is no longer valid")]
+    StaleSlot,
     #[error("user not in the lobby")]
     UserNotInLobby,
     #[error("session count limit exceeded")]
     SessionCountLimitExceeded,
     #[error("lobby size limit exceeded")]
     LobbySizeLimitExceeded,
+    #[error("lobby token expired")]
+    LobbyTokenExpired,
+    #[error("deadline was extended already")]
+    AlreadyExtended,
+    #[error("the ceremony is complete")]
+    LobbyClosed,
+}
+
+/// A participant waiting in the lobby, as saved to storage.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LobbySnapshotEntry {
+    pub session_id:     SessionId,
+    pub identity:       Identity,
+    /// See [`SessionInfo::uid`]. Missing in snapshots from before
+    /// pseudonymous sessions kept it.
+    #[serde(default)]
+    pub uid:            Option<String>,
+    pub exp:            u64,
+    pub region:         Option<Region>,
+    pub email_verified: bool,
+    pub joined_at:      Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub deferred_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub fingerprint:    Option<String>,
+    #[serde(default)]
+    pub deprioritized:  bool,
+}
+
+/// The lobby as saved to storage. The slot holder isn't saved, since the
+/// contribution they work on can't be resumed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LobbySnapshot {
+    pub saved_at: DateTime<Utc>,
+    pub sessions: Vec<LobbySnapshotEntry>,
+    pub on_deck:  Vec<SessionId>,
+}
+
+/// Lobby options that can be changed while the sequencer runs, see
+/// [`SharedLobbyState::reload`]. Durations are in seconds, and options left
+/// out keep their value.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LobbyParams {
+    pub max_lobby_size:                 Option<usize>,
+    pub compute_deadline:               Option<u64>,
+    pub compute_deadline_max_extension: Option<u64>,
+    pub lobby_checkin_frequency:        Option<u64>,
+    pub lobby_checkin_tolerance:        Option<u64>,
+}
+
+impl LobbyParams {
+    /// The current values of all parameters.
+    #[must_use]
+    pub fn of(options: &Options) -> Self {
+        Self {
+            max_lobby_size:                 Some(options.max_lobby_size),
+            compute_deadline:               Some(options.compute_deadline.as_secs()),
+            compute_deadline_max_extension: Some(options.compute_deadline_max_extension.as_secs()),
+            lobby_checkin_frequency:        Some(options.lobby_checkin_frequency.as_secs()),
+            lobby_checkin_tolerance:        Some(options.lobby_checkin_tolerance.as_secs()),
+        }
+    }
+
+    /// The options with these parameters applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the compute deadline is zero, or the check-in
+    /// tolerance exceeds the check-in frequency.
+    pub fn apply(&self, options: &Options) -> Result<Options, LobbyParamsError> {
+        let mut options = options.clone();
+        if let Some(max_lobby_size) = self.max_lobby_size {
+            options.max_lobby_size = max_lobby_size;
+        }
+        if let Some(seconds) = self.compute_deadline {
+            options.compute_deadline = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = self.compute_deadline_max_extension {
+            options.compute_deadline_max_extension = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = self.lobby_checkin_frequency {
+            options.lobby_checkin_frequency = Duration::from_secs(seconds);
+        }
+        if let Some(seconds) = self.lobby_checkin_tolerance {
+            options.lobby_checkin_tolerance = Duration::from_secs(seconds);
+        }
+        if options.compute_deadline.is_zero() {
+            return Err(LobbyParamsError::ZeroComputeDeadline);
+        }
+        if options.lobby_checkin_tolerance > options.lobby_checkin_frequency {
+            return Err(LobbyParamsError::ToleranceExceedsFrequency);
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum LobbyParamsError {
+    #[error("compute deadline must not be zero")]
+    ZeroComputeDeadline,
+    #[error("check-in tolerance must not exceed the check-in frequency")]
+    ToleranceExceedsFrequency,
 }
 
 #[derive(Clone)]
 pub struct SharedLobbyState {
     inner:   Arc<Mutex<LobbyState>>,
-    options: Options,
+    options: Arc<ArcSwap<Options>>,
+    /// Notified when participants join or leave the lobby.
+    changed: Arc<Notify>,
 }
 
 impl SharedLobbyState {
     pub fn new(options: Options) -> Self {
         Self {
-            inner: Arc::default(),
-            options,
+            inner:   Arc::default(),
+            options: Arc::new(ArcSwap::from_pointee(options)),
+            changed: Arc::default(),
+        }
+    }
+
+    /// The current options, which may change at runtime, see
+    /// [`Self::reload`].
+    #[must_use]
+    pub fn options(&self) -> Arc<Options> {
+        self.options.load_full()
+    }
+
+    /// Changes the lobby parameters while keeping everyone in the lobby. A
+    /// smaller lobby size only turns new participants away, and the slot
+    /// holder keeps their deadline. A shorter check-in deadline only applies
+    /// once participants had the time to ping at the old frequency.
+    ///
+    /// # Errors
+    ///
+    /// See [`LobbyParams::apply`].
+    pub async fn reload(&self, params: &LobbyParams) -> Result<LobbyParams, LobbyParamsError> {
+        let mut state = self.inner.lock().await;
+        let previous = self.options();
+        let options = params.apply(&previous)?;
+        let previous_deadline = previous.lobby_checkin_frequency + previous.lobby_checkin_tolerance;
+        if options.lobby_checkin_frequency + options.lobby_checkin_tolerance < previous_deadline {
+            let grace_until = Instant::now() + previous_deadline;
+            state.checkin_grace = Some(match state.checkin_grace {
+                Some((until, deadline)) if deadline > previous_deadline => {
+                    (until.max(grace_until), deadline)
+                }
+                _ => (grace_until, previous_deadline),
+            });
+        }
+        let reloaded = LobbyParams::of(&options);
+        info!(?reloaded, "Reloaded lobby parameters");
+        self.options.store(Arc::new(options));
+        drop(state);
+        self.lobby_changed();
+        Ok(reloaded)
+    }
+
+    /// How late a ping from a participant in the lobby may be, including the
+    /// grace period after the deadline was shortened.
+    pub async fn checkin_deadline(&self) -> Duration {
+        let options = self.options();
+        let deadline = options.lobby_checkin_frequency + options.lobby_checkin_tolerance;
+        let mut state = self.inner.lock().await;
+        match state.checkin_grace {
+            Some((until, previous)) if Instant::now() < until => deadline.max(previous),
+            Some(_) => {
+                state.checkin_grace = None;
+                deadline
+            }
+            None => deadline,
         }
     }
 
+    fn lobby_changed(&self) {
+        self.changed.notify_one();
+    }
+
     pub async fn set_current_contributor(
         &self,
         participant: &SessionId,
         compute_deadline: Duration,
         storage: PersistentStorage,
-    ) -> Result<(), ActiveContributorError> {
+    ) -> Result<SlotId, ActiveContributorError> {
         let mut state = self.inner.lock().await;
+        if state.closed {
+            return Err(ActiveContributorError::LobbyClosed);
+        }
 
-        if matches!(state.active_contributor, ActiveContributor::None) {
+        // Participants who stepped away wait until they are back
+        let now = Instant::now();
+        if state
+            .sessions_in_lobby
+            .get(participant)
+            .map_or(false, |session| session.is_deferred(now))
+        {
+            return Err(ActiveContributorError::AnotherContributionInProgress {
+                estimated_wait: state
+                    .estimated_wait_for(participant, self.options().compute_deadline),
+            });
+        }
+
+        // The slot is kept for whoever is on deck
+        let is_next = state
+            .on_deck
+            .first()
+            .map_or(true, |next| next == participant);
+
+        // Sessions that look automated wait until nobody else does
+        let is_deprioritized = state
+            .sessions_in_lobby
+            .get(participant)
+            .map_or(false, |session| session.deprioritized);
+        if is_deprioritized
+            && state.on_deck.first() != Some(participant)
+            && state.has_prioritized_waiting(now, Some(participant))
+        {
+            return Err(ActiveContributorError::AnotherContributionInProgress {
+                estimated_wait: state
+                    .estimated_wait_for(participant, self.options().compute_deadline),
+            });
+        }
+        if matches!(state.active_contributor, ActiveContributor::None) && is_next {
             let session_info = state
                 .sessions_in_lobby
                 .remove(participant)
                 .ok_or(ActiveContributorError::UserNotInLobby)?;
+            let rule = if state.on_deck.first() == Some(participant) {
+                SelectionRule::OnDeck
+            } else {
+                SelectionRule::FirstCome
+            };
+            let mut candidates = state.candidates(now);
+            // The participant was taken out of the lobby above
+            candidates.push((session_id_hash(participant), participant.clone()));
+            candidates.sort();
+            state.on_deck.retain(|id| id != participant);
+            self.lobby_changed();
 
-            state.active_contributor = ActiveContributor::AwaitingContribution(SessionInfoWithId {
-                id:   participant.clone(),
-                info: session_info,
-            });
-
-            let inner = self.inner.clone();
-            let participant = participant.clone();
-
-            tokio::spawn(Self::expire_current_contributor(
-                inner,
-                participant,
+            let assignment = SlotAssignment {
+                rule,
+                candidates: candidates.into_iter().map(|(hash, _)| hash).collect(),
+                selected: session_id_hash(participant),
+                seed: None,
+                beacon_round: None,
+            };
+            return Ok(self.assign_slot(
+                &mut state,
+                participant.clone(),
+                session_info,
                 compute_deadline,
+                assignment,
                 storage,
             ));
+        }
 
-            return Ok(());
+        if state.sessions_in_lobby.contains_key(participant)
+            && !state.on_deck.contains(participant)
+            && state.on_deck.len() < self.options().lobby_on_deck
+        {
+            state.on_deck.push(participant.clone());
+            self.lobby_changed();
         }
 
-        Err(ActiveContributorError::AnotherContributionInProgress)
+        Err(ActiveContributorError::AnotherContributionInProgress {
+            estimated_wait: state.estimated_wait_for(participant, self.options().compute_deadline),
+        })
+    }
+
+    /// Has a participant in the lobby step away for `duration`, e.g. because
+    /// they signed in early. They stay in the lobby without pinging, but
+    /// neither get the slot nor a place on deck until the time is up, or
+    /// until they defer again with a zero duration. Returns their estimated
+    /// wait.
+    pub async fn defer(
+        &self,
+        participant: &SessionId,
+        duration: Duration,
+    ) -> Result<Duration, ActiveContributorError> {
+        let mut state = self.inner.lock().await;
+        let now = Instant::now();
+        let session = state
+            .sessions_in_lobby
+            .get_mut(participant)
+            .ok_or(ActiveContributorError::UserNotInLobby)?;
+        session.last_heartbeat = now;
+        session.deferred_until = (!duration.is_zero()).then(|| now + duration);
+        state.on_deck.retain(|id| id != participant);
+        self.lobby_changed();
+        Ok(state.estimated_wait_for(participant, self.options().compute_deadline))
+    }
+
+    /// How many participants on deck are ahead of this one, if they are on
+    /// deck.
+    pub async fn on_deck_position(&self, participant: &SessionId) -> Option<usize> {
+        self.inner
+            .lock()
+            .await
+            .on_deck
+            .iter()
+            .position(|id| id == participant)
+    }
+
+    /// See [`LobbyState::estimated_wait_for`].
+    pub async fn estimated_wait_for(&self, participant: &SessionId) -> Duration {
+        self.inner
+            .lock()
+            .await
+            .estimated_wait_for(participant, self.options().compute_deadline)
+    }
+
+    /// The identities of the participants in the lobby, see
+    /// [`SessionInfo::uid`].
+    pub async fn lobby_uids(&self) -> BTreeSet<String> {
+        self.inner
+            .lock()
+            .await
+            .sessions_in_lobby
+            .values()
+            .map(|info| info.uid.clone())
+            .collect()
+    }
+
+    /// Hands the free slot to a participant picked from the lobby by the
+    /// beacon, and records the pick. Participants of the `excluded`
+    /// identities, which can't take the slot, are left out of the draw, so
+    /// that it doesn't go to someone who would only leave it idle. Returns
+    /// the pick, or `None` if the slot is taken or there is nobody to pick.
+    pub async fn select_contributor(
+        &self,
+        beacon: &Beacon,
+        excluded: &BTreeSet<String>,
+        compute_deadline: Duration,
+        storage: PersistentStorage,
+    ) -> Option<Pick> {
+        let mut state = self.inner.lock().await;
+
+        if !matches!(state.active_contributor, ActiveContributor::None)
+            || state.sessions_in_lobby.is_empty()
+            || state.closed
+        {
+            return None;
+        }
+
+        let candidates = state
+            .candidates(Instant::now())
+            .into_iter()
+            .filter(|(_, id)| {
+                state
+                    .sessions_in_lobby
+                    .get(id)
+                    .map_or(false, |info| !excluded.contains(&info.uid))
+            })
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = state.selections.len();
+        let selected = selection_index(&beacon.randomness, index, candidates.len());
+        let participant = candidates[selected].1.clone();
+        info!(
+            index,
+            round = beacon.round,
+            selected = %candidates[selected].0,
+            "Selected next contributor"
+        );
+        let selection = Selection {
+            index,
+            beacon_round: beacon.round,
+            beacon_randomness: hex::encode(&beacon.randomness),
+            candidates: candidates.into_iter().map(|(hash, _)| hash).collect(),
+            selected,
+        };
+        state.selections.push(selection.clone());
+
+        let session_info = state.sessions_in_lobby.remove(&participant)?;
+        let uid = session_info.uid.clone();
+        self.lobby_changed();
+        let assignment = SlotAssignment {
+            rule:         SelectionRule::Beacon,
+            candidates:   selection.candidates.clone(),
+            selected:     selection.candidates[selected].clone(),
+            seed:         Some(selection.beacon_randomness.clone()),
+            beacon_round: Some(beacon.round),
+        };
+        let slot_id = self.assign_slot(
+            &mut state,
+            participant.clone(),
+            session_info,
+            compute_deadline,
+            assignment,
+            storage,
+        );
+        Some(Pick {
+            participant,
+            uid,
+            slot_id,
+            selection,
+        })
+    }
+
+    /// Hands the slot to the participant, and records the decision in
+    /// storage.
+    fn assign_slot(
+        &self,
+        state: &mut LobbyState,
+        participant: SessionId,
+        session_info: SessionInfo,
+        compute_deadline: Duration,
+        assignment: SlotAssignment,
+        storage: PersistentStorage,
+    ) -> SlotId {
+        let slot_id = SlotId::new();
+        let started = Instant::now();
+        state.active_contributor = ActiveContributor::AwaitingContribution(ActiveSlot {
+            slot_id: slot_id.clone(),
+            participant: SessionInfoWithId {
+                id:   participant,
+                info: session_info,
+            },
+            started,
+            deadline: started + compute_deadline,
+            extended: false,
+        });
+
+        let log = storage.clone();
+        tokio::spawn(async move {
+            if let Err(error) = log.insert_slot_assignment(&assignment).await {
+                error!(?error, "Could not record slot assignment");
+            }
+        });
+        tokio::spawn(Self::expire_current_contributor(
+            self.inner.clone(),
+            slot_id.clone(),
+            started + compute_deadline,
+            storage,
+        ));
+
+        slot_id
+    }
+
+    /// The slot reserved for the participant, if they hold one they have not
+    /// started contributing with, and how long they have left to contribute.
+    pub async fn reserved_slot(&self, participant: &SessionId) -> Option<(SlotId, Duration)> {
+        match &self.inner.lock().await.active_contributor {
+            ActiveContributor::AwaitingContribution(slot)
+                if &slot.participant.id == participant =>
+            {
+                Some((
+                    slot.slot_id.clone(),
+                    slot.deadline.saturating_duration_since(Instant::now()),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Moves the deadline of the participant's reserved slot back by
+    /// `extension`, which is only possible once per slot. Returns the session
+    /// of the participant and how long they have left to contribute.
+    pub async fn extend_deadline(
+        &self,
+        participant: &SessionId,
+        extension: Duration,
+    ) -> Result<(SessionInfo, Duration), ActiveContributorError> {
+        match &mut self.inner.lock().await.active_contributor {
+            ActiveContributor::AwaitingContribution(slot)
+                if &slot.participant.id == participant =>
+            {
+                if slot.extended {
+                    return Err(ActiveContributorError::AlreadyExtended);
+                }
+                slot.extended = true;
+                slot.deadline += extension;
+                Ok((
+                    slot.participant.info.clone(),
+                    slot.deadline.saturating_duration_since(Instant::now()),
+                ))
+            }
+            _ => Err(ActiveContributorError::NotUsersTurn),
+        }
+    }
+
+    pub async fn is_slot_free(&self) -> bool {
+        matches!(
+            self.inner.lock().await.active_contributor,
+            ActiveContributor::None
+        )
+    }
+
+    pub async fn selection(&self, index: usize) -> Option<Selection> {
+        self.inner.lock().await.selections.get(index).cloned()
     }
 
     pub async fn begin_contributing(
         &self,
         participant: &SessionId,
+        slot_id: &SlotId,
     ) -> Result<SessionInfo, ActiveContributorError> {
         let mut state = self.inner.lock().await;
 
         match mem::replace(&mut state.active_contributor, ActiveContributor::None) {
-            ActiveContributor::AwaitingContribution(info) if &info.id == participant => {
-                state.active_contributor = ActiveContributor::Contributing(info.clone());
-                Ok(info.info)
+            ActiveContributor::AwaitingContribution(slot)
+                if &slot.participant.id == participant && &slot.slot_id == slot_id =>
+            {
+                state.active_contributor = ActiveContributor::Contributing(slot.clone());
+                Ok(slot.participant.info)
             }
             other => {
+                let error = match &other {
+                    ActiveContributor::AwaitingContribution(slot)
+                        if &slot.participant.id == participant =>
+                    {
+                        ActiveContributorError::StaleSlot
+                    }
+                    _ => ActiveContributorError::NotUsersTurn,
+                };
                 state.active_contributor = other;
-                Err(ActiveContributorError::NotUsersTurn)
+                Err(error)
             }
         }
     }
 
+    /// Gives back the slot of a participant who hasn't started contributing,
+    /// and returns their session.
     pub async fn abort_contribution(
         &self,
         participant: &SessionId,
-    ) -> Result<(), ActiveContributorError> {
+    ) -> Result<SessionInfo, ActiveContributorError> {
         let mut state = self.inner.lock().await;
 
-        if !matches!(&state.active_contributor, ActiveContributor::AwaitingContribution(x) if &x.id == participant)
-        {
-            return Err(ActiveContributorError::NotUsersTurn);
-        }
+        let session_info = match &state.active_contributor {
+            ActiveContributor::AwaitingContribution(x) if &x.participant.id == participant => {
+                x.participant.info.clone()
+            }
+            _ => return Err(ActiveContributorError::NotUsersTurn),
+        };
 
-        state.active_contributor = ActiveContributor::None;
+        state.release_slot();
 
-        Ok(())
+        Ok(session_info)
+    }
+
+    /// Gives back the slot if the session holds it, whether or not it started
+    /// contributing, and returns the session.
+    pub async fn release_slot_of(&self, participant: &SessionId) -> Option<SessionInfo> {
+        let mut state = self.inner.lock().await;
+
+        let session_info = match &state.active_contributor {
+            ActiveContributor::AwaitingContribution(x) | ActiveContributor::Contributing(x)
+                if &x.participant.id == participant =>
+            {
+                x.participant.info.clone()
+            }
+            _ => return None,
+        };
+
+        state.release_slot();
+
+        Some(session_info)
     }
 
     pub async fn clear_current_contributor(&self) {
         let mut state = self.inner.lock().await;
-        state.active_contributor = ActiveContributor::None;
+        state.release_slot();
+    }
+
+    /// See [`LobbyState::estimated_wait`]. Until the first cycle completes,
+    /// cycles are assumed to take the full compute deadline.
+    pub async fn estimated_wait(&self) -> Duration {
+        self.inner
+            .lock()
+            .await
+            .estimated_wait(self.options().compute_deadline)
     }
 
+    /// Drops the participants in the lobby matching the predicate, and
+    /// remembers them as evicted for being idle.
     pub async fn clear_lobby(&self, predicate: impl Fn(&SessionInfo) -> bool + Copy + Send) {
         let mut lobby_state = self.inner.lock().await;
-        lobby_state
-            .sessions_in_lobby
-            .retain(|_, info| !predicate(info));
+        let (evicted, kept) = mem::take(&mut lobby_state.sessions_in_lobby)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, info)| predicate(info));
+        lobby_state.sessions_in_lobby = kept.into_iter().collect();
+        let now = Instant::now();
+        if !evicted.is_empty() {
+            self.lobby_changed();
+        }
+        for (session_id, _) in evicted {
+            lobby_state.evict(session_id, EvictionReason::Idle, now);
+        }
+        let LobbyState {
+            on_deck,
+            sessions_in_lobby,
+            ..
+        } = &mut *lobby_state;
+        on_deck.retain(|id| sessions_in_lobby.contains_key(id));
     }
 
+    /// Drops the sessions outside the lobby matching the predicate, and
+    /// remembers them as expired.
     pub async fn clear_session(&self, predicate: impl Fn(&SessionInfo) -> bool + Send) {
         let mut lobby_state = self.inner.lock().await;
-        lobby_state
-            .sessions_out_of_lobby
-            .retain(|_, info| !predicate(info));
+        let (evicted, kept) = mem::take(&mut lobby_state.sessions_out_of_lobby)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, info)| predicate(info));
+        lobby_state.sessions_out_of_lobby = kept.into_iter().collect();
+        let now = Instant::now();
+        for (session_id, _) in evicted {
+            lobby_state.evict(session_id, EvictionReason::Expired, now);
+        }
+    }
+
+    /// Why the session was dropped, if it was. Each eviction is only
+    /// reported once.
+    pub async fn take_eviction(&self, session_id: &SessionId) -> Option<EvictionReason> {
+        self.inner
+            .lock()
+            .await
+            .evicted
+            .remove(session_id)
+            .map(|(reason, _)| reason)
+    }
+
+    /// Forgets evictions older than `age`, for clients that never came back.
+    pub async fn forget_evictions(&self, age: Duration) {
+        let now = Instant::now();
+        self.inner
+            .lock()
+            .await
+            .evicted
+            .retain(|_, (_, evicted_at)| now - *evicted_at <= age);
+    }
+
+    /// Who is in the lobby and who holds the slot, for the operator.
+    pub async fn overview(&self) -> LobbyOverview {
+        let state = self.inner.lock().await;
+        let waiting = state
+            .sessions_in_lobby
+            .iter()
+            .map(|(id, info)| WaitingParticipant {
+                identity:     info.token.identity.to_string(),
+                session_hash: session_id_hash(id),
+                idle:         info.last_heartbeat.elapsed(),
+                on_deck:      state.on_deck.contains(id),
+            })
+            .collect();
+        let slot_holder = match &state.active_contributor {
+            ActiveContributor::None => None,
+            ActiveContributor::AwaitingContribution(slot)
+            | ActiveContributor::Contributing(slot) => Some(SlotHolder {
+                identity:     slot.participant.info.token.identity.to_string(),
+                session_hash: session_id_hash(&slot.participant.id),
+                contributing: matches!(
+                    state.active_contributor,
+                    ActiveContributor::Contributing(_)
+                ),
+                held_for:     slot.started.elapsed(),
+            }),
+        };
+        LobbyOverview {
+            waiting,
+            slot_holder,
+        }
+    }
+
+    pub async fn eviction_stats(&self) -> EvictionStats {
+        self.inner.lock().await.eviction_stats.clone()
+    }
+
+    /// Number of evicted sessions whose owners haven't been told yet.
+    pub async fn pending_evictions(&self) -> usize {
+        self.inner.lock().await.evicted.len()
     }
 
     pub async fn modify_participant<R>(
@@ -203,6 +1291,83 @@ impl SharedLobbyState {
             .map(fun)
     }
 
+    /// Lets the session only get the slot when no other participant is
+    /// waiting, see [`crate::api::bots`]. Returns false for unknown sessions.
+    pub async fn deprioritize(&self, session_id: &SessionId) -> bool {
+        self.modify_participant(session_id, |info| info.deprioritized = true)
+            .await
+            .is_some()
+    }
+
+    /// Binds a session to the client `fingerprint` the first time it is
+    /// used, including while it holds the slot. Returns whether the
+    /// fingerprint matches the bound one, or `None` for unknown sessions.
+    pub async fn bind_fingerprint(
+        &self,
+        session_id: &SessionId,
+        fingerprint: &str,
+    ) -> Option<bool> {
+        let mut state = self.inner.lock().await;
+        let LobbyState {
+            sessions_in_lobby,
+            sessions_out_of_lobby,
+            active_contributor,
+            ..
+        } = &mut *state;
+        let slot_holder = match active_contributor {
+            ActiveContributor::AwaitingContribution(slot)
+            | ActiveContributor::Contributing(slot)
+                if &slot.participant.id == session_id =>
+            {
+                Some(&mut slot.participant.info)
+            }
+            _ => None,
+        };
+        let info = sessions_in_lobby
+            .get_mut(session_id)
+            .or_else(|| sessions_out_of_lobby.get_mut(session_id))
+            .or(slot_holder)?;
+        Some(
+            info.fingerprint
+                .get_or_insert_with(|| fingerprint.to_owned())
+                == fingerprint,
+        )
+    }
+
+    /// Whether the session is known, in or out of the lobby or holding the
+    /// slot.
+    pub async fn has_session(&self, session_id: &SessionId) -> bool {
+        let state = self.inner.lock().await;
+        state.sessions_in_lobby.contains_key(session_id)
+            || state.sessions_out_of_lobby.contains_key(session_id)
+            || state.holds_slot(session_id)
+    }
+
+    /// Whether the session holds the slot.
+    pub async fn holds_slot(&self, session_id: &SessionId) -> bool {
+        self.inner.lock().await.holds_slot(session_id)
+    }
+
+    /// Drops a session because its identity signed in again, and remembers
+    /// it as replaced. The slot holder's session is kept, in which case this
+    /// returns false.
+    pub async fn replace_session(&self, session_id: &SessionId) -> bool {
+        let mut state = self.inner.lock().await;
+        if state.holds_slot(session_id) {
+            return false;
+        }
+        let in_lobby = state.sessions_in_lobby.remove(session_id).is_some();
+        let out_of_lobby = state.sessions_out_of_lobby.remove(session_id).is_some();
+        if in_lobby {
+            state.on_deck.retain(|id| id != session_id);
+            self.lobby_changed();
+        }
+        if in_lobby || out_of_lobby {
+            state.evict(session_id.clone(), EvictionReason::Replaced, Instant::now());
+        }
+        true
+    }
+
     pub async fn get_lobby_size(&self) -> usize {
         self.inner.lock().await.sessions_in_lobby.len()
     }
@@ -216,8 +1381,8 @@ impl SharedLobbyState {
 
         let is_active_contributor = match &state.active_contributor {
             ActiveContributor::None => false,
-            ActiveContributor::AwaitingContribution(info)
-            | ActiveContributor::Contributing(info) => info.id == session_id,
+            ActiveContributor::AwaitingContribution(slot)
+            | ActiveContributor::Contributing(slot) => slot.participant.id == session_id,
         };
         let is_in_lobby = state.sessions_in_lobby.contains_key(&session_id);
 
@@ -226,7 +1391,8 @@ impl SharedLobbyState {
         }
 
         let sessions = &mut state.sessions_out_of_lobby;
-        if sessions.len() >= self.options.max_sessions_count && !sessions.contains_key(&session_id)
+        if sessions.len() >= self.options().max_sessions_count
+            && !sessions.contains_key(&session_id)
         {
             return Err(ActiveContributorError::SessionCountLimitExceeded);
         }
@@ -237,21 +1403,170 @@ impl SharedLobbyState {
 
     pub async fn enter_lobby(&self, session_id: &SessionId) -> Result<(), ActiveContributorError> {
         let mut state = self.inner.lock().await;
+        if state.closed {
+            return Err(ActiveContributorError::LobbyClosed);
+        }
 
         // If session is not in sessions_out_of_lobby, it was already moved to lobby or
         // to active contributor state
-        if let Some(session) = state.sessions_out_of_lobby.remove(session_id) {
+        if let Some(mut session) = state.sessions_out_of_lobby.remove(session_id) {
+            // The lobby token has to be redeemed before it expires. Expired
+            // sessions are dropped, so the user has to authenticate again.
+            if Instant::now() > session.lobby_token_deadline {
+                return Err(ActiveContributorError::LobbyTokenExpired);
+            }
+
             let lobby = &mut state.sessions_in_lobby;
 
-            if lobby.len() >= self.options.max_lobby_size {
+            if lobby.len() >= self.options().max_lobby_size {
                 return Err(ActiveContributorError::LobbySizeLimitExceeded);
             }
+            session.joined_lobby_at = Some(Utc::now());
             lobby.insert(session_id.clone(), session);
+            self.lobby_changed();
         }
 
         Ok(())
     }
 
+    /// Closes the lobby for good, once the ceremony is complete. Participants
+    /// waiting in it stay until they are evicted, but can't take the slot.
+    pub async fn close(&self) {
+        self.inner.lock().await.closed = true;
+    }
+
+    pub async fn is_in_lobby(&self, session_id: &SessionId) -> bool {
+        self.inner
+            .lock()
+            .await
+            .sessions_in_lobby
+            .contains_key(session_id)
+    }
+
+    /// Moves a session out of the lobby. It may join again until a new lobby
+    /// token deadline, after which it is dropped like any unredeemed session.
+    pub async fn leave_lobby(&self, session_id: &SessionId) -> Result<(), ActiveContributorError> {
+        let mut state = self.inner.lock().await;
+
+        let mut session = state
+            .sessions_in_lobby
+            .remove(session_id)
+            .ok_or(ActiveContributorError::UserNotInLobby)?;
+        state.on_deck.retain(|id| id != session_id);
+        session.lobby_token_deadline = Instant::now() + self.options().lobby_token_ttl;
+        session.deferred_until = None;
+        state
+            .sessions_out_of_lobby
+            .insert(session_id.clone(), session);
+        self.lobby_changed();
+
+        Ok(())
+    }
+
+    /// The participants in the lobby, to be saved to storage.
+    pub async fn snapshot(&self) -> LobbySnapshot {
+        let state = self.inner.lock().await;
+        let now = Instant::now();
+        LobbySnapshot {
+            saved_at: Utc::now(),
+            sessions: state
+                .sessions_in_lobby
+                .iter()
+                .map(|(id, info)| LobbySnapshotEntry {
+                    session_id:     id.clone(),
+                    identity:       info.token.identity.clone(),
+                    uid:            Some(info.uid.clone()),
+                    exp:            info.token.exp,
+                    region:         info.region,
+                    email_verified: info.email_verified,
+                    joined_at:      info.joined_lobby_at,
+                    deferred_until: info
+                        .deferred_until
+                        .and_then(|until| {
+                            chrono::Duration::from_std(until.saturating_duration_since(now)).ok()
+                        })
+                        .map(|left| Utc::now() + left),
+                    fingerprint:    info.fingerprint.clone(),
+                    deprioritized:  info.deprioritized,
+                })
+                .collect(),
+            on_deck:  state.on_deck.clone(),
+        }
+    }
+
+    /// Puts the participants of a saved lobby back into the lobby, unless it
+    /// is older than `lobby_restore_max_age`. Their heartbeats start over, so
+    /// that they aren't evicted for the time the sequencer was down. Returns
+    /// the number of participants restored.
+    pub async fn restore(&self, snapshot: LobbySnapshot) -> usize {
+        let max_age = chrono::Duration::from_std(self.options().lobby_restore_max_age)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        if Utc::now() - snapshot.saved_at > max_age {
+            return 0;
+        }
+
+        let mut state = self.inner.lock().await;
+        let now = Instant::now();
+        let mut restored = 0;
+        for entry in snapshot.sessions {
+            if state.sessions_in_lobby.len() >= self.options().max_lobby_size {
+                break;
+            }
+            state
+                .sessions_in_lobby
+                .insert(entry.session_id, SessionInfo {
+                    uid:                   entry
+                        .uid
+                        .unwrap_or_else(|| entry.identity.unique_id()),
+                    token:                 IdToken {
+                        identity: entry.identity,
+                        exp:      entry.exp,
+                    },
+                    last_ping_time:        now,
+                    last_heartbeat:        now,
+                    is_first_ping_attempt: true,
+                    lobby_token_deadline:  now + self.options().lobby_token_ttl,
+                    region:                entry.region,
+                    email_verified:        entry.email_verified,
+                    joined_lobby_at:       entry.joined_at,
+                    deferred_until:        entry
+                        .deferred_until
+                        .and_then(|until| (until - Utc::now()).to_std().ok())
+                        .map(|left| now + left),
+                    fingerprint:           entry.fingerprint,
+                    deprioritized:         entry.deprioritized,
+                });
+            restored += 1;
+        }
+        let LobbyState {
+            on_deck,
+            sessions_in_lobby,
+            ..
+        } = &mut *state;
+        *on_deck = snapshot
+            .on_deck
+            .into_iter()
+            .filter(|id| sessions_in_lobby.contains_key(id))
+            .take(self.options().lobby_on_deck)
+            .collect();
+        restored
+    }
+
+    /// Restores the lobby saved to storage, see [`Self::restore`].
+    pub async fn restore_from(&self, storage: &PersistentStorage) -> Result<usize, StorageError> {
+        let snapshot = match storage.lobby_snapshot().await? {
+            Some(snapshot) => snapshot,
+            None => return Ok(0),
+        };
+        match serde_json::from_str(&snapshot) {
+            Ok(snapshot) => Ok(self.restore(snapshot).await),
+            Err(error) => {
+                warn!(?error, "Could not read saved lobby");
+                Ok(0)
+            }
+        }
+    }
+
     #[cfg(test)]
     pub async fn get_all_participants(&self) -> Vec<SessionInfoWithId> {
         self.inner
@@ -268,26 +1583,78 @@ impl SharedLobbyState {
 
     async fn expire_current_contributor(
         inner: Arc<Mutex<LobbyState>>,
-        participant: SessionId,
-        compute_deadline: Duration,
+        slot_id: SlotId,
+        mut deadline: Instant,
         storage: PersistentStorage,
     ) {
-        tokio::time::sleep(compute_deadline).await;
+        let uid = loop {
+            tokio::time::sleep_until(deadline).await;
 
-        let mut state = inner.lock().await;
+            let mut state = inner.lock().await;
 
-        if matches!(&state.active_contributor, ActiveContributor::AwaitingContribution(x) if x.id == participant)
+            // Only expire the slot this task was started for, the participant
+            // may already hold a newer one.
+            let uid = match &state.active_contributor {
+                ActiveContributor::AwaitingContribution(x) if x.slot_id == slot_id => {
+                    // Keep waiting if the deadline was extended meanwhile
+                    if x.deadline > deadline {
+                        deadline = x.deadline;
+                        continue;
+                    }
+                    x.participant.info.uid.clone()
+                }
+                _ => return,
+            };
+            state.release_slot();
+            break uid;
+        };
+
+        if let Err(error) = storage.expire_contribution(&uid).await {
+            error!(?error, "Could not expire contribution");
+        }
+        guard::mark(&storage, &uid, Mark::Aborted).await;
+        wal::complete(&storage, Operation::SlotGrant, &uid).await;
+        if let Err(error) = storage
+            .insert_failed_attempt(&uid, AttemptOutcome::Expired.as_str())
+            .await
         {
-            state.active_contributor = ActiveContributor::None;
+            error!(?error, "Could not record failed attempt");
+        }
+    }
+}
 
-            drop(state);
-            storage.expire_contribution(&participant.0).await.unwrap();
+/// Saves the lobby to storage whenever participants join or leave it.
+/// Changes made while saving are saved together afterwards.
+pub async fn persist_lobby_on_change(state: SharedLobbyState, storage: PersistentStorage) {
+    loop {
+        state.changed.notified().await;
+        let snapshot = state.snapshot().await;
+        let snapshot = match serde_json::to_string(&snapshot) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                error!(?error, "Could not serialize lobby");
+                continue;
+            }
+        };
+        if let Err(error) = storage.save_lobby_snapshot(&snapshot).await {
+            error!(?error, "Could not save lobby");
         }
     }
 }
 
+/// Whether a participant in the lobby went longer than `max_diff` without a
+/// heartbeat. Participants who stepped away have until the end of their
+/// deferral.
+fn is_idle(session_info: &SessionInfo, now: Instant, max_diff: Duration) -> bool {
+    let last_seen = session_info
+        .deferred_until
+        .map_or(session_info.last_heartbeat, |until| {
+            until.max(session_info.last_heartbeat)
+        });
+    now.saturating_duration_since(last_seen) > max_diff
+}
+
 pub async fn clear_lobby_on_interval(state: SharedLobbyState, options: Options) {
-    let max_lobby_diff = options.lobby_checkin_frequency + options.lobby_checkin_tolerance;
     let max_session_diff = options.session_expiration;
 
     let mut interval = tokio::time::interval(options.lobby_flush_interval);
@@ -295,19 +1662,23 @@ pub async fn clear_lobby_on_interval(state: SharedLobbyState, options: Options)
     loop {
         interval.tick().await;
 
+        // Reloads may change the check-in deadline
+        let max_lobby_diff = state.checkin_deadline().await;
         let now = Instant::now();
-        // Predicate that returns true whenever users go over the ping deadline
-        let lobby_predicate = |session_info: &SessionInfo| -> bool {
-            let time_diff = now - session_info.last_ping_time;
-            time_diff > max_lobby_diff
-        };
+        // Predicate that returns true whenever users go over the heartbeat deadline
+        let lobby_predicate =
+            |session_info: &SessionInfo| -> bool { is_idle(session_info, now, max_lobby_diff) };
         state.clear_lobby(lobby_predicate).await;
 
+        // Sessions out of the lobby are also dropped once their lobby token expired,
+        // since they can no longer be redeemed
         let session_predicate = |session_info: &SessionInfo| -> bool {
-            let time_diff = now - session_info.last_ping_time;
-            time_diff > max_session_diff
+            let time_diff = now - session_info.last_heartbeat;
+            time_diff > max_session_diff || now > session_info.lobby_token_deadline
         };
         state.clear_session(session_predicate).await;
+
+        state.forget_evictions(max_session_diff).await;
     }
 }
 
@@ -360,3 +1731,587 @@ async fn flush_on_predicate() {
         assert_eq!(participant.info.token.exp % 2, 1);
     }
 }
+
+#[tokio::test]
+async fn reports_evictions_once() {
+    use crate::{
+        sessions::SessionId,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    tokio::time::pause();
+
+    let state = SharedLobbyState::new(test_options().lobby);
+    let idle = SessionId::new();
+    let expired = SessionId::new();
+    for id in [&idle, &expired] {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+    }
+    state.enter_lobby(&idle).await.unwrap();
+
+    state.clear_lobby(|_| true).await;
+    state.clear_session(|_| true).await;
+    assert_eq!(state.take_eviction(&idle).await, Some(EvictionReason::Idle));
+    assert_eq!(state.take_eviction(&idle).await, None);
+
+    let stats = state.eviction_stats().await;
+    assert_eq!(stats.idle_evictions, 1);
+    assert_eq!(stats.expired_sessions, 1);
+    assert!(stats.last_eviction_at.is_some());
+
+    // Clients that never come back are forgotten eventually
+    assert_eq!(state.pending_evictions().await, 1);
+    tokio::time::advance(Duration::from_secs(10)).await;
+    state.forget_evictions(Duration::from_secs(5)).await;
+    assert_eq!(state.take_eviction(&expired).await, None);
+}
+
+#[tokio::test]
+async fn estimates_wait_from_cycle_time() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    tokio::time::pause();
+
+    let options = test_options();
+    let deadline = options.lobby.compute_deadline;
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby);
+
+    assert_eq!(state.estimated_wait().await, Duration::ZERO);
+
+    let participants = [SessionId::new(), SessionId::new()];
+    for id in &participants {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        state.enter_lobby(id).await.unwrap();
+    }
+
+    // Without completed cycles, each one is assumed to take the full deadline
+    assert_eq!(state.estimated_wait().await, deadline * 2);
+
+    state
+        .set_current_contributor(&participants[0], deadline, db.clone())
+        .await
+        .unwrap();
+    tokio::time::advance(Duration::from_secs(10)).await;
+    state.clear_current_contributor().await;
+    assert_eq!(state.estimated_wait().await, Duration::from_secs(10));
+
+    // The current cycle only counts with its expected remaining time
+    state
+        .set_current_contributor(&participants[1], deadline, db.clone())
+        .await
+        .unwrap();
+    tokio::time::advance(Duration::from_secs(4)).await;
+    assert_eq!(state.estimated_wait().await, Duration::from_secs(6));
+
+    tokio::time::advance(Duration::from_secs(16)).await;
+    state.clear_current_contributor().await;
+    assert_eq!(
+        state.inner.lock().await.cycle_time.average,
+        Some(Duration::from_secs(11))
+    );
+}
+
+#[test]
+fn selection_index_is_uniform() {
+    let mut counts = [0; 3];
+    for index in 0..3000 {
+        counts[selection_index(&[7; 32], index, 3)] += 1;
+    }
+    assert!(counts.iter().all(|count| (900..1100).contains(count)));
+    assert_eq!(selection_index(&[7; 32], 0, 1), 0);
+}
+
+#[tokio::test]
+async fn selects_contributor_from_beacon() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    let options = test_options();
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    let beacon = Beacon {
+        round:      1,
+        randomness: vec![7; 32],
+    };
+    let deadline = options.lobby.compute_deadline;
+
+    assert_eq!(
+        state
+            .select_contributor(&beacon, &BTreeSet::new(), deadline, db.clone())
+            .await,
+        None
+    );
+
+    let mut sessions = Vec::new();
+    for i in 0..7 {
+        let id = SessionId::new();
+        let mut info = create_test_session_info(100);
+        info.uid = format!("uid{i}");
+        state.insert_session(id.clone(), info).await.unwrap();
+        state.enter_lobby(&id).await.unwrap();
+        sessions.push(id);
+    }
+    assert_eq!(state.lobby_uids().await.len(), 7);
+
+    // Identities that can't take the slot aren't drawn
+    let excluded = ["uid0", "uid1"].map(String::from).into_iter().collect();
+    let pick = state
+        .select_contributor(&beacon, &excluded, deadline, db.clone())
+        .await
+        .unwrap();
+    let (selected, selection) = (pick.participant, pick.selection);
+    assert!(!sessions[..2].contains(&selected));
+    assert!(!excluded.contains(&pick.uid));
+    assert_eq!(
+        state
+            .reserved_slot(&selected)
+            .await
+            .map(|(slot_id, _)| slot_id),
+        Some(pick.slot_id)
+    );
+    assert_eq!(state.get_lobby_size().await, 6);
+
+    // The pick can be recomputed from the published selection
+    assert_eq!(state.selection(0).await.as_ref(), Some(&selection));
+    assert_eq!(selection.candidates.len(), 5);
+    assert_eq!(selection.selected, selection_index(&[7; 32], 0, 5));
+    assert_eq!(
+        selection.candidates[selection.selected],
+        session_id_hash(&selected)
+    );
+
+    // The slot is taken, so nobody else is picked
+    assert_eq!(
+        state
+            .select_contributor(&beacon, &BTreeSet::new(), deadline, db)
+            .await,
+        None
+    );
+    assert_eq!(state.selection(1).await, None);
+}
+
+#[tokio::test]
+async fn restores_saved_lobby() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    let mut options = test_options();
+    options.lobby.lobby_on_deck = 1;
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    tokio::spawn(persist_lobby_on_change(state.clone(), db.clone()));
+
+    let ids = [SessionId::new(), SessionId::new()];
+    for id in &ids {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        state.enter_lobby(id).await.unwrap();
+    }
+    // Nobody holds the slot, so the first participant to ask takes it
+    state
+        .set_current_contributor(&ids[0], options.lobby.compute_deadline, db.clone())
+        .await
+        .unwrap();
+    state
+        .set_current_contributor(&ids[1], options.lobby.compute_deadline, db.clone())
+        .await
+        .unwrap_err();
+    let saved = state.snapshot().await;
+    assert_eq!(saved.sessions.len(), 1);
+    assert_eq!(saved.on_deck, vec![ids[1].clone()]);
+    assert!(saved.sessions[0].joined_at.is_some());
+
+    while db.lobby_snapshot().await.unwrap().map(|snapshot| {
+        serde_json::from_str::<LobbySnapshot>(&snapshot)
+            .unwrap()
+            .on_deck
+    }) != Some(saved.on_deck.clone())
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // The restarted sequencer sees the same lobby, without the slot holder
+    let restarted = SharedLobbyState::new(options.lobby.clone());
+    assert_eq!(restarted.restore_from(&db).await.unwrap(), 1);
+    assert!(restarted.is_in_lobby(&ids[1]).await);
+    assert_eq!(restarted.on_deck_position(&ids[1]).await, Some(0));
+    assert_eq!(
+        restarted.snapshot().await.sessions,
+        saved.sessions,
+        "sessions are restored as saved"
+    );
+
+    // Lobbies saved long ago are dropped
+    let mut stale = saved;
+    stale.saved_at = Utc::now() - chrono::Duration::hours(1);
+    let restarted = SharedLobbyState::new(options.lobby);
+    assert_eq!(restarted.restore(stale).await, 0);
+    assert_eq!(restarted.get_lobby_size().await, 0);
+}
+
+#[tokio::test]
+async fn admits_nobody_once_closed() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    let options = test_options();
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    let waiting = SessionId::new();
+    let late = SessionId::new();
+    for id in [&waiting, &late] {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+    }
+    state.enter_lobby(&waiting).await.unwrap();
+
+    state.close().await;
+    assert!(matches!(
+        state.enter_lobby(&late).await,
+        Err(ActiveContributorError::LobbyClosed)
+    ));
+    assert!(matches!(
+        state
+            .set_current_contributor(&waiting, options.lobby.compute_deadline, db)
+            .await,
+        Err(ActiveContributorError::LobbyClosed)
+    ));
+    assert!(state.is_slot_free().await);
+}
+
+#[tokio::test]
+async fn releases_slot_of_contributing_session() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    let options = test_options();
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    let holder = SessionId::new();
+    let other = SessionId::new();
+    for id in [&holder, &other] {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        state.enter_lobby(id).await.unwrap();
+    }
+    let slot_id = state
+        .set_current_contributor(&holder, options.lobby.compute_deadline, db)
+        .await
+        .unwrap();
+    state.begin_contributing(&holder, &slot_id).await.unwrap();
+
+    // Only the session holding the slot can give it back
+    assert!(state.release_slot_of(&other).await.is_none());
+    assert!(!state.is_slot_free().await);
+    assert!(state.release_slot_of(&holder).await.is_some());
+    assert!(state.is_slot_free().await);
+    assert!(state.release_slot_of(&holder).await.is_none());
+}
+
+#[tokio::test]
+async fn deprioritized_participant_goes_last() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    let options = test_options();
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    let bot = SessionId::new();
+    let person = SessionId::new();
+    for id in [&bot, &person] {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        state.enter_lobby(id).await.unwrap();
+    }
+    assert!(state.deprioritize(&bot).await);
+    assert!(!state.deprioritize(&SessionId::new()).await);
+
+    assert!(matches!(
+        state
+            .set_current_contributor(&bot, options.lobby.compute_deadline, db.clone())
+            .await,
+        Err(ActiveContributorError::AnotherContributionInProgress { .. })
+    ));
+    state
+        .set_current_contributor(&person, options.lobby.compute_deadline, db.clone())
+        .await
+        .unwrap();
+    state.clear_current_contributor().await;
+
+    // With nobody else waiting, they get the slot after all
+    state
+        .set_current_contributor(&bot, options.lobby.compute_deadline, db)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn deferred_participant_waits_until_back() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    tokio::time::pause();
+    let options = test_options();
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    let away = SessionId::new();
+    let present = SessionId::new();
+    for id in [&away, &present] {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        state.enter_lobby(id).await.unwrap();
+    }
+
+    let deferral = Duration::from_secs(600);
+    assert_eq!(state.defer(&away, deferral).await.unwrap(), deferral);
+    assert!(matches!(
+        state.defer(&SessionId::new(), deferral).await,
+        Err(ActiveContributorError::UserNotInLobby)
+    ));
+
+    // The free slot goes to whoever is present
+    assert!(matches!(
+        state
+            .set_current_contributor(&away, options.lobby.compute_deadline, db.clone())
+            .await,
+        Err(ActiveContributorError::AnotherContributionInProgress { estimated_wait })
+            if estimated_wait == deferral
+    ));
+    state
+        .set_current_contributor(&present, options.lobby.compute_deadline, db.clone())
+        .await
+        .unwrap();
+    state.clear_current_contributor().await;
+
+    // Not pinging while away doesn't get them evicted
+    tokio::time::advance(Duration::from_secs(300)).await;
+    let now = Instant::now();
+    let max_lobby_diff =
+        options.lobby.lobby_checkin_frequency + options.lobby.lobby_checkin_tolerance;
+    state
+        .clear_lobby(|session| is_idle(session, now, max_lobby_diff))
+        .await;
+    assert!(state.is_in_lobby(&away).await);
+
+    // Once back, they can take the slot
+    tokio::time::advance(Duration::from_secs(300)).await;
+    state
+        .set_current_contributor(&away, options.lobby.compute_deadline, db)
+        .await
+        .unwrap();
+}
+
+/// Participants poll for the slot while others contribute or run out of
+/// time, in an interleaving drawn from the seed. Whatever the interleaving,
+/// contributions must land in the transcript in the order their slots were
+/// handed out, and no slot may be handed out while a contribution is in
+/// progress.
+#[tokio::test]
+async fn hands_out_slot_in_total_order() {
+    use crate::{
+        storage::storage_client,
+        test_util::{
+            create_test_session_info, simulated_verifier, test_options, Scheduler, VirtualClock,
+        },
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+        SharedTranscript,
+    };
+
+    async fn simulate(seed: u64) -> (Vec<(Duration, usize, &'static str)>, Vec<Identity>) {
+        let clock = Arc::new(VirtualClock::start());
+        let mut scheduler = Scheduler::new(seed);
+        let options = test_options();
+        let deadline = Duration::from_secs(10);
+        let db = storage_client(&options.storage).await.unwrap();
+        let state = SharedLobbyState::new(options.lobby);
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+        let verifier = simulated_verifier(Duration::from_secs(3));
+        let events = Arc::new(std::sync::Mutex::new(Vec::<(Duration, usize, &str)>::new()));
+
+        let mut participants = Vec::new();
+        for index in 0..4_u8 {
+            let id = SessionId::new();
+            let mut info = create_test_session_info(100);
+            info.token.identity = Identity::Github {
+                id:       index.into(),
+                username: format!("participant{index}"),
+            };
+            state.insert_session(id.clone(), info).await.unwrap();
+            state.enter_lobby(&id).await.unwrap();
+            let compute = scheduler.duration(deadline * 3 / 2);
+            let (clock, state, db, transcript, verifier, events) = (
+                clock.clone(),
+                state.clone(),
+                db.clone(),
+                transcript.clone(),
+                verifier.clone(),
+                events.clone(),
+            );
+            participants.push(async move {
+                let event = |name: &'static str| {
+                    events
+                        .lock()
+                        .unwrap()
+                        .push((clock.elapsed(), usize::from(index), name));
+                };
+                let slot_id = loop {
+                    match state
+                        .set_current_contributor(&id, deadline, db.clone())
+                        .await
+                    {
+                        Ok(slot_id) => break slot_id,
+                        Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+                    }
+                };
+                event("granted");
+                tokio::time::sleep(compute).await;
+                match state.begin_contributing(&id, &slot_id).await {
+                    Ok(info) => {
+                        event("contributing");
+                        let contribution = valid_contribution(&transcript.snapshot(), index + 1);
+                        verifier
+                            .verify_add(&transcript, contribution, info.token.identity)
+                            .await
+                            .unwrap();
+                        state.clear_current_contributor().await;
+                        event("contributed");
+                    }
+                    Err(_) => event("expired"),
+                }
+            });
+        }
+        scheduler.run(participants).await;
+
+        let events = events.lock().unwrap().clone();
+        (events, transcript.snapshot().participant_ids.clone())
+    }
+
+    for seed in 0..4 {
+        let (events, participant_ids) = simulate(seed).await;
+        // The same seed gives the same run
+        assert_eq!(simulate(seed).await.0, events);
+        assert_eq!(events.iter().filter(|e| e.2 == "granted").count(), 4);
+
+        let contributed = events
+            .iter()
+            .filter(|e| e.2 == "contributed")
+            .map(|e| e.1)
+            .collect::<Vec<_>>();
+        let expected_ids = contributed
+            .iter()
+            .map(|&index| Identity::Github {
+                id:       u64::try_from(index).unwrap(),
+                username: format!("participant{index}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(participant_ids[1..], expected_ids, "seed {seed}");
+
+        // Grants never overlap a contribution in progress
+        let mut contributing = None;
+        for (_, index, name) in &events {
+            match *name {
+                "granted" => assert_eq!(
+                    contributing, None,
+                    "seed {seed}: slot handed to {index} during a contribution"
+                ),
+                "contributing" => contributing = Some(*index),
+                "contributed" => contributing = None,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn reloads_without_dropping_lobby() {
+    use crate::{
+        storage::storage_client,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    tokio::time::pause();
+
+    let options = test_options();
+    let db = storage_client(&options.storage).await.unwrap();
+    let state = SharedLobbyState::new(options.lobby.clone());
+    let participants = [SessionId::new(), SessionId::new()];
+    for id in &participants {
+        state
+            .insert_session(id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        state.enter_lobby(id).await.unwrap();
+    }
+    state
+        .set_current_contributor(&participants[0], options.lobby.compute_deadline, db)
+        .await
+        .unwrap();
+
+    let reloaded = state
+        .reload(&LobbyParams {
+            max_lobby_size: Some(1),
+            lobby_checkin_frequency: Some(10),
+            lobby_checkin_tolerance: Some(1),
+            ..LobbyParams::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(reloaded.lobby_checkin_frequency, Some(10));
+    assert_eq!(state.options().max_lobby_size, 1);
+    assert_eq!(state.get_lobby_size().await, 1);
+    assert!(state.reserved_slot(&participants[0]).await.is_some());
+
+    // The old deadline applies until everyone had the time to ping again
+    let old_deadline =
+        options.lobby.lobby_checkin_frequency + options.lobby.lobby_checkin_tolerance;
+    assert_eq!(state.checkin_deadline().await, old_deadline);
+    tokio::time::advance(old_deadline).await;
+    assert_eq!(state.checkin_deadline().await, Duration::from_secs(11));
+
+    assert_eq!(
+        state
+            .reload(&LobbyParams {
+                lobby_checkin_tolerance: Some(20),
+                ..LobbyParams::default()
+            })
+            .await,
+        Err(LobbyParamsError::ToleranceExceedsFrequency)
+    );
+    assert_eq!(
+        state.options().lobby_checkin_tolerance,
+        Duration::from_secs(1)
+    );
+}
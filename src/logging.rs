@@ -0,0 +1,56 @@
+//! Tracing of HTTP requests.
+//!
+//! The log format and filter are set with the `--log-format` and
+//! `--log-filter` flags of `cli_batteries`, which owns the global subscriber.
+
+use axum::Router;
+use clap::Parser;
+use http::header::{AUTHORIZATION, COOKIE, SET_COOKIE};
+use std::str::FromStr;
+use tower_http::{
+    sensitive_headers::{SetSensitiveRequestHeadersLayer, SetSensitiveResponseHeadersLayer},
+    trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+use tracing::Level;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Level of the span and event logged for each HTTP request, one of
+    /// `trace`, `debug`, `info`, `warn` or `error`. Requests are only logged if
+    /// the log filter lets the level through.
+    #[clap(long, env, value_parser = Level::from_str, default_value = "info")]
+    pub http_trace_level: Level,
+
+    /// Include the request and response headers in the HTTP request logs.
+    /// Credentials and cookies are redacted.
+    #[clap(long, env, default_value = "false")]
+    pub http_trace_headers: bool,
+}
+
+/// Logs every request to `router` as configured.
+pub fn trace_requests(router: Router, options: &Options) -> Router {
+    let level = options.http_trace_level;
+    let include_headers = options.http_trace_headers;
+    let redacted = [AUTHORIZATION, COOKIE, SET_COOKIE];
+    // Headers are marked as sensitive before the trace layer sees them, so
+    // that it only logs redacted values.
+    router
+        .layer(SetSensitiveResponseHeadersLayer::new(redacted.clone()))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(
+                    DefaultMakeSpan::new()
+                        .level(level)
+                        .include_headers(include_headers),
+                )
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(level)
+                        .latency_unit(LatencyUnit::Millis)
+                        .include_headers(include_headers),
+                ),
+        )
+        .layer(SetSensitiveRequestHeadersLayer::new(redacted))
+}
@@ -1,7 +1,11 @@
 use cli_batteries::version;
-use kzg_ceremony_sequencer::async_main;
+use kzg_ceremony_sequencer::{apply_config_file, async_main};
 
 #[allow(dead_code)] // Entry point
 fn main() {
+    if let Err(error) = apply_config_file() {
+        eprintln!("{error:?}");
+        std::process::exit(1);
+    }
     cli_batteries::run(version!(crypto, small_powers_of_tau), async_main);
 }
@@ -0,0 +1,496 @@
+//! Mirrors of the transcript file on other storage backends, so that a single
+//! storage failure doesn't lose the ceremony state. After every write, the
+//! transcript file is pushed to all mirrors at once, exactly as it is on disk,
+//! so encrypted transcripts stay encrypted on the mirrors.
+//!
+//! A failing mirror doesn't fail the write. Its health is tracked instead,
+//! and reported at `/info/storage` together with whether each mirror holds
+//! the same transcript as the local file.
+
+use crate::{cache::ResponseCache, lobby::duration_from_str, util::Secret};
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use eyre::{eyre, WrapErr};
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{error, info};
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Locations the transcript file is mirrored to, separated by commas.
+    /// Supported are local files as `file:///path/transcript.json`, S3
+    /// objects as `s3://bucket/key` and Google Cloud Storage objects as
+    /// `gs://bucket/object`.
+    #[clap(long, env, value_delimiter = ',')]
+    pub transcript_mirrors: Vec<Url>,
+
+    /// Region of the S3 buckets in `--transcript-mirrors`.
+    #[clap(long, env, default_value = "us-east-1")]
+    pub s3_region: String,
+
+    /// Access key id for the S3 mirrors.
+    #[clap(long, env)]
+    pub s3_access_key_id: Option<String>,
+
+    /// Secret access key for the S3 mirrors.
+    #[clap(long, env)]
+    pub s3_secret_access_key: Option<Secret>,
+
+    /// Access id of the HMAC key for the Google Cloud Storage mirrors, which
+    /// are written through its S3 compatible XML API.
+    #[clap(long, env)]
+    pub gcs_hmac_access_id: Option<String>,
+
+    /// Secret of the HMAC key for the Google Cloud Storage mirrors.
+    #[clap(long, env)]
+    pub gcs_hmac_secret: Option<Secret>,
+
+    /// How long the consistency check at `/info/storage` is cached, in
+    /// seconds. Every check downloads the transcript from all mirrors.
+    #[clap(long, env, value_parser=duration_from_str, default_value="60")]
+    pub mirror_check_ttl: Duration,
+}
+
+/// Signed headers of requests to S3 compatible APIs.
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+#[derive(Clone, Debug)]
+struct Credentials {
+    access_key_id: String,
+    secret:        Secret,
+}
+
+impl Credentials {
+    fn new(access_key_id: Option<&String>, secret: Option<&Secret>) -> Option<Self> {
+        Some(Self {
+            access_key_id: access_key_id?.clone(),
+            secret:        secret?.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Backend {
+    Local(PathBuf),
+    /// An object behind an S3 compatible API.
+    Object {
+        url:         Url,
+        region:      String,
+        credentials: Credentials,
+    },
+}
+
+impl Backend {
+    fn new(location: &Url, options: &Options) -> eyre::Result<Self> {
+        let bucket = || {
+            location
+                .host_str()
+                .ok_or_else(|| eyre!("Mirror {location} has no bucket"))
+        };
+        let key = location.path().trim_start_matches('/');
+        match location.scheme() {
+            "file" => location
+                .to_file_path()
+                .map(Self::Local)
+                .map_err(|_| eyre!("Mirror {location} is not a valid path")),
+            "s3" => Ok(Self::Object {
+                url:         Url::parse(&format!(
+                    "https://{}.s3.{}.amazonaws.com/{key}",
+                    bucket()?,
+                    options.s3_region
+                ))?,
+                region:      options.s3_region.clone(),
+                credentials: Credentials::new(
+                    options.s3_access_key_id.as_ref(),
+                    options.s3_secret_access_key.as_ref(),
+                )
+                .ok_or_else(|| {
+                    eyre!("Mirror {location} needs --s3-access-key-id and --s3-secret-access-key")
+                })?,
+            }),
+            "gs" => Ok(Self::Object {
+                url:         Url::parse(&format!(
+                    "https://storage.googleapis.com/{}/{key}",
+                    bucket()?
+                ))?,
+                region:      "auto".to_string(),
+                credentials: Credentials::new(
+                    options.gcs_hmac_access_id.as_ref(),
+                    options.gcs_hmac_secret.as_ref(),
+                )
+                .ok_or_else(|| {
+                    eyre!("Mirror {location} needs --gcs-hmac-access-id and --gcs-hmac-secret")
+                })?,
+            }),
+            scheme => Err(eyre!(
+                "Unsupported mirror {location}: {scheme}:// is neither file://, s3:// nor gs://"
+            )),
+        }
+    }
+
+    async fn put(&self, client: &reqwest::Client, contents: &Bytes) -> eyre::Result<()> {
+        match self {
+            Self::Local(path) => {
+                let mut work_path = path.clone().into_os_string();
+                work_path.push(".next");
+                tokio::fs::write(&work_path, contents).await?;
+                tokio::fs::rename(&work_path, path).await?;
+            }
+            Self::Object {
+                url,
+                region,
+                credentials,
+            } => {
+                let mut request = client.put(url.clone()).body(contents.clone());
+                for (name, value) in sign("PUT", url, region, credentials, contents, Utc::now()) {
+                    request = request.header(name, value);
+                }
+                request.send().await?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The mirrored transcript, or `None` if there is none yet.
+    async fn get(&self, client: &reqwest::Client) -> eyre::Result<Option<Bytes>> {
+        match self {
+            Self::Local(path) => match tokio::fs::read(path).await {
+                Ok(contents) => Ok(Some(contents.into())),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(error) => Err(error.into()),
+            },
+            Self::Object {
+                url,
+                region,
+                credentials,
+            } => {
+                let mut request = client.get(url.clone());
+                for (name, value) in sign("GET", url, region, credentials, b"", Utc::now()) {
+                    request = request.header(name, value);
+                }
+                let response = request.send().await?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                Ok(Some(response.error_for_status()?.bytes().await?))
+            }
+        }
+    }
+}
+
+/// Headers that authenticate a request to an S3 compatible API, using AWS
+/// signature version 4.
+fn sign(
+    method: &str,
+    url: &Url,
+    region: &str,
+    credentials: &Credentials,
+    payload: &[u8],
+    now: DateTime<Utc>,
+) -> [(&'static str, String); 3] {
+    let payload_hash = hex::encode(Sha256::digest(payload));
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &timestamp[..8];
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (host, None) => host.unwrap_or_default().to_string(),
+        (None, Some(_)) => String::new(),
+    };
+    let canonical_request = format!(
+        "{method}\n{}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\\
+         n\n{SIGNED_HEADERS}\n{payload_hash}",
+        url.path()
+    );
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request))
+    );
+    let key = signing_key(credentials.secret.get_secret(), date, region, "s3");
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={SIGNED_HEADERS}, \
+         Signature={signature}",
+        credentials.access_key_id
+    );
+    [
+        ("x-amz-content-sha256", payload_hash),
+        ("x-amz-date", timestamp),
+        ("authorization", authorization),
+    ]
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{secret}").into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes())
+        })
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Outcome of the pushes to a mirror.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MirrorHealth {
+    pub consecutive_failures: usize,
+    pub last_success:         Option<DateTime<Utc>>,
+    pub last_failure:         Option<DateTime<Utc>>,
+    pub last_error:           Option<String>,
+}
+
+impl MirrorHealth {
+    /// True if the last push succeeded.
+    #[must_use]
+    pub const fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0 && self.last_success.is_some()
+    }
+}
+
+#[derive(Debug)]
+struct Mirror {
+    location: Url,
+    backend:  Backend,
+    health:   Mutex<MirrorHealth>,
+}
+
+impl Mirror {
+    fn health(&self) -> MirrorHealth {
+        self.health
+            .lock()
+            .expect("Mirror health lock poisoned")
+            .clone()
+    }
+
+    fn record(&self, result: eyre::Result<()>) {
+        let mut health = self.health.lock().expect("Mirror health lock poisoned");
+        match result {
+            Ok(()) => {
+                health.consecutive_failures = 0;
+                health.last_success = Some(Utc::now());
+            }
+            Err(error) => {
+                error!(location = %self.location, ?error, "Could not mirror transcript");
+                health.consecutive_failures += 1;
+                health.last_failure = Some(Utc::now());
+                health.last_error = Some(format!("{error:#}"));
+            }
+        }
+    }
+}
+
+/// Whether a mirror holds the same transcript as the local file.
+#[derive(Debug, Serialize)]
+pub struct MirrorReport {
+    pub location:   String,
+    pub healthy:    bool,
+    #[serde(flatten)]
+    pub health:     MirrorHealth,
+    /// Hex encoded SHA-256 hash of the mirrored transcript, if there is one.
+    pub sha256:     Option<String>,
+    pub consistent: bool,
+    /// Why the mirrored transcript could not be read.
+    pub error:      Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageReport {
+    pub checked_at: DateTime<Utc>,
+    /// Hex encoded SHA-256 hash of the local transcript file.
+    pub sha256:     String,
+    /// True if all mirrors hold the same transcript as the local file.
+    pub consistent: bool,
+    pub mirrors:    Vec<MirrorReport>,
+}
+
+pub struct Mirrors {
+    client:  reqwest::Client,
+    mirrors: Vec<Mirror>,
+    /// Held while pushing, so that an older transcript never overwrites a
+    /// newer one.
+    pushing: tokio::sync::Mutex<()>,
+    check:   ResponseCache,
+}
+
+pub type SharedMirrors = Arc<Mirrors>;
+
+impl Mirrors {
+    /// Sets up the configured mirrors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a mirror location is not supported, or if the
+    /// credentials for it are missing.
+    pub fn new(options: &Options, client: reqwest::Client) -> eyre::Result<Self> {
+        let mirrors = options
+            .transcript_mirrors
+            .iter()
+            .map(|location| {
+                Ok(Mirror {
+                    location: location.clone(),
+                    backend:  Backend::new(location, options)?,
+                    health:   Mutex::default(),
+                })
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self {
+            client,
+            mirrors,
+            pushing: tokio::sync::Mutex::new(()),
+            check: ResponseCache::new(options.mirror_check_ttl),
+        })
+    }
+
+    /// Pushes the transcript file at `path` to all mirrors at once. The file
+    /// is read once it is this push's turn, so concurrent pushes leave the
+    /// mirrors with the latest transcript. Failures are logged and recorded
+    /// in the health of the mirror.
+    pub async fn push_file(&self, path: &Path) {
+        if self.mirrors.is_empty() {
+            return;
+        }
+        let _pushing = self.pushing.lock().await;
+        let contents = match tokio::fs::read(path).await {
+            Ok(contents) => Bytes::from(contents),
+            Err(error) => {
+                error!(?path, ?error, "Could not read transcript to mirror");
+                return;
+            }
+        };
+        join_all(self.mirrors.iter().map(|mirror| async {
+            mirror.record(mirror.backend.put(&self.client, &contents).await);
+        }))
+        .await;
+        info!(mirrors = self.mirrors.len(), "Mirrored transcript");
+    }
+
+    /// Compares the transcript on every mirror with the local file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the local file cannot be read.
+    pub async fn report(&self, path: &Path) -> eyre::Result<StorageReport> {
+        let local = tokio::fs::read(path)
+            .await
+            .wrap_err_with(|| format!("Cannot read {path:?}"))?;
+        let sha256 = hex::encode(Sha256::digest(&local));
+        let mirrors = join_all(self.mirrors.iter().map(|mirror| async {
+            let (hash, error) = match mirror.backend.get(&self.client).await {
+                Ok(contents) => (contents.map(|c| hex::encode(Sha256::digest(&c))), None),
+                Err(error) => (None, Some(format!("{error:#}"))),
+            };
+            let health = mirror.health();
+            MirrorReport {
+                location: mirror.location.to_string(),
+                healthy: health.is_healthy(),
+                health,
+                consistent: hash.as_ref() == Some(&sha256),
+                sha256: hash,
+                error,
+            }
+        }))
+        .await;
+        Ok(StorageReport {
+            checked_at: Utc::now(),
+            consistent: mirrors.iter().all(|mirror| mirror.consistent),
+            sha256,
+            mirrors,
+        })
+    }
+
+    /// The JSON encoded [`StorageReport`], checked at most once per
+    /// `--mirror-check-ttl`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the local file cannot be read.
+    pub async fn cached_report(&self, path: &Path) -> eyre::Result<Bytes> {
+        self.check
+            .get(|| async { Ok(Bytes::from(serde_json::to_vec(&self.report(path).await?)?)) })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn mirrors_to_all_backends() {
+        let dir = tempdir().unwrap();
+        let transcript = dir.path().join("transcript.json");
+        let good = dir.path().join("mirror.json");
+        let broken = dir.path().join("missing").join("mirror.json");
+        let mut options = Options::parse_from(Vec::<&str>::new());
+        options.transcript_mirrors = vec![
+            Url::from_file_path(&good).unwrap(),
+            Url::from_file_path(&broken).unwrap(),
+        ];
+        let mirrors = Mirrors::new(&options, reqwest::Client::new()).unwrap();
+
+        tokio::fs::write(&transcript, b"transcript").await.unwrap();
+        mirrors.push_file(&transcript).await;
+        assert_eq!(tokio::fs::read(&good).await.unwrap(), b"transcript");
+
+        let report = mirrors.report(&transcript).await.unwrap();
+        assert!(!report.consistent);
+        assert!(report.mirrors[0].healthy);
+        assert!(report.mirrors[0].consistent);
+        assert!(!report.mirrors[1].healthy);
+        assert!(!report.mirrors[1].consistent);
+        assert_eq!(report.mirrors[1].health.consecutive_failures, 1);
+
+        tokio::fs::create_dir(broken.parent().unwrap())
+            .await
+            .unwrap();
+        mirrors.push_file(&transcript).await;
+        let report = mirrors.report(&transcript).await.unwrap();
+        assert!(report.consistent);
+        assert!(report.mirrors.iter().all(|mirror| mirror.healthy));
+    }
+
+    #[test]
+    fn rejects_unsupported_mirrors() {
+        let mut options = Options::parse_from(Vec::<&str>::new());
+        options.transcript_mirrors = vec![Url::parse("ftp://example.com/transcript.json").unwrap()];
+        assert!(Mirrors::new(&options, reqwest::Client::new()).is_err());
+
+        // S3 mirrors need credentials
+        options.transcript_mirrors = vec![Url::parse("s3://bucket/transcript.json").unwrap()];
+        assert!(Mirrors::new(&options, reqwest::Client::new()).is_err());
+        options.s3_access_key_id = Some("AKIDEXAMPLE".to_string());
+        options.s3_secret_access_key = Some("secret".parse().unwrap());
+        assert!(Mirrors::new(&options, reqwest::Client::new()).is_ok());
+    }
+
+    #[test]
+    fn derives_signing_key() {
+        // Example from the AWS signature version 4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+}
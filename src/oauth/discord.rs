@@ -0,0 +1,98 @@
+use crate::{lobby::duration_from_str, util::Secret};
+use chrono::{DateTime, TimeZone, Utc};
+use clap::Parser;
+use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
+use std::time::Duration;
+
+/// Start of the Discord epoch, in milliseconds since the Unix epoch.
+const DISCORD_EPOCH_MILLIS: u64 = 1_420_070_400_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+pub struct DiscordAuthOptions {
+    /// How old a Discord account has to be in order to participate, in
+    /// seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="7776000")]
+    pub discord_min_account_age: Duration,
+
+    /// Discord OAuth2 authorization url.
+    #[clap(long, env, default_value = "https://discord.com/oauth2/authorize")]
+    pub discord_auth_url: String,
+
+    /// Discord OAuth2 token url.
+    #[clap(long, env, default_value = "https://discord.com/api/oauth2/token")]
+    pub discord_token_url: String,
+
+    /// Discord user info url.
+    #[clap(long, env, default_value = "https://discord.com/api/users/@me")]
+    pub discord_userinfo_url: String,
+
+    /// Discord OAuth2 callback redirect url.
+    #[clap(
+        long,
+        env,
+        default_value = "http://127.0.0.1:3000/auth/callback/discord"
+    )]
+    pub discord_redirect_url: String,
+
+    /// Discord OAuth2 client id. Signing in with Discord is disabled when not
+    /// set.
+    #[clap(long, env, requires = "discord_client_secret")]
+    pub discord_client_id: Option<Secret>,
+
+    /// Discord OAuth2 client secret.
+    #[clap(long, env)]
+    pub discord_client_secret: Option<Secret>,
+}
+
+#[derive(Clone)]
+pub struct DiscordOAuthClient {
+    client: Option<BasicClient>,
+}
+
+impl DiscordOAuthClient {
+    /// The client, if signing in with Discord is enabled.
+    pub const fn client(&self) -> Option<&BasicClient> {
+        self.client.as_ref()
+    }
+}
+
+pub fn discord_oauth_client(options: &DiscordAuthOptions) -> DiscordOAuthClient {
+    DiscordOAuthClient {
+        client: options.discord_client_id.as_ref().map(|client_id| {
+            BasicClient::new(
+                ClientId::new(client_id.get_secret().to_owned()),
+                options
+                    .discord_client_secret
+                    .as_ref()
+                    .map(|secret| ClientSecret::new(secret.get_secret().to_owned())),
+                AuthUrl::new(options.discord_auth_url.clone()).unwrap(),
+                Some(TokenUrl::new(options.discord_token_url.clone()).unwrap()),
+            )
+            .set_redirect_uri(RedirectUrl::new(options.discord_redirect_url.clone()).unwrap())
+        }),
+    }
+}
+
+/// Discord doesn't report when an account was created, but its ids start
+/// with the creation time in milliseconds since the Discord epoch.
+pub fn discord_creation_time(id: u64) -> Option<DateTime<Utc>> {
+    let millis = (id >> 22).checked_add(DISCORD_EPOCH_MILLIS)?;
+    Utc.timestamp_millis_opt(i64::try_from(millis).ok()?)
+        .single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_creation_time_from_ids() {
+        // The example from the Discord API documentation.
+        assert_eq!(
+            discord_creation_time(175_928_847_299_117_063)
+                .unwrap()
+                .to_rfc3339(),
+            "2016-04-30T11:18:25.796+00:00"
+        );
+    }
+}
@@ -0,0 +1,462 @@
+//! Confirmation of an email address as a second factor after signing in.
+//!
+//! Participants are sent a one-time code to the address they give, and may
+//! only enter the lobby once they entered it. Each address confirms a single
+//! identity, which makes contributing with throwaway accounts more laborious.
+//! Addresses are only stored as keyed hashes, so they can't be recovered from
+//! a storage dump by hashing candidate addresses.
+
+use crate::{lobby::duration_from_str, sessions::SessionId, storage::StorageError, util::Secret};
+use clap::Parser;
+use eyre::{eyre, Result as EyreResult};
+use hmac::{Hmac, Mac};
+use kzg_ceremony_crypto::ErrorCode;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Address, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use strum::IntoStaticStr;
+use thiserror::Error;
+use tokio::{sync::Mutex, time::Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+pub struct EmailOptions {
+    /// Require participants to confirm an email address with a code sent to
+    /// it, after signing in and before entering the lobby.
+    #[clap(long, env, default_value = "false")]
+    pub email_verification: bool,
+
+    /// SMTP server the codes are sent with. Required for email verification.
+    #[clap(long, env)]
+    pub smtp_host: Option<String>,
+
+    /// Port of the SMTP server. Connections are secured with STARTTLS.
+    #[clap(long, env, default_value = "587")]
+    pub smtp_port: u16,
+
+    /// User name to authenticate at the SMTP server with.
+    #[clap(long, env)]
+    pub smtp_username: Option<String>,
+
+    /// Password to authenticate at the SMTP server with.
+    #[clap(long, env)]
+    pub smtp_password: Option<Secret>,
+
+    /// Sender of the emails, e.g. `KZG Ceremony <ceremony@example.com>`.
+    #[clap(long, env, default_value = "KZG Ceremony <ceremony@localhost>")]
+    pub email_from: Mailbox,
+
+    /// How long an emailed code can be entered, in seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="600")]
+    pub email_code_ttl: Duration,
+
+    /// Number of wrong codes a session may enter before its code expires.
+    /// Requesting another code doesn't give more attempts.
+    #[clap(long, env, default_value = "5")]
+    pub email_code_max_attempts: usize,
+
+    /// How long a session, or anyone giving the same address, has to wait
+    /// before another code is sent, in seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="60")]
+    pub email_resend_cooldown: Duration,
+
+    /// Secret key for the HMAC-SHA256 that addresses are stored as. Required
+    /// for email verification, and must not change during the ceremony.
+    #[clap(long, env)]
+    pub email_hash_key: Option<Secret>,
+}
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum EmailError {
+    #[error("unknown session id")]
+    UnknownSessionId,
+    #[error("email verification is disabled")]
+    Disabled,
+    #[error("invalid email address")]
+    InvalidAddress,
+    #[error("email address confirms another identity already")]
+    AddressInUse,
+    #[error("no code was sent, or it expired")]
+    UnknownCode,
+    #[error("wrong code")]
+    WrongCode,
+    #[error("could not send email")]
+    DeliveryFailed,
+    #[error("a code was sent recently, retry in {retry_after_seconds} seconds")]
+    ResendTooSoon { retry_after_seconds: u64 },
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+impl ErrorCode for EmailError {
+    fn to_error_code(&self) -> String {
+        format!("EmailError::{}", <&str>::from(self))
+    }
+}
+
+/// Parses the address given by a participant.
+///
+/// # Errors
+///
+/// Returns an error if the address is invalid.
+pub fn parse_address(address: &str) -> Result<Address, EmailError> {
+    address
+        .trim()
+        .parse()
+        .map_err(|_| EmailError::InvalidAddress)
+}
+
+enum Mailer {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    /// Keeps the recipients and bodies of sent emails, for tests.
+    #[cfg(test)]
+    Outbox(Mutex<Vec<(Address, String)>>),
+}
+
+struct PendingCode {
+    code_hash:     [u8; 32],
+    email_hash:    String,
+    sent_at:       Instant,
+    deadline:      Instant,
+    attempts_left: usize,
+}
+
+#[derive(Default)]
+struct Codes {
+    pending: BTreeMap<SessionId, PendingCode>,
+    /// When a code was last sent to each address hash.
+    sent_to: BTreeMap<String, Instant>,
+}
+
+pub struct EmailVerifier {
+    options: EmailOptions,
+    mailer:  Option<Mailer>,
+    codes:   Mutex<Codes>,
+}
+
+pub type SharedEmailVerifier = Arc<EmailVerifier>;
+
+impl EmailVerifier {
+    /// # Errors
+    ///
+    /// Returns an error if email verification is enabled without an SMTP
+    /// server or hash key, or the server can't be used.
+    pub fn new(options: &EmailOptions) -> EyreResult<Self> {
+        let mailer = if options.email_verification {
+            if options.email_hash_key.is_none() {
+                return Err(eyre!("Email verification requires an email hash key"));
+            }
+            let host = options
+                .smtp_host
+                .as_deref()
+                .ok_or_else(|| eyre!("Email verification requires an SMTP host"))?;
+            let mut transport =
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?.port(options.smtp_port);
+            if let (Some(username), Some(password)) =
+                (&options.smtp_username, &options.smtp_password)
+            {
+                transport = transport.credentials(Credentials::new(
+                    username.clone(),
+                    password.get_secret().to_owned(),
+                ));
+            }
+            Some(Mailer::Smtp(transport.build()))
+        } else {
+            None
+        };
+        Ok(Self {
+            options: options.clone(),
+            mailer,
+            codes: Mutex::default(),
+        })
+    }
+
+    #[cfg(test)]
+    fn with_outbox(options: &EmailOptions) -> Self {
+        Self {
+            options: options.clone(),
+            mailer:  Some(Mailer::Outbox(Mutex::default())),
+            codes:   Mutex::default(),
+        }
+    }
+
+    /// Hex encoded HMAC-SHA256 of a normalized email address, as stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if email verification is disabled.
+    pub fn email_hash(&self, address: &Address) -> Result<String, EmailError> {
+        let key = match (&self.mailer, &self.options.email_hash_key) {
+            (Some(_), Some(key)) => key,
+            _ => return Err(EmailError::Disabled),
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.get_secret().as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(address.to_string().to_lowercase().as_bytes());
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// Sends a new code to the address, replacing any earlier code of the
+    /// session. The new code has the attempts left on the earlier one.
+    ///
+    /// Neither the session nor the address get another code within the
+    /// resend cooldown, and a session that used up its attempts gets none
+    /// until its code expired. A failed delivery counts as well.
+    pub async fn send_code(
+        &self,
+        session_id: &SessionId,
+        address: Address,
+    ) -> Result<(), EmailError> {
+        let mailer = self.mailer.as_ref().ok_or(EmailError::Disabled)?;
+        let email_hash = self.email_hash(&address)?;
+        let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        let body = format!(
+            "Your code to confirm this address for the KZG Ceremony is {code}. It is valid for {} \
+             minutes.\n\nIf you did not sign in to the ceremony, you can ignore this email.",
+            self.options.email_code_ttl.as_secs() / 60
+        );
+
+        // Reserve the cooldown before sending, so concurrent requests can't
+        // both send
+        {
+            let now = Instant::now();
+            let cooldown = self.options.email_resend_cooldown;
+            let mut codes = self.codes.lock().await;
+            codes.pending.retain(|_, code| code.deadline > now);
+            codes.sent_to.retain(|_, sent_at| *sent_at + cooldown > now);
+            let previous = codes.pending.get(session_id);
+            // A session that used up its attempts has to wait for the code
+            // to expire
+            let retry_at = previous
+                .map(|pending| {
+                    if pending.attempts_left == 0 {
+                        pending.deadline
+                    } else {
+                        pending.sent_at + cooldown
+                    }
+                })
+                .into_iter()
+                .chain(
+                    codes
+                        .sent_to
+                        .get(&email_hash)
+                        .map(|sent_at| *sent_at + cooldown),
+                )
+                .max();
+            if let Some(retry_at) = retry_at.filter(|retry_at| *retry_at > now) {
+                let retry_after = retry_at - now;
+                return Err(EmailError::ResendTooSoon {
+                    retry_after_seconds: retry_after.as_secs().max(1),
+                });
+            }
+            let pending = PendingCode {
+                code_hash:     Sha256::digest(&code).into(),
+                email_hash:    email_hash.clone(),
+                sent_at:       now,
+                deadline:      now + self.options.email_code_ttl,
+                attempts_left: previous.map_or(self.options.email_code_max_attempts, |pending| {
+                    pending.attempts_left
+                }),
+            };
+            codes.sent_to.insert(email_hash, now);
+            codes.pending.insert(session_id.clone(), pending);
+        }
+
+        match mailer {
+            Mailer::Smtp(transport) => {
+                let message = Message::builder()
+                    .from(self.options.email_from.clone())
+                    .to(Mailbox::new(None, address))
+                    .subject("Your KZG Ceremony code")
+                    .body(body)
+                    .map_err(|_| EmailError::InvalidAddress)?;
+                transport.send(message).await.map_err(|error| {
+                    tracing::error!(?error, "Could not send email");
+                    EmailError::DeliveryFailed
+                })?;
+            }
+            #[cfg(test)]
+            Mailer::Outbox(outbox) => outbox.lock().await.push((address, body)),
+        }
+        Ok(())
+    }
+
+    /// Checks a code entered for the session, and returns the hash of the
+    /// address it was sent to.
+    pub async fn confirm_code(
+        &self,
+        session_id: &SessionId,
+        code: &str,
+    ) -> Result<String, EmailError> {
+        let mut codes = self.codes.lock().await;
+        let pending = codes
+            .pending
+            .get_mut(session_id)
+            .filter(|pending| pending.deadline > Instant::now() && pending.attempts_left > 0)
+            .ok_or(EmailError::UnknownCode)?;
+        // Comparing hashes doesn't reveal how much of the code matched
+        if <[u8; 32]>::from(Sha256::digest(code.trim())) != pending.code_hash {
+            pending.attempts_left -= 1;
+            return Err(EmailError::WrongCode);
+        }
+        Ok(codes
+            .pending
+            .remove(session_id)
+            .map(|pending| pending.email_hash)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn sent_code(verifier: &EmailVerifier) -> String {
+        match &verifier.mailer {
+            Some(Mailer::Outbox(outbox)) => {
+                let (_, body) = outbox.lock().await.last().cloned().unwrap();
+                body.split_whitespace()
+                    .find(|word| word.len() == 7 && word.ends_with('.'))
+                    .unwrap()
+                    .trim_end_matches('.')
+                    .to_string()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn options() -> EmailOptions {
+        let mut options = EmailOptions::parse_from(["", "--email-hash-key", "secret"]);
+        options.email_verification = true;
+        options
+    }
+
+    #[tokio::test]
+    async fn confirms_codes() {
+        let mut options = options();
+        options.email_code_max_attempts = 2;
+        let verifier = EmailVerifier::with_outbox(&options);
+        let session_id = SessionId::new();
+        let address = parse_address(" Alice@Example.com ").unwrap();
+        assert!(parse_address("not an address").is_err());
+
+        tokio::time::pause();
+        assert!(matches!(
+            verifier.confirm_code(&session_id, "000000").await,
+            Err(EmailError::UnknownCode)
+        ));
+        verifier
+            .send_code(&session_id, address.clone())
+            .await
+            .unwrap();
+        let code = sent_code(&verifier).await;
+        assert_eq!(code.len(), 6);
+        assert_eq!(
+            verifier.confirm_code(&session_id, &code).await.unwrap(),
+            verifier
+                .email_hash(&parse_address("alice@example.com").unwrap())
+                .unwrap()
+        );
+        // Codes can only be used once
+        assert!(verifier.confirm_code(&session_id, &code).await.is_err());
+
+        // Wrong guesses use up the code, also across resends
+        tokio::time::advance(options.email_resend_cooldown).await;
+        verifier
+            .send_code(&session_id, address.clone())
+            .await
+            .unwrap();
+        let code = sent_code(&verifier).await;
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        assert!(matches!(
+            verifier.confirm_code(&session_id, wrong).await,
+            Err(EmailError::WrongCode)
+        ));
+        tokio::time::advance(options.email_resend_cooldown).await;
+        verifier
+            .send_code(&session_id, address.clone())
+            .await
+            .unwrap();
+        let code = sent_code(&verifier).await;
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        assert!(matches!(
+            verifier.confirm_code(&session_id, wrong).await,
+            Err(EmailError::WrongCode)
+        ));
+        assert!(matches!(
+            verifier.confirm_code(&session_id, &code).await,
+            Err(EmailError::UnknownCode)
+        ));
+        tokio::time::advance(options.email_resend_cooldown).await;
+        assert!(matches!(
+            verifier.send_code(&session_id, address.clone()).await,
+            Err(EmailError::ResendTooSoon { .. })
+        ));
+
+        // And so does time
+        tokio::time::advance(options.email_code_ttl).await;
+        verifier.send_code(&session_id, address).await.unwrap();
+        let code = sent_code(&verifier).await;
+        tokio::time::advance(options.email_code_ttl).await;
+        assert!(matches!(
+            verifier.confirm_code(&session_id, &code).await,
+            Err(EmailError::UnknownCode)
+        ));
+    }
+
+    #[tokio::test]
+    async fn throttles_resends() {
+        let options = options();
+        let verifier = EmailVerifier::with_outbox(&options);
+        let (alice, bob) = (SessionId::new(), SessionId::new());
+        let address = parse_address("alice@example.com").unwrap();
+
+        tokio::time::pause();
+        verifier.send_code(&alice, address.clone()).await.unwrap();
+        tokio::time::advance(Duration::from_secs(20)).await;
+        // Per session
+        assert!(matches!(
+            verifier
+                .send_code(&alice, parse_address("carol@example.com").unwrap())
+                .await,
+            Err(EmailError::ResendTooSoon {
+                retry_after_seconds: 40,
+            })
+        ));
+        // And per address
+        assert!(matches!(
+            verifier.send_code(&bob, address.clone()).await,
+            Err(EmailError::ResendTooSoon {
+                retry_after_seconds: 40,
+            })
+        ));
+        tokio::time::advance(Duration::from_secs(40)).await;
+        verifier.send_code(&bob, address).await.unwrap();
+    }
+
+    #[test]
+    fn hashes_with_key() {
+        let verifier = EmailVerifier::with_outbox(&options());
+        let mut options = options();
+        options.email_hash_key = Some("other".parse().unwrap());
+        let other = EmailVerifier::with_outbox(&options);
+        let address = parse_address("Alice@Example.com").unwrap();
+        assert_eq!(
+            verifier.email_hash(&address).unwrap(),
+            verifier
+                .email_hash(&parse_address("alice@example.com").unwrap())
+                .unwrap()
+        );
+        assert_ne!(
+            verifier.email_hash(&address).unwrap(),
+            other.email_hash(&address).unwrap()
+        );
+        assert_ne!(
+            verifier.email_hash(&address).unwrap(),
+            hex::encode(Sha256::digest("alice@example.com"))
+        );
+    }
+}
@@ -1,7 +1,10 @@
-use crate::util::Secret;
+use crate::{lobby::duration_from_str, util::Secret};
 use clap::Parser;
+use eyre::{eyre, Result as EyreResult, WrapErr};
 use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
-use std::{num::ParseIntError, ops::Deref};
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::{num::ParseIntError, ops::Deref, time::Duration};
 
 #[derive(Clone, Debug, PartialEq, Eq, Parser)]
 pub struct EthAuthOptions {
@@ -14,6 +17,12 @@ pub struct EthAuthOptions {
     #[clap(long, env, default_value = "4")]
     pub eth_min_nonce: u64,
 
+    /// How long a sign-in nonce stays valid after the auth link was
+    /// requested, in seconds. Each nonce can only be used once, and only with
+    /// the state it was issued in.
+    #[clap(long, env, value_parser=duration_from_str, default_value="600")]
+    pub eth_siwe_nonce_ttl: Duration,
+
     /// The Ethereum JSON-RPC endpoint to use.
     /// Defaults to the AllThatNode public node for testing.
     #[clap(
@@ -23,6 +32,14 @@ pub struct EthAuthOptions {
     )]
     pub eth_rpc_url: Secret,
 
+    /// Chains besides mainnet that users may sign in from, e.g. rollups where
+    /// their history lives. Each is given as `CHAIN_ID[@BLOCK]=RPC_URL`, with
+    /// the block height the transaction count is fetched at, or the latest
+    /// block if left out. The minimum nonce is the same on all chains. For
+    /// example `10@105235063=https://mainnet.optimism.io`.
+    #[clap(long, env, value_delimiter = ',', value_parser = EthChain::parse_from_cmd)]
+    pub eth_chains: Vec<EthChain>,
+
     //// Sign-in-with-Ethereum OAuth2 authorization url.
     #[clap(
         long,
@@ -56,6 +73,61 @@ pub struct EthAuthOptions {
     pub eth_client_secret: Secret,
 }
 
+/// Chain id of Ethereum mainnet, checked with `eth_rpc_url`.
+const MAINNET: u64 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EthChain {
+    pub chain_id: u64,
+    /// Block number as hex, or `latest`.
+    pub block:    String,
+    pub rpc_url:  Secret,
+}
+
+impl EthChain {
+    /// Parses a chain of the form `CHAIN_ID[@BLOCK]=RPC_URL`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the description is malformed.
+    pub fn parse_from_cmd(cmd: &str) -> EyreResult<Self> {
+        let (chain, rpc_url) = cmd
+            .split_once('=')
+            .ok_or_else(|| eyre!("Chain must be given as CHAIN_ID[@BLOCK]=RPC_URL"))?;
+        let (chain_id, block) = match chain.split_once('@') {
+            Some((chain_id, block)) => (
+                chain_id,
+                dec_to_hex(block).wrap_err("Invalid block height")?,
+            ),
+            None => (chain, "latest".to_string()),
+        };
+        let chain_id = chain_id.parse().wrap_err("Invalid chain id")?;
+        if chain_id == MAINNET {
+            return Err(eyre!("Mainnet is configured with --eth-rpc-url"));
+        }
+        Ok(Self {
+            chain_id,
+            block,
+            rpc_url: rpc_url.parse().unwrap_or_else(|never| match never {}),
+        })
+    }
+}
+
+impl EthAuthOptions {
+    /// The endpoint and block height to check the transaction count of users
+    /// signing in from `chain_id` with, if they may sign in from it.
+    #[must_use]
+    pub fn chain(&self, chain_id: u64) -> Option<(&Secret, &str)> {
+        if chain_id == MAINNET {
+            return Some((&self.eth_rpc_url, &self.eth_nonce_verification_block));
+        }
+        self.eth_chains
+            .iter()
+            .find(|chain| chain.chain_id == chain_id)
+            .map(|chain| (&chain.rpc_url, chain.block.as_str()))
+    }
+}
+
 #[derive(Clone)]
 pub struct EthOAuthClient {
     client: BasicClient,
@@ -85,6 +157,52 @@ pub fn eth_oauth_client(options: &EthAuthOptions) -> EthOAuthClient {
     }
 }
 
+/// Creates a fresh nonce for a sign-in attempt.
+pub fn siwe_nonce() -> String {
+    hex::encode(thread_rng().gen::<[u8; 16]>())
+}
+
+/// Binds a nonce to the OAuth state it was issued in, so that it can't be
+/// redeemed with a state that was altered on the way back from the provider.
+pub fn state_hash(state: &str) -> String {
+    hex::encode(Sha256::digest(state))
+}
+
 fn dec_to_hex(input: &str) -> Result<String, ParseIntError> {
     Ok(format!("0x{:x}", input.parse::<u64>()?))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_options;
+
+    #[test]
+    fn finds_chains() {
+        let optimism =
+            EthChain::parse_from_cmd("10@105235063=https://mainnet.optimism.io").unwrap();
+        assert_eq!(optimism.chain_id, 10);
+        assert_eq!(optimism.block, "0x645c277");
+        assert_eq!(optimism.rpc_url.get_secret(), "https://mainnet.optimism.io");
+        let arbitrum = EthChain::parse_from_cmd("42161=https://arb1.arbitrum.io/rpc").unwrap();
+        assert_eq!(arbitrum.block, "latest");
+        assert!(EthChain::parse_from_cmd("10").is_err());
+        assert!(EthChain::parse_from_cmd("op=https://mainnet.optimism.io").is_err());
+        assert!(EthChain::parse_from_cmd("10@soon=https://mainnet.optimism.io").is_err());
+        assert!(EthChain::parse_from_cmd("1=https://example.com").is_err());
+
+        let mut options = test_options().ethereum;
+        options.eth_chains = vec![optimism];
+        assert_eq!(
+            options.chain(1).map(|(_, block)| block.to_owned()),
+            Some(options.eth_nonce_verification_block.clone())
+        );
+        assert_eq!(
+            options
+                .chain(10)
+                .map(|(url, block)| (url.get_secret(), block)),
+            Some(("https://mainnet.optimism.io", "0x645c277"))
+        );
+        assert!(options.chain(42161).is_none());
+    }
+}
@@ -1,13 +1,31 @@
+mod discord;
+mod email;
 mod ethereum;
 mod github;
+mod passkey;
+mod pseudonym;
+mod twitter;
 
-use crate::sessions::SessionId;
-use std::{collections::BTreeMap, sync::Arc};
-use tokio::sync::RwLock;
+use crate::{
+    lobby::SharedLobbyState,
+    regions::Region,
+    sessions::{ConcurrentLoginPolicy, IdToken, Options as SessionOptions, SessionId, SessionInfo},
+};
+use chrono::{DateTime, Utc};
+use kzg_ceremony_crypto::signature::identity::Identity;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{sync::RwLock, time::Instant};
 
 pub use self::{
-    ethereum::{eth_oauth_client, EthAuthOptions, EthOAuthClient},
+    discord::{
+        discord_creation_time, discord_oauth_client, DiscordAuthOptions, DiscordOAuthClient,
+    },
+    email::{parse_address, EmailError, EmailOptions, EmailVerifier, SharedEmailVerifier},
+    ethereum::{eth_oauth_client, siwe_nonce, state_hash, EthAuthOptions, EthOAuthClient},
     github::{github_oauth_client, GithubAuthOptions, GithubOAuthClient},
+    passkey::{passkey_identity, PasskeyAuth, PasskeyError, PasskeyOptions, SharedPasskeyAuth},
+    pseudonym::PseudonymOptions,
+    twitter::{twitter_oauth_client, TwitterAuthOptions, TwitterOAuthClient},
 };
 
 pub type SharedAuthState = Arc<RwLock<AuthState>>;
@@ -16,7 +34,168 @@ pub type IdTokenSub = String;
 #[derive(Default)]
 pub struct AuthState {
     // A map between a users unique social id
-    // and their session.
-    // We use this to check if a user has already entered the lobby
-    pub unique_id_session: BTreeMap<IdTokenSub, SessionId>,
+    // and their sessions, oldest first.
+    // We use this to limit how many sessions a user holds at a time
+    pub unique_id_sessions: BTreeMap<IdTokenSub, Vec<SessionId>>,
+}
+
+impl AuthState {
+    /// Checks that `uid` may hold another session, as the policy says, and
+    /// returns the sessions that have to make room for it. Sessions the
+    /// lobby has dropped don't count. Returns `None` if the identity holds
+    /// too many sessions. Nothing is replaced until [`Self::add_session`].
+    pub async fn sessions_to_replace(
+        &mut self,
+        uid: &str,
+        options: &SessionOptions,
+        lobby_state: &SharedLobbyState,
+    ) -> Option<Vec<SessionId>> {
+        let sessions = self.unique_id_sessions.entry(uid.to_owned()).or_default();
+        let mut live = Vec::with_capacity(sessions.len());
+        for session_id in sessions.drain(..) {
+            if lobby_state.has_session(&session_id).await {
+                live.push(session_id);
+            }
+        }
+        *sessions = live;
+
+        let max_sessions = options.max_sessions_per_identity.max(1);
+        let excess = (sessions.len() + 1).saturating_sub(max_sessions);
+        if excess > 0 && options.concurrent_login_policy == ConcurrentLoginPolicy::RejectNew {
+            return None;
+        }
+        let replaced = sessions[..excess].to_vec();
+        // The slot holder's session is kept
+        for session_id in &replaced {
+            if lobby_state.holds_slot(session_id).await {
+                return None;
+            }
+        }
+        Some(replaced)
+    }
+
+    /// Records a new session of `uid`, once it is in the lobby, and drops the
+    /// sessions it replaces.
+    pub async fn add_session(
+        &mut self,
+        uid: &str,
+        session_id: SessionId,
+        replaced: &[SessionId],
+        lobby_state: &SharedLobbyState,
+    ) {
+        let sessions = self.unique_id_sessions.entry(uid.to_owned()).or_default();
+        for old in replaced {
+            if lobby_state.replace_session(old).await {
+                sessions.retain(|id| id != old);
+            }
+        }
+        sessions.push(session_id);
+    }
+}
+
+/// Issues a lobby token for a freshly authenticated user. The token has to be
+/// redeemed by entering the lobby within `ttl`, after which the user has to
+/// authenticate again.
+pub fn issue_lobby_token(identity: Identity, region: Option<Region>, ttl: Duration) -> SessionInfo {
+    let now = Instant::now();
+    let exp = u64::try_from(Utc::now().timestamp())
+        .unwrap_or_default()
+        .saturating_add(ttl.as_secs());
+    SessionInfo {
+        uid: identity.unique_id(),
+        token: IdToken { identity, exp },
+        last_ping_time: now,
+        last_heartbeat: now,
+        is_first_ping_attempt: true,
+        lobby_token_deadline: now + ttl,
+        region,
+        email_verified: false,
+        joined_lobby_at: None,
+        deferred_until: None,
+        fingerprint: None,
+        deprioritized: false,
+    }
+}
+
+/// True if an account created at `created_at` is at least `min_age` old.
+pub fn is_old_enough(created_at: DateTime<Utc>, min_age: Duration) -> bool {
+    chrono::Duration::from_std(min_age)
+        .ok()
+        .and_then(|min_age| created_at.checked_add_signed(min_age))
+        .map_or(false, |eligible_from| eligible_from <= Utc::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lobby::EvictionReason,
+        test_util::{create_test_session_info, test_options},
+    };
+
+    async fn login(
+        auth_state: &mut AuthState,
+        options: &SessionOptions,
+        lobby_state: &SharedLobbyState,
+    ) -> Option<SessionId> {
+        let replaced = auth_state
+            .sessions_to_replace("git|1|alice", options, lobby_state)
+            .await?;
+        let session_id = SessionId::new();
+        lobby_state
+            .insert_session(session_id.clone(), create_test_session_info(100))
+            .await
+            .unwrap();
+        auth_state
+            .add_session("git|1|alice", session_id.clone(), &replaced, lobby_state)
+            .await;
+        Some(session_id)
+    }
+
+    #[tokio::test]
+    async fn limits_sessions_per_identity() {
+        let mut options = test_options().sessions;
+        let lobby_state = SharedLobbyState::new(test_options().lobby);
+        let mut auth_state = AuthState::default();
+
+        // The older session is dropped, and its owner told why
+        let first = login(&mut auth_state, &options, &lobby_state)
+            .await
+            .unwrap();
+        lobby_state.enter_lobby(&first).await.unwrap();
+        let second = login(&mut auth_state, &options, &lobby_state)
+            .await
+            .unwrap();
+        assert_ne!(first, second);
+        assert!(!lobby_state.has_session(&first).await);
+        assert_eq!(
+            lobby_state.take_eviction(&first).await,
+            Some(EvictionReason::Replaced)
+        );
+        assert_eq!(lobby_state.get_lobby_size().await, 0);
+
+        // A sign-in that fails after the check doesn't cost the old session
+        let replaced = auth_state
+            .sessions_to_replace("git|1|alice", &options, &lobby_state)
+            .await
+            .unwrap();
+        assert_eq!(replaced, vec![second.clone()]);
+        assert!(lobby_state.has_session(&second).await);
+
+        options.concurrent_login_policy = ConcurrentLoginPolicy::RejectNew;
+        assert_eq!(login(&mut auth_state, &options, &lobby_state).await, None);
+        assert!(lobby_state.has_session(&second).await);
+
+        options.max_sessions_per_identity = 2;
+        assert!(login(&mut auth_state, &options, &lobby_state)
+            .await
+            .is_some());
+        assert_eq!(login(&mut auth_state, &options, &lobby_state).await, None);
+
+        // Sessions the lobby dropped don't count
+        lobby_state.clear_session(|_| true).await;
+        assert!(login(&mut auth_state, &options, &lobby_state)
+            .await
+            .is_some());
+    }
 }
@@ -0,0 +1,216 @@
+//! Sign in with passkeys (WebAuthn) registered at the sequencer.
+//!
+//! A passkey identity is not tied to any external account, so anyone can
+//! register as many of them as they like. Only enable passkeys for ceremonies
+//! where that is acceptable.
+
+use clap::Parser;
+use eyre::{eyre, Result as EyreResult};
+use kzg_ceremony_crypto::signature::identity::Identity;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::{sync::Mutex, time::Instant};
+use url::Url;
+use uuid::Uuid;
+use webauthn_rs::{
+    prelude::{
+        AuthenticationResult, CreationChallengeResponse, Passkey, PasskeyAuthentication,
+        PasskeyRegistration, PublicKeyCredential, RegisterPublicKeyCredential,
+        RequestChallengeResponse, WebauthnError,
+    },
+    Webauthn, WebauthnBuilder,
+};
+
+/// How long a client has to answer a registration or login challenge.
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+pub struct PasskeyOptions {
+    /// Relying party id for passkeys, usually the domain of the frontend.
+    /// Passkey sign in is disabled when not set. Passkeys are not tied to an
+    /// external account, so they offer no protection against one person
+    /// contributing many times.
+    #[clap(long, env)]
+    pub passkey_rp_id: Option<String>,
+
+    /// Origin of the frontend that creates and uses passkeys. Defaults to
+    /// `https://` followed by the relying party id.
+    #[clap(long, env)]
+    pub passkey_rp_origin: Option<Url>,
+}
+
+#[derive(Debug, Error)]
+pub enum PasskeyError {
+    #[error("passkeys are disabled")]
+    Disabled,
+    #[error("unknown or expired challenge")]
+    UnknownChallenge,
+    #[error("webauthn error: {0}")]
+    Webauthn(#[from] WebauthnError),
+}
+
+/// Challenges handed out to clients, by challenge id.
+struct Challenges<T>(Mutex<BTreeMap<Uuid, (Instant, T)>>);
+
+impl<T> Default for Challenges<T> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<T> Challenges<T> {
+    async fn insert(&self, state: T) -> Uuid {
+        let now = Instant::now();
+        let id = Uuid::new_v4();
+        let mut challenges = self.0.lock().await;
+        challenges.retain(|_, (deadline, _)| *deadline > now);
+        challenges.insert(id, (now + CHALLENGE_TTL, state));
+        id
+    }
+
+    async fn take(&self, id: &Uuid) -> Result<T, PasskeyError> {
+        match self.0.lock().await.remove(id) {
+            Some((deadline, state)) if deadline > Instant::now() => Ok(state),
+            _ => Err(PasskeyError::UnknownChallenge),
+        }
+    }
+}
+
+pub struct PasskeyAuth {
+    webauthn:        Option<Webauthn>,
+    registrations:   Challenges<(Uuid, PasskeyRegistration)>,
+    authentications: Challenges<(Uuid, PasskeyAuthentication)>,
+}
+
+pub type SharedPasskeyAuth = Arc<PasskeyAuth>;
+
+impl PasskeyAuth {
+    /// # Errors
+    ///
+    /// Returns an error if the relying party id or origin is invalid.
+    pub fn new(options: &PasskeyOptions) -> EyreResult<Self> {
+        let webauthn = options
+            .passkey_rp_id
+            .as_ref()
+            .map(|rp_id| {
+                let origin = options
+                    .passkey_rp_origin
+                    .clone()
+                    .map_or_else(|| Url::parse(&format!("https://{rp_id}")), Ok)?;
+                WebauthnBuilder::new(rp_id, &origin)
+                    .and_then(|builder| builder.rp_name("Ethereum KZG Ceremony").build())
+                    .map_err(|error| eyre!("Invalid passkey configuration: {error}"))
+            })
+            .transpose()?;
+        Ok(Self {
+            webauthn,
+            registrations: Challenges::default(),
+            authentications: Challenges::default(),
+        })
+    }
+
+    fn webauthn(&self) -> Result<&Webauthn, PasskeyError> {
+        self.webauthn.as_ref().ok_or(PasskeyError::Disabled)
+    }
+
+    /// Creates a new passkey user and the challenge to register their
+    /// passkey with. Returns the challenge id and the user id.
+    pub async fn start_registration(
+        &self,
+        name: &str,
+    ) -> Result<(Uuid, Uuid, CreationChallengeResponse), PasskeyError> {
+        let user_id = Uuid::new_v4();
+        let (challenge, state) = self
+            .webauthn()?
+            .start_passkey_registration(user_id, name, name, None)?;
+        let challenge_id = self.registrations.insert((user_id, state)).await;
+        Ok((challenge_id, user_id, challenge))
+    }
+
+    /// Checks the answer to a registration challenge and returns the user id
+    /// with their new passkey.
+    pub async fn finish_registration(
+        &self,
+        challenge_id: &Uuid,
+        credential: &RegisterPublicKeyCredential,
+    ) -> Result<(Uuid, Passkey), PasskeyError> {
+        let webauthn = self.webauthn()?;
+        let (user_id, state) = self.registrations.take(challenge_id).await?;
+        let passkey = webauthn.finish_passkey_registration(credential, &state)?;
+        Ok((user_id, passkey))
+    }
+
+    /// Creates the challenge for a user to sign in with their passkey.
+    pub async fn start_authentication(
+        &self,
+        user_id: Uuid,
+        passkey: Passkey,
+    ) -> Result<(Uuid, RequestChallengeResponse), PasskeyError> {
+        let (challenge, state) = self.webauthn()?.start_passkey_authentication(&[passkey])?;
+        let challenge_id = self.authentications.insert((user_id, state)).await;
+        Ok((challenge_id, challenge))
+    }
+
+    /// Checks the answer to a login challenge and returns the user id.
+    pub async fn finish_authentication(
+        &self,
+        challenge_id: &Uuid,
+        credential: &PublicKeyCredential,
+    ) -> Result<(Uuid, AuthenticationResult), PasskeyError> {
+        let webauthn = self.webauthn()?;
+        let (user_id, state) = self.authentications.take(challenge_id).await?;
+        let result = webauthn.finish_passkey_authentication(credential, &state)?;
+        Ok((user_id, result))
+    }
+}
+
+/// The identity that contributions of a passkey user are recorded under.
+pub const fn passkey_identity(user_id: Uuid) -> Identity {
+    Identity::Passkey {
+        id: user_id.into_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(rp_id: Option<&str>) -> PasskeyOptions {
+        PasskeyOptions {
+            passkey_rp_id:     rp_id.map(ToString::to_string),
+            passkey_rp_origin: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_when_disabled() {
+        let passkeys = PasskeyAuth::new(&options(None)).unwrap();
+        assert!(matches!(
+            passkeys.start_registration("test_user").await,
+            Err(PasskeyError::Disabled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn challenges_are_single_use() {
+        let passkeys = PasskeyAuth::new(&options(Some("localhost"))).unwrap();
+        let (challenge_id, ..) = passkeys.start_registration("test_user").await.unwrap();
+        assert!(passkeys.registrations.take(&challenge_id).await.is_ok());
+        assert!(matches!(
+            passkeys.registrations.take(&challenge_id).await,
+            Err(PasskeyError::UnknownChallenge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn challenges_expire() {
+        tokio::time::pause();
+        let challenges = Challenges::default();
+        let id = challenges.insert(()).await;
+        tokio::time::advance(CHALLENGE_TTL).await;
+        assert!(matches!(
+            challenges.take(&id).await,
+            Err(PasskeyError::UnknownChallenge)
+        ));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::util::Secret;
+use clap::Parser;
+use kzg_ceremony_crypto::signature::identity::Identity;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+pub struct PseudonymOptions {
+    /// Secret salt for the pseudonyms of participants who choose to contribute
+    /// pseudonymously. Pseudonymous contributions are rejected when not set.
+    /// Changing the salt changes all pseudonyms.
+    #[clap(long, env)]
+    pub pseudonym_salt: Option<Secret>,
+}
+
+impl PseudonymOptions {
+    /// Derives the pseudonym of an identity, if pseudonyms are enabled.
+    pub fn pseudonym(&self, identity: &Identity) -> Option<Identity> {
+        let salt = self.pseudonym_salt.as_ref()?;
+        let hash = Sha256::new()
+            .chain_update(salt.get_secret())
+            .chain_update(identity.unique_id())
+            .finalize();
+        Some(Identity::Pseudonym { hash: hash.into() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_stable_pseudonyms() {
+        let identity = Identity::Github {
+            id:       1234,
+            username: "test_user".to_string(),
+        };
+        let options = |salt: Option<&str>| PseudonymOptions {
+            pseudonym_salt: salt.map(|salt| salt.parse().unwrap()),
+        };
+
+        assert_eq!(options(None).pseudonym(&identity), None);
+        let pseudonym = options(Some("salt")).pseudonym(&identity).unwrap();
+        assert_eq!(
+            options(Some("salt")).pseudonym(&identity),
+            Some(pseudonym.clone())
+        );
+        assert_ne!(
+            options(Some("pepper")).pseudonym(&identity),
+            Some(pseudonym)
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{lobby::duration_from_str, util::Secret};
+use clap::Parser;
+use oauth2::{
+    basic::BasicClient, AuthUrl, ClientId, ClientSecret, PkceCodeVerifier, RedirectUrl, TokenUrl,
+};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+pub struct TwitterAuthOptions {
+    /// How old a Twitter account has to be in order to participate, in
+    /// seconds.
+    #[clap(long, env, value_parser=duration_from_str, default_value="7776000")]
+    pub tw_min_account_age: Duration,
+
+    /// Twitter OAuth2 authorization url.
+    #[clap(long, env, default_value = "https://twitter.com/i/oauth2/authorize")]
+    pub tw_auth_url: String,
+
+    /// Twitter OAuth2 token url.
+    #[clap(long, env, default_value = "https://api.twitter.com/2/oauth2/token")]
+    pub tw_token_url: String,
+
+    /// Twitter user info url.
+    #[clap(
+        long,
+        env,
+        default_value = "https://api.twitter.com/2/users/me?user.fields=created_at"
+    )]
+    pub tw_userinfo_url: String,
+
+    /// Twitter OAuth2 callback redirect url.
+    #[clap(
+        long,
+        env,
+        default_value = "http://127.0.0.1:3000/auth/callback/twitter"
+    )]
+    pub tw_redirect_url: String,
+
+    /// Twitter OAuth2 client id. Signing in with Twitter is disabled when not
+    /// set.
+    #[clap(long, env, requires = "tw_client_secret")]
+    pub tw_client_id: Option<Secret>,
+
+    /// Twitter OAuth2 client secret.
+    #[clap(long, env)]
+    pub tw_client_secret: Option<Secret>,
+}
+
+#[derive(Clone)]
+pub struct TwitterOAuthClient {
+    client: Option<BasicClient>,
+    secret: Option<Secret>,
+}
+
+impl TwitterOAuthClient {
+    /// The client, if signing in with Twitter is enabled.
+    pub const fn client(&self) -> Option<&BasicClient> {
+        self.client.as_ref()
+    }
+
+    /// Twitter requires PKCE. Instead of storing a verifier for every auth
+    /// link, it is derived from the link's nonce and the client secret, so
+    /// only the sequencer can redeem the code.
+    pub fn pkce_verifier(&self, nonce: &str) -> PkceCodeVerifier {
+        let secret = self.secret.as_ref().map(Secret::get_secret);
+        let hash = Sha256::new()
+            .chain_update(secret.unwrap_or_default())
+            .chain_update(nonce)
+            .finalize();
+        PkceCodeVerifier::new(base64::encode_config(hash, base64::URL_SAFE_NO_PAD))
+    }
+}
+
+pub fn twitter_oauth_client(options: &TwitterAuthOptions) -> TwitterOAuthClient {
+    let client = options.tw_client_id.as_ref().map(|client_id| {
+        BasicClient::new(
+            ClientId::new(client_id.get_secret().to_owned()),
+            options
+                .tw_client_secret
+                .as_ref()
+                .map(|secret| ClientSecret::new(secret.get_secret().to_owned())),
+            AuthUrl::new(options.tw_auth_url.clone()).unwrap(),
+            Some(TokenUrl::new(options.tw_token_url.clone()).unwrap()),
+        )
+        .set_redirect_uri(RedirectUrl::new(options.tw_redirect_url.clone()).unwrap())
+    });
+    TwitterOAuthClient {
+        client,
+        secret: options.tw_client_secret.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_options;
+
+    #[test]
+    fn derives_pkce_verifiers() {
+        let mut options = test_options().twitter;
+        assert!(twitter_oauth_client(&options).client().is_none());
+
+        options.tw_client_id = Some("id".parse().unwrap());
+        options.tw_client_secret = Some("secret".parse().unwrap());
+        let client = twitter_oauth_client(&options);
+        assert!(client.client().is_some());
+
+        let verifier = client.pkce_verifier("nonce");
+        assert_eq!(verifier.secret().len(), 43);
+        assert_eq!(verifier.secret(), client.pkce_verifier("nonce").secret());
+        assert_ne!(verifier.secret(), client.pkce_verifier("other").secret());
+    }
+}
@@ -0,0 +1,167 @@
+//! Proxy for the outbound requests of the sequencer, such as the OAuth token
+//! exchanges, the GitHub and Ethereum RPC lookups, captchas, mirrors and
+//! webhooks, for operators in networks without direct internet access.
+
+use crate::util::Secret;
+use clap::Parser;
+use eyre::Result as EyreResult;
+use oauth2::{HttpRequest, HttpResponse};
+use reqwest::{redirect::Policy, ClientBuilder, Proxy};
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Proxy that outbound requests are sent through, as `http://`,
+    /// `https://` or `socks5://` url. Requests go out directly when not set.
+    #[clap(long, env)]
+    pub outbound_proxy: Option<Url>,
+
+    /// User name for the outbound proxy.
+    #[clap(long, env, requires = "outbound_proxy")]
+    pub outbound_proxy_username: Option<String>,
+
+    /// Password for the outbound proxy.
+    #[clap(long, env, requires = "outbound_proxy_username")]
+    pub outbound_proxy_password: Option<Secret>,
+
+    /// Hosts that are reached without the proxy, separated by commas. A host
+    /// also matches its subdomains, `*` matches every host.
+    #[clap(long, env, value_delimiter = ',')]
+    pub outbound_no_proxy: Vec<String>,
+}
+
+/// Whether requests to `url` bypass the proxy.
+fn bypasses(no_proxy: &[String], url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return false,
+    };
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim().trim_start_matches('.');
+        if entry.is_empty() {
+            return false;
+        }
+        entry == "*"
+            || host.eq_ignore_ascii_case(entry)
+            || host
+                .len()
+                .checked_sub(entry.len() + 1)
+                .map_or(false, |start| {
+                    host.as_bytes()[start] == b'.' && host[start + 1..].eq_ignore_ascii_case(entry)
+                })
+    })
+}
+
+fn builder(options: &Options) -> EyreResult<ClientBuilder> {
+    let builder = reqwest::Client::builder();
+    let url = match &options.outbound_proxy {
+        Some(url) => url.clone(),
+        None => return Ok(builder),
+    };
+    // Fail on startup rather than on the first request
+    Proxy::all(url.clone())?;
+    let no_proxy = options.outbound_no_proxy.clone();
+    let mut proxy = Proxy::custom(move |target| {
+        if bypasses(&no_proxy, target) {
+            None
+        } else {
+            Some(url.clone())
+        }
+    });
+    if let Some(username) = &options.outbound_proxy_username {
+        let password = options
+            .outbound_proxy_password
+            .as_ref()
+            .map_or("", Secret::get_secret);
+        proxy = proxy.basic_auth(username, password);
+    }
+    Ok(builder.proxy(proxy))
+}
+
+/// The client shared by everything that makes outbound requests.
+///
+/// # Errors
+///
+/// Returns an error if the proxy url is not supported.
+pub fn http_client(options: &Options) -> EyreResult<reqwest::Client> {
+    Ok(builder(options)?.build()?)
+}
+
+/// Client for the OAuth token exchanges. Unlike the shared client it doesn't
+/// follow redirects, as the callbacks hand it answers from the providers.
+#[derive(Clone, Debug)]
+pub struct OAuthHttpClient(reqwest::Client);
+
+impl OAuthHttpClient {
+    /// # Errors
+    ///
+    /// Returns an error if the proxy url is not supported.
+    pub fn new(options: &Options) -> EyreResult<Self> {
+        Ok(Self(builder(options)?.redirect(Policy::none()).build()?))
+    }
+
+    /// Sends a request of the `oauth2` crate, for `request_async`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request can't be sent or its response can't
+    /// be read.
+    pub async fn request(&self, request: HttpRequest) -> Result<HttpResponse, reqwest::Error> {
+        let response = self
+            .0
+            .request(request.method, request.url.as_str())
+            .headers(request.headers)
+            .body(request.body)
+            .send()
+            .await?;
+        let status_code = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await?.to_vec();
+        Ok(HttpResponse {
+            status_code,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bypasses_listed_hosts() {
+        let no_proxy = ["localhost", ".internal.example", "10.0.0.1", "::1"].map(String::from);
+        let bypassed = |url: &str| bypasses(&no_proxy, &Url::parse(url).unwrap());
+        assert!(bypassed("http://localhost:8080/"));
+        assert!(bypassed("https://rpc.internal.example/"));
+        assert!(bypassed("https://INTERNAL.example/"));
+        assert!(bypassed("http://10.0.0.1/"));
+        assert!(bypassed("http://[::1]:3000/"));
+        assert!(!bypassed("https://github.com/"));
+        assert!(!bypassed("https://notinternal.example/"));
+        assert!(bypasses(
+            &["*".to_owned()],
+            &Url::parse("https://github.com").unwrap()
+        ));
+    }
+
+    #[test]
+    fn builds_clients() {
+        let options =
+            |args: &[&str]| Options::parse_from(["test"].into_iter().chain(args.iter().copied()));
+        assert!(http_client(&options(&[])).is_ok());
+        let proxied = options(&[
+            "--outbound-proxy",
+            "socks5://proxy.example:1080",
+            "--outbound-proxy-username",
+            "sequencer",
+            "--outbound-proxy-password",
+            "hunter2",
+        ]);
+        assert!(http_client(&proxied).is_ok());
+        assert!(OAuthHttpClient::new(&proxied).is_ok());
+        assert!(http_client(&options(&["--outbound-proxy", "ftp://proxy.example"])).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Quarantine of rejected contributions. Contributions that fail to verify
+//! in the wild are the best test cases for the engines, so when enabled
+//! their payloads are kept in storage with the reason they were rejected
+//! for, and can be exported with the `export-rejected` command. Identities
+//! are only kept hashed, and entries older than the retention are dropped
+//! whenever a new one comes in.
+
+use crate::{lobby::duration_from_str, storage::PersistentStorage};
+use chrono::Utc;
+use clap::Parser;
+use kzg_ceremony_crypto::{BatchContribution, ErrorCode};
+use sha2::{Digest, Sha256};
+use std::{fmt::Display, time::Duration};
+use tracing::{error, info};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Keep the payloads of rejected contributions in storage, with the
+    /// reason they were rejected for, to reproduce verification failures
+    /// offline.
+    #[clap(long, env, default_value = "false")]
+    pub quarantine_rejected: bool,
+
+    /// How many seconds rejected contributions are kept for.
+    #[clap(long, env, value_parser=duration_from_str, default_value="604800")]
+    pub quarantine_retention: Duration,
+}
+
+/// The hash rejected contributions are attributed to, so that the attempts
+/// of an identity can be told apart without keeping the identity.
+#[must_use]
+pub fn identity_hash(uid: &str) -> String {
+    hex::encode(Sha256::digest(uid.as_bytes()))
+}
+
+/// Keeps a contribution of `uid` that was rejected with `rejection`, if the
+/// quarantine is enabled. The contribution is rejected either way, so
+/// failing to do so is only logged.
+pub async fn store<E: ErrorCode + Display>(
+    options: &Options,
+    storage: &PersistentStorage,
+    uid: &str,
+    rejection: &E,
+    contribution: &BatchContribution,
+) {
+    if !options.quarantine_rejected {
+        return;
+    }
+    let payload = match serde_json::to_string(contribution) {
+        Ok(payload) => payload,
+        Err(error) => {
+            error!(?error, "Could not serialize rejected contribution");
+            return;
+        }
+    };
+    if let Err(error) = storage
+        .insert_rejected_contribution(
+            &identity_hash(uid),
+            &rejection.to_error_code(),
+            &rejection.to_string(),
+            &payload,
+        )
+        .await
+    {
+        error!(?error, "Could not quarantine rejected contribution");
+    }
+
+    let retention = i64::try_from(options.quarantine_retention.as_secs()).unwrap_or(i64::MAX);
+    match storage
+        .prune_rejected_contributions(Utc::now().timestamp().saturating_sub(retention))
+        .await
+    {
+        Ok(0) => {}
+        Ok(pruned) => info!(
+            pruned,
+            "Dropped rejected contributions past their retention"
+        ),
+        Err(error) => error!(?error, "Could not prune rejected contributions"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::v1::contribute::ContributeError,
+        storage::storage_client,
+        test_util::test_options,
+        tests::{test_transcript, valid_contribution},
+    };
+
+    #[tokio::test]
+    async fn keeps_rejected_contributions() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+        let contribution = valid_contribution(&test_transcript(), 1);
+        let error = ContributeError::TranscriptMismatch;
+
+        let disabled = Options::parse_from(["test"]);
+        store(&disabled, &storage, "alice", &error, &contribution).await;
+        assert!(storage.rejected_contributions().await.unwrap().is_empty());
+
+        let enabled = Options::parse_from(["test", "--quarantine-rejected"]);
+        store(&enabled, &storage, "alice", &error, &contribution).await;
+        let rejected = storage.rejected_contributions().await.unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].identity_hash, identity_hash("alice"));
+        assert_ne!(rejected[0].identity_hash, "alice");
+        assert_eq!(rejected[0].code, "ContributeError::TranscriptMismatch");
+        assert_eq!(rejected[0].reason, error.to_string());
+        assert_eq!(
+            serde_json::from_str::<BatchContribution>(&rejected[0].contribution).unwrap(),
+            contribution
+        );
+
+        // Only entries from before the retention are dropped
+        let now = Utc::now().timestamp();
+        let prune = |before| storage.prune_rejected_contributions(before);
+        assert_eq!(prune(now - 60).await.unwrap(), 0);
+        assert_eq!(prune(now + 1).await.unwrap(), 1);
+    }
+}
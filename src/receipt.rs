@@ -1,13 +1,45 @@
-use crate::keys::{Keys, Signature, SignatureError};
-use kzg_ceremony_crypto::{signature::identity::Identity, G2};
+use crate::{
+    keys::{Keys, Signature, SignatureError},
+    regions::Region,
+    sessions::SessionId,
+    Engine,
+};
+use ethers_core::{types::H256, utils::keccak256};
+use kzg_ceremony_crypto::{
+    signature::{identity::Identity, BlsSignature},
+    EntropyAttestation, G2,
+};
 use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Name of the EIP-712 signing domain of receipts.
+pub const EIP712_DOMAIN_NAME: &str = "Ethereum KZG Ceremony";
+
+/// Version of the EIP-712 signing domain of receipts.
+pub const EIP712_DOMAIN_VERSION: &str = "1.0";
+
+const EIP712_DOMAIN_TYPE: &str = "EIP712Domain(string name,string version)";
+const RECEIPT_TYPE: &str = "ContributionReceipt(string identity,bytes[] witness)";
 
 // Receipt for contributor that sequencer has
 // included their contribution
 #[derive(Serialize)]
 pub struct Receipt {
-    pub(crate) identity: Identity,
-    pub witness:         Vec<G2>,
+    pub(crate) identity:     Identity,
+    pub witness:             Vec<G2>,
+    /// Only part of the signed JSON receipt, not of the EIP-712 one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy_attestation: Option<EntropyAttestation>,
+    /// The region the participant chose to share. Also only part of the
+    /// signed JSON receipt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region:              Option<Region>,
+    /// Address the participant countersigns with, as `0x` and lowercase
+    /// hex. Also only part of the signed JSON receipt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countersigner:       Option<String>,
 }
 
 impl Receipt {
@@ -17,4 +49,228 @@ impl Receipt {
             .await
             .map(|sig| (receipt_message, sig))
     }
+
+    /// Signs the receipt as EIP-712 typed data, returning the typed data in
+    /// the format accepted by `eth_signTypedData_v4` together with the
+    /// signature.
+    pub fn sign_typed(&self, keys: &Keys) -> (Value, Signature) {
+        (self.typed_data(), keys.sign_hash(self.typed_data_hash()))
+    }
+
+    /// The EIP-712 typed data of this receipt.
+    pub fn typed_data(&self) -> Value {
+        json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                ],
+                "ContributionReceipt": [
+                    { "name": "identity", "type": "string" },
+                    { "name": "witness", "type": "bytes[]" },
+                ],
+            },
+            "primaryType": "ContributionReceipt",
+            "domain": {
+                "name": EIP712_DOMAIN_NAME,
+                "version": EIP712_DOMAIN_VERSION,
+            },
+            "message": {
+                "identity": self.identity,
+                "witness": self.witness,
+            },
+        })
+    }
+
+    /// The EIP-712 signing hash of this receipt,
+    /// `keccak256(0x1901 ‖ domainSeparator ‖ hashStruct(receipt))`.
+    pub fn typed_data_hash(&self) -> H256 {
+        let domain_separator = hash_words(&[
+            keccak256(EIP712_DOMAIN_TYPE),
+            keccak256(EIP712_DOMAIN_NAME),
+            keccak256(EIP712_DOMAIN_VERSION),
+        ]);
+        let witness = self
+            .witness
+            .iter()
+            .map(|point| keccak256(point.0))
+            .collect::<Vec<_>>();
+        let struct_hash = hash_words(&[
+            keccak256(RECEIPT_TYPE),
+            keccak256(self.identity.to_string()),
+            hash_words(&witness),
+        ]);
+
+        let mut message = vec![0x19, 0x01];
+        message.extend_from_slice(&domain_separator);
+        message.extend_from_slice(&struct_hash);
+        H256(keccak256(message))
+    }
+}
+
+fn hash_words(words: &[[u8; 32]]) -> [u8; 32] {
+    keccak256(words.concat())
+}
+
+/// Checks the aggregate of the BLS signatures of signed JSON receipts, as
+/// made by [`BlsSignature::aggregate`], against the sequencer's BLS key.
+/// Receipt exports publish the aggregate, see [`crate::receipt_export`].
+pub fn verify_aggregate(receipts: &[String], signature: &BlsSignature, pubkey: G2) -> bool {
+    let messages = receipts.iter().map(String::as_bytes).collect::<Vec<_>>();
+    signature.verify_aggregate::<Engine>(&messages, pubkey)
+}
+
+/// The key receipts are stored under. Session ids are bearer tokens, so only
+/// their hash is kept.
+pub fn session_hash(session_id: &SessionId) -> String {
+    hex::encode(Sha256::digest(session_id.0.as_bytes()))
+}
+
+/// Parses an Ethereum address given as hex, with or without `0x`.
+pub fn parse_address(address: &str) -> Option<[u8; 20]> {
+    hex::decode(address.trim_start_matches("0x"))
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// Whether `countersignature` signs the JSON `receipt` with the key of the
+/// `countersigner` address.
+pub fn is_countersigned(countersigner: &str, receipt: &str, countersignature: &Signature) -> bool {
+    match (
+        parse_address(countersigner),
+        countersignature.recover(receipt),
+    ) {
+        (Some(countersigner), Ok(signer)) => signer == countersigner,
+        _ => false,
+    }
+}
+
+/// Hash of a signed JSON receipt, as put in its verification url.
+pub fn receipt_hash(receipt: &str) -> String {
+    hex::encode(Sha256::digest(receipt.as_bytes()))
+}
+
+/// The url anyone can check a receipt at, e.g. by scanning its QR code. It
+/// is kept short enough for a QR code by only holding the receipt's hash and
+/// signature, and the participant to look the receipt up by.
+///
+/// # Panics
+///
+/// Panics if `server` can't be a base url, which the server url always can.
+pub fn verification_url(server: &Url, participant: usize, receipt: &str, signature: &str) -> Url {
+    let mut url = server
+        .join(&format!("receipt/{participant}/verify"))
+        .expect("Server url can be a base");
+    url.query_pairs_mut()
+        .append_pair("hash", &receipt_hash(receipt))
+        .append_pair("signature", signature);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Options;
+    use clap::Parser;
+    use kzg_ceremony_crypto::EntropySource;
+
+    fn receipt() -> Receipt {
+        Receipt {
+            identity:            Identity::Github {
+                id:       1234,
+                username: "test_user".to_string(),
+            },
+            witness:             vec![G2::one(), G2::one()],
+            entropy_attestation: None,
+            region:              None,
+            countersigner:       None,
+        }
+    }
+
+    #[test]
+    fn sign_and_verify_typed() {
+        let keys = Keys::new(&Options::parse_from(Vec::<&str>::new())).unwrap();
+        let receipt = receipt();
+
+        let (typed_data, signature) = receipt.sign_typed(&keys);
+        assert_eq!(typed_data["primaryType"], "ContributionReceipt");
+        assert_eq!(
+            typed_data["message"],
+            serde_json::to_value(&receipt).unwrap()
+        );
+        keys.verify_hash(receipt.typed_data_hash(), &signature)
+            .unwrap();
+
+        let mut other = receipt;
+        other.witness.pop();
+        assert!(keys
+            .verify_hash(other.typed_data_hash(), &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn aggregates_bls_signatures() {
+        let options = Options::parse_from(["", "--bls-signing-key", &"01".repeat(32)]);
+        let keys = Keys::new(&options).unwrap();
+        let pubkey = keys.bls_pubkey().unwrap();
+
+        let (receipts, signatures): (Vec<_>, Vec<_>) = (1..=3)
+            .map(|id| {
+                let mut receipt = receipt();
+                receipt.identity = Identity::Github {
+                    id,
+                    username: format!("user{id}"),
+                };
+                let message = serde_json::to_string(&receipt).unwrap();
+                let signature = keys.sign_bls(&message).unwrap();
+                (message, signature)
+            })
+            .unzip();
+
+        let aggregate = BlsSignature::aggregate::<Engine>(&signatures);
+        assert!(verify_aggregate(&receipts, &aggregate, pubkey));
+        assert!(!verify_aggregate(&receipts[1..], &aggregate, pubkey));
+    }
+
+    #[test]
+    fn entropy_attestation_is_only_in_json_receipt() {
+        let mut receipt = receipt();
+        let typed_data_hash = receipt.typed_data_hash();
+        receipt.entropy_attestation = Some(EntropyAttestation {
+            sources: vec![EntropySource {
+                kind: "os-rng".to_string(),
+                hash: [1; 32],
+            }],
+        });
+
+        let json = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(json["entropy_attestation"]["sources"][0]["kind"], "os-rng");
+        assert!(receipt.typed_data()["message"]
+            .get("entropy_attestation")
+            .is_none());
+        assert_eq!(receipt.typed_data_hash(), typed_data_hash);
+    }
+
+    #[tokio::test]
+    async fn checks_countersignatures() {
+        let participant = Keys::new(&Options::parse_from(Vec::<&str>::new())).unwrap();
+        let sequencer = Keys::new(&Options::parse_from(Vec::<&str>::new())).unwrap();
+        let (message, _) = receipt().sign(&sequencer).await.unwrap();
+        let countersigner = participant.address().to_string();
+        let countersignature = participant.sign(&message).await.unwrap();
+
+        assert!(is_countersigned(
+            &countersigner,
+            &message,
+            &countersignature
+        ));
+        assert!(!is_countersigned(&countersigner, "{}", &countersignature));
+        assert!(!is_countersigned(
+            &countersigner,
+            &message,
+            &sequencer.sign(&message).await.unwrap()
+        ));
+        assert!(!is_countersigned("0x1234", &message, &countersignature));
+    }
 }
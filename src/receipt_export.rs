@@ -0,0 +1,418 @@
+//! Export of all receipts, for the public data release at the end of the
+//! ceremony. Receipts are written as JSON lines or CSV with a chosen set of
+//! fields, with the `export-receipts` command or from `/admin/receipts`.
+//! Each receipt is checked before it is written: the signatures of the
+//! sequencer, with the current keys, and the countersignature, if any,
+//! against the countersigner the participant declared, which is part of the
+//! signed receipt. A single receipt failing the checks fails the export, so
+//! that the release only holds receipts anyone can verify.
+
+use crate::{
+    keys::{Keys, SharedKeys, Signature},
+    receipt::{is_countersigned, receipt_hash},
+    storage::{PersistentStorage, StorageError, StoredReceipt},
+    Engine,
+};
+use clap::ValueEnum;
+use kzg_ceremony_crypto::{
+    signature::{identity::Identity, BlsSignature},
+    ErrorCode, G1,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use strum::IntoStaticStr;
+use thiserror::Error;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiptFormat {
+    /// One JSON object per line.
+    #[default]
+    Jsonl,
+    /// A header row with the field names, then one row per receipt.
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoStaticStr, ValueEnum)]
+#[strum(serialize_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum ReceiptField {
+    /// Position of the participant in the transcript.
+    Participant,
+    /// Identity the receipt was issued to.
+    Identity,
+    /// The signed JSON receipt.
+    Receipt,
+    /// SHA-256 of the JSON receipt, as in its verification url.
+    ReceiptHash,
+    /// Signature of the sequencer.
+    Signature,
+    /// BLS signature of the sequencer, if it has a BLS key.
+    BlsSignature,
+    /// Signature of the participant, for confirmed contributions.
+    Countersignature,
+}
+
+/// The fields exported when none are selected.
+pub const ALL_FIELDS: [ReceiptField; 7] = [
+    ReceiptField::Participant,
+    ReceiptField::Identity,
+    ReceiptField::Receipt,
+    ReceiptField::ReceiptHash,
+    ReceiptField::Signature,
+    ReceiptField::BlsSignature,
+    ReceiptField::Countersignature,
+];
+
+#[derive(Debug, Error, IntoStaticStr)]
+pub enum ReceiptExportError {
+    #[error("unknown receipt field {0}")]
+    UnknownField(String),
+    #[error("receipt of participant {participant} is invalid: {reason}")]
+    InvalidReceipt {
+        participant: usize,
+        reason:      &'static str,
+    },
+    #[error("storage error: {0}")]
+    StorageError(#[from] StorageError),
+}
+
+impl ErrorCode for ReceiptExportError {
+    fn to_error_code(&self) -> String {
+        format!("ReceiptExportError::{}", <&str>::from(self))
+    }
+}
+
+/// Parses field names separated by commas, as given to `/admin/receipts`.
+///
+/// # Errors
+///
+/// Returns an error naming the first field that doesn't exist.
+pub fn parse_fields(fields: &str) -> Result<Vec<ReceiptField>, ReceiptExportError> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            ReceiptField::from_str(name, true)
+                .map_err(|_| ReceiptExportError::UnknownField(name.to_string()))
+        })
+        .collect()
+}
+
+pub struct ReceiptExport {
+    pub contents:      String,
+    /// Aggregate of the BLS signatures of the receipts, hex encoded, which
+    /// proves all of them with one check, see
+    /// [`crate::receipt::verify_aggregate`]. Only there if receipts were
+    /// signed with a BLS key.
+    pub bls_aggregate: Option<String>,
+}
+
+/// Checks and renders all receipts with `fields`, or with all fields if
+/// there are none.
+///
+/// # Errors
+///
+/// Returns an error if a receipt fails the checks, or if storage fails.
+pub async fn export(
+    storage: &PersistentStorage,
+    keys: SharedKeys,
+    format: ReceiptFormat,
+    fields: Vec<ReceiptField>,
+) -> Result<ReceiptExport, ReceiptExportError> {
+    let receipts = storage.receipts().await?;
+    let fields = if fields.is_empty() {
+        ALL_FIELDS.to_vec()
+    } else {
+        fields
+    };
+    // Checking the BLS signatures takes pairings
+    tokio::task::spawn_blocking(move || render(&keys, &receipts, format, &fields))
+        .await
+        .expect("Receipt export panicked")
+}
+
+fn render(
+    keys: &Keys,
+    receipts: &[StoredReceipt],
+    format: ReceiptFormat,
+    fields: &[ReceiptField],
+) -> Result<ReceiptExport, ReceiptExportError> {
+    let mut contents = String::new();
+    if format == ReceiptFormat::Csv {
+        let header = fields
+            .iter()
+            .map(|&field| <&str>::from(field))
+            .collect::<Vec<_>>();
+        contents.push_str(&header.join(","));
+        contents.push('\n');
+    }
+    for stored in receipts {
+        let identity = check(keys, stored)?;
+        let values = fields
+            .iter()
+            .map(|&field| (field, value(field, stored, &identity)));
+        match format {
+            ReceiptFormat::Jsonl => {
+                let line = values
+                    .map(|(field, value)| (<&str>::from(field).to_string(), value))
+                    .collect::<Map<_, _>>();
+                contents.push_str(&Value::Object(line).to_string());
+            }
+            ReceiptFormat::Csv => {
+                let row = values
+                    .map(|(_, value)| csv_cell(&value))
+                    .collect::<Vec<_>>();
+                contents.push_str(&row.join(","));
+            }
+        }
+        contents.push('\n');
+    }
+    Ok(ReceiptExport {
+        contents,
+        bls_aggregate: bls_aggregate(receipts),
+    })
+}
+
+/// Checks the signatures of a receipt, returning the identity it was issued
+/// to.
+fn check(keys: &Keys, stored: &StoredReceipt) -> Result<Identity, ReceiptExportError> {
+    let invalid = |reason| ReceiptExportError::InvalidReceipt {
+        participant: stored.participant,
+        reason,
+    };
+    let receipt = serde_json::from_str::<Value>(&stored.receipt)
+        .map_err(|_| invalid("receipt is not JSON"))?;
+    let identity = receipt
+        .get("identity")
+        .and_then(|identity| serde_json::from_value(identity.clone()).ok())
+        .ok_or_else(|| invalid("receipt has no identity"))?;
+    keys.verify(
+        &stored.receipt,
+        &Signature::from_hex(stored.signature.clone()),
+    )
+    .map_err(|_| invalid("signature is not by the sequencer"))?;
+    if let Some(bls_signature) = &stored.bls_signature {
+        let pubkey = keys
+            .bls_pubkey()
+            .ok_or_else(|| invalid("receipt has a BLS signature, but there is no BLS key"))?;
+        let signature = BlsSignature(decode_bls_signature(bls_signature));
+        if !signature.verify::<Engine>(stored.receipt.as_bytes(), pubkey) {
+            return Err(invalid("BLS signature is not by the sequencer"));
+        }
+    }
+    if let Some(countersignature) = &stored.countersignature {
+        let countersigner = receipt
+            .get("countersigner")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("receipt is countersigned, but names no countersigner"))?;
+        let countersignature = Signature::from_hex(countersignature.clone());
+        if !is_countersigned(countersigner, &stored.receipt, &countersignature) {
+            return Err(invalid("countersignature is not by the countersigner"));
+        }
+    }
+    Ok(identity)
+}
+
+/// Aggregate of the BLS signatures of all receipts that have one, hex
+/// encoded. The signatures are checked one by one in [`check`], as an
+/// aggregate check alone passes forged signatures that sum to valid ones.
+fn bls_aggregate(receipts: &[StoredReceipt]) -> Option<String> {
+    let signatures = receipts
+        .iter()
+        .filter_map(|stored| stored.bls_signature.as_deref())
+        .map(|signature| BlsSignature(decode_bls_signature(signature)))
+        .collect::<Vec<_>>();
+    if signatures.is_empty() {
+        return None;
+    }
+    BlsSignature::aggregate::<Engine>(&signatures)
+        .0
+        .map(|aggregate| hex::encode(aggregate.0))
+}
+
+fn decode_bls_signature(signature: &str) -> Option<G1> {
+    hex::decode(signature)
+        .ok()
+        .and_then(|bytes| <[u8; 48]>::try_from(bytes).ok())
+        .map(G1)
+}
+
+fn value(field: ReceiptField, stored: &StoredReceipt, identity: &Identity) -> Value {
+    match field {
+        ReceiptField::Participant => stored.participant.into(),
+        ReceiptField::Identity => identity.to_string().into(),
+        ReceiptField::Receipt => stored.receipt.clone().into(),
+        ReceiptField::ReceiptHash => receipt_hash(&stored.receipt).into(),
+        ReceiptField::Signature => stored.signature.clone().into(),
+        ReceiptField::BlsSignature => stored.bls_signature.clone().into(),
+        ReceiptField::Countersignature => stored.countersignature.clone().into(),
+    }
+}
+
+/// A CSV cell, quoted if it has to be. Missing values are left empty.
+fn csv_cell(value: &Value) -> String {
+    let text = match value {
+        Value::Null => return String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys, receipt::Receipt, storage::storage_client, test_util::test_options};
+    use clap::Parser;
+    use kzg_ceremony_crypto::G2;
+    use std::sync::Arc;
+
+    async fn signed_receipt(
+        keys: &Keys,
+        participant_keys: &Keys,
+        participant: usize,
+        id: u64,
+    ) -> StoredReceipt {
+        let receipt = Receipt {
+            identity:            Identity::Github {
+                id,
+                username: format!("user{id}"),
+            },
+            witness:             vec![G2::one()],
+            entropy_attestation: None,
+            region:              None,
+            countersigner:       Some(participant_keys.address().to_string()),
+        };
+        let (receipt, signature) = receipt.sign(keys).await.unwrap();
+        let countersignature = participant_keys.sign(&receipt).await.unwrap();
+        let bls_signature = keys
+            .sign_bls(&receipt)
+            .and_then(|signature| signature.0)
+            .map(|signature| hex::encode(signature.0));
+        StoredReceipt {
+            participant,
+            receipt,
+            signature: signature.as_hex().to_owned(),
+            bls_signature,
+            countersignature: (id % 2 == 0).then(|| countersignature.as_hex().to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_verified_receipts() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+        let keys = Arc::new(
+            Keys::new(&keys::Options::parse_from([
+                "",
+                "--bls-signing-key",
+                &"01".repeat(32),
+            ]))
+            .unwrap(),
+        );
+        let participant_keys = Keys::new(&keys::Options::parse_from([""])).unwrap();
+        for (participant, id) in [(2, 2), (1, 1)] {
+            let receipt = signed_receipt(&keys, &participant_keys, participant, id).await;
+            storage
+                .insert_receipt(&format!("session{participant}"), &receipt)
+                .await
+                .unwrap();
+        }
+
+        let jsonl = export(&storage, keys.clone(), ReceiptFormat::Jsonl, vec![])
+            .await
+            .unwrap();
+        let lines = jsonl
+            .contents
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(jsonl.bls_aggregate.as_deref().map(str::len), Some(96));
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["participant"], 1);
+        assert_eq!(lines[0]["identity"], "git|1|user1");
+        assert_eq!(lines[0]["countersignature"], Value::Null);
+        assert!(lines[1]["countersignature"].is_string());
+        assert!(lines[1]["bls_signature"].is_string());
+        assert_eq!(
+            lines[1]["receipt_hash"],
+            receipt_hash(lines[1]["receipt"].as_str().unwrap())
+        );
+
+        let fields = parse_fields("participant, receipt").unwrap();
+        let csv = export(&storage, keys.clone(), ReceiptFormat::Csv, fields)
+            .await
+            .unwrap();
+        let mut rows = csv.contents.lines();
+        assert_eq!(rows.next(), Some("participant,receipt"));
+        let row = rows.next().unwrap();
+        // Receipts hold commas and quotes, so they are quoted
+        assert!(row.starts_with("1,\"{\"\""), "{row}");
+        assert!(matches!(
+            parse_fields("participant,secret"),
+            Err(ReceiptExportError::UnknownField(field)) if field == "secret"
+        ));
+
+        // Receipts that don't verify under the current keys fail the export
+        let other_keys = Arc::new(Keys::new(&keys::Options::parse_from([""])).unwrap());
+        assert!(matches!(
+            export(&storage, other_keys, ReceiptFormat::Jsonl, vec![]).await,
+            Err(ReceiptExportError::InvalidReceipt { participant: 1, .. })
+        ));
+        let mut garbled = signed_receipt(&keys, &participant_keys, 3, 3).await;
+        garbled.countersignature = Some(garbled.signature.clone() + "00");
+        assert!(check(&keys, &garbled).is_err());
+
+        // The sequencer can't countersign in place of the participant
+        let mut forged = signed_receipt(&keys, &participant_keys, 3, 3).await;
+        forged.countersignature = Some(forged.signature.clone());
+        assert!(check(&keys, &forged).is_err());
+        storage.insert_receipt("session3", &forged).await.unwrap();
+        assert!(matches!(
+            export(&storage, keys, ReceiptFormat::Jsonl, vec![]).await,
+            Err(ReceiptExportError::InvalidReceipt { participant: 3, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_bls_signatures_that_only_verify_as_aggregate() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+        let keys = Arc::new(
+            Keys::new(&keys::Options::parse_from([
+                "",
+                "--bls-signing-key",
+                &"01".repeat(32),
+            ]))
+            .unwrap(),
+        );
+        let participant_keys = Keys::new(&keys::Options::parse_from([""])).unwrap();
+        let mut first = signed_receipt(&keys, &participant_keys, 1, 1).await;
+        let mut second = signed_receipt(&keys, &participant_keys, 2, 2).await;
+
+        // Swapping the signatures adds the same point to one as it takes
+        // from the other, so their sum still verifies
+        std::mem::swap(&mut first.bls_signature, &mut second.bls_signature);
+        let signatures = [&first, &second].map(|stored| {
+            BlsSignature(decode_bls_signature(stored.bls_signature.as_ref().unwrap()))
+        });
+        let aggregate = BlsSignature::aggregate::<Engine>(&signatures);
+        let messages = [first.receipt.clone(), second.receipt.clone()];
+        assert!(crate::receipt::verify_aggregate(
+            &messages,
+            &aggregate,
+            keys.bls_pubkey().unwrap()
+        ));
+
+        storage.insert_receipt("session1", &first).await.unwrap();
+        storage.insert_receipt("session2", &second).await.unwrap();
+        assert!(matches!(
+            export(&storage, keys, ReceiptFormat::Jsonl, vec![]).await,
+            Err(ReceiptExportError::InvalidReceipt { participant: 1, .. })
+        ));
+    }
+}
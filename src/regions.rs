@@ -0,0 +1,104 @@
+//! Coarse regions that participants may share when they sign in. Only the
+//! number of accepted contributions per region is stored, without any link to
+//! who made them, and regions with few contributions are left out of the
+//! published counts.
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use strum::{EnumString, IntoStaticStr};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Smallest number of contributions from a region for its count to be
+    /// published at `/info/stats/regions`. Smaller counts are only included
+    /// in the total of withheld contributions.
+    #[clap(long, env, default_value = "5")]
+    pub region_stats_min_count: usize,
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    IntoStaticStr,
+    EnumString,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Region {
+    Africa,
+    Asia,
+    Europe,
+    NorthAmerica,
+    Oceania,
+    SouthAmerica,
+}
+
+impl Region {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.into()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RegionStats {
+    /// Accepted contributions per region, for the regions with enough of them.
+    pub regions:  BTreeMap<Region, usize>,
+    /// Accepted contributions from the regions that are left out.
+    pub withheld: usize,
+}
+
+impl RegionStats {
+    /// Publishes the counts of at least `min_count` contributions.
+    pub fn aggregate(counts: impl IntoIterator<Item = (Region, usize)>, min_count: usize) -> Self {
+        let mut stats = Self::default();
+        for (region, count) in counts {
+            if count >= min_count {
+                stats.regions.insert(region, count);
+            } else {
+                stats.withheld += count;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn withholds_small_counts() {
+        let stats = RegionStats::aggregate(
+            [(Region::Europe, 7), (Region::Asia, 5), (Region::Oceania, 2)],
+            5,
+        );
+        assert_eq!(stats.regions.len(), 2);
+        assert_eq!(stats.regions[&Region::Europe], 7);
+        assert_eq!(stats.withheld, 2);
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            serde_json::json!({
+                "regions": { "asia": 5, "europe": 7 },
+                "withheld": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!("north_america".parse(), Ok(Region::NorthAmerica));
+        assert_eq!(Region::NorthAmerica.as_str(), "north_america");
+        assert!("atlantis".parse::<Region>().is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Statistics of the whole ceremony, for publishing once it is over. The
+//! report is computed from storage on request: accepted contributions and
+//! their verification timings from the audit log, failed attempts, compute
+//! times from the contributors, and the daily lobby peaks recorded by
+//! [`sample_lobby_size`].
+
+use crate::{
+    lobby::SharedLobbyState,
+    storage::{AuditEventRow, PersistentStorage, StorageError},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use kzg_ceremony_crypto::VerificationTimings;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+use tracing::error;
+
+/// How often the lobby size is sampled for the daily peaks.
+pub const LOBBY_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Audit event logged for every accepted contribution.
+pub const CONTRIBUTION_EVENT: &str = "contribution_verified";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DayReport {
+    pub contributions:   usize,
+    /// Failed attempts per outcome, see [`crate::attempts`].
+    pub failed_attempts: BTreeMap<String, usize>,
+    pub lobby_peak:      usize,
+}
+
+/// Average duration of the verification steps, in milliseconds.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AverageTimings {
+    pub subgroup_checks: f64,
+    pub pairing_checks:  f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CeremonyReport {
+    /// Unix timestamp.
+    pub generated_at: i64,
+    pub num_contributions: usize,
+    /// Per UTC day, as `YYYY-MM-DD`.
+    pub days: BTreeMap<String, DayReport>,
+    /// Accepted contributions per sign-in provider, e.g. `git` or `eth`.
+    pub providers: BTreeMap<String, usize>,
+    pub failed_attempts: BTreeMap<String, usize>,
+    /// Share of the verified contributions that were rejected.
+    pub verification_failure_ratio: f64,
+    /// Average time from getting the slot to the accepted contribution.
+    pub average_compute_seconds: Option<f64>,
+    pub average_verification_ms: Option<AverageTimings>,
+    pub lobby_peak: usize,
+}
+
+impl CeremonyReport {
+    /// Computes the report from storage.
+    pub async fn generate(storage: &PersistentStorage) -> Result<Self, StorageError> {
+        Ok(Self::aggregate(
+            Utc::now(),
+            &storage.audit_events(CONTRIBUTION_EVENT).await?,
+            &storage.failed_attempt_outcomes().await?,
+            &storage.contribution_times().await?,
+            &storage.lobby_peaks().await?,
+        ))
+    }
+
+    #[allow(clippy::cast_precision_loss)] // Averages of small counts
+    fn aggregate(
+        now: DateTime<Utc>,
+        contributions: &[AuditEventRow],
+        failed_attempts: &[(String, i64)],
+        contribution_times: &[(DateTime<Utc>, DateTime<Utc>)],
+        lobby_peaks: &[(String, i64)],
+    ) -> Self {
+        let mut report = Self {
+            generated_at: now.timestamp(),
+            num_contributions: contributions.len(),
+            ..Self::default()
+        };
+
+        let mut timings = Vec::new();
+        for event in contributions {
+            report
+                .days
+                .entry(day(event.logged_at))
+                .or_default()
+                .contributions += 1;
+            let provider = match event.uid.split('|').next() {
+                Some(provider) if !provider.is_empty() => provider,
+                _ => "none",
+            };
+            *report.providers.entry(provider.to_owned()).or_default() += 1;
+            if let Ok(timing) = serde_json::from_str::<VerificationTimings>(&event.details) {
+                timings.push(timing);
+            }
+        }
+
+        for (outcome, attempted_at) in failed_attempts {
+            *report
+                .days
+                .entry(day(*attempted_at))
+                .or_default()
+                .failed_attempts
+                .entry(outcome.clone())
+                .or_default() += 1;
+            *report.failed_attempts.entry(outcome.clone()).or_default() += 1;
+        }
+        let rejected = report.failed_attempts.get("rejected").copied().unwrap_or(0);
+        if rejected + report.num_contributions > 0 {
+            report.verification_failure_ratio =
+                rejected as f64 / (rejected + report.num_contributions) as f64;
+        }
+
+        if !contribution_times.is_empty() {
+            let total = contribution_times
+                .iter()
+                .map(|(started_at, finished_at)| (*finished_at - *started_at).num_milliseconds())
+                .sum::<i64>();
+            report.average_compute_seconds =
+                Some(total as f64 / 1000.0 / contribution_times.len() as f64);
+        }
+
+        if !timings.is_empty() {
+            let average = |duration: fn(&VerificationTimings) -> Duration| {
+                timings.iter().map(duration).sum::<Duration>().as_secs_f64() * 1000.0
+                    / timings.len() as f64
+            };
+            report.average_verification_ms = Some(AverageTimings {
+                subgroup_checks: average(|timing| timing.subgroup_checks),
+                pairing_checks:  average(|timing| timing.pairing_checks),
+            });
+        }
+
+        for (day, peak) in lobby_peaks {
+            let peak = usize::try_from(*peak).unwrap_or_default();
+            report.days.entry(day.clone()).or_default().lobby_peak = peak;
+            report.lobby_peak = report.lobby_peak.max(peak);
+        }
+        report
+    }
+
+    /// The report as CSV, with one `metric,key,value` row per number.
+    #[must_use]
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("metric,key,value\n");
+        let mut row = |metric: &str, key: &str, value: &dyn std::fmt::Display| {
+            // Keys are days, providers and outcomes, which need no quoting
+            let _ = writeln!(csv, "{metric},{key},{value}");
+        };
+        row("generated_at", "", &self.generated_at);
+        row("contributions", "total", &self.num_contributions);
+        for (day, report) in &self.days {
+            row("contributions_per_day", day, &report.contributions);
+        }
+        for (provider, count) in &self.providers {
+            row("contributions_per_provider", provider, count);
+        }
+        for (outcome, count) in &self.failed_attempts {
+            row("failed_attempts", outcome, count);
+        }
+        for (day, report) in &self.days {
+            for (outcome, count) in &report.failed_attempts {
+                row(
+                    "failed_attempts_per_day",
+                    &format!("{day} {outcome}"),
+                    count,
+                );
+            }
+        }
+        row(
+            "verification_failure_ratio",
+            "",
+            &format!("{:.4}", self.verification_failure_ratio),
+        );
+        if let Some(seconds) = self.average_compute_seconds {
+            row("average_compute_seconds", "", &format!("{seconds:.3}"));
+        }
+        if let Some(timings) = &self.average_verification_ms {
+            let subgroup_checks = format!("{:.3}", timings.subgroup_checks);
+            let pairing_checks = format!("{:.3}", timings.pairing_checks);
+            row(
+                "average_verification_ms",
+                "subgroup_checks",
+                &subgroup_checks,
+            );
+            row("average_verification_ms", "pairing_checks", &pairing_checks);
+        }
+        row("lobby_peak", "total", &self.lobby_peak);
+        for (day, report) in &self.days {
+            row("lobby_peak_per_day", day, &report.lobby_peak);
+        }
+        csv
+    }
+}
+
+/// The UTC day of a Unix timestamp, as `YYYY-MM-DD`.
+fn day(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Records the largest lobby size of each day, sampled every
+/// [`LOBBY_SAMPLE_INTERVAL`].
+pub async fn sample_lobby_size(lobby_state: SharedLobbyState, storage: PersistentStorage) {
+    let mut interval = tokio::time::interval(LOBBY_SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let size = lobby_state.get_lobby_size().await;
+        let today = day(Utc::now().timestamp());
+        if let Err(error) = storage.record_lobby_size(&today, size).await {
+            error!(?error, "Could not record lobby size");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{storage::storage_client, test_util::test_options};
+    use serde_json::json;
+
+    fn event(uid: &str, logged_at: i64, details: serde_json::Value) -> AuditEventRow {
+        AuditEventRow {
+            event: CONTRIBUTION_EVENT.to_string(),
+            uid: uid.to_string(),
+            details: details.to_string(),
+            logged_at,
+        }
+    }
+
+    #[test]
+    fn aggregates_ceremony() {
+        const DAY: i64 = 86_400;
+        let timings = |millis: u64| {
+            serde_json::to_value(VerificationTimings {
+                subgroup_checks: Duration::from_millis(millis),
+                pairing_checks:  Duration::from_millis(2 * millis),
+            })
+            .unwrap()
+        };
+        let at = |timestamp| Utc.timestamp_opt(timestamp, 0).unwrap();
+        let report = CeremonyReport::aggregate(
+            at(3 * DAY),
+            &[
+                event("git|1|alice", 0, timings(10)),
+                event("git|2|bob", 10, timings(30)),
+                event("eth|0x1234", DAY, json!({})),
+            ],
+            &[
+                ("rejected".to_string(), 5),
+                ("expired".to_string(), DAY + 5),
+            ],
+            &[(at(0), at(30)), (at(100), at(110))],
+            &[("1970-01-01".to_string(), 7), ("1970-01-02".to_string(), 3)],
+        );
+
+        assert_eq!(report.generated_at, 3 * DAY);
+        assert_eq!(report.num_contributions, 3);
+        assert_eq!(report.days.len(), 2);
+        assert_eq!(report.days["1970-01-01"].contributions, 2);
+        assert_eq!(report.days["1970-01-01"].failed_attempts["rejected"], 1);
+        assert_eq!(report.days["1970-01-02"].failed_attempts["expired"], 1);
+        assert_eq!(report.days["1970-01-02"].lobby_peak, 3);
+        assert_eq!(report.providers["git"], 2);
+        assert_eq!(report.providers["eth"], 1);
+        assert!((report.verification_failure_ratio - 0.25).abs() < 1e-9);
+        assert_eq!(report.average_compute_seconds, Some(20.0));
+        assert_eq!(
+            report.average_verification_ms,
+            Some(AverageTimings {
+                subgroup_checks: 20.0,
+                pairing_checks:  40.0,
+            })
+        );
+        assert_eq!(report.lobby_peak, 7);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("metric,key,value\n"));
+        assert!(csv.contains("contributions_per_day,1970-01-01,2\n"));
+        assert!(csv.contains("contributions_per_provider,eth,1\n"));
+        assert!(csv.contains("failed_attempts_per_day,1970-01-02 expired,1\n"));
+        assert!(csv.contains("verification_failure_ratio,,0.2500\n"));
+        assert!(csv.contains("lobby_peak_per_day,1970-01-01,7\n"));
+    }
+
+    #[test]
+    fn reports_empty_ceremony() {
+        let report = CeremonyReport::aggregate(Utc::now(), &[], &[], &[], &[]);
+        assert_eq!(report.num_contributions, 0);
+        assert!(report.verification_failure_ratio.abs() < f64::EPSILON);
+        assert_eq!(report.average_compute_seconds, None);
+        assert_eq!(report.average_verification_ms, None);
+    }
+
+    #[tokio::test]
+    async fn records_lobby_peaks() {
+        let storage = storage_client(&test_options().storage).await.unwrap();
+        storage.record_lobby_size("2023-01-01", 3).await.unwrap();
+        storage.record_lobby_size("2023-01-01", 8).await.unwrap();
+        storage.record_lobby_size("2023-01-01", 5).await.unwrap();
+        storage.record_lobby_size("2023-01-02", 1).await.unwrap();
+        assert_eq!(storage.lobby_peaks().await.unwrap(), vec![
+            ("2023-01-01".to_string(), 8),
+            ("2023-01-02".to_string(), 1)
+        ]);
+
+        storage
+            .insert_audit_event(CONTRIBUTION_EVENT, "git|1234|user", &json!({}))
+            .await
+            .unwrap();
+        storage
+            .insert_audit_event("deadline_extended", "git|1234|user", &json!({}))
+            .await
+            .unwrap();
+        let report = CeremonyReport::generate(&storage).await.unwrap();
+        assert_eq!(report.num_contributions, 1);
+        assert_eq!(report.lobby_peak, 8);
+    }
+}
@@ -0,0 +1,88 @@
+//! Optional reporting of errors to an external webhook, so operators get
+//! alerted instead of having to grep logs.
+
+use crate::sessions::SessionId;
+use chrono::Utc;
+use clap::Parser;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::panic;
+use tokio::runtime::Handle;
+use tracing::warn;
+use url::Url;
+
+static REPORTER: OnceCell<ErrorReporter> = OnceCell::new();
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Webhook url that errors are reported to as JSON `POST` requests. Error
+    /// reporting is disabled when not set.
+    #[clap(long, env)]
+    pub error_webhook_url: Option<Url>,
+}
+
+#[derive(Clone, Debug)]
+struct ErrorReporter {
+    client: reqwest::Client,
+    url:    Url,
+}
+
+/// Installs the global error reporter and a panic hook reporting panics to
+/// it. Does nothing if no webhook is configured, or if a reporter is already
+/// installed.
+pub fn init(options: &Options, client: reqwest::Client) {
+    let url = match &options.error_webhook_url {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    if REPORTER.set(ErrorReporter { client, url }).is_err() {
+        return;
+    }
+
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        report("panic", info.to_string(), json!({}));
+        previous_hook(info);
+    }));
+}
+
+/// Reports an error of the given kind with additional context. Reporting
+/// happens in the background and never fails the caller.
+pub fn report(kind: &'static str, message: String, context: Value) {
+    let reporter = match REPORTER.get() {
+        Some(reporter) => reporter.clone(),
+        None => return,
+    };
+    // Reports can only be sent from within the runtime.
+    let handle = match Handle::try_current() {
+        Ok(handle) => handle,
+        Err(_) => return,
+    };
+    let payload = json!({
+        "kind": kind,
+        "message": message,
+        "context": context,
+        "timestamp": Utc::now().to_rfc3339(),
+    });
+    handle.spawn(async move {
+        let result = reporter
+            .client
+            .post(reporter.url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(error) = result {
+            warn!(?error, "Could not report error to webhook");
+        }
+    });
+}
+
+/// Hashes a session id, so it can be included in reports without allowing
+/// the recipient to hijack the session.
+pub fn session_id_hash(session_id: &SessionId) -> String {
+    let hash = Sha256::digest(session_id.0.as_bytes());
+    hex::encode(&hash[..8])
+}
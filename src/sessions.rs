@@ -1,8 +1,11 @@
+use crate::regions::Region;
 use async_session::async_trait;
 use axum::{
     extract::{FromRequest, RequestParts},
     TypedHeader,
 };
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
 use headers::{authorization::Bearer, Authorization};
 use kzg_ceremony_crypto::{signature::identity::Identity, ErrorCode};
 use serde::{Deserialize, Serialize};
@@ -12,6 +15,28 @@ use thiserror::Error;
 use tokio::time::Instant;
 use uuid::Uuid;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ConcurrentLoginPolicy {
+    /// Signing in again is refused until a session ends.
+    RejectNew,
+    /// The oldest session is dropped, unless it holds the slot.
+    InvalidateOld,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// How many sessions an identity may hold at a time. Each session can
+    /// take its own spot in the lobby.
+    #[clap(long, env, default_value = "1")]
+    pub max_sessions_per_identity: usize,
+
+    /// What to do when an identity that holds the most sessions it may signs
+    /// in again, e.g. from another device.
+    #[clap(long, env, value_enum, default_value = "invalidate-old")]
+    pub concurrent_login_policy: ConcurrentLoginPolicy,
+}
+
 #[derive(Debug, Hash, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename = "session_id")]
 pub struct SessionId(pub String);
@@ -39,6 +64,8 @@ impl Display for SessionId {
 pub enum SessionError {
     #[error("unknown session id")]
     InvalidSessionId,
+    #[error("session is used from a different client than before")]
+    FingerprintMismatch,
 }
 
 impl ErrorCode for SessionError {
@@ -66,11 +93,46 @@ impl IdToken {
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
     pub token:                 IdToken,
-    // Specifies the last time the user pinged
+    // The unique id of the identity the user signed in with. It differs from
+    // the token's for pseudonymous sessions, and is what failed attempts,
+    // guard marks and contributions are kept under, so that a pseudonym
+    // doesn't give an identity a clean record.
+    pub uid:                   String,
+    // Specifies the last time the user called /lobby/try_contribute
     pub last_ping_time:        Instant,
+    // Specifies the last time the user showed any sign of life, either via
+    // /lobby/ping or /lobby/try_contribute
+    pub last_heartbeat:        Instant,
     // Indicates whether an early /lobby/try_contribute call is accepted.
     // (only allowed right after authentication)
     pub is_first_ping_attempt: bool,
+    // The time until which the lobby token has to be redeemed by entering the
+    // lobby
+    pub lobby_token_deadline:  Instant,
+    // The region the user chose to share, counted once they contribute
+    pub region:                Option<Region>,
+    // Whether the user confirmed an email address, see `oauth::email`
+    pub email_verified:        bool,
+    // When the user last entered the lobby, kept when the lobby is restored
+    // after a restart
+    pub joined_lobby_at:       Option<DateTime<Utc>>,
+    // Until when the user stepped away from the lobby, see
+    // `SharedLobbyState::defer`
+    pub deferred_until:        Option<Instant>,
+    // The client the session was first used from, see `api::fingerprint`
+    pub fingerprint:           Option<String>,
+    // Whether the session looks automated, see `api::bots`. It only gets the
+    // slot when nobody else is waiting for it.
+    pub deprioritized:         bool,
+}
+
+impl SessionInfo {
+    /// Whether the user stepped away from the lobby and shouldn't get the
+    /// slot yet.
+    #[must_use]
+    pub fn is_deferred(&self, now: Instant) -> bool {
+        self.deferred_until.map_or(false, |until| until > now)
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,120 @@
+//! Ready made payloads for participants to publish their receipt, as a GitHub
+//! gist or a tweet. Public attestations let anyone check that the transcript
+//! includes contributions from people they know.
+
+use crate::{keys::Signature, receipt::Receipt};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::{json, Value};
+use url::Url;
+
+/// Name of the file in the gist.
+const GIST_FILE_NAME: &str = "kzg-ceremony-receipt.json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Include payloads in the `/contribute` response for publishing the
+    /// receipt as a GitHub gist or a tweet.
+    #[clap(long, env, default_value = "false")]
+    pub share_payloads: bool,
+
+    /// Text at the start of the tweet and the description of the gist.
+    #[clap(
+        long,
+        env,
+        default_value = "I contributed to the Ethereum KZG Ceremony!"
+    )]
+    pub share_message: String,
+
+    /// Twitter web intent the tweet is prepared with.
+    #[clap(long, env, default_value = "https://twitter.com/intent/tweet")]
+    pub share_tweet_intent_url: Url,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SharePayloads {
+    /// Body for `POST https://api.github.com/gists`, holding the signed
+    /// receipt and its signature.
+    pub gist:             Value,
+    /// Opens a tweet with the first pot pubkey and the receipt signature.
+    pub tweet_intent_url: String,
+}
+
+impl SharePayloads {
+    #[must_use]
+    pub fn new(
+        options: &Options,
+        receipt: &Receipt,
+        signed_receipt: &str,
+        signature: &Signature,
+    ) -> Self {
+        let content = serde_json::to_string_pretty(&json!({
+            "receipt": signed_receipt,
+            "signature": signature.as_hex(),
+        }))
+        .unwrap_or_default();
+        let gist = json!({
+            "description": options.share_message,
+            "public": true,
+            "files": {
+                GIST_FILE_NAME: { "content": content },
+            },
+        });
+
+        let pubkey = receipt
+            .witness
+            .first()
+            .map(|pubkey| format!("\n\nPot pubkey: 0x{}", hex::encode(pubkey.0)))
+            .unwrap_or_default();
+        let text = format!(
+            "{}{pubkey}\nReceipt signature: 0x{}",
+            options.share_message,
+            signature.as_hex()
+        );
+        let mut tweet_intent_url = options.share_tweet_intent_url.clone();
+        tweet_intent_url
+            .query_pairs_mut()
+            .append_pair("text", &text);
+
+        Self {
+            gist,
+            tweet_intent_url: tweet_intent_url.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kzg_ceremony_crypto::{signature::identity::Identity, G2};
+
+    #[test]
+    fn builds_payloads() {
+        let options = Options::parse_from(Vec::<&str>::new());
+        let receipt = Receipt {
+            identity:            Identity::None,
+            witness:             vec![G2::one()],
+            entropy_attestation: None,
+            region:              None,
+            countersigner:       None,
+        };
+        let signature = Signature::from_hex("abcd".to_string());
+        let payloads = SharePayloads::new(&options, &receipt, "{}", &signature);
+
+        let content = payloads.gist["files"][GIST_FILE_NAME]["content"]
+            .as_str()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(content).unwrap(),
+            json!({ "receipt": "{}", "signature": "abcd" })
+        );
+
+        let url = Url::parse(&payloads.tweet_intent_url).unwrap();
+        assert_eq!(url.path(), "/intent/tweet");
+        let (_, text) = url.query_pairs().next().unwrap();
+        assert!(text.starts_with(&options.share_message));
+        assert!(text.contains(&hex::encode(G2::one().0)));
+        assert!(text.ends_with("Receipt signature: 0xabcd"));
+    }
+}
@@ -0,0 +1,267 @@
+//! Contributions that were accepted, but are not yet in the transcript file.
+//!
+//! Accepted contributions are staged in storage before the transcript file
+//! is written, so that a failing disk doesn't lose them: failed writes are
+//! retried in the background, and contributions still staged on startup are
+//! added to the transcript read from disk.
+
+use crate::{
+    io::{self, try_write_transcript_file},
+    lobby::duration_from_str,
+    mirror::SharedMirrors,
+    storage::PersistentStorage,
+    verifier::SEQUENCER_VERSION,
+    Engine, SharedTranscript,
+};
+use clap::Parser;
+use eyre::{ensure, Result as EyreResult, WrapErr};
+use kzg_ceremony_crypto::{BatchContribution, BatchTranscript, Identity};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{Mutex, Notify};
+use tracing::{error, info};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Seconds between attempts to write the transcript file after a write
+    /// failed. Accepted contributions are kept in storage meanwhile.
+    #[clap(long, env, value_parser=duration_from_str, default_value="5")]
+    pub transcript_write_retry_interval: Duration,
+}
+
+/// An accepted contribution, with what is needed to add it to the transcript
+/// again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagedContribution {
+    pub contribution: BatchContribution,
+    pub identity:     Identity,
+    pub received_at:  u64,
+    pub verified_at:  u64,
+}
+
+pub struct TranscriptWriter {
+    target_path: PathBuf,
+    work_path:   PathBuf,
+    io:          io::Options,
+    transcript:  SharedTranscript,
+    storage:     PersistentStorage,
+    /// Writes share the work file, so only one may run at a time.
+    writing:     Mutex<()>,
+    failed:      Notify,
+}
+
+pub type SharedTranscriptWriter = Arc<TranscriptWriter>;
+
+impl TranscriptWriter {
+    pub fn new(
+        options: &crate::Options,
+        transcript: SharedTranscript,
+        storage: PersistentStorage,
+    ) -> Self {
+        Self {
+            target_path: options.transcript_file.clone(),
+            work_path: options.transcript_in_progress_file.clone(),
+            io: options.io.clone(),
+            transcript,
+            storage,
+            writing: Mutex::default(),
+            failed: Notify::new(),
+        }
+    }
+
+    /// Stages an accepted contribution, then writes the transcript that
+    /// includes it as `participant`. Failed writes are retried by
+    /// [`retry_failed_writes`]. Returns whether the transcript file is up to
+    /// date.
+    pub async fn persist(&self, participant: usize, staged: &StagedContribution) -> bool {
+        let stored = match serde_json::to_string(staged) {
+            Ok(staged) => self.storage.stage_contribution(participant, &staged).await,
+            Err(error) => {
+                error!(?error, "Could not serialize contribution");
+                Ok(())
+            }
+        };
+        if let Err(error) = stored {
+            error!(?error, participant, "Could not stage contribution");
+        }
+        self.write_or_retry().await
+    }
+
+    /// Whether the transcript file is being written.
+    #[must_use]
+    pub fn is_writing(&self) -> bool {
+        self.writing.try_lock().is_err()
+    }
+
+    /// Writes the latest transcript, or has [`retry_failed_writes`] retry it
+    /// if that fails. Returns whether the transcript file is up to date.
+    pub async fn write_or_retry(&self) -> bool {
+        match self.write().await {
+            Ok(()) => true,
+            Err(error) => {
+                error!(
+                    ?error,
+                    "Could not write transcript, retrying in the background"
+                );
+                self.failed.notify_one();
+                false
+            }
+        }
+    }
+
+    /// Writes the latest transcript, and drops the staged contributions it
+    /// includes.
+    async fn write(&self) -> EyreResult<()> {
+        let _writing = self.writing.lock().await;
+        let transcript = self.transcript.snapshot();
+        let participants = transcript.num_participants();
+        try_write_transcript_file(
+            self.target_path.clone(),
+            self.work_path.clone(),
+            &self.io,
+            transcript,
+        )
+        .await?;
+        self.storage
+            .clear_staged_contributions(participants)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Retries failed transcript writes until one succeeds, and pushes the
+/// transcript to the mirrors once it does.
+pub async fn retry_failed_writes(
+    writer: SharedTranscriptWriter,
+    mirrors: SharedMirrors,
+    options: Options,
+) {
+    loop {
+        writer.failed.notified().await;
+        loop {
+            tokio::time::sleep(options.transcript_write_retry_interval).await;
+            match writer.write().await {
+                Ok(()) => break,
+                Err(error) => error!(?error, "Could not write transcript, retrying"),
+            }
+        }
+        info!("Wrote transcript after failed attempts");
+        mirrors.push_file(&writer.target_path).await;
+    }
+}
+
+/// Adds the staged contributions missing from the transcript, e.g. because
+/// the sequencer stopped before writing them succeeded. They are verified
+/// again, in case the transcript file was replaced meanwhile. Returns how
+/// many were added.
+///
+/// # Errors
+///
+/// Returns an error if a staged contribution doesn't follow the transcript,
+/// or fails verification.
+pub async fn replay_staged(
+    transcript: &SharedTranscript,
+    storage: &PersistentStorage,
+) -> EyreResult<usize> {
+    let staged = storage.staged_contributions().await?;
+    let mut replayed = BatchTranscript::clone(&transcript.snapshot());
+    let (replayed, count) = tokio::task::spawn_blocking(move || {
+        let mut count = 0;
+        for (participant, staged) in staged {
+            if participant <= replayed.num_participants() {
+                continue;
+            }
+            ensure!(
+                participant == replayed.num_participants() + 1,
+                "Staged contribution {participant} does not follow the transcript with {} \
+                 participants",
+                replayed.num_participants()
+            );
+            let staged: StagedContribution =
+                serde_json::from_str(&staged).wrap_err("Unreadable staged contribution")?;
+            replayed
+                .verify::<Engine>(&staged.contribution)
+                .wrap_err_with(|| format!("Staged contribution {participant} is invalid"))?;
+            let entropy_attestation = staged.contribution.entropy_attestation.clone();
+            replayed.add::<Engine>(staged.contribution, staged.identity);
+            replayed.record_metadata::<Engine>(
+                SEQUENCER_VERSION,
+                staged.received_at,
+                staged.verified_at,
+                entropy_attestation,
+            );
+            count += 1;
+        }
+        eyre::Ok((replayed, count))
+    })
+    .await??;
+    if count > 0 {
+        transcript
+            .update(|transcript| {
+                *transcript = replayed;
+                Ok::<_, Infallible>(())
+            })
+            .await
+            .unwrap_or_else(|never| match never {});
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        io::read_transcript,
+        storage::storage_client,
+        test_util::test_options,
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+    };
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn keeps_contributions_until_written() {
+        let dir = tempdir().unwrap();
+        let mut options = test_options();
+        // The missing directory makes writes fail
+        options.transcript_file = dir.path().join("missing/transcript.json");
+        options.transcript_in_progress_file = dir.path().join("missing/transcript.json.next");
+        let storage = storage_client(&options.storage).await.unwrap();
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+
+        let contribution = valid_contribution(&transcript.snapshot(), 1);
+        let staged = StagedContribution {
+            contribution: contribution.clone(),
+            identity:     Identity::None,
+            received_at:  1,
+            verified_at:  2,
+        };
+        transcript
+            .update(|transcript| transcript.verify_add::<Engine>(contribution, Identity::None))
+            .await
+            .unwrap();
+        let writer = TranscriptWriter::new(&options, transcript.clone(), storage.clone());
+        assert!(!writer.persist(1, &staged).await);
+        assert_eq!(storage.staged_contributions().await.unwrap().len(), 1);
+
+        // After a restart, the staged contribution is added again
+        let restarted: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+        assert_eq!(replay_staged(&restarted, &storage).await.unwrap(), 1);
+        assert_eq!(restarted.snapshot().num_participants(), 1);
+        assert_eq!(
+            restarted.snapshot().participant_ids,
+            transcript.snapshot().participant_ids
+        );
+        assert_eq!(replay_staged(&transcript, &storage).await.unwrap(), 0);
+
+        // Once the disk is back, the retry writes it and clears the stage
+        std::fs::create_dir(dir.path().join("missing")).unwrap();
+        assert!(writer.write().await.is_ok());
+        assert!(storage.staged_contributions().await.unwrap().is_empty());
+        let written = read_transcript(options.transcript_file, &options.io)
+            .await
+            .unwrap();
+        assert_eq!(written.num_participants(), 1);
+    }
+}
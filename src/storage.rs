@@ -1,18 +1,28 @@
+use crate::{
+    api_types::{ApiErrorCode, ErrorResponse},
+    lobby::SlotAssignment,
+    reporting,
+};
 use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::Utc;
+use chrono::{DateTime, TimeZone, Utc};
 use clap::Parser;
 use eyre::{eyre, WrapErr};
 use http::StatusCode;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use sqlx::{
-    any::{AnyConnectOptions, AnyKind},
+    any::{AnyConnectOptions, AnyKind, AnyRow},
     migrate::{Migrate, MigrateDatabase, Migrator},
-    Any, AnyConnection, ConnectOptions, Executor, Row,
+    Any, AnyConnection, ConnectOptions, Connection, Executor, Row,
+};
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::{str::FromStr, sync::Arc};
 use thiserror::Error;
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
@@ -44,6 +54,25 @@ pub struct Options {
 #[derive(Clone, Debug)]
 pub struct PersistentStorage(Arc<Mutex<AnyConnection>>);
 
+/// A signed receipt, as handed out after the contribution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredReceipt {
+    pub participant:      usize,
+    pub receipt:          String,
+    pub signature:        String,
+    /// Only there if the sequencer has a BLS key.
+    pub bls_signature:    Option<String>,
+    /// Signature of the participant, for contributions they confirmed.
+    pub countersignature: Option<String>,
+}
+
+/// The contribution attempts of an identity that failed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FailedAttempts {
+    pub count:   usize,
+    pub last_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Error)]
 pub enum StorageError {
     #[error("Database error: {0}")]
@@ -136,20 +165,127 @@ pub async fn storage_client(options: &Options) -> eyre::Result<PersistentStorage
     Ok(PersistentStorage(Arc::new(Mutex::new(connection))))
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContributorRow {
+    pub uid:         String,
+    pub started_at:  DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub expired_at:  Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptRow {
+    pub session_hash:     String,
+    pub participant:      i64,
+    pub receipt:          String,
+    pub signature:        String,
+    #[serde(default)]
+    pub bls_signature:    Option<String>,
+    #[serde(default)]
+    pub countersignature: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedAttemptRow {
+    pub uid:          String,
+    pub outcome:      String,
+    /// Unix timestamp.
+    pub attempted_at: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEventRow {
+    pub event:     String,
+    pub uid:       String,
+    /// JSON encoded details of the event.
+    pub details:   String,
+    /// Unix timestamp.
+    pub logged_at: i64,
+}
+
+/// A [`SlotAssignment`], numbered in the order they were made.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotAssignmentRow {
+    pub id:           i64,
+    pub rule:         String,
+    /// JSON encoded list of hashed session ids.
+    pub candidates:   String,
+    pub selected:     String,
+    pub seed:         Option<String>,
+    pub beacon_round: Option<i64>,
+    /// Unix timestamp.
+    pub assigned_at:  i64,
+}
+
+/// A rejected contribution kept for analysis, see [`crate::quarantine`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedContributionRow {
+    pub id:            i64,
+    pub identity_hash: String,
+    /// Error code of the rejection.
+    pub code:          String,
+    pub reason:        String,
+    /// JSON encoded batch contribution, as uploaded.
+    pub contribution:  String,
+    /// Unix timestamp.
+    pub rejected_at:   i64,
+}
+
+/// The persistent state needed to continue a ceremony elsewhere. Sign-in
+/// nonces are short lived, so they are left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageDump {
+    pub contributors:       Vec<ContributorRow>,
+    /// Pairs of pseudonym and uid.
+    pub pseudonyms:         Vec<(String, String)>,
+    /// Pairs of user id and JSON encoded passkey.
+    pub passkeys:           Vec<(String, String)>,
+    pub receipts:           Vec<ReceiptRow>,
+    /// Pairs of region and number of contributions.
+    #[serde(default)]
+    pub region_counts:      Vec<(String, i64)>,
+    #[serde(default)]
+    pub failed_attempts:    Vec<FailedAttemptRow>,
+    /// Pairs of email address hash and uid.
+    #[serde(default)]
+    pub verified_emails:    Vec<(String, String)>,
+    #[serde(default)]
+    pub audit_log:          Vec<AuditEventRow>,
+    /// Pairs of uid and mark, see [`crate::guard`].
+    #[serde(default)]
+    pub contribution_guard: Vec<(String, String)>,
+    /// Pairs of UTC day and largest lobby size seen on it, see
+    /// [`crate::report`].
+    #[serde(default)]
+    pub lobby_peaks:        Vec<(String, i64)>,
+    /// See [`crate::lobby::SlotAssignment`].
+    #[serde(default)]
+    pub slot_assignments:   Vec<SlotAssignmentRow>,
+}
+
 impl IntoResponse for StorageError {
     fn into_response(self) -> Response {
         let message = match &self {
             Self::DatabaseError(error) => error.to_string(),
         };
-        let body = Json(json!({
-            "code": "StorageError::DatabaseError",
-            "error": message
-        }));
+        reporting::report("storage_error", message.clone(), json!({}));
+        let body = Json(ErrorResponse {
+            code: ApiErrorCode::StorageDatabaseError,
+            message,
+            details: Map::new(),
+        });
         (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
     }
 }
 
 impl PersistentStorage {
+    /// Runs a trivial query, returning how long it took.
+    pub async fn check_health(&self) -> Result<Duration, StorageError> {
+        let started = Instant::now();
+        self.0.lock().await.execute("SELECT 1").await?;
+        Ok(started.elapsed())
+    }
+
     pub async fn has_contributed(&self, uid: &str) -> Result<bool, StorageError> {
         let sql = "SELECT EXISTS(SELECT 1 FROM contributors WHERE uid = ?1)";
         let result = self
@@ -182,6 +318,922 @@ impl PersistentStorage {
         Ok(())
     }
 
+    /// Records which identity a pseudonym stands for. The mapping is only
+    /// kept in the database and never served.
+    pub async fn insert_pseudonym(&self, pseudonym: &str, uid: &str) -> Result<(), StorageError> {
+        let sql = "INSERT INTO pseudonyms (pseudonym, uid) VALUES (?1, ?2) ON CONFLICT \
+                   (pseudonym) DO NOTHING";
+        self.0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(pseudonym).bind(uid))
+            .await?;
+        Ok(())
+    }
+
+    /// The uid that confirmed the email address with this hash, if any.
+    pub async fn email_owner(&self, email_hash: &str) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT uid FROM verified_emails WHERE email_hash = ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sqlx::query(sql).bind(email_hash))
+            .await?
+            .map(|row| row.get(0));
+        Ok(result)
+    }
+
+    /// Binds a confirmed email address to a uid. Returns false if the address
+    /// is bound to another uid already.
+    pub async fn claim_email(&self, email_hash: &str, uid: &str) -> Result<bool, StorageError> {
+        let mut connection = self.0.lock().await;
+        let sql = "INSERT INTO verified_emails (email_hash, uid) VALUES (?1, ?2) ON CONFLICT \
+                   (email_hash) DO NOTHING";
+        connection
+            .execute(sqlx::query(sql).bind(email_hash).bind(uid))
+            .await?;
+        let sql = "SELECT uid FROM verified_emails WHERE email_hash = ?1";
+        let owner: String = connection
+            .fetch_one(sqlx::query(sql).bind(email_hash))
+            .await
+            .map(|row| row.get(0))?;
+        Ok(owner == uid)
+    }
+
+    pub async fn has_verified_email(&self, uid: &str) -> Result<bool, StorageError> {
+        let sql = "SELECT EXISTS(SELECT 1 FROM verified_emails WHERE uid = ?1)";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_one(sqlx::query(sql).bind(uid))
+            .await
+            .map(|row| row.get(0))?;
+        Ok(result)
+    }
+
+    /// Stores the passkey of a user, replacing any previous one. Passkeys are
+    /// stored in their JSON encoding.
+    pub async fn store_passkey(&self, user_id: &str, passkey: &str) -> Result<(), StorageError> {
+        let sql = "INSERT INTO passkeys (user_id, passkey) VALUES (?1, ?2) ON CONFLICT (user_id) \
+                   DO UPDATE SET passkey = excluded.passkey";
+        self.0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(user_id).bind(passkey))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_passkey(&self, user_id: &str) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT passkey FROM passkeys WHERE user_id = ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sqlx::query(sql).bind(user_id))
+            .await?
+            .map(|row| row.get(0));
+        Ok(result)
+    }
+
+    /// Records a nonce issued for signing in with Ethereum, and removes
+    /// expired ones.
+    pub async fn insert_siwe_nonce(
+        &self,
+        nonce: &str,
+        state_hash: &str,
+        ttl: Duration,
+    ) -> Result<(), StorageError> {
+        let now = Utc::now().timestamp();
+        let expires_at = now.saturating_add(i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX));
+        let mut connection = self.0.lock().await;
+        let sql = "DELETE FROM siwe_nonces WHERE expires_at <= ?1";
+        connection.execute(sqlx::query(sql).bind(now)).await?;
+        let sql = "INSERT INTO siwe_nonces (nonce, state_hash, expires_at) VALUES (?1, ?2, ?3)";
+        connection
+            .execute(
+                sqlx::query(sql)
+                    .bind(nonce)
+                    .bind(state_hash)
+                    .bind(expires_at),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Marks a sign-in nonce as used. Returns false if the nonce is unknown,
+    /// expired, already used or was issued with another state.
+    pub async fn consume_siwe_nonce(
+        &self,
+        nonce: &str,
+        state_hash: &str,
+    ) -> Result<bool, StorageError> {
+        let sql = "UPDATE siwe_nonces SET used_at = ?1 WHERE nonce = ?2 AND state_hash = ?3 AND \
+                   used_at IS NULL AND expires_at > ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(Utc::now().timestamp())
+                    .bind(nonce)
+                    .bind(state_hash),
+            )
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Stores the receipt of a contribution under a hash of the session it
+    /// was made in.
+    pub async fn insert_receipt(
+        &self,
+        session_hash: &str,
+        receipt: &StoredReceipt,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO receipts (session_hash, participant, receipt, signature, \
+                   bls_signature, countersignature) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(session_hash)
+                    .bind(i64::try_from(receipt.participant).unwrap_or(i64::MAX))
+                    .bind(&receipt.receipt)
+                    .bind(&receipt.signature)
+                    .bind(&receipt.bls_signature)
+                    .bind(&receipt.countersignature),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_receipt(
+        &self,
+        session_hash: &str,
+    ) -> Result<Option<StoredReceipt>, StorageError> {
+        let sql = "SELECT participant, receipt, signature, bls_signature, countersignature FROM \
+                   receipts WHERE session_hash = ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sqlx::query(sql).bind(session_hash))
+            .await?
+            .map(|row| stored_receipt(&row));
+        Ok(result)
+    }
+
+    /// All receipts, in the order of the transcript.
+    pub async fn receipts(&self) -> Result<Vec<StoredReceipt>, StorageError> {
+        let sql = "SELECT participant, receipt, signature, bls_signature, countersignature FROM \
+                   receipts ORDER BY participant";
+        let receipts = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(stored_receipt)
+            .collect();
+        Ok(receipts)
+    }
+
+    /// The receipt of the participant at `participant` in the transcript.
+    pub async fn get_receipt_of_participant(
+        &self,
+        participant: usize,
+    ) -> Result<Option<StoredReceipt>, StorageError> {
+        let sql = "SELECT participant, receipt, signature, bls_signature, countersignature FROM \
+                   receipts WHERE participant = ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sqlx::query(sql).bind(i64::try_from(participant).unwrap_or(i64::MAX)))
+            .await?
+            .map(|row| stored_receipt(&row));
+        Ok(result)
+    }
+
+    /// Counts an accepted contribution from a region.
+    pub async fn count_region(&self, region: &str) -> Result<(), StorageError> {
+        let sql = "INSERT INTO region_counts (region, contributions) VALUES (?1, 1) ON CONFLICT \
+                   (region) DO UPDATE SET contributions = region_counts.contributions + 1";
+        self.0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(region))
+            .await?;
+        Ok(())
+    }
+
+    /// The number of accepted contributions per region.
+    pub async fn region_counts(&self) -> Result<Vec<(String, i64)>, StorageError> {
+        let sql = "SELECT region, contributions FROM region_counts ORDER BY region";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        Ok(result)
+    }
+
+    /// Records a contribution attempt of an identity that ended without a
+    /// contribution.
+    pub async fn insert_failed_attempt(
+        &self,
+        uid: &str,
+        outcome: &str,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO failed_attempts (uid, outcome, attempted_at) VALUES (?1, ?2, ?3)";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(uid)
+                    .bind(outcome)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Records an action of an identity that operators may want to review
+    /// later, such as a deadline extension.
+    pub async fn insert_audit_event(
+        &self,
+        event: &str,
+        uid: &str,
+        details: &Value,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO audit_log (event, uid, details, logged_at) VALUES (?1, ?2, ?3, ?4)";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(event)
+                    .bind(uid)
+                    .bind(details.to_string())
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Adds an invitation code, by its hash, that admits `max_uses`
+    /// identities.
+    pub async fn insert_invitation(
+        &self,
+        code_hash: &str,
+        max_uses: usize,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO invitations (code_hash, max_uses, uses, created_at) VALUES (?1, \
+                   ?2, 0, ?3)";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(code_hash)
+                    .bind(i64::try_from(max_uses).unwrap_or(i64::MAX))
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Whether there is an invitation code with the hash, used up or not.
+    pub async fn has_invitation(&self, code_hash: &str) -> Result<bool, StorageError> {
+        let sql = "SELECT 1 FROM invitations WHERE code_hash = ?1";
+        let row = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sqlx::query(sql).bind(code_hash))
+            .await?;
+        Ok(row.is_some())
+    }
+
+    /// Admits `uid` with the invitation code of the hash, if it has uses
+    /// left. Identities admitted before stay admitted without a code. Returns
+    /// whether the identity is admitted.
+    pub async fn redeem_invitation(
+        &self,
+        code_hash: Option<&str>,
+        uid: &str,
+    ) -> Result<bool, StorageError> {
+        let mut connection = self.0.lock().await;
+        let mut transaction = connection.begin().await?;
+        let sql = "SELECT 1 FROM invitation_redemptions WHERE uid = ?1";
+        if transaction
+            .fetch_optional(sqlx::query(sql).bind(uid))
+            .await?
+            .is_some()
+        {
+            return Ok(true);
+        }
+        let code_hash = match code_hash {
+            Some(code_hash) => code_hash,
+            None => return Ok(false),
+        };
+        let sql = "UPDATE invitations SET uses = uses + 1 WHERE code_hash = ?1 AND uses < max_uses";
+        let result = transaction
+            .execute(sqlx::query(sql).bind(code_hash))
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        let sql =
+            "INSERT INTO invitation_redemptions (uid, code_hash, redeemed_at) VALUES (?1, ?2, ?3)";
+        transaction
+            .execute(
+                sqlx::query(sql)
+                    .bind(uid)
+                    .bind(code_hash)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    /// Keeps a rejected contribution with the reason it was rejected for.
+    pub async fn insert_rejected_contribution(
+        &self,
+        identity_hash: &str,
+        code: &str,
+        reason: &str,
+        contribution: &str,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO rejected_contributions (identity_hash, code, reason, contribution, \
+                   rejected_at) VALUES (?1, ?2, ?3, ?4, ?5)";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(identity_hash)
+                    .bind(code)
+                    .bind(reason)
+                    .bind(contribution)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the rejected contributions from before the Unix timestamp
+    /// `before`. Returns how many were dropped.
+    pub async fn prune_rejected_contributions(&self, before: i64) -> Result<u64, StorageError> {
+        let sql = "DELETE FROM rejected_contributions WHERE rejected_at < ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(before))
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// The kept rejected contributions, oldest first.
+    pub async fn rejected_contributions(
+        &self,
+    ) -> Result<Vec<RejectedContributionRow>, StorageError> {
+        let sql = "SELECT id, identity_hash, code, reason, contribution, rejected_at FROM \
+                   rejected_contributions ORDER BY id";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| RejectedContributionRow {
+                id:            row.get(0),
+                identity_hash: row.get(1),
+                code:          row.get(2),
+                reason:        row.get(3),
+                contribution:  row.get(4),
+                rejected_at:   row.get(5),
+            })
+            .collect();
+        Ok(result)
+    }
+
+    /// Keeps an accepted contribution until the transcript file includes it,
+    /// see [`crate::staging`].
+    pub async fn stage_contribution(
+        &self,
+        participant: usize,
+        contribution: &str,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO staged_contributions (participant, contribution, staged_at) VALUES \
+                   (?1, ?2, ?3) ON CONFLICT (participant) DO UPDATE SET contribution = \
+                   excluded.contribution, staged_at = excluded.staged_at";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(i64::try_from(participant).unwrap_or(i64::MAX))
+                    .bind(contribution)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// The staged contributions, by participant number.
+    pub async fn staged_contributions(&self) -> Result<Vec<(usize, String)>, StorageError> {
+        let sql = "SELECT participant, contribution FROM staged_contributions ORDER BY participant";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| {
+                (
+                    usize::try_from(row.get::<i64, _>(0)).unwrap_or_default(),
+                    row.get(1),
+                )
+            })
+            .collect();
+        Ok(result)
+    }
+
+    /// Drops the staged contributions up to and including `participant`,
+    /// once the transcript file includes them.
+    pub async fn clear_staged_contributions(&self, participant: usize) -> Result<(), StorageError> {
+        let sql = "DELETE FROM staged_contributions WHERE participant <= ?1";
+        self.0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(i64::try_from(participant).unwrap_or(i64::MAX)))
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the saved lobby, see [`crate::lobby::LobbySnapshot`].
+    pub async fn save_lobby_snapshot(&self, snapshot: &str) -> Result<(), StorageError> {
+        let sql = "INSERT INTO lobby_snapshot (id, snapshot, saved_at) VALUES (1, ?1, ?2) ON \
+                   CONFLICT (id) DO UPDATE SET snapshot = excluded.snapshot, saved_at = \
+                   excluded.saved_at";
+        self.0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(snapshot).bind(Utc::now().timestamp()))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn lobby_snapshot(&self) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT snapshot FROM lobby_snapshot WHERE id = 1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sql)
+            .await?
+            .map(|row| row.get(0));
+        Ok(result)
+    }
+
+    /// Replaces the announcement, or removes it with `None`, see
+    /// [`crate::announcement`].
+    pub async fn set_announcement(&self, message: Option<&str>) -> Result<(), StorageError> {
+        let mut connection = self.0.lock().await;
+        match message {
+            Some(message) => {
+                let sql = "INSERT INTO announcement (id, message, set_at) VALUES (1, ?1, ?2) ON \
+                           CONFLICT (id) DO UPDATE SET message = excluded.message, set_at = \
+                           excluded.set_at";
+                connection
+                    .execute(sqlx::query(sql).bind(message).bind(Utc::now().timestamp()))
+                    .await?;
+            }
+            None => {
+                connection.execute("DELETE FROM announcement").await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn announcement(&self) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT message FROM announcement WHERE id = 1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sql)
+            .await?
+            .map(|row| row.get(0));
+        Ok(result)
+    }
+
+    /// Start and end of every finished contribution.
+    pub async fn contribution_times(
+        &self,
+    ) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>, StorageError> {
+        let sql = "SELECT started_at, finished_at FROM contributors WHERE finished_at IS NOT NULL \
+                   AND expired_at IS NULL ORDER BY id";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        Ok(result)
+    }
+
+    /// Outcome and Unix timestamp of every failed attempt.
+    pub async fn failed_attempt_outcomes(&self) -> Result<Vec<(String, i64)>, StorageError> {
+        let sql = "SELECT outcome, attempted_at FROM failed_attempts ORDER BY id";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        Ok(result)
+    }
+
+    /// All audit events of one kind, oldest first.
+    pub async fn audit_events(&self, event: &str) -> Result<Vec<AuditEventRow>, StorageError> {
+        let sql =
+            "SELECT event, uid, details, logged_at FROM audit_log WHERE event = ?1 ORDER BY id";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sqlx::query(sql).bind(event))
+            .await?
+            .iter()
+            .map(|row| AuditEventRow {
+                event:     row.get(0),
+                uid:       row.get(1),
+                details:   row.get(2),
+                logged_at: row.get(3),
+            })
+            .collect();
+        Ok(result)
+    }
+
+    pub async fn insert_slot_assignment(
+        &self,
+        assignment: &SlotAssignment,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO slot_assignments (rule, candidates, selected, seed, beacon_round, \
+                   assigned_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        let candidates =
+            serde_json::to_string(&assignment.candidates).expect("Serializing strings can't fail");
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(assignment.rule.as_str())
+                    .bind(candidates)
+                    .bind(&assignment.selected)
+                    .bind(&assignment.seed)
+                    .bind(
+                        assignment
+                            .beacon_round
+                            .map(|round| i64::try_from(round).unwrap_or(i64::MAX)),
+                    )
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Up to `limit` slot assignments numbered after `after`, oldest first.
+    pub async fn slot_assignments(
+        &self,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<SlotAssignmentRow>, StorageError> {
+        let sql = "SELECT id, rule, candidates, selected, seed, beacon_round, assigned_at FROM \
+                   slot_assignments WHERE id > ?1 ORDER BY id LIMIT ?2";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(
+                sqlx::query(sql)
+                    .bind(after)
+                    .bind(i64::try_from(limit).unwrap_or(i64::MAX)),
+            )
+            .await?
+            .iter()
+            .map(slot_assignment_row)
+            .collect();
+        Ok(result)
+    }
+
+    /// Raises the lobby peak of a UTC day to `size`, if it is lower.
+    pub async fn record_lobby_size(&self, day: &str, size: usize) -> Result<(), StorageError> {
+        let sql = "INSERT INTO lobby_peaks (day, peak) VALUES (?1, ?2) ON CONFLICT (day) DO \
+                   UPDATE SET peak = excluded.peak WHERE lobby_peaks.peak < excluded.peak";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(day)
+                    .bind(i64::try_from(size).unwrap_or(i64::MAX)),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Pairs of UTC day and largest lobby size seen on it, oldest first.
+    pub async fn lobby_peaks(&self) -> Result<Vec<(String, i64)>, StorageError> {
+        let sql = "SELECT day, peak FROM lobby_peaks ORDER BY day";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        Ok(result)
+    }
+
+    pub async fn failed_attempts(&self, uid: &str) -> Result<FailedAttempts, StorageError> {
+        let sql = "SELECT COUNT(*), MAX(attempted_at) FROM failed_attempts WHERE uid = ?1";
+        let row = self
+            .0
+            .lock()
+            .await
+            .fetch_one(sqlx::query(sql).bind(uid))
+            .await?;
+        Ok(FailedAttempts {
+            count:   usize::try_from(row.get::<i64, _>(0)).unwrap_or_default(),
+            last_at: row
+                .get::<Option<i64>, _>(1)
+                .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single()),
+        })
+    }
+
+    /// Reads all persistent state, in insertion order.
+    pub async fn dump(&self) -> Result<StorageDump, StorageError> {
+        let mut connection = self.0.lock().await;
+        let sql = "SELECT uid, started_at, finished_at, expired_at FROM contributors ORDER BY id";
+        let contributors = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| ContributorRow {
+                uid:         row.get(0),
+                started_at:  row.get(1),
+                finished_at: row.get(2),
+                expired_at:  row.get(3),
+            })
+            .collect();
+        let sql = "SELECT pseudonym, uid FROM pseudonyms ORDER BY pseudonym";
+        let pseudonyms = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let sql = "SELECT user_id, passkey FROM passkeys ORDER BY user_id";
+        let passkeys = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let sql = "SELECT session_hash, participant, receipt, signature, bls_signature, \
+                   countersignature FROM receipts ORDER BY participant";
+        let receipts = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| ReceiptRow {
+                session_hash:     row.get(0),
+                participant:      row.get(1),
+                receipt:          row.get(2),
+                signature:        row.get(3),
+                bls_signature:    row.get(4),
+                countersignature: row.get(5),
+            })
+            .collect();
+        let sql = "SELECT region, contributions FROM region_counts ORDER BY region";
+        let region_counts = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let sql = "SELECT uid, outcome, attempted_at FROM failed_attempts ORDER BY id";
+        let failed_attempts = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| FailedAttemptRow {
+                uid:          row.get(0),
+                outcome:      row.get(1),
+                attempted_at: row.get(2),
+            })
+            .collect();
+        let sql = "SELECT email_hash, uid FROM verified_emails ORDER BY email_hash";
+        let verified_emails = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let sql = "SELECT event, uid, details, logged_at FROM audit_log ORDER BY id";
+        let audit_log = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| AuditEventRow {
+                event:     row.get(0),
+                uid:       row.get(1),
+                details:   row.get(2),
+                logged_at: row.get(3),
+            })
+            .collect();
+        let sql = "SELECT uid, status FROM contribution_guard ORDER BY uid";
+        let contribution_guard = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let sql = "SELECT day, peak FROM lobby_peaks ORDER BY day";
+        let lobby_peaks = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let sql = "SELECT id, rule, candidates, selected, seed, beacon_round, assigned_at FROM \
+                   slot_assignments ORDER BY id";
+        let slot_assignments = connection
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(slot_assignment_row)
+            .collect();
+        Ok(StorageDump {
+            contributors,
+            pseudonyms,
+            passkeys,
+            receipts,
+            region_counts,
+            failed_attempts,
+            verified_emails,
+            audit_log,
+            contribution_guard,
+            lobby_peaks,
+            slot_assignments,
+        })
+    }
+
+    /// Writes a dump into the database in a single transaction. Fails
+    /// without changes if any of the rows already exist.
+    pub async fn restore(&self, dump: &StorageDump) -> Result<(), StorageError> {
+        let mut connection = self.0.lock().await;
+        let mut transaction = connection.begin().await?;
+        for contributor in &dump.contributors {
+            let sql = "INSERT INTO contributors (uid, started_at, finished_at, expired_at) VALUES \
+                       (?1, ?2, ?3, ?4)";
+            transaction
+                .execute(
+                    sqlx::query(sql)
+                        .bind(&contributor.uid)
+                        .bind(contributor.started_at)
+                        .bind(contributor.finished_at)
+                        .bind(contributor.expired_at),
+                )
+                .await?;
+        }
+        for (pseudonym, uid) in &dump.pseudonyms {
+            let sql = "INSERT INTO pseudonyms (pseudonym, uid) VALUES (?1, ?2)";
+            transaction
+                .execute(sqlx::query(sql).bind(pseudonym).bind(uid))
+                .await?;
+        }
+        for (user_id, passkey) in &dump.passkeys {
+            let sql = "INSERT INTO passkeys (user_id, passkey) VALUES (?1, ?2)";
+            transaction
+                .execute(sqlx::query(sql).bind(user_id).bind(passkey))
+                .await?;
+        }
+        for receipt in &dump.receipts {
+            let sql = "INSERT INTO receipts (session_hash, participant, receipt, signature, \
+                       bls_signature, countersignature) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+            transaction
+                .execute(
+                    sqlx::query(sql)
+                        .bind(&receipt.session_hash)
+                        .bind(receipt.participant)
+                        .bind(&receipt.receipt)
+                        .bind(&receipt.signature)
+                        .bind(&receipt.bls_signature)
+                        .bind(&receipt.countersignature),
+                )
+                .await?;
+        }
+        for (region, contributions) in &dump.region_counts {
+            let sql = "INSERT INTO region_counts (region, contributions) VALUES (?1, ?2)";
+            transaction
+                .execute(sqlx::query(sql).bind(region).bind(contributions))
+                .await?;
+        }
+        for attempt in &dump.failed_attempts {
+            let sql =
+                "INSERT INTO failed_attempts (uid, outcome, attempted_at) VALUES (?1, ?2, ?3)";
+            transaction
+                .execute(
+                    sqlx::query(sql)
+                        .bind(&attempt.uid)
+                        .bind(&attempt.outcome)
+                        .bind(attempt.attempted_at),
+                )
+                .await?;
+        }
+        for (email_hash, uid) in &dump.verified_emails {
+            let sql = "INSERT INTO verified_emails (email_hash, uid) VALUES (?1, ?2)";
+            transaction
+                .execute(sqlx::query(sql).bind(email_hash).bind(uid))
+                .await?;
+        }
+        for event in &dump.audit_log {
+            let sql =
+                "INSERT INTO audit_log (event, uid, details, logged_at) VALUES (?1, ?2, ?3, ?4)";
+            transaction
+                .execute(
+                    sqlx::query(sql)
+                        .bind(&event.event)
+                        .bind(&event.uid)
+                        .bind(&event.details)
+                        .bind(event.logged_at),
+                )
+                .await?;
+        }
+        for (uid, status) in &dump.contribution_guard {
+            let sql = "INSERT INTO contribution_guard (uid, status, marked_at) VALUES (?1, ?2, ?3)";
+            transaction
+                .execute(
+                    sqlx::query(sql)
+                        .bind(uid)
+                        .bind(status)
+                        .bind(Utc::now().timestamp()),
+                )
+                .await?;
+        }
+        for (day, peak) in &dump.lobby_peaks {
+            let sql = "INSERT INTO lobby_peaks (day, peak) VALUES (?1, ?2)";
+            transaction
+                .execute(sqlx::query(sql).bind(day).bind(peak))
+                .await?;
+        }
+        // The ids are kept, since they are published
+        for assignment in &dump.slot_assignments {
+            let sql = "INSERT INTO slot_assignments (id, rule, candidates, selected, seed, \
+                       beacon_round, assigned_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+            transaction
+                .execute(
+                    sqlx::query(sql)
+                        .bind(assignment.id)
+                        .bind(&assignment.rule)
+                        .bind(&assignment.candidates)
+                        .bind(&assignment.selected)
+                        .bind(&assignment.seed)
+                        .bind(assignment.beacon_round)
+                        .bind(assignment.assigned_at),
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
     pub async fn expire_contribution(&self, uid: &str) -> Result<(), StorageError> {
         let sql = "UPDATE contributors SET expired_at = ?1 WHERE uid = ?2";
         self.0
@@ -191,4 +1243,142 @@ impl PersistentStorage {
             .await?;
         Ok(())
     }
+
+    /// Expires a contribution that neither finished nor expired yet. Returns
+    /// whether there was one.
+    pub async fn expire_unfinished_contribution(&self, uid: &str) -> Result<bool, StorageError> {
+        let sql = "UPDATE contributors SET expired_at = ?1 WHERE uid = ?2 AND finished_at IS NULL \
+                   AND expired_at IS NULL";
+        let result = self
+            .0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(Utc::now()).bind(uid))
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Records an operation before it starts, see [`crate::wal`].
+    pub async fn wal_append(
+        &self,
+        operation: &str,
+        uid: &str,
+        payload: &str,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO wal (operation, uid, payload, logged_at) VALUES (?1, ?2, ?3, ?4)";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(operation)
+                    .bind(uid)
+                    .bind(payload)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the entries of an operation once it completed.
+    pub async fn wal_remove(&self, operation: &str, uid: &str) -> Result<(), StorageError> {
+        let sql = "DELETE FROM wal WHERE operation = ?1 AND uid = ?2";
+        self.0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(operation).bind(uid))
+            .await?;
+        Ok(())
+    }
+
+    /// The operations that did not complete, as operation, uid and payload,
+    /// in the order they were logged.
+    pub async fn wal_entries(&self) -> Result<Vec<(String, String, String)>, StorageError> {
+        let sql = "SELECT operation, uid, payload FROM wal ORDER BY id";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_all(sql)
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+        Ok(result)
+    }
+
+    /// Marks the identity as holding the contribution slot, unless it is
+    /// marked as having contributed. Returns whether it was marked, see
+    /// [`crate::guard`].
+    pub async fn claim_contribution(&self, uid: &str) -> Result<bool, StorageError> {
+        let sql = "INSERT INTO contribution_guard (uid, status, marked_at) VALUES (?1, \
+                   'contributing', ?2) ON CONFLICT (uid) DO UPDATE SET status = excluded.status, \
+                   marked_at = excluded.marked_at WHERE contribution_guard.status != 'contributed'";
+        let result = self
+            .0
+            .lock()
+            .await
+            .execute(sqlx::query(sql).bind(uid).bind(Utc::now().timestamp()))
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Marks how the last attempt of an identity ended. Identities marked as
+    /// having contributed keep their mark.
+    pub async fn mark_identity(&self, uid: &str, status: &str) -> Result<(), StorageError> {
+        let sql = "INSERT INTO contribution_guard (uid, status, marked_at) VALUES (?1, ?2, ?3) ON \
+                   CONFLICT (uid) DO UPDATE SET status = excluded.status, marked_at = \
+                   excluded.marked_at WHERE contribution_guard.status != 'contributed'";
+        self.0
+            .lock()
+            .await
+            .execute(
+                sqlx::query(sql)
+                    .bind(uid)
+                    .bind(status)
+                    .bind(Utc::now().timestamp()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn identity_mark(&self, uid: &str) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT status FROM contribution_guard WHERE uid = ?1";
+        let result = self
+            .0
+            .lock()
+            .await
+            .fetch_optional(sqlx::query(sql).bind(uid))
+            .await?
+            .map(|row| row.get(0));
+        Ok(result)
+    }
+
+    /// Drops all logged operations, once they were recovered.
+    pub async fn wal_clear(&self) -> Result<(), StorageError> {
+        self.0.lock().await.execute("DELETE FROM wal").await?;
+        Ok(())
+    }
+}
+
+fn stored_receipt(row: &AnyRow) -> StoredReceipt {
+    StoredReceipt {
+        participant:      usize::try_from(row.get::<i64, _>(0)).unwrap_or_default(),
+        receipt:          row.get(1),
+        signature:        row.get(2),
+        bls_signature:    row.get(3),
+        countersignature: row.get(4),
+    }
+}
+
+fn slot_assignment_row(row: &AnyRow) -> SlotAssignmentRow {
+    SlotAssignmentRow {
+        id:           row.get(0),
+        rule:         row.get(1),
+        candidates:   row.get(2),
+        selected:     row.get(3),
+        seed:         row.get(4),
+        beacon_round: row.get(5),
+        assigned_at:  row.get(6),
+    }
 }
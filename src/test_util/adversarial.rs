@@ -0,0 +1,239 @@
+//! Malformed contributions, for verifiers and client authors to test against
+//! the same corpus the sequencer rejects. Each [`Tampering`] breaks one
+//! property of an otherwise valid contribution to the first ceremony.
+
+use kzg_ceremony_crypto::{
+    BatchContribution, BatchTranscript, CeremonyError, Engine, Identity, ParseError, Secret, G1, G2,
+};
+use strum::{EnumIter, IntoEnumIterator, IntoStaticStr};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum Tampering {
+    /// A G1 power on the curve, but outside the prime order subgroup.
+    G1NotInSubgroup,
+    /// A G2 power on the curve, but outside the prime order subgroup.
+    G2NotInSubgroup,
+    /// A `potPubkey` on the curve, but outside the prime order subgroup.
+    PubkeyNotInSubgroup,
+    /// A G1 power whose x coordinate is not on the curve.
+    G1NotOnCurve,
+    /// One G1 power less than the ceremony has.
+    MissingG1Power,
+    /// One G2 power more than the ceremony has.
+    ExtraG2Power,
+    /// No contribution to the last ceremony.
+    MissingContribution,
+    /// A G1 power at infinity.
+    ZeroG1Power,
+    /// A `potPubkey` at infinity.
+    ZeroPubkey,
+    /// The generator as `potPubkey`, as if no entropy had been added.
+    NoEntropy,
+    /// A running product, the first power of tau in G1, that the
+    /// `potPubkey` doesn't account for.
+    TamperedRunningProduct,
+    /// Two G1 powers in the wrong order.
+    SwappedG1Powers,
+}
+
+impl Tampering {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.into()
+    }
+
+    /// Applies the tampering to a valid contribution.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the first ceremony has fewer than four G1 powers.
+    pub fn apply<E: Engine>(self, contribution: &mut BatchContribution) {
+        if self == Self::MissingContribution {
+            contribution.contributions.pop();
+            return;
+        }
+        let first = &mut contribution.contributions[0];
+        let (g1, g2) = (&mut first.powers.g1, &mut first.powers.g2);
+        assert!(g1.len() >= 4, "Tampering needs at least four G1 powers");
+        let last_g1 = g1.len() - 1;
+        let last_g2 = g2.len() - 1;
+        match self {
+            Self::G1NotInSubgroup => g1[last_g1] = non_subgroup_g1::<E>(),
+            Self::G2NotInSubgroup => g2[last_g2] = non_subgroup_g2::<E>(),
+            Self::PubkeyNotInSubgroup => first.pot_pubkey = non_subgroup_g2::<E>(),
+            Self::G1NotOnCurve => g1[last_g1] = off_curve_g1::<E>(),
+            Self::MissingG1Power => {
+                g1.pop();
+            }
+            Self::ExtraG2Power => g2.push(G2::one()),
+            Self::ZeroG1Power => g1[last_g1] = G1::zero(),
+            Self::ZeroPubkey => first.pot_pubkey = G2::zero(),
+            Self::NoEntropy => first.pot_pubkey = G2::one(),
+            Self::TamperedRunningProduct => g1[1] = g1[2],
+            Self::SwappedG1Powers => g1.swap(last_g1 - 1, last_g1),
+            Self::MissingContribution => unreachable!(),
+        }
+    }
+}
+
+/// A valid contribution to `transcript` from `seed`, signed for `identity`.
+///
+/// # Panics
+///
+/// Panics if the contribution can't be computed.
+#[must_use]
+pub fn valid<E: Engine>(
+    transcript: &BatchTranscript,
+    seed: u8,
+    identity: &Identity,
+) -> BatchContribution {
+    let mut contribution = transcript.contribution();
+    contribution
+        .add_entropy::<E>(&Secret::new([seed; 32]), identity)
+        .expect("Cannot compute contribution");
+    contribution
+}
+
+/// A contribution to `transcript` from `seed`, malformed by `tampering`.
+///
+/// # Panics
+///
+/// See [`Tampering::apply`].
+#[must_use]
+pub fn tampered<E: Engine>(
+    transcript: &BatchTranscript,
+    seed: u8,
+    identity: &Identity,
+    tampering: Tampering,
+) -> BatchContribution {
+    let mut contribution = valid::<E>(transcript, seed, identity);
+    tampering.apply::<E>(&mut contribution);
+    contribution
+}
+
+/// Every tampering applied to a contribution from `seed`.
+///
+/// # Panics
+///
+/// See [`Tampering::apply`].
+#[must_use]
+pub fn corpus<E: Engine>(
+    transcript: &BatchTranscript,
+    seed: u8,
+    identity: &Identity,
+) -> Vec<(Tampering, BatchContribution)> {
+    Tampering::iter()
+        .map(|tampering| {
+            (
+                tampering,
+                tampered::<E>(transcript, seed, identity, tampering),
+            )
+        })
+        .collect()
+}
+
+/// The compressed G1 point with the smallest x coordinate that fails
+/// validation with `error`.
+fn find_g1<E: Engine>(error: ParseError) -> G1 {
+    (1..=u8::MAX)
+        .map(|x| {
+            let mut bytes = [0; 48];
+            bytes[0] = 0x80;
+            bytes[47] = x;
+            G1(bytes)
+        })
+        .find(|point| E::validate_g1(&[*point]) == Err(CeremonyError::InvalidG1Power(0, error)))
+        .expect("No such point with a small x coordinate")
+}
+
+/// A G1 point on the curve, outside the prime order subgroup.
+#[must_use]
+pub fn non_subgroup_g1<E: Engine>() -> G1 {
+    find_g1::<E>(ParseError::InvalidSubgroup)
+}
+
+/// A compressed G1 encoding of an x coordinate without a point on the
+/// curve.
+#[must_use]
+pub fn off_curve_g1<E: Engine>() -> G1 {
+    find_g1::<E>(ParseError::InvalidXCoordinate)
+}
+
+/// A G2 point on the curve, outside the prime order subgroup.
+///
+/// # Panics
+///
+/// Panics if there is no such point with a small x coordinate, which
+/// doesn't happen on BLS12-381.
+#[must_use]
+pub fn non_subgroup_g2<E: Engine>() -> G2 {
+    (1..=u8::MAX)
+        .map(|x| {
+            let mut bytes = [0; 96];
+            bytes[0] = 0x80;
+            bytes[95] = x;
+            G2(bytes)
+        })
+        .find(|point| {
+            E::validate_g2(&[*point])
+                == Err(CeremonyError::InvalidG2Power(
+                    0,
+                    ParseError::InvalidSubgroup,
+                ))
+        })
+        .expect("No such point with a small x coordinate")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::test_jwt, tests::test_transcript};
+
+    type E = crate::Engine;
+
+    #[test]
+    fn corpus_is_rejected() {
+        let transcript = BatchTranscript::new(&[(4, 2), (8, 3)]);
+        let identity = test_jwt(0).identity;
+        assert!(transcript
+            .verify::<E>(&valid::<E>(&transcript, 1, &identity))
+            .is_ok());
+
+        let corpus = corpus::<E>(&transcript, 1, &identity);
+        assert_eq!(corpus.len(), Tampering::iter().count());
+        for (tampering, contribution) in corpus {
+            assert!(
+                transcript.verify::<E>(&contribution).is_err(),
+                "{} was accepted",
+                tampering.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn finds_invalid_points() {
+        assert!(matches!(
+            E::validate_g1(&[non_subgroup_g1::<E>()]),
+            Err(CeremonyError::InvalidG1Power(
+                0,
+                ParseError::InvalidSubgroup
+            ))
+        ));
+        assert!(matches!(
+            E::validate_g2(&[non_subgroup_g2::<E>()]),
+            Err(CeremonyError::InvalidG2Power(
+                0,
+                ParseError::InvalidSubgroup
+            ))
+        ));
+        let transcript = test_transcript();
+        let contribution = tampered::<E>(
+            &transcript,
+            1,
+            &Identity::None,
+            Tampering::MissingContribution,
+        );
+        assert!(contribution.contributions.is_empty());
+    }
+}
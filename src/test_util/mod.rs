@@ -0,0 +1,174 @@
+//! Helpers for tests. With the `deterministic` feature they are also
+//! available outside the crate, including a harness that makes the
+//! interleaving of concurrent tasks depend only on a seed: a
+//! [`VirtualClock`] drives the lobby timers, a [`Scheduler`] decides which
+//! task runs next, and [`simulated_verifier`] takes a fixed time to verify.
+//! Run the test on a current-thread runtime, which `#[tokio::test]` is by
+//! default.
+//!
+//! With the `adversarial` feature, [`adversarial`] generates malformed
+//! contributions for testing verifiers and clients.
+
+use crate::{
+    access::{AccessLists, SharedAccessLists},
+    sessions::{IdToken, SessionInfo},
+    verifier::{SharedVerifier, Verifier},
+    Options,
+};
+use clap::Parser;
+use futures::future::poll_fn;
+use kzg_ceremony_crypto::signature::identity::Identity;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{future::Future, pin::Pin, sync::Arc, task::Poll, time::Duration};
+use tokio::time::Instant;
+
+#[cfg(any(test, feature = "adversarial"))]
+pub mod adversarial;
+
+#[must_use]
+pub fn test_jwt(exp: u64) -> IdToken {
+    IdToken {
+        identity: Identity::Github {
+            id:       1234,
+            username: "test_user".to_string(),
+        },
+        exp,
+    }
+}
+
+#[must_use]
+pub fn create_test_session_info(exp: u64) -> SessionInfo {
+    let token = test_jwt(exp);
+    SessionInfo {
+        uid:                   token.unique_identifier(),
+        token,
+        last_ping_time:        Instant::now(),
+        last_heartbeat:        Instant::now(),
+        is_first_ping_attempt: true,
+        lobby_token_deadline:  Instant::now() + test_options().lobby.lobby_token_ttl,
+        region:                None,
+        email_verified:        false,
+        joined_lobby_at:       None,
+        deferred_until:        None,
+        fingerprint:           None,
+        deprioritized:         false,
+    }
+}
+
+#[must_use]
+pub fn test_options() -> Options {
+    let args: Vec<&str> = vec![
+        "kzg-ceremony-sequencer",
+        "--gh-client-secret",
+        "INVALID",
+        "--gh-client-id",
+        "INVALID",
+        "--eth-rpc-url",
+        "INVALID",
+        "--eth-client-secret",
+        "INVALID",
+        "--eth-client-id",
+        "INVALID",
+        "--database-url",
+        "sqlite://:memory:",
+    ];
+    Options::parse_from(args)
+}
+
+#[must_use]
+pub fn shared_access_lists() -> SharedAccessLists {
+    Arc::new(AccessLists::new(&test_options().access).unwrap())
+}
+
+/// The runtime's clock, paused so that it only moves when advanced, or when
+/// all tasks wait for timers. Resumes the clock when dropped.
+pub struct VirtualClock {
+    started: Instant,
+}
+
+impl VirtualClock {
+    /// # Panics
+    ///
+    /// Panics if the clock is paused already, or the runtime is not a
+    /// current-thread runtime.
+    #[must_use]
+    pub fn start() -> Self {
+        tokio::time::pause();
+        Self {
+            started: Instant::now(),
+        }
+    }
+
+    /// Virtual time since the clock was started.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub async fn advance(&self, duration: Duration) {
+        tokio::time::advance(duration).await;
+    }
+}
+
+impl Drop for VirtualClock {
+    fn drop(&mut self) {
+        tokio::time::resume();
+    }
+}
+
+/// Runs futures within the current task, polling them in an order drawn
+/// from a seeded generator. Tasks spawned by the futures are still run by
+/// the runtime.
+pub struct Scheduler {
+    rng: StdRng,
+}
+
+impl Scheduler {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Draws a duration up to `max`, e.g. how long a simulated participant
+    /// takes to compute.
+    pub fn duration(&mut self, max: Duration) -> Duration {
+        max.mul_f64(self.rng.gen_range(0.0..1.0))
+    }
+
+    /// Runs the futures until all completed. Whenever one of them is woken,
+    /// all pending ones are polled once, in a fresh random order.
+    pub async fn run<F>(&mut self, futures: Vec<F>)
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let mut futures: Vec<Option<Pin<Box<F>>>> = futures
+            .into_iter()
+            .map(|future| Some(Box::pin(future)))
+            .collect();
+        let mut order: Vec<usize> = (0..futures.len()).collect();
+        poll_fn(|cx| {
+            order.shuffle(&mut self.rng);
+            for &index in &order {
+                if let Some(future) = &mut futures[index] {
+                    if future.as_mut().poll(cx).is_ready() {
+                        futures[index] = None;
+                    }
+                }
+            }
+            if futures.iter().all(Option::is_none) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+/// A verifier that takes `duration` on the runtime's clock per contribution.
+#[must_use]
+pub fn simulated_verifier(duration: Duration) -> SharedVerifier {
+    Arc::new(Verifier::simulated(duration))
+}
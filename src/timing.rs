@@ -0,0 +1,70 @@
+//! Padding of `/contribute` response times, so that observers of the network
+//! can't tell from the timing how far the verification of a contribution got,
+//! or why it was rejected.
+
+use crate::cache::duration_from_millis;
+use clap::Parser;
+use rand::Rng;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Shortest time, in milliseconds, between receiving a contribution and
+    /// responding to it. Should exceed the time verification takes for the
+    /// largest ceremonies, so that all responses take the same time.
+    #[clap(long, env, value_parser=duration_from_millis, default_value="0")]
+    pub contribute_min_response_time: Duration,
+
+    /// Largest random delay, in milliseconds, added to rejections of
+    /// contributions on top of the padding above.
+    #[clap(long, env, value_parser=duration_from_millis, default_value="0")]
+    pub contribute_error_jitter: Duration,
+}
+
+impl Options {
+    /// Waits until the response to a contribution received at `started` may
+    /// be sent.
+    pub async fn pad(&self, started: Instant, rejected: bool) {
+        let mut until = started + self.contribute_min_response_time;
+        if rejected && !self.contribute_error_jitter.is_zero() {
+            let jitter =
+                rand::thread_rng().gen_range(Duration::ZERO..=self.contribute_error_jitter);
+            until = until.max(Instant::now()) + jitter;
+        }
+        tokio::time::sleep_until(until).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pads_responses() {
+        let options = Options {
+            contribute_min_response_time: Duration::from_secs(2),
+            contribute_error_jitter:      Duration::from_millis(500),
+        };
+        tokio::time::pause();
+
+        let started = Instant::now();
+        tokio::time::advance(Duration::from_millis(300)).await;
+        options.pad(started, false).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+
+        let started = Instant::now();
+        options.pad(started, true).await;
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        assert!(started.elapsed() <= Duration::from_millis(2_500));
+
+        // Slow verifications aren't delayed further, but rejections still are
+        let started = Instant::now();
+        tokio::time::advance(Duration::from_secs(3)).await;
+        options.pad(started, false).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+        options.pad(started, true).await;
+        assert!(started.elapsed() <= Duration::from_millis(3_500));
+    }
+}
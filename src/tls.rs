@@ -0,0 +1,95 @@
+//! Serving the API over HTTPS, for deployments without a reverse proxy.
+
+use axum::Router;
+use clap::Parser;
+use eyre::{eyre, Result as EyreResult, WrapErr};
+use futures::{future, StreamExt};
+use hyper::server::{accept, conn::AddrIncoming, Server};
+use std::{fs::File, future::Future, io::BufReader, net::SocketAddr, path::PathBuf, sync::Arc};
+use tls_listener::TlsListener;
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+use tracing::debug;
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// PEM file with the TLS certificate chain. When set, the server only
+    /// accepts HTTPS connections.
+    #[clap(long, env, requires = "tls_key_file")]
+    pub tls_cert_file: Option<PathBuf>,
+
+    /// PEM file with the private key of the TLS certificate.
+    #[clap(long, env, requires = "tls_cert_file")]
+    pub tls_key_file: Option<PathBuf>,
+}
+
+/// Loads the TLS configuration, or `None` if TLS is not enabled.
+///
+/// # Errors
+///
+/// Returns an error if the certificate or key cannot be read.
+pub fn server_config(options: &Options) -> EyreResult<Option<Arc<ServerConfig>>> {
+    let (cert_file, key_file) = match (&options.tls_cert_file, &options.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        _ => return Ok(None),
+    };
+
+    let mut reader = BufReader::new(
+        File::open(cert_file).wrap_err_with(|| format!("Cannot open {cert_file:?}"))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    let mut reader =
+        BufReader::new(File::open(key_file).wrap_err_with(|| format!("Cannot open {key_file:?}"))?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break PrivateKey(key),
+            Some(_) => {}
+            None => return Err(eyre!("No private key found in {key_file:?}")),
+        }
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .wrap_err("Invalid TLS certificate or key")?;
+    Ok(Some(Arc::new(config)))
+}
+
+/// Serves the app over HTTPS until `shutdown` completes. Failed handshakes
+/// only drop the connection they happened on.
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be bound or the server fails.
+pub async fn serve(
+    addr: &SocketAddr,
+    config: Arc<ServerConfig>,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send,
+) -> EyreResult<()> {
+    let incoming = TlsListener::new(TlsAcceptor::from(config), AddrIncoming::bind(addr)?).filter(
+        |connection| {
+            if let Err(error) = connection {
+                debug!(?error, "TLS handshake failed");
+            }
+            future::ready(connection.is_ok())
+        },
+    );
+    Server::builder(accept::from_stream(incoming))
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! The in-memory transcript. Readers get a consistent snapshot without taking
+//! a lock, while the single writer builds the next version on a copy.
+
+use crate::io::transcript_hash;
+use arc_swap::ArcSwap;
+use kzg_ceremony_crypto::{BatchContribution, BatchTranscript, G2};
+use once_cell::sync::OnceCell;
+use serde_json::value::RawValue;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+
+/// A published transcript, with what is handed out to every contributor
+/// computed at most once.
+struct Version {
+    transcript:   Arc<BatchTranscript>,
+    contribution: OnceCell<ContributionTemplate>,
+    /// The witness entry of each `potPubkey` in any of the ceremonies,
+    /// except for the initial entries.
+    pubkeys:      HashMap<G2, usize>,
+}
+
+impl Version {
+    fn new(transcript: Arc<BatchTranscript>) -> Self {
+        let mut pubkeys = HashMap::new();
+        for ceremony in &transcript.transcripts {
+            for (index, pubkey) in ceremony.witness.pubkeys.iter().enumerate().skip(1) {
+                pubkeys.entry(*pubkey).or_insert(index);
+            }
+        }
+        Self {
+            transcript,
+            contribution: OnceCell::new(),
+            pubkeys,
+        }
+    }
+}
+
+/// The contribution to build on a transcript, already serialized, and the
+/// hash of the transcript.
+#[derive(Clone, Debug)]
+pub struct ContributionTemplate {
+    pub contribution:    Box<RawValue>,
+    pub transcript_hash: String,
+}
+
+pub struct TranscriptStore {
+    current: ArcSwap<Version>,
+    writer:  Mutex<()>,
+}
+
+impl TranscriptStore {
+    pub fn new(transcript: BatchTranscript) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(Version::new(Arc::new(transcript))),
+            writer:  Mutex::new(()),
+        }
+    }
+
+    /// The latest published transcript. Later updates don't affect it.
+    pub fn snapshot(&self) -> Arc<BatchTranscript> {
+        self.current.load().transcript.clone()
+    }
+
+    /// The contribution to build on the latest published transcript. It is
+    /// serialized once per version, so handing it out only copies bytes.
+    ///
+    /// # Panics
+    ///
+    /// * Panics if the contribution can't be serialized.
+    pub fn contribution_template(&self) -> ContributionTemplate {
+        let version = self.current.load();
+        version
+            .contribution
+            .get_or_init(|| ContributionTemplate {
+                contribution:    serde_json::value::to_raw_value(
+                    &version.transcript.contribution(),
+                )
+                .expect("Cannot serialize contribution"),
+                transcript_hash: transcript_hash(&version.transcript),
+            })
+            .clone()
+    }
+
+    /// The first `potPubkey` of `contribution` that already is in a witness
+    /// of the latest transcript, as the index of its ceremony in the
+    /// contribution and the witness entry it appears at. Repeated pubkeys
+    /// mean reused entropy or a replayed contribution.
+    pub fn find_duplicate_pubkey(
+        &self,
+        contribution: &BatchContribution,
+    ) -> Option<(usize, usize)> {
+        let version = self.current.load();
+        contribution
+            .contributions
+            .iter()
+            .enumerate()
+            .find_map(|(ceremony, contribution)| {
+                version
+                    .pubkeys
+                    .get(&contribution.pot_pubkey)
+                    .map(|index| (ceremony, *index))
+            })
+    }
+
+    /// Whether an update is being applied.
+    #[must_use]
+    pub fn is_updating(&self) -> bool {
+        self.writer.try_lock().is_err()
+    }
+
+    /// Applies `update` to a copy of the latest transcript and publishes the
+    /// copy if it succeeds. Updates are applied one at a time, and readers
+    /// keep seeing the previous version until the new one is published.
+    pub async fn update<R, E>(
+        &self,
+        update: impl FnOnce(&mut BatchTranscript) -> Result<R, E> + Send,
+    ) -> Result<R, E> {
+        let _writer = self.writer.lock().await;
+        let mut next = BatchTranscript::clone(&self.current.load().transcript);
+        let result = update(&mut next)?;
+        // A new version starts without a cached template
+        self.current.store(Arc::new(Version::new(Arc::new(next))));
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        tests::{test_transcript, valid_contribution},
+        Engine,
+    };
+    use kzg_ceremony_crypto::signature::identity::Identity;
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn snapshots_are_not_affected_by_updates() {
+        let store = TranscriptStore::new(test_transcript());
+        let before = store.snapshot();
+
+        store
+            .update(|transcript| {
+                transcript.participant_ids.push(Identity::None);
+                Ok::<_, Infallible>(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(*before, test_transcript());
+        assert_eq!(
+            store.snapshot().participant_ids.len(),
+            before.participant_ids.len() + 1
+        );
+
+        let result = store
+            .update(|transcript| {
+                transcript.participant_ids.clear();
+                Err::<(), _>("rejected")
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(
+            store.snapshot().participant_ids.len(),
+            before.participant_ids.len() + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn updates_contribution_template() {
+        let store = TranscriptStore::new(test_transcript());
+        let template = store.contribution_template();
+        assert_eq!(
+            template.contribution.get(),
+            serde_json::to_string(&test_transcript().contribution()).unwrap()
+        );
+        assert_eq!(
+            template.transcript_hash,
+            transcript_hash(&test_transcript())
+        );
+
+        store
+            .update(|transcript| {
+                transcript.participant_ids.push(Identity::None);
+                Ok::<_, Infallible>(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            store.contribution_template().transcript_hash,
+            transcript_hash(&store.snapshot())
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_duplicate_pubkeys() {
+        let mut transcript = test_transcript();
+        let first = valid_contribution(&transcript, 1);
+        transcript
+            .verify_add::<Engine>(first.clone(), Identity::None)
+            .unwrap();
+        let store = TranscriptStore::new(transcript);
+        assert_eq!(store.find_duplicate_pubkey(&first), Some((0, 1)));
+
+        let second = valid_contribution(&store.snapshot(), 2);
+        assert_eq!(store.find_duplicate_pubkey(&second), None);
+        store
+            .update(|transcript| transcript.verify_add::<Engine>(second.clone(), Identity::None))
+            .await
+            .unwrap();
+        assert_eq!(store.find_duplicate_pubkey(&second), Some((0, 2)));
+
+        // Pubkeys of any ceremony count
+        let mut replayed = valid_contribution(&store.snapshot(), 3);
+        replayed.contributions[1].pot_pubkey = first.contributions[0].pot_pubkey;
+        assert_eq!(store.find_duplicate_pubkey(&replayed), Some((1, 1)));
+    }
+}
@@ -9,8 +9,8 @@ use url::{Host, Url};
 
 pub fn parse_url(url: &Url) -> EyreResult<(SocketAddr, &str)> {
     ensure!(
-        url.scheme() == "http",
-        "Only http:// is supported in {}",
+        matches!(url.scheme(), "http" | "https"),
+        "Only http:// and https:// are supported in {}",
         url
     );
     let prefix = url.path();
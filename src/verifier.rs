@@ -0,0 +1,647 @@
+//! Verification of contributions, optionally in separate worker processes so
+//! that the pairing checks can't starve the server's async runtime.
+
+use crate::{Engine, SharedTranscript};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use eyre::{ensure, eyre, Result as EyreResult};
+use kzg_ceremony_crypto::{
+    set_batch_security_bits, set_precompute_budget, BatchContribution, CeremoniesError, ErrorCode,
+    Identity, VerificationTimings, MAX_BATCH_SECURITY_BITS, MIN_BATCH_SECURITY_BITS,
+};
+use once_cell::sync::Lazy;
+use prometheus::{exponential_buckets, register_histogram_vec, HistogramVec};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    env,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout},
+    sync::Mutex,
+};
+use tracing::{error, info};
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Number of worker processes to verify contributions in. Workers are
+    /// started from the same binary and arguments, and talk to the server
+    /// over their standard input and output. With 0, contributions are
+    /// verified in the server process.
+    #[clap(long, env, default_value = "0")]
+    pub verification_workers: usize,
+
+    /// Bytes of memory the verification engines may each keep in
+    /// precomputed tables, which are reused between contributions. With 0,
+    /// nothing is precomputed. Shared by all ceremonies of the process.
+    #[clap(long, env, default_value = "1048576")]
+    pub precompute_memory_bytes: usize,
+
+    /// Bits of the random factors that the pairing checks of a contribution
+    /// are batched with. Invalid contributions pass with probability at most
+    /// 2^-bits, and fewer bits verify faster. Between 64 and 255, and shared
+    /// by all ceremonies of the process.
+    #[clap(long, env, value_parser = batch_security_bits_from_str, default_value = "128")]
+    pub batch_security_bits: usize,
+
+    /// Verify the transcript from scratch after reading it on startup, and
+    /// refuse to start if it is invalid. Takes a while for large transcripts.
+    #[clap(long, env, default_value = "false")]
+    pub verify_on_start: bool,
+}
+
+fn batch_security_bits_from_str(bits: &str) -> EyreResult<usize> {
+    let bits = bits.parse()?;
+    ensure!(
+        (MIN_BATCH_SECURITY_BITS..=MAX_BATCH_SECURITY_BITS).contains(&bits),
+        "Must be between {MIN_BATCH_SECURITY_BITS} and {MAX_BATCH_SECURITY_BITS}"
+    );
+    Ok(bits)
+}
+
+/// Sets up the verification engines. They are shared by all ceremonies of the
+/// process, so this only applies the options of the main ceremony.
+pub fn init(options: &Options) {
+    set_precompute_budget(options.precompute_memory_bytes);
+    set_batch_security_bits(options.batch_security_bits);
+}
+
+/// A contribution to verify, together with the contribution it builds on.
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    previous:     BatchContribution,
+    contribution: BatchContribution,
+}
+
+type Response = Result<Vec<VerificationTimings>, CeremoniesError>;
+
+pub(crate) const SEQUENCER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Number of verification results kept for [`Verifier::recent_results`].
+const RECENT_RESULTS: usize = 20;
+
+/// Verification times of accepted contributions, labelled with the ceremony
+/// size as `<g1 powers>x<g2 powers>` and the stage: `subgroup_checks` and
+/// `pairing_checks` per ceremony, and `add` for adding the whole contribution
+/// to the transcript, with size `all`.
+static VERIFICATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "verification_seconds",
+        "Time spent verifying accepted contributions, by ceremony size and stage.",
+        &["size", "stage"],
+        exponential_buckets(0.001, 2.0, 16).expect("Buckets are valid")
+    )
+    .expect("Metric can be registered")
+});
+
+/// How long verifying the ceremony of one size took.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct CeremonyTimings {
+    pub num_g1_powers:      usize,
+    pub num_g2_powers:      usize,
+    pub subgroup_checks_ms: u64,
+    pub pairing_checks_ms:  u64,
+}
+
+/// How long verifying an accepted contribution took. Recorded in the audit
+/// log as is, so fields may be added but not renamed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ContributionTimings {
+    pub ceremonies: Vec<CeremonyTimings>,
+    /// Adding the contribution to the transcript, including pruning its
+    /// signatures.
+    pub add_ms:     u64,
+}
+
+impl ContributionTimings {
+    fn new(sizes: &[(usize, usize)], timings: &[VerificationTimings], add: Duration) -> Self {
+        Self {
+            ceremonies: sizes
+                .iter()
+                .zip(timings)
+                .map(
+                    |(&(num_g1_powers, num_g2_powers), timings)| CeremonyTimings {
+                        num_g1_powers,
+                        num_g2_powers,
+                        subgroup_checks_ms: millis(timings.subgroup_checks),
+                        pairing_checks_ms: millis(timings.pairing_checks),
+                    },
+                )
+                .collect(),
+            add_ms:     millis(add),
+        }
+    }
+
+    fn record_metrics(&self) {
+        for ceremony in &self.ceremonies {
+            let size = format!("{}x{}", ceremony.num_g1_powers, ceremony.num_g2_powers);
+            for (stage, ms) in [
+                ("subgroup_checks", ceremony.subgroup_checks_ms),
+                ("pairing_checks", ceremony.pairing_checks_ms),
+            ] {
+                VERIFICATION_SECONDS
+                    .with_label_values(&[&size, stage])
+                    .observe(seconds(ms));
+            }
+        }
+        VERIFICATION_SECONDS
+            .with_label_values(&["all", "add"])
+            .observe(seconds(self.add_ms));
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[allow(clippy::cast_precision_loss)] // Precise enough for times in ms
+fn seconds(ms: u64) -> f64 {
+    ms as f64 / 1000.0
+}
+
+/// The outcome of verifying a contribution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationResult {
+    pub finished_at: DateTime<Utc>,
+    pub duration:    Duration,
+    /// Error code of a rejected contribution.
+    pub error:       Option<String>,
+}
+
+struct Worker {
+    _child: Child,
+    stdin:  ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+    fn spawn() -> EyreResult<Self> {
+        let mut child = tokio::process::Command::new(env::current_exe()?)
+            .args(env::args_os().skip(1))
+            .arg("verify-worker")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("Worker has no stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| eyre!("Worker has no stdout"))?;
+        info!(pid = ?child.id(), "Started verification worker");
+        Ok(Self {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+}
+
+/// A contribution that passed verification, to be added to the transcript it
+/// was verified against.
+pub struct VerifiedContribution {
+    contribution: BatchContribution,
+    identity:     Identity,
+    received_at:  u64,
+    verified_at:  u64,
+    sizes:        Vec<(usize, usize)>,
+    timings:      Vec<VerificationTimings>,
+}
+
+impl VerifiedContribution {
+    #[must_use]
+    pub const fn contribution(&self) -> &BatchContribution {
+        &self.contribution
+    }
+
+    /// Adds the contribution to the transcript, recording when it was
+    /// received and verified in the transcript metadata.
+    pub async fn add_to(
+        self,
+        transcript: &SharedTranscript,
+    ) -> Result<ContributionTimings, CeremoniesError> {
+        let Self {
+            contribution,
+            identity,
+            received_at,
+            verified_at,
+            sizes,
+            timings,
+        } = self;
+        let entropy_attestation = contribution.entropy_attestation.clone();
+        let started = Instant::now();
+        transcript
+            .update(|transcript| {
+                transcript.add::<Engine>(contribution, identity);
+                transcript.record_metadata::<Engine>(
+                    SEQUENCER_VERSION,
+                    received_at,
+                    verified_at,
+                    entropy_attestation,
+                );
+                Ok::<_, CeremoniesError>(())
+            })
+            .await?;
+        let timings = ContributionTimings::new(&sizes, &timings, started.elapsed());
+        timings.record_metrics();
+        Ok(timings)
+    }
+}
+
+pub struct Verifier {
+    workers:   Vec<Mutex<Option<Worker>>>,
+    next:      AtomicUsize,
+    /// Number of contributions being verified.
+    in_flight: AtomicUsize,
+    recent:    Mutex<VecDeque<VerificationResult>>,
+    /// Time verification takes on the runtime's clock, see
+    /// [`Verifier::simulated`].
+    simulated: Option<Duration>,
+}
+
+pub type SharedVerifier = Arc<Verifier>;
+
+/// Counts a verification as in flight until dropped, also when it panics.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Verifier {
+    /// Starts the configured number of workers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a worker process can't be started.
+    pub fn new(options: &Options) -> EyreResult<Self> {
+        let workers = (0..options.verification_workers)
+            .map(|_| Worker::spawn().map(|worker| Mutex::new(Some(worker))))
+            .collect::<EyreResult<_>>()?;
+        Ok(Self {
+            workers,
+            next: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            recent: Mutex::default(),
+            simulated: None,
+        })
+    }
+
+    /// A verifier that takes `duration` on the runtime's clock to verify a
+    /// contribution, and verifies on the runtime itself. With a paused clock
+    /// verification takes no time that timers could race against, so tests
+    /// don't depend on how fast the machine is.
+    #[cfg(any(test, feature = "deterministic"))]
+    #[must_use]
+    pub fn simulated(duration: Duration) -> Self {
+        Self {
+            workers:   Vec::new(),
+            next:      AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            recent:    Mutex::default(),
+            simulated: Some(duration),
+        }
+    }
+
+    /// Verifies a contribution and adds it to the transcript, recording when
+    /// it was received and verified in the transcript metadata.
+    ///
+    /// The ceremonies of a contribution are verified concurrently, and never
+    /// on the async runtime. The transcript is not locked during
+    /// verification. This relies on there being only one contributor at a
+    /// time.
+    ///
+    /// The timings of accepted contributions are recorded in the
+    /// `verification_seconds` metric, and returned.
+    pub async fn verify_add(
+        &self,
+        transcript: &SharedTranscript,
+        contribution: BatchContribution,
+        identity: Identity,
+    ) -> Result<ContributionTimings, CeremoniesError> {
+        self.verify(transcript, contribution, identity)
+            .await?
+            .add_to(transcript)
+            .await
+    }
+
+    /// Verifies a contribution without adding it to the transcript, see
+    /// [`Verifier::verify_add`]. The transcript must not change until the
+    /// contribution is added.
+    pub async fn verify(
+        &self,
+        transcript: &SharedTranscript,
+        contribution: BatchContribution,
+        identity: Identity,
+    ) -> Result<VerifiedContribution, CeremoniesError> {
+        let started = Instant::now();
+        let result = {
+            let _in_flight = InFlight::enter(&self.in_flight);
+            self.verify_contribution(transcript, contribution, identity)
+                .await
+        };
+
+        let mut recent = self.recent.lock().await;
+        if recent.len() == RECENT_RESULTS {
+            recent.pop_back();
+        }
+        recent.push_front(VerificationResult {
+            finished_at: Utc::now(),
+            duration:    started.elapsed(),
+            error:       result.as_ref().err().map(ErrorCode::to_error_code),
+        });
+        result
+    }
+
+    /// Whether every worker, or the server itself without workers, is busy
+    /// verifying.
+    #[must_use]
+    pub fn is_saturated(&self) -> bool {
+        self.in_flight.load(Ordering::Relaxed) >= self.workers.len().max(1)
+    }
+
+    /// The latest verification results, newest first.
+    pub async fn recent_results(&self) -> Vec<VerificationResult> {
+        self.recent.lock().await.iter().cloned().collect()
+    }
+
+    async fn verify_contribution(
+        &self,
+        transcript: &SharedTranscript,
+        contribution: BatchContribution,
+        identity: Identity,
+    ) -> Result<VerifiedContribution, CeremoniesError> {
+        let received_at = unix_timestamp();
+        let sizes = contribution
+            .contributions
+            .iter()
+            .map(|contribution| (contribution.powers.g1.len(), contribution.powers.g2.len()))
+            .collect::<Vec<_>>();
+        let (contribution, timings) = if let Some(duration) = self.simulated {
+            tokio::time::sleep(duration).await;
+            let timings = transcript
+                .snapshot()
+                .verify_timed::<Engine>(&contribution)?;
+            (contribution, timings)
+        } else if self.workers.is_empty() {
+            let snapshot = transcript.snapshot();
+            tokio::task::spawn_blocking(move || {
+                snapshot
+                    .verify_timed::<Engine>(&contribution)
+                    .map(|timings| (contribution, timings))
+            })
+            .await
+            .expect("Verification panicked")?
+        } else {
+            self.verify_with_workers(transcript, contribution).await?
+        };
+        Ok(VerifiedContribution {
+            contribution,
+            identity,
+            received_at,
+            verified_at: unix_timestamp(),
+            sizes,
+            timings,
+        })
+    }
+
+    /// Verifies a contribution in a worker process, falling back to local
+    /// verification if the worker fails.
+    async fn verify_with_workers(
+        &self,
+        transcript: &SharedTranscript,
+        contribution: BatchContribution,
+    ) -> Result<(BatchContribution, Vec<VerificationTimings>), CeremoniesError> {
+        let request = Request {
+            previous: transcript.snapshot().contribution(),
+            contribution,
+        };
+        let (request, response) = match self.verify_remote(&request).await {
+            Ok(response) => (request, response),
+            Err(error) => {
+                // Don't reject the participant because of a broken worker.
+                error!(?error, "Verification worker failed, verifying locally");
+                tokio::task::spawn_blocking(move || {
+                    let response = verify(&request);
+                    (request, response)
+                })
+                .await
+                .expect("Verification panicked")
+            }
+        };
+        response.map(|timings| (request.contribution, timings))
+    }
+
+    async fn verify_remote(&self, request: &Request) -> EyreResult<Response> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+        let mut slot = self.workers[index].lock().await;
+        if slot.is_none() {
+            *slot = Some(Worker::spawn()?);
+        }
+        let worker = slot.as_mut().expect("Worker was just started");
+        let response = exchange(&mut worker.stdout, &mut worker.stdin, request).await;
+        if response.is_err() {
+            // Replace the worker on next use.
+            *slot = None;
+        }
+        response
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    u64::try_from(Utc::now().timestamp()).unwrap_or_default()
+}
+
+fn verify(request: &Request) -> Response {
+    request
+        .contribution
+        .verify_after_timed::<Engine>(&request.previous)
+}
+
+/// Sends a request to a worker and waits for its response. Messages are
+/// JSON encoded, one per line.
+async fn exchange<R, W>(reader: &mut R, writer: &mut W, request: &Request) -> EyreResult<Response>
+where
+    R: AsyncBufRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut message = serde_json::to_vec(request)?;
+    message.push(b'\n');
+    writer.write_all(&message).await?;
+    writer.flush().await?;
+
+    let mut line = String::new();
+    ensure!(
+        reader.read_line(&mut line).await? > 0,
+        "Verification worker exited"
+    );
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Runs a verification worker, answering requests until the input is closed.
+///
+/// # Errors
+///
+/// Returns an error if a request can't be read or answered.
+pub async fn serve<R, W>(reader: R, mut writer: W) -> EyreResult<()>
+where
+    R: AsyncBufRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        let request: Request = serde_json::from_str(&line)?;
+        let response = tokio::task::spawn_blocking(move || verify(&request)).await?;
+        let mut message = serde_json::to_vec(&response)?;
+        message.push(b'\n');
+        writer.write_all(&message).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::VirtualClock,
+        tests::{invalid_contribution, test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+    };
+    use kzg_ceremony_crypto::CeremonyError;
+    use tokio::io::{duplex, split};
+
+    #[test]
+    fn rejects_weak_batch_security() {
+        let parse =
+            |bits: &str| Options::try_parse_from(["verifier", "--batch-security-bits", bits]);
+        assert_eq!(parse("64").unwrap().batch_security_bits, 64);
+        assert_eq!(parse("255").unwrap().batch_security_bits, 255);
+        assert!(parse("1").is_err());
+        assert!(parse("63").is_err());
+        assert!(parse("256").is_err());
+    }
+
+    #[tokio::test]
+    async fn verifies_over_ipc() {
+        let (client, server) = duplex(1024);
+        let (server_reader, server_writer) = split(server);
+        tokio::spawn(serve(BufReader::new(server_reader), server_writer));
+        let (client_reader, mut client_writer) = split(client);
+        let mut client_reader = BufReader::new(client_reader);
+
+        let transcript = test_transcript();
+        let valid = Request {
+            previous:     transcript.contribution(),
+            contribution: valid_contribution(&transcript, 1),
+        };
+        let invalid = Request {
+            previous:     transcript.contribution(),
+            contribution: invalid_contribution(&transcript, 2),
+        };
+
+        let response = exchange(&mut client_reader, &mut client_writer, &valid).await;
+        assert_eq!(
+            response.unwrap().unwrap().len(),
+            transcript.transcripts.len()
+        );
+        let response = exchange(&mut client_reader, &mut client_writer, &invalid).await;
+        assert_eq!(
+            response.unwrap(),
+            Err(CeremoniesError::InvalidCeremony(
+                0,
+                CeremonyError::ZeroPubkey
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn verifies_in_process() {
+        let verifier = Verifier::new(&Options {
+            verification_workers:    0,
+            precompute_memory_bytes: 0,
+            batch_security_bits:     64,
+            verify_on_start:         false,
+        })
+        .unwrap();
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+
+        let invalid = invalid_contribution(&transcript.snapshot(), 1);
+        let result = verifier
+            .verify_add(&transcript, invalid, Identity::None)
+            .await;
+        assert!(result.is_err());
+        assert_eq!(transcript.snapshot().num_participants(), 0);
+
+        let valid = valid_contribution(&transcript.snapshot(), 2);
+        let timings = verifier
+            .verify_add(&transcript, valid, Identity::None)
+            .await
+            .unwrap();
+        assert_eq!(transcript.snapshot().num_participants(), 1);
+        let sizes = transcript
+            .snapshot()
+            .transcripts
+            .iter()
+            .map(|transcript| (transcript.powers.g1.len(), transcript.powers.g2.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timings
+                .ceremonies
+                .iter()
+                .map(|ceremony| (ceremony.num_g1_powers, ceremony.num_g2_powers))
+                .collect::<Vec<_>>(),
+            sizes
+        );
+        let histogram = VERIFICATION_SECONDS.with_label_values(&["all", "add"]);
+        assert!(histogram.get_sample_count() >= 1);
+
+        let recent = verifier.recent_results().await;
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].error, None);
+        assert!(recent[1].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn reports_saturation() {
+        let _clock = VirtualClock::start();
+        let verifier = Arc::new(Verifier::simulated(Duration::from_secs(10)));
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+        assert!(!verifier.is_saturated());
+
+        let valid = valid_contribution(&transcript.snapshot(), 1);
+        let verifying = tokio::spawn({
+            let verifier = verifier.clone();
+            let transcript = transcript.clone();
+            async move {
+                verifier
+                    .verify_add(&transcript, valid, Identity::None)
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        assert!(verifier.is_saturated());
+        verifying.await.unwrap().unwrap();
+        assert!(!verifier.is_saturated());
+    }
+}
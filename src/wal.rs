@@ -0,0 +1,204 @@
+//! Write-ahead log of operations that change state in more than one place.
+//!
+//! Handing out the contribution slot, applying a contribution and issuing a
+//! session each update the lobby in memory as well as storage. Each is logged
+//! before it starts and removed once it completed, so that after a crash the
+//! operations that were cut short can be brought to a consistent end on
+//! startup, instead of leaving contributions that are neither finished nor
+//! expired.
+
+use crate::{
+    guard::Mark,
+    storage::{PersistentStorage, StorageError},
+    SharedTranscript,
+};
+use serde_json::{json, Value};
+use strum::{EnumString, IntoStaticStr};
+use tracing::{error, info, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoStaticStr, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Operation {
+    /// The contribution slot is handed to a participant, until they
+    /// contribute, abort or run out of time.
+    SlotGrant,
+    /// A contribution is verified and added to the transcript. The payload
+    /// holds the participant number it will get.
+    ContributionApply,
+    /// A session is issued after signing in.
+    SessionIssue,
+}
+
+impl Operation {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.into()
+    }
+}
+
+/// Logs an operation on behalf of `uid` before it starts.
+///
+/// # Errors
+///
+/// Returns an error if the entry could not be stored, in which case the
+/// operation should not go ahead.
+pub async fn log(
+    storage: &PersistentStorage,
+    operation: Operation,
+    uid: &str,
+    payload: &Value,
+) -> Result<(), StorageError> {
+    storage
+        .wal_append(operation.as_str(), uid, &payload.to_string())
+        .await
+}
+
+/// Marks an operation as completed. The operation already happened, so
+/// failing to do so is only logged; recovery treats the entry as cut short.
+pub async fn complete(storage: &PersistentStorage, operation: Operation, uid: &str) {
+    if let Err(error) = storage.wal_remove(operation.as_str(), uid).await {
+        error!(
+            ?error,
+            operation = operation.as_str(),
+            "Could not complete logged operation"
+        );
+    }
+}
+
+/// Brings the operations that did not complete before the sequencer stopped
+/// to an end, and clears the log. Contributions that made it into the
+/// transcript are finished, other slots are expired. Sessions live in memory,
+/// so participants whose sign-in was cut short only have to sign in again.
+/// Returns how many operations were recovered.
+///
+/// # Errors
+///
+/// Returns an error if storage fails, in which case the log is kept.
+pub async fn recover(
+    storage: &PersistentStorage,
+    transcript: &SharedTranscript,
+) -> Result<usize, StorageError> {
+    let mut entries = storage.wal_entries().await?;
+    // Contributions go first, so that their slots aren't expired before they
+    // are finished.
+    entries.sort_by_key(|(operation, ..)| operation != Operation::ContributionApply.as_str());
+    let participants = transcript.snapshot().num_participants();
+    for (operation, uid, payload) in &entries {
+        match operation.parse::<Operation>() {
+            Ok(Operation::ContributionApply) => {
+                let participant = serde_json::from_str::<Value>(payload)
+                    .ok()
+                    .and_then(|payload| payload["participant"].as_u64())
+                    .and_then(|participant| usize::try_from(participant).ok());
+                if participant.map_or(false, |participant| participant <= participants) {
+                    info!(%uid, ?participant, "Finishing contribution that is in the transcript");
+                    storage.finish_contribution(uid).await?;
+                    storage
+                        .mark_identity(uid, Mark::Contributed.as_str())
+                        .await?;
+                } else if storage.expire_unfinished_contribution(uid).await? {
+                    info!(%uid, "Expired contribution that was cut short");
+                    storage.mark_identity(uid, Mark::Aborted.as_str()).await?;
+                }
+            }
+            Ok(Operation::SlotGrant) => {
+                // The participant lost the slot to the restart, which doesn't
+                // count as a failed attempt.
+                if storage.expire_unfinished_contribution(uid).await? {
+                    info!(%uid, "Expired slot that was held when the sequencer stopped");
+                    storage.mark_identity(uid, Mark::Aborted.as_str()).await?;
+                }
+            }
+            Ok(Operation::SessionIssue) => {
+                info!(%uid, "Discarding session that was being issued");
+            }
+            Err(_) => warn!(%operation, %uid, "Unknown logged operation"),
+        }
+    }
+    storage.wal_clear().await?;
+    Ok(entries.len())
+}
+
+/// The payload of [`Operation::ContributionApply`].
+#[must_use]
+pub fn contribution_payload(participant: usize) -> Value {
+    json!({ "participant": participant })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::storage_client,
+        test_util::test_options,
+        tests::{test_transcript, valid_contribution},
+        transcript::TranscriptStore,
+        Engine,
+    };
+    use kzg_ceremony_crypto::Identity;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn recovers_interrupted_operations() {
+        let options = test_options();
+        let storage = storage_client(&options.storage).await.unwrap();
+        let transcript: SharedTranscript = Arc::new(TranscriptStore::new(test_transcript()));
+        let contribution = valid_contribution(&transcript.snapshot(), 1);
+        transcript
+            .update(|transcript| transcript.verify_add::<Engine>(contribution, Identity::None))
+            .await
+            .unwrap();
+
+        for uid in ["applied", "verifying", "waiting", "done"] {
+            storage.insert_contributor(uid).await.unwrap();
+            log(&storage, Operation::SlotGrant, uid, &json!({}))
+                .await
+                .unwrap();
+        }
+        // Crashed after adding the contribution, before finishing it
+        log(
+            &storage,
+            Operation::ContributionApply,
+            "applied",
+            &contribution_payload(1),
+        )
+        .await
+        .unwrap();
+        // Crashed while verifying
+        log(
+            &storage,
+            Operation::ContributionApply,
+            "verifying",
+            &contribution_payload(2),
+        )
+        .await
+        .unwrap();
+        // Completed normally
+        storage.finish_contribution("done").await.unwrap();
+        complete(&storage, Operation::SlotGrant, "done").await;
+        log(&storage, Operation::SessionIssue, "signing_in", &json!({}))
+            .await
+            .unwrap();
+
+        assert_eq!(recover(&storage, &transcript).await.unwrap(), 6);
+        assert!(storage.wal_entries().await.unwrap().is_empty());
+        let contributors = storage.dump().await.unwrap().contributors;
+        let row = |uid: &str| contributors.iter().find(|row| row.uid == uid).unwrap();
+        assert!(row("applied").finished_at.is_some());
+        assert!(row("applied").expired_at.is_none());
+        assert!(row("verifying").finished_at.is_none());
+        assert!(row("verifying").expired_at.is_some());
+        assert!(row("waiting").expired_at.is_some());
+        assert!(row("done").finished_at.is_some());
+        assert!(row("done").expired_at.is_none());
+        assert_eq!(storage.failed_attempts("waiting").await.unwrap().count, 0);
+        assert_eq!(
+            storage.identity_mark("applied").await.unwrap().as_deref(),
+            Some("contributed")
+        );
+        assert_eq!(
+            storage.identity_mark("verifying").await.unwrap().as_deref(),
+            Some("aborted")
+        );
+    }
+}
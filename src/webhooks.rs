@@ -0,0 +1,123 @@
+//! Optional webhooks pushing ceremony events, like accepted contributions, to
+//! external systems such as chat bots or the ceremony explorer.
+
+use crate::util::Secret;
+use chrono::Utc;
+use clap::Parser;
+use hmac::{Hmac, Mac};
+use http::header::CONTENT_TYPE;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::runtime::Handle;
+use tracing::warn;
+use url::Url;
+
+/// Header holding the signature of a webhook payload.
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+static WEBHOOKS: OnceCell<Webhooks> = OnceCell::new();
+
+#[derive(Clone, Debug, PartialEq, Eq, Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Webhook urls that ceremony events are sent to as JSON `POST` requests,
+    /// separated by commas. Event webhooks are disabled when not set.
+    #[clap(long, env, value_delimiter = ',', requires = "event_webhook_secret")]
+    pub event_webhook_urls: Vec<Url>,
+
+    /// Secret that event payloads are signed with. The signature is sent in
+    /// the `X-Signature-256` header as `sha256=` followed by the hex encoded
+    /// HMAC-SHA256 of the request body.
+    #[clap(long, env)]
+    pub event_webhook_secret: Option<Secret>,
+}
+
+#[derive(Clone, Debug)]
+struct Webhooks {
+    client: reqwest::Client,
+    urls:   Vec<Url>,
+    secret: Option<Secret>,
+}
+
+/// Installs the global event webhooks. Does nothing if no webhook is
+/// configured, or if webhooks are already installed.
+pub fn init(options: &Options, client: reqwest::Client) {
+    if options.event_webhook_urls.is_empty() {
+        return;
+    }
+    let _ = WEBHOOKS.set(Webhooks {
+        client,
+        urls: options.event_webhook_urls.clone(),
+        secret: options.event_webhook_secret.clone(),
+    });
+}
+
+/// Sends an event to the webhooks in the background. Never fails the caller.
+pub fn notify(event: &'static str, data: Value) {
+    if WEBHOOKS.get().is_none() {
+        return;
+    }
+    // Events can only be sent from within the runtime.
+    if let Ok(handle) = Handle::try_current() {
+        handle.spawn(send(event, data));
+    }
+}
+
+/// Sends an event to the webhooks and waits until it is delivered, for
+/// callers that exit right after. Failed deliveries are logged.
+pub async fn send(event: &'static str, data: Value) {
+    let webhooks = match WEBHOOKS.get() {
+        Some(webhooks) => webhooks,
+        None => return,
+    };
+    let body = json!({
+        "event": event,
+        "data": data,
+        "timestamp": Utc::now().to_rfc3339(),
+    })
+    .to_string();
+    let signature = webhooks
+        .secret
+        .as_ref()
+        .map(|secret| sign(secret.get_secret(), &body));
+
+    for url in &webhooks.urls {
+        let mut request = webhooks
+            .client
+            .post(url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        let result = request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(error) = result {
+            warn!(?error, %url, event, "Could not deliver event to webhook");
+        }
+    }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -13,12 +13,23 @@ use url::Url;
 /// This function acts both as a test and a utility. This way, we'll test the
 /// behavior in a variety of different app states.
 pub async fn get_and_validate_csrf_token(harness: &Harness, redirect_url: Option<&str>) -> String {
+    let query = redirect_url
+        .map(|redirect| ("redirect_to", redirect))
+        .into_iter()
+        .collect::<Vec<_>>();
+    get_and_validate_csrf_token_with_query(harness, &query).await
+}
+
+pub async fn get_and_validate_csrf_token_with_query(
+    harness: &Harness,
+    query: &[(&str, &str)],
+) -> String {
     let client = reqwest::Client::new();
 
     let mut url = harness.app_path("auth/request_link");
-    redirect_url.into_iter().for_each(|redirect| {
-        url.query_pairs_mut().append_pair("redirect_to", redirect);
-    });
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
 
     let response = client
         .get(url)
@@ -137,11 +148,13 @@ pub async fn request_try_contribute(
         .unwrap()
 }
 
+/// Requests the contribution slot and returns its slot id together with the
+/// contribution to extend.
 pub async fn try_contribute(
     harness: &Harness,
     http_client: &reqwest::Client,
     session_id: &str,
-) -> BatchContribution {
+) -> (String, BatchContribution) {
     let response = request_try_contribute(harness, http_client, session_id).await;
 
     assert_eq!(
@@ -150,21 +163,36 @@ pub async fn try_contribute(
         "Response must be successful"
     );
 
-    response
-        .json::<BatchContribution>()
+    let response_json = response
+        .json::<Value>()
         .await
-        .expect("Successful response must be a contribution")
+        .expect("Successful response must be valid JSON");
+    parse_try_contribute_response(response_json).expect("Successful response must be a slot")
+}
+
+/// Extracts the slot id and contribution from a successful `try_contribute`
+/// response.
+pub fn parse_try_contribute_response(mut response: Value) -> Option<(String, BatchContribution)> {
+    let slot_id = response
+        .get("reservation")?
+        .get("slot_id")?
+        .as_str()?
+        .to_string();
+    let contribution = serde_json::from_value(response.get_mut("contribution")?.take()).ok()?;
+    Some((slot_id, contribution))
 }
 
 pub async fn request_contribute(
     harness: &Harness,
     http_client: &reqwest::Client,
     session_id: &str,
+    slot_id: &str,
     contribution: &BatchContribution,
 ) -> reqwest::Response {
     http_client
         .post(harness.options.server.join("contribute").unwrap())
         .header("Authorization", format!("Bearer {session_id}"))
+        .query(&[("slot_id", slot_id)])
         .json(contribution)
         .send()
         .await
@@ -191,10 +219,12 @@ pub async fn contribute_successfully(
     harness: &Harness,
     http_client: &reqwest::Client,
     session_id: &str,
+    slot_id: &str,
     contribution: &BatchContribution,
     user_id: &str,
 ) {
-    let response = request_contribute(harness, http_client, session_id, contribution).await;
+    let response =
+        request_contribute(harness, http_client, session_id, slot_id, contribution).await;
 
     assert_eq!(
         response.status(),
@@ -239,6 +269,13 @@ pub async fn contribute_successfully(
         user_id
     );
 
+    assert_eq!(
+        response_json
+            .pointer("/eip712/typed_data/message")
+            .expect("must contain the EIP-712 receipt"),
+        &receipt_contents
+    );
+
     let witness: Vec<G2> = serde_json::from_value(
         receipt_contents
             .get("witness")
@@ -171,11 +171,31 @@ impl Builder {
         self
     }
 
+    pub fn set_pseudonym_salt(mut self, salt: &str) -> Self {
+        self.options.pseudonym.pseudonym_salt = Some(salt.parse().unwrap());
+        self
+    }
+
     pub fn allow_multi_contribution(mut self) -> Self {
         self.options.multi_contribution = true;
         self
     }
 
+    pub fn allow_unbound_contributions(mut self) -> Self {
+        self.options.allow_unbound_contributions = true;
+        self
+    }
+
+    pub fn set_abuse_checks(mut self, checks: &str) -> Self {
+        self.options.checks.abuse_checks = checks.parse().unwrap();
+        self
+    }
+
+    pub fn set_min_client_version(mut self, version: &str) -> Self {
+        self.options.client_version.min_client_version = Some(version.parse().unwrap());
+        self
+    }
+
     #[allow(dead_code)]
     pub fn set_transcript_file(mut self, path: PathBuf) -> Self {
         self.options.transcript_file = path;
@@ -15,17 +15,18 @@ async fn await_contribution_slot(
     harness: &Harness,
     client: &reqwest::Client,
     session_id: &str,
-) -> BatchContribution {
+) -> (String, BatchContribution) {
     loop {
         let try_contribute_response =
             actions::request_try_contribute(harness, client, session_id).await;
         assert_eq!(try_contribute_response.status(), StatusCode::OK);
-        let maybe_contribution = try_contribute_response
-            .json::<BatchContribution>()
+        let maybe_slot = try_contribute_response
+            .json::<serde_json::Value>()
             .await
-            .ok();
-        if let Some(contrib) = maybe_contribution {
-            return contrib;
+            .ok()
+            .and_then(actions::parse_try_contribute_response);
+        if let Some(slot) = maybe_slot {
+            return slot;
         }
 
         tokio::time::sleep(
@@ -42,7 +43,7 @@ pub async fn well_behaved(
     user: TestUser,
 ) -> PostConditionCheck {
     let session_id = actions::login(harness, client, &user).await;
-    let mut contribution = await_contribution_slot(harness, client, &session_id).await;
+    let (slot_id, mut contribution) = await_contribution_slot(harness, client, &session_id).await;
     contribution
         .add_entropy::<BLST>(
             &entropy_from_str(&user.identity().to_string()),
@@ -63,6 +64,7 @@ pub async fn well_behaved(
         harness,
         client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -85,7 +87,7 @@ pub async fn wrong_ecdsa(
     user: TestUser,
 ) -> PostConditionCheck {
     let session_id = actions::login(harness, client, &user).await;
-    let mut contribution = await_contribution_slot(harness, client, &session_id).await;
+    let (slot_id, mut contribution) = await_contribution_slot(harness, client, &session_id).await;
     contribution
         .add_entropy::<DefaultEngine>(
             &entropy_from_str(&user.identity().to_string()),
@@ -105,6 +107,7 @@ pub async fn wrong_ecdsa(
         harness,
         client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -121,7 +124,7 @@ pub async fn wrong_bls(
     user: TestUser,
 ) -> PostConditionCheck {
     let session_id = actions::login(harness, client, &user).await;
-    let mut contribution = await_contribution_slot(harness, client, &session_id).await;
+    let (slot_id, mut contribution) = await_contribution_slot(harness, client, &session_id).await;
     contribution
         .add_entropy::<DefaultEngine>(
             &entropy_from_str(&user.identity().to_string()),
@@ -137,6 +140,7 @@ pub async fn wrong_bls(
         harness,
         client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -153,7 +157,7 @@ pub async fn slow_compute(
     user: TestUser,
 ) -> PostConditionCheck {
     let session_id = actions::login(harness, client, &user).await;
-    let mut contribution = await_contribution_slot(harness, client, &session_id).await;
+    let (slot_id, mut contribution) = await_contribution_slot(harness, client, &session_id).await;
     tokio::time::sleep(harness.options.lobby.compute_deadline).await;
     contribution
         .add_entropy::<Arkworks>(
@@ -161,7 +165,8 @@ pub async fn slow_compute(
             &user.identity(),
         )
         .expect("Adding entropy must be possible");
-    let response = actions::request_contribute(harness, client, &session_id, &contribution).await;
+    let response =
+        actions::request_contribute(harness, client, &session_id, &slot_id, &contribution).await;
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     Box::new(|_| {})
 }
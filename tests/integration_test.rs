@@ -13,7 +13,7 @@ use ethers_core::types::Address;
 use ethers_signers::{LocalWallet, Signer};
 use http::StatusCode;
 use kzg_ceremony_crypto::{
-    signature::{BlsSignature, ContributionTypedData, EcdsaSignature},
+    signature::{identity::Identity, BlsSignature, ContributionTypedData, EcdsaSignature},
     Arkworks, DefaultEngine, G1,
 };
 use rand::thread_rng;
@@ -44,6 +44,52 @@ async fn test_eth_auth_happy_path() {
     actions::login(&harness, &http_client, &user).await;
 }
 
+#[tokio::test]
+async fn test_eth_auth_rejects_replayed_nonce() {
+    let harness = run_test_harness().await;
+    let http_client = reqwest::Client::new();
+    let user = harness.create_eth_user().await;
+    let csrf = actions::get_and_validate_csrf_token(&harness, None).await;
+
+    let response = actions::request_auth_callback(&harness, &http_client, &user, &csrf).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = actions::request_auth_callback(&harness, &http_client, &user, &csrf).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["code"], "AuthErrorPayload::InvalidNonce");
+
+    // The nonce is bound to the state it was issued in
+    let csrf = actions::get_and_validate_csrf_token(&harness, None).await;
+    let mut state: serde_json::Value = serde_json::from_slice(
+        &base64::decode_config(&csrf, base64::URL_SAFE_NO_PAD).unwrap(),
+    )
+    .unwrap();
+    state["invite_code"] = "altered".into();
+    let altered = base64::encode_config(state.to_string(), base64::URL_SAFE_NO_PAD);
+    let response = actions::request_auth_callback(&harness, &http_client, &user, &altered).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["code"], "AuthErrorPayload::InvalidNonce");
+}
+
+#[tokio::test]
+async fn test_eth_auth_completes_in_another_client() {
+    let harness = run_test_harness().await;
+    let user = harness.create_eth_user().await;
+    let csrf = actions::get_and_validate_csrf_token(&harness, None).await;
+
+    // The contribution client requests the link, the browser signs in
+    let response = reqwest::Client::new()
+        .get(harness.app_path("auth/callback/eth"))
+        .header("User-Agent", "browser")
+        .query(&[("state", csrf.as_str()), ("code", &user.id.to_string())])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
 #[tokio::test]
 async fn test_gh_auth_with_custom_frontend_redirect() {
     let harness = run_test_harness().await;
@@ -121,7 +167,8 @@ async fn test_gh_contribution_happy_path() {
     let (user, session_id) =
         actions::create_and_login_gh_user(&harness, &http_client, "kustosz".to_string()).await;
 
-    let mut contribution = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
 
     // not only is it unguessable, it is also 32 characters long and we depend on
     // this.
@@ -139,6 +186,7 @@ async fn test_gh_contribution_happy_path() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -190,7 +238,8 @@ async fn test_double_contribution() {
     )
     .await;
 
-    let mut contribution = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
 
     // not only is it unguessable, it is also 32 characters long and we depend on
     // this.
@@ -209,6 +258,7 @@ async fn test_double_contribution() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution,
         &user_id.to_string(),
     )
@@ -225,7 +275,8 @@ async fn test_double_contribution() {
     // Try contributing again right away – fails because the contribution spot is
     // emptied
     let second_contribute_response =
-        actions::request_contribute(&harness, &http_client, &session_id, &contribution).await;
+        actions::request_contribute(&harness, &http_client, &session_id, &slot_id, &contribution)
+            .await;
     assert_eq!(second_contribute_response.status(), StatusCode::BAD_REQUEST);
 
     // Try pinging the lobby again – fails because the user got logged out
@@ -265,7 +316,8 @@ async fn test_double_contribution_when_allowed() {
     )
     .await;
 
-    let mut contribution1 = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution1) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
 
     contribution1
         .add_entropy::<DefaultEngine>(
@@ -278,6 +330,7 @@ async fn test_double_contribution_when_allowed() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution1,
         &user_id.to_string(),
     )
@@ -289,7 +342,8 @@ async fn test_double_contribution_when_allowed() {
     )
     .await;
 
-    let mut contribution2 = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution2) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
     contribution2
         .add_entropy::<DefaultEngine>(
             &actions::entropy_from_str("another unguessable string, wow!"),
@@ -301,6 +355,7 @@ async fn test_double_contribution_when_allowed() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution2,
         &user_id.to_string(),
     )
@@ -310,6 +365,67 @@ async fn test_double_contribution_when_allowed() {
     actions::assert_includes_contribution(&transcript, &contribution2, &user, false, true);
 }
 
+#[tokio::test]
+async fn test_pseudonymous_contribution() {
+    let harness = harness::Builder::new()
+        .set_pseudonym_salt("such a secret salt")
+        .run()
+        .await;
+    let http_client = reqwest::Client::new();
+    let user = harness.create_gh_user("kustosz".to_string()).await;
+    let csrf =
+        actions::get_and_validate_csrf_token_with_query(&harness, &[("pseudonymous", "true")])
+            .await;
+    let auth_response = actions::request_auth_callback(&harness, &http_client, &user, &csrf)
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let session_id = auth_response["session_id"].as_str().unwrap();
+    let sub = auth_response["id_token"]["sub"].as_str().unwrap();
+    let pseudonym: Identity = sub.parse().expect("sub must be an identity");
+    assert!(matches!(pseudonym, Identity::Pseudonym { .. }));
+
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, session_id).await;
+    contribution
+        .add_entropy::<DefaultEngine>(
+            &actions::entropy_from_str("such an unguessable string, wow!"),
+            &pseudonym,
+        )
+        .expect("Adding entropy must be possible");
+    actions::contribute_successfully(
+        &harness,
+        &http_client,
+        session_id,
+        &slot_id,
+        &contribution,
+        sub,
+    )
+    .await;
+
+    let transcript = harness.read_transcript_file().await;
+    assert!(transcript.participant_ids.contains(&pseudonym));
+    assert!(!transcript.participant_ids.contains(&user.identity()));
+
+    // Signing in again, without a pseudonym, must not allow another contribution
+    let new_csrf = actions::get_and_validate_csrf_token(&harness, None).await;
+    let response = actions::request_auth_callback(&harness, &http_client, &user, &new_csrf).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_pseudonymous_contribution_when_disabled() {
+    let harness = run_test_harness().await;
+    let response = reqwest::Client::new()
+        .get(harness.app_path("auth/request_link"))
+        .query(&[("pseudonymous", "true")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
 #[tokio::test]
 async fn test_large_lobby() {
     let harness = Arc::new(run_test_harness().await);
@@ -419,7 +535,8 @@ async fn test_contribution_after_lobby_cleanup() {
     let (user, session_id) =
         actions::create_and_login_gh_user(&harness, &http_client, "kustosz".to_string()).await;
 
-    let mut contribution = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
 
     let entropy = "such an unguessable string, wow!"
         .bytes()
@@ -437,6 +554,7 @@ async fn test_contribution_after_lobby_cleanup() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -450,7 +568,8 @@ async fn test_wrong_ecdsa_signature() {
     let http_client = reqwest::Client::new();
     let user = harness.create_eth_user().await;
     let session_id = actions::login(&harness, &http_client, &user).await;
-    let mut contribution = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
     let entropy = actions::entropy_from_str("foo bar baz");
     contribution
         .add_entropy::<DefaultEngine>(&entropy, &user.identity())
@@ -466,6 +585,7 @@ async fn test_wrong_ecdsa_signature() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -482,7 +602,36 @@ async fn test_wrong_bls_signature() {
     let http_client = reqwest::Client::new();
     let user = harness.create_eth_user().await;
     let session_id = actions::login(&harness, &http_client, &user).await;
-    let mut contribution = actions::try_contribute(&harness, &http_client, &session_id).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
+    let entropy = actions::entropy_from_str("foo bar baz");
+    contribution
+        .add_entropy::<DefaultEngine>(&entropy, &user.identity())
+        .expect("Adding entropy must be possible");
+
+    contribution.contributions.iter_mut().for_each(|c| {
+        c.bls_signature = BlsSignature(Some(G1::one()));
+    });
+
+    let response =
+        actions::request_contribute(&harness, &http_client, &session_id, &slot_id, &contribution)
+            .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.json::<serde_json::Value>().await.unwrap();
+    assert_eq!(body["code"], "ContributeError::UnboundContribution");
+}
+
+#[tokio::test]
+async fn test_wrong_bls_signature_when_unbound_allowed() {
+    let harness = harness::Builder::new()
+        .allow_unbound_contributions()
+        .run()
+        .await;
+    let http_client = reqwest::Client::new();
+    let user = harness.create_eth_user().await;
+    let session_id = actions::login(&harness, &http_client, &user).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
     let entropy = actions::entropy_from_str("foo bar baz");
     contribution
         .add_entropy::<DefaultEngine>(&entropy, &user.identity())
@@ -496,6 +645,7 @@ async fn test_wrong_bls_signature() {
         &harness,
         &http_client,
         &session_id,
+        &slot_id,
         &contribution,
         &user.identity().to_string(),
     )
@@ -505,3 +655,33 @@ async fn test_wrong_bls_signature() {
 
     assert_includes_contribution(&transcript, &contribution, &user, false, false)
 }
+
+#[tokio::test]
+async fn test_contribute_without_client_version_check() {
+    // Without the check in the chain, clients aren't asked for their version
+    // when uploading either
+    let harness = harness::Builder::new()
+        .set_min_client_version("9.0.0")
+        .set_abuse_checks("allowlist")
+        .run()
+        .await;
+    let http_client = reqwest::Client::new();
+    let user = harness.create_eth_user().await;
+    let session_id = actions::login(&harness, &http_client, &user).await;
+    let (slot_id, mut contribution) =
+        actions::try_contribute(&harness, &http_client, &session_id).await;
+    let entropy = actions::entropy_from_str("foo bar baz");
+    contribution
+        .add_entropy::<DefaultEngine>(&entropy, &user.identity())
+        .expect("Adding entropy must be possible");
+
+    actions::contribute_successfully(
+        &harness,
+        &http_client,
+        &session_id,
+        &slot_id,
+        &contribution,
+        &user.identity().to_string(),
+    )
+    .await;
+}